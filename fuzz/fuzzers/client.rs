@@ -1,9 +1,9 @@
 #![no_main]
 #[macro_use] extern crate libfuzzer_sys;
 extern crate rustls;
-extern crate webpki;
 
 use rustls::{ConfigBuilder, ClientConnection, Connection, RootCertStore};
+use std::convert::TryInto;
 use std::io;
 use std::sync::Arc;
 
@@ -14,7 +14,7 @@ fuzz_target!(|data: &[u8]| {
         .unwrap()
         .with_root_certificates(root_store, &[])
         .with_no_client_auth());
-    let example_com = webpki::DnsNameRef::try_from_ascii_str("example.com").unwrap();
+    let example_com = "example.com".try_into().unwrap();
     let mut client = ClientConnection::new(config, example_com).unwrap();
     let _ = client.read_tls(&mut io::Cursor::new(data));
 });
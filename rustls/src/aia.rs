@@ -0,0 +1,181 @@
+// Completing server certificate chains which are missing intermediates,
+// from a preloaded set or using the authority information access
+// extension ("AIA chasing").
+
+use crate::anchors::RootCertStore;
+use crate::key::Certificate;
+#[cfg(feature = "logging")]
+use crate::log::debug;
+use crate::x509::CertFields;
+use std::collections::HashMap;
+
+/// How many times intermediates are fetched for the certificates
+/// previously fetched, so a chain missing several intermediates can be
+/// completed, but a malicious chain can't cause unbounded fetching.
+const MAX_FETCH_ROUNDS: usize = 3;
+
+/// Supplies intermediate certificates missing from the chain a server
+/// sent, for [`WebPkiVerifier::with_intermediate_fetcher`].
+///
+/// rustls does no network access itself: this is where an application
+/// fetches the certificates named by the `caIssuers` URLs in a
+/// certificate's authority information access extension.
+///
+/// [`WebPkiVerifier::with_intermediate_fetcher`]: crate::WebPkiVerifier::with_intermediate_fetcher
+pub trait IntermediateFetcher: Send + Sync {
+    /// Return the certificates which may have issued `cert`, fetched
+    /// from `ca_issuers`, the `caIssuers` URLs in `cert`.
+    ///
+    /// This is called during the handshake, so should give up quickly.
+    /// Return an empty `Vec` if nothing could be fetched; certificates
+    /// returned which don't lead to a trust anchor are ignored.
+    fn fetch_intermediates(&self, cert: &Certificate, ca_issuers: &[&str]) -> Vec<Certificate>;
+}
+
+/// Intermediate certificates known in advance, such as Mozilla's
+/// intermediate preload list, indexed by subject.
+#[derive(Default)]
+pub(crate) struct KnownIntermediates {
+    by_subject: HashMap<Vec<u8>, Vec<Certificate>>,
+    count: usize,
+}
+
+impl KnownIntermediates {
+    /// Index `certs`, skipping any which can't be parsed.
+    pub(crate) fn new(certs: &[Certificate]) -> Self {
+        let mut known = Self::default();
+        for cert in certs {
+            match CertFields::parse(&cert.0) {
+                Some(fields) => {
+                    known
+                        .by_subject
+                        .entry(fields.subject.to_vec())
+                        .or_insert_with(Vec::new)
+                        .push(cert.clone());
+                    known.count += 1;
+                }
+                None => debug!("skipping unparsable known intermediate"),
+            }
+        }
+        known
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.count
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Returns `intermediates`, followed by the known intermediates
+    /// which may have issued a certificate whose issuer is neither in
+    /// the chain nor a trust anchor.
+    pub(crate) fn complete_chain(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        roots: &RootCertStore,
+    ) -> Vec<Certificate> {
+        let mut all = intermediates.to_vec();
+        let mut pending = vec![end_entity.clone()];
+        pending.extend_from_slice(intermediates);
+
+        while !pending.is_empty() {
+            let mut added = Vec::new();
+
+            for cert in &pending {
+                let fields = match CertFields::parse(&cert.0) {
+                    Some(fields) if !issuer_known(&fields, &all, roots) => fields,
+                    _ => continue,
+                };
+
+                for issuer in self
+                    .by_subject
+                    .get(fields.issuer)
+                    .into_iter()
+                    .flatten()
+                {
+                    if !all.contains(issuer) && !added.contains(issuer) {
+                        added.push(issuer.clone());
+                    }
+                }
+            }
+
+            all.extend_from_slice(&added);
+            pending = added;
+        }
+
+        all
+    }
+}
+
+/// Returns `intermediates`, followed by any intermediates `fetcher`
+/// supplies for certificates whose issuer is neither in the chain
+/// nor a trust anchor.
+pub(crate) fn fetch_missing_intermediates(
+    fetcher: &dyn IntermediateFetcher,
+    end_entity: &Certificate,
+    intermediates: &[Certificate],
+    roots: &RootCertStore,
+) -> Vec<Certificate> {
+    let mut all = intermediates.to_vec();
+    let mut pending = vec![end_entity.clone()];
+    pending.extend_from_slice(intermediates);
+
+    for _ in 0..MAX_FETCH_ROUNDS {
+        let mut fetched: Vec<Certificate> = Vec::new();
+
+        for cert in &pending {
+            let fields = match CertFields::parse(&cert.0) {
+                Some(fields) => fields,
+                None => continue,
+            };
+
+            if issuer_known(&fields, &all, roots) {
+                continue;
+            }
+
+            let ca_issuers = fields
+                .ca_issuers()
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|uri| std::str::from_utf8(uri).ok())
+                .collect::<Vec<_>>();
+            if ca_issuers.is_empty() {
+                continue;
+            }
+
+            debug!("fetching issuer of certificate from {:?}", ca_issuers);
+            for issuer in fetcher.fetch_intermediates(cert, &ca_issuers) {
+                if !all.contains(&issuer) && !fetched.contains(&issuer) {
+                    fetched.push(issuer);
+                }
+            }
+        }
+
+        if fetched.is_empty() {
+            break;
+        }
+
+        all.extend_from_slice(&fetched);
+        pending = fetched;
+    }
+
+    all
+}
+
+/// Is `cert` self-issued, or is its issuer among `intermediates` or
+/// `roots`?
+fn issuer_known(cert: &CertFields, intermediates: &[Certificate], roots: &RootCertStore) -> bool {
+    cert.issuer == cert.subject
+        || roots
+            .roots
+            .iter()
+            .any(|ota| ota.to_trust_anchor().subject == cert.issuer)
+        || intermediates.iter().any(|c| {
+            CertFields::parse(&c.0)
+                .map(|c| c.subject == cert.issuer)
+                .unwrap_or(false)
+        })
+}
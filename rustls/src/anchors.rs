@@ -1,39 +1,338 @@
+use crate::error::Error;
 use crate::key;
 #[cfg(feature = "logging")]
 use crate::log::{debug, trace};
+use crate::msgs::base::PayloadU16;
+use crate::msgs::codec::{self, Codec, Reader};
+use crate::msgs::enums::SignatureAlgorithm;
 pub use crate::msgs::handshake::{DistinguishedName, DistinguishedNames};
 use crate::x509;
 
+use std::collections::HashSet;
+#[cfg(feature = "pem_files")]
+use std::fs;
+#[cfg(feature = "pem_files")]
+use std::io::{self, BufRead};
+use std::net::IpAddr;
+#[cfg(feature = "pem_files")]
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
 /// This is like a `webpki::TrustAnchor`, except it owns
 /// rather than borrows its memory.  That prevents lifetimes
 /// leaking up the object tree.
+///
+/// Anchors made with `from_static_der` are the exception: they borrow
+/// a certificate which lives forever, and extract the components from
+/// it when they're needed.
 #[derive(Debug, Clone)]
 pub struct OwnedTrustAnchor {
-    subject: Vec<u8>,
-    spki: Vec<u8>,
-    name_constraints: Option<Vec<u8>>,
+    source: AnchorSource,
+    not_after: Option<u64>,
+    extra_name_constraints: Option<NameConstraints>,
+    trusted_from: Option<u64>,
+    trusted_until: Option<u64>,
+}
+
+/// Where an `OwnedTrustAnchor` keeps its components.
+#[derive(Debug, Clone)]
+enum AnchorSource {
+    /// Copied out of a certificate or `webpki::TrustAnchor`.
+    Extracted {
+        subject: Vec<u8>,
+        spki: Vec<u8>,
+        name_constraints: Option<Vec<u8>>,
+    },
+    /// A certificate to extract them from each time, which has already
+    /// been checked to contain them.
+    Certificate(&'static [u8]),
 }
 
 impl OwnedTrustAnchor {
     /// Copy a `webpki::TrustAnchor` into owned memory
     pub fn from_trust_anchor(t: &webpki::TrustAnchor) -> OwnedTrustAnchor {
-        OwnedTrustAnchor {
+        Self::from_source(AnchorSource::Extracted {
             subject: t.subject.to_vec(),
             spki: t.spki.to_vec(),
             name_constraints: t.name_constraints.map(|x| x.to_vec()),
+        })
+    }
+
+    fn from_source(source: AnchorSource) -> Self {
+        Self {
+            source,
+            not_after: None,
+            extra_name_constraints: None,
+            trusted_from: None,
+            trusted_until: None,
         }
     }
 
     /// Get a `webpki::TrustAnchor` by borrowing the owned elements.
     pub fn to_trust_anchor(&self) -> webpki::TrustAnchor {
-        webpki::TrustAnchor {
-            subject: &self.subject,
-            spki: &self.spki,
-            name_constraints: self.name_constraints.as_deref(),
+        match &self.source {
+            AnchorSource::Extracted {
+                subject,
+                spki,
+                name_constraints,
+            } => webpki::TrustAnchor {
+                subject,
+                spki,
+                name_constraints: name_constraints.as_deref(),
+            },
+            AnchorSource::Certificate(der) => webpki::TrustAnchor::try_from_cert_der(der)
+                .expect("certificate was parsed when the anchor was made"),
+        }
+    }
+
+    /// Limit the names of certificates issued under this anchor to
+    /// those `constraints` allow, in addition to any name constraints
+    /// of the anchor itself.  This replaces constraints previously set
+    /// with this method.
+    ///
+    /// These are enforced by [`WebPkiVerifier`] and
+    /// [`AllowAnyAuthenticatedClient`], which fail with
+    /// `NameConstraintViolation` if a chain only leads to anchors
+    /// whose constraints it doesn't meet.
+    ///
+    /// [`WebPkiVerifier`]: crate::WebPkiVerifier
+    /// [`AllowAnyAuthenticatedClient`]: crate::AllowAnyAuthenticatedClient
+    pub fn set_name_constraints(&mut self, constraints: NameConstraints) {
+        self.extra_name_constraints = Some(constraints);
+    }
+
+    /// Trust this anchor only from `not_before` until `not_after`
+    /// inclusive, to the second; `None` leaves that end of the window
+    /// open.  This replaces a window previously set with this method.
+    ///
+    /// Verifiers ignore the anchor outside the window, and
+    /// [`RootCertStore::prune_expired`] removes it once the window has
+    /// ended.  This is independent of the validity period of the
+    /// certificate the anchor was made from, which, like other TLS
+    /// implementations, rustls doesn't enforce for anchors.
+    pub fn set_validity(&mut self, not_before: Option<SystemTime>, not_after: Option<SystemTime>) {
+        self.trusted_from = not_before.map(unix_time);
+        self.trusted_until = not_after.map(unix_time);
+    }
+
+    /// Is this anchor trusted at `now`, according to the window set
+    /// with `set_validity`?
+    pub fn is_trusted_at(&self, now: SystemTime) -> bool {
+        let now = unix_time(now);
+        self.trusted_from
+            .map_or(true, |from| from <= now)
+            && !self.has_expired_at(now)
+    }
+
+    /// Has the window set with `set_validity` ended at `now`, in
+    /// seconds since the UNIX epoch?
+    fn has_expired_at(&self, now: u64) -> bool {
+        self.trusted_until
+            .map_or(false, |until| until < now)
+    }
+
+    /// The parsed details of this anchor.
+    pub fn info(&self) -> TrustAnchorInfo {
+        let ta = self.to_trust_anchor();
+        let mut subject = ta.subject.to_vec();
+        x509::wrap_in_sequence(&mut subject);
+        let mut spki = ta.spki.to_vec();
+        x509::wrap_in_sequence(&mut spki);
+
+        TrustAnchorInfo {
+            subject,
+            spki_sha256: ring::digest::digest(&ring::digest::SHA256, &spki)
+                .as_ref()
+                .to_vec(),
+            key_algorithm: x509::key_algorithm(ta.spki),
+            key_bits: x509::key_bits(ta.spki),
+            not_after: self.not_after.map(from_unix_time),
+            name_constrained: ta.name_constraints.is_some()
+                || self.extra_name_constraints.is_some(),
+            trusted_from: self.trusted_from.map(from_unix_time),
+            trusted_until: self.trusted_until.map(from_unix_time),
+        }
+    }
+
+    /// Make an anchor from the DER-encoded certificate `der`, as
+    /// `RootCertStore::add` does.
+    pub fn try_from_der(der: &[u8]) -> Result<Self, webpki::Error> {
+        let ta = webpki::TrustAnchor::try_from_cert_der(der)?;
+        let mut ota = Self::from_trust_anchor(&ta);
+        ota.not_after = x509::CertFields::parse(der).map(|cert| cert.not_after);
+        Ok(ota)
+    }
+
+    /// Make an anchor which borrows the DER-encoded certificate `der`,
+    /// such as one from a bundle compiled into the program with
+    /// `include_bytes!`, instead of copying parts of it.
+    ///
+    /// This saves memory when there are many anchors: they use no more
+    /// than the certificates themselves.  In return, the anchor is
+    /// extracted from `der` again each time it's used, such as when a
+    /// verifier prepares a chain for validation.  `der` is checked when
+    /// the anchor is made, so this fails in the same cases as
+    /// `try_from_der`.
+    pub fn from_static_der(der: &'static [u8]) -> Result<Self, webpki::Error> {
+        webpki::TrustAnchor::try_from_cert_der(der)?;
+        let mut ota = Self::from_source(AnchorSource::Certificate(der));
+        ota.not_after = x509::CertFields::parse(der).map(|cert| cert.not_after);
+        Ok(ota)
+    }
+
+    /// Make an anchor from components already extracted from its
+    /// certificate, in the form `webpki::TrustAnchor` and
+    /// `webpki-roots` use: see `subject`, `spki` and `name_constraints`.
+    ///
+    /// Unlike `from_trust_anchor`, this checks the components are
+    /// well-formed, as `RootCertStore::from_trust_anchors` does, and
+    /// fails with `BadDer` if not.
+    pub fn try_from_components(
+        subject: &[u8],
+        spki: &[u8],
+        name_constraints: Option<&[u8]>,
+    ) -> Result<Self, webpki::Error> {
+        let ta = webpki::TrustAnchor {
+            subject,
+            spki,
+            name_constraints,
+        };
+        if !trust_anchor_is_well_formed(&ta) {
+            return Err(webpki::Error::BadDer);
+        }
+        Ok(Self::from_trust_anchor(&ta))
+    }
+
+    /// The anchor's subject: the contents of its DER-encoded `Name`,
+    /// without the outer SEQUENCE.
+    pub fn subject(&self) -> &[u8] {
+        self.to_trust_anchor().subject
+    }
+
+    /// The anchor's public key: the contents of its DER-encoded
+    /// `SubjectPublicKeyInfo`, without the outer SEQUENCE.
+    pub fn spki(&self) -> &[u8] {
+        self.to_trust_anchor().spki
+    }
+
+    /// The contents of the name constraints extension of the
+    /// certificate the anchor was made from, if it had one.  This
+    /// doesn't include constraints set with `set_name_constraints`.
+    pub fn name_constraints(&self) -> Option<&[u8]> {
+        self.to_trust_anchor().name_constraints
+    }
+
+    /// The anchor's subject and public key, which identify it.
+    fn id(&self) -> (&[u8], &[u8]) {
+        let ta = self.to_trust_anchor();
+        (ta.subject, ta.spki)
+    }
+
+    /// Does this anchor have the subject and public key of `ta`?
+    fn is_same_anchor(&self, ta: &webpki::TrustAnchor) -> bool {
+        self.id() == (ta.subject, ta.spki)
+    }
+
+    /// The name constraints set with `set_name_constraints`.
+    pub(crate) fn extra_name_constraints(&self) -> Option<&NameConstraints> {
+        self.extra_name_constraints.as_ref()
+    }
+
+    /// The `notAfter` of the certificate this anchor was made from, in
+    /// seconds since the UNIX epoch.  This is `None` for anchors made
+    /// from a `webpki::TrustAnchor`, which doesn't record it.
+    pub(crate) fn not_after(&self) -> Option<u64> {
+        self.not_after
+    }
+}
+
+impl Codec for OwnedTrustAnchor {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        let ta = self.to_trust_anchor();
+        PayloadU16::encode_slice(ta.subject, bytes);
+        PayloadU16::encode_slice(ta.spki, bytes);
+        match ta.name_constraints {
+            Some(nc) => {
+                1u8.encode(bytes);
+                PayloadU16::encode_slice(nc, bytes);
+            }
+            None => 0u8.encode(bytes),
+        }
+        encode_optional_u64(self.not_after, bytes);
+        match &self.extra_name_constraints {
+            Some(constraints) => {
+                1u8.encode(bytes);
+                constraints.encode(bytes);
+            }
+            None => 0u8.encode(bytes),
         }
+        encode_optional_u64(self.trusted_from, bytes);
+        encode_optional_u64(self.trusted_until, bytes);
+    }
+
+    fn read(r: &mut Reader) -> Option<Self> {
+        let subject = PayloadU16::read(r)?.0;
+        let spki = PayloadU16::read(r)?.0;
+        let name_constraints = match u8::read(r)? {
+            0 => None,
+            1 => Some(PayloadU16::read(r)?.0),
+            _ => return None,
+        };
+        let not_after = read_optional_u64(r)?;
+        let extra_name_constraints = match u8::read(r)? {
+            0 => None,
+            1 => Some(NameConstraints::read(r)?),
+            _ => return None,
+        };
+        let trusted_from = read_optional_u64(r)?;
+        let trusted_until = read_optional_u64(r)?;
+
+        Some(Self {
+            source: AnchorSource::Extracted {
+                subject,
+                spki,
+                name_constraints,
+            },
+            not_after,
+            extra_name_constraints,
+            trusted_from,
+            trusted_until,
+        })
     }
 }
 
+fn encode_optional_u64(value: Option<u64>, bytes: &mut Vec<u8>) {
+    match value {
+        Some(value) => {
+            1u8.encode(bytes);
+            value.encode(bytes);
+        }
+        None => 0u8.encode(bytes),
+    }
+}
+
+/// Read an optional `u64` written by `encode_optional_u64`.  The outer
+/// `Option` is `None` if it's malformed.
+fn read_optional_u64(r: &mut Reader) -> Option<Option<u64>> {
+    match u8::read(r)? {
+        0 => Some(None),
+        1 => Some(Some(u64::read(r)?)),
+        _ => None,
+    }
+}
+
+/// `time` in seconds since the UNIX epoch; times before it are taken
+/// to be the epoch itself.
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+fn from_unix_time(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
 impl From<webpki::TrustAnchor<'_>> for OwnedTrustAnchor {
     fn from(t: webpki::TrustAnchor) -> OwnedTrustAnchor {
         Self::from_trust_anchor(&t)
@@ -46,6 +345,279 @@ impl<'a> From<&'a OwnedTrustAnchor> for webpki::TrustAnchor<'a> {
     }
 }
 
+/// The parsed details of a trust anchor, as returned by
+/// [`OwnedTrustAnchor::info`] and [`RootCertStore::anchor_info`].
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct TrustAnchorInfo {
+    /// The DER encoding of the anchor's subject `Name`.
+    pub subject: Vec<u8>,
+    /// SHA-256 hash of the anchor's DER-encoded subjectPublicKeyInfo.
+    pub spki_sha256: Vec<u8>,
+    /// The family of the anchor's public key, or `None` if it is not
+    /// recognised.
+    pub key_algorithm: Option<SignatureAlgorithm>,
+    /// The size of the anchor's public key in bits, or `None` if it is
+    /// not known.
+    pub key_bits: Option<usize>,
+    /// The end of the validity period of the certificate the anchor was
+    /// made from, or `None` if it wasn't made from a certificate.
+    pub not_after: Option<SystemTime>,
+    /// Whether the anchor has name constraints, from its certificate
+    /// or set with [`OwnedTrustAnchor::set_name_constraints`].
+    pub name_constrained: bool,
+    /// The start of the window set with
+    /// [`OwnedTrustAnchor::set_validity`], if any.
+    pub trusted_from: Option<SystemTime>,
+    /// The end of the window set with
+    /// [`OwnedTrustAnchor::set_validity`], if any.
+    pub trusted_until: Option<SystemTime>,
+}
+
+impl TrustAnchorInfo {
+    /// The subject, in the same form as
+    /// [`CertificateInfo::subject_string`](crate::x509::CertificateInfo::subject_string).
+    ///
+    /// Returns `None` if the subject is malformed.
+    pub fn subject_string(&self) -> Option<String> {
+        x509::name_to_string(unwrap_name(&self.subject))
+    }
+}
+
+/// Name constraints for a trust anchor, limiting the names which
+/// certificates issued under it may have, like the nameConstraints
+/// extension of RFC 5280 section 4.2.1.10.
+///
+/// This lets an application trust, say, a corporate CA only for the
+/// corporation's own domains.  The DNS name and IP address subject
+/// alternative names of each certificate in a chain are checked.  A
+/// name must not be in an excluded subtree, and if any subtrees of its
+/// kind are permitted, it must be in one of them: so a DNS name is
+/// acceptable if only IP addresses are constrained, and vice versa.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NameConstraints {
+    permitted: Vec<GeneralSubtree>,
+    excluded: Vec<GeneralSubtree>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum GeneralSubtree {
+    DnsName(String),
+    IpSubnet(IpAddr, u8),
+}
+
+impl NameConstraints {
+    /// Make name constraints which permit and exclude nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Permit `name` and its subdomains, or only its subdomains if it
+    /// starts with `.`.  If any DNS names are permitted, all others are
+    /// forbidden.
+    pub fn permit_dns_name(mut self, name: &str) -> Self {
+        self.permitted
+            .push(GeneralSubtree::DnsName(name.to_ascii_lowercase()));
+        self
+    }
+
+    /// Forbid `name` and its subdomains, or only its subdomains if it
+    /// starts with `.`.
+    pub fn exclude_dns_name(mut self, name: &str) -> Self {
+        self.excluded
+            .push(GeneralSubtree::DnsName(name.to_ascii_lowercase()));
+        self
+    }
+
+    /// Permit addresses in the subnet of `addr` with the given prefix
+    /// length.  If any subnets are permitted, all other addresses are
+    /// forbidden.
+    pub fn permit_ip_subnet(mut self, addr: IpAddr, prefix_len: u8) -> Self {
+        self.permitted
+            .push(GeneralSubtree::IpSubnet(addr, prefix_len));
+        self
+    }
+
+    /// Forbid addresses in the subnet of `addr` with the given prefix
+    /// length.
+    pub fn exclude_ip_subnet(mut self, addr: IpAddr, prefix_len: u8) -> Self {
+        self.excluded
+            .push(GeneralSubtree::IpSubnet(addr, prefix_len));
+        self
+    }
+
+    /// Does `cert` only have names these constraints allow?
+    pub(crate) fn permits(&self, cert: &x509::CertFields) -> bool {
+        cert.subject_alt_names()
+            .unwrap_or_default()
+            .into_iter()
+            .all(|(tag, value)| self.permits_name(tag, value))
+    }
+
+    fn permits_name(&self, tag: u8, value: &[u8]) -> bool {
+        let constrains = |subtree: &GeneralSubtree| match subtree {
+            GeneralSubtree::DnsName(_) => tag == x509::GENERAL_NAME_DNS_NAME,
+            GeneralSubtree::IpSubnet(..) => tag == x509::GENERAL_NAME_IP_ADDRESS,
+        };
+
+        let excluded = self
+            .excluded
+            .iter()
+            .any(|subtree| constrains(subtree) && subtree.may_contain(value));
+        let permitted = !self.permitted.iter().any(constrains)
+            || self
+                .permitted
+                .iter()
+                .any(|subtree| constrains(subtree) && subtree.contains(value));
+
+        !excluded && permitted
+    }
+}
+
+impl GeneralSubtree {
+    /// Is the name `value`, of this subtree's kind, in the subtree?
+    fn contains(&self, value: &[u8]) -> bool {
+        match self {
+            Self::DnsName(base) => match std::str::from_utf8(value) {
+                Ok(name) => dns_name_in_subtree(&name.to_ascii_lowercase(), base),
+                Err(_) => false,
+            },
+            Self::IpSubnet(addr, prefix_len) => {
+                let addr = match addr {
+                    IpAddr::V4(addr) => addr.octets().to_vec(),
+                    IpAddr::V6(addr) => addr.octets().to_vec(),
+                };
+                value.len() == addr.len()
+                    && (0..usize::from(*prefix_len).min(addr.len() * 8)).all(|bit| {
+                        let mask = 0x80 >> (bit % 8);
+                        value[bit / 8] & mask == addr[bit / 8] & mask
+                    })
+            }
+        }
+    }
+
+    /// Could the name `value` refer to something in the subtree?  This
+    /// differs from `contains` for wildcards: `*.example.com` may be
+    /// `www.example.com`.
+    fn may_contain(&self, value: &[u8]) -> bool {
+        if let Self::DnsName(base) = self {
+            if let Some(domain) = value.strip_prefix(b"*.") {
+                let domain = String::from_utf8_lossy(domain).to_ascii_lowercase();
+                if base
+                    .trim_start_matches('.')
+                    .ends_with(&format!(".{}", domain))
+                {
+                    return true;
+                }
+            }
+        }
+        self.contains(value)
+    }
+}
+
+/// Is `name` in the DNS subtree `base`?  Both are lowercase.
+fn dns_name_in_subtree(name: &str, base: &str) -> bool {
+    let name = name.trim_end_matches('.');
+    let base = base.trim_end_matches('.');
+    if base.is_empty() {
+        return true;
+    }
+
+    match base.strip_prefix('.') {
+        Some(_) => name.ends_with(base),
+        None => name == base || name.ends_with(&format!(".{}", base)),
+    }
+}
+
+impl Codec for NameConstraints {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        codec::encode_vec_u16(bytes, &self.permitted);
+        codec::encode_vec_u16(bytes, &self.excluded);
+    }
+
+    fn read(r: &mut Reader) -> Option<Self> {
+        Some(Self {
+            permitted: codec::read_vec_u16(r)?,
+            excluded: codec::read_vec_u16(r)?,
+        })
+    }
+}
+
+impl Codec for GeneralSubtree {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        match self {
+            Self::DnsName(name) => {
+                0u8.encode(bytes);
+                PayloadU16::encode_slice(name.as_bytes(), bytes);
+            }
+            Self::IpSubnet(addr, prefix_len) => {
+                1u8.encode(bytes);
+                match addr {
+                    IpAddr::V4(addr) => PayloadU16::encode_slice(&addr.octets(), bytes),
+                    IpAddr::V6(addr) => PayloadU16::encode_slice(&addr.octets(), bytes),
+                }
+                prefix_len.encode(bytes);
+            }
+        }
+    }
+
+    fn read(r: &mut Reader) -> Option<Self> {
+        match u8::read(r)? {
+            0 => String::from_utf8(PayloadU16::read(r)?.0)
+                .ok()
+                .map(Self::DnsName),
+            1 => {
+                let addr = match PayloadU16::read(r)?.0.as_slice() {
+                    &[a, b, c, d] => IpAddr::from([a, b, c, d]),
+                    octets => {
+                        let mut addr = [0u8; 16];
+                        if octets.len() != addr.len() {
+                            return None;
+                        }
+                        addr.copy_from_slice(octets);
+                        IpAddr::from(addr)
+                    }
+                };
+                Some(Self::IpSubnet(addr, u8::read(r)?))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Which certificates were added by
+/// [`RootCertStore::add_parsable_certificates_detailed`], and which
+/// weren't.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[non_exhaustive]
+pub struct AddCertificatesOutcome {
+    /// The number of certificates added.
+    pub added: usize,
+    /// The indices of the certificates not added because they give the
+    /// same anchor (subject and public key) as one already in the store,
+    /// or earlier in the input.
+    pub duplicates: Vec<usize>,
+    /// The indices of the certificates which couldn't be parsed, and why.
+    pub invalid: Vec<(usize, webpki::Error)>,
+}
+
+/// The outcome of loading one file with [`RootCertStore::add_from_dir`].
+#[cfg(feature = "pem_files")]
+#[cfg_attr(docsrs, doc(cfg(feature = "pem_files")))]
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct CertFileOutcome {
+    /// The file's path: the directory joined with its name.
+    pub path: PathBuf,
+    /// The number of certificates added from the file and the number
+    /// ignored, or why the file couldn't be read.
+    pub result: Result<(usize, usize), Error>,
+}
+
+/// The start of the output of `RootCertStore::serialize`.  The last
+/// byte is the version of the format, incremented whenever it changes.
+const SERIALIZED_MAGIC: &[u8; 4] = b"RCS\x02";
+
 /// A container for root certificates able to provide a root-of-trust
 /// for connection authentication.
 #[derive(Debug, Clone)]
@@ -76,7 +648,7 @@ impl RootCertStore {
 
         for ota in &self.roots {
             let mut name = Vec::new();
-            name.extend_from_slice(&ota.subject);
+            name.extend_from_slice(ota.subject());
             x509::wrap_in_sequence(&mut name);
             r.push(DistinguishedName::new(name));
         }
@@ -86,13 +658,220 @@ impl RootCertStore {
 
     /// Add a single DER-encoded certificate to the store.
     pub fn add(&mut self, der: &key::Certificate) -> Result<(), webpki::Error> {
-        let ta = webpki::TrustAnchor::try_from_cert_der(&der.0)?;
+        self.roots
+            .push(OwnedTrustAnchor::try_from_der(&der.0)?);
+        Ok(())
+    }
+
+    /// Add a single DER-encoded certificate to the store, keeping it as
+    /// the anchor instead of extracting the anchor now, as with
+    /// [`OwnedTrustAnchor::from_static_der`].
+    pub fn add_static(&mut self, der: &'static [u8]) -> Result<(), webpki::Error> {
+        self.roots
+            .push(OwnedTrustAnchor::from_static_der(der)?);
+        Ok(())
+    }
 
-        let ota = OwnedTrustAnchor::from_trust_anchor(&ta);
-        self.roots.push(ota);
+    /// Add a single DER-encoded certificate to the store, trusting it
+    /// only for the names `constraints` allows, as with
+    /// [`OwnedTrustAnchor::set_name_constraints`].
+    pub fn add_with_name_constraints(
+        &mut self,
+        der: &key::Certificate,
+        constraints: NameConstraints,
+    ) -> Result<(), webpki::Error> {
+        self.add(der)?;
+        if let Some(ota) = self.roots.last_mut() {
+            ota.set_name_constraints(constraints);
+        }
         Ok(())
     }
 
+    /// Add a single DER-encoded certificate to the store, trusting it
+    /// only from `not_before` until `not_after`, as with
+    /// [`OwnedTrustAnchor::set_validity`].
+    pub fn add_with_validity(
+        &mut self,
+        der: &key::Certificate,
+        not_before: Option<SystemTime>,
+        not_after: Option<SystemTime>,
+    ) -> Result<(), webpki::Error> {
+        self.add(der)?;
+        if let Some(ota) = self.roots.last_mut() {
+            ota.set_validity(not_before, not_after);
+        }
+        Ok(())
+    }
+
+    /// Remove the anchors whose window set with
+    /// [`OwnedTrustAnchor::set_validity`] ended before `now`.  Anchors
+    /// whose window hasn't started yet are kept.
+    ///
+    /// Returns the number of anchors removed.
+    pub fn prune_expired(&mut self, now: SystemTime) -> usize {
+        let now = unix_time(now);
+        let before = self.roots.len();
+        self.roots
+            .retain(|ota| !ota.has_expired_at(now));
+        before - self.roots.len()
+    }
+
+    /// The anchors trusted at `now`, according to the windows set with
+    /// [`OwnedTrustAnchor::set_validity`].
+    pub(crate) fn trusted_at(&self, now: SystemTime) -> impl Iterator<Item = &OwnedTrustAnchor> {
+        self.roots
+            .iter()
+            .filter(move |ota| ota.is_trusted_at(now))
+    }
+
+    /// Returns true if the store has an anchor made from the
+    /// DER-encoded certificate `der`: one with its subject and public
+    /// key.  This is false if `der` can't be parsed.
+    pub fn contains(&self, der: &key::Certificate) -> bool {
+        match webpki::TrustAnchor::try_from_cert_der(&der.0) {
+            Ok(ta) => self
+                .roots
+                .iter()
+                .any(|ota| ota.is_same_anchor(&ta)),
+            Err(_) => false,
+        }
+    }
+
+    /// The anchors with the subject `subject`, a DER-encoded Subject
+    /// Name as returned by `subjects`.  The contents of the Name,
+    /// without the outer SEQUENCE, are also accepted.
+    pub fn find_by_subject<'a>(
+        &'a self,
+        subject: &'a [u8],
+    ) -> impl Iterator<Item = &'a OwnedTrustAnchor> + 'a {
+        let subject = unwrap_name(subject);
+        self.roots
+            .iter()
+            .filter(move |ota| ota.subject() == subject)
+    }
+
+    /// The parsed details of each anchor, in order.
+    pub fn anchor_info(&self) -> impl Iterator<Item = TrustAnchorInfo> + '_ {
+        self.roots
+            .iter()
+            .map(OwnedTrustAnchor::info)
+    }
+
+    /// Remove the anchors made from the DER-encoded certificate `der`:
+    /// those with its subject and public key.
+    ///
+    /// Returns the number of anchors removed, or an error if `der`
+    /// can't be parsed.
+    pub fn remove(&mut self, der: &key::Certificate) -> Result<usize, webpki::Error> {
+        let ta = webpki::TrustAnchor::try_from_cert_der(&der.0)?;
+        let before = self.len();
+        self.roots
+            .retain(|ota| !ota.is_same_anchor(&ta));
+        Ok(before - self.len())
+    }
+
+    /// Remove every anchor with the subject `subject`, a DER-encoded
+    /// Subject Name as returned by `subjects`.  The contents of the
+    /// Name, without the outer SEQUENCE, are also accepted.
+    ///
+    /// Returns the number of anchors removed.
+    pub fn remove_by_subject(&mut self, subject: &[u8]) -> usize {
+        let subject = unwrap_name(subject);
+        let before = self.len();
+        self.roots
+            .retain(|ota| ota.subject() != subject);
+        before - self.len()
+    }
+
+    /// Replace the anchors made from the DER-encoded certificate `old`
+    /// with one made from `new`, such as when a root is re-issued.
+    ///
+    /// `new` takes the place of the first anchor removed, or is added at
+    /// the end if there were none.  Returns the number of anchors
+    /// removed.  If either certificate can't be parsed, the store is
+    /// unchanged.
+    pub fn replace(
+        &mut self,
+        old: &key::Certificate,
+        new: &key::Certificate,
+    ) -> Result<usize, webpki::Error> {
+        let old = webpki::TrustAnchor::try_from_cert_der(&old.0)?;
+        let new = OwnedTrustAnchor::try_from_der(&new.0)?;
+
+        // Anchors before the first removed are kept, so it's still the
+        // right place for `new` afterwards.
+        let position = self
+            .roots
+            .iter()
+            .position(|ota| ota.is_same_anchor(&old))
+            .unwrap_or_else(|| self.len());
+        let before = self.len();
+        self.roots
+            .retain(|ota| !ota.is_same_anchor(&old));
+        let removed = before - self.len();
+
+        self.roots.insert(position, new);
+        Ok(removed)
+    }
+
+    /// Add the anchors in `other` which aren't in this store, in the
+    /// order they appear in `other`.  Anchors are the same if they have
+    /// the same subject and public key; their name constraints and
+    /// validity windows are not compared.
+    ///
+    /// Returns the number of anchors added.
+    pub fn merge(&mut self, other: &RootCertStore) -> usize {
+        let added: Vec<OwnedTrustAnchor> = {
+            let known = self.anchor_ids();
+            other
+                .roots
+                .iter()
+                .filter(|ota| !known.contains(&ota.id()))
+                .cloned()
+                .collect()
+        };
+
+        debug!("merged {} anchors", added.len());
+        let count = added.len();
+        self.roots.extend(added);
+        count
+    }
+
+    /// Compare this store with `other`, such as a newer version of the
+    /// bundle it was loaded from.
+    ///
+    /// Returns the anchors in `other` which aren't in this store, and
+    /// the anchors in this store which aren't in `other`, compared as
+    /// for `merge`.  Applying the first with `merge` and removing the
+    /// second makes this store trust the same anchors as `other`.
+    pub fn diff<'a>(
+        &'a self,
+        other: &'a RootCertStore,
+    ) -> (Vec<&'a OwnedTrustAnchor>, Vec<&'a OwnedTrustAnchor>) {
+        let ours = self.anchor_ids();
+        let theirs = other.anchor_ids();
+
+        let added = other
+            .roots
+            .iter()
+            .filter(|ota| !ours.contains(&ota.id()))
+            .collect();
+        let removed = self
+            .roots
+            .iter()
+            .filter(|ota| !theirs.contains(&ota.id()))
+            .collect();
+        (added, removed)
+    }
+
+    /// The subject and public key of each anchor.
+    fn anchor_ids(&self) -> HashSet<(&[u8], &[u8])> {
+        self.roots
+            .iter()
+            .map(OwnedTrustAnchor::id)
+            .collect()
+    }
+
     /// Adds all the given TrustAnchors `anchors`.  This does not
     /// fail.
     pub fn add_server_trust_anchors(
@@ -105,34 +884,401 @@ impl RootCertStore {
         }
     }
 
+    /// Make a store containing each of `anchors` whose encoding is
+    /// valid.
+    ///
+    /// An anchor is skipped if its subject isn't a sequence of relative
+    /// distinguished names, its public key isn't a `SubjectPublicKeyInfo`,
+    /// or its name constraints (if any) aren't well-formed DER.
+    ///
+    /// Returns the store, and the number of anchors that were skipped.
+    pub fn from_trust_anchors(
+        &webpki::TlsServerTrustAnchors(anchors): &webpki::TlsServerTrustAnchors,
+    ) -> (RootCertStore, usize) {
+        let mut store = RootCertStore::empty();
+        let mut invalid_count = 0;
+
+        for ta in anchors {
+            if trust_anchor_is_well_formed(ta) {
+                store
+                    .roots
+                    .push(OwnedTrustAnchor::from_trust_anchor(ta));
+            } else {
+                trace!("invalid trust anchor subject {:?}", ta.subject);
+                invalid_count += 1;
+            }
+        }
+
+        debug!(
+            "from_trust_anchors converted {} valid and {} invalid anchors",
+            store.len(),
+            invalid_count
+        );
+
+        (store, invalid_count)
+    }
+
     /// Parse the given DER-encoded certificates and add all that can be parsed
     /// in a best-effort fashion.
     ///
     /// This is because large collections of root certificates often
     /// include ancient or syntactically invalid certificates.
+    /// Certificates giving the same anchor as one already in the store,
+    /// or earlier in `der_certs`, are not added again.
     ///
-    /// Returns the number of certificates added, and the number that were ignored.
+    /// Returns the number of certificates added, and the number that were
+    /// ignored as invalid or duplicates.  Use
+    /// `add_parsable_certificates_detailed` to find out which.
     pub fn add_parsable_certificates(&mut self, der_certs: &[Vec<u8>]) -> (usize, usize) {
-        let mut valid_count = 0;
-        let mut invalid_count = 0;
+        let outcome = self.add_parsable_certificates_detailed(der_certs);
+        (
+            outcome.added,
+            outcome.duplicates.len() + outcome.invalid.len(),
+        )
+    }
+
+    /// Like `add_parsable_certificates`, but returns which certificates
+    /// were duplicates and which couldn't be parsed, and why.
+    pub fn add_parsable_certificates_detailed(
+        &mut self,
+        der_certs: &[Vec<u8>],
+    ) -> AddCertificatesOutcome {
+        let mut known: HashSet<(Vec<u8>, Vec<u8>)> = self
+            .roots
+            .iter()
+            .map(|ota| (ota.subject().to_vec(), ota.spki().to_vec()))
+            .collect();
+        let mut outcome = AddCertificatesOutcome::default();
 
-        for der_cert in der_certs {
-            #[cfg_attr(not(feature = "logging"), allow(unused_variables))]
-            match self.add(&key::Certificate(der_cert.clone())) {
-                Ok(_) => valid_count += 1,
+        for (index, der_cert) in der_certs.iter().enumerate() {
+            match OwnedTrustAnchor::try_from_der(der_cert) {
+                Ok(ota) => {
+                    if known.insert((ota.subject().to_vec(), ota.spki().to_vec())) {
+                        self.roots.push(ota);
+                        outcome.added += 1;
+                    } else {
+                        trace!("duplicate cert der {:?}", der_cert);
+                        outcome.duplicates.push(index);
+                    }
+                }
                 Err(err) => {
                     trace!("invalid cert der {:?}", der_cert);
                     debug!("certificate parsing failed: {:?}", err);
-                    invalid_count += 1
+                    outcome.invalid.push((index, err));
                 }
             }
         }
 
         debug!(
-            "add_parsable_certificates processed {} valid and {} invalid certs",
-            valid_count, invalid_count
+            "add_parsable_certificates processed {} valid, {} duplicate and {} invalid certs",
+            outcome.added,
+            outcome.duplicates.len(),
+            outcome.invalid.len()
         );
 
-        (valid_count, invalid_count)
+        outcome
+    }
+
+    /// Read a PEM bundle from `reader`, and add the certificates it
+    /// contains in the same best-effort fashion as
+    /// `add_parsable_certificates`.
+    ///
+    /// Text outside PEM sections, and sections other than `CERTIFICATE`,
+    /// are skipped.  Sections which cannot be decoded are counted as
+    /// ignored.  Only errors from `reader` itself fail the whole call.
+    ///
+    /// Returns the number of certificates added, and the number that were ignored.
+    #[cfg(feature = "pem_files")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "pem_files")))]
+    pub fn add_pem_file(&mut self, reader: &mut dyn BufRead) -> Result<(usize, usize), Error> {
+        let mut der_certs = Vec::new();
+        let mut undecodable = 0;
+
+        loop {
+            match rustls_pemfile::read_one(reader) {
+                Ok(Some(rustls_pemfile::Item::X509Certificate(der))) => der_certs.push(der),
+                Ok(Some(_)) => {}
+                Ok(None) => break,
+                Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                    debug!("skipping invalid PEM section: {}", err);
+                    undecodable += 1;
+                }
+                Err(err) => return Err(Error::InvalidPemFile(err.to_string())),
+            }
+        }
+
+        let (valid_count, invalid_count) = self.add_parsable_certificates(&der_certs);
+        Ok((valid_count, invalid_count + undecodable))
+    }
+
+    /// Add the certificates in the files in the directory `dir`, such as
+    /// an OpenSSL `CApath` directory like `/etc/ssl/certs`.
+    ///
+    /// Each file may be a PEM bundle, read as with `add_pem_file`, or a
+    /// single DER-encoded certificate.  A file is loaded only once,
+    /// however many links to it the directory has, so the symlinks named
+    /// by subject hash in a `c_rehash` layout don't add duplicates; the
+    /// CRLs in such a layout (`<hash>.r<n>`) are skipped.  Subdirectories
+    /// are not searched.
+    ///
+    /// Returns the outcome for each file loaded, in order of name.
+    /// Only failure to list `dir` fails the whole call.
+    #[cfg(feature = "pem_files")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "pem_files")))]
+    pub fn add_from_dir(&mut self, dir: &Path) -> Result<Vec<CertFileOutcome>, Error> {
+        let cannot_read = |path: &Path, err: io::Error| {
+            Error::General(format!("cannot read {}: {}", path.display(), err))
+        };
+
+        let mut paths = fs::read_dir(dir)
+            .and_then(|entries| {
+                entries
+                    .map(|entry| entry.map(|entry| entry.path()))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .map_err(|err| cannot_read(dir, err))?;
+        paths.sort();
+
+        let mut seen = HashSet::new();
+        let mut outcomes = Vec::new();
+        for path in paths {
+            if is_hashed_crl_name(&path) || !path.is_file() {
+                continue;
+            }
+            if let Ok(real_path) = fs::canonicalize(&path) {
+                if !seen.insert(real_path) {
+                    trace!("skipping {:?}, already loaded", path);
+                    continue;
+                }
+            }
+
+            let result = match fs::read(&path) {
+                Ok(contents) => Ok(self.add_cert_file_contents(&contents)),
+                Err(err) => Err(cannot_read(&path, err)),
+            };
+            debug!("loaded {:?}: {:?}", path, result);
+            outcomes.push(CertFileOutcome { path, result });
+        }
+
+        Ok(outcomes)
+    }
+
+    /// Add the certificates in `contents`, a PEM bundle or a single
+    /// DER-encoded certificate.
+    #[cfg(feature = "pem_files")]
+    fn add_cert_file_contents(&mut self, contents: &[u8]) -> (usize, usize) {
+        if contents.first() == Some(&x509::SEQUENCE) {
+            return self.add_parsable_certificates(&[contents.to_vec()]);
+        }
+
+        // add_pem_file only fails when reading fails, and reading from a
+        // slice can't.
+        self.add_pem_file(&mut io::BufReader::new(contents))
+            .unwrap_or((0, 0))
+    }
+
+    /// Encode this store's anchors in a compact binary form, for loading
+    /// with [`RootCertStore::deserialize`].
+    ///
+    /// The anchors are stored already extracted from their certificates,
+    /// so loading them does no certificate parsing.  The form is
+    /// versioned; output from one version of rustls may be rejected by
+    /// another, in which case the anchors should be loaded from their
+    /// certificates again.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = SERIALIZED_MAGIC.to_vec();
+        self.encode(&mut bytes);
+        bytes
+    }
+
+    /// Decode a store encoded by [`RootCertStore::serialize`].
+    ///
+    /// Fails with `Error::General` if `bytes` is malformed or was
+    /// written by an incompatible version, or if any anchor isn't
+    /// well-formed, as for `from_trust_anchors`.
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, Error> {
+        let malformed = || Error::General("malformed serialized root store".into());
+        let body = bytes
+            .strip_prefix(&SERIALIZED_MAGIC[..])
+            .ok_or_else(malformed)?;
+
+        let mut reader = Reader::init(body);
+        let store = Self::read(&mut reader).ok_or_else(malformed)?;
+        if reader.any_left()
+            || !store
+                .roots
+                .iter()
+                .all(|ota| trust_anchor_is_well_formed(&ota.to_trust_anchor()))
+        {
+            return Err(malformed());
+        }
+
+        Ok(store)
+    }
+
+    /// Make a store containing the roots trusted by the operating
+    /// system.
+    ///
+    /// On Linux and other Unix systems, these are read from the PEM
+    /// bundle named by the `SSL_CERT_FILE` environment variable, or else
+    /// from the distribution's bundle in a well-known location.
+    /// Certificates which can't be parsed are skipped, as with
+    /// `add_parsable_certificates`.
+    ///
+    /// Other platforms, including macOS and Windows, qualify their roots
+    /// with trust settings that can only be read through system APIs.
+    /// Those aren't supported, and this function returns an error there.
+    #[cfg(feature = "platform_roots")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "platform_roots")))]
+    pub fn from_platform() -> Result<RootCertStore, Error> {
+        let mut store = RootCertStore::empty();
+        crate::platform_roots::load(&mut store)?;
+        Ok(store)
+    }
+}
+
+impl Codec for RootCertStore {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        (self.roots.len() as u32).encode(bytes);
+        for anchor in &self.roots {
+            anchor.encode(bytes);
+        }
+    }
+
+    fn read(r: &mut Reader) -> Option<Self> {
+        let mut store = Self::empty();
+        for _ in 0..u32::read(r)? {
+            store
+                .roots
+                .push(OwnedTrustAnchor::read(r)?);
+        }
+        Some(store)
+    }
+}
+
+/// Is `path` named like a CRL in a `c_rehash` directory: eight hex
+/// digits, `.r` and a number?
+#[cfg(feature = "pem_files")]
+fn is_hashed_crl_name(path: &Path) -> bool {
+    let name = match path
+        .file_name()
+        .and_then(|name| name.to_str())
+    {
+        Some(name) => name,
+        None => return false,
+    };
+
+    match name.find(".r") {
+        Some(dot) => {
+            let (hash, n) = (&name[..dot], &name[dot + 2..]);
+            hash.len() == 8
+                && hash
+                    .bytes()
+                    .all(|b| b.is_ascii_hexdigit())
+                && !n.is_empty()
+                && n.bytes().all(|b| b.is_ascii_digit())
+        }
+        None => false,
+    }
+}
+
+/// The contents of the DER-encoded `Name` in `name`, or `name` itself
+/// if it isn't a single SEQUENCE.
+fn unwrap_name(name: &[u8]) -> &[u8] {
+    let mut reader = x509::DerReader::new(name);
+    match reader.read(x509::SEQUENCE) {
+        Some(contents) if reader.at_end() => contents,
+        _ => name,
+    }
+}
+
+fn trust_anchor_is_well_formed(ta: &webpki::TrustAnchor) -> bool {
+    fn all_elements(input: &[u8], tag: Option<u8>) -> bool {
+        let mut reader = x509::DerReader::new(input);
+        while !reader.at_end() {
+            match reader.read_element() {
+                Some((t, _, _)) if tag.is_none() || tag == Some(t) => {}
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    let name_constraints_ok = match ta.name_constraints {
+        Some(nc) => all_elements(nc, None),
+        None => true,
+    };
+
+    // Each relative distinguished name is a SET.
+    all_elements(ta.subject, Some(0x31))
+        && x509::split_spki(ta.spki).is_some()
+        && name_constraints_ok
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn permits(constraints: &NameConstraints, tag: u8, name: &[u8]) -> bool {
+        constraints.permits_name(tag, name)
+    }
+
+    #[test]
+    fn dns_name_constraints() {
+        let dns = x509::GENERAL_NAME_DNS_NAME;
+        let constraints = NameConstraints::new()
+            .permit_dns_name("Example.com")
+            .permit_dns_name(".example.net")
+            .exclude_dns_name("secret.example.com");
+
+        assert!(permits(&constraints, dns, b"example.com"));
+        assert!(permits(&constraints, dns, b"WWW.example.com"));
+        assert!(permits(&constraints, dns, b"www.example.net"));
+        assert!(!permits(&constraints, dns, b"example.net"));
+        assert!(!permits(&constraints, dns, b"badexample.com"));
+        assert!(!permits(&constraints, dns, b"secret.example.com"));
+        assert!(!permits(&constraints, dns, b"a.secret.example.com"));
+        assert!(!permits(&constraints, dns, b"*.example.com"));
+        assert!(permits(&constraints, dns, b"*.www.example.com"));
+
+        // Only DNS names are constrained.
+        assert!(permits(
+            &constraints,
+            x509::GENERAL_NAME_IP_ADDRESS,
+            &[192, 0, 2, 1]
+        ));
+    }
+
+    #[test]
+    fn ip_address_constraints() {
+        let ip = x509::GENERAL_NAME_IP_ADDRESS;
+        let constraints = NameConstraints::new()
+            .permit_ip_subnet(IpAddr::from([192, 0, 2, 0]), 24)
+            .exclude_ip_subnet(IpAddr::from([192, 0, 2, 128]), 25);
+
+        assert!(permits(&constraints, ip, &[192, 0, 2, 1]));
+        assert!(!permits(&constraints, ip, &[192, 0, 2, 129]));
+        assert!(!permits(&constraints, ip, &[198, 51, 100, 1]));
+        assert!(!permits(&constraints, ip, &[0; 16]));
+        assert!(permits(
+            &constraints,
+            x509::GENERAL_NAME_DNS_NAME,
+            b"example.com"
+        ));
+
+        let everything = NameConstraints::new().permit_ip_subnet(IpAddr::from([0; 16]), 0);
+        assert!(permits(&everything, ip, &[0x20; 16]));
+        assert!(!permits(&everything, ip, &[192, 0, 2, 1]));
+    }
+
+    #[test]
+    fn name_constraints_roundtrip() {
+        let constraints = NameConstraints::new()
+            .permit_dns_name("example.com")
+            .exclude_ip_subnet(IpAddr::from([0x20; 16]), 64)
+            .permit_ip_subnet(IpAddr::from([192, 0, 2, 0]), 24);
+        let bytes = constraints.get_encoding();
+        assert_eq!(NameConstraints::read_bytes(&bytes), Some(constraints));
+        assert_eq!(NameConstraints::read_bytes(&bytes[..bytes.len() - 1]), None);
     }
 }
@@ -0,0 +1,346 @@
+// Structured audit records of certificate verifications.
+
+use crate::anchors::DistinguishedNames;
+use crate::client::ServerName;
+use crate::error::Error;
+use crate::key::Certificate;
+use crate::msgs::enums::SignatureScheme;
+use crate::msgs::handshake::DigitallySignedStruct;
+use crate::verify::{
+    ClientCertVerified, ClientCertVerifier, HandshakeSignatureValid, ServerCertVerification,
+    ServerCertVerified, ServerCertVerifier,
+};
+use crate::x509::{general_name_to_string, name_to_string, CertFields};
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// A record of one certificate verification by an [`AuditingVerifier`].
+///
+/// The certificate fields are `None` if the end-entity certificate
+/// couldn't be parsed.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct VerificationAuditRecord {
+    /// The time the verification was made for, in seconds since the
+    /// Unix epoch.
+    pub time: u64,
+    /// For a server certificate, the name it was verified for.  For a
+    /// client certificate, the SNI sent by the client, if any.
+    pub peer_name: Option<String>,
+    /// The end-entity certificate's subject, like `CN=example.com, O=Example`.
+    pub subject: Option<String>,
+    /// The end-entity certificate's issuer, in the same form as `subject`.
+    pub issuer: Option<String>,
+    /// The start of the certificate's validity period, in seconds since
+    /// the Unix epoch.
+    pub not_before: Option<u64>,
+    /// The end of the certificate's validity period, in seconds since
+    /// the Unix epoch.
+    pub not_after: Option<u64>,
+    /// The certificate's DNS name, email address and IP address subject
+    /// alternative names, like `DNS:example.com`.
+    pub subject_alt_names: Vec<String>,
+    /// Why verification failed, or `None` if it succeeded.
+    pub error: Option<Error>,
+}
+
+impl VerificationAuditRecord {
+    fn new(
+        end_entity: &Certificate,
+        peer_name: Option<&str>,
+        now: SystemTime,
+        error: Option<Error>,
+    ) -> Self {
+        let cert = CertFields::parse(&end_entity.0);
+
+        Self {
+            time: now
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|dur| dur.as_secs())
+                .unwrap_or(0),
+            peer_name: peer_name.map(str::to_string),
+            subject: cert
+                .as_ref()
+                .and_then(|c| name_to_string(c.subject)),
+            issuer: cert
+                .as_ref()
+                .and_then(|c| name_to_string(c.issuer)),
+            not_before: cert.as_ref().map(|c| c.not_before),
+            not_after: cert.as_ref().map(|c| c.not_after),
+            subject_alt_names: cert
+                .and_then(|c| c.subject_alt_names())
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|(tag, value)| general_name_to_string(tag, value))
+                .collect(),
+            error,
+        }
+    }
+
+    /// Did verification succeed?
+    pub fn verified(&self) -> bool {
+        self.error.is_none()
+    }
+
+    /// Encode this record as a single line of JSON.
+    ///
+    /// The object has the members `time`, `peer_name`, `subject`,
+    /// `issuer`, `not_before`, `not_after`, `subject_alt_names`,
+    /// `verified` and `error`.  `error` is a description of the error,
+    /// or `null` if verification succeeded.
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        out.push('{');
+        write!(out, "\"time\":{}", self.time).unwrap();
+        out.push_str(",\"peer_name\":");
+        json_opt_string(&mut out, self.peer_name.as_deref());
+        out.push_str(",\"subject\":");
+        json_opt_string(&mut out, self.subject.as_deref());
+        out.push_str(",\"issuer\":");
+        json_opt_string(&mut out, self.issuer.as_deref());
+        out.push_str(",\"not_before\":");
+        json_opt_number(&mut out, self.not_before);
+        out.push_str(",\"not_after\":");
+        json_opt_number(&mut out, self.not_after);
+        out.push_str(",\"subject_alt_names\":[");
+        for name in &self.subject_alt_names {
+            json_string(&mut out, name);
+            out.push(',');
+        }
+        if out.ends_with(',') {
+            out.pop();
+        }
+        write!(out, "],\"verified\":{}", self.verified()).unwrap();
+        out.push_str(",\"error\":");
+        json_opt_string(
+            &mut out,
+            self.error
+                .as_ref()
+                .map(|e| e.to_string())
+                .as_deref(),
+        );
+        out.push('}');
+        out
+    }
+}
+
+fn json_string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn json_opt_string(out: &mut String, value: Option<&str>) {
+    match value {
+        Some(value) => json_string(out, value),
+        None => out.push_str("null"),
+    }
+}
+
+fn json_opt_number(out: &mut String, value: Option<u64>) {
+    match value {
+        Some(value) => write!(out, "{}", value).unwrap(),
+        None => out.push_str("null"),
+    }
+}
+
+/// A wrapper which passes a [`VerificationAuditRecord`] to a sink after
+/// each certificate verification by another verifier `V`.
+///
+/// Records are made whether verification succeeds or fails; the sink
+/// cannot change the result.  Use [`VerificationAuditRecord::to_json`]
+/// to produce one JSON line per verification.
+///
+/// If `V` completes a server certificate verification asynchronously,
+/// the record is made when the connection collects the result.
+pub struct AuditingVerifier<V> {
+    inner: V,
+    sink: Arc<dyn Fn(&VerificationAuditRecord) + Send + Sync>,
+}
+
+impl<V> AuditingVerifier<V> {
+    /// Wrap `inner`, passing a record of each verification to `sink`.
+    pub fn new(inner: V, sink: impl Fn(&VerificationAuditRecord) + Send + Sync + 'static) -> Self {
+        Self {
+            inner,
+            sink: Arc::new(sink),
+        }
+    }
+
+    fn audit<T>(
+        &self,
+        end_entity: &Certificate,
+        peer_name: Option<&str>,
+        now: SystemTime,
+        result: Result<T, Error>,
+    ) -> Result<T, Error> {
+        audit(&*self.sink, end_entity, peer_name, now, result)
+    }
+}
+
+fn audit<T>(
+    sink: &(dyn Fn(&VerificationAuditRecord) + Send + Sync),
+    end_entity: &Certificate,
+    peer_name: Option<&str>,
+    now: SystemTime,
+    result: Result<T, Error>,
+) -> Result<T, Error> {
+    let error = result.as_ref().err().cloned();
+    sink(&VerificationAuditRecord::new(
+        end_entity, peer_name, now, error,
+    ));
+    result
+}
+
+impl<V: ServerCertVerifier> ServerCertVerifier for AuditingVerifier<V> {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let result = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        );
+        self.audit(end_entity, Some(&server_name.to_str()), now, result)
+    }
+
+    fn start_verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> ServerCertVerification {
+        match self.inner.start_verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        ) {
+            ServerCertVerification::Complete(result) => ServerCertVerification::Complete(
+                self.audit(end_entity, Some(&server_name.to_str()), now, result),
+            ),
+            ServerCertVerification::Pending(pending) => {
+                let sink = Arc::clone(&self.sink);
+                let end_entity = end_entity.clone();
+                let server_name = server_name.to_str().into_owned();
+                pending.map(move |result, _| {
+                    audit(&*sink, &end_entity, Some(&server_name), now, result)
+                });
+                ServerCertVerification::Pending(pending)
+            }
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner
+            .verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner
+            .verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+
+    fn request_scts(&self) -> bool {
+        self.inner.request_scts()
+    }
+}
+
+impl<V: ClientCertVerifier> ClientCertVerifier for AuditingVerifier<V> {
+    fn offer_client_auth(&self) -> bool {
+        self.inner.offer_client_auth()
+    }
+
+    fn offer_client_auth_for_sni(&self, sni: Option<&webpki::DnsName>) -> bool {
+        self.inner.offer_client_auth_for_sni(sni)
+    }
+
+    fn client_auth_mandatory(&self, sni: Option<&webpki::DnsName>) -> Option<bool> {
+        self.inner.client_auth_mandatory(sni)
+    }
+
+    fn client_auth_root_subjects(
+        &self,
+        sni: Option<&webpki::DnsName>,
+    ) -> Option<DistinguishedNames> {
+        self.inner
+            .client_auth_root_subjects(sni)
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        sni: Option<&webpki::DnsName>,
+        now: SystemTime,
+    ) -> Result<ClientCertVerified, Error> {
+        let result = self
+            .inner
+            .verify_client_cert(end_entity, intermediates, sni, now);
+        let sni: Option<&str> = sni.map(|name| name.as_ref().into());
+        self.audit(end_entity, sni, now, result)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner
+            .verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner
+            .verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
@@ -0,0 +1,242 @@
+// Bounding the time spent verifying certificates.
+
+use crate::anchors::DistinguishedNames;
+use crate::client::ServerName;
+use crate::error::Error;
+use crate::key::Certificate;
+#[cfg(feature = "logging")]
+use crate::log::{debug, warn};
+use crate::msgs::enums::SignatureScheme;
+use crate::msgs::handshake::DigitallySignedStruct;
+use crate::verify::{
+    ClientCertVerified, ClientCertVerifier, HandshakeSignatureValid, ServerCertVerification,
+    ServerCertVerified, ServerCertVerifier,
+};
+use std::time::{Duration, Instant, SystemTime};
+
+/// A wrapper which bounds the time spent verifying certificates by
+/// another verifier `V`.
+///
+/// Chains with more intermediates than `max_intermediates` are rejected
+/// with `Error::TooManyIntermediates`, without calling `V`.  Otherwise
+/// `V` is called, and its result is replaced with
+/// `Error::VerificationTimedOut` if the call took longer than the budget.
+///
+/// Note that a call to `V` cannot be interrupted: the budget only
+/// decides whether its result is accepted.  A pathological chain still
+/// costs however long `V` (and webpki within it) takes to reject it.
+pub struct BudgetedVerifier<V> {
+    inner: V,
+    budget: Duration,
+    max_intermediates: usize,
+}
+
+impl<V> BudgetedVerifier<V> {
+    /// Wrap `inner`, allowing it `budget` per certificate verification.
+    ///
+    /// Chains may have up to 6 intermediates, the most webpki will
+    /// build a path through.
+    pub fn new(inner: V, budget: Duration) -> Self {
+        Self {
+            inner,
+            budget,
+            max_intermediates: 6,
+        }
+    }
+
+    /// Reject chains with more than `max` intermediates before
+    /// calling the inner verifier.
+    pub fn with_max_intermediates(mut self, max: usize) -> Self {
+        self.max_intermediates = max;
+        self
+    }
+
+    fn check_intermediates(&self, intermediates: &[Certificate]) -> Result<(), Error> {
+        if intermediates.len() > self.max_intermediates {
+            debug!(
+                "rejecting chain with {} intermediates before verification",
+                intermediates.len()
+            );
+            return Err(Error::TooManyIntermediates);
+        }
+        Ok(())
+    }
+
+    fn within_budget<T>(
+        &self,
+        intermediates: &[Certificate],
+        verify: impl FnOnce() -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        self.check_intermediates(intermediates)?;
+
+        let start = Instant::now();
+        let result = verify();
+        check_budget(self.budget, start.elapsed(), result)
+    }
+}
+
+fn check_budget<T>(
+    budget: Duration,
+    elapsed: Duration,
+    result: Result<T, Error>,
+) -> Result<T, Error> {
+    if elapsed > budget {
+        warn!(
+            "certificate verification took {:?}, over budget of {:?}; result was {:?}",
+            elapsed,
+            budget,
+            result.as_ref().err()
+        );
+        return Err(Error::VerificationTimedOut);
+    }
+
+    result
+}
+
+impl<V: ServerCertVerifier> ServerCertVerifier for BudgetedVerifier<V> {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        self.within_budget(intermediates, || {
+            self.inner.verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                scts,
+                ocsp_response,
+                now,
+            )
+        })
+    }
+
+    /// An asynchronous verification by `V` is measured until it is
+    /// completed.
+    fn start_verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> ServerCertVerification {
+        if let Err(err) = self.check_intermediates(intermediates) {
+            return ServerCertVerification::Complete(Err(err));
+        }
+
+        let start = Instant::now();
+        match self.inner.start_verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        ) {
+            ServerCertVerification::Complete(result) => {
+                ServerCertVerification::Complete(check_budget(self.budget, start.elapsed(), result))
+            }
+            ServerCertVerification::Pending(pending) => {
+                let budget = self.budget;
+                pending.map(move |result, completed_at| {
+                    check_budget(budget, completed_at.duration_since(start), result)
+                });
+                ServerCertVerification::Pending(pending)
+            }
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner
+            .verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner
+            .verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+
+    fn request_scts(&self) -> bool {
+        self.inner.request_scts()
+    }
+}
+
+impl<V: ClientCertVerifier> ClientCertVerifier for BudgetedVerifier<V> {
+    fn offer_client_auth(&self) -> bool {
+        self.inner.offer_client_auth()
+    }
+
+    fn offer_client_auth_for_sni(&self, sni: Option<&webpki::DnsName>) -> bool {
+        self.inner
+            .offer_client_auth_for_sni(sni)
+    }
+
+    fn client_auth_mandatory(&self, sni: Option<&webpki::DnsName>) -> Option<bool> {
+        self.inner.client_auth_mandatory(sni)
+    }
+
+    fn client_auth_root_subjects(
+        &self,
+        sni: Option<&webpki::DnsName>,
+    ) -> Option<DistinguishedNames> {
+        self.inner
+            .client_auth_root_subjects(sni)
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        sni: Option<&webpki::DnsName>,
+        now: SystemTime,
+    ) -> Result<ClientCertVerified, Error> {
+        self.within_budget(intermediates, || {
+            self.inner
+                .verify_client_cert(end_entity, intermediates, sni, now)
+        })
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner
+            .verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner
+            .verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
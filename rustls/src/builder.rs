@@ -1,11 +1,15 @@
 use crate::client::builder::ClientConfigBuilder;
+use crate::client::ClientConfig;
 use crate::error::Error;
-use crate::kx::{SupportedKxGroup, ALL_KX_GROUPS};
+use crate::kx::{self, SupportedKxGroup, ALL_KX_GROUPS};
 use crate::msgs::enums::ProtocolVersion;
 use crate::server::builder::ServerConfigBuilder;
-use crate::suites::{SupportedCipherSuite, DEFAULT_CIPHERSUITES};
+use crate::server::ServerConfig;
+use crate::suites::{self, SupportedCipherSuite, DEFAULT_CIPHERSUITES};
 use crate::versions;
 
+use std::marker::PhantomData;
+
 /// Building a [`ServerConfig`] or [`ClientConfig`] in a linker-friendly way.
 ///
 /// Linker-friendly: meaning unused cipher suites, protocol
@@ -45,6 +49,10 @@ use crate::versions;
 ///    make a [`ServerConfig`] or [`ClientConfig`] -- call [`ConfigBuilderWithVersions::for_server()`]
 ///    or [`ConfigBuilderWithVersions::for_client()`] respectively.
 /// 5. Now see [`ServerConfigBuilder`] or [`ClientConfigBuilder`] for further steps.
+///
+/// When you know from the start which config you're making, begin with
+/// [`ClientConfig::builder()`] or [`ServerConfig::builder()`] instead;
+/// see [`ConfigBuilderFor`].
 pub struct ConfigBuilder;
 
 impl ConfigBuilder {
@@ -58,12 +66,21 @@ impl ConfigBuilder {
             .with_safe_default_protocol_versions()
     }
 
+    /// Start building a [`ServerConfig`] or [`ClientConfig`] with the
+    /// cryptography chosen by `profile`.
+    pub fn with_profile(profile: ConfigProfile) -> ConfigBuilderWithVersions {
+        ConfigBuilder::with_cipher_suites(profile.cipher_suites())
+            .with_kx_groups(profile.kx_groups())
+            .with_protocol_versions(profile.protocol_versions())
+    }
+
     /// Choose a specific set of cipher suites.
     pub fn with_cipher_suites(
         cipher_suites: &[&'static SupportedCipherSuite],
     ) -> ConfigBuilderWithSuites {
         ConfigBuilderWithSuites {
             cipher_suites: cipher_suites.to_vec(),
+            side: PhantomData,
         }
     }
 
@@ -77,41 +94,206 @@ impl ConfigBuilder {
     }
 }
 
+/// The start of building a [`ClientConfig`] or [`ServerConfig`] which is
+/// known to be one or the other from the start, returned by
+/// [`ClientConfig::builder()`] and [`ServerConfig::builder()`].
+///
+/// The steps are the same as for [`ConfigBuilder`], except that the
+/// builders carry the kind of config being made, so the last step only
+/// offers the matching `for_client()` or `for_server()`.  Accepting all
+/// the defaults skips straight to that step:
+///
+/// ```
+/// # use rustls::ClientConfig;
+/// # let root_certs = rustls::RootCertStore::empty();
+/// ClientConfig::builder()
+///     .with_safe_defaults()
+///     .with_root_certificates(root_certs, &[])
+///     .with_no_client_auth();
+/// ```
+pub struct ConfigBuilderFor<Side> {
+    side: PhantomData<fn() -> Side>,
+}
+
+impl<Side> ConfigBuilderFor<Side> {
+    pub(crate) fn new() -> Self {
+        Self { side: PhantomData }
+    }
+
+    /// Choose a specific set of cipher suites.
+    pub fn with_cipher_suites(
+        self,
+        cipher_suites: &[&'static SupportedCipherSuite],
+    ) -> ConfigBuilderWithSuites<Side> {
+        ConfigBuilderWithSuites {
+            cipher_suites: cipher_suites.to_vec(),
+            side: PhantomData,
+        }
+    }
+
+    /// Choose the default set of cipher suites, as
+    /// [`ConfigBuilder::with_safe_default_cipher_suites()`] does.
+    pub fn with_safe_default_cipher_suites(self) -> ConfigBuilderWithSuites<Side> {
+        self.with_cipher_suites(DEFAULT_CIPHERSUITES)
+    }
+
+    fn with_safe_default_crypto(self) -> ConfigBuilderWithVersions<Side> {
+        self.with_safe_default_cipher_suites()
+            .with_safe_default_kx_groups()
+            .with_safe_default_protocol_versions()
+    }
+
+    fn with_profile_crypto(self, profile: ConfigProfile) -> ConfigBuilderWithVersions<Side> {
+        self.with_cipher_suites(profile.cipher_suites())
+            .with_kx_groups(profile.kx_groups())
+            .with_protocol_versions(profile.protocol_versions())
+    }
+}
+
+impl ConfigBuilderFor<ClientConfig> {
+    /// Accept defaults for the underlying cryptography, as
+    /// [`ConfigBuilder::with_safe_defaults()`] does, and continue
+    /// building the `ClientConfig`.  The defaults are always usable,
+    /// so unlike [`ConfigBuilderWithVersions::for_client()`] this can't
+    /// fail.
+    pub fn with_safe_defaults(self) -> ClientConfigBuilder {
+        self.with_safe_default_crypto()
+            .into_client_builder()
+    }
+
+    /// Use the cryptography chosen by `profile`, and continue building
+    /// the `ClientConfig`.  Every profile is usable, so this can't fail.
+    pub fn with_profile(self, profile: ConfigProfile) -> ClientConfigBuilder {
+        self.with_profile_crypto(profile)
+            .into_client_builder()
+    }
+}
+
+impl ConfigBuilderFor<ServerConfig> {
+    /// Accept defaults for the underlying cryptography, as
+    /// [`ConfigBuilder::with_safe_defaults()`] does, and continue
+    /// building the `ServerConfig`.  The defaults are always usable,
+    /// so unlike [`ConfigBuilderWithVersions::for_server()`] this can't
+    /// fail.
+    pub fn with_safe_defaults(self) -> ServerConfigBuilder {
+        self.with_safe_default_crypto()
+            .into_server_builder()
+    }
+
+    /// Use the cryptography chosen by `profile`, and continue building
+    /// the `ServerConfig`.  Every profile is usable, so this can't fail.
+    pub fn with_profile(self, profile: ConfigProfile) -> ServerConfigBuilder {
+        self.with_profile_crypto(profile)
+            .into_server_builder()
+    }
+}
+
+/// Presets for the protocol versions, cipher suites and key exchange
+/// groups, following the configurations in Mozilla's server-side TLS
+/// guidance (version 5.7), for use with [`ConfigBuilder::with_profile()`]
+/// and [`ConfigBuilderFor`]'s `with_profile()`.
+///
+/// Each profile is the part of Mozilla's configuration which rustls
+/// implements, with the cipher suites in Mozilla's order.  rustls
+/// implements neither TLS1.1 and earlier nor any of the suites Mozilla
+/// adds for old clients, so [`ConfigProfile::Old`] is the same as
+/// [`ConfigProfile::Intermediate`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigProfile {
+    /// For services with clients which all support TLS1.3.
+    Modern,
+    /// For general-purpose services: TLS1.2 and TLS1.3, with forward
+    /// secrecy and authenticated encryption.
+    Intermediate,
+    /// For services which must be reachable by very old clients.
+    Old,
+}
+
+impl ConfigProfile {
+    /// The cipher suites the profile enables, in order of preference.
+    pub fn cipher_suites(self) -> &'static [&'static SupportedCipherSuite] {
+        static MODERN: &[&SupportedCipherSuite] = &[
+            &suites::TLS13_AES_128_GCM_SHA256,
+            &suites::TLS13_AES_256_GCM_SHA384,
+            &suites::TLS13_CHACHA20_POLY1305_SHA256,
+        ];
+        static INTERMEDIATE: &[&SupportedCipherSuite] = &[
+            &suites::TLS13_AES_128_GCM_SHA256,
+            &suites::TLS13_AES_256_GCM_SHA384,
+            &suites::TLS13_CHACHA20_POLY1305_SHA256,
+            &suites::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
+            &suites::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+            &suites::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
+            &suites::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+            &suites::TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256,
+            &suites::TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+        ];
+
+        match self {
+            Self::Modern => MODERN,
+            Self::Intermediate | Self::Old => INTERMEDIATE,
+        }
+    }
+
+    /// The key exchange groups the profile enables, in order of
+    /// preference.
+    pub fn kx_groups(self) -> &'static [&'static SupportedKxGroup] {
+        static GROUPS: &[&SupportedKxGroup] = &[&kx::X25519, &kx::SECP256R1, &kx::SECP384R1];
+        GROUPS
+    }
+
+    /// The protocol versions the profile enables.
+    pub fn protocol_versions(self) -> &'static [&'static versions::SupportedProtocolVersion] {
+        static TLS13_ONLY: &[&versions::SupportedProtocolVersion] = &[&versions::TLS13];
+
+        match self {
+            Self::Modern => TLS13_ONLY,
+            Self::Intermediate | Self::Old => versions::ALL_VERSIONS,
+        }
+    }
+}
+
 /// A [`ConfigBuilder`] where we know the cipher suites.
-pub struct ConfigBuilderWithSuites {
+///
+/// `Side` is `()` if this could still make either config, or else
+/// [`ClientConfig`] or [`ServerConfig`]: see [`ConfigBuilderFor`].
+pub struct ConfigBuilderWithSuites<Side = ()> {
     cipher_suites: Vec<&'static SupportedCipherSuite>,
+    side: PhantomData<fn() -> Side>,
 }
 
-impl ConfigBuilderWithSuites {
+impl<Side> ConfigBuilderWithSuites<Side> {
     /// Choose a specific set of key exchange groups.
     pub fn with_kx_groups(
         self,
         kx_groups: &[&'static SupportedKxGroup],
-    ) -> ConfigBuilderWithKxGroups {
+    ) -> ConfigBuilderWithKxGroups<Side> {
         ConfigBuilderWithKxGroups {
             cipher_suites: self.cipher_suites,
             kx_groups: kx_groups.to_vec(),
+            side: PhantomData,
         }
     }
 
     /// Choose the default set of key exchange groups.
     ///
     /// This is a safe default: rustls doesn't implement any poor-quality groups.
-    pub fn with_safe_default_kx_groups(self) -> ConfigBuilderWithKxGroups {
+    pub fn with_safe_default_kx_groups(self) -> ConfigBuilderWithKxGroups<Side> {
         self.with_kx_groups(&ALL_KX_GROUPS)
     }
 }
 
 /// A [`ConfigBuilder`] where we know the cipher suites and key exchange
 /// groups.
-pub struct ConfigBuilderWithKxGroups {
+pub struct ConfigBuilderWithKxGroups<Side = ()> {
     cipher_suites: Vec<&'static SupportedCipherSuite>,
     kx_groups: Vec<&'static SupportedKxGroup>,
+    side: PhantomData<fn() -> Side>,
 }
 
-impl ConfigBuilderWithKxGroups {
+impl<Side> ConfigBuilderWithKxGroups<Side> {
     /// Accept the default protocol versions: both TLS1.2 and TLS1.3 are enabled.
-    pub fn with_safe_default_protocol_versions(self) -> ConfigBuilderWithVersions {
+    pub fn with_safe_default_protocol_versions(self) -> ConfigBuilderWithVersions<Side> {
         self.with_protocol_versions(versions::DEFAULT_VERSIONS)
     }
 
@@ -119,24 +301,26 @@ impl ConfigBuilderWithKxGroups {
     pub fn with_protocol_versions(
         self,
         versions: &[&'static versions::SupportedProtocolVersion],
-    ) -> ConfigBuilderWithVersions {
+    ) -> ConfigBuilderWithVersions<Side> {
         ConfigBuilderWithVersions {
             cipher_suites: self.cipher_suites,
             kx_groups: self.kx_groups,
             versions: versions::EnabledVersions::new(versions),
+            side: PhantomData,
         }
     }
 }
 
 /// A [`ConfigBuilder`] where we know the cipher suites, key exchange groups,
 /// and protocol versions.
-pub struct ConfigBuilderWithVersions {
+pub struct ConfigBuilderWithVersions<Side = ()> {
     cipher_suites: Vec<&'static SupportedCipherSuite>,
     kx_groups: Vec<&'static SupportedKxGroup>,
     versions: versions::EnabledVersions,
+    side: PhantomData<fn() -> Side>,
 }
 
-impl ConfigBuilderWithVersions {
+impl<Side> ConfigBuilderWithVersions<Side> {
     fn validate(&self) -> Result<(), Error> {
         let mut any_usable_suite = false;
         for suite in &self.cipher_suites {
@@ -159,17 +343,31 @@ impl ConfigBuilderWithVersions {
         Ok(())
     }
 
+    fn into_client_builder(self) -> ClientConfigBuilder {
+        ClientConfigBuilder {
+            cipher_suites: self.cipher_suites,
+            kx_groups: self.kx_groups,
+            versions: self.versions,
+        }
+    }
+
+    fn into_server_builder(self) -> ServerConfigBuilder {
+        ServerConfigBuilder {
+            cipher_suites: self.cipher_suites,
+            kx_groups: self.kx_groups,
+            versions: self.versions,
+        }
+    }
+}
+
+impl ConfigBuilderWithVersions {
     /// Continue building a `ClientConfig`.
     ///
     /// This may fail, if the previous selections are contradictory or
     /// not useful (for example, if no protocol versions are enabled).
     pub fn for_client(self) -> Result<ClientConfigBuilder, Error> {
         self.validate()?;
-        Ok(ClientConfigBuilder {
-            cipher_suites: self.cipher_suites,
-            kx_groups: self.kx_groups,
-            versions: self.versions,
-        })
+        Ok(self.into_client_builder())
     }
 
     /// Continue building a `ServerConfig`.
@@ -178,10 +376,28 @@ impl ConfigBuilderWithVersions {
     /// not useful (for example, if no protocol versions are enabled).
     pub fn for_server(self) -> Result<ServerConfigBuilder, Error> {
         self.validate()?;
-        Ok(ServerConfigBuilder {
-            cipher_suites: self.cipher_suites,
-            kx_groups: self.kx_groups,
-            versions: self.versions,
-        })
+        Ok(self.into_server_builder())
+    }
+}
+
+impl ConfigBuilderWithVersions<ClientConfig> {
+    /// Continue building the `ClientConfig`.
+    ///
+    /// This may fail, if the previous selections are contradictory or
+    /// not useful (for example, if no protocol versions are enabled).
+    pub fn for_client(self) -> Result<ClientConfigBuilder, Error> {
+        self.validate()?;
+        Ok(self.into_client_builder())
+    }
+}
+
+impl ConfigBuilderWithVersions<ServerConfig> {
+    /// Continue building the `ServerConfig`.
+    ///
+    /// This may fail, if the previous selections are contradictory or
+    /// not useful (for example, if no protocol versions are enabled).
+    pub fn for_server(self) -> Result<ServerConfigBuilder, Error> {
+        self.validate()?;
+        Ok(self.into_server_builder())
     }
 }
@@ -1,11 +1,14 @@
 use crate::anchors;
 use crate::client::handy;
-use crate::client::{ClientConfig, ResolvesClientCert};
+use crate::client::{ClientConfig, ClientHelloProfile, RenegotiationPolicy, ResolvesClientCert};
 use crate::error::Error;
+use crate::fips::FipsModeVerifier;
 use crate::key;
 use crate::keylog::NoKeyLog;
 use crate::kx::SupportedKxGroup;
+use crate::msgs::enums::PSKKeyExchangeMode;
 use crate::suites::SupportedCipherSuite;
+use crate::time_provider::DefaultTimeProvider;
 use crate::verify;
 use crate::versions;
 
@@ -76,6 +79,40 @@ impl ClientConfigBuilder {
         }
     }
 
+    /// Verify server certificates with `verifier`.
+    ///
+    /// This is like [`ClientConfigBuilder::with_root_certificates`], but allows
+    /// the [`verify::WebPkiVerifier`] to be configured with stricter policies.
+    /// Keep a clone of `verifier` to read its statistics later.
+    pub fn with_webpki_verifier(
+        self,
+        verifier: Arc<verify::WebPkiVerifier>,
+    ) -> ClientConfigBuilderWithCertVerifier {
+        ClientConfigBuilderWithCertVerifier {
+            cipher_suites: self.cipher_suites,
+            kx_groups: self.kx_groups,
+            versions: self.versions,
+            verifier,
+        }
+    }
+
+    /// Verify server certificates with `verifier`, which only accepts
+    /// FIPS-approved algorithms.
+    ///
+    /// Note that this doesn't restrict the cipher suites or key exchange
+    /// groups: choose FIPS-approved ones when making the builder.
+    pub fn with_fips_verifier(
+        self,
+        verifier: Arc<FipsModeVerifier>,
+    ) -> ClientConfigBuilderWithCertVerifier {
+        ClientConfigBuilderWithCertVerifier {
+            cipher_suites: self.cipher_suites,
+            kx_groups: self.kx_groups,
+            versions: self.versions,
+            verifier,
+        }
+    }
+
     #[cfg(feature = "dangerous_configuration")]
     pub fn with_custom_certificate_verifier(
         self,
@@ -132,13 +169,25 @@ impl ClientConfigBuilderWithCertVerifier {
             alpn_protocols: Vec::new(),
             session_storage: handy::ClientSessionMemoryCache::new(256),
             max_fragment_size: None,
+            max_fragment_length: None,
+            record_size_limit: None,
             client_auth_cert_resolver,
             enable_tickets: true,
+            external_psk: None,
+            psk_key_exchange_modes: vec![PSKKeyExchangeMode::PSK_DHE_KE],
             versions: self.versions,
             enable_sni: true,
+            sni_decoy_name: None,
             verifier: self.verifier,
             key_log: Arc::new(NoKeyLog {}),
+            time_provider: Arc::new(DefaultTimeProvider),
             enable_early_data: false,
+            enable_ech_grease: false,
+            enable_grease: false,
+            client_hello_profile: ClientHelloProfile::default(),
+            enable_false_start: false,
+            certificate_authorities: Vec::new(),
+            renegotiation_policy: RenegotiationPolicy::Refuse,
         }
     }
 }
@@ -3,6 +3,7 @@ use crate::log::trace;
 use crate::msgs::enums::ExtensionType;
 use crate::msgs::handshake::CertificatePayload;
 use crate::msgs::handshake::DigitallySignedStruct;
+use crate::msgs::handshake::GreaseValues;
 use crate::msgs::handshake::SCTList;
 use crate::msgs::handshake::ServerExtension;
 use crate::sign;
@@ -13,6 +14,8 @@ pub struct ServerCertDetails {
     pub cert_chain: CertificatePayload,
     pub ocsp_response: Vec<u8>,
     pub scts: Option<SCTList>,
+    /// `cert_chain` is a single raw public key (RFC7250).
+    pub raw_public_key: bool,
 }
 
 impl ServerCertDetails {
@@ -20,11 +23,13 @@ impl ServerCertDetails {
         cert_chain: CertificatePayload,
         ocsp_response: Vec<u8>,
         scts: Option<SCTList>,
+        raw_public_key: bool,
     ) -> ServerCertDetails {
         ServerCertDetails {
             cert_chain,
             ocsp_response,
             scts,
+            raw_public_key,
         }
     }
 
@@ -39,11 +44,12 @@ impl ServerCertDetails {
 
 pub struct ServerKxDetails {
     pub kx_params: Vec<u8>,
-    pub kx_sig: DigitallySignedStruct,
+    /// None for the ECDHE_PSK suites, where the PSK authenticates the kx.
+    pub kx_sig: Option<DigitallySignedStruct>,
 }
 
 impl ServerKxDetails {
-    pub fn new(params: Vec<u8>, sig: DigitallySignedStruct) -> ServerKxDetails {
+    pub fn new(params: Vec<u8>, sig: Option<DigitallySignedStruct>) -> ServerKxDetails {
         ServerKxDetails {
             kx_params: params,
             kx_sig: sig,
@@ -53,12 +59,15 @@ impl ServerKxDetails {
 
 pub struct ClientHelloDetails {
     pub sent_extensions: Vec<ExtensionType>,
+    /// Repeated in a second ClientHello.
+    pub grease: Option<GreaseValues>,
 }
 
 impl ClientHelloDetails {
-    pub fn new() -> ClientHelloDetails {
+    pub fn new(grease: Option<GreaseValues>) -> ClientHelloDetails {
         ClientHelloDetails {
             sent_extensions: Vec::new(),
+            grease,
         }
     }
 
@@ -1,7 +1,7 @@
 #[cfg(feature = "logging")]
 use crate::bs_debug;
 use crate::check::check_message;
-use crate::conn::{ConnectionCommon, ConnectionRandoms};
+use crate::conn::{ConnectionCommon, ConnectionRandoms, MIN_RECORD_SIZE_LIMIT};
 use crate::error::Error;
 use crate::hash_hs::HandshakeHash;
 use crate::key_schedule::KeyScheduleEarly;
@@ -13,22 +13,24 @@ use crate::msgs::base::Payload;
 use crate::msgs::base::PayloadU16;
 use crate::msgs::codec::{Codec, Reader};
 use crate::msgs::enums::{AlertDescription, CipherSuite, Compression, ProtocolVersion};
-use crate::msgs::enums::{ContentType, ExtensionType, HandshakeType};
-use crate::msgs::enums::{ECPointFormat, PSKKeyExchangeMode};
+use crate::msgs::enums::{CertificateType, ContentType, ExtensionType, HandshakeType};
+use crate::msgs::enums::{ECPointFormat, MaxFragmentLength};
 use crate::msgs::handshake::{CertificateStatusRequest, SCTList};
-use crate::msgs::handshake::{ClientExtension, HasServerExtensions};
+use crate::msgs::handshake::{ClientExtension, EncryptedClientHelloOuter, HasServerExtensions};
 use crate::msgs::handshake::{ClientHelloPayload, HandshakeMessagePayload, HandshakePayload};
 use crate::msgs::handshake::{ConvertProtocolNameList, ProtocolNameList};
 use crate::msgs::handshake::{ECPointFormatList, SupportedPointFormats};
+use crate::msgs::handshake::{GreaseValues, UnknownExtension};
 use crate::msgs::handshake::{HelloRetryRequest, KeyShareEntry};
 use crate::msgs::handshake::{Random, SessionID};
 use crate::msgs::message::{Message, MessagePayload};
 use crate::msgs::persist;
 use crate::ticketer::TimeBase;
+use crate::verify::{PendingVerification, ServerCertVerification, ServerCertVerified};
 use crate::SupportedCipherSuite;
 
-use crate::client::common::ClientHelloDetails;
-use crate::client::{tls12, tls13, ClientConfig, ClientConnectionData};
+use crate::client::common::{ClientHelloDetails, ServerCertDetails};
+use crate::client::{tls12, tls13, ClientConfig, ClientConnectionData, ServerName};
 
 use std::sync::Arc;
 
@@ -50,6 +52,19 @@ pub(super) trait State: Send + Sync {
     }
 
     fn perhaps_write_key_update(&mut self, _common: &mut ConnectionCommon) {}
+
+    /// Returns true if this state is waiting for the server certificate
+    /// verification to complete.
+    fn is_waiting(&self) -> bool {
+        false
+    }
+
+    /// Continue the handshake once a waiting state's verification has
+    /// completed, or return this state if it has not.  Only called on
+    /// states which are waiting.
+    fn resume(self: Box<Self>, _cx: &mut ClientContext<'_>) -> NextStateOrError {
+        unreachable!("resume() on a state which is not waiting")
+    }
 }
 
 impl crate::conn::HandleState for Box<dyn State> {
@@ -64,6 +79,15 @@ impl crate::conn::HandleState for Box<dyn State> {
         let mut cx = ClientContext { common, data };
         self.handle(&mut cx, message)
     }
+
+    fn is_waiting(&self) -> bool {
+        (**self).is_waiting()
+    }
+
+    fn resume(self, data: &mut Self::Data, common: &mut ConnectionCommon) -> Result<Self, Error> {
+        let mut cx = ClientContext { common, data };
+        State::resume(self, &mut cx)
+    }
 }
 
 pub(super) struct ClientContext<'a> {
@@ -72,24 +96,24 @@ pub(super) struct ClientContext<'a> {
 }
 
 fn find_session(
-    dns_name: webpki::DnsNameRef,
+    server_name: &ServerName,
     config: &ClientConfig,
     #[cfg(feature = "quic")] cx: &mut ClientContext<'_>,
 ) -> Option<persist::ClientSessionValueWithResolvedCipherSuite> {
-    let key = persist::ClientSessionKey::session_for_dns_name(dns_name);
+    let key = persist::ClientSessionKey::session_for_server_name(server_name);
     let key_buf = key.get_encoding();
 
     let value = config
         .session_storage
         .get(&key_buf)
         .or_else(|| {
-            debug!("No cached session for {:?}", dns_name);
+            debug!("No cached session for {:?}", server_name);
             None
         })?;
 
     let mut reader = Reader::init(&value[..]);
     let result = persist::ClientSessionValue::read(&mut reader).and_then(|csv| {
-        let time = TimeBase::now().ok()?;
+        let time = TimeBase::from_provider(config.time_provider.as_ref()).ok()?;
         csv.resolve_cipher_suite(&config.cipher_suites, time)
     });
     if let Some(result) = result {
@@ -111,8 +135,8 @@ fn find_session(
 }
 
 pub(super) fn start_handshake(
-    dns_name: webpki::DnsName,
-    extra_exts: Vec<ClientExtension>,
+    server_name: ServerName,
+    mut extra_exts: Vec<ClientExtension>,
     config: Arc<ClientConfig>,
     cx: &mut ClientContext<'_>,
 ) -> NextStateOrError {
@@ -127,15 +151,19 @@ pub(super) fn start_handshake(
     let support_tls13 = config.supports_version(ProtocolVersion::TLSv1_3);
 
     let mut session_id: Option<SessionID> = None;
-    let mut resuming_session = find_session(
-        dns_name.as_ref(),
-        &config,
-        #[cfg(feature = "quic")]
-        cx,
-    );
+    let mut resuming_session = if cx.data.resumption_enabled() {
+        find_session(
+            &server_name,
+            &config,
+            #[cfg(feature = "quic")]
+            cx,
+        )
+    } else {
+        None
+    };
 
     let key_share = if support_tls13 {
-        Some(tls13::initial_key_share(&config, dns_name.as_ref())?)
+        Some(tls13::initial_key_share(&config, cx.data, &server_name)?)
     } else {
         None
     };
@@ -146,7 +174,7 @@ pub(super) fn start_handshake(
             // we're  doing an abbreviated handshake.  See section 3.4 in
             // RFC5077.
             if !resuming.ticket.0.is_empty() {
-                resuming.set_session_id(SessionID::random()?);
+                resuming.set_session_id(cx.data.new_session_id()?);
             }
             session_id = Some(resuming.session_id);
         }
@@ -159,11 +187,23 @@ pub(super) fn start_handshake(
     // https://tools.ietf.org/html/rfc8446#appendix-D.4
     // https://tools.ietf.org/html/draft-ietf-quic-tls-34#section-8.4
     if session_id.is_none() && !cx.common.is_quic() {
-        session_id = Some(SessionID::random()?);
+        session_id = Some(cx.data.new_session_id()?);
+    }
+
+    // A second ClientHello repeats this exactly, as real ECH would.
+    if support_tls13 && config.enable_ech_grease {
+        extra_exts.push(ClientExtension::EncryptedClientHello(
+            EncryptedClientHelloOuter::grease()?,
+        ));
     }
 
-    let randoms = ConnectionRandoms::for_client()?;
-    let hello_details = ClientHelloDetails::new();
+    let randoms = cx.data.randoms()?;
+    let grease = if config.enable_grease {
+        Some(GreaseValues::random()?)
+    } else {
+        None
+    };
+    let hello_details = ClientHelloDetails::new(grease);
     let sent_tls13_fake_ccs = false;
     let may_send_sct_list = config.verifier.request_scts();
     Ok(emit_client_hello_for_retry(
@@ -177,7 +217,7 @@ pub(super) fn start_handshake(
         hello_details,
         session_id,
         None,
-        dns_name,
+        server_name,
         key_share,
         extra_exts,
         may_send_sct_list,
@@ -188,7 +228,7 @@ pub(super) fn start_handshake(
 struct ExpectServerHello {
     config: Arc<ClientConfig>,
     resuming_session: Option<persist::ClientSessionValueWithResolvedCipherSuite>,
-    dns_name: webpki::DnsName,
+    server_name: ServerName,
     randoms: ConnectionRandoms,
     using_ems: bool,
     transcript: HandshakeHash,
@@ -205,6 +245,70 @@ struct ExpectServerHelloOrHelloRetryRequest {
     extra_exts: Vec<ClientExtension>,
 }
 
+/// Put `exts` in the order given by
+/// [`ClientHelloProfile::extension_order`](crate::ClientHelloProfile::extension_order).
+fn order_extensions(
+    exts: &mut [ClientExtension],
+    order: &[ExtensionType],
+    grease: Option<&GreaseValues>,
+) {
+    if order.is_empty() {
+        return;
+    }
+
+    let position = |ext: &ClientExtension| {
+        let typ = ext.get_type();
+        let placed = match grease {
+            Some(grease) if grease.is_extension(typ) => {
+                let nth = if typ == grease.first_extension { 0 } else { 1 };
+                order
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, typ)| GreaseValues::is_grease(typ.get_u16()))
+                    .nth(nth)
+                    .map(|(i, _)| i)
+            }
+            _ => order.iter().position(|t| *t == typ),
+        };
+
+        match ext {
+            ClientExtension::PresharedKey(_) => usize::MAX,
+            _ => placed.unwrap_or(order.len()),
+        }
+    };
+
+    exts.sort_by_key(position);
+}
+
+/// Add a padding extension (RFC7685) to `chp` so its encoding is
+/// `target` bytes long.  If it's already that long, nothing's added;
+/// if it's a few bytes short, it becomes a few bytes longer, since the
+/// extension's header takes four bytes.
+fn pad_client_hello(chp: &mut HandshakeMessagePayload, target: usize) {
+    let len = chp.get_encoding().len();
+    if len >= target {
+        return;
+    }
+
+    let padding = (target - len)
+        .saturating_sub(4)
+        .min(0xffff);
+    if let HandshakePayload::ClientHello(ch) = &mut chp.payload {
+        // The PSK extension must stay last.
+        let at = match ch.extensions.last() {
+            Some(ClientExtension::PresharedKey(_)) => ch.extensions.len() - 1,
+            _ => ch.extensions.len(),
+        };
+        ch.extensions.insert(
+            at,
+            ClientExtension::Unknown(UnknownExtension {
+                typ: ExtensionType::Padding,
+                payload: Payload::new(vec![0; padding]),
+            }),
+        );
+    }
+}
+
 fn emit_client_hello_for_retry(
     config: Arc<ClientConfig>,
     cx: &mut ClientContext<'_>,
@@ -216,7 +320,7 @@ fn emit_client_hello_for_retry(
     mut hello: ClientHelloDetails,
     session_id: Option<SessionID>,
     retryreq: Option<&HelloRetryRequest>,
-    dns_name: webpki::DnsName,
+    server_name: ServerName,
     key_share: Option<kx::KeyExchange>,
     extra_exts: Vec<ClientExtension>,
     may_send_sct_list: bool,
@@ -241,27 +345,76 @@ fn emit_client_hello_for_retry(
         supported_versions.push(ProtocolVersion::TLSv1_2);
     }
 
+    let grease = hello.grease;
+    let profile = &config.client_hello_profile;
     let mut exts = Vec::new();
+    if let Some(grease) = &grease {
+        exts.push(ClientExtension::Unknown(UnknownExtension {
+            typ: grease.first_extension,
+            payload: Payload::empty(),
+        }));
+        if !supported_versions.is_empty() {
+            supported_versions.insert(0, grease.version);
+        }
+    }
+
     if !supported_versions.is_empty() {
         exts.push(ClientExtension::SupportedVersions(supported_versions));
     }
-    if config.enable_sni {
+    let sni_name = match &cx.data.options.sni_name {
+        _ if !cx.data.sni_enabled(&config) => None,
+        Some(name) => Some(name),
+        None => Some(
+            config
+                .sni_decoy_name
+                .as_ref()
+                .unwrap_or(&server_name),
+        ),
+    };
+    if let Some(ServerName::DnsName(dns_name)) = sni_name {
         exts.push(ClientExtension::make_sni(dns_name.as_ref()));
     }
+    // QUIC has no records to limit.
+    if !cx.common.is_quic() {
+        if let Some(len) = config.max_fragment_length {
+            exts.push(ClientExtension::MaxFragmentLength(len));
+        }
+        if let Some(limit) = config.record_size_limit {
+            exts.push(ClientExtension::RecordSizeLimit(limit as u16));
+        }
+    }
     exts.push(ClientExtension::ECPointFormats(
         ECPointFormatList::supported(),
     ));
     exts.push(ClientExtension::NamedGroups(
-        config
-            .kx_groups
-            .iter()
-            .map(|skxg| skxg.name)
+        grease
+            .map(|grease| grease.group)
+            .into_iter()
+            .chain(
+                config
+                    .kx_groups
+                    .iter()
+                    .map(|skxg| skxg.name),
+            )
+            .chain(profile.extra_groups.iter().copied())
             .collect(),
     ));
     exts.push(ClientExtension::SignatureAlgorithms(
-        config
-            .verifier
-            .supported_verify_schemes(),
+        grease
+            .map(|grease| grease.sig_scheme)
+            .into_iter()
+            .chain(
+                config
+                    .verifier
+                    .supported_verify_schemes(),
+            )
+            .chain(
+                profile
+                    .extra_signature_schemes
+                    .iter()
+                    .copied(),
+            )
+            .collect(),
     ));
     exts.push(ClientExtension::ExtendedMasterSecretRequest);
     exts.push(ClientExtension::CertificateStatusRequest(
@@ -272,27 +425,53 @@ fn emit_client_hello_for_retry(
         exts.push(ClientExtension::SignedCertificateTimestampRequest);
     }
 
+    if config.verifier.offer_raw_public_keys() {
+        exts.push(ClientExtension::ServerCertificateType(vec![
+            CertificateType::RawPublicKey,
+            CertificateType::X509,
+        ]));
+    }
+
     if let Some(key_share) = &key_share {
         debug_assert!(support_tls13);
         let key_share = KeyShareEntry::new(key_share.group(), key_share.pubkey.as_ref());
-        exts.push(ClientExtension::KeyShare(vec![key_share]));
+        // A second ClientHello must have only the share the server asked for.
+        let grease_share = grease
+            .filter(|_| retryreq.is_none())
+            .map(|grease| KeyShareEntry::new(grease.group, &[0]));
+        exts.push(ClientExtension::KeyShare(
+            grease_share
+                .into_iter()
+                .chain(Some(key_share))
+                .collect(),
+        ));
+    }
+
+    if support_tls13
+        && !config
+            .certificate_authorities
+            .is_empty()
+    {
+        exts.push(ClientExtension::CertificateAuthorities(
+            config.certificate_authorities.clone(),
+        ));
     }
 
     if let Some(cookie) = retryreq.and_then(HelloRetryRequest::get_cookie) {
         exts.push(ClientExtension::Cookie(cookie.clone()));
     }
 
-    if support_tls13 && config.enable_tickets {
-        // We could support PSK_KE here too. Such connections don't
-        // have forward secrecy, and are similar to TLS1.2 resumption.
-        let psk_modes = vec![PSKKeyExchangeMode::PSK_DHE_KE];
-        exts.push(ClientExtension::PresharedKeyModes(psk_modes));
+    let offer_psks = support_tls13 && !config.psk_key_exchange_modes.is_empty();
+    if offer_psks && (config.enable_tickets || config.external_psk.is_some()) {
+        exts.push(ClientExtension::PresharedKeyModes(
+            config.psk_key_exchange_modes.clone(),
+        ));
     }
 
-    if !config.alpn_protocols.is_empty() {
+    let alpn_protocols = cx.data.alpn_protocols(&config);
+    if !alpn_protocols.is_empty() {
         exts.push(ClientExtension::Protocols(ProtocolNameList::from_slices(
-            &config
-                .alpn_protocols
+            &alpn_protocols
                 .iter()
                 .map(|proto| &proto[..])
                 .collect::<Vec<_>>(),
@@ -309,6 +488,7 @@ fn emit_client_hello_for_retry(
     {
         resuming_session
             .as_ref()
+            .filter(|_| offer_psks)
             .filter(|resuming| match suite {
                 Some(suite) => suite.can_resume_to(&resuming.supported_cipher_suite()),
                 None => true,
@@ -336,17 +516,87 @@ fn emit_client_hello_for_retry(
         None
     };
 
-    // Note what extensions we sent.
+    // An external PSK is offered instead of resuming a TLS1.3 session.
+    // After a HelloRetryRequest it must suit the server's chosen suite.
+    let external_psk = config
+        .external_psk
+        .as_ref()
+        .filter(|_| offer_psks && resume_version != ProtocolVersion::TLSv1_3)
+        .and_then(|psk| {
+            let psk_suite = match suite {
+                Some(suite) => Some(suite).filter(|suite| psk.usable_with(suite)),
+                None => config
+                    .cipher_suites
+                    .iter()
+                    .copied()
+                    .find(|suite| psk.usable_with(suite)),
+            };
+            psk_suite.map(|psk_suite| (psk, psk_suite))
+        });
+    if let Some((psk, psk_suite)) = external_psk {
+        tls13::prepare_external_psk(psk, psk_suite, &mut exts);
+    }
+
+    // The PSK extension must stay last.
+    let mut at = match exts.last() {
+        Some(ClientExtension::PresharedKey(_)) => exts.len() - 1,
+        _ => exts.len(),
+    };
+
+    for (typ, payload) in &profile.extra_extensions {
+        if exts
+            .iter()
+            .any(|ext| ext.get_type() == *typ)
+        {
+            continue;
+        }
+        exts.insert(
+            at,
+            ClientExtension::Unknown(UnknownExtension {
+                typ: *typ,
+                payload: Payload::new(payload.clone()),
+            }),
+        );
+        at += 1;
+    }
+
+    if let Some(grease) = &grease {
+        exts.insert(
+            at,
+            ClientExtension::Unknown(UnknownExtension {
+                typ: grease.last_extension,
+                payload: Payload::new(vec![0]),
+            }),
+        );
+    }
+
+    order_extensions(&mut exts, &profile.extension_order, grease.as_ref());
+
+    // Note what extensions we sent.  Servers mustn't respond to GREASE.
     hello.sent_extensions = exts
         .iter()
         .map(ClientExtension::get_type)
+        .filter(|typ| !matches!(&grease, Some(grease) if grease.is_extension(*typ)))
         .collect();
 
     let session_id = session_id.unwrap_or_else(SessionID::empty);
-    let mut cipher_suites: Vec<_> = config
-        .cipher_suites
-        .iter()
-        .map(|cs| cs.suite)
+    let mut cipher_suites: Vec<_> = grease
+        .map(|grease| grease.cipher_suite)
+        .into_iter()
+        .chain(
+            config
+                .cipher_suites
+                .iter()
+                // ECDHE_PSK suites are no use without a PSK.
+                .filter(|cs| !cs.uses_psk() || config.external_psk.is_some())
+                .map(|cs| cs.suite),
+        )
+        .chain(
+            profile
+                .extra_cipher_suites
+                .iter()
+                .copied(),
+        )
         .collect();
     // We don't do renegotiation at all, in fact.
     cipher_suites.push(CipherSuite::TLS_EMPTY_RENEGOTIATION_INFO_SCSV);
@@ -363,9 +613,23 @@ fn emit_client_hello_for_retry(
         }),
     };
 
+    if let Some(target) = profile.padded_length {
+        pad_client_hello(&mut chp, target);
+        if let HandshakePayload::ClientHello(ch) = &mut chp.payload {
+            order_extensions(
+                &mut ch.extensions,
+                &profile.extension_order,
+                grease.as_ref(),
+            );
+        }
+    }
+
     let early_key_schedule = if let Some(resuming) = fill_in_binder {
         let schedule = tls13::fill_in_psk_binder(&resuming, &transcript, &mut chp);
-        Some((resuming, schedule))
+        Some((Some(resuming), schedule))
+    } else if let Some((psk, psk_suite)) = external_psk {
+        let schedule = tls13::fill_in_external_psk_binder(psk, psk_suite, &transcript, &mut chp);
+        Some((None, schedule))
     } else {
         None
     };
@@ -395,9 +659,10 @@ fn emit_client_hello_for_retry(
 
     // Calculate the hash of ClientHello and use it to derive EarlyTrafficSecret
     let early_key_schedule = early_key_schedule.map(|(resuming, schedule)| {
-        if !cx.data.early_data.is_enabled() {
-            return schedule;
-        }
+        let resuming = match resuming {
+            Some(resuming) if cx.data.early_data.is_enabled() => resuming,
+            _ => return schedule,
+        };
 
         tls13::derive_early_traffic_secret(
             &*config.key_log,
@@ -414,7 +679,7 @@ fn emit_client_hello_for_retry(
     let next = ExpectServerHello {
         config,
         resuming_session,
-        dns_name,
+        server_name,
         randoms,
         using_ems,
         transcript,
@@ -441,8 +706,9 @@ pub(super) fn process_alpn_protocol(
     cx.common.alpn_protocol = proto.map(ToOwned::to_owned);
 
     if let Some(alpn_protocol) = &cx.common.alpn_protocol {
-        if !config
-            .alpn_protocols
+        if !cx
+            .data
+            .alpn_protocols(config)
             .contains(alpn_protocol)
         {
             return Err(cx
@@ -461,6 +727,53 @@ pub(super) fn process_alpn_protocol(
     Ok(())
 }
 
+pub(super) fn process_record_limits(
+    common: &mut ConnectionCommon,
+    config: &ClientConfig,
+    max_fragment_length: Option<MaxFragmentLength>,
+    record_size_limit: Option<u16>,
+) -> Result<(), Error> {
+    if max_fragment_length.is_some() && record_size_limit.is_some() {
+        return Err(common.illegal_param("server sent max fragment length and record size limit"));
+    }
+
+    if let Some(len) = max_fragment_length {
+        if Some(len) != config.max_fragment_length {
+            return Err(common.illegal_param("server sent different max fragment length"));
+        }
+
+        debug!("Max fragment length is {:?}", len);
+        common.set_max_fragment_length(len);
+    }
+
+    if let Some(limit) = record_size_limit {
+        if usize::from(limit) < MIN_RECORD_SIZE_LIMIT {
+            return Err(common.illegal_param("server sent too small record size limit"));
+        }
+
+        debug!("Server's record size limit is {}", limit);
+        common.set_record_size_limits(limit);
+    }
+
+    Ok(())
+}
+
+/// Returns true if the server chose to authenticate with a raw public
+/// key rather than a certificate chain.
+pub(super) fn process_server_certificate_type(
+    common: &mut ConnectionCommon,
+    cert_type: Option<CertificateType>,
+) -> Result<bool, Error> {
+    match cert_type {
+        None | Some(CertificateType::X509) => Ok(false),
+        Some(CertificateType::RawPublicKey) => {
+            debug!("Server will send a raw public key");
+            Ok(true)
+        }
+        Some(_) => Err(common.illegal_param("server chose non-offered certificate type")),
+    }
+}
+
 pub fn sct_list_is_invalid(scts: &SCTList) -> bool {
     scts.is_empty() || scts.iter().any(|sct| sct.0.is_empty())
 }
@@ -544,6 +857,12 @@ impl State for ExpectServerHello {
         // Extract ALPN protocol
         if !cx.common.is_tls13() {
             process_alpn_protocol(cx, &self.config, server_hello.get_alpn_protocol())?;
+            process_record_limits(
+                cx.common,
+                &self.config,
+                server_hello.get_max_fragment_length(),
+                server_hello.get_record_size_limit(),
+            )?;
         }
 
         // If ECPointFormats extension is supplied by the server, it must contain
@@ -561,6 +880,7 @@ impl State for ExpectServerHello {
         let suite = self
             .config
             .find_cipher_suite(server_hello.cipher_suite)
+            .filter(|suite| !suite.uses_psk() || self.config.external_psk.is_some())
             .ok_or_else(|| {
                 cx.common
                     .send_fatal_alert(AlertDescription::HandshakeFailure);
@@ -593,7 +913,7 @@ impl State for ExpectServerHello {
                 cx,
                 server_hello,
                 self.resuming_session,
-                self.dns_name,
+                self.server_name,
                 self.randoms,
                 suite,
                 self.transcript,
@@ -607,7 +927,7 @@ impl State for ExpectServerHello {
             tls12::CompleteServerHelloHandling {
                 config: self.config,
                 resuming_session: self.resuming_session,
-                dns_name: self.dns_name,
+                server_name: self.server_name,
                 randoms: self.randoms,
                 using_ems: self.using_ems,
                 transcript: self.transcript,
@@ -660,8 +980,16 @@ impl ExpectServerHelloOrHelloRetryRequest {
             }
         }
 
-        // Or has something unrecognised
-        if hrr.has_unknown_extension() {
+        // Or has something unrecognised, or replies to an ECH extension
+        // we didn't send.  We ignore replies to GREASE ECH.
+        if hrr.has_unknown_extension()
+            || (hrr.has_ech_confirmation()
+                && !self
+                    .next
+                    .hello
+                    .sent_extensions
+                    .contains(&ExtensionType::EncryptedClientHello))
+        {
             cx.common
                 .send_fatal_alert(AlertDescription::UnsupportedExtension);
             return Err(Error::PeerIncompatibleError(
@@ -736,7 +1064,9 @@ impl ExpectServerHelloOrHelloRetryRequest {
                         cx.common
                             .illegal_param("server requested hrr with bad group")
                     })?;
-                kx::KeyExchange::start(group).ok_or(Error::FailedToGetRandomBytes)?
+                cx.data
+                    .start_key_exchange(group)
+                    .ok_or(Error::FailedToGetRandomBytes)?
             }
             _ => offered_key_share,
         };
@@ -752,7 +1082,7 @@ impl ExpectServerHelloOrHelloRetryRequest {
             self.next.hello,
             Some(self.next.session_id),
             Some(&hrr),
-            self.next.dns_name,
+            self.next.server_name,
             Some(key_share),
             self.extra_exts,
             may_send_sct_list,
@@ -778,17 +1108,96 @@ impl State for ExpectServerHelloOrHelloRetryRequest {
 }
 
 pub(super) fn send_cert_error_alert(common: &mut ConnectionCommon, err: Error) -> Error {
-    match err {
-        Error::WebPkiError(webpki::Error::BadDer, _) => {
-            common.send_fatal_alert(AlertDescription::DecodeError);
-        }
-        Error::PeerMisbehavedError(_) => {
-            common.send_fatal_alert(AlertDescription::IllegalParameter);
+    common.send_fatal_alert(err.certificate_alert());
+    err
+}
+
+/// Continues the handshake once the server's certificate has been
+/// verified, by a verifier which returned `ServerCertVerification::Pending`.
+pub(super) trait ServerCertVerificationContinuation: Send + Sync + 'static {
+    fn handle_verification(
+        self,
+        cx: &mut ClientContext<'_>,
+        result: Result<ServerCertVerified, Error>,
+    ) -> NextStateOrError;
+}
+
+/// Start the configured verifier on the server's certificate chain.
+pub(super) fn start_verify_server_cert(
+    config: &ClientConfig,
+    server_name: &ServerName,
+    server_cert: &ServerCertDetails,
+) -> Result<ServerCertVerification, Error> {
+    let (end_entity, intermediates) = server_cert
+        .cert_chain
+        .split_first()
+        .ok_or(Error::NoCertificatesPresented)?;
+    let now = config
+        .time_provider
+        .current_time()
+        .ok_or(Error::FailedToGetCurrentTime)?;
+    if server_cert.raw_public_key {
+        if !intermediates.is_empty() {
+            return Err(Error::PeerMisbehavedError(
+                "server sent more than one raw public key".to_string(),
+            ));
         }
-        _ => {
-            common.send_fatal_alert(AlertDescription::BadCertificate);
+        return Ok(ServerCertVerification::Complete(
+            config
+                .verifier
+                .verify_server_raw_public_key(&end_entity.0, server_name, now),
+        ));
+    }
+    Ok(config
+        .verifier
+        .start_verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            &mut server_cert.scts(),
+            &server_cert.ocsp_response,
+            now,
+        ))
+}
+
+/// Continue with `next` if `verification` has finished; otherwise
+/// wait for it.
+pub(super) fn handle_server_cert_verification<T: ServerCertVerificationContinuation>(
+    cx: &mut ClientContext<'_>,
+    verification: ServerCertVerification,
+    next: T,
+) -> NextStateOrError {
+    match verification {
+        ServerCertVerification::Complete(result) => next.handle_verification(cx, result),
+        ServerCertVerification::Pending(pending) => {
+            debug!("Waiting for server certificate verification");
+            Ok(Box::new(ExpectServerCertVerification { pending, next }))
         }
-    };
+    }
+}
 
-    err
+/// Waits for an asynchronous server certificate verification.  No
+/// messages are handled in this state.
+struct ExpectServerCertVerification<T> {
+    pending: PendingVerification,
+    next: T,
+}
+
+impl<T: ServerCertVerificationContinuation> State for ExpectServerCertVerification<T> {
+    fn handle(self: Box<Self>, _cx: &mut ClientContext<'_>, _m: Message) -> NextStateOrError {
+        unreachable!("handle() on a state which is waiting")
+    }
+
+    fn is_waiting(&self) -> bool {
+        true
+    }
+
+    fn resume(self: Box<Self>, cx: &mut ClientContext<'_>) -> NextStateOrError {
+        match self.pending.take() {
+            Some(result) => self
+                .next
+                .handle_verification(cx, result),
+            None => Ok(self),
+        }
+    }
 }
@@ -1,27 +1,41 @@
-use crate::conn::{Connection, ConnectionCommon, IoState, PlaintextSink, Protocol, Reader, Writer};
+use crate::anchors::DistinguishedNames;
+use crate::builder::ConfigBuilderFor;
+use crate::conn::{Connection, ConnectionCommon, ConnectionRandoms, IoState, PlaintextSink};
+use crate::conn::{Protocol, Reader, Writer};
 use crate::error::Error;
 use crate::key;
 use crate::keylog::KeyLog;
+use crate::kx;
 use crate::kx::SupportedKxGroup;
 #[cfg(feature = "logging")]
 use crate::log::trace;
 #[cfg(feature = "quic")]
 use crate::msgs::enums::AlertDescription;
 use crate::msgs::enums::CipherSuite;
+use crate::msgs::enums::ExtensionType;
+use crate::msgs::enums::MaxFragmentLength;
+use crate::msgs::enums::NamedGroup;
+use crate::msgs::enums::PSKKeyExchangeMode;
 use crate::msgs::enums::ProtocolVersion;
 use crate::msgs::enums::SignatureScheme;
-use crate::msgs::handshake::{CertificatePayload, ClientExtension};
+use crate::msgs::handshake::{CertificatePayload, ClientExtension, SessionID};
+use crate::psk::ExternalPsk;
+use crate::rand;
 use crate::sign;
 use crate::suites::SupportedCipherSuite;
+use crate::time_provider::TimeProvider;
 use crate::verify;
 use crate::versions;
+use crate::x509;
 
 #[cfg(feature = "quic")]
 use crate::quic;
 
+use std::borrow::Cow;
+use std::convert::TryFrom;
 use std::fmt;
 use std::io::{self, IoSlice};
-use std::mem;
+use std::net::{IpAddr, Ipv6Addr};
 use std::sync::Arc;
 
 #[macro_use]
@@ -73,10 +87,73 @@ pub trait ResolvesClientCert: Send + Sync {
         sigschemes: &[SignatureScheme],
     ) -> Option<Arc<sign::CertifiedKey>>;
 
+    /// Return a certificate chain and signing key to authenticate with,
+    /// given everything known about the server's request for them.
+    ///
+    /// This is what rustls calls.  The default calls
+    /// [`ResolvesClientCert::resolve`]; implement this instead when the
+    /// choice depends on the server's name, or is easier made with the
+    /// acceptable issuers parsed.
+    fn resolve_request(&self, request: &ClientCertRequest) -> Option<Arc<sign::CertifiedKey>> {
+        self.resolve(request.acceptable_issuers(), request.signature_schemes())
+    }
+
     /// Return true if any certificates at all are available.
     fn has_certs(&self) -> bool;
 }
 
+/// A server's request for a client certificate, given to
+/// [`ResolvesClientCert::resolve_request`].
+pub struct ClientCertRequest<'a> {
+    server_name: &'a ServerName,
+    acceptable_issuers: &'a [&'a [u8]],
+    signature_schemes: &'a [SignatureScheme],
+}
+
+impl<'a> ClientCertRequest<'a> {
+    fn new(
+        server_name: &'a ServerName,
+        acceptable_issuers: &'a [&'a [u8]],
+        signature_schemes: &'a [SignatureScheme],
+    ) -> Self {
+        ClientCertRequest {
+            server_name,
+            acceptable_issuers,
+            signature_schemes,
+        }
+    }
+
+    /// Get the name of the server, which its certificate is verified
+    /// against.
+    pub fn server_name(&self) -> &'a ServerName {
+        self.server_name
+    }
+
+    /// Get the DER encodings of the distinguished names of the issuers
+    /// the server accepts client certificates from, as sent.
+    ///
+    /// These are unverified, and empty if the server doesn't say.
+    pub fn acceptable_issuers(&self) -> &'a [&'a [u8]] {
+        self.acceptable_issuers
+    }
+
+    /// Get the distinguished names of the issuers the server accepts
+    /// client certificates from, parsed.  Any which can't be parsed are
+    /// skipped.
+    pub fn acceptable_issuer_names(&self) -> Vec<x509::DistinguishedNameInfo> {
+        self.acceptable_issuers
+            .iter()
+            .filter_map(|name| x509::DistinguishedNameInfo::parse(name))
+            .collect()
+    }
+
+    /// Get the signature schemes the server accepts, which the client's
+    /// key must be able to use.
+    pub fn signature_schemes(&self) -> &'a [SignatureScheme] {
+        self.signature_schemes
+    }
+}
+
 /// Common configuration for (typically) all connections made by
 /// a program.
 ///
@@ -110,6 +187,30 @@ pub struct ClientConfig {
     /// Setting this value to the TCP MSS may improve latency for stream-y workloads.
     pub max_fragment_size: Option<usize>,
 
+    /// The maximum fragment length to ask the server to use, with the
+    /// max_fragment_length extension (RFC6066).  If the server agrees,
+    /// records in both directions are limited to this length.  Our
+    /// receive buffer starts at this size, and grows only if the server
+    /// sends larger records, which suits clients with little memory.
+    /// Servers which don't support the extension ignore it.
+    ///
+    /// This is not sent for QUIC connections.  The default is `None`.
+    pub max_fragment_length: Option<MaxFragmentLength>,
+
+    /// The largest record we'll accept from the server, advertised with
+    /// the record_size_limit extension (RFC8449).  If the server supports
+    /// it, protected records in both directions are limited to the size
+    /// each side advertised.  Our receive buffer starts at this size, and
+    /// grows only for larger records, such as unprotected TLS1.2
+    /// handshake records.  Unlike `max_fragment_length`, any size can be
+    /// chosen.
+    ///
+    /// In TLS1.3 this counts the content type byte, so records hold one
+    /// byte less.  Values outside 64 to 16385 are reported as errors from
+    /// ClientConnection::new.  This is not sent for QUIC connections.
+    /// The default is `None`.
+    pub record_size_limit: Option<usize>,
+
     /// How to decide what client auth certificate/keys to use.
     pub client_auth_cert_resolver: Arc<dyn ResolvesClientCert>,
 
@@ -120,6 +221,29 @@ pub struct ClientConfig {
     /// The default is true.
     pub enable_tickets: bool,
 
+    /// An external pre-shared key to authenticate TLS1.3 handshakes
+    /// with, instead of the server's certificate.  It's offered when
+    /// we're not resuming a TLS1.3 session, if a configured TLS1.3
+    /// ciphersuite uses the key's hash.  If the server doesn't take
+    /// it, its certificate is verified as usual.
+    ///
+    /// Early data is never sent with an external PSK.
+    ///
+    /// This key is also used by any TLS1.2 ECDHE_PSK ciphersuites in
+    /// `cipher_suites`, which are only offered when it is set.  Those
+    /// suites choose their own hash, so the key's hash is ignored.
+    ///
+    /// The default is None.
+    pub external_psk: Option<ExternalPsk>,
+
+    /// The ways we offer to use PSKs in TLS1.3, in preference order.
+    /// These apply to both resumption and `external_psk`.  If empty,
+    /// no PSKs are offered.
+    ///
+    /// The default is `PSK_DHE_KE` only.  `PSK_KE` does without a key
+    /// exchange, and so gives up forward secrecy.
+    pub psk_key_exchange_modes: Vec<PSKKeyExchangeMode>,
+
     /// Supported versions, in no particular order.  The default
     /// is all supported versions.
     pub versions: versions::EnabledVersions,
@@ -130,6 +254,15 @@ pub struct ClientConfig {
     /// The default is true.
     pub enable_sni: bool,
 
+    /// A name to send in the Server Name Indication extension instead
+    /// of the name of the server, whose certificate is still verified
+    /// against its real name.  This keeps the real name from observers,
+    /// for example when fronting through a CDN.  Ignored if `enable_sni`
+    /// is false.
+    ///
+    /// The default is None.
+    pub sni_decoy_name: Option<ServerName>,
+
     /// How to verify the server certificate chain.
     verifier: Arc<dyn verify::ServerCertVerifier>,
 
@@ -137,14 +270,85 @@ pub struct ClientConfig {
     /// does nothing.
     pub key_log: Arc<dyn KeyLog>,
 
+    /// Where to get the current time, for verifying certificates
+    /// and expiring sessions.  The default reads the system clock.
+    pub time_provider: Arc<dyn TimeProvider>,
+
     /// Whether to send data on the first flight ("early data") in
     /// TLS 1.3 handshakes.
     ///
     /// The default is false.
     pub enable_early_data: bool,
+
+    /// Whether to send a GREASE Encrypted Client Hello (ECH) extension
+    /// in TLS1.3 handshakes.
+    ///
+    /// rustls doesn't implement ECH, but clients which send GREASE ECH
+    /// look like clients using ECH to an observer, so the clients
+    /// really using it don't stand out.  The extension is padded like
+    /// a real one, and servers ignore it.
+    ///
+    /// The default is false.
+    pub enable_ech_grease: bool,
+
+    /// Whether to send GREASE values (RFC8701) in our ClientHello: a
+    /// cipher suite, protocol version, key exchange group, signature
+    /// scheme and two extensions which servers must ignore.  They're
+    /// chosen at random for each connection.
+    ///
+    /// This finds servers which fail on values they don't know, so
+    /// that new ones can be deployed, and makes rustls clients look
+    /// more like web browsers, which send them too.
+    ///
+    /// The default is false.
+    pub enable_grease: bool,
+
+    /// How our ClientHello is shaped: the order of its extensions,
+    /// and values we advertise but can't use.  This lets a client
+    /// present a chosen fingerprint, such as a web browser's.
+    ///
+    /// The default changes nothing.
+    pub client_hello_profile: ClientHelloProfile,
+
+    /// Whether to send application data in full TLS1.2 handshakes as
+    /// soon as our Finished message is sent, without waiting for the
+    /// server's ("False Start", RFC7918).  This saves a round trip.
+    ///
+    /// RFC7918 allows this only with forward-secret key exchange and
+    /// AEAD cipher suites.  It is also not done with the ECDHE_PSK
+    /// suites, where only the server's Finished authenticates it.
+    /// Even so, data sent this way is exposed to an attacker able to
+    /// force a downgrade to the weakest suite and group both peers
+    /// support, before the handshake would detect it.
+    ///
+    /// The default is false.
+    pub enable_false_start: bool,
+
+    /// The distinguished names of the CAs we trust, sent to TLS1.3
+    /// servers in the certificate_authorities extension (RFC8446
+    /// section 4.2.4) so one with several certificates can choose a
+    /// chain we will accept.  [`RootCertStore::subjects`] gives these
+    /// for a set of roots.
+    ///
+    /// The default is empty, meaning the extension isn't sent.  It
+    /// can be large, so only send it to servers which use it.
+    ///
+    /// [`RootCertStore::subjects`]: crate::RootCertStore::subjects
+    pub certificate_authorities: DistinguishedNames,
+
+    /// What to do when a TLS1.2 server asks to renegotiate an
+    /// established connection, which rustls never does.
+    ///
+    /// The default is [`RenegotiationPolicy::Refuse`].
+    pub renegotiation_policy: RenegotiationPolicy,
 }
 
 impl ClientConfig {
+    /// Start building a `ClientConfig`: see [`ConfigBuilderFor`].
+    pub fn builder() -> ConfigBuilderFor<ClientConfig> {
+        ConfigBuilderFor::new()
+    }
+
     #[doc(hidden)]
     /// We support a given TLS version if it's quoted in the configured
     /// versions *and* at least one ciphersuite for this version is
@@ -176,9 +380,14 @@ impl ClientConfig {
 #[cfg(feature = "dangerous_configuration")]
 pub mod danger {
     use std::sync::Arc;
+    use std::time::SystemTime;
 
-    use super::verify::ServerCertVerifier;
-    use super::ClientConfig;
+    use super::verify::{ServerCertVerified, ServerCertVerifier};
+    use super::{ClientConfig, ServerName};
+    use crate::error::Error;
+    use crate::key::Certificate;
+    #[cfg(feature = "logging")]
+    use crate::log::warn;
 
     /// Accessor for dangerous configuration options.
     pub struct DangerousClientConfig<'a> {
@@ -192,6 +401,42 @@ pub mod danger {
             self.cfg.verifier = verifier;
         }
     }
+
+    /// A `ServerCertVerifier` which accepts every server certificate,
+    /// for any name, without examining it.
+    ///
+    /// **This removes all protection against an active attacker.**  It is
+    /// only for tests and local development.  To talk to a server with a
+    /// self-signed certificate, use `SelfSignedPinnedVerifier` instead.
+    ///
+    /// Handshake signatures are still verified, so the server must hold
+    /// the private key of the certificate it presents; but that can be
+    /// anyone's certificate.  SCTs are not requested.
+    #[derive(Debug, Default)]
+    pub struct NoCertificateVerification;
+
+    impl ServerCertVerifier for NoCertificateVerification {
+        #[cfg_attr(not(feature = "logging"), allow(unused_variables))]
+        fn verify_server_cert(
+            &self,
+            _end_entity: &Certificate,
+            _intermediates: &[Certificate],
+            server_name: &ServerName,
+            _scts: &mut dyn Iterator<Item = &[u8]>,
+            _ocsp_response: &[u8],
+            _now: SystemTime,
+        ) -> Result<ServerCertVerified, Error> {
+            warn!(
+                "Accepting certificate for {:?} without verification",
+                server_name
+            );
+            Ok(ServerCertVerified::assertion())
+        }
+
+        fn request_scts(&self) -> bool {
+            false
+        }
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -252,30 +497,78 @@ impl EarlyData {
         }
     }
 
-    fn check_write(&mut self, sz: usize) -> io::Result<usize> {
+    fn status(&self) -> EarlyDataStatus {
         match self.state {
-            EarlyDataState::Disabled => unreachable!(),
-            EarlyDataState::Ready | EarlyDataState::Accepted => {
-                let take = if self.left < sz {
-                    mem::replace(&mut self.left, 0)
-                } else {
-                    self.left -= sz;
-                    sz
-                };
-
-                Ok(take)
+            EarlyDataState::Disabled => EarlyDataStatus::NotOffered,
+            EarlyDataState::Ready => EarlyDataStatus::Pending,
+            EarlyDataState::Accepted | EarlyDataState::AcceptedFinished => {
+                EarlyDataStatus::Accepted
             }
+            EarlyDataState::Rejected => EarlyDataStatus::Rejected,
+        }
+    }
+
+    /// How much of `sz` bytes may be written.
+    fn check_write(&self, sz: usize) -> io::Result<usize> {
+        match self.state {
+            EarlyDataState::Disabled => unreachable!(),
+            EarlyDataState::Ready | EarlyDataState::Accepted => Ok(sz.min(self.left)),
             EarlyDataState::Rejected | EarlyDataState::AcceptedFinished => {
                 Err(io::Error::from(io::ErrorKind::InvalidInput))
             }
         }
     }
 
+    fn written(&mut self, sz: usize) {
+        self.left -= sz;
+    }
+
     fn bytes_left(&self) -> usize {
         self.left
     }
 }
 
+/// What became of the early data a client may send, from
+/// [`ClientConnection::early_data_status`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EarlyDataStatus {
+    /// Early data can't be sent: it isn't enabled, there's no session
+    /// to resume which allows it, or we sent a second ClientHello.
+    NotOffered,
+
+    /// Early data may be sent, and the server hasn't answered yet.
+    Pending,
+
+    /// The server accepted the early data: it will process what was
+    /// written with [`ClientConnection::early_data`].
+    Accepted,
+
+    /// The server rejected the early data, and discarded what was
+    /// written.  The connection continues, so anything still needed
+    /// can be written again as ordinary data once the handshake
+    /// completes.
+    Rejected,
+}
+
+/// How a client answers a TLS1.2 server's HelloRequest, which asks to
+/// renegotiate the connection.  Renegotiation is never done.
+///
+/// HelloRequests arriving during a handshake are always ignored, as
+/// RFC5246 requires.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RenegotiationPolicy {
+    /// Ignore the request without answering it.  Some servers expect
+    /// no answer, and carry on with the connection regardless.
+    Ignore,
+
+    /// Answer with a `no_renegotiation` warning alert, leaving the
+    /// server to decide whether to continue.
+    Refuse,
+
+    /// Close the connection with a fatal `handshake_failure` alert.
+    Abort,
+}
+
 /// Stub that implements io::Write and dispatches to `write_early_data`.
 pub struct WriteEarlyData<'a> {
     sess: &'a mut ClientConnection,
@@ -303,6 +596,231 @@ impl<'a> io::Write for WriteEarlyData<'a> {
     }
 }
 
+/// The name of the server a client connects to: either a DNS name or
+/// an IP address.
+///
+/// The server's certificate is verified against this name.  A DNS name
+/// is also sent in the SNI extension, if that's enabled; an IP address
+/// never is, because RFC6066 forbids it.
+///
+/// A `ServerName` can be made from a string with `TryFrom`, which accepts
+/// IPv4 and IPv6 addresses as well as DNS names.  IPv6 addresses may be
+/// in brackets, as they are in URLs.
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ServerName {
+    /// A DNS name.  The certificate must have a matching DNS name
+    /// subject alternative name.
+    DnsName(webpki::DnsName),
+
+    /// An IP address.  The certificate must have a matching iPAddress
+    /// subject alternative name.
+    IpAddress(IpAddr),
+}
+
+impl ServerName {
+    /// The name in text form, used in session cache keys and logs.
+    pub(crate) fn to_str(&self) -> Cow<'_, str> {
+        match self {
+            Self::DnsName(name) => {
+                let name: &str = name.as_ref().into();
+                Cow::Borrowed(name)
+            }
+            Self::IpAddress(addr) => Cow::Owned(addr.to_string()),
+        }
+    }
+}
+
+impl From<webpki::DnsNameRef<'_>> for ServerName {
+    fn from(name: webpki::DnsNameRef<'_>) -> Self {
+        Self::DnsName(name.to_owned())
+    }
+}
+
+impl From<IpAddr> for ServerName {
+    fn from(addr: IpAddr) -> Self {
+        Self::IpAddress(addr)
+    }
+}
+
+impl TryFrom<&str> for ServerName {
+    type Error = webpki::InvalidDnsNameError;
+
+    /// Parse `value` as an IP address, or failing that, a DNS name.
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let bracketed_v6 = value
+            .strip_prefix('[')
+            .and_then(|rest| rest.strip_suffix(']'))
+            .and_then(|inner| inner.parse::<Ipv6Addr>().ok());
+        if let Some(addr) = bracketed_v6 {
+            return Ok(Self::IpAddress(addr.into()));
+        }
+
+        match value.parse() {
+            Ok(addr) => Ok(Self::IpAddress(addr)),
+            Err(_) => webpki::DnsNameRef::try_from_ascii_str(value).map(Self::from),
+        }
+    }
+}
+
+/// Changes to the ClientHello a client sends, for
+/// [`ClientConfig::client_hello_profile`].
+///
+/// Servers and middleboxes may identify clients from their
+/// ClientHello (for example, with a JA3 fingerprint), so a client
+/// may want to look like another.  The values advertised here are
+/// only advertised: if a server chooses one, the handshake fails.
+#[derive(Debug, Clone, Default)]
+pub struct ClientHelloProfile {
+    /// The order to send extensions in.  Extensions listed here are
+    /// sent first, in this order; the rest follow in the usual order.
+    /// Listing an extension which isn't sent does nothing.
+    ///
+    /// Any GREASE value (RFC8701) listed stands for the GREASE
+    /// extensions sent when [`ClientConfig::enable_grease`] is set:
+    /// the first such value places the first GREASE extension, and
+    /// the second the last.
+    ///
+    /// The `pre_shared_key` extension is always last, as TLS1.3
+    /// requires.
+    pub extension_order: Vec<ExtensionType>,
+
+    /// Cipher suites to offer after those in
+    /// [`ClientConfig::cipher_suites`].
+    pub extra_cipher_suites: Vec<CipherSuite>,
+
+    /// Key exchange groups to offer after those in
+    /// [`ClientConfig::kx_groups`].  No key shares are sent for them.
+    pub extra_groups: Vec<NamedGroup>,
+
+    /// Signature schemes to offer after those the certificate
+    /// verifier supports.
+    pub extra_signature_schemes: Vec<SignatureScheme>,
+
+    /// Extensions to send with the given types and encoded contents,
+    /// after the others unless placed by `extension_order`.  Those
+    /// with the type of an extension rustls sends itself are ignored.
+    ///
+    /// A server may answer these as if we understood them, which
+    /// fails the handshake if rustls can't process its answer.
+    pub extra_extensions: Vec<(ExtensionType, Vec<u8>)>,
+
+    /// The length to pad the ClientHello to with the padding extension
+    /// (RFC7685), counting its four byte handshake message header.
+    /// Shorter ClientHellos are padded to this length, or up to three
+    /// bytes longer if there isn't room for the extension's header;
+    /// longer ones are unchanged.
+    ///
+    /// Padding to 512 bytes avoids a bug in some middleboxes, which
+    /// fail on ClientHellos of between 256 and 511 bytes.  Padding to
+    /// a larger length hides the length of the values sent, such as
+    /// the server name.
+    pub padded_length: Option<usize>,
+}
+
+/// Settings for one client connection which override those in its
+/// [`ClientConfig`], for [`ClientConnection::new_with_options`].
+///
+/// These are the settings which commonly vary between connections to
+/// the same servers, so connections can share a config and its session
+/// cache.  `None` means the config's setting applies.
+///
+/// More options may be added, so start from [`ConnectionOptions::new`]
+/// and set the fields wanted.
+#[non_exhaustive]
+#[derive(Debug, Clone)]
+pub struct ConnectionOptions {
+    /// The name to send in the Server Name Indication extension, in
+    /// place of the name the server's certificate is verified against.
+    ///
+    /// This is for domain fronting, split-horizon DNS, or connecting
+    /// to an IP address whose certificate name is known: no SNI is sent
+    /// if this is an IP address.  It also takes the place of
+    /// [`ClientConfig::sni_decoy_name`].  Sessions are still cached and
+    /// resumed under the verified name.
+    pub sni_name: Option<ServerName>,
+
+    /// The ALPN protocols to offer, in place of
+    /// [`ClientConfig::alpn_protocols`].  If empty, no ALPN extension
+    /// is sent.
+    pub alpn_protocols: Option<Vec<Vec<u8>>>,
+
+    /// Whether to send the Server Name Indication extension, in place
+    /// of [`ClientConfig::enable_sni`].
+    pub enable_sni: Option<bool>,
+
+    /// Whether to resume a session from the config's session storage,
+    /// and to save this connection's session there for later.  When
+    /// false, the storage isn't used at all: that includes the key
+    /// exchange group TLS1.3 servers prefer, which is otherwise
+    /// remembered so later connections can send a key share for it.
+    ///
+    /// Other connections sharing the config are unaffected, so this
+    /// suits connections which shouldn't be linked to others, such as
+    /// privacy-sensitive requests and health checks.
+    ///
+    /// `None` means true.
+    pub enable_resumption: Option<bool>,
+
+    /// Whether to send early data when resuming a TLS1.3 session, in
+    /// place of [`ClientConfig::enable_early_data`].
+    pub enable_early_data: Option<bool>,
+
+    /// Fixed values to use in place of random ones, so the handshake
+    /// is reproducible.  For tests only.
+    #[cfg(feature = "deterministic_testing")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "deterministic_testing")))]
+    pub deterministic_hello: Option<DeterministicHello>,
+}
+
+/// Values a client uses in place of random ones, for
+/// [`ConnectionOptions::deterministic_hello`].
+///
+/// With the same values, config and server responses, a client sends
+/// the same bytes, so its handshakes can be compared against golden
+/// files or other TLS implementations.  The GREASE values sent with
+/// [`ClientConfig::enable_grease`] and
+/// [`ClientConfig::enable_ech_grease`] are still random.
+///
+/// This removes all the secrecy a handshake has: never use it
+/// outside tests.
+#[cfg(feature = "deterministic_testing")]
+#[cfg_attr(docsrs, doc(cfg(feature = "deterministic_testing")))]
+#[derive(Debug, Clone)]
+pub struct DeterministicHello {
+    /// The `random` in our ClientHello.
+    pub client_random: [u8; 32],
+
+    /// The session ID we send when not resuming a TLS1.2 session by
+    /// its ID.
+    pub session_id: [u8; 32],
+
+    /// The seed our ephemeral key exchange private keys are derived
+    /// from, with the group.
+    pub key_share_seed: [u8; 32],
+}
+
+impl ConnectionOptions {
+    /// Make `ConnectionOptions` which override nothing.
+    pub fn new() -> Self {
+        Self {
+            sni_name: None,
+            alpn_protocols: None,
+            enable_sni: None,
+            enable_resumption: None,
+            enable_early_data: None,
+            #[cfg(feature = "deterministic_testing")]
+            deterministic_hello: None,
+        }
+    }
+}
+
+impl Default for ConnectionOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// This represents a single TLS client connection.
 pub struct ClientConnection {
     common: ConnectionCommon,
@@ -319,39 +837,65 @@ impl fmt::Debug for ClientConnection {
 
 impl ClientConnection {
     /// Make a new ClientConnection.  `config` controls how
-    /// we behave in the TLS protocol, `hostname` is the
-    /// hostname of who we want to talk to.
-    pub fn new(
+    /// we behave in the TLS protocol, `name` is the
+    /// name of who we want to talk to.
+    ///
+    /// `name` may be an IP address, in which case no SNI is sent and
+    /// the server's certificate must have a matching iPAddress subject
+    /// alternative name.
+    pub fn new(config: Arc<ClientConfig>, name: ServerName) -> Result<ClientConnection, Error> {
+        Self::new_inner(
+            config,
+            name,
+            ClientConnectionData::new(),
+            Vec::new(),
+            Protocol::Tcp,
+        )
+    }
+
+    /// Make a new ClientConnection whose behaviour differs from that
+    /// `config` describes as `options` says.
+    ///
+    /// The server's certificate is verified against `name`, even if
+    /// `options` gives another name to send in SNI.
+    pub fn new_with_options(
         config: Arc<ClientConfig>,
-        hostname: webpki::DnsNameRef,
+        name: ServerName,
+        options: ConnectionOptions,
     ) -> Result<ClientConnection, Error> {
-        Self::new_inner(config, hostname, Vec::new(), Protocol::Tcp)
+        let data = ClientConnectionData {
+            options,
+            ..ClientConnectionData::new()
+        };
+        Self::new_inner(config, name, data, Vec::new(), Protocol::Tcp)
     }
 
     fn new_inner(
         config: Arc<ClientConfig>,
-        hostname: webpki::DnsNameRef,
+        name: ServerName,
+        data: ClientConnectionData,
         extra_exts: Vec<ClientExtension>,
         proto: Protocol,
     ) -> Result<Self, Error> {
         let mut new = ClientConnection {
-            common: ConnectionCommon::new(config.max_fragment_size, true)?,
+            common: ConnectionCommon::new(
+                config.max_fragment_size,
+                config.max_fragment_length,
+                config.record_size_limit,
+                true,
+            )?,
             state: None,
-            data: ClientConnectionData::new(),
+            data,
         };
         new.common.protocol = proto;
+        new.common.renegotiation_policy = config.renegotiation_policy;
 
         let mut cx = hs::ClientContext {
             common: &mut new.common,
             data: &mut new.data,
         };
 
-        new.state = Some(hs::start_handshake(
-            hostname.into(),
-            extra_exts,
-            config,
-            &mut cx,
-        )?);
+        new.state = Some(hs::start_handshake(name, extra_exts, config, &mut cx)?);
         Ok(new)
     }
 
@@ -372,7 +916,7 @@ impl ClientConnection {
     ///
     /// The server can choose not to accept any sent early data --
     /// in this case the data is lost but the connection continues.  You
-    /// can tell this happened using `is_early_data_accepted`.
+    /// can tell this happened using [`ClientConnection::early_data_status`].
     pub fn early_data(&mut self) -> Option<WriteEarlyData> {
         if self.data.early_data.is_enabled() {
             Some(WriteEarlyData::new(self))
@@ -390,14 +934,45 @@ impl ClientConnection {
         self.data.early_data.is_accepted()
     }
 
-    fn write_early_data(&mut self, data: &[u8]) -> io::Result<usize> {
+    /// Returns whether early data may be sent, and if it was, whether
+    /// the server accepted it.  This is known once the server's
+    /// `EncryptedExtensions` message is processed, before the
+    /// handshake completes.
+    pub fn early_data_status(&self) -> EarlyDataStatus {
+        self.data.early_data.status()
+    }
+
+    /// Returns the PSK identity hint the server sent, if we negotiated
+    /// a TLS1.2 ECDHE_PSK ciphersuite and the hint is not empty.
+    pub fn psk_identity_hint(&self) -> Option<&[u8]> {
         self.data
+            .psk_identity_hint
+            .as_deref()
+    }
+
+    /// Returns true if the handshake is waiting for the server's
+    /// certificate to be verified.
+    ///
+    /// This happens when the certificate verifier returns
+    /// `ServerCertVerification::Pending`.  Received TLS data is buffered
+    /// but not processed until the verifier completes its
+    /// `PendingVerification`; call `process_new_packets` after that to
+    /// continue the handshake.
+    pub fn is_verification_pending(&self) -> bool {
+        matches!(&self.state, Some(st) if st.is_waiting())
+    }
+
+    fn write_early_data(&mut self, data: &[u8]) -> io::Result<usize> {
+        let sz = self
+            .data
             .early_data
-            .check_write(data.len())
-            .map(|sz| {
-                self.common
-                    .send_early_plaintext(&data[..sz])
-            })
+            .check_write(data.len())?;
+        // Buffer limits may mean not all of it is sent.
+        let sent = self
+            .common
+            .send_early_plaintext(&data[..sz]);
+        self.data.early_data.written(sent);
+        Ok(sent)
     }
 
     fn send_some_plaintext(&mut self, buf: &[u8]) -> usize {
@@ -517,6 +1092,8 @@ struct ClientConnectionData {
     server_cert_chain: CertificatePayload,
     early_data: EarlyData,
     resumption_ciphersuite: Option<&'static SupportedCipherSuite>,
+    options: ConnectionOptions,
+    psk_identity_hint: Option<Vec<u8>>,
 }
 
 impl ClientConnectionData {
@@ -525,7 +1102,64 @@ impl ClientConnectionData {
             server_cert_chain: Vec::new(),
             early_data: EarlyData::new(),
             resumption_ciphersuite: None,
+            options: ConnectionOptions::default(),
+            psk_identity_hint: None,
+        }
+    }
+
+    /// The ALPN protocols this connection offers.
+    fn alpn_protocols<'a>(&'a self, config: &'a ClientConfig) -> &'a [Vec<u8>] {
+        self.options
+            .alpn_protocols
+            .as_ref()
+            .unwrap_or(&config.alpn_protocols)
+    }
+
+    fn sni_enabled(&self, config: &ClientConfig) -> bool {
+        self.options
+            .enable_sni
+            .unwrap_or(config.enable_sni)
+    }
+
+    fn resumption_enabled(&self) -> bool {
+        self.options
+            .enable_resumption
+            .unwrap_or(true)
+    }
+
+    fn early_data_enabled(&self, config: &ClientConfig) -> bool {
+        self.options
+            .enable_early_data
+            .unwrap_or(config.enable_early_data)
+    }
+
+    fn randoms(&self) -> Result<ConnectionRandoms, rand::GetRandomFailed> {
+        #[cfg(feature = "deterministic_testing")]
+        if let Some(fixed) = &self.options.deterministic_hello {
+            let mut randoms = ConnectionRandoms::for_client()?;
+            randoms.client = fixed.client_random;
+            return Ok(randoms);
         }
+
+        ConnectionRandoms::for_client()
+    }
+
+    fn new_session_id(&self) -> Result<SessionID, rand::GetRandomFailed> {
+        #[cfg(feature = "deterministic_testing")]
+        if let Some(fixed) = &self.options.deterministic_hello {
+            return Ok(SessionID::from(fixed.session_id));
+        }
+
+        SessionID::random()
+    }
+
+    fn start_key_exchange(&self, skxg: &'static SupportedKxGroup) -> Option<kx::KeyExchange> {
+        #[cfg(feature = "deterministic_testing")]
+        if let Some(fixed) = &self.options.deterministic_hello {
+            return kx::KeyExchange::start_with_seed(skxg, &fixed.key_share_seed);
+        }
+
+        kx::KeyExchange::start(skxg)
     }
 }
 
@@ -574,7 +1208,7 @@ pub trait ClientQuicExt {
     fn new_quic(
         config: Arc<ClientConfig>,
         quic_version: quic::Version,
-        hostname: webpki::DnsNameRef,
+        name: ServerName,
         params: Vec<u8>,
     ) -> Result<ClientConnection, Error> {
         if !config.supports_version(ProtocolVersion::TLSv1_3) {
@@ -588,7 +1222,13 @@ pub trait ClientQuicExt {
             quic::Version::V1 => ClientExtension::TransportParameters(params),
         };
 
-        ClientConnection::new_inner(config, hostname, vec![ext], Protocol::Quic)
+        ClientConnection::new_inner(
+            config,
+            name,
+            ClientConnectionData::new(),
+            vec![ext],
+            Protocol::Quic,
+        )
     }
 }
 
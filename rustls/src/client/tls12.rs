@@ -2,9 +2,10 @@ use crate::check::{check_message, inappropriate_message};
 use crate::conn::{ConnectionCommon, ConnectionRandoms, ConnectionSecrets};
 use crate::error::Error;
 use crate::hash_hs::HandshakeHash;
+use crate::key::Certificate;
 #[cfg(feature = "logging")]
 use crate::log::{debug, trace};
-use crate::msgs::base::{Payload, PayloadU8};
+use crate::msgs::base::{Payload, PayloadU16, PayloadU8};
 use crate::msgs::ccs::ChangeCipherSpecPayload;
 use crate::msgs::codec::Codec;
 use crate::msgs::enums::{AlertDescription, ProtocolVersion};
@@ -22,7 +23,7 @@ use crate::{kx, tls12};
 use super::hs::ClientContext;
 use crate::client::common::{ClientAuthDetails, ReceivedTicketDetails};
 use crate::client::common::{ServerCertDetails, ServerKxDetails};
-use crate::client::{hs, ClientConfig};
+use crate::client::{hs, ClientCertRequest, ClientConfig, ServerName};
 
 use crate::suites::Tls12CipherSuite;
 use crate::ticketer::TimeBase;
@@ -44,7 +45,7 @@ mod server_hello {
     pub(in crate::client) struct CompleteServerHelloHandling {
         pub config: Arc<ClientConfig>,
         pub resuming_session: Option<persist::ClientSessionValueWithResolvedCipherSuite>,
-        pub dns_name: webpki::DnsName,
+        pub server_name: ServerName,
         pub randoms: ConnectionRandoms,
         pub using_ems: bool,
         pub transcript: HandshakeHash,
@@ -104,6 +105,11 @@ mod server_hello {
                 debug!("Server may staple OCSP response");
             }
 
+            let raw_public_key = hs::process_server_certificate_type(
+                cx.common,
+                server_hello.get_server_certificate_type(),
+            )?;
+
             // Save any sent SCTs for verification against the certificate.
             let server_cert_sct_list = if let Some(sct_list) = server_hello.get_sct_list() {
                 debug!("Server sent {:?} SCTs", sct_list.len());
@@ -160,7 +166,7 @@ mod server_hello {
                             secrets,
                             resuming_session: self.resuming_session,
                             session_id: self.session_id,
-                            dns_name: self.dns_name,
+                            server_name: self.server_name,
                             using_ems: self.using_ems,
                             transcript: self.transcript,
                             resuming: true,
@@ -173,7 +179,7 @@ mod server_hello {
                             secrets,
                             resuming_session: self.resuming_session,
                             session_id: self.session_id,
-                            dns_name: self.dns_name,
+                            server_name: self.server_name,
                             using_ems: self.using_ems,
                             transcript: self.transcript,
                             ticket: ReceivedTicketDetails::new(),
@@ -185,11 +191,28 @@ mod server_hello {
                 }
             }
 
+            // The ECDHE_PSK suites have no server certificate: the PSK
+            // authenticates the server instead.
+            if suite.supported_suite().uses_psk() {
+                return Ok(Box::new(ExpectServerKx {
+                    config: self.config,
+                    resuming_session: self.resuming_session,
+                    session_id: self.session_id,
+                    server_name: self.server_name,
+                    randoms: self.randoms,
+                    using_ems: self.using_ems,
+                    transcript: self.transcript,
+                    suite,
+                    server_cert: ServerCertDetails::new(vec![], vec![], None, false),
+                    must_issue_new_ticket,
+                }));
+            }
+
             Ok(Box::new(ExpectCertificate {
                 config: self.config,
                 resuming_session: self.resuming_session,
                 session_id: self.session_id,
-                dns_name: self.dns_name,
+                server_name: self.server_name,
                 randoms: self.randoms,
                 using_ems: self.using_ems,
                 transcript: self.transcript,
@@ -197,6 +220,7 @@ mod server_hello {
                 may_send_cert_status,
                 must_issue_new_ticket,
                 server_cert_sct_list,
+                raw_public_key,
             }))
         }
     }
@@ -206,7 +230,7 @@ struct ExpectCertificate {
     config: Arc<ClientConfig>,
     resuming_session: Option<persist::ClientSessionValueWithResolvedCipherSuite>,
     session_id: SessionID,
-    dns_name: webpki::DnsName,
+    server_name: ServerName,
     randoms: ConnectionRandoms,
     using_ems: bool,
     transcript: HandshakeHash,
@@ -214,6 +238,7 @@ struct ExpectCertificate {
     may_send_cert_status: bool,
     must_issue_new_ticket: bool,
     server_cert_sct_list: Option<SCTList>,
+    raw_public_key: bool,
 }
 
 impl hs::State for ExpectCertificate {
@@ -223,35 +248,49 @@ impl hs::State for ExpectCertificate {
         m: Message,
     ) -> hs::NextStateOrError {
         self.transcript.add_message(&m);
-        let server_cert_chain = require_handshake_msg_move!(
-            m,
-            HandshakeType::Certificate,
-            HandshakePayload::Certificate
-        )?;
+        let server_cert_chain = if self.raw_public_key {
+            let spki = require_handshake_msg_move!(
+                m,
+                HandshakeType::Certificate,
+                HandshakePayload::CertificateRawPublicKey
+            )?;
+            vec![Certificate(spki.0)]
+        } else {
+            require_handshake_msg_move!(
+                m,
+                HandshakeType::Certificate,
+                HandshakePayload::Certificate
+            )?
+        };
 
         if self.may_send_cert_status {
             Ok(Box::new(ExpectCertificateStatusOrServerKx {
                 config: self.config,
                 resuming_session: self.resuming_session,
                 session_id: self.session_id,
-                dns_name: self.dns_name,
+                server_name: self.server_name,
                 randoms: self.randoms,
                 using_ems: self.using_ems,
                 transcript: self.transcript,
                 suite: self.suite,
                 server_cert_sct_list: self.server_cert_sct_list,
                 server_cert_chain,
+                raw_public_key: self.raw_public_key,
                 must_issue_new_ticket: self.must_issue_new_ticket,
             }))
         } else {
-            let server_cert =
-                ServerCertDetails::new(server_cert_chain, vec![], self.server_cert_sct_list);
+            let server_cert = ServerCertDetails::new(
+                server_cert_chain,
+                vec![],
+                self.server_cert_sct_list,
+                self.raw_public_key,
+            );
 
             Ok(Box::new(ExpectServerKx {
                 config: self.config,
                 resuming_session: self.resuming_session,
                 session_id: self.session_id,
-                dns_name: self.dns_name,
+                server_name: self.server_name,
                 randoms: self.randoms,
                 using_ems: self.using_ems,
                 transcript: self.transcript,
@@ -267,13 +306,14 @@ struct ExpectCertificateStatusOrServerKx {
     config: Arc<ClientConfig>,
     resuming_session: Option<persist::ClientSessionValueWithResolvedCipherSuite>,
     session_id: SessionID,
-    dns_name: webpki::DnsName,
+    server_name: ServerName,
     randoms: ConnectionRandoms,
     using_ems: bool,
     transcript: HandshakeHash,
     suite: Tls12CipherSuite,
     server_cert_sct_list: Option<SCTList>,
     server_cert_chain: CertificatePayload,
+    raw_public_key: bool,
     must_issue_new_ticket: bool,
 }
 
@@ -293,7 +333,7 @@ impl hs::State for ExpectCertificateStatusOrServerKx {
                 config: self.config,
                 resuming_session: self.resuming_session,
                 session_id: self.session_id,
-                dns_name: self.dns_name,
+                server_name: self.server_name,
                 randoms: self.randoms,
                 using_ems: self.using_ems,
                 transcript: self.transcript,
@@ -302,6 +342,7 @@ impl hs::State for ExpectCertificateStatusOrServerKx {
                     self.server_cert_chain,
                     vec![],
                     self.server_cert_sct_list,
+                    self.raw_public_key,
                 ),
                 must_issue_new_ticket: self.must_issue_new_ticket,
             })
@@ -311,13 +352,14 @@ impl hs::State for ExpectCertificateStatusOrServerKx {
                 config: self.config,
                 resuming_session: self.resuming_session,
                 session_id: self.session_id,
-                dns_name: self.dns_name,
+                server_name: self.server_name,
                 randoms: self.randoms,
                 using_ems: self.using_ems,
                 transcript: self.transcript,
                 suite: self.suite,
                 server_cert_sct_list: self.server_cert_sct_list,
                 server_cert_chain: self.server_cert_chain,
+                raw_public_key: self.raw_public_key,
                 must_issue_new_ticket: self.must_issue_new_ticket,
             })
             .handle(cx, m)
@@ -329,13 +371,14 @@ struct ExpectCertificateStatus {
     config: Arc<ClientConfig>,
     resuming_session: Option<persist::ClientSessionValueWithResolvedCipherSuite>,
     session_id: SessionID,
-    dns_name: webpki::DnsName,
+    server_name: ServerName,
     randoms: ConnectionRandoms,
     using_ems: bool,
     transcript: HandshakeHash,
     suite: Tls12CipherSuite,
     server_cert_sct_list: Option<SCTList>,
     server_cert_chain: CertificatePayload,
+    raw_public_key: bool,
     must_issue_new_ticket: bool,
 }
 
@@ -362,13 +405,14 @@ impl hs::State for ExpectCertificateStatus {
             self.server_cert_chain,
             server_cert_ocsp_response,
             self.server_cert_sct_list,
+            self.raw_public_key,
         );
 
         Ok(Box::new(ExpectServerKx {
             config: self.config,
             resuming_session: self.resuming_session,
             session_id: self.session_id,
-            dns_name: self.dns_name,
+            server_name: self.server_name,
             randoms: self.randoms,
             using_ems: self.using_ems,
             transcript: self.transcript,
@@ -383,7 +427,7 @@ struct ExpectServerKx {
     config: Arc<ClientConfig>,
     resuming_session: Option<persist::ClientSessionValueWithResolvedCipherSuite>,
     session_id: SessionID,
-    dns_name: webpki::DnsName,
+    server_name: ServerName,
     randoms: ConnectionRandoms,
     using_ems: bool,
     transcript: HandshakeHash,
@@ -412,7 +456,12 @@ impl hs::State for ExpectServerKx {
         // Save the signature and signed parameters for later verification.
         let mut kx_params = Vec::new();
         decoded_kx.encode_params(&mut kx_params);
-        let server_kx = ServerKxDetails::new(kx_params, decoded_kx.get_sig().unwrap());
+        let server_kx = ServerKxDetails::new(kx_params, decoded_kx.get_sig());
+
+        cx.data.psk_identity_hint = decoded_kx
+            .get_psk_identity_hint()
+            .filter(|hint| !hint.is_empty())
+            .map(|hint| hint.to_vec());
 
         #[cfg_attr(not(feature = "logging"), allow(unused_variables))]
         {
@@ -425,7 +474,7 @@ impl hs::State for ExpectServerKx {
             config: self.config,
             resuming_session: self.resuming_session,
             session_id: self.session_id,
-            dns_name: self.dns_name,
+            server_name: self.server_name,
             randoms: self.randoms,
             using_ems: self.using_ems,
             transcript: self.transcript,
@@ -457,9 +506,13 @@ fn emit_certificate(
 fn emit_clientkx(
     transcript: &mut HandshakeHash,
     common: &mut ConnectionCommon,
+    psk_identity: Option<&[u8]>,
     kxd: &kx::KeyExchangeResult,
 ) {
     let mut buf = Vec::new();
+    if let Some(identity) = psk_identity {
+        PayloadU16::new(identity.to_vec()).encode(&mut buf);
+    }
     let ecpoint = PayloadU8::new(Vec::from(kxd.pubkey.as_ref()));
     ecpoint.encode(&mut buf);
     let pubkey = Payload::new(buf);
@@ -542,7 +595,7 @@ struct ExpectServerDoneOrCertReq {
     config: Arc<ClientConfig>,
     resuming_session: Option<persist::ClientSessionValueWithResolvedCipherSuite>,
     session_id: SessionID,
-    dns_name: webpki::DnsName,
+    server_name: ServerName,
     randoms: ConnectionRandoms,
     using_ems: bool,
     transcript: HandshakeHash,
@@ -565,7 +618,7 @@ impl hs::State for ExpectServerDoneOrCertReq {
                 config: self.config,
                 resuming_session: self.resuming_session,
                 session_id: self.session_id,
-                dns_name: self.dns_name,
+                server_name: self.server_name,
                 randoms: self.randoms,
                 using_ems: self.using_ems,
                 transcript: self.transcript,
@@ -582,7 +635,7 @@ impl hs::State for ExpectServerDoneOrCertReq {
                 config: self.config,
                 resuming_session: self.resuming_session,
                 session_id: self.session_id,
-                dns_name: self.dns_name,
+                server_name: self.server_name,
                 randoms: self.randoms,
                 using_ems: self.using_ems,
                 transcript: self.transcript,
@@ -604,7 +657,7 @@ struct ExpectCertificateRequest {
     config: Arc<ClientConfig>,
     resuming_session: Option<persist::ClientSessionValueWithResolvedCipherSuite>,
     session_id: SessionID,
-    dns_name: webpki::DnsName,
+    server_name: ServerName,
     randoms: ConnectionRandoms,
     using_ems: bool,
     transcript: HandshakeHash,
@@ -641,10 +694,11 @@ impl hs::State for ExpectCertificateRequest {
             .iter()
             .map(|p| p.0.as_slice())
             .collect::<Vec<&[u8]>>();
+        let request = ClientCertRequest::new(&self.server_name, &canames, &certreq.sigschemes);
         let maybe_certkey = self
             .config
             .client_auth_cert_resolver
-            .resolve(&canames, &certreq.sigschemes);
+            .resolve_request(&request);
 
         if let Some(certkey) = maybe_certkey {
             let maybe_signer = certkey
@@ -664,7 +718,7 @@ impl hs::State for ExpectCertificateRequest {
             config: self.config,
             resuming_session: self.resuming_session,
             session_id: self.session_id,
-            dns_name: self.dns_name,
+            server_name: self.server_name,
             randoms: self.randoms,
             using_ems: self.using_ems,
             transcript: self.transcript,
@@ -681,7 +735,7 @@ struct ExpectServerDone {
     config: Arc<ClientConfig>,
     resuming_session: Option<persist::ClientSessionValueWithResolvedCipherSuite>,
     session_id: SessionID,
-    dns_name: webpki::DnsName,
+    server_name: ServerName,
     randoms: ConnectionRandoms,
     using_ems: bool,
     transcript: HandshakeHash,
@@ -705,9 +759,7 @@ impl hs::State for ExpectServerDone {
         cx.common.check_aligned_handshake()?;
 
         trace!("Server cert is {:?}", st.server_cert.cert_chain);
-        debug!("Server DNS name is {:?}", st.dns_name);
-
-        let suite = st.suite;
+        debug!("Server name is {:?}", st.server_name);
 
         // 1. Verify the cert chain.
         // 2. Verify any SCTs provided with the certificate.
@@ -722,52 +774,66 @@ impl hs::State for ExpectServerDone {
         // 6. emit a Finished, our first encrypted message under the new keys.
 
         // 1.
-        let (end_entity, intermediates) = st
-            .server_cert
-            .cert_chain
-            .split_first()
-            .ok_or(Error::NoCertificatesPresented)?;
-        let now = std::time::SystemTime::now();
-        let cert_verified = st
-            .config
-            .verifier
-            .verify_server_cert(
-                end_entity,
-                intermediates,
-                st.dns_name.as_ref(),
-                &mut st.server_cert.scts(),
-                &st.server_cert.ocsp_response,
-                now,
-            )
-            .map_err(|err| hs::send_cert_error_alert(cx.common, err))?;
+        // (The ECDHE_PSK suites have no certificate to verify.)
+        if st.suite.supported_suite().uses_psk() {
+            let verified = Ok(verify::ServerCertVerified::assertion());
+            return hs::ServerCertVerificationContinuation::handle_verification(st, cx, verified);
+        }
+
+        let verification =
+            hs::start_verify_server_cert(&st.config, &st.server_name, &st.server_cert)?;
+        hs::handle_server_cert_verification(cx, verification, st)
+    }
+}
+
+impl hs::ServerCertVerificationContinuation for ExpectServerDone {
+    fn handle_verification(
+        self,
+        cx: &mut ClientContext<'_>,
+        result: Result<verify::ServerCertVerified, Error>,
+    ) -> hs::NextStateOrError {
+        let mut st = self;
+        let suite = st.suite;
+        let cert_verified = result.map_err(|err| hs::send_cert_error_alert(cx.common, err))?;
 
         // 3.
         // Build up the contents of the signed message.
         // It's ClientHello.random || ServerHello.random || ServerKeyExchange.params
-        let sig_verified = {
-            let mut message = Vec::new();
-            message.extend_from_slice(&st.randoms.client);
-            message.extend_from_slice(&st.randoms.server);
-            message.extend_from_slice(&st.server_kx.kx_params);
-
-            // Check the signature is compatible with the ciphersuite.
-            let sig = &st.server_kx.kx_sig;
-            if !suite
-                .supported_suite()
-                .usable_for_sigalg(sig.scheme.sign())
-            {
-                let error_message = format!(
-                    "peer signed kx with wrong algorithm (got {:?} expect {:?})",
-                    sig.scheme.sign(),
-                    suite.tls12().sign
-                );
-                return Err(Error::PeerMisbehavedError(error_message));
-            }
+        // (The ECDHE_PSK suites don't sign it: the PSK proves who
+        // the server is, once we check its Finished.)
+        let sig_verified = match &st.server_kx.kx_sig {
+            Some(sig) => {
+                let mut message = Vec::new();
+                message.extend_from_slice(&st.randoms.client);
+                message.extend_from_slice(&st.randoms.server);
+                message.extend_from_slice(&st.server_kx.kx_params);
+
+                // Check the signature is compatible with the ciphersuite.
+                if !suite
+                    .supported_suite()
+                    .usable_for_sigalg(sig.scheme.sign())
+                {
+                    let error_message = format!(
+                        "peer signed kx with wrong algorithm (got {:?} expect {:?})",
+                        sig.scheme.sign(),
+                        suite.tls12().sign
+                    );
+                    return Err(Error::PeerMisbehavedError(error_message));
+                }
 
-            st.config
-                .verifier
-                .verify_tls12_signature(&message, &st.server_cert.cert_chain[0], sig)
-                .map_err(|err| hs::send_cert_error_alert(cx.common, err))?
+                st.config
+                    .verifier
+                    .verify_tls12_signature(&message, &st.server_cert.cert_chain[0], sig)
+                    .map_err(|err| hs::send_cert_error_alert(cx.common, err))?
+            }
+            None if suite.supported_suite().uses_psk() => {
+                verify::HandshakeSignatureValid::assertion()
+            }
+            None => {
+                return Err(Error::PeerMisbehavedError(
+                    "server key exchange is not signed".to_string(),
+                ));
+            }
         };
         cx.data.server_cert_chain = st.server_cert.cert_chain;
 
@@ -788,11 +854,24 @@ impl hs::State for ExpectServerDone {
                 .ok_or_else(|| {
                     Error::PeerMisbehavedError("peer chose an unsupported group".to_string())
                 })?;
-        let kx = kx::KeyExchange::start(group).ok_or(Error::FailedToGetRandomBytes)?;
+        let kx = cx
+            .data
+            .start_key_exchange(group)
+            .ok_or(Error::FailedToGetRandomBytes)?;
         let kxd = tls12::complete_ecdh(kx, &ecdh_params.public.0)?;
 
         // 5b.
-        emit_clientkx(&mut st.transcript, cx.common, &kxd);
+        let psk = st
+            .config
+            .external_psk
+            .as_ref()
+            .filter(|_| suite.supported_suite().uses_psk());
+        emit_clientkx(
+            &mut st.transcript,
+            cx.common,
+            psk.map(|psk| psk.identity()),
+            &kxd,
+        );
         // nb. EMS handshake hash only runs up to ClientKeyExchange.
         let handshake_hash = st.transcript.get_current_hash();
 
@@ -805,10 +884,14 @@ impl hs::State for ExpectServerDone {
         emit_ccs(cx.common);
 
         // 5e. Now commit secrets.
+        let premaster_secret = match psk {
+            Some(psk) => tls12::ecdhe_psk_premaster_secret(&kxd.shared_secret, psk.secret()),
+            None => kxd.shared_secret,
+        };
         let secrets = if st.using_ems {
-            ConnectionSecrets::new_ems(&st.randoms, &handshake_hash, suite, &kxd.shared_secret)
+            ConnectionSecrets::new_ems(&st.randoms, &handshake_hash, suite, &premaster_secret)
         } else {
-            ConnectionSecrets::new(&st.randoms, suite, &kxd.shared_secret)
+            ConnectionSecrets::new(&st.randoms, suite, &premaster_secret)
         };
         st.config.key_log.log(
             "CLIENT_RANDOM",
@@ -824,13 +907,22 @@ impl hs::State for ExpectServerDone {
         // 6.
         emit_finished(&secrets, &mut st.transcript, cx.common);
 
+        if st.config.enable_false_start {
+            if suite.allows_false_start() {
+                debug!("False Start: sending data before server's Finished");
+                cx.common.start_outgoing_traffic();
+            } else {
+                debug!("False Start not allowed with {:?}", suite.supported_suite().suite);
+            }
+        }
+
         if st.must_issue_new_ticket {
             Ok(Box::new(ExpectNewTicket {
                 config: st.config,
                 secrets,
                 resuming_session: st.resuming_session,
                 session_id: st.session_id,
-                dns_name: st.dns_name,
+                server_name: st.server_name,
                 using_ems: st.using_ems,
                 transcript: st.transcript,
                 resuming: false,
@@ -843,7 +935,7 @@ impl hs::State for ExpectServerDone {
                 secrets,
                 resuming_session: st.resuming_session,
                 session_id: st.session_id,
-                dns_name: st.dns_name,
+                server_name: st.server_name,
                 using_ems: st.using_ems,
                 transcript: st.transcript,
                 ticket: ReceivedTicketDetails::new(),
@@ -860,7 +952,7 @@ pub struct ExpectNewTicket {
     secrets: ConnectionSecrets,
     resuming_session: Option<persist::ClientSessionValueWithResolvedCipherSuite>,
     session_id: SessionID,
-    dns_name: webpki::DnsName,
+    server_name: ServerName,
     using_ems: bool,
     transcript: HandshakeHash,
     resuming: bool,
@@ -887,7 +979,7 @@ impl hs::State for ExpectNewTicket {
             secrets: self.secrets,
             resuming_session: self.resuming_session,
             session_id: self.session_id,
-            dns_name: self.dns_name,
+            server_name: self.server_name,
             using_ems: self.using_ems,
             transcript: self.transcript,
             ticket: ReceivedTicketDetails::from(nst.ticket.0, nst.lifetime_hint),
@@ -904,7 +996,7 @@ pub struct ExpectCcs {
     secrets: ConnectionSecrets,
     resuming_session: Option<persist::ClientSessionValueWithResolvedCipherSuite>,
     session_id: SessionID,
-    dns_name: webpki::DnsName,
+    server_name: ServerName,
     using_ems: bool,
     transcript: HandshakeHash,
     ticket: ReceivedTicketDetails,
@@ -930,7 +1022,7 @@ impl hs::State for ExpectCcs {
             secrets: self.secrets,
             resuming_session: self.resuming_session,
             session_id: self.session_id,
-            dns_name: self.dns_name,
+            server_name: self.server_name,
             using_ems: self.using_ems,
             transcript: self.transcript,
             ticket: self.ticket,
@@ -945,7 +1037,7 @@ struct ExpectFinished {
     config: Arc<ClientConfig>,
     resuming_session: Option<persist::ClientSessionValueWithResolvedCipherSuite>,
     session_id: SessionID,
-    dns_name: webpki::DnsName,
+    server_name: ServerName,
     using_ems: bool,
     transcript: HandshakeHash,
     ticket: ReceivedTicketDetails,
@@ -958,6 +1050,11 @@ struct ExpectFinished {
 impl ExpectFinished {
     // -- Waiting for their finished --
     fn save_session(&mut self, cx: &mut ClientContext<'_>) {
+        if !cx.data.resumption_enabled() {
+            debug!("Session not saved: resumption disabled");
+            return;
+        }
+
         // Save a ticket.  If we got a new ticket, save that.  Otherwise, save the
         // original ticket again.
         let mut ticket = mem::take(&mut self.ticket.new_ticket);
@@ -973,7 +1070,7 @@ impl ExpectFinished {
             return;
         }
 
-        let time_now = match TimeBase::now() {
+        let time_now = match TimeBase::from_provider(self.config.time_provider.as_ref()) {
             Ok(time_now) => time_now,
             Err(_) => {
                 debug!("Session not saved: failed to get system time");
@@ -981,7 +1078,7 @@ impl ExpectFinished {
             }
         };
 
-        let key = persist::ClientSessionKey::session_for_dns_name(self.dns_name.as_ref());
+        let key = persist::ClientSessionKey::session_for_server_name(&self.server_name);
 
         let master_secret = self.secrets.get_master_secret();
         let mut value = persist::ClientSessionValueWithResolvedCipherSuite::new(
@@ -12,9 +12,9 @@ use crate::log::{debug, trace, warn};
 use crate::msgs::base::{Payload, PayloadU8};
 use crate::msgs::ccs::ChangeCipherSpecPayload;
 use crate::msgs::codec::Codec;
-use crate::msgs::enums::KeyUpdateRequest;
 use crate::msgs::enums::{AlertDescription, NamedGroup, ProtocolVersion};
 use crate::msgs::enums::{ContentType, ExtensionType, HandshakeType, SignatureScheme};
+use crate::msgs::enums::{KeyUpdateRequest, PSKKeyExchangeMode};
 use crate::msgs::handshake::ClientExtension;
 use crate::msgs::handshake::DigitallySignedStruct;
 use crate::msgs::handshake::EncryptedExtensions;
@@ -25,6 +25,7 @@ use crate::msgs::handshake::{HasServerExtensions, ServerHelloPayload, SessionID}
 use crate::msgs::handshake::{PresharedKeyIdentity, PresharedKeyOffer};
 use crate::msgs::message::{Message, MessagePayload};
 use crate::msgs::persist;
+use crate::psk::ExternalPsk;
 use crate::verify;
 use crate::{cipher, SupportedCipherSuite};
 #[cfg(feature = "quic")]
@@ -34,7 +35,7 @@ use crate::{sign, KeyLog};
 use super::hs::ClientContext;
 use crate::client::common::ServerCertDetails;
 use crate::client::common::{ClientAuthDetails, ClientHelloDetails};
-use crate::client::{hs, ClientConfig};
+use crate::client::{hs, ClientCertRequest, ClientConfig, ClientConnectionData, ServerName};
 
 use crate::ticketer::TimeBase;
 use ring::constant_time;
@@ -63,7 +64,7 @@ pub(super) fn handle_server_hello(
     cx: &mut ClientContext,
     server_hello: &ServerHelloPayload,
     mut resuming_session: Option<persist::ClientSessionValueWithResolvedCipherSuite>,
-    dns_name: webpki::DnsName,
+    server_name: ServerName,
     randoms: ConnectionRandoms,
     suite: &'static SupportedCipherSuite,
     transcript: HandshakeHash,
@@ -80,24 +81,38 @@ pub(super) fn handle_server_hello(
 
     validate_server_hello(cx.common, &server_hello)?;
 
-    let their_key_share = server_hello
-        .get_key_share()
-        .ok_or_else(|| {
+    let psk_selected = server_hello.get_psk_index().is_some() && early_key_schedule.is_some();
+    let their_key_share = server_hello.get_key_share();
+    let shared_secret = match their_key_share {
+        Some(their_key_share) => {
+            if our_key_share.group() != their_key_share.group {
+                return Err(cx
+                    .common
+                    .illegal_param("wrong group for key share"));
+            }
+
+            our_key_share
+                .complete(&their_key_share.payload.0)
+                .ok_or_else(|| Error::PeerMisbehavedError("key exchange failed".to_string()))?
+                .shared_secret
+        }
+        None if psk_selected
+            && config
+                .psk_key_exchange_modes
+                .contains(&PSKKeyExchangeMode::PSK_KE) =>
+        {
+            // psk_ke: the (EC)DHE input to the key schedule is zeroes.
+            debug!("Server chose PSK without key exchange");
+            vec![0u8; suite.get_hash().output_len]
+        }
+        None => {
             cx.common
                 .send_fatal_alert(AlertDescription::MissingExtension);
-            Error::PeerMisbehavedError("missing key share".to_string())
-        })?;
-
-    if our_key_share.group() != their_key_share.group {
-        return Err(cx
-            .common
-            .illegal_param("wrong group for key share"));
-    }
-
-    let shared = our_key_share
-        .complete(&their_key_share.payload.0)
-        .ok_or_else(|| Error::PeerMisbehavedError("key exchange failed".to_string()))?;
+            return Err(Error::PeerMisbehavedError("missing key share".to_string()));
+        }
+    };
 
+    let mut external_psk = false;
     let mut key_schedule = if let (Some(selected_psk), Some(early_key_schedule)) =
         (server_hello.get_psk_index(), early_key_schedule)
     {
@@ -127,23 +142,42 @@ pub(super) fn handle_server_hello(
 
             debug!("Resuming using PSK");
             // The key schedule has been initialized and set in fill_in_psk_binder()
+        } else if let Some(psk) = &config.external_psk {
+            if !psk.usable_with(suite) {
+                return Err(cx
+                    .common
+                    .illegal_param("server chose suite unsuitable for psk"));
+            }
+
+            if selected_psk != 0 {
+                return Err(cx
+                    .common
+                    .illegal_param("server selected invalid psk"));
+            }
+
+            debug!("Authenticating using external PSK");
+            external_psk = true;
         } else {
             return Err(Error::PeerMisbehavedError(
                 "server selected unoffered psk".to_string(),
             ));
         }
-        early_key_schedule.into_handshake(&shared.shared_secret)
+        early_key_schedule.into_handshake(&shared_secret)
     } else {
         debug!("Not resuming");
         // Discard the early data key schedule.
-        cx.data.early_data.rejected();
+        if cx.data.early_data.is_enabled() {
+            cx.data.early_data.rejected();
+        }
         cx.common.early_traffic = false;
         resuming_session.take();
-        KeyScheduleNonSecret::new(suite.hkdf_algorithm).into_handshake(&shared.shared_secret)
+        KeyScheduleNonSecret::new(suite.hkdf_algorithm).into_handshake(&shared_secret)
     };
 
     // Remember what KX group the server liked for next time.
-    save_kx_hint(&config, dns_name.as_ref(), their_key_share.group);
+    if let (Some(their_key_share), true) = (their_key_share, cx.data.resumption_enabled()) {
+        save_kx_hint(&config, &server_name, their_key_share.group);
+    }
 
     // If we change keying when a subsequent handshake message is being joined,
     // the two halves will have different record layer protections.  Disallow this.
@@ -194,12 +228,13 @@ pub(super) fn handle_server_hello(
     Ok(Box::new(ExpectEncryptedExtensions {
         config,
         resuming_session,
-        dns_name,
+        server_name,
         randoms,
         suite,
         transcript,
         key_schedule,
         hello,
+        external_psk,
         hash_at_client_recvd_server_hello,
     }))
 }
@@ -222,12 +257,17 @@ fn validate_server_hello(
 
 pub(super) fn initial_key_share(
     config: &ClientConfig,
-    dns_name: webpki::DnsNameRef,
+    data: &ClientConnectionData,
+    server_name: &ServerName,
 ) -> Result<kx::KeyExchange, Error> {
-    let key = persist::ClientSessionKey::hint_for_dns_name(dns_name);
+    let key = persist::ClientSessionKey::hint_for_server_name(server_name);
     let key_buf = key.get_encoding();
 
-    let maybe_value = config.session_storage.get(&key_buf);
+    let maybe_value = if data.resumption_enabled() {
+        config.session_storage.get(&key_buf)
+    } else {
+        None
+    };
 
     let group = maybe_value
         .and_then(|enc| NamedGroup::read_bytes(&enc))
@@ -239,11 +279,12 @@ pub(super) fn initial_key_share(
                 .expect("No kx groups configured")
         });
 
-    kx::KeyExchange::start(group).ok_or(Error::FailedToGetRandomBytes)
+    data.start_key_exchange(group)
+        .ok_or(Error::FailedToGetRandomBytes)
 }
 
-fn save_kx_hint(config: &ClientConfig, dns_name: webpki::DnsNameRef, group: NamedGroup) {
-    let key = persist::ClientSessionKey::hint_for_dns_name(dns_name);
+fn save_kx_hint(config: &ClientConfig, server_name: &ServerName, group: NamedGroup) {
+    let key = persist::ClientSessionKey::hint_for_server_name(server_name);
 
     config
         .session_storage
@@ -259,18 +300,28 @@ pub fn fill_in_psk_binder(
 ) -> KeyScheduleEarly {
     // We need to know the hash function of the suite we're trying to resume into.
     let suite = resuming.supported_cipher_suite();
-    let hkdf_alg = suite.hkdf_algorithm;
-    let suite_hash = suite.get_hash();
+    let key_schedule = KeyScheduleEarly::new(suite.hkdf_algorithm, &resuming.master_secret.0);
+    let handshake_hash = binder_handshake_hash(suite, transcript, hmp);
+    let real_binder = key_schedule.resumption_psk_binder_key_and_sign_verify_data(&handshake_hash);
 
-    // The binder is calculated over the clienthello, but doesn't include itself or its
-    // length, or the length of its container.
-    let binder_plaintext = hmp.get_encoding_for_binder_signing();
-    let handshake_hash = transcript.get_hash_given(suite_hash, &binder_plaintext);
+    if let HandshakePayload::ClientHello(ref mut ch) = hmp.payload {
+        ch.set_psk_binder(real_binder.as_ref());
+    };
 
-    // Run a fake key_schedule to simulate what the server will do if it chooses
-    // to resume.
-    let key_schedule = KeyScheduleEarly::new(hkdf_alg, &resuming.master_secret.0);
-    let real_binder = key_schedule.resumption_psk_binder_key_and_sign_verify_data(&handshake_hash);
+    key_schedule
+}
+
+/// As `fill_in_psk_binder`, for an external PSK offered for use with
+/// ciphersuites like `suite`.
+pub(super) fn fill_in_external_psk_binder(
+    psk: &ExternalPsk,
+    suite: &'static SupportedCipherSuite,
+    transcript: &HandshakeHash,
+    hmp: &mut HandshakeMessagePayload,
+) -> KeyScheduleEarly {
+    let key_schedule = KeyScheduleEarly::new(suite.hkdf_algorithm, psk.secret());
+    let handshake_hash = binder_handshake_hash(suite, transcript, hmp);
+    let real_binder = key_schedule.external_psk_binder_key_and_sign_verify_data(&handshake_hash);
 
     if let HandshakePayload::ClientHello(ref mut ch) = hmp.payload {
         ch.set_psk_binder(real_binder.as_ref());
@@ -279,6 +330,17 @@ pub fn fill_in_psk_binder(
     key_schedule
 }
 
+fn binder_handshake_hash(
+    suite: &'static SupportedCipherSuite,
+    transcript: &HandshakeHash,
+    hmp: &HandshakeMessagePayload,
+) -> Digest {
+    // The binder is calculated over the clienthello, but doesn't include itself or its
+    // length, or the length of its container.
+    let binder_plaintext = hmp.get_encoding_for_binder_signing();
+    transcript.get_hash_given(suite.get_hash(), &binder_plaintext)
+}
+
 pub(super) fn prepare_resumption(
     config: &ClientConfig,
     cx: &mut ClientContext<'_>,
@@ -293,7 +355,7 @@ pub(super) fn prepare_resumption(
     // The EarlyData extension MUST be supplied together with the
     // PreSharedKey extension.
     let max_early_data_size = resuming_session.max_early_data_size;
-    if config.enable_early_data && max_early_data_size > 0 && !doing_retry {
+    if cx.data.early_data_enabled(config) && max_early_data_size > 0 && !doing_retry {
         cx.data
             .early_data
             .enable(max_early_data_size as usize);
@@ -316,6 +378,21 @@ pub(super) fn prepare_resumption(
     exts.push(ClientExtension::PresharedKey(psk_ext));
 }
 
+pub(super) fn prepare_external_psk(
+    psk: &ExternalPsk,
+    suite: &'static SupportedCipherSuite,
+    exts: &mut Vec<ClientExtension>,
+) {
+    // As for resumption, the binder is filled in once the rest of the
+    // message is complete.  External PSKs have no age to obfuscate.
+    let binder = vec![0u8; suite.get_hash().output_len];
+    let psk_identity = PresharedKeyIdentity::new(psk.identity().to_vec(), 0);
+    exts.push(ClientExtension::PresharedKey(PresharedKeyOffer::new(
+        psk_identity,
+        binder,
+    )));
+}
+
 pub(super) fn derive_early_traffic_secret(
     key_log: &dyn KeyLog,
     cx: &mut ClientContext<'_>,
@@ -401,12 +478,13 @@ fn validate_encrypted_extensions(
 struct ExpectEncryptedExtensions {
     config: Arc<ClientConfig>,
     resuming_session: Option<persist::ClientSessionValueWithResolvedCipherSuite>,
-    dns_name: webpki::DnsName,
+    server_name: ServerName,
     randoms: ConnectionRandoms,
     suite: &'static SupportedCipherSuite,
     transcript: HandshakeHash,
     key_schedule: KeyScheduleHandshake,
     hello: ClientHelloDetails,
+    external_psk: bool,
     hash_at_client_recvd_server_hello: Digest,
 }
 
@@ -422,6 +500,14 @@ impl hs::State for ExpectEncryptedExtensions {
 
         validate_encrypted_extensions(cx.common, &self.hello, &exts)?;
         hs::process_alpn_protocol(cx, &self.config, exts.get_alpn_protocol())?;
+        hs::process_record_limits(
+            cx.common,
+            &self.config,
+            exts.get_max_fragment_length(),
+            exts.get_record_size_limit(),
+        )?;
+        let raw_public_key =
+            hs::process_server_certificate_type(cx.common, exts.get_server_certificate_type())?;
 
         #[cfg(feature = "quic")]
         {
@@ -438,7 +524,12 @@ impl hs::State for ExpectEncryptedExtensions {
             }
         }
 
-        if let Some(resuming_session) = self.resuming_session {
+        if exts.early_data_extension_offered() && self.resuming_session.is_none() {
+            let msg = "server sent early data extension without resumption".to_string();
+            return Err(Error::PeerMisbehavedError(msg));
+        }
+
+        if let Some(resuming_session) = &self.resuming_session {
             let was_early_traffic = cx.common.early_traffic;
             if was_early_traffic {
                 if exts.early_data_extension_offered() {
@@ -466,14 +557,17 @@ impl hs::State for ExpectEncryptedExtensions {
             cx.data.server_cert_chain = resuming_session
                 .server_cert_chain
                 .clone();
+        }
 
+        if self.resuming_session.is_some() || self.external_psk {
             // We *don't* reverify the certificate chain here: resumption is a
             // continuation of the previous session in terms of security policy.
+            // An external PSK authenticates the server instead of a certificate.
             let cert_verified = verify::ServerCertVerified::assertion();
             let sig_verified = verify::HandshakeSignatureValid::assertion();
             Ok(Box::new(ExpectFinished {
                 config: self.config,
-                dns_name: self.dns_name,
+                server_name: self.server_name,
                 randoms: self.randoms,
                 suite: self.suite,
                 transcript: self.transcript,
@@ -484,18 +578,15 @@ impl hs::State for ExpectEncryptedExtensions {
                 hash_at_client_recvd_server_hello: self.hash_at_client_recvd_server_hello,
             }))
         } else {
-            if exts.early_data_extension_offered() {
-                let msg = "server sent early data extension without resumption".to_string();
-                return Err(Error::PeerMisbehavedError(msg));
-            }
             Ok(Box::new(ExpectCertificateOrCertReq {
                 config: self.config,
-                dns_name: self.dns_name,
+                server_name: self.server_name,
                 randoms: self.randoms,
                 suite: self.suite,
                 transcript: self.transcript,
                 key_schedule: self.key_schedule,
                 may_send_sct_list: self.hello.server_may_send_sct_list(),
+                raw_public_key,
                 hash_at_client_recvd_server_hello: self.hash_at_client_recvd_server_hello,
             }))
         }
@@ -504,12 +595,13 @@ impl hs::State for ExpectEncryptedExtensions {
 
 struct ExpectCertificateOrCertReq {
     config: Arc<ClientConfig>,
-    dns_name: webpki::DnsName,
+    server_name: ServerName,
     randoms: ConnectionRandoms,
     suite: &'static SupportedCipherSuite,
     transcript: HandshakeHash,
     key_schedule: KeyScheduleHandshake,
     may_send_sct_list: bool,
+    raw_public_key: bool,
     hash_at_client_recvd_server_hello: Digest,
 }
 
@@ -526,12 +618,13 @@ impl hs::State for ExpectCertificateOrCertReq {
         if m.is_handshake_type(HandshakeType::Certificate) {
             Box::new(ExpectCertificate {
                 config: self.config,
-                dns_name: self.dns_name,
+                server_name: self.server_name,
                 randoms: self.randoms,
                 suite: self.suite,
                 transcript: self.transcript,
                 key_schedule: self.key_schedule,
                 may_send_sct_list: self.may_send_sct_list,
+                raw_public_key: self.raw_public_key,
                 client_auth: None,
                 hash_at_client_recvd_server_hello: self.hash_at_client_recvd_server_hello,
             })
@@ -539,12 +632,13 @@ impl hs::State for ExpectCertificateOrCertReq {
         } else {
             Box::new(ExpectCertificateRequest {
                 config: self.config,
-                dns_name: self.dns_name,
+                server_name: self.server_name,
                 randoms: self.randoms,
                 suite: self.suite,
                 transcript: self.transcript,
                 key_schedule: self.key_schedule,
                 may_send_sct_list: self.may_send_sct_list,
+                raw_public_key: self.raw_public_key,
                 hash_at_client_recvd_server_hello: self.hash_at_client_recvd_server_hello,
             })
             .handle(cx, m)
@@ -557,12 +651,13 @@ impl hs::State for ExpectCertificateOrCertReq {
 // in TLS1.3.
 struct ExpectCertificateRequest {
     config: Arc<ClientConfig>,
-    dns_name: webpki::DnsName,
+    server_name: ServerName,
     randoms: ConnectionRandoms,
     suite: &'static SupportedCipherSuite,
     transcript: HandshakeHash,
     key_schedule: KeyScheduleHandshake,
     may_send_sct_list: bool,
+    raw_public_key: bool,
     hash_at_client_recvd_server_hello: Digest,
 }
 
@@ -612,10 +707,11 @@ impl hs::State for ExpectCertificateRequest {
             .iter()
             .map(|p| p.0.as_slice())
             .collect::<Vec<&[u8]>>();
+        let request = ClientCertRequest::new(&self.server_name, &canames, &compat_sigschemes);
         let maybe_certkey = self
             .config
             .client_auth_cert_resolver
-            .resolve(&canames, &compat_sigschemes);
+            .resolve_request(&request);
 
         let mut client_auth = ClientAuthDetails::new();
         if let Some(certkey) = maybe_certkey {
@@ -632,12 +728,13 @@ impl hs::State for ExpectCertificateRequest {
 
         Ok(Box::new(ExpectCertificate {
             config: self.config,
-            dns_name: self.dns_name,
+            server_name: self.server_name,
             randoms: self.randoms,
             suite: self.suite,
             transcript: self.transcript,
             key_schedule: self.key_schedule,
             may_send_sct_list: self.may_send_sct_list,
+            raw_public_key: self.raw_public_key,
             client_auth: Some(client_auth),
             hash_at_client_recvd_server_hello: self.hash_at_client_recvd_server_hello,
         }))
@@ -646,12 +743,13 @@ impl hs::State for ExpectCertificateRequest {
 
 struct ExpectCertificate {
     config: Arc<ClientConfig>,
-    dns_name: webpki::DnsName,
+    server_name: ServerName,
     randoms: ConnectionRandoms,
     suite: &'static SupportedCipherSuite,
     transcript: HandshakeHash,
     key_schedule: KeyScheduleHandshake,
     may_send_sct_list: bool,
+    raw_public_key: bool,
     client_auth: Option<ClientAuthDetails>,
     hash_at_client_recvd_server_hello: Digest,
 }
@@ -688,6 +786,7 @@ impl hs::State for ExpectCertificate {
             cert_chain.convert(),
             cert_chain.get_end_entity_ocsp(),
             cert_chain.get_end_entity_scts(),
+            self.raw_public_key,
         );
 
         if let Some(sct_list) = server_cert.scts.as_ref() {
@@ -704,7 +803,7 @@ impl hs::State for ExpectCertificate {
 
         Ok(Box::new(ExpectCertificateVerify {
             config: self.config,
-            dns_name: self.dns_name,
+            server_name: self.server_name,
             randoms: self.randoms,
             suite: self.suite,
             transcript: self.transcript,
@@ -719,7 +818,7 @@ impl hs::State for ExpectCertificate {
 // --- TLS1.3 CertificateVerify ---
 struct ExpectCertificateVerify {
     config: Arc<ClientConfig>,
-    dns_name: webpki::DnsName,
+    server_name: ServerName,
     randoms: ConnectionRandoms,
     suite: &'static SupportedCipherSuite,
     transcript: HandshakeHash,
@@ -730,7 +829,7 @@ struct ExpectCertificateVerify {
 }
 
 impl hs::State for ExpectCertificateVerify {
-    fn handle(mut self: Box<Self>, cx: &mut ClientContext<'_>, m: Message) -> hs::NextStateOrError {
+    fn handle(self: Box<Self>, cx: &mut ClientContext<'_>, m: Message) -> hs::NextStateOrError {
         let cert_verify = require_handshake_msg!(
             m,
             HandshakeType::CertificateVerify,
@@ -740,51 +839,67 @@ impl hs::State for ExpectCertificateVerify {
         trace!("Server cert is {:?}", self.server_cert.cert_chain);
 
         // 1. Verify the certificate chain.
-        let (end_entity, intermediates) = self
-            .server_cert
-            .cert_chain
-            .split_first()
-            .ok_or(Error::NoCertificatesPresented)?;
-        let now = std::time::SystemTime::now();
-        let cert_verified = self
-            .config
-            .verifier
-            .verify_server_cert(
-                end_entity,
-                intermediates,
-                self.dns_name.as_ref(),
-                &mut self.server_cert.scts(),
-                &self.server_cert.ocsp_response,
-                now,
-            )
-            .map_err(|err| hs::send_cert_error_alert(cx.common, err))?;
+        let verification =
+            hs::start_verify_server_cert(&self.config, &self.server_name, &self.server_cert)?;
+        hs::handle_server_cert_verification(
+            cx,
+            verification,
+            VerifyServerSignature {
+                st: self,
+                cert_verify: cert_verify.clone(),
+                m,
+            },
+        )
+    }
+}
+
+/// The remainder of `ExpectCertificateVerify`, once the certificate
+/// chain has been verified.
+struct VerifyServerSignature {
+    st: Box<ExpectCertificateVerify>,
+    cert_verify: DigitallySignedStruct,
+    m: Message,
+}
+
+impl hs::ServerCertVerificationContinuation for VerifyServerSignature {
+    fn handle_verification(
+        self,
+        cx: &mut ClientContext<'_>,
+        result: Result<verify::ServerCertVerified, Error>,
+    ) -> hs::NextStateOrError {
+        let VerifyServerSignature {
+            mut st,
+            cert_verify,
+            m,
+        } = self;
+        let cert_verified = result.map_err(|err| hs::send_cert_error_alert(cx.common, err))?;
 
         // 2. Verify their signature on the handshake.
-        let handshake_hash = self.transcript.get_current_hash();
-        let sig_verified = self
+        let handshake_hash = st.transcript.get_current_hash();
+        let sig_verified = st
             .config
             .verifier
             .verify_tls13_signature(
                 &verify::construct_tls13_server_verify_message(&handshake_hash),
-                &self.server_cert.cert_chain[0],
+                &st.server_cert.cert_chain[0],
                 &cert_verify,
             )
             .map_err(|err| hs::send_cert_error_alert(cx.common, err))?;
 
-        cx.data.server_cert_chain = self.server_cert.cert_chain;
-        self.transcript.add_message(&m);
+        cx.data.server_cert_chain = st.server_cert.cert_chain;
+        st.transcript.add_message(&m);
 
         Ok(Box::new(ExpectFinished {
-            config: self.config,
-            dns_name: self.dns_name,
-            randoms: self.randoms,
-            suite: self.suite,
-            transcript: self.transcript,
-            key_schedule: self.key_schedule,
-            client_auth: self.client_auth,
+            config: st.config,
+            server_name: st.server_name,
+            randoms: st.randoms,
+            suite: st.suite,
+            transcript: st.transcript,
+            key_schedule: st.key_schedule,
+            client_auth: st.client_auth,
             cert_verified,
             sig_verified,
-            hash_at_client_recvd_server_hello: self.hash_at_client_recvd_server_hello,
+            hash_at_client_recvd_server_hello: st.hash_at_client_recvd_server_hello,
         }))
     }
 }
@@ -895,7 +1010,7 @@ fn emit_end_of_early_data_tls13(transcript: &mut HandshakeHash, common: &mut Con
 
 struct ExpectFinished {
     config: Arc<ClientConfig>,
-    dns_name: webpki::DnsName,
+    server_name: ServerName,
     randoms: ConnectionRandoms,
     suite: &'static SupportedCipherSuite,
     transcript: HandshakeHash,
@@ -998,7 +1113,7 @@ impl hs::State for ExpectFinished {
 
         let st = ExpectTraffic {
             config: st.config,
-            dns_name: st.dns_name,
+            server_name: st.server_name,
             suite: st.suite,
             transcript: st.transcript,
             key_schedule: key_schedule_traffic,
@@ -1028,7 +1143,7 @@ impl hs::State for ExpectFinished {
 // and application data.
 struct ExpectTraffic {
     config: Arc<ClientConfig>,
-    dns_name: webpki::DnsName,
+    server_name: ServerName,
     suite: &'static SupportedCipherSuite,
     transcript: HandshakeHash,
     key_schedule: KeyScheduleTraffic,
@@ -1050,7 +1165,7 @@ impl ExpectTraffic {
             .key_schedule
             .resumption_master_secret_and_derive_ticket_psk(&handshake_hash, &nst.nonce.0);
 
-        let time_now = TimeBase::now()?;
+        let time_now = TimeBase::from_provider(self.config.time_provider.as_ref())?;
         let mut value = persist::ClientSessionValueWithResolvedCipherSuite::new(
             ProtocolVersion::TLSv1_3,
             self.suite,
@@ -1074,7 +1189,12 @@ impl ExpectTraffic {
             }
         }
 
-        let key = persist::ClientSessionKey::session_for_dns_name(self.dns_name.as_ref());
+        if !cx.data.resumption_enabled() {
+            debug!("Ticket not saved: resumption disabled");
+            return Ok(());
+        }
+
+        let key = persist::ClientSessionKey::session_for_server_name(&self.server_name);
         #[allow(unused_mut)]
         let mut ticket = value.get_encoding();
 
@@ -0,0 +1,551 @@
+// Server certificate verifiers built from two others: requiring both
+// to accept a certificate, either to, or comparing their results.
+
+use crate::client::ServerName;
+use crate::error::Error;
+use crate::key::Certificate;
+use crate::limited_cache::LimitedCache;
+#[cfg(feature = "logging")]
+use crate::log::{debug, warn};
+use crate::msgs::enums::SignatureScheme;
+use crate::msgs::handshake::DigitallySignedStruct;
+use crate::verify::{
+    HandshakeSignatureValid, ServerCertVerification, ServerCertVerified, ServerCertVerifier,
+};
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// A verifier which accepts a server certificate only if both `A` and
+/// `B` accept it: for example, a `WebPkiVerifier` and a verifier which
+/// pins the server's key.
+///
+/// `B` is not called if `A` rejects the certificate.  Handshake
+/// signatures are verified by `A`.
+///
+/// `B` may complete asynchronously, but `A` may not: if it returns
+/// `ServerCertVerification::Pending`, verification fails.
+pub struct AndVerifier<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A, B> AndVerifier<A, B> {
+    /// Require certificates to be accepted by both `first` and `second`.
+    pub fn new(first: A, second: B) -> Self {
+        Self { first, second }
+    }
+}
+
+impl<A: ServerCertVerifier, B: ServerCertVerifier> ServerCertVerifier for AndVerifier<A, B> {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let scts: Vec<&[u8]> = scts.collect();
+
+        self.first.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            &mut scts.iter().copied(),
+            ocsp_response,
+            now,
+        )?;
+        self.second.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            &mut scts.iter().copied(),
+            ocsp_response,
+            now,
+        )
+    }
+
+    fn start_verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> ServerCertVerification {
+        let scts: Vec<&[u8]> = scts.collect();
+
+        let first = self.first.start_verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            &mut scts.iter().copied(),
+            ocsp_response,
+            now,
+        );
+        match first {
+            ServerCertVerification::Complete(Ok(_)) => {}
+            ServerCertVerification::Complete(Err(err)) => {
+                return ServerCertVerification::Complete(Err(err))
+            }
+            ServerCertVerification::Pending(_) => return first_verifier_pending("AndVerifier"),
+        }
+
+        self.second.start_verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            &mut scts.iter().copied(),
+            ocsp_response,
+            now,
+        )
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.first
+            .verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.first
+            .verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.first.supported_verify_schemes()
+    }
+
+    fn request_scts(&self) -> bool {
+        self.first.request_scts() || self.second.request_scts()
+    }
+}
+
+/// A verifier which accepts a server certificate if either `A` or `B`
+/// accepts it: for example, a `WebPkiVerifier` trusting a corporate CA,
+/// and another trusting public roots.
+///
+/// `B` is only called if `A` rejects the certificate; if both reject it,
+/// `A`'s error is returned.  Handshake signatures are verified by
+/// whichever verifier accepted the server's certificate.
+///
+/// `B` may complete asynchronously, but `A` may not: if it returns
+/// `ServerCertVerification::Pending`, verification fails.
+pub struct OrVerifier<A, B> {
+    first: A,
+    second: B,
+    /// End-entity certificates recently accepted by `second`, rather
+    /// than `first`.
+    second_accepted: Arc<Mutex<LimitedCache<Vec<u8>, ()>>>,
+}
+
+impl<A, B> OrVerifier<A, B> {
+    /// Accept certificates accepted by either `first` or `second`.
+    pub fn new(first: A, second: B) -> Self {
+        Self {
+            first,
+            second,
+            second_accepted: Arc::new(Mutex::new(LimitedCache::new(32))),
+        }
+    }
+
+    fn second_accepted(&self, cert: &Certificate) -> bool {
+        self.second_accepted
+            .lock()
+            .get(&cert.0)
+            .is_some()
+    }
+
+    fn first_accepted(&self, end_entity: &Certificate) {
+        self.second_accepted
+            .lock()
+            .remove(&end_entity.0);
+    }
+}
+
+/// Finish an `OrVerifier` verification, once `first` has rejected the
+/// certificate with `first_error` and `second` has returned `result`.
+fn or_second_result(
+    second_accepted: &Mutex<LimitedCache<Vec<u8>, ()>>,
+    end_entity: &Certificate,
+    first_error: Error,
+    result: Result<ServerCertVerified, Error>,
+) -> Result<ServerCertVerified, Error> {
+    #[cfg_attr(not(feature = "logging"), allow(unused_variables))]
+    match result {
+        Ok(verified) => {
+            debug!(
+                "first verifier rejected certificate ({:?}); second accepted it",
+                first_error
+            );
+            second_accepted
+                .lock()
+                .insert(end_entity.0.clone(), ());
+            Ok(verified)
+        }
+        Err(err) => {
+            debug!(
+                "both verifiers rejected certificate: {:?}, {:?}",
+                first_error, err
+            );
+            Err(first_error)
+        }
+    }
+}
+
+/// The error when a combinator's first verifier returns
+/// `ServerCertVerification::Pending`.
+fn first_verifier_pending(combinator: &str) -> ServerCertVerification {
+    ServerCertVerification::Complete(Err(Error::General(format!(
+        "{} cannot wait for an asynchronous first verifier",
+        combinator
+    ))))
+}
+
+impl<A: ServerCertVerifier, B: ServerCertVerifier> ServerCertVerifier for OrVerifier<A, B> {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let scts: Vec<&[u8]> = scts.collect();
+
+        let first_error = match self.first.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            &mut scts.iter().copied(),
+            ocsp_response,
+            now,
+        ) {
+            Ok(verified) => {
+                self.first_accepted(end_entity);
+                return Ok(verified);
+            }
+            Err(err) => err,
+        };
+
+        let result = self.second.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            &mut scts.iter().copied(),
+            ocsp_response,
+            now,
+        );
+        or_second_result(&self.second_accepted, end_entity, first_error, result)
+    }
+
+    fn start_verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> ServerCertVerification {
+        let scts: Vec<&[u8]> = scts.collect();
+
+        let first = self.first.start_verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            &mut scts.iter().copied(),
+            ocsp_response,
+            now,
+        );
+        let first_error = match first {
+            ServerCertVerification::Complete(Ok(verified)) => {
+                self.first_accepted(end_entity);
+                return ServerCertVerification::Complete(Ok(verified));
+            }
+            ServerCertVerification::Complete(Err(err)) => err,
+            ServerCertVerification::Pending(_) => return first_verifier_pending("OrVerifier"),
+        };
+
+        match self.second.start_verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            &mut scts.iter().copied(),
+            ocsp_response,
+            now,
+        ) {
+            ServerCertVerification::Complete(result) => ServerCertVerification::Complete(
+                or_second_result(&self.second_accepted, end_entity, first_error, result),
+            ),
+            ServerCertVerification::Pending(pending) => {
+                let second_accepted = Arc::clone(&self.second_accepted);
+                let end_entity = end_entity.clone();
+                pending.map(move |result, _| {
+                    or_second_result(&second_accepted, &end_entity, first_error, result)
+                });
+                ServerCertVerification::Pending(pending)
+            }
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        if self.second_accepted(cert) {
+            self.second
+                .verify_tls12_signature(message, cert, dss)
+        } else {
+            self.first
+                .verify_tls12_signature(message, cert, dss)
+        }
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        if self.second_accepted(cert) {
+            self.second
+                .verify_tls13_signature(message, cert, dss)
+        } else {
+            self.first
+                .verify_tls13_signature(message, cert, dss)
+        }
+    }
+
+    /// Returns the schemes supported by `A`, followed by any others
+    /// supported by `B`: the server's choice is made before it is known
+    /// which verifier will accept its certificate.
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        let mut schemes = self.first.supported_verify_schemes();
+        for scheme in self.second.supported_verify_schemes() {
+            if !schemes.contains(&scheme) {
+                schemes.push(scheme);
+            }
+        }
+        schemes
+    }
+
+    fn request_scts(&self) -> bool {
+        self.first.request_scts() || self.second.request_scts()
+    }
+}
+
+/// A disagreement between the verifiers of a [`ShadowVerifier`]: one
+/// accepted a server certificate which the other rejected.
+#[derive(Debug, Clone)]
+pub struct ShadowDivergence {
+    /// The name the certificate was verified for.
+    pub server_name: ServerName,
+    /// The server's end-entity certificate.
+    pub end_entity: Certificate,
+    /// The primary verifier's result, which was used.
+    pub primary: Result<(), Error>,
+    /// The candidate verifier's result, which was discarded.
+    pub candidate: Result<(), Error>,
+}
+
+/// A wrapper which verifies each server certificate with both a `primary`
+/// and a `candidate` verifier, but only ever uses the primary's result.
+///
+/// This is for trialling a change of trust anchors or policy on real
+/// traffic: each time the verifiers disagree, the divergence is passed
+/// to a callback.  Each certificate is verified twice, so this costs
+/// roughly double the primary alone.
+///
+/// Handshake signatures are only verified by the primary.  The primary
+/// may complete asynchronously; if the candidate does, its result is
+/// not compared.
+pub struct ShadowVerifier<P, C> {
+    primary: P,
+    candidate: C,
+    on_divergence: Arc<dyn Fn(&ShadowDivergence) + Send + Sync>,
+}
+
+impl<P, C> ShadowVerifier<P, C> {
+    /// Wrap `primary` and `candidate`, calling `on_divergence` whenever
+    /// one accepts a certificate the other rejects.
+    pub fn new(
+        primary: P,
+        candidate: C,
+        on_divergence: impl Fn(&ShadowDivergence) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            primary,
+            candidate,
+            on_divergence: Arc::new(on_divergence),
+        }
+    }
+}
+
+fn check_divergence(
+    on_divergence: &(dyn Fn(&ShadowDivergence) + Send + Sync),
+    server_name: &ServerName,
+    end_entity: &Certificate,
+    primary: Result<ServerCertVerified, Error>,
+    candidate: Result<(), Error>,
+) -> Result<ServerCertVerified, Error> {
+    if primary.is_ok() != candidate.is_ok() {
+        let divergence = ShadowDivergence {
+            server_name: server_name.clone(),
+            end_entity: end_entity.clone(),
+            primary: primary
+                .as_ref()
+                .map(|_| ())
+                .map_err(Clone::clone),
+            candidate,
+        };
+        warn!("shadow verification diverged: {:?}", divergence);
+        on_divergence(&divergence);
+    }
+
+    primary
+}
+
+impl<P: ServerCertVerifier, C: ServerCertVerifier> ServerCertVerifier for ShadowVerifier<P, C> {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let scts: Vec<&[u8]> = scts.collect();
+
+        let primary = self.primary.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            &mut scts.iter().copied(),
+            ocsp_response,
+            now,
+        );
+        let candidate = self.candidate.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            &mut scts.iter().copied(),
+            ocsp_response,
+            now,
+        );
+
+        check_divergence(
+            &*self.on_divergence,
+            server_name,
+            end_entity,
+            primary,
+            candidate.map(|_| ()),
+        )
+    }
+
+    fn start_verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> ServerCertVerification {
+        let scts: Vec<&[u8]> = scts.collect();
+
+        let primary = self.primary.start_verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            &mut scts.iter().copied(),
+            ocsp_response,
+            now,
+        );
+        let candidate = match self.candidate.start_verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            &mut scts.iter().copied(),
+            ocsp_response,
+            now,
+        ) {
+            ServerCertVerification::Complete(result) => result.map(|_| ()),
+            ServerCertVerification::Pending(_) => {
+                warn!("not comparing asynchronous candidate verification");
+                return primary;
+            }
+        };
+
+        match primary {
+            ServerCertVerification::Complete(primary) => {
+                ServerCertVerification::Complete(check_divergence(
+                    &*self.on_divergence,
+                    server_name,
+                    end_entity,
+                    primary,
+                    candidate,
+                ))
+            }
+            ServerCertVerification::Pending(pending) => {
+                let on_divergence = Arc::clone(&self.on_divergence);
+                let server_name = server_name.clone();
+                let end_entity = end_entity.clone();
+                pending.map(move |primary, _| {
+                    check_divergence(
+                        &*on_divergence,
+                        &server_name,
+                        &end_entity,
+                        primary,
+                        candidate,
+                    )
+                });
+                ServerCertVerification::Pending(pending)
+            }
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.primary
+            .verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.primary
+            .verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.primary.supported_verify_schemes()
+    }
+
+    fn request_scts(&self) -> bool {
+        self.primary.request_scts() || self.candidate.request_scts()
+    }
+}
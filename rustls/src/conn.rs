@@ -1,4 +1,5 @@
 use crate::cipher;
+use crate::client::RenegotiationPolicy;
 use crate::error::Error;
 use crate::key;
 #[cfg(feature = "logging")]
@@ -7,9 +8,9 @@ use crate::msgs::alert::AlertMessagePayload;
 use crate::msgs::base::Payload;
 use crate::msgs::codec::Codec;
 use crate::msgs::deframer::MessageDeframer;
-use crate::msgs::enums::HandshakeType;
 use crate::msgs::enums::{AlertDescription, AlertLevel, ContentType, ProtocolVersion};
-use crate::msgs::fragmenter::MessageFragmenter;
+use crate::msgs::enums::{HandshakeType, MaxFragmentLength};
+use crate::msgs::fragmenter::{MessageFragmenter, MAX_FRAGMENT_LEN};
 use crate::msgs::hsjoiner::HandshakeJoiner;
 use crate::msgs::message::{BorrowedOpaqueMessage, Message, MessagePayload, OpaqueMessage};
 use crate::prf;
@@ -222,7 +223,9 @@ pub trait Connection: quic::QuicExt + Send + Sync {
     ///
     /// This is made available for both full and resumed handshakes.
     ///
-    /// For clients, this is the certificate chain of the server.
+    /// For clients, this is the certificate chain of the server.  If the
+    /// server authenticated with a raw public key (RFC7250), it is instead
+    /// a single DER-encoded `SubjectPublicKeyInfo`.
     ///
     /// For servers, this is the certificate chain of the client,
     /// if client authentication was completed.
@@ -351,6 +354,29 @@ pub struct ConnectionRandoms {
     pub server: [u8; 32],
 }
 
+/// The smallest record_size_limit allowed by RFC8449.
+pub const MIN_RECORD_SIZE_LIMIT: usize = 64;
+
+/// The largest record_size_limit useful in TLS1.3; in TLS1.2 it's
+/// one less.
+const MAX_RECORD_SIZE_LIMIT: usize = MAX_FRAGMENT_LEN + 1;
+
+/// The most a record's ciphertext may exceed its plaintext by, as
+/// RFC8446 allows.
+const MAX_CIPHERTEXT_EXPANSION: usize = 256;
+
+/// The number of bytes of plaintext a record may hold under the
+/// max_fragment_length extension's `len`.
+fn fragment_len(len: MaxFragmentLength) -> Option<usize> {
+    match len {
+        MaxFragmentLength::Len512 => Some(512),
+        MaxFragmentLength::Len1024 => Some(1024),
+        MaxFragmentLength::Len2048 => Some(2048),
+        MaxFragmentLength::Len4096 => Some(4096),
+        MaxFragmentLength::Unknown(_) => None,
+    }
+}
+
 static TLS12_DOWNGRADE_SENTINEL: [u8; 8] = [0x44, 0x4f, 0x57, 0x4e, 0x47, 0x52, 0x44, 0x01];
 
 impl ConnectionRandoms {
@@ -559,9 +585,23 @@ pub struct ConnectionCommon {
     peer_eof: bool,
     pub traffic: bool,
     pub early_traffic: bool,
+    /// Whether application data may be sent: set with `traffic`, or
+    /// before it by a TLS1.2 client doing False Start.
+    outgoing_traffic: bool,
     sent_fatal_alert: bool,
     received_middlebox_ccs: bool,
+    /// How many more bytes of early data we'll skip, if we're a
+    /// server which rejected some.
+    rejected_early_data_left: Option<usize>,
     error: Option<Error>,
+    /// The record_size_limit we advertise, if configured.
+    record_size_limit: Option<usize>,
+    /// The largest plaintext fragment we accept, if the
+    /// max_fragment_length extension was negotiated.
+    max_received_fragment_len: Option<usize>,
+    /// The largest plaintext fragment we accept in protected records,
+    /// if the record_size_limit extension was negotiated.
+    max_protected_fragment_len: Option<usize>,
     message_deframer: MessageDeframer,
     pub handshake_joiner: HandshakeJoiner,
     pub message_fragmenter: MessageFragmenter,
@@ -570,12 +610,39 @@ pub struct ConnectionCommon {
     pub sendable_tls: ChunkVecBuffer,
     /// Protocol whose key schedule should be used. Unused for TLS < 1.3.
     pub protocol: Protocol,
+    /// How a client answers TLS1.2 renegotiation requests.
+    pub(crate) renegotiation_policy: RenegotiationPolicy,
     #[cfg(feature = "quic")]
     pub(crate) quic: Quic,
 }
 
 impl ConnectionCommon {
-    pub fn new(max_fragment_size: Option<usize>, client: bool) -> Result<ConnectionCommon, Error> {
+    pub fn new(
+        max_fragment_size: Option<usize>,
+        max_fragment_length: Option<MaxFragmentLength>,
+        record_size_limit: Option<usize>,
+        client: bool,
+    ) -> Result<ConnectionCommon, Error> {
+        if let Some(limit) = record_size_limit {
+            if !(MIN_RECORD_SIZE_LIMIT..=MAX_RECORD_SIZE_LIMIT).contains(&limit) {
+                return Err(Error::BadRecordSizeLimit);
+            }
+        }
+
+        // Expect the peer to agree to the limits we ask for, so our
+        // buffer needn't start any larger.
+        let message_deframer = match max_fragment_length
+            .and_then(fragment_len)
+            .into_iter()
+            .chain(record_size_limit)
+            .min()
+        {
+            Some(max_len) => {
+                MessageDeframer::with_expected_payload(max_len + MAX_CIPHERTEXT_EXPANSION)
+            }
+            None => MessageDeframer::new(),
+        };
+
         Ok(ConnectionCommon {
             negotiated_version: None,
             is_client: client,
@@ -585,10 +652,15 @@ impl ConnectionCommon {
             peer_eof: false,
             traffic: false,
             early_traffic: false,
+            outgoing_traffic: false,
             sent_fatal_alert: false,
             received_middlebox_ccs: false,
+            rejected_early_data_left: None,
             error: None,
-            message_deframer: MessageDeframer::new(),
+            record_size_limit,
+            max_received_fragment_len: None,
+            max_protected_fragment_len: None,
+            message_deframer,
             handshake_joiner: HandshakeJoiner::new(),
             message_fragmenter: MessageFragmenter::new(max_fragment_size)
                 .map_err(|_| Error::BadMaxFragmentSize)?,
@@ -596,6 +668,7 @@ impl ConnectionCommon {
             sendable_plaintext: ChunkVecBuffer::new(),
             sendable_tls: ChunkVecBuffer::new(),
             protocol: Protocol::Tcp,
+            renegotiation_policy: RenegotiationPolicy::Refuse,
             #[cfg(feature = "quic")]
             quic: Quic::new(),
         })
@@ -635,10 +708,30 @@ impl ConnectionCommon {
             }
         }
 
+        // Skip early data we rejected (RFC8446 section 4.2.10): it's
+        // protected with keys we don't have, or unprotected ones if we
+        // sent a HelloRetryRequest.
+        let skippable = self.rejected_early_data_left.is_some()
+            && msg.typ == ContentType::ApplicationData;
+        let len = msg.payload.0.len();
+
         // Decrypt if demanded by current state.
         if self.record_layer.is_decrypting() {
-            let dm = self.decrypt_incoming(msg)?;
-            msg = dm;
+            match self.decrypt_incoming(msg) {
+                Ok(dm) => msg = dm,
+                Err(Error::DecryptError) if skippable => return self.skip_early_data(len),
+                Err(err) => return Err(err),
+            }
+        } else if skippable {
+            return self.skip_early_data(len);
+        }
+        self.rejected_early_data_left = None;
+
+        if let Some(max_len) = self.max_received_fragment_len {
+            if msg.payload.0.len() > max_len {
+                self.send_fatal_alert(AlertDescription::RecordOverflow);
+                return Err(Error::PeerSentOversizedRecord);
+            }
         }
 
         // For handshake messages, we need to join them before parsing
@@ -677,38 +770,78 @@ impl ConnectionCommon {
             return Err(Error::CorruptMessage);
         }
 
-        while let Some(msg) = self.message_deframer.frames.pop_front() {
-            let result = self
-                .process_msg(msg)
-                .and_then(|val| match val {
-                    Some(MessageType::Handshake) => {
-                        self.process_new_handshake_messages(state, data)
-                    }
-                    Some(MessageType::Data(msg)) => self.process_main_protocol(msg, state, data),
-                    None => Ok(()),
-                });
-
-            if let Err(err) = result {
-                self.error = Some(err.clone());
-                return Err(err);
-            }
+        if let Err(err) = self.process_queued_messages(state, data) {
+            self.error = Some(err.clone());
+            return Err(err);
         }
 
         Ok(self.current_io_state())
     }
 
+    fn process_queued_messages<S: HandleState>(
+        &mut self,
+        state: &mut Option<S>,
+        data: &mut S::Data,
+    ) -> Result<(), Error> {
+        // Handshake messages may have been left in the joiner when we
+        // last stopped to wait; they come before any later records.
+        self.process_new_handshake_messages(state, data)?;
+
+        while self.ready_for_messages(state, data)? {
+            let msg = match self.message_deframer.frames.pop_front() {
+                Some(msg) => msg,
+                None => break,
+            };
+
+            match self.process_msg(msg)? {
+                Some(MessageType::Handshake) => self.process_new_handshake_messages(state, data)?,
+                Some(MessageType::Data(msg)) => self.process_main_protocol(msg, state, data)?,
+                None => {}
+            }
+        }
+
+        Ok(())
+    }
+
     pub(crate) fn process_new_handshake_messages<S: HandleState>(
         &mut self,
         state: &mut Option<S>,
         data: &mut S::Data,
     ) -> Result<(), Error> {
-        while let Some(msg) = self.handshake_joiner.frames.pop_front() {
+        while self.ready_for_messages(state, data)? {
+            let msg = match self.handshake_joiner.frames.pop_front() {
+                Some(msg) => msg,
+                None => break,
+            };
             self.process_main_protocol(msg, state, data)?;
         }
 
         Ok(())
     }
 
+    /// Returns false if the current state is waiting, and cannot handle
+    /// messages yet.  A state which has finished waiting is resumed.
+    ///
+    /// Records are left undecrypted while we wait, because the state
+    /// we resume into may change the keys.
+    fn ready_for_messages<S: HandleState>(
+        &mut self,
+        state: &mut Option<S>,
+        data: &mut S::Data,
+    ) -> Result<bool, Error> {
+        if !matches!(state, Some(st) if st.is_waiting()) {
+            return Ok(true);
+        }
+
+        let next = state
+            .take()
+            .unwrap()
+            .resume(data, self)?;
+        let ready = !next.is_waiting();
+        *state = Some(next);
+        Ok(ready)
+    }
+
     /// Process `msg`.  First, we get the current state.  Then we ask what messages
     /// that state expects, enforced via `check_message`.  Finally, we ask the handler
     /// to handle the message.
@@ -718,17 +851,8 @@ impl ConnectionCommon {
         state: &mut Option<S>,
         data: &mut S::Data,
     ) -> Result<(), Error> {
-        // For TLS1.2, outside of the handshake, send rejection alerts for
-        // renegotiation requests.  These can occur any time.
-        if self.traffic && !self.is_tls13() {
-            let reject_ty = match self.is_client {
-                true => HandshakeType::HelloRequest,
-                false => HandshakeType::ClientHello,
-            };
-            if msg.is_handshake_type(reject_ty) {
-                self.send_warning_alert(AlertDescription::NoRenegotiation);
-                return Ok(());
-            }
+        if self.handle_renegotiation_request(&msg)? {
+            return Ok(());
         }
 
         let current = state.take().unwrap();
@@ -746,6 +870,49 @@ impl ConnectionCommon {
         }
     }
 
+    /// For TLS1.2, deal with renegotiation requests, which can occur
+    /// any time.  Returns true if `msg` was one, and has been handled.
+    fn handle_renegotiation_request(&mut self, msg: &Message) -> Result<bool, Error> {
+        if self.is_tls13() {
+            return Ok(false);
+        }
+
+        if !self.is_client {
+            if self.traffic && msg.is_handshake_type(HandshakeType::ClientHello) {
+                self.send_warning_alert(AlertDescription::NoRenegotiation);
+                return Ok(true);
+            }
+            return Ok(false);
+        }
+
+        if !msg.is_handshake_type(HandshakeType::HelloRequest) {
+            return Ok(false);
+        }
+
+        // RFC5246 section 7.4.1.1: clients ignore these mid-handshake.
+        if !self.traffic {
+            debug!("Ignoring HelloRequest during handshake");
+            return Ok(true);
+        }
+
+        match self.renegotiation_policy {
+            RenegotiationPolicy::Ignore => {
+                debug!("Ignoring renegotiation request");
+                Ok(true)
+            }
+            RenegotiationPolicy::Refuse => {
+                self.send_warning_alert(AlertDescription::NoRenegotiation);
+                Ok(true)
+            }
+            RenegotiationPolicy::Abort => {
+                self.send_fatal_alert(AlertDescription::HandshakeFailure);
+                Err(Error::PeerIncompatibleError(
+                    "server requested renegotiation".to_string(),
+                ))
+            }
+        }
+    }
+
     // Changing the keys must not span any fragmented handshake
     // messages.  Otherwise the defragmented messages will have
     // been protected with two different record layer protections,
@@ -766,6 +933,64 @@ impl ConnectionCommon {
         Error::PeerMisbehavedError(why.to_string())
     }
 
+    /// Limit records in both directions to `len` bytes of plaintext,
+    /// once the max_fragment_length extension has been negotiated.
+    pub fn set_max_fragment_length(&mut self, len: MaxFragmentLength) {
+        let len = match fragment_len(len) {
+            Some(len) => len,
+            // Rejected during negotiation.
+            None => return,
+        };
+
+        self.message_fragmenter
+            .set_max_fragment_len(len);
+        self.max_received_fragment_len = Some(len);
+        self.limit_deframer();
+    }
+
+    /// The record_size_limit value we advertise: the configured one,
+    /// or else the largest the protocol allows.
+    pub fn advertised_record_size_limit(&self) -> u16 {
+        let max = if self.is_tls13() {
+            MAX_RECORD_SIZE_LIMIT
+        } else {
+            MAX_FRAGMENT_LEN
+        };
+        self.record_size_limit.unwrap_or(max) as u16
+    }
+
+    /// Once the record_size_limit extension has been negotiated, limit
+    /// the records we send to `peer_limit` and those we receive to the
+    /// limit we advertised.  In TLS1.3 these count the content type.
+    /// Unprotected records are not limited.
+    pub fn set_record_size_limits(&mut self, peer_limit: u16) {
+        let content_type_len = if self.is_tls13() { 1 } else { 0 };
+        self.message_fragmenter
+            .set_max_fragment_len(usize::from(peer_limit) - content_type_len);
+        let limit = usize::from(self.advertised_record_size_limit());
+        self.max_protected_fragment_len = Some(limit - content_type_len);
+        self.limit_deframer();
+    }
+
+    /// Stop the deframer buffering records larger than we now accept.
+    /// In TLS1.2 the rest of the handshake may arrive unprotected, and
+    /// so be larger than the record_size_limit we advertised.
+    fn limit_deframer(&mut self) {
+        let protected = self
+            .max_protected_fragment_len
+            .filter(|_| self.is_tls13());
+        let max_len = self
+            .max_received_fragment_len
+            .into_iter()
+            .chain(protected)
+            .min();
+
+        if let Some(max_len) = max_len {
+            self.message_deframer
+                .set_max_payload(max_len + MAX_CIPHERTEXT_EXPANSION);
+        }
+    }
+
     pub fn get_suite(&self) -> Option<&'static SupportedCipherSuite> {
         self.suite
     }
@@ -776,6 +1001,26 @@ impl ConnectionCommon {
             .map(AsRef::as_ref)
     }
 
+    /// Skip up to `limit` bytes of records the peer protected with
+    /// early data keys, which we've rejected (RFC8446 section 4.2.10).
+    /// This stops at the first record which isn't early data.
+    pub fn skip_rejected_early_data(&mut self, limit: usize) {
+        self.rejected_early_data_left = Some(limit);
+    }
+
+    fn skip_early_data(&mut self, len: usize) -> Result<Option<MessageType>, Error> {
+        match self.rejected_early_data_left {
+            Some(left) if len <= left => {
+                trace!("Skipping {} bytes of rejected early data", len);
+                self.rejected_early_data_left = Some(left - len);
+                Ok(None)
+            }
+            _ => Err(Error::PeerMisbehavedError(
+                "too much early data received".into(),
+            )),
+        }
+    }
+
     pub fn decrypt_incoming(&mut self, encr: OpaqueMessage) -> Result<OpaqueMessage, Error> {
         if self
             .record_layer
@@ -784,7 +1029,13 @@ impl ConnectionCommon {
             self.send_close_notify();
         }
 
-        let rc = self.record_layer.decrypt_incoming(encr);
+        let mut rc = self.record_layer.decrypt_incoming(encr);
+        if let Some(max_len) = self.max_protected_fragment_len {
+            if matches!(&rc, Ok(plain) if plain.payload.0.len() > max_len) {
+                rc = Err(Error::PeerSentOversizedRecord);
+            }
+        }
+
         if let Err(Error::PeerSentOversizedRecord) = rc {
             self.send_fatal_alert(AlertDescription::RecordOverflow);
         }
@@ -933,7 +1184,7 @@ impl ConnectionCommon {
     /// Returns the number of bytes written from `data`: this might
     /// be less than `data.len()` if buffer limits were exceeded.
     fn send_plain(&mut self, data: &[u8], limit: Limit) -> usize {
-        if !self.traffic {
+        if !self.outgoing_traffic {
             // If we haven't completed handshaking, buffer
             // plaintext to send once we do.
             let len = match limit {
@@ -959,13 +1210,19 @@ impl ConnectionCommon {
 
     pub fn start_traffic(&mut self) {
         self.traffic = true;
+        self.start_outgoing_traffic();
+    }
+
+    /// Start sending application data before the handshake completes.
+    pub fn start_outgoing_traffic(&mut self) {
+        self.outgoing_traffic = true;
         self.flush_plaintext();
     }
 
     /// Send any buffered plaintext.  Plaintext is buffered if
     /// written during handshake.
     fn flush_plaintext(&mut self) {
-        if !self.traffic {
+        if !self.outgoing_traffic {
             return;
         }
 
@@ -1087,6 +1344,18 @@ pub(crate) trait HandleState: Sized {
         data: &mut Self::Data,
         common: &mut ConnectionCommon,
     ) -> Result<Self, Error>;
+
+    /// Returns true if this state is waiting for something other than
+    /// a message, such as an asynchronous certificate verification.
+    fn is_waiting(&self) -> bool {
+        false
+    }
+
+    /// Continue from a waiting state, if possible.  Returns the state
+    /// unchanged if it must wait longer.
+    fn resume(self, _data: &mut Self::Data, _common: &mut ConnectionCommon) -> Result<Self, Error> {
+        Ok(self)
+    }
 }
 
 pub enum MessageType {
@@ -1121,3 +1390,162 @@ impl Quic {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msgs::handshake::{HandshakeMessagePayload, HandshakePayload};
+
+    fn hello_request() -> Message {
+        Message {
+            version: ProtocolVersion::TLSv1_2,
+            payload: MessagePayload::Handshake(HandshakeMessagePayload {
+                typ: HandshakeType::HelloRequest,
+                payload: HandshakePayload::HelloRequest,
+            }),
+        }
+    }
+
+    fn tls12_client(policy: RenegotiationPolicy, traffic: bool) -> ConnectionCommon {
+        let mut common = ConnectionCommon::new(None, None, None, true).unwrap();
+        common.negotiated_version = Some(ProtocolVersion::TLSv1_2);
+        common.traffic = traffic;
+        common.renegotiation_policy = policy;
+        common
+    }
+
+    fn sent_tls(common: &mut ConnectionCommon) -> Vec<u8> {
+        let mut buf = Vec::new();
+        common.write_tls(&mut buf).unwrap();
+        buf
+    }
+
+    fn alert(level: AlertLevel, desc: AlertDescription) -> Vec<u8> {
+        OpaqueMessage::from(Message::build_alert(level, desc)).encode()
+    }
+
+    #[test]
+    fn client_refuses_renegotiation_by_default() {
+        let mut common = ConnectionCommon::new(None, None, None, true).unwrap();
+        common.negotiated_version = Some(ProtocolVersion::TLSv1_2);
+        common.traffic = true;
+        assert_eq!(
+            common.handle_renegotiation_request(&hello_request()),
+            Ok(true)
+        );
+        assert_eq!(
+            sent_tls(&mut common),
+            alert(AlertLevel::Warning, AlertDescription::NoRenegotiation)
+        );
+    }
+
+    #[test]
+    fn client_can_ignore_renegotiation() {
+        let mut common = tls12_client(RenegotiationPolicy::Ignore, true);
+        assert_eq!(
+            common.handle_renegotiation_request(&hello_request()),
+            Ok(true)
+        );
+        assert!(sent_tls(&mut common).is_empty());
+    }
+
+    #[test]
+    fn client_can_abort_on_renegotiation() {
+        let mut common = tls12_client(RenegotiationPolicy::Abort, true);
+        assert_eq!(
+            common.handle_renegotiation_request(&hello_request()),
+            Err(Error::PeerIncompatibleError(
+                "server requested renegotiation".to_string()
+            ))
+        );
+        assert_eq!(
+            sent_tls(&mut common),
+            alert(AlertLevel::Fatal, AlertDescription::HandshakeFailure)
+        );
+    }
+
+    #[test]
+    fn client_ignores_hello_request_during_handshake() {
+        let mut common = tls12_client(RenegotiationPolicy::Abort, false);
+        assert_eq!(
+            common.handle_renegotiation_request(&hello_request()),
+            Ok(true)
+        );
+        assert!(sent_tls(&mut common).is_empty());
+    }
+
+    #[test]
+    fn server_does_not_take_hello_request() {
+        let mut common = ConnectionCommon::new(None, None, None, false).unwrap();
+        common.negotiated_version = Some(ProtocolVersion::TLSv1_2);
+        common.traffic = true;
+        assert_eq!(
+            common.handle_renegotiation_request(&hello_request()),
+            Ok(false)
+        );
+    }
+
+    fn application_data(len: usize) -> OpaqueMessage {
+        OpaqueMessage {
+            typ: ContentType::ApplicationData,
+            version: ProtocolVersion::TLSv1_2,
+            payload: Payload::new(vec![0u8; len]),
+        }
+    }
+
+    #[test]
+    fn max_fragment_length_limits_unprotected_records() {
+        let mut common = tls12_client(RenegotiationPolicy::Refuse, false);
+        common.set_max_fragment_length(MaxFragmentLength::Len512);
+        assert!(common.process_msg(application_data(512)).is_ok());
+        assert!(matches!(
+            common.process_msg(application_data(513)),
+            Err(Error::PeerSentOversizedRecord)
+        ));
+        assert_eq!(
+            sent_tls(&mut common),
+            alert(AlertLevel::Fatal, AlertDescription::RecordOverflow)
+        );
+    }
+
+    #[test]
+    fn deframer_is_sized_for_requested_limits() {
+        let common =
+            ConnectionCommon::new(None, Some(MaxFragmentLength::Len512), None, true).unwrap();
+        assert_eq!(common.message_deframer.buffer_len(), 512 + 256 + 5);
+
+        let common = ConnectionCommon::new(None, None, Some(1024), false).unwrap();
+        assert_eq!(common.message_deframer.buffer_len(), 1024 + 256 + 5);
+
+        let common = ConnectionCommon::new(None, None, None, true).unwrap();
+        assert_eq!(
+            common.message_deframer.buffer_len(),
+            OpaqueMessage::MAX_WIRE_SIZE
+        );
+    }
+
+    #[test]
+    fn deframer_grows_if_peer_ignores_requested_limits() {
+        let mut common =
+            ConnectionCommon::new(None, Some(MaxFragmentLength::Len512), None, true).unwrap();
+        let record = application_data(4096).encode();
+        let mut rd = &record[..];
+        while !rd.is_empty() {
+            common.read_tls(&mut rd).unwrap();
+        }
+        assert!(!common.message_deframer.desynced);
+        assert_eq!(common.message_deframer.frames.len(), 1);
+    }
+
+    #[test]
+    fn record_size_limit_ignores_unprotected_records() {
+        let mut common = tls12_client(RenegotiationPolicy::Refuse, false);
+        common.record_size_limit = Some(MIN_RECORD_SIZE_LIMIT);
+        common.set_record_size_limits(MIN_RECORD_SIZE_LIMIT as u16);
+
+        let record = application_data(1024).encode();
+        assert_eq!(common.read_tls(&mut &record[..]).unwrap(), record.len());
+        assert!(!common.message_deframer.desynced);
+        assert!(common.process_msg(application_data(1024)).is_ok());
+    }
+}
@@ -0,0 +1,410 @@
+// Gathering and verifying Signed Certificate Timestamps, per RFC6962.
+//
+// A server can deliver SCTs embedded in its certificate, in a stapled
+// OCSP response, or in the TLS extension.  The sct crate verifies those
+// logged against the final certificate (the latter two); embedded SCTs
+// are logged against the precertificate, so we verify those here.
+//
+// Trusted logs can be given as `sct::Log`s compiled into the program,
+// or loaded at runtime from a log list.
+
+use crate::anchors::RootCertStore;
+#[cfg(feature = "ct_log_lists")]
+use crate::error::Error;
+#[cfg(feature = "ct_log_lists")]
+use crate::json::Value;
+use crate::key::Certificate;
+#[cfg(feature = "logging")]
+use crate::log::debug;
+use crate::msgs::codec::{u24, Codec};
+use crate::ocsp;
+use crate::x509::{self, oid, CertFields, DerReader};
+use ring::io::der;
+
+/// A Certificate Transparency log, owning its data.
+///
+/// This is like a `sct::Log`, except it owns rather than borrows its
+/// memory, so logs can be loaded at runtime.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OwnedCtLog {
+    /// The log's name.
+    pub description: String,
+    /// The log's URL.
+    pub url: String,
+    /// The name of the log's operator.
+    pub operated_by: String,
+    /// The log's public key, as for `sct::Log::key`.
+    pub key: Vec<u8>,
+    /// The log's ID: the SHA-256 hash of its `SubjectPublicKeyInfo`.
+    pub id: [u8; 32],
+    /// The log's maximum merge delay, in seconds.
+    pub max_merge_delay: usize,
+}
+
+impl OwnedCtLog {
+    /// Copy a `sct::Log` into owned memory.
+    pub fn from_log(log: &sct::Log) -> Self {
+        Self {
+            description: log.description.to_string(),
+            url: log.url.to_string(),
+            operated_by: log.operated_by.to_string(),
+            key: log.key.to_vec(),
+            id: log.id,
+            max_merge_delay: log.max_merge_delay,
+        }
+    }
+
+    /// Get a `sct::Log` by borrowing the owned elements.
+    pub fn to_log(&self) -> sct::Log<'_> {
+        sct::Log {
+            description: &self.description,
+            url: &self.url,
+            operated_by: &self.operated_by,
+            key: &self.key,
+            id: self.id,
+            max_merge_delay: self.max_merge_delay,
+        }
+    }
+
+    /// Parse a log list in the format Google publishes for Chrome (at
+    /// <https://www.gstatic.com/ct/log_list/v3/log_list.json>), returning
+    /// its logs.
+    ///
+    /// Logs which are pending inclusion, or were rejected, are skipped.
+    /// The list is not authenticated: callers must obtain it from a
+    /// trusted source.  A list which doesn't parse, or contains a log
+    /// whose ID doesn't match its key, is rejected with
+    /// `Error::InvalidCtLogList`.
+    #[cfg(feature = "ct_log_lists")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "ct_log_lists")))]
+    pub fn from_log_list_json(json: &str) -> Result<Vec<Self>, Error> {
+        let list = Value::parse(json).ok_or_else(|| bad_log_list("malformed JSON"))?;
+        let operators = list
+            .get("operators")
+            .and_then(Value::as_array)
+            .ok_or_else(|| bad_log_list("missing operators"))?;
+
+        let mut logs = Vec::new();
+        for operator in operators {
+            let operated_by = operator
+                .get("name")
+                .and_then(Value::as_str)
+                .ok_or_else(|| bad_log_list("operator has no name"))?;
+            let operator_logs = operator
+                .get("logs")
+                .and_then(Value::as_array)
+                .ok_or_else(|| bad_log_list("operator has no logs"))?;
+
+            for log in operator_logs {
+                let state = log
+                    .get("state")
+                    .and_then(|state| match state {
+                        Value::Object(members) => members.first(),
+                        _ => None,
+                    })
+                    .map(|(name, _)| name.as_str());
+                if let Some("pending") | Some("rejected") = state {
+                    continue;
+                }
+
+                logs.push(Self::from_log_list_entry(log, operated_by)?);
+            }
+        }
+
+        Ok(logs)
+    }
+
+    #[cfg(feature = "ct_log_lists")]
+    fn from_log_list_entry(log: &Value, operated_by: &str) -> Result<Self, Error> {
+        let field = |name| {
+            log.get(name)
+                .and_then(Value::as_str)
+                .ok_or_else(|| bad_log_list(&format!("log has no {}", name)))
+        };
+        let base64_field = |name| {
+            base64::decode(field(name)?)
+                .map_err(|_| bad_log_list(&format!("log {} is not base64", name)))
+        };
+
+        let spki = base64_field("key")?;
+        let id = base64_field("log_id")?;
+        if id != ring::digest::digest(&ring::digest::SHA256, &spki).as_ref() {
+            return Err(bad_log_list("log ID does not match its key"));
+        }
+
+        let key = DerReader::new(&spki)
+            .read(der::Tag::Sequence as u8)
+            .and_then(x509::split_spki)
+            .map(|(_, key)| key)
+            .ok_or_else(|| bad_log_list("log key is not a SubjectPublicKeyInfo"))?;
+
+        let mut log_id = [0u8; 32];
+        log_id.copy_from_slice(&id);
+
+        let max_merge_delay = log
+            .get("mmd")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| bad_log_list("log has no mmd"))?;
+
+        Ok(Self {
+            description: field("description")?.to_string(),
+            url: field("url")?.to_string(),
+            operated_by: operated_by.to_string(),
+            key: key.to_vec(),
+            id: log_id,
+            max_merge_delay: max_merge_delay as usize,
+        })
+    }
+}
+
+#[cfg(feature = "ct_log_lists")]
+fn bad_log_list(why: &str) -> Error {
+    Error::InvalidCtLogList(why.to_string())
+}
+
+/// Where a server delivered an SCT.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum SctSource {
+    Embedded,
+    Ocsp,
+    TlsExtension,
+}
+
+/// The SCTs a server delivered, from all sources.
+pub(crate) struct SctSources<'a> {
+    scts: Vec<(SctSource, &'a [u8])>,
+}
+
+impl<'a> SctSources<'a> {
+    /// Gather the SCTs embedded in `end_entity`, those in the stapled
+    /// `ocsp_response`, and those in `tls_extension`.  SCT lists which
+    /// fail to parse contribute nothing.
+    ///
+    /// `intermediates` and `roots` are used to find the issuer, which
+    /// identifies the OCSP response for `end_entity`.
+    pub(crate) fn new<'b: 'a>(
+        end_entity: &'a Certificate,
+        intermediates: &[Certificate],
+        roots: &RootCertStore,
+        tls_extension: &mut dyn Iterator<Item = &'b [u8]>,
+        ocsp_response: &'a [u8],
+    ) -> Self {
+        let embedded = CertFields::parse(&end_entity.0)
+            .and_then(|cert| cert.extension(oid::CT_PRECERT_SCTS))
+            .and_then(|value| DerReader::new(value).read(der::Tag::OctetString as u8))
+            .and_then(split_sct_list)
+            .unwrap_or_default();
+        let stapled = ocsp::scts(ocsp_response, end_entity, intermediates, roots)
+            .and_then(split_sct_list)
+            .unwrap_or_default();
+
+        let scts = embedded
+            .into_iter()
+            .map(|sct| (SctSource::Embedded, sct))
+            .chain(
+                stapled
+                    .into_iter()
+                    .map(|sct| (SctSource::Ocsp, sct)),
+            )
+            .chain(tls_extension.map(|sct| (SctSource::TlsExtension, sct)))
+            .collect();
+
+        Self { scts }
+    }
+
+    /// Iterate over the SCTs, skipping any with the same encoding as
+    /// one seen earlier.  Embedded SCTs come first, then those from
+    /// OCSP, then those from the TLS extension.
+    pub(crate) fn distinct(&self) -> impl Iterator<Item = (SctSource, &'a [u8])> + '_ {
+        self.scts
+            .iter()
+            .enumerate()
+            .filter(move |(i, (_, sct))| {
+                !self.scts[..*i]
+                    .iter()
+                    .any(|(_, earlier)| earlier == sct)
+            })
+            .map(|(_, sct)| *sct)
+    }
+}
+
+/// Split a TLS-encoded SignedCertificateTimestampList into its SCTs.
+fn split_sct_list(list: &[u8]) -> Option<Vec<&[u8]>> {
+    let (mut rest, trailing) = split_u16_prefixed(list)?;
+    if !trailing.is_empty() || rest.is_empty() {
+        return None;
+    }
+
+    let mut scts = Vec::new();
+    while !rest.is_empty() {
+        let (sct, next) = split_u16_prefixed(rest)?;
+        if sct.is_empty() {
+            return None;
+        }
+        scts.push(sct);
+        rest = next;
+    }
+    Some(scts)
+}
+
+/// Split `bytes` into the item prefixed by a 16-bit length, and
+/// whatever follows it.
+fn split_u16_prefixed(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    let len = usize::from(u16::read_bytes(bytes.get(..2)?)?);
+    let rest = &bytes[2..];
+    if rest.len() < len {
+        return None;
+    }
+    Some((&rest[..len], &rest[len..]))
+}
+
+/// Verify `sct`, returning the index of the log in `logs` which issued
+/// it.  `intermediates` and `roots` are used to find the issuer of
+/// `end_entity`, which embedded SCTs depend on.
+pub(crate) fn verify_sct(
+    source: SctSource,
+    end_entity: &Certificate,
+    intermediates: &[Certificate],
+    roots: &RootCertStore,
+    sct: &[u8],
+    now: u64,
+    logs: &[&sct::Log],
+) -> Result<usize, sct::Error> {
+    match source {
+        SctSource::Ocsp | SctSource::TlsExtension => sct::verify_sct(&end_entity.0, sct, now, logs),
+        SctSource::Embedded => {
+            let cert = CertFields::parse(&end_entity.0).ok_or(sct::Error::MalformedSct)?;
+            let issuer_spki =
+                ocsp::find_issuer_spki(&cert, intermediates, roots).ok_or_else(|| {
+                    debug!("cannot find issuer to verify embedded SCT");
+                    sct::Error::UnknownLog
+                })?;
+            let tbs = precertificate_tbs(cert.tbs).ok_or(sct::Error::MalformedSct)?;
+            verify_precert_sct(&tbs, issuer_spki, sct, now, logs)
+        }
+    }
+}
+
+/// The fields of an SCT.
+struct Sct<'a> {
+    log_id: &'a [u8],
+    timestamp: u64,
+    extensions: &'a [u8],
+    signature_alg: u16,
+    signature: &'a [u8],
+}
+
+impl<'a> Sct<'a> {
+    fn parse(sct: &'a [u8]) -> Result<Self, sct::Error> {
+        match sct.first() {
+            Some(0) => {}
+            Some(_) => return Err(sct::Error::UnsupportedSctVersion),
+            None => return Err(sct::Error::MalformedSct),
+        }
+
+        let rest = &sct[1..];
+        let log_id = rest
+            .get(..32)
+            .ok_or(sct::Error::MalformedSct)?;
+        let timestamp = rest
+            .get(32..40)
+            .and_then(u64::read_bytes)
+            .ok_or(sct::Error::MalformedSct)?;
+        let (extensions, rest) = split_u16_prefixed(&rest[40..]).ok_or(sct::Error::MalformedSct)?;
+        let signature_alg = rest
+            .get(..2)
+            .and_then(u16::read_bytes)
+            .ok_or(sct::Error::MalformedSct)?;
+        match split_u16_prefixed(&rest[2..]) {
+            Some((signature, [])) => Ok(Self {
+                log_id,
+                timestamp,
+                extensions,
+                signature_alg,
+                signature,
+            }),
+            _ => Err(sct::Error::MalformedSct),
+        }
+    }
+}
+
+/// Verify an SCT logged against a precertificate with the given
+/// `tbs`, issued by the holder of `issuer_spki`.
+fn verify_precert_sct(
+    tbs: &[u8],
+    issuer_spki: &[u8],
+    sct: &[u8],
+    now: u64,
+    logs: &[&sct::Log],
+) -> Result<usize, sct::Error> {
+    let sct = Sct::parse(sct)?;
+    let index = logs
+        .iter()
+        .position(|log| log.id == sct.log_id)
+        .ok_or(sct::Error::UnknownLog)?;
+
+    let alg: &dyn ring::signature::VerificationAlgorithm = match sct.signature_alg {
+        0x0403 => &ring::signature::ECDSA_P256_SHA256_ASN1,
+        0x0503 => &ring::signature::ECDSA_P384_SHA384_ASN1,
+        0x0401 => &ring::signature::RSA_PKCS1_2048_8192_SHA256,
+        0x0501 => &ring::signature::RSA_PKCS1_2048_8192_SHA384,
+        _ => return Err(sct::Error::InvalidSignature),
+    };
+
+    let mut issuer_spki = issuer_spki.to_vec();
+    x509::wrap_in_sequence(&mut issuer_spki);
+
+    // version, signature_type, timestamp, entry_type
+    let mut signed = vec![0, 0];
+    sct.timestamp.encode(&mut signed);
+    signed.extend_from_slice(&[0, 1]);
+    signed.extend_from_slice(ring::digest::digest(&ring::digest::SHA256, &issuer_spki).as_ref());
+    u24(tbs.len() as u32).encode(&mut signed);
+    signed.extend_from_slice(tbs);
+    (sct.extensions.len() as u16).encode(&mut signed);
+    signed.extend_from_slice(sct.extensions);
+
+    ring::signature::UnparsedPublicKey::new(alg, logs[index].key)
+        .verify(&signed, sct.signature)
+        .map_err(|_| sct::Error::InvalidSignature)?;
+
+    if sct.timestamp > now {
+        return Err(sct::Error::TimestampInFuture);
+    }
+
+    Ok(index)
+}
+
+/// Rebuild the precertificate `TBSCertificate` a log signed, from the
+/// final certificate's `tbs`: this is the same, less the SCT list
+/// extension.
+fn precertificate_tbs(tbs: &[u8]) -> Option<Vec<u8>> {
+    let mut fields = DerReader::new(DerReader::new(tbs).read(der::Tag::Sequence as u8)?);
+    let mut out = Vec::new();
+
+    while !fields.at_end() {
+        let (tag, contents, whole) = fields.read_element()?;
+        if tag != der::Tag::ContextSpecificConstructed3 as u8 {
+            out.extend_from_slice(whole);
+            continue;
+        }
+
+        let mut exts = DerReader::new(DerReader::new(contents).read(der::Tag::Sequence as u8)?);
+        let mut kept = Vec::new();
+        while !exts.at_end() {
+            let (_, ext, whole) = exts.read_element()?;
+            if DerReader::new(ext).read(der::Tag::OID as u8)? != oid::CT_PRECERT_SCTS {
+                kept.extend_from_slice(whole);
+            }
+        }
+
+        if !kept.is_empty() {
+            x509::wrap_in_sequence(&mut kept);
+            x509::wrap_in_tag(der::Tag::ContextSpecificConstructed3 as u8, &mut kept);
+            out.extend_from_slice(&kept);
+        }
+    }
+
+    x509::wrap_in_sequence(&mut out);
+    Some(out)
+}
@@ -0,0 +1,253 @@
+// DNS-Based Authentication of Named Entities (DANE): authenticating
+// servers with TLSA records, as described in RFC 6698 and RFC 7671.
+
+use crate::client::ServerName;
+use crate::error::{Error, WebPkiOp};
+use crate::key::Certificate;
+#[cfg(feature = "logging")]
+use crate::log::debug;
+use crate::verify::{self, ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use crate::x509;
+use ring::digest;
+use std::convert::TryFrom;
+use std::time::SystemTime;
+
+/// How a TLSA record authenticates the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlsaUsage {
+    /// DANE-TA(2): the record matches a trust anchor for the server's
+    /// certificate.  The anchor must be one of the certificates sent
+    /// by the server.
+    DaneTa,
+    /// DANE-EE(3): the record matches the server's certificate itself.
+    DaneEe,
+}
+
+/// Which part of a certificate a TLSA record matches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlsaSelector {
+    /// Cert(0): the whole DER-encoded certificate.
+    FullCertificate,
+    /// SPKI(1): the certificate's DER-encoded `SubjectPublicKeyInfo`.
+    SubjectPublicKeyInfo,
+}
+
+/// How a TLSA record's data is compared with the selected part of a
+/// certificate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlsaMatchingType {
+    /// Full(0): the data is the selected content itself.
+    Exact,
+    /// SHA2-256(1): the data is the SHA-256 hash of the selected content.
+    Sha256,
+    /// SHA2-512(2): the data is the SHA-512 hash of the selected content.
+    Sha512,
+}
+
+/// A TLSA record, associating a certificate or public key with a
+/// TLS service.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsaRecord {
+    /// How the record authenticates the server.
+    pub usage: TlsaUsage,
+    /// Which part of a certificate the record matches.
+    pub selector: TlsaSelector,
+    /// How `data` is compared with the selected part of a certificate.
+    pub matching_type: TlsaMatchingType,
+    /// The certificate association data.
+    pub data: Vec<u8>,
+}
+
+impl TlsaRecord {
+    /// Parse the RDATA of a TLSA record, as returned by a DNS lookup.
+    ///
+    /// Returns `None` for malformed records and for records with a
+    /// usage, selector or matching type which isn't supported: these
+    /// include the PKIX-TA(0) and PKIX-EE(1) usages.  RFC 7671 says
+    /// such records are to be ignored.
+    pub fn from_rdata(rdata: &[u8]) -> Option<Self> {
+        if rdata.len() < 3 {
+            return None;
+        }
+
+        let usage = match rdata[0] {
+            2 => TlsaUsage::DaneTa,
+            3 => TlsaUsage::DaneEe,
+            _ => return None,
+        };
+        let selector = match rdata[1] {
+            0 => TlsaSelector::FullCertificate,
+            1 => TlsaSelector::SubjectPublicKeyInfo,
+            _ => return None,
+        };
+        let matching_type = match rdata[2] {
+            0 => TlsaMatchingType::Exact,
+            1 => TlsaMatchingType::Sha256,
+            2 => TlsaMatchingType::Sha512,
+            _ => return None,
+        };
+
+        Some(Self {
+            usage,
+            selector,
+            matching_type,
+            data: rdata[3..].to_vec(),
+        })
+    }
+
+    fn matches(&self, cert: &Certificate) -> bool {
+        let selected = match self.selector {
+            TlsaSelector::FullCertificate => cert.0.clone(),
+            TlsaSelector::SubjectPublicKeyInfo => match x509::CertFields::parse(&cert.0) {
+                Some(fields) => {
+                    let mut spki = fields.spki.to_vec();
+                    x509::wrap_in_sequence(&mut spki);
+                    spki
+                }
+                None => return false,
+            },
+        };
+
+        match self.matching_type {
+            TlsaMatchingType::Exact => selected == self.data,
+            TlsaMatchingType::Sha256 => {
+                digest::digest(&digest::SHA256, &selected).as_ref() == &self.data[..]
+            }
+            TlsaMatchingType::Sha512 => {
+                digest::digest(&digest::SHA512, &selected).as_ref() == &self.data[..]
+            }
+        }
+    }
+}
+
+/// A `ServerCertVerifier` which authenticates servers using TLSA
+/// records, as SMTP (RFC 7672) and XMPP (RFC 7712) servers are.
+///
+/// The application looks up the TLSA records for the service, and must
+/// check they are DNSSEC-validated.  The server is accepted if any
+/// record matches:
+///
+/// - a DANE-EE record matches if it matches the end-entity certificate.
+///   As RFC 7671 requires, the certificate's names and validity period
+///   are not checked.
+/// - a DANE-TA record matches if it matches a certificate sent by the
+///   server which the end-entity certificate chains to.  The chain is
+///   validated as usual, including for the server's name, but with
+///   that certificate as the only trust anchor.
+///
+/// If there are no records, DANE doesn't apply, and only the
+/// `WebPkiVerifier` given to `require_webpki` -- if any -- is used.
+pub struct DaneVerifier {
+    records: Vec<TlsaRecord>,
+    webpki: Option<WebPkiVerifier>,
+}
+
+impl DaneVerifier {
+    /// Constructs a `DaneVerifier` which trusts servers matching
+    /// `records`.
+    pub fn new(records: Vec<TlsaRecord>) -> Self {
+        Self {
+            records,
+            webpki: None,
+        }
+    }
+
+    /// Also require the server's certificate to be validated by
+    /// `webpki`, as well as matching a TLSA record.
+    pub fn require_webpki(mut self, webpki: WebPkiVerifier) -> Self {
+        self.webpki = Some(webpki);
+        self
+    }
+}
+
+impl ServerCertVerifier for DaneVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        if let Some(webpki) = &self.webpki {
+            webpki.verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                scts,
+                ocsp_response,
+                now,
+            )?;
+
+            if self.records.is_empty() {
+                return Ok(ServerCertVerified::assertion());
+            }
+        }
+
+        // Reported if no record matches.  If a DANE-TA record matches a
+        // certificate, but the chain doesn't validate, that error is more
+        // useful.
+        let mut error = Error::NoMatchingTlsaRecord;
+
+        for record in &self.records {
+            match record.usage {
+                TlsaUsage::DaneEe => {
+                    if record.matches(end_entity) {
+                        return Ok(ServerCertVerified::assertion());
+                    }
+                }
+                TlsaUsage::DaneTa => {
+                    for anchor in intermediates
+                        .iter()
+                        .filter(|cert| record.matches(cert))
+                    {
+                        match verify_chain_to(anchor, end_entity, intermediates, server_name, now) {
+                            Ok(()) => return Ok(ServerCertVerified::assertion()),
+                            Err(err) => {
+                                debug!("DANE-TA record matched, but chain did not: {:?}", err);
+                                error = err;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        Err(error)
+    }
+
+    fn request_scts(&self) -> bool {
+        self.webpki.is_some()
+    }
+}
+
+/// Validate the chain from `end_entity` through `intermediates` to
+/// `anchor`, and that `end_entity` is valid for `server_name`.
+fn verify_chain_to(
+    anchor: &Certificate,
+    end_entity: &Certificate,
+    intermediates: &[Certificate],
+    server_name: &ServerName,
+    now: SystemTime,
+) -> Result<(), Error> {
+    let anchor = webpki::TrustAnchor::try_from_cert_der(&anchor.0)
+        .map_err(|e| Error::WebPkiError(e, WebPkiOp::ValidateServerCert))?;
+    let cert = webpki::EndEntityCert::try_from(end_entity.0.as_ref())
+        .map_err(|e| Error::WebPkiError(e, WebPkiOp::ParseEndEntity))?;
+    let chain: Vec<&[u8]> = intermediates
+        .iter()
+        .map(|cert| cert.0.as_ref())
+        .collect();
+    let now = webpki::Time::try_from(now).map_err(|_| Error::FailedToGetCurrentTime)?;
+
+    cert.verify_is_valid_tls_server_cert(
+        verify::SUPPORTED_SIG_ALGS,
+        &webpki::TlsServerTrustAnchors(&[anchor]),
+        &chain,
+        now,
+    )
+    .map_err(|e| Error::WebPkiError(e, WebPkiOp::ValidateServerCert))?;
+
+    verify::verify_server_name(&cert, end_entity, server_name)
+}
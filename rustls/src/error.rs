@@ -1,4 +1,4 @@
-use crate::msgs::enums::{AlertDescription, ContentType, HandshakeType};
+use crate::msgs::enums::{AlertDescription, ContentType, HandshakeType, SignatureScheme};
 use crate::rand;
 
 use std::error::Error as StdError;
@@ -15,6 +15,12 @@ pub enum WebPkiOp {
     ValidateClientCert,
     /// Validate certificate for DNS name
     ValidateForDnsName,
+    /// Validate certificate for IP address.
+    ValidateForIpAddress,
+    /// Validate certificate for email address.
+    ValidateForEmail,
+    /// Validate certificate for URI.
+    ValidateForUri,
     /// Parse end entity certificate.
     ParseEndEntity,
     /// Verify message signature using the certificate.
@@ -27,6 +33,9 @@ impl fmt::Display for WebPkiOp {
             WebPkiOp::ValidateServerCert => write!(f, "validate server certificate"),
             WebPkiOp::ValidateClientCert => write!(f, "validate client certificate"),
             WebPkiOp::ValidateForDnsName => write!(f, "validate certificate for DNS name"),
+            WebPkiOp::ValidateForIpAddress => write!(f, "validate certificate for IP address"),
+            WebPkiOp::ValidateForEmail => write!(f, "validate certificate for email address"),
+            WebPkiOp::ValidateForUri => write!(f, "validate certificate for URI"),
             WebPkiOp::ParseEndEntity => write!(f, "parse end entity certificate"),
             WebPkiOp::VerifySignature => write!(f, "verify signature"),
         }
@@ -87,6 +96,115 @@ pub enum Error {
     /// The presented SCT(s) were invalid.
     InvalidSct(sct::Error),
 
+    /// The stapled OCSP response was invalid.  The parameter says why.
+    InvalidOcspResponse(String),
+
+    /// The stapled OCSP response was signed using a signature scheme
+    /// not allowed by the verifier's policy.
+    OcspSignatureAlgorithmNotAllowed(SignatureScheme),
+
+    /// The stapled OCSP response says the certificate is revoked.
+    CertificateRevoked,
+
+    /// The stapled OCSP response says the certificate's status is
+    /// unknown, and the verifier's policy rejects that.
+    OcspStatusUnknown,
+
+    /// A certificate's public key is not of a family (or curve)
+    /// allowed by the verifier's policy.
+    DisallowedKeyAlgorithm,
+
+    /// A certificate's public key is smaller than the verifier's
+    /// policy allows.
+    KeyTooSmall,
+
+    /// A certificate in the chain is signed using an algorithm not
+    /// allowed by the verifier's policy.
+    ChainSignatureAlgorithmNotAllowed,
+
+    /// A certificate's authority key identifier doesn't match the
+    /// subject key identifier of the certificate which issued it.
+    AkiSkiMismatch,
+
+    /// The client certificate has no email address subject alternative
+    /// name matching the verifier's requirement.
+    EmailIdentityMismatch,
+
+    /// A PEM file could not be read.  The parameter says why.
+    InvalidPemFile(String),
+
+    /// Certificate verification took longer than its budget.
+    VerificationTimedOut,
+
+    /// The peer's certificate chain had more intermediates than the
+    /// verifier allows, so it was rejected without being verified.
+    TooManyIntermediates,
+
+    /// The server sent more SCTs than the verifier's `CtPolicy` allows
+    /// processing, and none of those processed were valid.
+    TooManyScts,
+
+    /// The certificate's validity period is longer than the verifier's
+    /// `ValidityPolicy` allows for certificates issued when it was.
+    CertificateValidityTooLong,
+
+    /// An intermediate certificate's extended key usage doesn't include
+    /// the usage required of the end-entity certificate.
+    EkuNotChained,
+
+    /// The certificate has a wildcard name covering every name under a
+    /// public suffix, such as `*.co.uk`.
+    WildcardSpansPublicSuffix,
+
+    /// The certificate's serial number is not positive, is longer than
+    /// 20 octets, or is shorter than the verifier requires.
+    InvalidSerialNumber,
+
+    /// The certificate's `notBefore` is earlier than the verifier allows.
+    CertificateIssuedTooEarly,
+
+    /// The certificate requires a stapled OCSP response (it is
+    /// "must-staple"), and the server didn't provide one.
+    OcspStapleRequired,
+
+    /// A Certificate Transparency log list could not be parsed.
+    InvalidCtLogList(String),
+
+    /// The certificate's valid SCTs don't satisfy the verifier's
+    /// `CtPolicy`: they come from too few log operators, or none was
+    /// embedded in the certificate when that is required.
+    CtPolicyNotSatisfied,
+
+    /// None of the TLSA records given to a `DaneVerifier` matched the
+    /// server's certificate chain.
+    NoMatchingTlsaRecord,
+
+    /// The client certificate's extended key usage doesn't include one
+    /// the verifier requires.
+    RequiredEkuMissing,
+
+    /// The client certificate asserts none of the certificate policies
+    /// the verifier accepts.
+    RequiredPolicyMissing,
+
+    /// The server presented a different key from the one a
+    /// `TofuVerifier` recorded for it on first use.
+    ServerKeyChanged,
+
+    /// The peer's certificate doesn't have exactly one URI subject
+    /// alternative name, or it isn't a valid SPIFFE ID.
+    InvalidSpiffeId,
+
+    /// A `SpiffeVerifier`'s authorizer rejected the peer's SPIFFE ID.
+    SpiffeIdNotAuthorized,
+
+    /// The peer's certificate was rejected for the reason given by the
+    /// inner error, and the peer should be sent the given alert.
+    ///
+    /// Verifiers can return this to choose the alert themselves: other
+    /// errors are mapped to an alert by the handshake.
+    InvalidCertificate(AlertDescription, Box<Error>),
+
     /// A catch-all error for unlikely errors.
     General(String),
 
@@ -109,6 +227,29 @@ pub enum Error {
     /// The `max_fragment_size` value supplied in configuration was too small,
     /// or too large.
     BadMaxFragmentSize,
+
+    /// The `record_size_limit` value supplied in configuration was too small,
+    /// or too large.
+    BadRecordSizeLimit,
+}
+
+impl Error {
+    /// The alert to send a peer whose certificate was rejected with
+    /// this error.
+    pub(crate) fn certificate_alert(&self) -> AlertDescription {
+        match *self {
+            Error::InvalidCertificate(alert, _) => alert,
+            Error::WebPkiError(webpki::Error::BadDer, _) => AlertDescription::DecodeError,
+            Error::WebPkiError(webpki::Error::CertExpired, _)
+            | Error::WebPkiError(webpki::Error::CertNotValidYet, _) => {
+                AlertDescription::CertificateExpired
+            }
+            Error::WebPkiError(webpki::Error::UnknownIssuer, _) => AlertDescription::UnknownCA,
+            Error::CertificateRevoked => AlertDescription::CertificateRevoked,
+            Error::PeerMisbehavedError(_) => AlertDescription::IllegalParameter,
+            _ => AlertDescription::BadCertificate,
+        }
+    }
 }
 
 fn join<T: fmt::Debug>(items: &[T]) -> String {
@@ -156,11 +297,60 @@ impl fmt::Display for Error {
             Error::HandshakeNotComplete => write!(f, "handshake not complete"),
             Error::NoApplicationProtocol => write!(f, "peer doesn't support any known protocol"),
             Error::InvalidSct(ref err) => write!(f, "invalid certificate timestamp: {:?}", err),
+            Error::InvalidOcspResponse(ref why) => write!(f, "invalid OCSP response: {}", why),
+            Error::OcspSignatureAlgorithmNotAllowed(ref scheme) => {
+                write!(f, "OCSP response signed with disallowed {:?}", scheme)
+            }
+            Error::CertificateRevoked => write!(f, "certificate is revoked"),
+            Error::OcspStatusUnknown => write!(f, "OCSP responder does not know certificate"),
+            Error::DisallowedKeyAlgorithm => write!(f, "certificate key algorithm is not allowed"),
+            Error::KeyTooSmall => write!(f, "certificate key is too small"),
+            Error::ChainSignatureAlgorithmNotAllowed => {
+                write!(f, "certificate signature algorithm is not allowed")
+            }
+            Error::AkiSkiMismatch => write!(f, "authority key identifier does not match issuer"),
+            Error::EmailIdentityMismatch => {
+                write!(f, "certificate has no acceptable email identity")
+            }
+            Error::InvalidPemFile(ref why) => write!(f, "cannot read PEM file: {}", why),
+            Error::VerificationTimedOut => write!(f, "certificate verification timed out"),
+            Error::TooManyIntermediates => write!(f, "too many intermediate certificates"),
+            Error::TooManyScts => write!(f, "too many SCTs"),
+            Error::CertificateValidityTooLong => write!(f, "certificate validity period too long"),
+            Error::EkuNotChained => write!(f, "intermediate certificate does not allow key usage"),
+            Error::WildcardSpansPublicSuffix => {
+                write!(f, "certificate has a wildcard name for a public suffix")
+            }
+            Error::InvalidSerialNumber => write!(f, "invalid certificate serial number"),
+            Error::CertificateIssuedTooEarly => write!(f, "certificate issued too early"),
+            Error::OcspStapleRequired => {
+                write!(f, "certificate requires a stapled OCSP response")
+            }
+            Error::InvalidCtLogList(ref why) => write!(f, "invalid CT log list: {}", why),
+            Error::CtPolicyNotSatisfied => {
+                write!(f, "certificate transparency policy not satisfied")
+            }
+            Error::NoMatchingTlsaRecord => write!(f, "no TLSA record matched the certificate"),
+            Error::RequiredEkuMissing => {
+                write!(f, "certificate lacks a required extended key usage")
+            }
+            Error::RequiredPolicyMissing => {
+                write!(f, "certificate has no acceptable certificate policy")
+            }
+            Error::ServerKeyChanged => {
+                write!(f, "server key differs from the one first seen")
+            }
+            Error::InvalidSpiffeId => write!(f, "certificate has no valid SPIFFE ID"),
+            Error::SpiffeIdNotAuthorized => write!(f, "SPIFFE ID not authorized"),
+            Error::InvalidCertificate(_, ref err) => write!(f, "invalid certificate: {}", err),
             Error::FailedToGetCurrentTime => write!(f, "failed to get current time"),
             Error::FailedToGetRandomBytes => write!(f, "failed to get random bytes"),
             Error::BadMaxFragmentSize => {
                 write!(f, "the supplied max_fragment_size was too small or large")
             }
+            Error::BadRecordSizeLimit => {
+                write!(f, "the supplied record_size_limit was too small or large")
+            }
             Error::General(ref err) => write!(f, "unexpected error: {}", err), // (please file a bug)
         }
     }
@@ -187,7 +377,7 @@ mod tests {
     fn smoke() {
         use super::Error;
         use super::WebPkiOp;
-        use crate::msgs::enums::{AlertDescription, ContentType, HandshakeType};
+        use crate::msgs::enums::{AlertDescription, ContentType, HandshakeType, SignatureScheme};
         use sct;
         use webpki;
 
@@ -212,6 +402,37 @@ mod tests {
                 WebPkiOp::ParseEndEntity,
             ),
             Error::InvalidSct(sct::Error::MalformedSct),
+            Error::InvalidOcspResponse("no response for certificate".to_string()),
+            Error::OcspSignatureAlgorithmNotAllowed(SignatureScheme::RSA_PKCS1_SHA1),
+            Error::CertificateRevoked,
+            Error::OcspStatusUnknown,
+            Error::DisallowedKeyAlgorithm,
+            Error::KeyTooSmall,
+            Error::ChainSignatureAlgorithmNotAllowed,
+            Error::AkiSkiMismatch,
+            Error::EmailIdentityMismatch,
+            Error::InvalidPemFile("section end missing".to_string()),
+            Error::VerificationTimedOut,
+            Error::TooManyIntermediates,
+            Error::TooManyScts,
+            Error::CertificateValidityTooLong,
+            Error::EkuNotChained,
+            Error::WildcardSpansPublicSuffix,
+            Error::InvalidSerialNumber,
+            Error::CertificateIssuedTooEarly,
+            Error::OcspStapleRequired,
+            Error::InvalidCtLogList("malformed JSON".to_string()),
+            Error::CtPolicyNotSatisfied,
+            Error::NoMatchingTlsaRecord,
+            Error::RequiredEkuMissing,
+            Error::RequiredPolicyMissing,
+            Error::ServerKeyChanged,
+            Error::InvalidSpiffeId,
+            Error::SpiffeIdNotAuthorized,
+            Error::InvalidCertificate(
+                AlertDescription::AccessDenied,
+                Box::new(Error::SpiffeIdNotAuthorized),
+            ),
             Error::General("undocumented error".to_string()),
             Error::FailedToGetCurrentTime,
             Error::FailedToGetRandomBytes,
@@ -219,6 +440,7 @@ mod tests {
             Error::PeerSentOversizedRecord,
             Error::NoApplicationProtocol,
             Error::BadMaxFragmentSize,
+            Error::BadRecordSizeLimit,
         ];
 
         for err in all {
@@ -226,4 +448,49 @@ mod tests {
             println!("  fmt '{}'", err);
         }
     }
+
+    #[test]
+    fn certificate_alerts() {
+        use super::{Error, WebPkiOp};
+        use crate::msgs::enums::AlertDescription;
+
+        let op = || WebPkiOp::ValidateServerCert;
+        for (err, alert) in &[
+            (
+                Error::WebPkiError(webpki::Error::CertExpired, op()),
+                AlertDescription::CertificateExpired,
+            ),
+            (
+                Error::WebPkiError(webpki::Error::CertNotValidYet, op()),
+                AlertDescription::CertificateExpired,
+            ),
+            (
+                Error::WebPkiError(webpki::Error::UnknownIssuer, op()),
+                AlertDescription::UnknownCA,
+            ),
+            (
+                Error::WebPkiError(webpki::Error::BadDer, op()),
+                AlertDescription::DecodeError,
+            ),
+            (
+                Error::CertificateRevoked,
+                AlertDescription::CertificateRevoked,
+            ),
+            (
+                Error::WebPkiError(webpki::Error::CertNotValidForName, op()),
+                AlertDescription::BadCertificate,
+            ),
+            (Error::KeyTooSmall, AlertDescription::BadCertificate),
+            (Error::TooManyIntermediates, AlertDescription::BadCertificate),
+            (
+                Error::InvalidCertificate(
+                    AlertDescription::AccessDenied,
+                    Box::new(Error::CertificateRevoked),
+                ),
+                AlertDescription::AccessDenied,
+            ),
+        ] {
+            assert_eq!(err.certificate_alert(), *alert, "{:?}", err);
+        }
+    }
 }
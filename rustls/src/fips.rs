@@ -0,0 +1,199 @@
+// A server certificate verifier for FIPS deployments, accepting only
+// FIPS-approved algorithms and key sizes.
+
+use crate::anchors::RootCertStore;
+use crate::client::ServerName;
+use crate::error::{Error, WebPkiOp};
+use crate::key::Certificate;
+#[cfg(feature = "logging")]
+use crate::log::debug;
+use crate::msgs::enums::{SignatureAlgorithm, SignatureScheme};
+use crate::msgs::handshake::DigitallySignedStruct;
+use crate::verify::{
+    self, HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier, WebPkiVerifier,
+};
+use crate::x509;
+use std::time::SystemTime;
+
+/// The signature schemes allowed for handshake signatures by a
+/// `FipsModeVerifier`, in order of preference.
+static FIPS_HANDSHAKE_SCHEMES: &[SignatureScheme] = &[
+    SignatureScheme::ECDSA_NISTP384_SHA384,
+    SignatureScheme::ECDSA_NISTP256_SHA256,
+    SignatureScheme::RSA_PSS_SHA512,
+    SignatureScheme::RSA_PSS_SHA384,
+    SignatureScheme::RSA_PSS_SHA256,
+    SignatureScheme::RSA_PKCS1_SHA512,
+    SignatureScheme::RSA_PKCS1_SHA384,
+    SignatureScheme::RSA_PKCS1_SHA256,
+];
+
+/// The signature schemes allowed for certificate signatures by a
+/// `FipsModeVerifier`.
+static FIPS_CHAIN_SCHEMES: &[SignatureScheme] = &[
+    SignatureScheme::ECDSA_NISTP384_SHA384,
+    SignatureScheme::ECDSA_NISTP256_SHA256,
+    SignatureScheme::RSA_PSS_SHA512,
+    SignatureScheme::RSA_PSS_SHA384,
+    SignatureScheme::RSA_PSS_SHA256,
+    SignatureScheme::RSA_PKCS1_SHA512,
+    SignatureScheme::RSA_PKCS1_SHA384,
+    SignatureScheme::RSA_PKCS1_SHA256,
+];
+
+const FIPS_MIN_RSA_BITS: usize = 2048;
+
+/// A `ServerCertVerifier` for FIPS deployments, which only accepts
+/// certificates and handshake signatures using FIPS-approved algorithms.
+///
+/// Certificates are first verified exactly as by a [`WebPkiVerifier`].
+/// Then the end-entity certificate and each intermediate sent by the
+/// server must conform to these rules:
+///
+/// - The certificate is signed using RSA PKCS#1 v1.5 or RSA-PSS with
+///   SHA-256, SHA-384 or SHA-512, or ECDSA with SHA-256 or SHA-384.
+///   Anything else, including SHA-1 and Ed25519, is rejected with
+///   `Error::ChainSignatureAlgorithmNotAllowed`.
+/// - The public key is RSA, or ECDSA on the P-256 or P-384 curves.
+///   Anything else, including Ed25519, is rejected with
+///   `Error::DisallowedKeyAlgorithm`.
+/// - RSA keys have a modulus of at least 2048 bits, else they are
+///   rejected with `Error::KeyTooSmall`.
+///
+/// The public keys of the trust anchors must follow the same rules;
+/// this is checked once, when the verifier is made.
+///
+/// Handshake signatures must use ECDSA P-256 with SHA-256, ECDSA P-384
+/// with SHA-384, or RSA PKCS#1 v1.5 or RSA-PSS with SHA-256, SHA-384 or
+/// SHA-512.
+pub struct FipsModeVerifier {
+    inner: WebPkiVerifier,
+}
+
+impl FipsModeVerifier {
+    /// Constructs a new `FipsModeVerifier`.  `roots` and `ct_logs`
+    /// are as for [`WebPkiVerifier::new`].
+    ///
+    /// This fails if any trust anchor in `roots` has a public key
+    /// which FIPS mode doesn't allow.
+    pub fn new(
+        roots: RootCertStore,
+        ct_logs: &'static [&'static sct::Log<'static>],
+    ) -> Result<Self, Error> {
+        for anchor in &roots.roots {
+            check_fips_key(anchor.spki())?;
+        }
+
+        Ok(Self {
+            inner: WebPkiVerifier::new(roots, ct_logs),
+        })
+    }
+}
+
+impl ServerCertVerifier for FipsModeVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        )?;
+
+        for cert in std::iter::once(end_entity).chain(intermediates) {
+            check_fips_certificate(cert)?;
+        }
+
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        check_fips_handshake_scheme(dss.scheme)?;
+        verify::verify_signed_struct(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        check_fips_handshake_scheme(dss.scheme)?;
+        verify::verify_tls13(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        FIPS_HANDSHAKE_SCHEMES.to_vec()
+    }
+}
+
+fn check_fips_handshake_scheme(scheme: SignatureScheme) -> Result<(), Error> {
+    if FIPS_HANDSHAKE_SCHEMES.contains(&scheme) {
+        Ok(())
+    } else {
+        Err(Error::PeerMisbehavedError(format!(
+            "signature scheme {:?} not allowed in FIPS mode",
+            scheme
+        )))
+    }
+}
+
+fn check_fips_certificate(cert: &Certificate) -> Result<(), Error> {
+    let fields = x509::CertFields::parse(&cert.0).ok_or(Error::WebPkiError(
+        webpki::Error::BadDer,
+        WebPkiOp::ValidateServerCert,
+    ))?;
+
+    #[cfg_attr(not(feature = "logging"), allow(unused_variables))]
+    match x509::signature_scheme_for_algorithm(fields.signature_alg) {
+        Some(scheme) if FIPS_CHAIN_SCHEMES.contains(&scheme) => {}
+        scheme => {
+            debug!(
+                "Certificate signature {:?} not allowed in FIPS mode",
+                scheme
+            );
+            return Err(Error::ChainSignatureAlgorithmNotAllowed);
+        }
+    }
+
+    check_fips_key(fields.spki)
+}
+
+/// Check the public key in `spki` (the contents of a
+/// `SubjectPublicKeyInfo`) is allowed in FIPS mode.
+fn check_fips_key(spki: &[u8]) -> Result<(), Error> {
+    #[cfg_attr(not(feature = "logging"), allow(unused_variables))]
+    match x509::key_algorithm(spki) {
+        Some(SignatureAlgorithm::RSA) => {
+            let bits = x509::rsa_modulus_bits(spki).unwrap_or(0);
+            if bits < FIPS_MIN_RSA_BITS {
+                debug!("{}-bit RSA key not allowed in FIPS mode", bits);
+                return Err(Error::KeyTooSmall);
+            }
+        }
+        Some(SignatureAlgorithm::ECDSA) => match x509::ec_curve(spki) {
+            Some(x509::oid::SECP256R1) | Some(x509::oid::SECP384R1) => {}
+            _ => return Err(Error::DisallowedKeyAlgorithm),
+        },
+        key_alg => {
+            debug!("Key algorithm {:?} not allowed in FIPS mode", key_alg);
+            return Err(Error::DisallowedKeyAlgorithm);
+        }
+    }
+
+    Ok(())
+}
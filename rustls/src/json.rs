@@ -0,0 +1,334 @@
+// A minimal JSON (RFC8259) parser, for the few JSON documents rustls
+// reads, such as Certificate Transparency log lists.
+
+/// Documents nested more deeply than this are rejected, rather than
+/// risking exhausting the stack.
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// Parse `input`, which must contain exactly one JSON value.
+    pub(crate) fn parse(input: &str) -> Option<Self> {
+        let mut parser = Parser {
+            input: input.as_bytes(),
+            pos: 0,
+        };
+        let value = parser.value(0)?;
+        parser.skip_whitespace();
+        if parser.pos == parser.input.len() {
+            Some(value)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the member of an object named `key`.
+    pub(crate) fn get(&self, key: &str) -> Option<&Self> {
+        match self {
+            Self::Object(members) => members
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_array(&self) -> Option<&[Self]> {
+        match self {
+            Self::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    /// Returns a number which is a non-negative integer.
+    pub(crate) fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Self::Number(n) if n >= 0.0 && n.fract() == 0.0 && n <= u64::MAX as f64 => {
+                Some(n as u64)
+            }
+            _ => None,
+        }
+    }
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let byte = self.peek()?;
+        self.pos += 1;
+        Some(byte)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn literal(&mut self, literal: &[u8], value: Value) -> Option<Value> {
+        if self.input[self.pos..].starts_with(literal) {
+            self.pos += literal.len();
+            Some(value)
+        } else {
+            None
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Option<Value> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+
+        self.skip_whitespace();
+        match self.peek()? {
+            b'n' => self.literal(b"null", Value::Null),
+            b't' => self.literal(b"true", Value::Bool(true)),
+            b'f' => self.literal(b"false", Value::Bool(false)),
+            b'"' => self.string().map(Value::String),
+            b'[' => self.array(depth),
+            b'{' => self.object(depth),
+            _ => self.number(),
+        }
+    }
+
+    fn array(&mut self, depth: usize) -> Option<Value> {
+        self.pos += 1; // [
+        let mut items = Vec::new();
+
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Some(Value::Array(items));
+        }
+
+        loop {
+            items.push(self.value(depth + 1)?);
+            self.skip_whitespace();
+            match self.next()? {
+                b',' => continue,
+                b']' => return Some(Value::Array(items)),
+                _ => return None,
+            }
+        }
+    }
+
+    fn object(&mut self, depth: usize) -> Option<Value> {
+        self.pos += 1; // {
+        let mut members = Vec::new();
+
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Some(Value::Object(members));
+        }
+
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return None;
+            }
+            let name = self.string()?;
+
+            self.skip_whitespace();
+            if self.next()? != b':' {
+                return None;
+            }
+            members.push((name, self.value(depth + 1)?));
+
+            self.skip_whitespace();
+            match self.next()? {
+                b',' => continue,
+                b'}' => return Some(Value::Object(members)),
+                _ => return None,
+            }
+        }
+    }
+
+    fn number(&mut self) -> Option<Value> {
+        let start = self.pos;
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+
+        match self.next()? {
+            b'0' => {}
+            b'1'..=b'9' => self.digits(),
+            _ => return None,
+        }
+
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            self.require_digits()?;
+        }
+
+        if let Some(b'e') | Some(b'E') = self.peek() {
+            self.pos += 1;
+            if let Some(b'+') | Some(b'-') = self.peek() {
+                self.pos += 1;
+            }
+            self.require_digits()?;
+        }
+
+        std::str::from_utf8(&self.input[start..self.pos])
+            .ok()?
+            .parse()
+            .ok()
+            .map(Value::Number)
+    }
+
+    fn digits(&mut self) {
+        while let Some(b'0'..=b'9') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn require_digits(&mut self) -> Option<()> {
+        let start = self.pos;
+        self.digits();
+        if self.pos > start {
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    fn string(&mut self) -> Option<String> {
+        self.pos += 1; // "
+        let mut out = Vec::new();
+
+        loop {
+            match self.next()? {
+                b'"' => return String::from_utf8(out).ok(),
+                b'\\' => {
+                    let c = match self.next()? {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => self.escaped_char()?,
+                        _ => return None,
+                    };
+                    out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                0..=0x1f => return None,
+                byte => out.push(byte),
+            }
+        }
+    }
+
+    /// Decode the `XXXX` of a `\uXXXX` escape, and its partner if it is
+    /// the first of a surrogate pair.
+    fn escaped_char(&mut self) -> Option<char> {
+        let first = self.hex4()?;
+        if !(0xd800..0xdc00).contains(&first) {
+            return std::char::from_u32(first);
+        }
+
+        if self.next()? != b'\\' || self.next()? != b'u' {
+            return None;
+        }
+        let second = self.hex4()?;
+        if !(0xdc00..0xe000).contains(&second) {
+            return None;
+        }
+        std::char::from_u32(0x10000 + ((first - 0xd800) << 10) + (second - 0xdc00))
+    }
+
+    fn hex4(&mut self) -> Option<u32> {
+        let digits = self.input.get(self.pos..self.pos + 4)?;
+        if !digits.iter().all(u8::is_ascii_hexdigit) {
+            return None;
+        }
+        self.pos += 4;
+        u32::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Value;
+
+    #[test]
+    fn parses_values() {
+        assert_eq!(Value::parse(" null "), Some(Value::Null));
+        assert_eq!(Value::parse("true"), Some(Value::Bool(true)));
+        assert_eq!(Value::parse("-1.5e2"), Some(Value::Number(-150.0)));
+        assert_eq!(
+            Value::parse(r#""a\"\u00e9\ud83d\ude00""#),
+            Some(Value::String("a\"\u{e9}\u{1f600}".to_string()))
+        );
+        assert_eq!(
+            Value::parse(r#"{"a": [1, {}], "b": []}"#),
+            Some(Value::Object(vec![
+                (
+                    "a".to_string(),
+                    Value::Array(vec![Value::Number(1.0), Value::Object(vec![])])
+                ),
+                ("b".to_string(), Value::Array(vec![])),
+            ]))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_documents() {
+        for input in &[
+            "",
+            "nul",
+            "[1,]",
+            "{\"a\" 1}",
+            "{1: 2}",
+            "01",
+            "1.",
+            "\"\\x\"",
+            "\"\\ud800\"",
+            "\"\\u+123\"",
+            "\"a\nb\"",
+            "[] []",
+        ] {
+            assert_eq!(Value::parse(input), None, "{:?}", input);
+        }
+
+        assert_eq!(Value::parse(&"[".repeat(100)), None);
+    }
+
+    #[test]
+    fn accessors() {
+        let value = Value::parse(r#"{"s": "x", "n": 3, "f": 0.5, "a": [null]}"#).unwrap();
+        assert_eq!(value.get("s").and_then(Value::as_str), Some("x"));
+        assert_eq!(value.get("n").and_then(Value::as_u64), Some(3));
+        assert_eq!(value.get("f").and_then(Value::as_u64), None);
+        assert_eq!(
+            value.get("a").and_then(Value::as_array),
+            Some(&[Value::Null][..])
+        );
+        assert_eq!(value.get("missing"), None);
+    }
+}
@@ -1,3 +1,7 @@
+use crate::error::Error;
+use crate::msgs::codec::{Codec, Reader};
+use crate::msgs::enums::ContentType;
+use crate::msgs::handshake::CertificatePayloadTLS13;
 use std::fmt;
 
 /// This type contains a private key by value.
@@ -18,6 +22,33 @@ pub struct PrivateKey(pub Vec<u8>);
 #[derive(Clone, Eq, PartialEq)]
 pub struct Certificate(pub Vec<u8>);
 
+impl Certificate {
+    /// Parse a certificate chain from a decompressed TLS1.3 `Certificate`
+    /// message, as carried compressed in RFC8879's `CompressedCertificate`.
+    ///
+    /// Decompression is not done by rustls: `bytes` must already be the
+    /// output of the negotiated algorithm (zlib, brotli or zstd).  The
+    /// chain is returned end-entity first, so it can be verified by
+    /// passing `chain[0]` and `&chain[1..]` to `verify_server_cert`,
+    /// exactly like an uncompressed chain.
+    ///
+    /// Fails with `Error::NoCertificatesPresented` if the message has no
+    /// certificates, or `Error::CorruptMessagePayload` if it is malformed.
+    pub fn from_decompressed(bytes: &[u8]) -> Result<Vec<Certificate>, Error> {
+        let mut rd = Reader::init(bytes);
+        let payload = match CertificatePayloadTLS13::read(&mut rd) {
+            Some(payload) if !rd.any_left() => payload,
+            _ => return Err(Error::CorruptMessagePayload(ContentType::Handshake)),
+        };
+
+        if payload.entries.is_empty() {
+            return Err(Error::NoCertificatesPresented);
+        }
+
+        Ok(payload.convert())
+    }
+}
+
 impl AsRef<[u8]> for Certificate {
     fn as_ref(&self) -> &[u8] {
         &self.0
@@ -14,6 +14,7 @@ use ring::{
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum SecretKind {
     ResumptionPskBinderKey,
+    ExternalPskBinderKey,
     ClientEarlyTrafficSecret,
     ClientHandshakeTrafficSecret,
     ServerHandshakeTrafficSecret,
@@ -28,6 +29,7 @@ impl SecretKind {
     fn to_bytes(self) -> &'static [u8] {
         match self {
             SecretKind::ResumptionPskBinderKey => b"res binder",
+            SecretKind::ExternalPskBinderKey => b"ext binder",
             SecretKind::ClientEarlyTrafficSecret => b"c e traffic",
             SecretKind::ClientHandshakeTrafficSecret => b"c hs traffic",
             SecretKind::ServerHandshakeTrafficSecret => b"s hs traffic",
@@ -103,6 +105,14 @@ impl KeyScheduleEarly {
             .sign_verify_data(&resumption_psk_binder_key, hs_hash)
     }
 
+    pub fn external_psk_binder_key_and_sign_verify_data(&self, hs_hash: &Digest) -> hmac::Tag {
+        let external_psk_binder_key = self
+            .ks
+            .derive_for_empty_hash(SecretKind::ExternalPskBinderKey);
+        self.ks
+            .sign_verify_data(&external_psk_binder_key, hs_hash)
+    }
+
     pub fn into_handshake(mut self, secret: &[u8]) -> KeyScheduleHandshake {
         self.ks.input_secret(secret);
         KeyScheduleHandshake {
@@ -393,8 +403,8 @@ impl KeySchedule {
 
     /// Derive a secret of given `kind` using the hash of the empty string
     /// for the handshake hash.  Useful only for
-    /// `SecretKind::ResumptionPSKBinderKey` and
-    /// `SecretKind::DerivedSecret`.
+    /// `SecretKind::ResumptionPSKBinderKey`,
+    /// `SecretKind::ExternalPskBinderKey` and `SecretKind::DerivedSecret`.
     fn derive_for_empty_hash<T>(&self, kind: SecretKind) -> T
     where
         T: for<'a> From<hkdf::Okm<'a, hkdf::Algorithm>>,
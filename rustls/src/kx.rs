@@ -32,9 +32,37 @@ impl KeyExchange {
     ///
     /// This generates an ephemeral key pair and stores it in the returned KeyExchange object.
     pub fn start(skxg: &'static SupportedKxGroup) -> Option<KeyExchange> {
-        let rng = ring::rand::SystemRandom::new();
+        Self::start_with_rng(skxg, &ring::rand::SystemRandom::new())
+    }
+
+    /// Start a key exchange with a private key derived from `seed`,
+    /// so the same seed always gives the same key pair.
+    #[cfg(feature = "deterministic_testing")]
+    pub fn start_with_seed(
+        skxg: &'static SupportedKxGroup,
+        seed: &[u8; 32],
+    ) -> Option<KeyExchange> {
+        let mut ctx = ring::digest::Context::new(&ring::digest::SHA512);
+        ctx.update(seed);
+        ctx.update(&skxg.name.get_u16().to_be_bytes());
+        let key = ctx.finish();
+        let len = match skxg.name {
+            NamedGroup::secp384r1 => 48,
+            _ => 32,
+        };
+
+        let rng = ring::test::rand::FixedSliceRandom {
+            bytes: &key.as_ref()[..len],
+        };
+        Self::start_with_rng(skxg, &rng)
+    }
+
+    fn start_with_rng(
+        skxg: &'static SupportedKxGroup,
+        rng: &dyn ring::rand::SecureRandom,
+    ) -> Option<KeyExchange> {
         let ours =
-            ring::agreement::EphemeralPrivateKey::generate(skxg.agreement_algorithm, &rng).ok()?;
+            ring::agreement::EphemeralPrivateKey::generate(skxg.agreement_algorithm, rng).ok()?;
 
         let pubkey = ours.compute_public_key().ok()?;
 
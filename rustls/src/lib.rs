@@ -19,6 +19,8 @@
 //! * TLS1.2 resumption via tickets ([RFC5077](https://tools.ietf.org/html/rfc5077)).
 //! * TLS1.3 resumption via tickets or session storage.
 //! * TLS1.3 0-RTT data for clients.
+//! * TLS1.3 authentication with external pre-shared keys.
+//! * TLS1.2 ECDHE_PSK ciphersuites ([RFC5489](https://tools.ietf.org/html/rfc5489)), enabled explicitly.
 //! * Client authentication by clients.
 //! * Client authentication by servers.
 //! * Extended master secret support ([RFC7627](https://tools.ietf.org/html/rfc7627)).
@@ -29,7 +31,6 @@
 //!
 //! ## Possible future features
 //!
-//! * PSK support.
 //! * OCSP verification by clients.
 //! * Certificate pinning.
 //!
@@ -119,6 +120,7 @@
 //! ```
 //! # use rustls;
 //! # use webpki;
+//! # use std::convert::TryInto;
 //! # use std::sync::Arc;
 //! # let mut root_store = rustls::RootCertStore::empty();
 //! # root_store.add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
@@ -129,7 +131,7 @@
 //! #     .with_root_certificates(root_store, trusted_ct_logs)
 //! #     .with_no_client_auth();
 //! let rc_config = Arc::new(config);
-//! let example_com = webpki::DnsNameRef::try_from_ascii_str("example.com").unwrap();
+//! let example_com = "example.com".try_into().unwrap();
 //! let mut client = rustls::ClientConnection::new(rc_config, example_com);
 //! ```
 //!
@@ -202,6 +204,24 @@
 //!   details of these.  You will only need this if you're writing a QUIC
 //!   implementation.
 //!
+//! - `pem_files`: this feature adds `RootCertStore::add_pem_file` and
+//!   `RootCertStore::add_from_dir`, for loading trust anchors from PEM
+//!   bundles and certificate directories.  It makes the rustls crate
+//!   depend on the `rustls-pemfile` crate.
+//!
+//! - `platform_roots`: this feature adds `RootCertStore::from_platform`,
+//!   which loads the operating system's trust anchors.  It implies
+//!   `pem_files`.
+//!
+//! - `ct_log_lists`: this feature adds `OwnedCtLog::from_log_list_json`,
+//!   for loading Certificate Transparency logs from the list Google
+//!   publishes.  It makes the rustls crate depend on the `base64` crate.
+//!
+//! - `deterministic_testing`: this feature lets tests fix the values
+//!   a client would otherwise choose at random, using
+//!   `ConnectionOptions::deterministic_hello`, so its handshakes are
+//!   reproducible.  It must never be enabled outside tests.
+//!
 
 // Require docs for public APIs, deny unsafe code, etc.
 #![forbid(unsafe_code, unused_must_use, unstable_features)]
@@ -250,23 +270,49 @@ mod log {
 #[allow(missing_docs)]
 #[macro_use]
 mod msgs;
+mod aia;
 mod anchors;
+#[cfg(feature = "dangerous_configuration")]
+mod audit;
+#[cfg(feature = "dangerous_configuration")]
+mod budgeted;
 mod cipher;
+#[cfg(feature = "dangerous_configuration")]
+mod combinators;
 mod conn;
+mod ct;
+#[cfg(feature = "dangerous_configuration")]
+mod dane;
 mod error;
+mod fips;
 mod hash_hs;
+#[cfg(feature = "ct_log_lists")]
+mod json;
 mod key_schedule;
 mod limited_cache;
+mod ocsp;
+#[cfg(feature = "dangerous_configuration")]
+mod pinned;
+#[cfg(feature = "platform_roots")]
+mod platform_roots;
 mod prf;
+mod psk;
 mod rand;
+#[cfg(feature = "dangerous_configuration")]
+mod raw_public_key;
 mod record_layer;
+#[cfg(feature = "dangerous_configuration")]
+mod report_only;
+mod revocation;
+mod spiffe;
 mod stream;
 mod tls12;
+#[cfg(feature = "dangerous_configuration")]
+mod tofu;
 mod vecbuf;
 mod verify;
 #[cfg(test)]
 mod verifybench;
-mod x509;
 #[macro_use]
 mod check;
 mod bs_debug;
@@ -278,6 +324,7 @@ mod kx;
 mod server;
 mod suites;
 mod ticketer;
+mod time_provider;
 mod versions;
 
 /// Internal classes which may be useful outside the library.
@@ -290,40 +337,76 @@ pub mod internal {
 }
 
 // The public interface is:
-pub use crate::anchors::{DistinguishedNames, OwnedTrustAnchor, RootCertStore};
+pub use crate::aia::IntermediateFetcher;
+#[cfg(feature = "pem_files")]
+#[cfg_attr(docsrs, doc(cfg(feature = "pem_files")))]
+pub use crate::anchors::CertFileOutcome;
+pub use crate::anchors::{
+    AddCertificatesOutcome, DistinguishedNames, NameConstraints, OwnedTrustAnchor, RootCertStore,
+    TrustAnchorInfo,
+};
 pub use crate::builder::{
-    ConfigBuilder, ConfigBuilderWithKxGroups, ConfigBuilderWithSuites, ConfigBuilderWithVersions,
+    ConfigBuilder, ConfigBuilderFor, ConfigBuilderWithKxGroups, ConfigBuilderWithSuites,
+    ConfigBuilderWithVersions, ConfigProfile,
 };
 pub use crate::client::handy::{ClientSessionMemoryCache, NoClientSessionStorage};
-pub use crate::client::ResolvesClientCert;
+pub use crate::client::{ClientCertRequest, ResolvesClientCert};
 pub use crate::client::StoresClientSessions;
-pub use crate::client::{ClientConfig, ClientConnection, WriteEarlyData};
+pub use crate::client::{
+    ClientConfig, ClientConnection, ClientHelloProfile, ConnectionOptions, EarlyDataStatus,
+    RenegotiationPolicy, ServerName, WriteEarlyData,
+};
 pub use crate::conn::{Connection, Reader, Writer};
+pub use crate::ct::OwnedCtLog;
 pub use crate::error::Error;
 pub use crate::error::WebPkiOp;
+pub use crate::fips::FipsModeVerifier;
 pub use crate::key::{Certificate, PrivateKey};
 pub use crate::keylog::{KeyLog, KeyLogFile, NoKeyLog};
 pub use crate::kx::{SupportedKxGroup, ALL_KX_GROUPS};
 pub use crate::msgs::enums::CipherSuite;
+pub use crate::msgs::enums::ExtensionType;
+pub use crate::msgs::enums::HashAlgorithm;
+pub use crate::msgs::enums::MaxFragmentLength;
+pub use crate::msgs::enums::NamedGroup;
+pub use crate::msgs::enums::PSKKeyExchangeMode;
 pub use crate::msgs::enums::ProtocolVersion;
+pub use crate::msgs::enums::SignatureAlgorithm;
 pub use crate::msgs::enums::SignatureScheme;
+pub use crate::ocsp::{CertStatus, OcspFreshness, OcspPolicy, OcspUnknownStatus};
 pub use crate::server::builder::{ServerConfigBuilder, ServerConfigBuilderWithClientAuth};
 pub use crate::server::handy::{NoServerSessionStorage, ServerSessionMemoryCache};
-pub use crate::server::handy::{ResolvesServerCertUsingSni, SharedSniResolver};
+pub use crate::server::handy::{
+    ResolvesServerCertUsingAuthorities, ResolvesServerCertUsingSni, SharedSniResolver,
+};
 pub use crate::server::StoresServerSessions;
-pub use crate::server::{ClientHello, ProducesTickets, ResolvesServerCert};
+pub use crate::server::{ClientHello, ProducesTickets, ResolvesServerCert, ResolvesServerPsk};
 pub use crate::server::{ServerConfig, ServerConnection};
+pub use crate::psk::ExternalPsk;
+pub use crate::revocation::{RevocationFilter, RevocationFilterCascade};
+pub use crate::spiffe::{SpiffeId, SpiffeVerifier};
 pub use crate::stream::{Stream, StreamOwned};
 pub use crate::suites::{
     BulkAlgorithm, SupportedCipherSuite, ALL_CIPHERSUITES, DEFAULT_CIPHERSUITES,
 };
 pub use crate::ticketer::Ticketer;
-pub use crate::verify::{AllowAnyAuthenticatedClient, NoClientAuth, SafeDefaultClientVerifier};
+pub use crate::time_provider::{DefaultTimeProvider, TimeProvider};
+pub use crate::verify::{
+    construct_tls13_client_verify_message_checked, construct_tls13_server_verify_message_checked,
+};
+pub use crate::verify::{verify_possession, verify_tls12_signature_detached};
+pub use crate::verify::{
+    AllowAnyAuthenticatedClient, AnchorUsage, ChainExpiry, ChainPosition, ClientRootResolver,
+    CtPolicy, DynamicRootClientVerifier, NoClientAuth, PathCertificate,
+    RevocationStatus, SafeDefaultClientVerifier, SniClientCertVerifier, ValidityPolicy,
+    VerifiedServerCert, VerifierPolicy, WebPkiVerifier, WebPkiVerifierBuilder,
+};
 pub use crate::versions::{SupportedProtocolVersion, ALL_VERSIONS, DEFAULT_VERSIONS};
 
 /// All defined ciphersuites appear in this module.
 ///
-/// ALL_CIPHERSUITES is provided as an array of all of these values.
+/// ALL_CIPHERSUITES is provided as an array of all of these values,
+/// except the ECDHE_PSK suites.
 pub mod cipher_suite {
     pub use crate::suites::TLS13_AES_128_GCM_SHA256;
     pub use crate::suites::TLS13_AES_256_GCM_SHA384;
@@ -331,6 +414,9 @@ pub mod cipher_suite {
     pub use crate::suites::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256;
     pub use crate::suites::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384;
     pub use crate::suites::TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256;
+    pub use crate::suites::TLS_ECDHE_PSK_WITH_AES_128_GCM_SHA256;
+    pub use crate::suites::TLS_ECDHE_PSK_WITH_AES_256_GCM_SHA384;
+    pub use crate::suites::TLS_ECDHE_PSK_WITH_CHACHA20_POLY1305_SHA256;
     pub use crate::suites::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256;
     pub use crate::suites::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384;
     pub use crate::suites::TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256;
@@ -356,6 +442,8 @@ pub mod kx_group {
 /// Message signing interfaces and implementations.
 pub mod sign;
 
+pub mod x509;
+
 #[cfg(feature = "quic")]
 #[cfg_attr(docsrs, doc(cfg(feature = "quic")))]
 /// APIs for implementing QUIC TLS
@@ -372,12 +460,39 @@ mod quic {
 
 #[cfg(feature = "dangerous_configuration")]
 #[cfg_attr(docsrs, doc(cfg(feature = "dangerous_configuration")))]
-pub use crate::client::danger::DangerousClientConfig;
+pub use crate::audit::{AuditingVerifier, VerificationAuditRecord};
+#[cfg(feature = "deterministic_testing")]
+#[cfg_attr(docsrs, doc(cfg(feature = "deterministic_testing")))]
+pub use crate::client::DeterministicHello;
+#[cfg(feature = "dangerous_configuration")]
+#[cfg_attr(docsrs, doc(cfg(feature = "dangerous_configuration")))]
+pub use crate::budgeted::BudgetedVerifier;
+#[cfg(feature = "dangerous_configuration")]
+#[cfg_attr(docsrs, doc(cfg(feature = "dangerous_configuration")))]
+pub use crate::client::danger::{DangerousClientConfig, NoCertificateVerification};
+#[cfg(feature = "dangerous_configuration")]
+#[cfg_attr(docsrs, doc(cfg(feature = "dangerous_configuration")))]
+pub use crate::combinators::{AndVerifier, OrVerifier, ShadowDivergence, ShadowVerifier};
+#[cfg(feature = "dangerous_configuration")]
+#[cfg_attr(docsrs, doc(cfg(feature = "dangerous_configuration")))]
+pub use crate::dane::{DaneVerifier, TlsaMatchingType, TlsaRecord, TlsaSelector, TlsaUsage};
+#[cfg(feature = "dangerous_configuration")]
+#[cfg_attr(docsrs, doc(cfg(feature = "dangerous_configuration")))]
+pub use crate::pinned::SelfSignedPinnedVerifier;
+#[cfg(feature = "dangerous_configuration")]
+#[cfg_attr(docsrs, doc(cfg(feature = "dangerous_configuration")))]
+pub use crate::raw_public_key::RawPublicKeyVerifier;
+#[cfg(feature = "dangerous_configuration")]
+#[cfg_attr(docsrs, doc(cfg(feature = "dangerous_configuration")))]
+pub use crate::report_only::ReportOnlyVerifier;
+#[cfg(feature = "dangerous_configuration")]
+#[cfg_attr(docsrs, doc(cfg(feature = "dangerous_configuration")))]
+pub use crate::tofu::{TofuMemoryStore, TofuStore, TofuVerifier};
 #[cfg(feature = "dangerous_configuration")]
 #[cfg_attr(docsrs, doc(cfg(feature = "dangerous_configuration")))]
 pub use crate::verify::{
-    ClientCertVerified, ClientCertVerifier, HandshakeSignatureValid, ServerCertVerified,
-    ServerCertVerifier, WebPkiVerifier,
+    ClientCertVerified, ClientCertVerifier, ClientCertVerifyContext, HandshakeSignatureValid,
+    PendingVerification, ServerCertVerification, ServerCertVerified, ServerCertVerifier,
 };
 
 /// This is the rustls manual.
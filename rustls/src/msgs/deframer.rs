@@ -16,9 +16,13 @@ pub struct MessageDeframer {
     /// the deframer cannot recover.
     pub desynced: bool,
 
-    /// A fixed-size buffer containing the currently-accumulating
-    /// TLS message.
-    buf: Box<[u8; OpaqueMessage::MAX_WIRE_SIZE]>,
+    /// A buffer containing the currently-accumulating TLS message.
+    /// It starts at the size we expect messages to be, and grows if
+    /// a larger one arrives.
+    buf: Vec<u8>,
+
+    /// The size of the largest message we accept, including its header.
+    max_wire_size: usize,
 
     /// What size prefix of `buf` is used.
     used: usize,
@@ -44,14 +48,34 @@ impl Default for MessageDeframer {
 
 impl MessageDeframer {
     pub fn new() -> MessageDeframer {
+        Self::with_expected_payload(OpaqueMessage::MAX_WIRE_SIZE)
+    }
+
+    /// Make a deframer whose buffer starts just large enough for messages
+    /// with a payload of `expected_payload` bytes.  It grows if larger
+    /// messages arrive.
+    pub fn with_expected_payload(expected_payload: usize) -> MessageDeframer {
+        let size = expected_payload + usize::from(OpaqueMessage::HEADER_SIZE);
         MessageDeframer {
             frames: VecDeque::new(),
             desynced: false,
-            buf: Box::new([0u8; OpaqueMessage::MAX_WIRE_SIZE]),
+            buf: vec![0u8; size.min(OpaqueMessage::MAX_WIRE_SIZE)],
+            max_wire_size: OpaqueMessage::MAX_WIRE_SIZE,
             used: 0,
         }
     }
 
+    /// Accept only messages with a payload of at most `max_payload`
+    /// bytes from now on, shrinking our buffer to match.
+    pub fn set_max_payload(&mut self, max_payload: usize) {
+        let size = max_payload + usize::from(OpaqueMessage::HEADER_SIZE);
+        self.max_wire_size = self.max_wire_size.min(size);
+        if size < self.buf.len() {
+            self.buf.truncate(size.max(self.used));
+            self.buf.shrink_to_fit();
+        }
+    }
+
     /// Read some bytes from `rd`, and add them to our internal
     /// buffer.  If this means our internal buffer contains
     /// full messages, decode them all.
@@ -60,7 +84,7 @@ impl MessageDeframer {
         // we get a message with a length field out of range here,
         // we do a zero length read.  That looks like an EOF to
         // the next layer up, which is fine.
-        debug_assert!(self.used <= OpaqueMessage::MAX_WIRE_SIZE);
+        debug_assert!(self.used <= self.buf.len());
         let new_bytes = rd.read(&mut self.buf[self.used..])?;
 
         self.used += new_bytes;
@@ -79,6 +103,13 @@ impl MessageDeframer {
         Ok(new_bytes)
     }
 
+    /// The size of our buffer: the largest message we can currently
+    /// hold without growing it.
+    #[cfg(test)]
+    pub fn buffer_len(&self) -> usize {
+        self.buf.len()
+    }
+
     /// Returns true if we have messages for the caller
     /// to process, either whole messages in our output
     /// queue or partial messages in our buffer.
@@ -100,9 +131,20 @@ impl MessageDeframer {
                 self.buf_consume(used);
                 BufferContents::Valid
             }
-            Err(MessageError::TooShortForHeader) | Err(MessageError::TooShortForLength) => {
+            // The header is complete, so says how large the message is.
+            // Make room for it, unless it's too big.
+            Err(MessageError::TooShortForLength) => {
+                let len = usize::from(u16::from_be_bytes([self.buf[3], self.buf[4]]));
+                let size = usize::from(OpaqueMessage::HEADER_SIZE) + len;
+                if size > self.max_wire_size {
+                    return BufferContents::Invalid;
+                }
+                if size > self.buf.len() {
+                    self.buf.resize(size, 0);
+                }
                 BufferContents::Partial
             }
+            Err(MessageError::TooShortForHeader) => BufferContents::Partial,
             Err(_) => BufferContents::Invalid,
         }
     }
@@ -342,4 +384,32 @@ mod tests {
         pop_first(&mut d);
         assert_eq!(d.has_pending(), false);
     }
+
+    #[test]
+    fn test_limited_payload() {
+        let mut d = MessageDeframer::new();
+        d.set_max_payload(FIRST_MESSAGE.len() - 5);
+        assert_len(FIRST_MESSAGE.len(), input_bytes(&mut d, FIRST_MESSAGE));
+        pop_first(&mut d);
+        assert_eq!(d.desynced, false);
+
+        d.set_max_payload(FIRST_MESSAGE.len() - 6);
+        input_bytes(&mut d, FIRST_MESSAGE).unwrap();
+        assert_eq!(d.desynced, true);
+    }
+
+    #[test]
+    fn test_grows_for_larger_payload() {
+        let mut d = MessageDeframer::with_expected_payload(8);
+        assert_eq!(d.buf.len(), 13);
+        input_whole_incremental(&mut d, FIRST_MESSAGE);
+        assert_eq!(d.buf.len(), FIRST_MESSAGE.len());
+        pop_first(&mut d);
+        assert_eq!(d.desynced, false);
+
+        let mut d = MessageDeframer::with_expected_payload(8);
+        d.set_max_payload(FIRST_MESSAGE.len() - 6);
+        input_bytes(&mut d, FIRST_MESSAGE).unwrap();
+        assert_eq!(d.desynced, true);
+    }
 }
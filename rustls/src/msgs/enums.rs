@@ -219,8 +219,11 @@ enum_builder! {
         Heartbeat => 0x000f,
         ALProtocolNegotiation => 0x0010,
         SCT => 0x0012,
+        ClientCertificateType => 0x0013,
+        ServerCertificateType => 0x0014,
         Padding => 0x0015,
         ExtendedMasterSecret => 0x0017,
+        RecordSizeLimit => 0x001c,
         SessionTicket => 0x0023,
         PreSharedKey => 0x0029,
         EarlyData => 0x002a,
@@ -236,6 +239,7 @@ enum_builder! {
         TransportParameters => 0x0039,
         NextProtocolNegotiation => 0x3374,
         ChannelId => 0x754f,
+        EncryptedClientHello => 0xfe0d,
         RenegotiationInfo => 0xff01,
         TransportParametersDraft => 0xffa5
     }
@@ -691,6 +695,8 @@ enum_builder! {
         TLS_ECDHE_PSK_WITH_CHACHA20_POLY1305_SHA256 => 0xccac,
         TLS_DHE_PSK_WITH_CHACHA20_POLY1305_SHA256 => 0xccad,
         TLS_RSA_PSK_WITH_CHACHA20_POLY1305_SHA256 => 0xccae,
+        TLS_ECDHE_PSK_WITH_AES_128_GCM_SHA256 => 0xd001,
+        TLS_ECDHE_PSK_WITH_AES_256_GCM_SHA384 => 0xd002,
         SSL_RSA_FIPS_WITH_DES_CBC_SHA => 0xfefe,
         SSL_RSA_FIPS_WITH_3DES_EDE_CBC_SHA => 0xfeff
     }
@@ -791,3 +797,29 @@ enum_builder! {
         OCSP => 0x01
     }
 }
+
+enum_builder! {
+    /// The `CertificateType` TLS protocol enum.  Values in this enum are taken
+    /// from the various RFCs covering TLS, and are listed by IANA.
+    /// The `Unknown` item is used when processing unrecognised ordinals.
+    @U8
+    EnumName: CertificateType;
+    EnumVal{
+        X509 => 0x00,
+        RawPublicKey => 0x02
+    }
+}
+
+enum_builder! {
+    /// The `MaxFragmentLength` TLS protocol enum.  Values in this enum are taken
+    /// from the various RFCs covering TLS, and are listed by IANA.
+    /// The `Unknown` item is used when processing unrecognised ordinals.
+    @U8
+    EnumName: MaxFragmentLength;
+    EnumVal{
+        Len512 => 0x01,
+        Len1024 => 0x02,
+        Len2048 => 0x03,
+        Len4096 => 0x04
+    }
+}
@@ -91,4 +91,6 @@ fn test_enums() {
         KeyUpdateRequest::UpdateRequested,
     );
     test_enum8::<CertificateStatusType>(CertificateStatusType::OCSP, CertificateStatusType::OCSP);
+    test_enum8::<CertificateType>(CertificateType::X509, CertificateType::RawPublicKey);
+    test_enum8::<MaxFragmentLength>(MaxFragmentLength::Len512, MaxFragmentLength::Len4096);
 }
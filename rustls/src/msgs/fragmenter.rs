@@ -30,6 +30,12 @@ impl MessageFragmenter {
         })
     }
 
+    /// Lower the maximum fragment length (excluding overhead) to
+    /// `max_fragment_len`, if it is currently larger.
+    pub fn set_max_fragment_len(&mut self, max_fragment_len: usize) {
+        self.max_frag = self.max_frag.min(max_fragment_len);
+    }
+
     /// Take the Message `msg` and re-fragment it into new
     /// messages whose fragment is no more than max_frag.
     /// The new messages are appended to the `out` deque.
@@ -4,9 +4,9 @@ use crate::msgs::codec;
 use crate::msgs::codec::{Codec, Reader};
 use crate::msgs::enums::ECCurveType;
 use crate::msgs::enums::PSKKeyExchangeMode;
-use crate::msgs::enums::{CertificateStatusType, ClientCertificateType};
+use crate::msgs::enums::{CertificateStatusType, CertificateType, ClientCertificateType};
 use crate::msgs::enums::{CipherSuite, Compression, ECPointFormat, ExtensionType};
-use crate::msgs::enums::{HandshakeType, ProtocolVersion};
+use crate::msgs::enums::{HandshakeType, MaxFragmentLength, ProtocolVersion};
 use crate::msgs::enums::{HashAlgorithm, ServerNameType, SignatureAlgorithm};
 use crate::msgs::enums::{KeyUpdateRequest, NamedGroup, SignatureScheme};
 use crate::rand;
@@ -165,6 +165,12 @@ impl SessionID {
     }
 }
 
+impl From<[u8; 32]> for SessionID {
+    fn from(data: [u8; 32]) -> Self {
+        Self { data, len: 32 }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct UnknownExtension {
     pub typ: ExtensionType,
@@ -541,6 +547,132 @@ pub type SCTList = VecU16OfPayloadU16;
 declare_u8_vec!(PSKKeyExchangeModes, PSKKeyExchangeMode);
 declare_u16_vec!(KeyShareEntries, KeyShareEntry);
 declare_u8_vec!(ProtocolVersions, ProtocolVersion);
+declare_u8_vec!(CertificateTypes, CertificateType);
+
+// ---
+// Encrypted Client Hello (draft-ietf-tls-esni-13 onwards)
+
+/// The `ECHClientHelloType` of an outer `encrypted_client_hello`
+/// extension.
+const ECH_CLIENT_HELLO_OUTER: u8 = 0;
+
+/// HPKE's HKDF-SHA256 KDF.
+const HPKE_KDF_HKDF_SHA256: u16 = 0x0001;
+
+/// HPKE's AES-128-GCM AEAD.
+const HPKE_AEAD_AES_128_GCM: u16 = 0x0001;
+
+/// The length of the tag HPKE's AES-128-GCM AEAD appends.
+const HPKE_AEAD_AES_128_GCM_TAG_LEN: usize = 16;
+
+/// The `encrypted_client_hello` extension of a ClientHelloOuter.
+#[derive(Clone, Debug)]
+pub struct EncryptedClientHelloOuter {
+    pub kdf_id: u16,
+    pub aead_id: u16,
+    pub config_id: u8,
+    pub enc: PayloadU16,
+    pub payload: PayloadU16,
+}
+
+impl Codec for EncryptedClientHelloOuter {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        ECH_CLIENT_HELLO_OUTER.encode(bytes);
+        self.kdf_id.encode(bytes);
+        self.aead_id.encode(bytes);
+        self.config_id.encode(bytes);
+        self.enc.encode(bytes);
+        self.payload.encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Option<EncryptedClientHelloOuter> {
+        if u8::read(r)? != ECH_CLIENT_HELLO_OUTER {
+            return None;
+        }
+
+        Some(EncryptedClientHelloOuter {
+            kdf_id: u16::read(r)?,
+            aead_id: u16::read(r)?,
+            config_id: u8::read(r)?,
+            enc: PayloadU16::read(r)?,
+            payload: PayloadU16::read(r)?,
+        })
+    }
+}
+
+impl EncryptedClientHelloOuter {
+    /// Make a GREASE extension: one indistinguishable from a real one
+    /// to anyone without the server's ECH keys, but which encrypts
+    /// nothing.  See draft-ietf-tls-esni section 6.2.
+    pub fn grease() -> Result<Self, rand::GetRandomFailed> {
+        let mut config_id = [0u8; 1];
+        rand::fill_random(&mut config_id)?;
+
+        // Every 32-byte string is an X25519 public key.
+        let enc = rand::random_vec(32)?;
+
+        // Pad as a ClientHelloInner of a typical size would be: to a
+        // multiple of 32 bytes, here between 128 and 224 bytes.
+        let padded_len = 128 + 32 * (rand::random_u32()? % 4) as usize;
+        let payload = rand::random_vec(padded_len + HPKE_AEAD_AES_128_GCM_TAG_LEN)?;
+
+        Ok(EncryptedClientHelloOuter {
+            kdf_id: HPKE_KDF_HKDF_SHA256,
+            aead_id: HPKE_AEAD_AES_128_GCM,
+            config_id: config_id[0],
+            enc: PayloadU16::new(enc),
+            payload: PayloadU16::new(payload),
+        })
+    }
+}
+
+/// GREASE values (RFC8701) for a ClientHello: reserved values which
+/// servers must ignore, sent so that they continue to.
+#[derive(Clone, Copy, Debug)]
+pub struct GreaseValues {
+    pub cipher_suite: CipherSuite,
+    pub version: ProtocolVersion,
+    pub group: NamedGroup,
+    pub sig_scheme: SignatureScheme,
+    pub first_extension: ExtensionType,
+    pub last_extension: ExtensionType,
+}
+
+impl GreaseValues {
+    /// Choose values at random, so servers can't special-case any.
+    pub fn random() -> Result<Self, rand::GetRandomFailed> {
+        let mut choices = [0u8; 6];
+        rand::fill_random(&mut choices)?;
+        let value = |choice: u8| {
+            let n = u16::from(choice & 0x0f);
+            (n << 12) | (n << 4) | 0x0a0a
+        };
+
+        // The two extensions can't have the same type.
+        let mut last_extension = value(choices[5]);
+        if last_extension == value(choices[4]) {
+            last_extension ^= 0x1010;
+        }
+
+        Ok(GreaseValues {
+            cipher_suite: CipherSuite::Unknown(value(choices[0])),
+            version: ProtocolVersion::Unknown(value(choices[1])),
+            group: NamedGroup::Unknown(value(choices[2])),
+            sig_scheme: SignatureScheme::Unknown(value(choices[3])),
+            first_extension: ExtensionType::Unknown(value(choices[4])),
+            last_extension: ExtensionType::Unknown(last_extension),
+        })
+    }
+
+    pub fn is_extension(&self, typ: ExtensionType) -> bool {
+        typ == self.first_extension || typ == self.last_extension
+    }
+
+    /// Is `value` one of the sixteen values RFC8701 reserves?
+    pub fn is_grease(value: u16) -> bool {
+        value & 0x0f0f == 0x0a0a && value >> 12 == (value >> 4) & 0x0f
+    }
+}
 
 #[derive(Clone, Debug)]
 pub enum ClientExtension {
@@ -548,6 +680,8 @@ pub enum ClientExtension {
     NamedGroups(NamedGroups),
     SignatureAlgorithms(SupportedSignatureSchemes),
     ServerName(ServerNameRequest),
+    MaxFragmentLength(MaxFragmentLength),
+    RecordSizeLimit(u16),
     SessionTicketRequest,
     SessionTicketOffer(Payload),
     Protocols(ProtocolNameList),
@@ -559,9 +693,12 @@ pub enum ClientExtension {
     ExtendedMasterSecretRequest,
     CertificateStatusRequest(CertificateStatusRequest),
     SignedCertificateTimestampRequest,
+    ServerCertificateType(CertificateTypes),
     TransportParameters(Vec<u8>),
     TransportParametersDraft(Vec<u8>),
     EarlyData,
+    EncryptedClientHello(EncryptedClientHelloOuter),
+    CertificateAuthorities(DistinguishedNames),
     Unknown(UnknownExtension),
 }
 
@@ -572,6 +709,8 @@ impl ClientExtension {
             ClientExtension::NamedGroups(_) => ExtensionType::EllipticCurves,
             ClientExtension::SignatureAlgorithms(_) => ExtensionType::SignatureAlgorithms,
             ClientExtension::ServerName(_) => ExtensionType::ServerName,
+            ClientExtension::MaxFragmentLength(_) => ExtensionType::MaxFragmentLength,
+            ClientExtension::RecordSizeLimit(_) => ExtensionType::RecordSizeLimit,
             ClientExtension::SessionTicketRequest | ClientExtension::SessionTicketOffer(_) => {
                 ExtensionType::SessionTicket
             }
@@ -584,9 +723,12 @@ impl ClientExtension {
             ClientExtension::ExtendedMasterSecretRequest => ExtensionType::ExtendedMasterSecret,
             ClientExtension::CertificateStatusRequest(_) => ExtensionType::StatusRequest,
             ClientExtension::SignedCertificateTimestampRequest => ExtensionType::SCT,
+            ClientExtension::ServerCertificateType(_) => ExtensionType::ServerCertificateType,
             ClientExtension::TransportParameters(_) => ExtensionType::TransportParameters,
             ClientExtension::TransportParametersDraft(_) => ExtensionType::TransportParametersDraft,
             ClientExtension::EarlyData => ExtensionType::EarlyData,
+            ClientExtension::EncryptedClientHello(_) => ExtensionType::EncryptedClientHello,
+            ClientExtension::CertificateAuthorities(_) => ExtensionType::CertificateAuthorities,
             ClientExtension::Unknown(ref r) => r.typ,
         }
     }
@@ -602,6 +744,8 @@ impl Codec for ClientExtension {
             ClientExtension::NamedGroups(ref r) => r.encode(&mut sub),
             ClientExtension::SignatureAlgorithms(ref r) => r.encode(&mut sub),
             ClientExtension::ServerName(ref r) => r.encode(&mut sub),
+            ClientExtension::MaxFragmentLength(ref r) => r.encode(&mut sub),
+            ClientExtension::RecordSizeLimit(r) => r.encode(&mut sub),
             ClientExtension::SessionTicketRequest
             | ClientExtension::ExtendedMasterSecretRequest
             | ClientExtension::SignedCertificateTimestampRequest
@@ -614,8 +758,11 @@ impl Codec for ClientExtension {
             ClientExtension::PresharedKey(ref r) => r.encode(&mut sub),
             ClientExtension::Cookie(ref r) => r.encode(&mut sub),
             ClientExtension::CertificateStatusRequest(ref r) => r.encode(&mut sub),
+            ClientExtension::ServerCertificateType(ref r) => r.encode(&mut sub),
             ClientExtension::TransportParameters(ref r)
             | ClientExtension::TransportParametersDraft(ref r) => sub.extend_from_slice(r),
+            ClientExtension::EncryptedClientHello(ref r) => r.encode(&mut sub),
+            ClientExtension::CertificateAuthorities(ref r) => r.encode(&mut sub),
             ClientExtension::Unknown(ref r) => r.encode(&mut sub),
         }
 
@@ -642,6 +789,12 @@ impl Codec for ClientExtension {
             ExtensionType::ServerName => {
                 ClientExtension::ServerName(ServerNameRequest::read(&mut sub)?)
             }
+            ExtensionType::MaxFragmentLength => {
+                ClientExtension::MaxFragmentLength(MaxFragmentLength::read(&mut sub)?)
+            }
+            ExtensionType::RecordSizeLimit => {
+                ClientExtension::RecordSizeLimit(u16::read(&mut sub)?)
+            }
             ExtensionType::SessionTicket => {
                 if sub.any_left() {
                     let contents = Payload::read(&mut sub);
@@ -674,6 +827,9 @@ impl Codec for ClientExtension {
             ExtensionType::SCT if !sub.any_left() => {
                 ClientExtension::SignedCertificateTimestampRequest
             }
+            ExtensionType::ServerCertificateType => {
+                ClientExtension::ServerCertificateType(CertificateTypes::read(&mut sub)?)
+            }
             ExtensionType::TransportParameters => {
                 ClientExtension::TransportParameters(sub.rest().to_vec())
             }
@@ -681,6 +837,12 @@ impl Codec for ClientExtension {
                 ClientExtension::TransportParametersDraft(sub.rest().to_vec())
             }
             ExtensionType::EarlyData if !sub.any_left() => ClientExtension::EarlyData,
+            ExtensionType::EncryptedClientHello => {
+                ClientExtension::EncryptedClientHello(EncryptedClientHelloOuter::read(&mut sub)?)
+            }
+            ExtensionType::CertificateAuthorities => {
+                ClientExtension::CertificateAuthorities(DistinguishedNames::read(&mut sub)?)
+            }
             _ => ClientExtension::Unknown(UnknownExtension::read(typ, &mut sub)),
         };
 
@@ -719,6 +881,8 @@ impl ClientExtension {
 pub enum ServerExtension {
     ECPointFormats(ECPointFormatList),
     ServerNameAck,
+    MaxFragmentLength(MaxFragmentLength),
+    RecordSizeLimit(u16),
     SessionTicketAck,
     RenegotiationInfo(PayloadU8),
     Protocols(ProtocolNameList),
@@ -728,6 +892,7 @@ pub enum ServerExtension {
     CertificateStatusAck,
     SignedCertificateTimestamp(SCTList),
     SupportedVersions(ProtocolVersion),
+    ServerCertificateType(CertificateType),
     TransportParameters(Vec<u8>),
     TransportParametersDraft(Vec<u8>),
     EarlyData,
@@ -739,6 +904,8 @@ impl ServerExtension {
         match *self {
             ServerExtension::ECPointFormats(_) => ExtensionType::ECPointFormats,
             ServerExtension::ServerNameAck => ExtensionType::ServerName,
+            ServerExtension::MaxFragmentLength(_) => ExtensionType::MaxFragmentLength,
+            ServerExtension::RecordSizeLimit(_) => ExtensionType::RecordSizeLimit,
             ServerExtension::SessionTicketAck => ExtensionType::SessionTicket,
             ServerExtension::RenegotiationInfo(_) => ExtensionType::RenegotiationInfo,
             ServerExtension::Protocols(_) => ExtensionType::ALProtocolNegotiation,
@@ -748,6 +915,7 @@ impl ServerExtension {
             ServerExtension::CertificateStatusAck => ExtensionType::StatusRequest,
             ServerExtension::SignedCertificateTimestamp(_) => ExtensionType::SCT,
             ServerExtension::SupportedVersions(_) => ExtensionType::SupportedVersions,
+            ServerExtension::ServerCertificateType(_) => ExtensionType::ServerCertificateType,
             ServerExtension::TransportParameters(_) => ExtensionType::TransportParameters,
             ServerExtension::TransportParametersDraft(_) => ExtensionType::TransportParametersDraft,
             ServerExtension::EarlyData => ExtensionType::EarlyData,
@@ -768,12 +936,15 @@ impl Codec for ServerExtension {
             | ServerExtension::ExtendedMasterSecretAck
             | ServerExtension::CertificateStatusAck
             | ServerExtension::EarlyData => {}
+            ServerExtension::MaxFragmentLength(ref r) => r.encode(&mut sub),
+            ServerExtension::RecordSizeLimit(r) => r.encode(&mut sub),
             ServerExtension::RenegotiationInfo(ref r) => r.encode(&mut sub),
             ServerExtension::Protocols(ref r) => r.encode(&mut sub),
             ServerExtension::KeyShare(ref r) => r.encode(&mut sub),
             ServerExtension::PresharedKey(r) => r.encode(&mut sub),
             ServerExtension::SignedCertificateTimestamp(ref r) => r.encode(&mut sub),
             ServerExtension::SupportedVersions(ref r) => r.encode(&mut sub),
+            ServerExtension::ServerCertificateType(ref r) => r.encode(&mut sub),
             ServerExtension::TransportParameters(ref r)
             | ServerExtension::TransportParametersDraft(ref r) => sub.extend_from_slice(r),
             ServerExtension::Unknown(ref r) => r.encode(&mut sub),
@@ -793,6 +964,12 @@ impl Codec for ServerExtension {
                 ServerExtension::ECPointFormats(ECPointFormatList::read(&mut sub)?)
             }
             ExtensionType::ServerName => ServerExtension::ServerNameAck,
+            ExtensionType::MaxFragmentLength => {
+                ServerExtension::MaxFragmentLength(MaxFragmentLength::read(&mut sub)?)
+            }
+            ExtensionType::RecordSizeLimit => {
+                ServerExtension::RecordSizeLimit(u16::read(&mut sub)?)
+            }
             ExtensionType::SessionTicket => ServerExtension::SessionTicketAck,
             ExtensionType::StatusRequest => ServerExtension::CertificateStatusAck,
             ExtensionType::RenegotiationInfo => {
@@ -811,6 +988,9 @@ impl Codec for ServerExtension {
             ExtensionType::SupportedVersions => {
                 ServerExtension::SupportedVersions(ProtocolVersion::read(&mut sub)?)
             }
+            ExtensionType::ServerCertificateType => {
+                ServerExtension::ServerCertificateType(CertificateType::read(&mut sub)?)
+            }
             ExtensionType::TransportParameters => {
                 ServerExtension::TransportParameters(sub.rest().to_vec())
             }
@@ -1011,6 +1191,14 @@ impl ClientHelloPayload {
             .map_or(false, |ext| ext.get_type() == ExtensionType::PreSharedKey)
     }
 
+    pub fn get_certificate_authorities_extension(&self) -> Option<&DistinguishedNames> {
+        let ext = self.find_extension(ExtensionType::CertificateAuthorities)?;
+        match *ext {
+            ClientExtension::CertificateAuthorities(ref names) => Some(names),
+            _ => None,
+        }
+    }
+
     pub fn get_psk_modes(&self) -> Option<&PSKKeyExchangeModes> {
         let ext = self.find_extension(ExtensionType::PSKKeyExchangeModes)?;
         match *ext {
@@ -1038,6 +1226,22 @@ impl ClientHelloPayload {
             .is_some()
     }
 
+    pub fn get_max_fragment_length(&self) -> Option<MaxFragmentLength> {
+        let ext = self.find_extension(ExtensionType::MaxFragmentLength)?;
+        match *ext {
+            ClientExtension::MaxFragmentLength(len) => Some(len),
+            _ => None,
+        }
+    }
+
+    pub fn get_record_size_limit(&self) -> Option<u16> {
+        let ext = self.find_extension(ExtensionType::RecordSizeLimit)?;
+        match *ext {
+            ClientExtension::RecordSizeLimit(limit) => Some(limit),
+            _ => None,
+        }
+    }
+
     pub fn early_data_extension_offered(&self) -> bool {
         self.find_extension(ExtensionType::EarlyData)
             .is_some()
@@ -1049,6 +1253,7 @@ pub enum HelloRetryExtension {
     KeyShare(NamedGroup),
     Cookie(PayloadU16),
     SupportedVersions(ProtocolVersion),
+    EchConfirmation([u8; 8]),
     Unknown(UnknownExtension),
 }
 
@@ -1058,6 +1263,7 @@ impl HelloRetryExtension {
             HelloRetryExtension::KeyShare(_) => ExtensionType::KeyShare,
             HelloRetryExtension::Cookie(_) => ExtensionType::Cookie,
             HelloRetryExtension::SupportedVersions(_) => ExtensionType::SupportedVersions,
+            HelloRetryExtension::EchConfirmation(_) => ExtensionType::EncryptedClientHello,
             HelloRetryExtension::Unknown(ref r) => r.typ,
         }
     }
@@ -1072,6 +1278,7 @@ impl Codec for HelloRetryExtension {
             HelloRetryExtension::KeyShare(ref r) => r.encode(&mut sub),
             HelloRetryExtension::Cookie(ref r) => r.encode(&mut sub),
             HelloRetryExtension::SupportedVersions(ref r) => r.encode(&mut sub),
+            HelloRetryExtension::EchConfirmation(ref r) => sub.extend_from_slice(r),
             HelloRetryExtension::Unknown(ref r) => r.encode(&mut sub),
         }
 
@@ -1090,6 +1297,11 @@ impl Codec for HelloRetryExtension {
             ExtensionType::SupportedVersions => {
                 HelloRetryExtension::SupportedVersions(ProtocolVersion::read(&mut sub)?)
             }
+            ExtensionType::EncryptedClientHello => {
+                let mut confirmation = [0u8; 8];
+                confirmation.copy_from_slice(sub.take(8)?);
+                HelloRetryExtension::EchConfirmation(confirmation)
+            }
             _ => HelloRetryExtension::Unknown(UnknownExtension::read(typ, &mut sub)),
         };
 
@@ -1156,9 +1368,15 @@ impl HelloRetryRequest {
             ext.get_type() != ExtensionType::KeyShare
                 && ext.get_type() != ExtensionType::SupportedVersions
                 && ext.get_type() != ExtensionType::Cookie
+                && ext.get_type() != ExtensionType::EncryptedClientHello
         })
     }
 
+    pub fn has_ech_confirmation(&self) -> bool {
+        self.find_extension(ExtensionType::EncryptedClientHello)
+            .is_some()
+    }
+
     fn find_extension(&self, ext: ExtensionType) -> Option<&HelloRetryExtension> {
         self.extensions
             .iter()
@@ -1521,6 +1739,7 @@ impl CertificatePayloadTLS13 {
     }
 }
 
+#[allow(non_camel_case_types)]
 #[derive(Debug)]
 pub enum KeyExchangeAlgorithm {
     BulkOnly,
@@ -1529,6 +1748,7 @@ pub enum KeyExchangeAlgorithm {
     RSA,
     ECDH,
     ECDHE,
+    ECDHE_PSK,
 }
 
 // We don't support arbitrary curves.  It's a terrible
@@ -1607,6 +1827,26 @@ impl Codec for ClientECDHParams {
     }
 }
 
+/// RFC5489's ClientKeyExchange: the PSK identity, then our ECDHE share.
+#[derive(Debug)]
+pub struct ClientECDHEPSKParams {
+    pub identity: PayloadU16,
+    pub public: PayloadU8,
+}
+
+impl Codec for ClientECDHEPSKParams {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.identity.encode(bytes);
+        self.public.encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Option<ClientECDHEPSKParams> {
+        let identity = PayloadU16::read(r)?;
+        let public = PayloadU8::read(r)?;
+        Some(ClientECDHEPSKParams { identity, public })
+    }
+}
+
 #[derive(Debug)]
 pub struct ServerECDHParams {
     pub curve_params: ECParameters,
@@ -1662,9 +1902,35 @@ impl Codec for ECDHEServerKeyExchange {
     }
 }
 
+/// RFC5489's ServerKeyExchange: the PSK identity hint, then our
+/// ECDHE parameters.  These aren't signed.
+#[derive(Debug)]
+pub struct ECDHEPSKServerKeyExchange {
+    pub identity_hint: PayloadU16,
+    pub params: ServerECDHParams,
+}
+
+impl Codec for ECDHEPSKServerKeyExchange {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.identity_hint.encode(bytes);
+        self.params.encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Option<ECDHEPSKServerKeyExchange> {
+        let identity_hint = PayloadU16::read(r)?;
+        let params = ServerECDHParams::read(r)?;
+
+        Some(ECDHEPSKServerKeyExchange {
+            identity_hint,
+            params,
+        })
+    }
+}
+
 #[derive(Debug)]
 pub enum ServerKeyExchangePayload {
     ECDHE(ECDHEServerKeyExchange),
+    ECDHEPSK(ECDHEPSKServerKeyExchange),
     Unknown(Payload),
 }
 
@@ -1672,6 +1938,7 @@ impl Codec for ServerKeyExchangePayload {
     fn encode(&self, bytes: &mut Vec<u8>) {
         match *self {
             ServerKeyExchangePayload::ECDHE(ref x) => x.encode(bytes),
+            ServerKeyExchangePayload::ECDHEPSK(ref x) => x.encode(bytes),
             ServerKeyExchangePayload::Unknown(ref x) => x.encode(bytes),
         }
     }
@@ -1692,6 +1959,8 @@ impl ServerKeyExchangePayload {
                 KeyExchangeAlgorithm::ECDHE => {
                     ECDHEServerKeyExchange::read(&mut rd).map(ServerKeyExchangePayload::ECDHE)
                 }
+                KeyExchangeAlgorithm::ECDHE_PSK => ECDHEPSKServerKeyExchange::read(&mut rd)
+                    .map(ServerKeyExchangePayload::ECDHEPSK),
                 _ => None,
             };
 
@@ -1706,8 +1975,10 @@ impl ServerKeyExchangePayload {
     pub fn encode_params(&self, bytes: &mut Vec<u8>) {
         bytes.clear();
 
-        if let ServerKeyExchangePayload::ECDHE(ref x) = *self {
-            x.params.encode(bytes);
+        match *self {
+            ServerKeyExchangePayload::ECDHE(ref x) => x.params.encode(bytes),
+            ServerKeyExchangePayload::ECDHEPSK(ref x) => x.params.encode(bytes),
+            ServerKeyExchangePayload::Unknown(_) => {}
         }
     }
 
@@ -1717,6 +1988,13 @@ impl ServerKeyExchangePayload {
             _ => None,
         }
     }
+
+    pub fn get_psk_identity_hint(&self) -> Option<&[u8]> {
+        match *self {
+            ServerKeyExchangePayload::ECDHEPSK(ref x) => Some(&x.identity_hint.0),
+            _ => None,
+        }
+    }
 }
 
 // -- EncryptedExtensions (TLS1.3 only) --
@@ -1771,6 +2049,30 @@ pub trait HasServerExtensions {
         self.find_extension(ExtensionType::EarlyData)
             .is_some()
     }
+
+    fn get_max_fragment_length(&self) -> Option<MaxFragmentLength> {
+        let ext = self.find_extension(ExtensionType::MaxFragmentLength)?;
+        match *ext {
+            ServerExtension::MaxFragmentLength(len) => Some(len),
+            _ => None,
+        }
+    }
+
+    fn get_record_size_limit(&self) -> Option<u16> {
+        let ext = self.find_extension(ExtensionType::RecordSizeLimit)?;
+        match *ext {
+            ServerExtension::RecordSizeLimit(limit) => Some(limit),
+            _ => None,
+        }
+    }
+
+    fn get_server_certificate_type(&self) -> Option<CertificateType> {
+        let ext = self.find_extension(ExtensionType::ServerCertificateType)?;
+        match *ext {
+            ServerExtension::ServerCertificateType(typ) => Some(typ),
+            _ => None,
+        }
+    }
 }
 
 impl HasServerExtensions for EncryptedExtensions {
@@ -2111,6 +2413,7 @@ pub enum HandshakePayload {
     ServerHello(ServerHelloPayload),
     HelloRetryRequest(HelloRetryRequest),
     Certificate(CertificatePayload),
+    CertificateRawPublicKey(PayloadU24),
     CertificateTLS13(CertificatePayloadTLS13),
     ServerKeyExchange(ServerKeyExchangePayload),
     CertificateRequest(CertificateRequestPayload),
@@ -2141,6 +2444,7 @@ impl HandshakePayload {
             HandshakePayload::ServerHello(ref x) => x.encode(bytes),
             HandshakePayload::HelloRetryRequest(ref x) => x.encode(bytes),
             HandshakePayload::Certificate(ref x) => x.encode(bytes),
+            HandshakePayload::CertificateRawPublicKey(ref x) => x.encode(bytes),
             HandshakePayload::CertificateTLS13(ref x) => x.encode(bytes),
             HandshakePayload::ServerKeyExchange(ref x) => x.encode(bytes),
             HandshakePayload::ClientKeyExchange(ref x) => x.encode(bytes),
@@ -2159,6 +2463,18 @@ impl HandshakePayload {
     }
 }
 
+/// Read the DER-encoded `SubjectPublicKeyInfo` which RFC7250 sends in
+/// place of a certificate list.  This is limited in size just as
+/// certificate lists are.
+fn read_raw_public_key(body: &[u8]) -> Option<PayloadU24> {
+    let mut r = Reader::init(body);
+    let spki = PayloadU24::read(&mut r)?;
+    match spki.0.first() {
+        Some(0x30) if !r.any_left() && spki.0.len() <= 0x10000 => Some(spki),
+        _ => None,
+    }
+}
+
 #[derive(Debug)]
 pub struct HandshakeMessagePayload {
     pub typ: HandshakeType,
@@ -2218,7 +2534,16 @@ impl HandshakeMessagePayload {
                 HandshakePayload::CertificateTLS13(p)
             }
             HandshakeType::Certificate => {
-                HandshakePayload::Certificate(CertificatePayload::read(&mut sub)?)
+                // RFC7250 replaces the certificate list with a single raw
+                // SubjectPublicKeyInfo.  Its leading SEQUENCE tag would make
+                // the first certificate impossibly long, so the two can't be
+                // mistaken for one another.
+                let body = sub.rest();
+                let mut chain = Reader::init(body);
+                match CertificatePayload::read(&mut chain) {
+                    Some(certs) if !chain.any_left() => HandshakePayload::Certificate(certs),
+                    _ => HandshakePayload::CertificateRawPublicKey(read_raw_public_key(body)?),
+                }
             }
             HandshakeType::ServerKeyExchange => {
                 let p = ServerKeyExchangePayload::read(&mut sub)?;
@@ -369,6 +369,8 @@ fn get_sample_clienthellopayload() -> ClientHelloPayload {
             ClientExtension::NamedGroups(vec![NamedGroup::X25519]),
             ClientExtension::SignatureAlgorithms(vec![SignatureScheme::ECDSA_NISTP256_SHA256]),
             ClientExtension::make_sni(DnsNameRef::try_from_ascii_str("hello").unwrap()),
+            ClientExtension::MaxFragmentLength(MaxFragmentLength::Len1024),
+            ClientExtension::RecordSizeLimit(1024),
             ClientExtension::SessionTicketRequest,
             ClientExtension::SessionTicketOffer(Payload(vec![])),
             ClientExtension::Protocols(vec![PayloadU8(vec![0])]),
@@ -389,7 +391,19 @@ fn get_sample_clienthellopayload() -> ClientHelloPayload {
             ClientExtension::ExtendedMasterSecretRequest,
             ClientExtension::CertificateStatusRequest(CertificateStatusRequest::build_ocsp()),
             ClientExtension::SignedCertificateTimestampRequest,
+            ClientExtension::ServerCertificateType(vec![
+                CertificateType::RawPublicKey,
+                CertificateType::X509,
+            ]),
             ClientExtension::TransportParameters(vec![1, 2, 3]),
+            ClientExtension::EncryptedClientHello(EncryptedClientHelloOuter {
+                kdf_id: 1,
+                aead_id: 1,
+                config_id: 2,
+                enc: PayloadU16(vec![3, 4, 5]),
+                payload: PayloadU16(vec![6, 7, 8]),
+            }),
+            ClientExtension::CertificateAuthorities(vec![PayloadU16(vec![0x30, 0x00])]),
             ClientExtension::Unknown(UnknownExtension {
                 typ: ExtensionType::Unknown(12345),
                 payload: Payload(vec![1, 2, 3]),
@@ -441,6 +455,16 @@ fn test_truncated_psk_offer() {
     }
 }
 
+#[test]
+fn client_hello_has_certificate_authorities() {
+    let ch = get_sample_clienthellopayload();
+    let ch = ClientHelloPayload::read_bytes(&ch.get_encoding()).unwrap();
+    assert_eq!(
+        ch.get_certificate_authorities_extension(),
+        Some(&vec![PayloadU16(vec![0x30, 0x00])])
+    );
+}
+
 #[test]
 fn test_truncated_client_hello_is_detected() {
     let ch = get_sample_clienthellopayload();
@@ -541,6 +565,20 @@ fn client_get_alpn_extension() {
     });
 }
 
+#[test]
+fn client_get_max_fragment_length() {
+    test_client_extension_getter(ExtensionType::MaxFragmentLength, |chp| {
+        chp.get_max_fragment_length().is_some()
+    });
+}
+
+#[test]
+fn client_get_record_size_limit() {
+    test_client_extension_getter(ExtensionType::RecordSizeLimit, |chp| {
+        chp.get_record_size_limit().is_some()
+    });
+}
+
 #[test]
 fn client_get_quic_params_extension() {
     test_client_extension_getter(ExtensionType::TransportParameters, |chp| {
@@ -717,6 +755,20 @@ fn server_get_sct_list() {
     test_server_extension_getter(ExtensionType::SCT, |shp| shp.get_sct_list().is_some());
 }
 
+#[test]
+fn server_get_max_fragment_length() {
+    test_server_extension_getter(ExtensionType::MaxFragmentLength, |shp| {
+        shp.get_max_fragment_length().is_some()
+    });
+}
+
+#[test]
+fn server_get_record_size_limit() {
+    test_server_extension_getter(ExtensionType::RecordSizeLimit, |shp| {
+        shp.get_record_size_limit().is_some()
+    });
+}
+
 #[test]
 fn server_get_supported_versions() {
     test_server_extension_getter(ExtensionType::SupportedVersions, |shp| {
@@ -765,6 +817,8 @@ fn get_sample_serverhellopayload() -> ServerHelloPayload {
         extensions: vec![
             ServerExtension::ECPointFormats(ECPointFormatList::supported()),
             ServerExtension::ServerNameAck,
+            ServerExtension::MaxFragmentLength(MaxFragmentLength::Len1024),
+            ServerExtension::RecordSizeLimit(1024),
             ServerExtension::SessionTicketAck,
             ServerExtension::RenegotiationInfo(PayloadU8(vec![0])),
             ServerExtension::Protocols(vec![PayloadU8(vec![0])]),
@@ -774,6 +828,7 @@ fn get_sample_serverhellopayload() -> ServerHelloPayload {
             ServerExtension::CertificateStatusAck,
             ServerExtension::SignedCertificateTimestamp(vec![PayloadU16(vec![0])]),
             ServerExtension::SupportedVersions(ProtocolVersion::TLSv1_2),
+            ServerExtension::ServerCertificateType(CertificateType::RawPublicKey),
             ServerExtension::TransportParameters(vec![1, 2, 3]),
             ServerExtension::Unknown(UnknownExtension {
                 typ: ExtensionType::Unknown(12345),
@@ -804,6 +859,7 @@ fn get_sample_helloretryrequest() -> HelloRetryRequest {
             HelloRetryExtension::KeyShare(NamedGroup::X25519),
             HelloRetryExtension::Cookie(PayloadU16(vec![0])),
             HelloRetryExtension::SupportedVersions(ProtocolVersion::TLSv1_2),
+            HelloRetryExtension::EchConfirmation([1, 2, 3, 4, 5, 6, 7, 8]),
             HelloRetryExtension::Unknown(UnknownExtension {
                 typ: ExtensionType::Unknown(12345),
                 payload: Payload(vec![1, 2, 3]),
@@ -847,6 +903,47 @@ fn get_sample_serverkeyexchangepayload_ecdhe() -> ServerKeyExchangePayload {
     })
 }
 
+fn get_sample_serverkeyexchangepayload_ecdhepsk() -> ServerKeyExchangePayload {
+    ServerKeyExchangePayload::ECDHEPSK(ECDHEPSKServerKeyExchange {
+        identity_hint: PayloadU16(b"hint".to_vec()),
+        params: ServerECDHParams {
+            curve_params: ECParameters {
+                curve_type: ECCurveType::NamedCurve,
+                named_group: NamedGroup::X25519,
+            },
+            public: PayloadU8(vec![1, 2, 3]),
+        },
+    })
+}
+
+#[test]
+fn can_unwrap_ecdhepsk_serverkeyexchange() {
+    let mut bytes = Vec::new();
+    get_sample_serverkeyexchangepayload_ecdhepsk().encode(&mut bytes);
+    let skx = ServerKeyExchangePayload::Unknown(Payload(bytes))
+        .unwrap_given_kxa(&KeyExchangeAlgorithm::ECDHE_PSK)
+        .unwrap();
+    assert_eq!(skx.get_psk_identity_hint(), Some(&b"hint"[..]));
+    assert!(skx.get_sig().is_none());
+
+    let mut params = Vec::new();
+    skx.encode_params(&mut params);
+    assert_eq!(params, vec![3, 0, 0x1d, 3, 1, 2, 3]);
+}
+
+#[test]
+fn can_roundtrip_ecdhepsk_clientkeyexchange() {
+    let ckx = ClientECDHEPSKParams {
+        identity: PayloadU16(b"client".to_vec()),
+        public: PayloadU8(vec![1, 2, 3]),
+    };
+    let bytes = ckx.get_encoding();
+    let other = ClientECDHEPSKParams::read_bytes(&bytes).unwrap();
+    assert_eq!(other.identity.0, b"client".to_vec());
+    assert_eq!(other.public.0, vec![1, 2, 3]);
+    assert!(ClientECDHEPSKParams::read_bytes(&bytes[..bytes.len() - 1]).is_none());
+}
+
 fn get_sample_serverkeyexchangepayload_unknown() -> ServerKeyExchangePayload {
     ServerKeyExchangePayload::Unknown(Payload(vec![1, 2, 3]))
 }
@@ -925,12 +1022,24 @@ fn get_all_tls12_handshake_payloads() -> Vec<HandshakeMessagePayload> {
             typ: HandshakeType::Certificate,
             payload: HandshakePayload::Certificate(vec![Certificate(vec![1, 2, 3])]),
         },
+        HandshakeMessagePayload {
+            typ: HandshakeType::Certificate,
+            payload: HandshakePayload::CertificateRawPublicKey(PayloadU24(vec![
+                0x30, 0x03, 0x01, 0x02, 0x03,
+            ])),
+        },
         HandshakeMessagePayload {
             typ: HandshakeType::ServerKeyExchange,
             payload: HandshakePayload::ServerKeyExchange(
                 get_sample_serverkeyexchangepayload_ecdhe(),
             ),
         },
+        HandshakeMessagePayload {
+            typ: HandshakeType::ServerKeyExchange,
+            payload: HandshakePayload::ServerKeyExchange(
+                get_sample_serverkeyexchangepayload_ecdhepsk(),
+            ),
+        },
         HandshakeMessagePayload {
             typ: HandshakeType::ServerKeyExchange,
             payload: HandshakePayload::ServerKeyExchange(
@@ -1061,6 +1170,12 @@ fn get_all_tls13_handshake_payloads() -> Vec<HandshakeMessagePayload> {
                 get_sample_serverkeyexchangepayload_ecdhe(),
             ),
         },
+        HandshakeMessagePayload {
+            typ: HandshakeType::ServerKeyExchange,
+            payload: HandshakePayload::ServerKeyExchange(
+                get_sample_serverkeyexchangepayload_ecdhepsk(),
+            ),
+        },
         HandshakeMessagePayload {
             typ: HandshakeType::ServerKeyExchange,
             payload: HandshakePayload::ServerKeyExchange(
@@ -1217,3 +1332,25 @@ fn cannot_decode_huge_certificate() {
     buf[6] = 0x01;
     assert!(HandshakeMessagePayload::read_bytes(&buf).is_none());
 }
+
+#[test]
+fn recognises_grease_values() {
+    let grease = (0..16)
+        .map(|n| (n << 12) | (n << 4) | 0x0a0a)
+        .collect::<Vec<u16>>();
+    assert_eq!(
+        (0..=0xffff)
+            .filter(|value| GreaseValues::is_grease(*value))
+            .collect::<Vec<u16>>(),
+        grease
+    );
+
+    let values = GreaseValues::random().unwrap();
+    assert!(GreaseValues::is_grease(values.cipher_suite.get_u16()));
+    assert!(GreaseValues::is_grease(values.version.get_u16()));
+    assert!(GreaseValues::is_grease(values.group.get_u16()));
+    assert!(GreaseValues::is_grease(values.sig_scheme.get_u16()));
+    assert!(GreaseValues::is_grease(values.first_extension.get_u16()));
+    assert!(GreaseValues::is_grease(values.last_extension.get_u16()));
+    assert_ne!(values.first_extension, values.last_extension);
+}
@@ -134,7 +134,7 @@ impl OpaqueMessage {
     const MAX_PAYLOAD: u16 = 16384 + 2048;
 
     /// Content type, version and size.
-    const HEADER_SIZE: u16 = 1 + 2 + 2;
+    pub const HEADER_SIZE: u16 = 1 + 2 + 2;
 
     /// Maximum on-wire message size.
     pub const MAX_WIRE_SIZE: usize = (Self::MAX_PAYLOAD + Self::HEADER_SIZE) as usize;
@@ -0,0 +1,525 @@
+// Validation of stapled OCSP responses, per RFC6960.
+
+use crate::anchors::RootCertStore;
+use crate::error::Error;
+use crate::key::Certificate;
+#[cfg(feature = "logging")]
+use crate::log::debug;
+use crate::msgs::enums::SignatureScheme;
+use crate::verify::{self, SignatureAlgorithms};
+use crate::x509::{self, oid, CertFields, DerReader};
+use ring::digest;
+use ring::io::der;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How a [`WebPkiVerifier`] treats OCSP responses stapled by the server.
+///
+/// [`WebPkiVerifier`]: crate::WebPkiVerifier
+#[derive(Debug, Clone, PartialEq)]
+pub struct OcspPolicy {
+    /// Further restricts the signature schemes acceptable for the
+    /// responder's signature over the response.
+    ///
+    /// Responses are always held to the algorithms the verifier accepts
+    /// for certificate chains (see
+    /// [`WebPkiVerifier::with_signature_algorithms`]): a strong chain
+    /// policy is worthless if a forged response can be made with a weak
+    /// algorithm.  If this is `None`, that is the only restriction.
+    /// Schemes using SHA-1 are never supported, so such responses are
+    /// always rejected.
+    ///
+    /// [`WebPkiVerifier::with_signature_algorithms`]: crate::WebPkiVerifier::with_signature_algorithms
+    pub signature_schemes: Option<Vec<SignatureScheme>>,
+
+    /// What to do when the responder says the certificate's status is
+    /// `unknown`.
+    pub unknown_status: OcspUnknownStatus,
+
+    /// Whether to reject responses from delegated responders whose
+    /// certificate lacks the `id-pkix-ocsp-nocheck` extension.
+    ///
+    /// RFC6960 says the revocation status of such a responder should
+    /// be checked by other means.  Nothing else is available here, so
+    /// by default these responders are trusted without a check.
+    pub require_responder_nocheck: bool,
+}
+
+impl OcspPolicy {
+    /// Make an `OcspPolicy` which accepts responses signed with the
+    /// algorithms accepted for certificate chains, and treats an
+    /// `unknown` status as a soft failure.
+    pub fn new() -> Self {
+        Self {
+            signature_schemes: None,
+            unknown_status: OcspUnknownStatus::Warn,
+            require_responder_nocheck: false,
+        }
+    }
+
+    /// Whether responses and responder certificates may be signed using
+    /// `scheme`, given the verifier accepts `chain_algorithms`.
+    fn allows(&self, scheme: SignatureScheme, chain_algorithms: SignatureAlgorithms) -> bool {
+        let listed = match &self.signature_schemes {
+            Some(schemes) => schemes.contains(&scheme),
+            None => true,
+        };
+        listed && verify::scheme_in_algorithms(scheme, chain_algorithms)
+    }
+}
+
+impl Default for OcspPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// How to treat an OCSP response with the status `unknown`, meaning
+/// the responder knows nothing about the certificate.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OcspUnknownStatus {
+    /// Accept the certificate.
+    Accept,
+    /// Accept the certificate, but log a warning.  This matches
+    /// the behaviour of browsers.
+    Warn,
+    /// Reject the certificate with `Error::OcspStatusUnknown`.
+    Reject,
+}
+
+/// The status of a certificate, as asserted by an OCSP responder.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CertStatus {
+    /// The certificate is not revoked.
+    Good,
+    /// The certificate is revoked.
+    Revoked,
+    /// The responder doesn't know about the certificate.
+    Unknown,
+}
+
+/// The period for which an OCSP response is valid, as reported in
+/// [`VerifiedServerCert::ocsp_freshness`].
+///
+/// [`VerifiedServerCert::ocsp_freshness`]: crate::VerifiedServerCert::ocsp_freshness
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct OcspFreshness {
+    /// When the responder last knew the status to be correct
+    /// (`thisUpdate`).
+    pub this_update: SystemTime,
+    /// When newer information will be available (`nextUpdate`), if
+    /// the responder said.
+    pub next_update: Option<SystemTime>,
+}
+
+/// The `CertID` identifying the certificate a `SingleResponse` is for.
+struct CertId<'a> {
+    /// The contents of the hash `AlgorithmIdentifier`.
+    hash_alg: &'a [u8],
+    issuer_name_hash: &'a [u8],
+    issuer_key_hash: &'a [u8],
+    serial: &'a [u8],
+}
+
+struct SingleResponse<'a> {
+    cert_id: CertId<'a>,
+    status: CertStatus,
+    this_update: u64,
+    next_update: Option<u64>,
+    extensions: Option<&'a [u8]>,
+}
+
+struct BasicResponse<'a> {
+    tbs: &'a [u8],
+    signature_alg: &'a [u8],
+    signature: &'a [u8],
+    certs: Vec<&'a [u8]>,
+    responses: Vec<SingleResponse<'a>>,
+}
+
+fn bad(why: &str) -> Error {
+    Error::InvalidOcspResponse(why.to_string())
+}
+
+impl<'a> BasicResponse<'a> {
+    fn parse(response: &'a [u8]) -> Option<Self> {
+        let mut outer = DerReader::new(response);
+        let mut ocsp_response = DerReader::new(outer.read(der::Tag::Sequence as u8)?);
+
+        // Only `successful` responses carry a status.
+        if ocsp_response.read(x509::ENUMERATED)? != [0] {
+            return None;
+        }
+
+        let mut explicit =
+            DerReader::new(ocsp_response.read(der::Tag::ContextSpecificConstructed0 as u8)?);
+        let mut response_bytes = DerReader::new(explicit.read(der::Tag::Sequence as u8)?);
+        if response_bytes.read(der::Tag::OID as u8)? != oid::PKIX_OCSP_BASIC {
+            return None;
+        }
+        let basic = response_bytes.read(der::Tag::OctetString as u8)?;
+
+        let mut basic = DerReader::new(DerReader::new(basic).read(der::Tag::Sequence as u8)?);
+        let (tag, tbs_contents, tbs) = basic.read_element()?;
+        if tag != der::Tag::Sequence as u8 {
+            return None;
+        }
+        let signature_alg = basic.read(der::Tag::Sequence as u8)?;
+        let signature = x509::read_bit_string(&mut basic)?;
+        let certs = match basic.read_optional(der::Tag::ContextSpecificConstructed0 as u8) {
+            Some(certs) => {
+                let certs = DerReader::new(certs).read(der::Tag::Sequence as u8)?;
+                let mut reader = DerReader::new(certs);
+                let mut out = Vec::new();
+                while !reader.at_end() {
+                    let (_, _, cert) = reader.read_element()?;
+                    out.push(cert);
+                }
+                out
+            }
+            None => Vec::new(),
+        };
+
+        let mut tbs_reader = DerReader::new(tbs_contents);
+        tbs_reader.read_optional(der::Tag::ContextSpecificConstructed0 as u8);
+        tbs_reader.read_element()?; // responderID
+        tbs_reader.read(der::Tag::GeneralizedTime as u8)?; // producedAt
+        let responses = DerReader::new(tbs_reader.read(der::Tag::Sequence as u8)?)
+            .read_all(der::Tag::Sequence as u8)?
+            .into_iter()
+            .map(SingleResponse::parse)
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(Self {
+            tbs,
+            signature_alg,
+            signature,
+            certs,
+            responses,
+        })
+    }
+}
+
+impl<'a> CertId<'a> {
+    /// Whether this identifies `cert`, issued by the holder of
+    /// `issuer_spki` (RFC6960 section 4.1.1).  The issuer's name and
+    /// key must match too, as serial numbers are only unique per
+    /// issuer.
+    fn matches(&self, cert: &CertFields, issuer_spki: &[u8]) -> bool {
+        // The hash only identifies the issuer; the response signature
+        // is what authenticates it.  So SHA-1, which most responders
+        // use here, is acceptable.
+        let alg = match DerReader::new(self.hash_alg).read(der::Tag::OID as u8) {
+            Some(oid::SHA1) => &digest::SHA1_FOR_LEGACY_USE_ONLY,
+            Some(oid::SHA256) => &digest::SHA256,
+            Some(oid::SHA384) => &digest::SHA384,
+            Some(oid::SHA512) => &digest::SHA512,
+            _ => return false,
+        };
+        let issuer_key = match x509::split_spki(issuer_spki) {
+            Some((_, key)) => key,
+            None => return false,
+        };
+        let mut issuer_name = cert.issuer.to_vec();
+        x509::wrap_in_sequence(&mut issuer_name);
+
+        self.serial == cert.serial
+            && digest::digest(alg, &issuer_name).as_ref() == self.issuer_name_hash
+            && digest::digest(alg, issuer_key).as_ref() == self.issuer_key_hash
+    }
+}
+
+impl<'a> SingleResponse<'a> {
+    fn parse(single: &'a [u8]) -> Option<Self> {
+        let mut reader = DerReader::new(single);
+
+        let mut cert_id = DerReader::new(reader.read(der::Tag::Sequence as u8)?);
+        let cert_id = CertId {
+            hash_alg: cert_id.read(der::Tag::Sequence as u8)?,
+            issuer_name_hash: cert_id.read(der::Tag::OctetString as u8)?,
+            issuer_key_hash: cert_id.read(der::Tag::OctetString as u8)?,
+            serial: cert_id.read(der::Tag::Integer as u8)?,
+        };
+
+        let status = match reader.read_element()? {
+            (x509::CONTEXT_SPECIFIC_0, _, _) => CertStatus::Good,
+            (tag, _, _) if tag == der::Tag::ContextSpecificConstructed1 as u8 => {
+                CertStatus::Revoked
+            }
+            (x509::CONTEXT_SPECIFIC_2, _, _) => CertStatus::Unknown,
+            _ => return None,
+        };
+
+        let this_update = x509::parse_time(
+            der::Tag::GeneralizedTime as u8,
+            reader.read(der::Tag::GeneralizedTime as u8)?,
+        )?;
+        let next_update = match reader.read_optional(der::Tag::ContextSpecificConstructed0 as u8) {
+            Some(explicit) => Some(x509::parse_time(
+                der::Tag::GeneralizedTime as u8,
+                DerReader::new(explicit).read(der::Tag::GeneralizedTime as u8)?,
+            )?),
+            None => None,
+        };
+        let extensions = match reader.read_optional(der::Tag::ContextSpecificConstructed1 as u8) {
+            Some(explicit) => Some(DerReader::new(explicit).read(der::Tag::Sequence as u8)?),
+            None => None,
+        };
+
+        Some(Self {
+            cert_id,
+            status,
+            this_update,
+            next_update,
+            extensions,
+        })
+    }
+}
+
+/// Validate the OCSP `response` for `end_entity`, returning the status it
+/// asserts and how fresh the response is.
+///
+/// The response must be signed by the end-entity certificate's issuer, or
+/// by a responder certificate the issuer delegated OCSP signing to.  The
+/// issuer is looked for in `intermediates`, then in `roots`.  `end_entity`
+/// and its chain must already have been validated.
+pub(crate) fn verify_response(
+    policy: &OcspPolicy,
+    response: &[u8],
+    end_entity: &Certificate,
+    intermediates: &[Certificate],
+    roots: &RootCertStore,
+    chain_algorithms: SignatureAlgorithms,
+    now: u64,
+) -> Result<(CertStatus, OcspFreshness), Error> {
+    let basic = BasicResponse::parse(response).ok_or_else(|| bad("malformed response"))?;
+    let cert = CertFields::parse(&end_entity.0).ok_or_else(|| bad("cannot parse certificate"))?;
+
+    let scheme = x509::signature_scheme_for_algorithm(basic.signature_alg)
+        .ok_or_else(|| bad("unrecognised signature algorithm"))?;
+    if !policy.allows(scheme, chain_algorithms) {
+        return Err(Error::OcspSignatureAlgorithmNotAllowed(scheme));
+    }
+
+    let issuers = issuer_spkis(&cert, intermediates, roots);
+    if issuers.is_empty() {
+        return Err(bad("cannot find certificate issuer"));
+    }
+    let (issuer_spki, single) =
+        find_response(&basic, &cert, &issuers).ok_or_else(|| bad("no response for certificate"))?;
+
+    if x509::verify_signed_data(issuer_spki, scheme, basic.tbs, basic.signature).is_err() {
+        verify_delegated_signature(
+            policy,
+            chain_algorithms,
+            &basic,
+            scheme,
+            issuer_spki,
+            cert.issuer,
+            now,
+        )?;
+    }
+
+    if single.this_update > now {
+        return Err(bad("response is not yet valid"));
+    }
+
+    if let Some(next_update) = single.next_update {
+        if next_update < now {
+            return Err(bad("response has expired"));
+        }
+    }
+
+    debug!("OCSP response asserts status {:?}", single.status);
+    let freshness = OcspFreshness {
+        this_update: UNIX_EPOCH + Duration::from_secs(single.this_update),
+        next_update: single
+            .next_update
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+    };
+    Ok((single.status, freshness))
+}
+
+/// Returns the SignedCertificateTimestampList carried by `response` for
+/// `end_entity`, if any.  The response itself is not validated: SCTs
+/// carry their own signatures.  The issuer is found as for
+/// `verify_response`.
+pub(crate) fn scts<'a>(
+    response: &'a [u8],
+    end_entity: &Certificate,
+    intermediates: &[Certificate],
+    roots: &RootCertStore,
+) -> Option<&'a [u8]> {
+    let basic = BasicResponse::parse(response)?;
+    let cert = CertFields::parse(&end_entity.0)?;
+    let issuers = issuer_spkis(&cert, intermediates, roots);
+    let (_, single) = find_response(&basic, &cert, &issuers)?;
+
+    let mut exts = DerReader::new(single.extensions?);
+    while !exts.at_end() {
+        let mut ext = DerReader::new(exts.read(der::Tag::Sequence as u8)?);
+        let ext_oid = ext.read(der::Tag::OID as u8)?;
+        ext.read_optional(der::Tag::Boolean as u8); // critical
+        let value = ext.read(der::Tag::OctetString as u8)?;
+        if ext_oid == oid::CT_OCSP_SCTS {
+            return DerReader::new(value).read(der::Tag::OctetString as u8);
+        }
+    }
+    None
+}
+
+/// Find the public keys which may be that of the issuer of `cert`:
+/// those of the certificates in `intermediates` with a matching subject,
+/// followed by those of such trust anchors in `roots`.
+///
+/// Names needn't be unique, so keys which didn't sign `cert` are left
+/// out.  If `cert` is signed with an algorithm that can't be checked
+/// here, every key with a matching subject is kept.
+pub(crate) fn issuer_spkis<'a>(
+    cert: &CertFields,
+    intermediates: &'a [Certificate],
+    roots: &'a RootCertStore,
+) -> Vec<&'a [u8]> {
+    let scheme = x509::signature_scheme_for_algorithm(cert.signature_alg);
+    let signed_cert = |spki: &[u8]| match scheme {
+        Some(scheme) => match x509::verify_signed_data(spki, scheme, cert.tbs, cert.signature) {
+            Err(webpki::Error::UnsupportedSignatureAlgorithm) => true,
+            result => result.is_ok(),
+        },
+        None => true,
+    };
+
+    intermediates
+        .iter()
+        .filter_map(|c| CertFields::parse(&c.0))
+        .filter(|c| c.subject == cert.issuer)
+        .map(|c| c.spki)
+        .chain(
+            roots
+                .roots
+                .iter()
+                .map(|ota| ota.to_trust_anchor())
+                .filter(|ta| ta.subject == cert.issuer)
+                .map(|ta| ta.spki),
+        )
+        .filter(|spki| signed_cert(spki))
+        .collect()
+}
+
+/// Find the public key of the issuer of `cert`, as the first of
+/// `issuer_spkis`.
+pub(crate) fn find_issuer_spki<'a>(
+    cert: &CertFields,
+    intermediates: &'a [Certificate],
+    roots: &'a RootCertStore,
+) -> Option<&'a [u8]> {
+    issuer_spkis(cert, intermediates, roots)
+        .into_iter()
+        .next()
+}
+
+/// Find the response in `basic` for `cert`, and which of `issuers` it
+/// names as the issuer.
+fn find_response<'a, 'b>(
+    basic: &'b BasicResponse<'a>,
+    cert: &CertFields,
+    issuers: &[&'b [u8]],
+) -> Option<(&'b [u8], &'b SingleResponse<'a>)> {
+    issuers.iter().find_map(|issuer_spki| {
+        basic
+            .responses
+            .iter()
+            .find(|single| {
+                single
+                    .cert_id
+                    .matches(cert, issuer_spki)
+            })
+            .map(|single| (*issuer_spki, single))
+    })
+}
+
+/// Check that the response is signed with `scheme` by a certificate
+/// embedded in it which the issuer has delegated OCSP signing to.
+///
+/// Every candidate is tried.  If none is acceptable, the error is why
+/// the last was rejected.
+fn verify_delegated_signature(
+    policy: &OcspPolicy,
+    chain_algorithms: SignatureAlgorithms,
+    basic: &BasicResponse,
+    scheme: SignatureScheme,
+    issuer_spki: &[u8],
+    issuer_name: &[u8],
+    now: u64,
+) -> Result<(), Error> {
+    let mut error = bad("response not signed by issuer or an authorised responder");
+
+    for cert_der in &basic.certs {
+        let responder = match CertFields::parse(cert_der) {
+            Some(responder) => responder,
+            None => continue,
+        };
+
+        let delegated = responder
+            .extended_key_usage()
+            .map(|ekus| ekus.contains(&oid::KP_OCSP_SIGNING))
+            .unwrap_or(false);
+        if responder.issuer != issuer_name || !delegated {
+            continue;
+        }
+
+        if let Err(err) =
+            check_delegated_responder(policy, chain_algorithms, &responder, issuer_spki, now)
+        {
+            debug!("skipping delegated OCSP responder: {:?}", err);
+            error = err;
+            continue;
+        }
+
+        match x509::verify_signed_data(responder.spki, scheme, basic.tbs, basic.signature) {
+            Ok(()) => return Ok(()),
+            Err(_) => error = bad("invalid signature"),
+        }
+    }
+
+    Err(error)
+}
+
+/// Check that `responder`, a certificate the issuer has delegated OCSP
+/// signing to, was signed by the issuer and is acceptable now.
+fn check_delegated_responder(
+    policy: &OcspPolicy,
+    chain_algorithms: SignatureAlgorithms,
+    responder: &CertFields,
+    issuer_spki: &[u8],
+    now: u64,
+) -> Result<(), Error> {
+    // The responder certificate is subject to the same algorithm
+    // policy as the response itself.
+    let scheme = x509::signature_scheme_for_algorithm(responder.signature_alg)
+        .ok_or_else(|| bad("unrecognised responder signature algorithm"))?;
+    if !policy.allows(scheme, chain_algorithms) {
+        return Err(Error::OcspSignatureAlgorithmNotAllowed(scheme));
+    }
+
+    x509::verify_signed_data(issuer_spki, scheme, responder.tbs, responder.signature)
+        .map_err(|_| bad("responder certificate not signed by issuer"))?;
+
+    if now < responder.not_before || now > responder.not_after {
+        return Err(bad("responder certificate is not valid now"));
+    }
+
+    // id-pkix-ocsp-nocheck says the responder certificate needn't
+    // be checked for revocation, as we'd have to ask the responder
+    // itself.  Without it we have no way to check.
+    if responder
+        .extension(oid::PKIX_OCSP_NOCHECK)
+        .is_none()
+    {
+        if policy.require_responder_nocheck {
+            return Err(bad("responder certificate lacks id-pkix-ocsp-nocheck"));
+        }
+        debug!("delegated OCSP responder's revocation status is not checked");
+    }
+
+    Ok(())
+}
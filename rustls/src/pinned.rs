@@ -0,0 +1,79 @@
+// Trusting particular self-signed server certificates by their hash.
+
+use crate::client::ServerName;
+use crate::error::{Error, WebPkiOp};
+use crate::key::Certificate;
+use crate::verify::{self, ServerCertVerified, ServerCertVerifier};
+use crate::x509;
+use std::convert::TryFrom;
+use std::time::SystemTime;
+
+/// A `ServerCertVerifier` which trusts only particular certificates,
+/// identified by the SHA-256 hash of their DER encoding.  This is
+/// intended for self-signed certificates, such as when pairing with
+/// an appliance.
+///
+/// No chain is built: a certificate matching a pin is accepted if it
+/// is currently valid and valid for the server's name.  Anything else
+/// is rejected as having an unknown issuer.
+pub struct SelfSignedPinnedVerifier {
+    fingerprints: Vec<[u8; 32]>,
+}
+
+impl SelfSignedPinnedVerifier {
+    /// Constructs a `SelfSignedPinnedVerifier` trusting certificates with
+    /// the given SHA-256 `fingerprints`.
+    pub fn new(fingerprints: &[[u8; 32]]) -> Self {
+        Self {
+            fingerprints: fingerprints.to_vec(),
+        }
+    }
+}
+
+impl ServerCertVerifier for SelfSignedPinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let fingerprint = ring::digest::digest(&ring::digest::SHA256, &end_entity.0);
+        if !self
+            .fingerprints
+            .iter()
+            .any(|pin| pin[..] == *fingerprint.as_ref())
+        {
+            return Err(Error::WebPkiError(
+                webpki::Error::UnknownIssuer,
+                WebPkiOp::ValidateServerCert,
+            ));
+        }
+
+        let cert = webpki::EndEntityCert::try_from(end_entity.0.as_ref())
+            .map_err(|e| Error::WebPkiError(e, WebPkiOp::ParseEndEntity))?;
+        let fields = x509::CertFields::parse(&end_entity.0).ok_or(Error::WebPkiError(
+            webpki::Error::BadDer,
+            WebPkiOp::ParseEndEntity,
+        ))?;
+
+        let now = verify::unix_time_secs(now)?;
+        if now < fields.not_before {
+            return Err(Error::WebPkiError(
+                webpki::Error::CertNotValidYet,
+                WebPkiOp::ValidateServerCert,
+            ));
+        }
+        if now > fields.not_after {
+            return Err(Error::WebPkiError(
+                webpki::Error::CertExpired,
+                WebPkiOp::ValidateServerCert,
+            ));
+        }
+
+        verify::verify_server_name(&cert, end_entity, server_name)
+            .map(|_| ServerCertVerified::assertion())
+    }
+}
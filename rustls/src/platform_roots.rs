@@ -0,0 +1,111 @@
+// Loading the operating system's trust store, without depending on
+// platform-specific crates.
+
+use crate::anchors::RootCertStore;
+use crate::error::Error;
+#[cfg(feature = "logging")]
+use crate::log::debug;
+
+/// Add the platform's trusted roots to `store`.
+///
+/// Returns the number of certificates added, and the number that were
+/// ignored.
+pub(crate) fn load(store: &mut RootCertStore) -> Result<(usize, usize), Error> {
+    let (valid_count, invalid_count) = imp::load(store)?;
+    debug!(
+        "loaded {} platform roots, ignoring {}",
+        valid_count, invalid_count
+    );
+    Ok((valid_count, invalid_count))
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod imp {
+    use super::*;
+    use std::env;
+    use std::fs::File;
+    use std::io::BufReader;
+    use std::path::Path;
+
+    /// Where distributions keep their PEM bundle of trusted roots.
+    const BUNDLE_FILES: &[&str] = &[
+        // Debian, Ubuntu, Arch, Gentoo
+        "/etc/ssl/certs/ca-certificates.crt",
+        // Fedora, RHEL 6
+        "/etc/pki/tls/certs/ca-bundle.crt",
+        // RHEL 7 and later
+        "/etc/pki/ca-trust/extracted/pem/tls-ca-bundle.pem",
+        // openSUSE
+        "/etc/ssl/ca-bundle.pem",
+        // Alpine, OpenBSD
+        "/etc/ssl/cert.pem",
+        // FreeBSD
+        "/usr/local/share/certs/ca-root-nss.crt",
+    ];
+
+    /// Load the bundle named by `SSL_CERT_FILE`, as OpenSSL does, or
+    /// else the first of `BUNDLE_FILES` which exists.
+    pub(super) fn load(store: &mut RootCertStore) -> Result<(usize, usize), Error> {
+        if let Some(path) = env::var_os("SSL_CERT_FILE") {
+            return load_bundle(store, Path::new(&path));
+        }
+
+        match BUNDLE_FILES
+            .iter()
+            .map(Path::new)
+            .find(|path| path.is_file())
+        {
+            Some(path) => load_bundle(store, path),
+            None => Err(Error::General("no platform trust store found".into())),
+        }
+    }
+
+    pub(super) fn load_bundle(
+        store: &mut RootCertStore,
+        path: &Path,
+    ) -> Result<(usize, usize), Error> {
+        debug!("loading platform roots from {:?}", path);
+        let file = File::open(path)
+            .map_err(|err| Error::General(format!("cannot open {}: {}", path.display(), err)))?;
+        store.add_pem_file(&mut BufReader::new(file))
+    }
+}
+
+/// macOS and Windows keep their roots, and the trust settings which
+/// qualify them, behind system APIs we don't bind to.  Exporting the
+/// certificates with a command-line tool would lose those settings, so
+/// these platforms are unsupported rather than approximated.
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+mod imp {
+    use super::*;
+
+    pub(super) fn load(_store: &mut RootCertStore) -> Result<(usize, usize), Error> {
+        Err(Error::General(
+            "no platform trust store on this platform".into(),
+        ))
+    }
+}
+
+#[cfg(all(test, unix, not(target_os = "macos")))]
+mod test {
+    use super::imp::load_bundle;
+    use crate::anchors::RootCertStore;
+    use crate::error::Error;
+    use std::path::Path;
+
+    #[test]
+    fn loads_pem_bundle() {
+        let mut store = RootCertStore::empty();
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../test-ca/verifier/ca.cert");
+        assert_eq!(load_bundle(&mut store, &path), Ok((1, 0)));
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn missing_bundle_is_an_error() {
+        let mut store = RootCertStore::empty();
+        let result = load_bundle(&mut store, Path::new("/nonexistent/bundle.pem"));
+        assert!(matches!(result, Err(Error::General(_))));
+        assert!(store.is_empty());
+    }
+}
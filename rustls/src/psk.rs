@@ -0,0 +1,89 @@
+// Out-of-band pre-shared keys for TLS1.3 (RFC8446 section 2.2), as
+// provisioned into industrial and embedded devices instead of certificates.
+
+use crate::error::Error;
+use crate::msgs::enums::{HashAlgorithm, ProtocolVersion};
+use crate::suites::SupportedCipherSuite;
+use ring::digest;
+use std::fmt;
+
+/// A pre-shared key agreed out-of-band, used to authenticate a TLS1.3
+/// handshake in place of certificates.
+///
+/// Each key is bound to a hash function, and is only used with the
+/// TLS1.3 ciphersuites using that hash.
+#[derive(Clone)]
+pub struct ExternalPsk {
+    identity: Vec<u8>,
+    secret: Vec<u8>,
+    hash: HashAlgorithm,
+}
+
+impl ExternalPsk {
+    /// Make an `ExternalPsk` named `identity`, with key `secret`, for
+    /// use with ciphersuites using `hash`.
+    ///
+    /// `hash` must be SHA256 or SHA384, and neither `identity` nor
+    /// `secret` may be empty.
+    pub fn new(identity: &[u8], secret: &[u8], hash: HashAlgorithm) -> Result<Self, Error> {
+        if identity.is_empty() || identity.len() > 0xffff {
+            return Err(Error::General(
+                "external PSK identity must be 1 to 65535 bytes".to_string(),
+            ));
+        }
+
+        if secret.is_empty() {
+            return Err(Error::General("external PSK secret is empty".to_string()));
+        }
+
+        match hash {
+            HashAlgorithm::SHA256 | HashAlgorithm::SHA384 => {}
+            _ => {
+                return Err(Error::General(format!(
+                    "unsupported hash {:?} for external PSK",
+                    hash
+                )))
+            }
+        }
+
+        Ok(Self {
+            identity: identity.to_vec(),
+            secret: secret.to_vec(),
+            hash,
+        })
+    }
+
+    /// The identity the client sends to name this key.
+    pub fn identity(&self) -> &[u8] {
+        &self.identity
+    }
+
+    /// The hash function this key is used with.
+    pub fn hash(&self) -> HashAlgorithm {
+        self.hash
+    }
+
+    pub(crate) fn secret(&self) -> &[u8] {
+        &self.secret
+    }
+
+    /// Can this key be used with `suite`?
+    pub(crate) fn usable_with(&self, suite: &SupportedCipherSuite) -> bool {
+        let hash = match self.hash {
+            HashAlgorithm::SHA256 => &digest::SHA256,
+            HashAlgorithm::SHA384 => &digest::SHA384,
+            _ => return false,
+        };
+
+        suite.usable_for_version(ProtocolVersion::TLSv1_3) && suite.get_hash() == hash
+    }
+}
+
+impl fmt::Debug for ExternalPsk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExternalPsk")
+            .field("identity", &crate::bs_debug::BsDebug(&self.identity))
+            .field("hash", &self.hash)
+            .finish()
+    }
+}
@@ -0,0 +1,181 @@
+// Authentication of servers by raw public key (RFC7250), as used by
+// constrained devices which have no certificates.
+
+use crate::client::ServerName;
+use crate::error::{Error, WebPkiOp};
+use crate::key::Certificate;
+#[cfg(feature = "logging")]
+use crate::log::debug;
+use crate::msgs::enums::SignatureScheme;
+use crate::msgs::handshake::DigitallySignedStruct;
+use crate::verify::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use crate::x509;
+use ring::io::der;
+use ring::signature;
+use std::time::SystemTime;
+
+/// A `ServerCertVerifier` which accepts servers presenting one of a
+/// list of public keys, offering to receive them as raw public keys
+/// (RFC7250) rather than in certificates.
+///
+/// Keys are given as DER-encoded `SubjectPublicKeyInfo`s.  Servers not
+/// supporting raw public keys may still send a certificate, which is
+/// accepted if its public key is listed: the certificate's issuer,
+/// names and validity period are not checked.  Any other key is
+/// rejected as having an unknown issuer.
+///
+/// The same keys are accepted for every server name.
+pub struct RawPublicKeyVerifier {
+    spkis: Vec<Vec<u8>>,
+}
+
+impl RawPublicKeyVerifier {
+    /// Constructs a `RawPublicKeyVerifier` accepting the DER-encoded
+    /// `SubjectPublicKeyInfo`s in `spkis`.
+    pub fn new(spkis: &[&[u8]]) -> Self {
+        Self {
+            spkis: spkis
+                .iter()
+                .map(|spki| spki.to_vec())
+                .collect(),
+        }
+    }
+
+    fn check_allowed(&self, spki: &[u8]) -> Result<ServerCertVerified, Error> {
+        if self
+            .spkis
+            .iter()
+            .any(|allowed| allowed == spki)
+        {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            debug!("server public key is not in allow-list");
+            Err(Error::WebPkiError(
+                webpki::Error::UnknownIssuer,
+                WebPkiOp::ValidateServerCert,
+            ))
+        }
+    }
+}
+
+impl ServerCertVerifier for RawPublicKeyVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let fields = x509::CertFields::parse(&end_entity.0).ok_or(Error::WebPkiError(
+            webpki::Error::BadDer,
+            WebPkiOp::ParseEndEntity,
+        ))?;
+        let mut spki = fields.spki.to_vec();
+        x509::wrap_in_sequence(&mut spki);
+        self.check_allowed(&spki)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        verify_signature(message, cert, dss, false)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        verify_signature(message, cert, dss, true)
+    }
+
+    fn request_scts(&self) -> bool {
+        false
+    }
+
+    fn offer_raw_public_keys(&self) -> bool {
+        true
+    }
+
+    fn verify_server_raw_public_key(
+        &self,
+        spki: &[u8],
+        _server_name: &ServerName,
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        public_key(spki).ok_or(Error::WebPkiError(
+            webpki::Error::BadDer,
+            WebPkiOp::ParseEndEntity,
+        ))?;
+        self.check_allowed(spki)
+    }
+}
+
+/// The public key bits of `cert`, which is either a DER-encoded
+/// `SubjectPublicKeyInfo` or a certificate containing one.
+fn public_key(cert: &[u8]) -> Option<&[u8]> {
+    let mut reader = x509::DerReader::new(cert);
+    let spki = match reader.read(der::Tag::Sequence as u8) {
+        Some(contents) if reader.at_end() && x509::split_spki(contents).is_some() => contents,
+        _ => x509::CertFields::parse(cert)?.spki,
+    };
+    x509::split_spki(spki).map(|(_, key)| key)
+}
+
+/// Verify `dss` over `message` with the public key in `cert`.  ECDSA
+/// schemes fix the curve only in TLS1.3, and PKCS#1 schemes are only
+/// allowed in TLS1.2.
+fn verify_signature(
+    message: &[u8],
+    cert: &Certificate,
+    dss: &DigitallySignedStruct,
+    tls13: bool,
+) -> Result<HandshakeSignatureValid, Error> {
+    let algs: &[&dyn signature::VerificationAlgorithm] = match (dss.scheme, tls13) {
+        (SignatureScheme::ECDSA_NISTP256_SHA256, true) => &[&signature::ECDSA_P256_SHA256_ASN1],
+        (SignatureScheme::ECDSA_NISTP384_SHA384, true) => &[&signature::ECDSA_P384_SHA384_ASN1],
+        (SignatureScheme::ECDSA_NISTP256_SHA256, false) => &[
+            &signature::ECDSA_P256_SHA256_ASN1,
+            &signature::ECDSA_P384_SHA256_ASN1,
+        ],
+        (SignatureScheme::ECDSA_NISTP384_SHA384, false) => &[
+            &signature::ECDSA_P256_SHA384_ASN1,
+            &signature::ECDSA_P384_SHA384_ASN1,
+        ],
+        (SignatureScheme::ED25519, _) => &[&signature::ED25519],
+        (SignatureScheme::RSA_PSS_SHA256, _) => &[&signature::RSA_PSS_2048_8192_SHA256],
+        (SignatureScheme::RSA_PSS_SHA384, _) => &[&signature::RSA_PSS_2048_8192_SHA384],
+        (SignatureScheme::RSA_PSS_SHA512, _) => &[&signature::RSA_PSS_2048_8192_SHA512],
+        (SignatureScheme::RSA_PKCS1_SHA256, false) => &[&signature::RSA_PKCS1_2048_8192_SHA256],
+        (SignatureScheme::RSA_PKCS1_SHA384, false) => &[&signature::RSA_PKCS1_2048_8192_SHA384],
+        (SignatureScheme::RSA_PKCS1_SHA512, false) => &[&signature::RSA_PKCS1_2048_8192_SHA512],
+        (scheme, _) => {
+            let error_msg = format!("received unadvertised sig scheme {:?}", scheme);
+            return Err(Error::PeerMisbehavedError(error_msg));
+        }
+    };
+
+    let key = public_key(&cert.0).ok_or(Error::WebPkiError(
+        webpki::Error::BadDer,
+        WebPkiOp::ParseEndEntity,
+    ))?;
+
+    if algs.iter().any(|alg| {
+        signature::UnparsedPublicKey::new(*alg, key)
+            .verify(message, &dss.sig.0)
+            .is_ok()
+    }) {
+        Ok(HandshakeSignatureValid::assertion())
+    } else {
+        Err(Error::WebPkiError(
+            webpki::Error::InvalidSignatureForPublicKey,
+            WebPkiOp::VerifySignature,
+        ))
+    }
+}
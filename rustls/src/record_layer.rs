@@ -118,13 +118,16 @@ impl RecordLayer {
     ///
     /// `encr` is a decoded message allegedly received from the peer.
     /// If it can be decrypted, its decryption is returned.  Otherwise,
-    /// an error is returned.
+    /// an error is returned, and the sequence number is unchanged so
+    /// that a skipped message doesn't affect the next.
     pub fn decrypt_incoming(&mut self, encr: OpaqueMessage) -> Result<OpaqueMessage, Error> {
         debug_assert!(self.decrypt_state == DirectionState::Active);
         let seq = self.read_seq;
+        let plain = self
+            .message_decrypter
+            .decrypt(encr, seq)?;
         self.read_seq += 1;
-        self.message_decrypter
-            .decrypt(encr, seq)
+        Ok(plain)
     }
 
     /// Encrypt a TLS message.
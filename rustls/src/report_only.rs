@@ -0,0 +1,151 @@
+// Measuring which server certificates a verifier would reject,
+// without rejecting them.
+
+use crate::client::ServerName;
+use crate::error::Error;
+use crate::key::Certificate;
+#[cfg(feature = "logging")]
+use crate::log::warn;
+use crate::msgs::enums::SignatureScheme;
+use crate::msgs::handshake::DigitallySignedStruct;
+use crate::verify::{
+    HandshakeSignatureValid, ServerCertVerification, ServerCertVerified, ServerCertVerifier,
+};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Called with the server's name, end-entity certificate and error for
+/// each certificate a [`ReportOnlyVerifier`] accepts despite its inner
+/// verifier rejecting it.
+type ReportOnlyObserver = dyn Fn(&ServerName, &Certificate, &Error) + Send + Sync;
+
+/// A wrapper which verifies server certificates with `V`, but accepts
+/// those it rejects after passing the failure to a callback.
+///
+/// This is for measuring what a new trust anchor or policy would break
+/// before enforcing it.  It provides no authentication: any
+/// certificate is accepted.
+///
+/// Handshake signatures are verified by `V`, and failing them still
+/// fails the handshake: they show the server does not have the key for
+/// the certificate it sent.
+///
+/// `V` may complete asynchronously.
+pub struct ReportOnlyVerifier<V> {
+    inner: V,
+    on_failure: Arc<ReportOnlyObserver>,
+}
+
+impl<V> ReportOnlyVerifier<V> {
+    /// Wrap `inner`, calling `on_failure` with the server's name,
+    /// end-entity certificate and the error for each certificate it
+    /// rejects.
+    pub fn new(
+        inner: V,
+        on_failure: impl Fn(&ServerName, &Certificate, &Error) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            inner,
+            on_failure: Arc::new(on_failure),
+        }
+    }
+}
+
+fn report_failure(
+    on_failure: &ReportOnlyObserver,
+    server_name: &ServerName,
+    end_entity: &Certificate,
+    result: Result<ServerCertVerified, Error>,
+) -> Result<ServerCertVerified, Error> {
+    if let Err(err) = result {
+        warn!(
+            "accepting certificate for {:?} in report-only mode: {:?}",
+            server_name, err
+        );
+        on_failure(server_name, end_entity, &err);
+    }
+
+    Ok(ServerCertVerified::assertion())
+}
+
+impl<V: ServerCertVerifier> ServerCertVerifier for ReportOnlyVerifier<V> {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let result = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        );
+        report_failure(&*self.on_failure, server_name, end_entity, result)
+    }
+
+    fn start_verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> ServerCertVerification {
+        match self.inner.start_verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        ) {
+            ServerCertVerification::Complete(result) => ServerCertVerification::Complete(
+                report_failure(&*self.on_failure, server_name, end_entity, result),
+            ),
+            ServerCertVerification::Pending(pending) => {
+                let on_failure = Arc::clone(&self.on_failure);
+                let server_name = server_name.clone();
+                let end_entity = end_entity.clone();
+                pending.map(move |result, _| {
+                    report_failure(&*on_failure, &server_name, &end_entity, result)
+                });
+                ServerCertVerification::Pending(pending)
+            }
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner
+            .verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner
+            .verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+
+    fn request_scts(&self) -> bool {
+        self.inner.request_scts()
+    }
+}
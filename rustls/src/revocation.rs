@@ -0,0 +1,275 @@
+// Revocation checking against a compact set of revoked certificates
+// loaded in advance, in the style of CRLite.
+
+use crate::error::Error;
+use ring::digest;
+
+/// A set of revoked certificates consulted by a [`WebPkiVerifier`]
+/// before it accepts a chain, configured with
+/// [`WebPkiVerifier::with_revocation_filter`].
+///
+/// Certificates are identified by the public key of their issuer and
+/// their serial number, so no network access is needed during the
+/// handshake.  Implementations must be safe to share between threads.
+///
+/// [`WebPkiVerifier`]: crate::WebPkiVerifier
+/// [`WebPkiVerifier::with_revocation_filter`]: crate::WebPkiVerifier::with_revocation_filter
+pub trait RevocationFilter: Send + Sync {
+    /// Returns `true` if the certificate with `serial` (the big-endian
+    /// contents of its `INTEGER`) issued by the holder of
+    /// `issuer_spki` (a DER-encoded `SubjectPublicKeyInfo`) is revoked.
+    fn is_revoked(&self, issuer_spki: &[u8], serial: &[u8]) -> bool;
+}
+
+/// A `RevocationFilter` using a cascade of Bloom filters, as CRLite
+/// does.
+///
+/// The first filter contains the revoked certificates; the second, the
+/// valid certificates the first wrongly contains; the third, the revoked
+/// certificates the second wrongly contains, and so on until there are
+/// no false positives.  The answer is exact for every certificate the
+/// cascade was built from, and arbitrary for others: it must be built
+/// from every certificate which may be presented, such as those from
+/// Certificate Transparency logs.
+///
+/// A cascade covering millions of certificates is a few megabytes.
+/// It's built ahead of time with [`RevocationFilterCascade::build`],
+/// distributed using [`RevocationFilterCascade::to_bytes`], and loaded
+/// at startup with [`RevocationFilterCascade::from_bytes`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RevocationFilterCascade {
+    layers: Vec<BloomFilter>,
+}
+
+/// The number of filter bits per certificate in each layer, giving
+/// a false positive rate of about 1%.
+const BITS_PER_ENTRY: usize = 10;
+
+/// The number of hash functions used by each layer.
+const HASH_COUNT: u8 = 7;
+
+/// Cascades are deeper than this only if something's gone wrong, such
+/// as a certificate being both revoked and valid.
+const MAX_LAYERS: usize = 32;
+
+impl RevocationFilterCascade {
+    /// Build a cascade answering that each of `revoked` is revoked and
+    /// each of `valid` is not.  Each certificate is given as the
+    /// DER-encoded `SubjectPublicKeyInfo` of its issuer and its serial
+    /// number, as for [`RevocationFilter::is_revoked`].
+    ///
+    /// Fails with `Error::General` if a certificate is in both sets.
+    pub fn build(revoked: &[(&[u8], &[u8])], valid: &[(&[u8], &[u8])]) -> Result<Self, Error> {
+        let mut include: Vec<Vec<u8>> = revoked
+            .iter()
+            .map(|(spki, serial)| key(spki, serial))
+            .collect();
+        let mut exclude: Vec<Vec<u8>> = valid
+            .iter()
+            .map(|(spki, serial)| key(spki, serial))
+            .collect();
+        let mut layers = Vec::new();
+
+        while !include.is_empty() {
+            if layers.len() == MAX_LAYERS {
+                return Err(Error::General(
+                    "certificate is both revoked and valid".into(),
+                ));
+            }
+
+            let layer = BloomFilter::new(layers.len() as u8, &include);
+            let false_positives = exclude
+                .into_iter()
+                .filter(|key| layer.contains(key))
+                .collect();
+            layers.push(layer);
+            exclude = include;
+            include = false_positives;
+        }
+
+        Ok(Self { layers })
+    }
+
+    /// Decode a cascade encoded by [`RevocationFilterCascade::to_bytes`].
+    ///
+    /// Fails with `Error::General` if `bytes` is malformed.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let malformed = || Error::General("malformed revocation filter".into());
+        let (&count, mut rest) = bytes
+            .split_first()
+            .ok_or_else(malformed)?;
+
+        let mut layers = Vec::new();
+        for level in 0..count {
+            if rest.len() < 5 {
+                return Err(malformed());
+            }
+            let bit_count = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            let hash_count = rest[4];
+            let byte_count = (bit_count + 7) / 8;
+            let bits = rest
+                .get(5..5 + byte_count)
+                .ok_or_else(malformed)?;
+            if bit_count == 0 || hash_count == 0 {
+                return Err(malformed());
+            }
+
+            layers.push(BloomFilter {
+                level,
+                bit_count,
+                hash_count,
+                bits: bits.to_vec(),
+            });
+            rest = &rest[5 + byte_count..];
+        }
+
+        if !rest.is_empty() {
+            return Err(malformed());
+        }
+
+        Ok(Self { layers })
+    }
+
+    /// Encode this cascade, for loading with
+    /// [`RevocationFilterCascade::from_bytes`].
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = vec![self.layers.len() as u8];
+        for layer in &self.layers {
+            out.extend_from_slice(&(layer.bit_count as u32).to_be_bytes());
+            out.push(layer.hash_count);
+            out.extend_from_slice(&layer.bits);
+        }
+        out
+    }
+}
+
+impl RevocationFilter for RevocationFilterCascade {
+    fn is_revoked(&self, issuer_spki: &[u8], serial: &[u8]) -> bool {
+        let key = key(issuer_spki, serial);
+        match self
+            .layers
+            .iter()
+            .position(|layer| !layer.contains(&key))
+        {
+            Some(depth) => depth % 2 == 1,
+            None => self.layers.len() % 2 == 1,
+        }
+    }
+}
+
+/// The key identifying a certificate in a cascade: the SHA-256 hash of
+/// its issuer's public key, followed by its serial number.
+fn key(issuer_spki: &[u8], serial: &[u8]) -> Vec<u8> {
+    let mut key = digest::digest(&digest::SHA256, issuer_spki)
+        .as_ref()
+        .to_vec();
+    key.extend_from_slice(serial);
+    key
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct BloomFilter {
+    level: u8,
+    bit_count: usize,
+    hash_count: u8,
+    bits: Vec<u8>,
+}
+
+impl BloomFilter {
+    fn new(level: u8, keys: &[Vec<u8>]) -> Self {
+        let bit_count = (keys.len() * BITS_PER_ENTRY).max(8);
+        let mut filter = Self {
+            level,
+            bit_count,
+            hash_count: HASH_COUNT,
+            bits: vec![0; (bit_count + 7) / 8],
+        };
+
+        for key in keys {
+            let indices: Vec<usize> = filter.bit_indices(key).collect();
+            for bit in indices {
+                filter.bits[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+
+        filter
+    }
+
+    fn contains(&self, key: &[u8]) -> bool {
+        self.bit_indices(key)
+            .all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    /// The bits set for `key`.  Each layer hashes differently, so a
+    /// false positive in one layer is unlikely to be one in the next.
+    fn bit_indices<'a>(&'a self, key: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+        (0..self.hash_count).map(move |i| {
+            let mut ctx = digest::Context::new(&digest::SHA256);
+            ctx.update(&[self.level, i]);
+            ctx.update(key);
+            let hash = ctx.finish();
+            let mut prefix = [0u8; 8];
+            prefix.copy_from_slice(&hash.as_ref()[..8]);
+            (u64::from_be_bytes(prefix) % self.bit_count as u64) as usize
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn certs(issuer: &'static [u8], count: u32) -> Vec<(&'static [u8], Vec<u8>)> {
+        (0..count)
+            .map(|serial| (issuer, serial.to_be_bytes().to_vec()))
+            .collect()
+    }
+
+    fn refs<'a>(certs: &'a [(&'static [u8], Vec<u8>)]) -> Vec<(&'a [u8], &'a [u8])> {
+        certs
+            .iter()
+            .map(|(spki, serial)| (*spki, serial.as_slice()))
+            .collect()
+    }
+
+    #[test]
+    fn cascade_is_exact_for_its_certificates() {
+        let revoked = certs(b"issuer a", 200);
+        let valid = certs(b"issuer b", 5000);
+        let cascade = RevocationFilterCascade::build(&refs(&revoked), &refs(&valid)).unwrap();
+
+        assert!(cascade.layers.len() > 1);
+        for (spki, serial) in &revoked {
+            assert!(cascade.is_revoked(spki, serial));
+        }
+        for (spki, serial) in &valid {
+            assert!(!cascade.is_revoked(spki, serial));
+        }
+    }
+
+    #[test]
+    fn cascade_roundtrips() {
+        let revoked = certs(b"issuer", 50);
+        let valid = certs(b"other issuer", 500);
+        let cascade = RevocationFilterCascade::build(&refs(&revoked), &refs(&valid)).unwrap();
+
+        let bytes = cascade.to_bytes();
+        assert_eq!(RevocationFilterCascade::from_bytes(&bytes), Ok(cascade));
+        assert!(RevocationFilterCascade::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(RevocationFilterCascade::from_bytes(&[bytes.as_slice(), &[0]].concat()).is_err());
+        assert!(RevocationFilterCascade::from_bytes(&[]).is_err());
+    }
+
+    #[test]
+    fn empty_cascade_revokes_nothing() {
+        let cascade = RevocationFilterCascade::build(&[], &[(b"issuer", b"\x01")]).unwrap();
+        assert_eq!(cascade.to_bytes(), vec![0]);
+        assert!(!cascade.is_revoked(b"issuer", b"\x01"));
+    }
+
+    #[test]
+    fn cascade_rejects_contradictions() {
+        let both: &[(&[u8], &[u8])] = &[(b"issuer", b"\x01")];
+        assert!(RevocationFilterCascade::build(both, both).is_err());
+    }
+}
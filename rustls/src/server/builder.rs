@@ -2,9 +2,11 @@ use crate::error::Error;
 use crate::key;
 use crate::keylog::NoKeyLog;
 use crate::kx::SupportedKxGroup;
+use crate::msgs::enums::PSKKeyExchangeMode;
 use crate::server::handy;
 use crate::server::{ResolvesServerCert, ServerConfig};
 use crate::suites::SupportedCipherSuite;
+use crate::time_provider::DefaultTimeProvider;
 use crate::verify;
 use crate::versions;
 
@@ -139,13 +141,18 @@ impl ServerConfigBuilderWithClientAuth {
             kx_groups: self.kx_groups,
             verifier: self.verifier,
             cert_resolver,
+            psk_resolver: None,
+            psk_identity_hint: Vec::new(),
+            psk_key_exchange_modes: vec![PSKKeyExchangeMode::PSK_DHE_KE],
             ignore_client_order: false,
             max_fragment_size: None,
+            record_size_limit: None,
             session_storage: handy::ServerSessionMemoryCache::new(256),
             ticketer: Arc::new(handy::NeverProducesTickets {}),
             alpn_protocols: Vec::new(),
             versions: self.versions,
             key_log: Arc::new(NoKeyLog {}),
+            time_provider: Arc::new(DefaultTimeProvider),
             #[cfg(feature = "quic")]
             max_early_data_size: 0,
         }
@@ -4,6 +4,7 @@ use crate::limited_cache;
 use crate::server;
 use crate::server::ClientHello;
 use crate::sign;
+use crate::x509;
 
 use parking_lot::{Mutex, RwLock};
 use std::collections;
@@ -166,6 +167,52 @@ impl server::ResolvesServerCert for ResolvesServerCertUsingSni {
     }
 }
 
+/// Something that resolves to one of several cert chains/keys, each
+/// leading to a different CA, based on the CAs the client says it
+/// trusts (via the certificate_authorities extension).
+///
+/// The first chain added is used if the client doesn't say, or
+/// trusts none of the CAs.
+pub struct ResolvesServerCertUsingAuthorities {
+    by_authority: Vec<(Vec<u8>, Arc<sign::CertifiedKey>)>,
+}
+
+impl ResolvesServerCertUsingAuthorities {
+    /// Create a new and empty (i.e., knows no certificates) resolver.
+    pub fn new() -> Self {
+        Self {
+            by_authority: Vec::new(),
+        }
+    }
+
+    /// Add a new `sign::CertifiedKey`, whose chain leads to the
+    /// DER-encoded CA certificate `root`.
+    ///
+    /// This function fails if `root` is syntactically faulty.
+    pub fn add(&mut self, root: &key::Certificate, ck: sign::CertifiedKey) -> Result<(), Error> {
+        let anchor = webpki::TrustAnchor::try_from_cert_der(&root.0)
+            .map_err(|_| Error::General("invalid CA certificate".into()))?;
+        let mut name = anchor.subject.to_vec();
+        x509::wrap_in_sequence(&mut name);
+        self.by_authority
+            .push((name, Arc::new(ck)));
+        Ok(())
+    }
+}
+
+impl server::ResolvesServerCert for ResolvesServerCertUsingAuthorities {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<sign::CertifiedKey>> {
+        let trusted = client_hello
+            .certificate_authorities()
+            .unwrap_or(&[]);
+        self.by_authority
+            .iter()
+            .find(|(name, _)| trusted.contains(&name.as_slice()))
+            .or_else(|| self.by_authority.first())
+            .map(|(_, ck)| Arc::clone(ck))
+    }
+}
+
 /// Works almost like `ResolvesServerCertUsingSni` except it allows runtime
 /// modifications of its inner `sign::CertifiedKey` store using interior
 /// mutability.
@@ -301,7 +348,7 @@ mod test {
         let rscsni = ResolvesServerCertUsingSni::new();
         assert!(
             rscsni
-                .resolve(ClientHello::new(None, &[], None))
+                .resolve(ClientHello::new(None, &[], None, None))
                 .is_none()
         );
     }
@@ -312,7 +359,18 @@ mod test {
         let name = webpki::DnsNameRef::try_from_ascii_str("hello.com").unwrap();
         assert!(
             rscsni
-                .resolve(ClientHello::new(Some(name), &[], None))
+                .resolve(ClientHello::new(Some(name), &[], None, None))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_resolvesservercertusingauthorities_handles_no_certificates() {
+        let rscca = ResolvesServerCertUsingAuthorities::new();
+        let authorities: &[&[u8]] = &[&[0x30, 0x00]];
+        assert!(
+            rscca
+                .resolve(ClientHello::new(None, &[], None, Some(authorities)))
                 .is_none()
         );
     }
@@ -1,12 +1,12 @@
 #[cfg(feature = "quic")]
 use crate::conn::Protocol;
-use crate::conn::{ConnectionCommon, ConnectionRandoms};
+use crate::conn::{ConnectionCommon, ConnectionRandoms, MIN_RECORD_SIZE_LIMIT};
 use crate::error::Error;
 use crate::hash_hs::HandshakeHash;
 #[cfg(feature = "logging")]
 use crate::log::{debug, trace};
 use crate::msgs::enums::{AlertDescription, ExtensionType};
-use crate::msgs::enums::{CipherSuite, Compression};
+use crate::msgs::enums::{CipherSuite, Compression, MaxFragmentLength};
 use crate::msgs::enums::{ContentType, HandshakeType, ProtocolVersion};
 use crate::msgs::handshake::SessionID;
 use crate::msgs::handshake::{ClientHelloPayload, ServerExtension};
@@ -16,6 +16,7 @@ use crate::msgs::message::{Message, MessagePayload};
 use crate::msgs::persist;
 use crate::server::{ClientHello, ServerConfig};
 use crate::suites;
+use crate::verify::ClientCertVerifyContext;
 use crate::SupportedCipherSuite;
 
 use crate::server::common::ActiveCertifiedKey;
@@ -61,6 +62,22 @@ pub(super) struct ServerContext<'a> {
     pub(super) data: &'a mut ServerConnectionData,
 }
 
+impl ServerContext<'_> {
+    /// Details of this handshake, for the client certificate verifier.
+    pub(super) fn client_cert_verify_context(
+        &self,
+        protocol_version: ProtocolVersion,
+    ) -> ClientCertVerifyContext {
+        ClientCertVerifyContext {
+            sni: self.data.get_sni(),
+            alpn_protocol: self.common.alpn_protocol.as_deref(),
+            protocol_version,
+            peer_addr: self.data.peer_addr,
+            user_data: self.data.verifier_data.as_deref(),
+        }
+    }
+}
+
 pub fn incompatible(common: &mut ConnectionCommon, why: &str) -> Error {
     common.send_fatal_alert(AlertDescription::HandshakeFailure);
     Error::PeerIncompatibleError(why.to_string())
@@ -185,6 +202,39 @@ impl ExtensionProcessing {
             }
         }
 
+        // Record size limits.  QUIC has no records to limit.
+        let record_size_limit = hello.get_record_size_limit();
+        if let Some(limit) = record_size_limit {
+            if usize::from(limit) < MIN_RECORD_SIZE_LIMIT {
+                return Err(cx
+                    .common
+                    .illegal_param("client sent too small record size limit"));
+            }
+
+            if !cx.common.is_quic() {
+                self.exts
+                    .push(ServerExtension::RecordSizeLimit(
+                        cx.common.advertised_record_size_limit(),
+                    ));
+                cx.common.set_record_size_limits(limit);
+            }
+        }
+
+        // A max fragment length is ignored in favour of a record size limit.
+        if let Some(len) = hello.get_max_fragment_length() {
+            if let MaxFragmentLength::Unknown(_) = len {
+                return Err(cx
+                    .common
+                    .illegal_param("client sent unknown max fragment length"));
+            }
+
+            if !cx.common.is_quic() && record_size_limit.is_none() {
+                self.exts
+                    .push(ServerExtension::MaxFragmentLength(len));
+                cx.common.set_max_fragment_length(len);
+            }
+        }
+
         let for_resume = resumedata.is_some();
         // SNI
         if !for_resume && hello.get_sni_extension().is_some() {
@@ -435,6 +485,15 @@ impl State for ExpectClientHello {
             .get_alpn_extension()
             .map(|protos| protos.to_slices());
 
+        let certificate_authorities = client_hello
+            .get_certificate_authorities_extension()
+            .map(|names| {
+                names
+                    .iter()
+                    .map(|name| name.0.as_slice())
+                    .collect::<Vec<_>>()
+            });
+
         // Choose a certificate.
         let certkey = {
             let sni_ref = sni
@@ -445,40 +504,90 @@ impl State for ExpectClientHello {
             trace!("alpn protocols {:?}", alpn_protocols);
 
             let alpn_slices = alpn_protocols.as_deref();
-            let client_hello = ClientHello::new(sni_ref, &sigschemes_ext, alpn_slices);
+            let client_hello = ClientHello::new(
+                sni_ref,
+                &sigschemes_ext,
+                alpn_slices,
+                certificate_authorities.as_deref(),
+            );
 
             let certkey = self
                 .config
                 .cert_resolver
                 .resolve(client_hello);
-            certkey.ok_or_else(|| {
-                cx.common
-                    .send_fatal_alert(AlertDescription::AccessDenied);
-                Error::General("no server certificate chain resolved".to_string())
-            })?
+
+            // The client might authenticate us with an external PSK
+            // instead: we find out once the ciphersuite is chosen.
+            match certkey {
+                Some(certkey) => Some(certkey),
+                None if self.config.psk_resolver.is_some() => None,
+                None => {
+                    cx.common
+                        .send_fatal_alert(AlertDescription::AccessDenied);
+                    return Err(Error::General(
+                        "no server certificate chain resolved".to_string(),
+                    ));
+                }
+            }
         };
-        let certkey = ActiveCertifiedKey::from_certified_key(&certkey);
+        let certkey = certkey
+            .as_deref()
+            .map(ActiveCertifiedKey::from_certified_key);
 
         // Reduce our supported ciphersuites by the certificate.
-        // (no-op for TLS1.3)
-        let suitable_suites =
-            suites::reduce_given_sigalg(&self.config.cipher_suites, certkey.get_key().algorithm());
+        // (no-op for TLS1.3)  The TLS1.2 PSK suites don't need one, but
+        // do need somewhere to look up keys.
+        let suitable_suites: Vec<_> = self
+            .config
+            .cipher_suites
+            .iter()
+            .copied()
+            .filter(|suite| match &certkey {
+                _ if suite.uses_psk() => self.config.psk_resolver.is_some(),
+                Some(certkey) => suite.usable_for_sigalg(certkey.get_key().algorithm()),
+                None => suite.usable_for_version(ProtocolVersion::TLSv1_3),
+            })
+            .collect();
 
         // And version
         let suitable_suites = suites::reduce_given_version(&suitable_suites, version);
 
-        let suite = if self.config.ignore_client_order {
-            suites::choose_ciphersuite_preferring_server(
-                &client_hello.cipher_suites,
-                &suitable_suites,
-            )
-        } else {
-            suites::choose_ciphersuite_preferring_client(
-                &client_hello.cipher_suites,
-                &suitable_suites,
-            )
-        }
-        .ok_or_else(|| incompatible(&mut cx.common, "no ciphersuites in common"))?;
+        let ignore_client_order = self.config.ignore_client_order;
+        let choose_suite = |suitable_suites: &[&'static SupportedCipherSuite]| {
+            if ignore_client_order {
+                suites::choose_ciphersuite_preferring_server(
+                    &client_hello.cipher_suites,
+                    suitable_suites,
+                )
+            } else {
+                suites::choose_ciphersuite_preferring_client(
+                    &client_hello.cipher_suites,
+                    suitable_suites,
+                )
+            }
+        };
+
+        // An external PSK is only usable with suites using its hash,
+        // so prefer those if the client offers one we know.
+        let psk_suite = match (&self.config.psk_resolver, client_hello.get_psk()) {
+            (Some(resolver), Some(psk_offer)) if version == ProtocolVersion::TLSv1_3 => psk_offer
+                .identities
+                .iter()
+                .find_map(|psk_id| resolver.resolve(&psk_id.identity.0))
+                .and_then(|psk| {
+                    let psk_suites: Vec<_> = suitable_suites
+                        .iter()
+                        .copied()
+                        .filter(|suite| psk.usable_with(suite))
+                        .collect();
+                    choose_suite(&psk_suites)
+                }),
+            _ => None,
+        };
+
+        let suite = psk_suite
+            .or_else(|| choose_suite(&suitable_suites))
+            .ok_or_else(|| incompatible(&mut cx.common, "no ciphersuites in common"))?;
 
         debug!("decided upon suite {:?}", suite);
         cx.common.suite = Some(suite);
@@ -1,3 +1,4 @@
+use crate::builder::ConfigBuilderFor;
 use crate::conn::{Connection, ConnectionCommon, IoState, PlaintextSink, Reader, Writer};
 use crate::error::Error;
 use crate::key;
@@ -5,17 +6,22 @@ use crate::keylog::KeyLog;
 use crate::kx::SupportedKxGroup;
 #[cfg(feature = "quic")]
 use crate::msgs::enums::AlertDescription;
+use crate::msgs::enums::PSKKeyExchangeMode;
 use crate::msgs::enums::ProtocolVersion;
 use crate::msgs::enums::SignatureScheme;
 use crate::msgs::handshake::ServerExtension;
+use crate::psk::ExternalPsk;
 use crate::sign;
 use crate::suites::SupportedCipherSuite;
+use crate::time_provider::TimeProvider;
 use crate::verify;
 #[cfg(feature = "quic")]
 use crate::{conn::Protocol, quic};
 
+use std::any::Any;
 use std::fmt;
 use std::io::{self, IoSlice};
+use std::net::SocketAddr;
 use std::sync::Arc;
 
 #[macro_use]
@@ -102,11 +108,22 @@ pub trait ResolvesServerCert: Send + Sync {
     fn resolve(&self, client_hello: ClientHello) -> Option<Arc<sign::CertifiedKey>>;
 }
 
+/// How to find the external pre-shared key a client names in a
+/// TLS1.3 ClientHello, or a TLS1.2 ECDHE_PSK ClientKeyExchange.
+pub trait ResolvesServerPsk: Send + Sync {
+    /// Return the key named `identity`, or None if there is no such key.
+    ///
+    /// `identity` is attacker controlled, and this is called for identities
+    /// which are not valid tickets before the client has authenticated.
+    fn resolve(&self, identity: &[u8]) -> Option<ExternalPsk>;
+}
+
 /// A struct representing the received Client Hello
 pub struct ClientHello<'a> {
     server_name: Option<webpki::DnsNameRef<'a>>,
     signature_schemes: &'a [SignatureScheme],
     alpn: Option<&'a [&'a [u8]]>,
+    certificate_authorities: Option<&'a [&'a [u8]]>,
 }
 
 impl<'a> ClientHello<'a> {
@@ -115,11 +132,13 @@ impl<'a> ClientHello<'a> {
         server_name: Option<webpki::DnsNameRef<'a>>,
         signature_schemes: &'a [SignatureScheme],
         alpn: Option<&'a [&'a [u8]]>,
+        certificate_authorities: Option<&'a [&'a [u8]]>,
     ) -> Self {
         ClientHello {
             server_name,
             signature_schemes,
             alpn,
+            certificate_authorities,
         }
     }
 
@@ -143,6 +162,16 @@ impl<'a> ClientHello<'a> {
     pub fn alpn(&self) -> Option<&'a [&'a [u8]]> {
         self.alpn
     }
+
+    /// Get the DER encodings of the distinguished names of the CAs the
+    /// client trusts, from its certificate_authorities extension
+    /// (RFC8446 section 4.2.4).  A certificate chain leading to one of
+    /// these is the most likely to be accepted.
+    ///
+    /// Returns `None` if the client did not include this extension.
+    pub fn certificate_authorities(&self) -> Option<&'a [&'a [u8]]> {
+        self.certificate_authorities
+    }
 }
 
 /// Common configuration for a set of server sessions.
@@ -176,6 +205,16 @@ pub struct ServerConfig {
     /// Setting this value to the TCP MSS may improve latency for stream-y workloads.
     pub max_fragment_size: Option<usize>,
 
+    /// The largest record we'll accept from clients which send the
+    /// record_size_limit extension (RFC8449), which we always answer.
+    /// If `None`, we advertise the largest the protocol allows, and so
+    /// only limit the records we send to the client's limit.
+    ///
+    /// In TLS1.3 this counts the content type byte, so records hold one
+    /// byte less.  Values outside 64 to 16385 are reported as errors from
+    /// ServerConnection::new.  The default is `None`.
+    pub record_size_limit: Option<usize>,
+
     /// How to store client sessions.
     pub session_storage: Arc<dyn StoresServerSessions + Send + Sync>,
 
@@ -183,8 +222,31 @@ pub struct ServerConfig {
     pub ticketer: Arc<dyn ProducesTickets>,
 
     /// How to choose a server cert and key.
+    ///
+    /// With a `psk_resolver`, handshakes may go ahead without a
+    /// certificate: they fail later unless the client authenticates
+    /// with an external PSK.
     pub cert_resolver: Arc<dyn ResolvesServerCert>,
 
+    /// How to find external pre-shared keys named by clients.  If
+    /// None, the default, clients may only use PSKs for resumption.
+    ///
+    /// This is also needed for the TLS1.2 ECDHE_PSK ciphersuites to be
+    /// chosen, if they are among `cipher_suites`.
+    pub psk_resolver: Option<Arc<dyn ResolvesServerPsk>>,
+
+    /// The PSK identity hint we send in TLS1.2 ECDHE_PSK handshakes, to
+    /// help the client choose a key.  The default is empty, meaning no
+    /// hint.  This has no effect in TLS1.3, which has no such hint.
+    pub psk_identity_hint: Vec<u8>,
+
+    /// The ways we allow PSKs to be used in TLS1.3, in preference order.
+    /// These apply to both resumption and external PSKs.
+    ///
+    /// The default is `PSK_DHE_KE` only.  `PSK_KE` does without a key
+    /// exchange, and so gives up forward secrecy.
+    pub psk_key_exchange_modes: Vec<PSKKeyExchangeMode>,
+
     /// Protocol names we support, most preferred first.
     /// If empty we don't do ALPN at all.
     pub alpn_protocols: Vec<Vec<u8>>,
@@ -200,6 +262,10 @@ pub struct ServerConfig {
     /// does nothing.
     pub key_log: Arc<dyn KeyLog>,
 
+    /// Where to get the current time, for verifying certificates
+    /// and expiring sessions.  The default reads the system clock.
+    pub time_provider: Arc<dyn TimeProvider>,
+
     /// Amount of early data to accept; 0 to disable.
     #[cfg(feature = "quic")] // TLS support unimplemented
     #[doc(hidden)]
@@ -207,6 +273,11 @@ pub struct ServerConfig {
 }
 
 impl ServerConfig {
+    /// Start building a `ServerConfig`: see [`ConfigBuilderFor`].
+    pub fn builder() -> ConfigBuilderFor<ServerConfig> {
+        ConfigBuilderFor::new()
+    }
+
     #[doc(hidden)]
     /// We support a given TLS version if it's quoted in the configured
     /// versions *and* at least one ciphersuite for this version is
@@ -242,7 +313,12 @@ impl ServerConnection {
         extra_exts: Vec<ServerExtension>,
     ) -> Result<Self, Error> {
         Ok(ServerConnection {
-            common: ConnectionCommon::new(config.max_fragment_size, false)?,
+            common: ConnectionCommon::new(
+                config.max_fragment_size,
+                None,
+                config.record_size_limit,
+                false,
+            )?,
             state: Some(Box::new(hs::ExpectClientHello::new(config, extra_exts))),
             data: ServerConnectionData::default(),
         })
@@ -282,6 +358,14 @@ impl ServerConnection {
             .map(|x| &x[..])
     }
 
+    /// The identity of the external PSK the client authenticated with
+    /// in this handshake, if any: see [`ServerConfig::psk_resolver`].
+    pub fn psk_identity(&self) -> Option<&[u8]> {
+        self.data
+            .psk_identity
+            .as_deref()
+    }
+
     /// Set the resumption data to embed in future resumption tickets supplied to the client.
     ///
     /// Defaults to the empty byte string. Must be less than 2^15 bytes to allow room for other
@@ -295,6 +379,27 @@ impl ServerConnection {
         self.data.resumption_data = data.into();
     }
 
+    /// Record the client's address, for the client certificate verifier.
+    ///
+    /// This is passed to [`ClientCertVerifier::verify_client_cert_in_context`]
+    /// and must be set before the client's certificate is received.
+    ///
+    /// [`ClientCertVerifier::verify_client_cert_in_context`]: crate::ClientCertVerifier::verify_client_cert_in_context
+    pub fn set_peer_addr(&mut self, addr: SocketAddr) {
+        self.data.peer_addr = Some(addr);
+    }
+
+    /// Attach `data` to this connection, for the client certificate
+    /// verifier.
+    ///
+    /// This is passed to [`ClientCertVerifier::verify_client_cert_in_context`]
+    /// and must be set before the client's certificate is received.
+    ///
+    /// [`ClientCertVerifier::verify_client_cert_in_context`]: crate::ClientCertVerifier::verify_client_cert_in_context
+    pub fn set_verifier_data(&mut self, data: Arc<dyn Any + Send + Sync>) {
+        self.data.verifier_data = Some(data);
+    }
+
     /// Explicitly discard early data, notifying the client
     ///
     /// Useful if invariants encoded in `received_resumption_data()` cannot be respected.
@@ -427,8 +532,11 @@ struct ServerConnectionData {
     received_resumption_data: Option<Vec<u8>>,
     resumption_data: Vec<u8>,
     client_cert_chain: Option<Vec<key::Certificate>>,
+    psk_identity: Option<Vec<u8>>,
     /// Whether to reject early data even if it would otherwise be accepted
     reject_early_data: bool,
+    peer_addr: Option<SocketAddr>,
+    verifier_data: Option<Arc<dyn Any + Send + Sync>>,
 }
 
 impl ServerConnectionData {
@@ -5,11 +5,12 @@ use crate::hash_hs::HandshakeHash;
 use crate::key::Certificate;
 #[cfg(feature = "logging")]
 use crate::log::{debug, trace};
-use crate::msgs::base::Payload;
+use crate::msgs::base::{Payload, PayloadU16};
 use crate::msgs::ccs::ChangeCipherSpecPayload;
 use crate::msgs::codec::Codec;
 use crate::msgs::enums::{AlertDescription, ContentType, HandshakeType, ProtocolVersion};
-use crate::msgs::handshake::{ClientECDHParams, HandshakeMessagePayload, HandshakePayload};
+use crate::msgs::handshake::{ClientECDHEPSKParams, ClientECDHParams};
+use crate::msgs::handshake::{HandshakeMessagePayload, HandshakePayload};
 use crate::msgs::handshake::{NewSessionTicketPayload, SessionID};
 use crate::msgs::message::{Message, MessagePayload};
 use crate::msgs::persist;
@@ -32,7 +33,7 @@ mod client_hello {
     use crate::msgs::enums::{ClientCertificateType, Compression, SignatureScheme};
     use crate::msgs::handshake::{CertificateRequestPayload, Random};
     use crate::msgs::handshake::{
-        CertificateStatus, DigitallySignedStruct, ECDHEServerKeyExchange,
+        CertificateStatus, DigitallySignedStruct, ECDHEPSKServerKeyExchange, ECDHEServerKeyExchange,
     };
     use crate::msgs::handshake::{ClientExtension, SessionID};
     use crate::msgs::handshake::{ClientHelloPayload, ServerHelloPayload};
@@ -58,7 +59,7 @@ mod client_hello {
         pub(in crate::server) fn handle_client_hello(
             mut self,
             cx: &mut ServerContext<'_>,
-            server_key: ActiveCertifiedKey,
+            server_key: Option<ActiveCertifiedKey>,
             chm: &Message,
             client_hello: &ClientHelloPayload,
             sigschemes_ext: Vec<SignatureScheme>,
@@ -150,12 +151,16 @@ mod client_hello {
                 return self.start_resumption(cx, client_hello, &client_hello.session_id, data);
             }
 
+            // The ECDHE_PSK suites authenticate with a PSK instead of our
+            // certificate; all others were chosen because we have one.
+            let server_key = server_key.filter(|_| !self.suite.supported_suite().uses_psk());
+
             // Now we have chosen a ciphersuite, we can make kx decisions.
             let sigschemes = self
                 .suite
                 .resolve_sig_schemes(&sigschemes_ext);
 
-            if sigschemes.is_empty() {
+            if server_key.is_some() && sigschemes.is_empty() {
                 return Err(hs::incompatible(
                     &mut cx.common,
                     "no overlapping sigschemes",
@@ -178,8 +183,10 @@ mod client_hello {
 
             debug_assert_eq!(ecpoint, ECPointFormat::Uncompressed);
 
-            let (mut ocsp_response, mut sct_list) =
-                (server_key.get_ocsp(), server_key.get_sct_list());
+            let (mut ocsp_response, mut sct_list) = match &server_key {
+                Some(server_key) => (server_key.get_ocsp(), server_key.get_sct_list()),
+                None => (None, None),
+            };
 
             // If we're not offered a ticket or a potential connection ID,
             // allocate a connection ID.
@@ -201,19 +208,31 @@ mod client_hello {
                 &self.randoms,
                 self.extra_exts,
             )?;
-            emit_certificate(&mut self.transcript, &mut cx.common, server_key.get_cert());
-            if let Some(ocsp_response) = ocsp_response {
-                emit_cert_status(&mut self.transcript, &mut cx.common, ocsp_response);
-            }
-            let server_kx = emit_server_kx(
-                &mut self.transcript,
-                &mut cx.common,
-                sigschemes,
-                group,
-                server_key.get_key(),
-                &self.randoms,
-            )?;
-            let doing_client_auth = emit_certificate_req(&self.config, &mut self.transcript, cx)?;
+            let server_kx = match &server_key {
+                Some(server_key) => {
+                    emit_certificate(&mut self.transcript, &mut cx.common, server_key.get_cert());
+                    if let Some(ocsp_response) = ocsp_response {
+                        emit_cert_status(&mut self.transcript, &mut cx.common, ocsp_response);
+                    }
+                    emit_server_kx(
+                        &mut self.transcript,
+                        &mut cx.common,
+                        sigschemes,
+                        group,
+                        server_key.get_key(),
+                        &self.randoms,
+                    )?
+                }
+                None => emit_server_psk_kx(
+                    &mut self.transcript,
+                    &mut cx.common,
+                    &self.config.psk_identity_hint,
+                    group,
+                )?,
+            };
+            // PSK-authenticated clients don't get asked for certificates.
+            let doing_client_auth = server_key.is_some()
+                && emit_certificate_req(&self.config, &mut self.transcript, cx)?;
             emit_server_hello_done(&mut self.transcript, &mut cx.common);
 
             if doing_client_auth {
@@ -438,6 +457,32 @@ mod client_hello {
         Ok(kx)
     }
 
+    fn emit_server_psk_kx(
+        transcript: &mut HandshakeHash,
+        common: &mut ConnectionCommon,
+        identity_hint: &[u8],
+        skxg: &'static kx::SupportedKxGroup,
+    ) -> Result<kx::KeyExchange, Error> {
+        let kx = kx::KeyExchange::start(skxg).ok_or(Error::FailedToGetRandomBytes)?;
+
+        let skx = ServerKeyExchangePayload::ECDHEPSK(ECDHEPSKServerKeyExchange {
+            identity_hint: PayloadU16::new(identity_hint.to_vec()),
+            params: ServerECDHParams::new(skxg.name, kx.pubkey.as_ref()),
+        });
+
+        let m = Message {
+            version: ProtocolVersion::TLSv1_2,
+            payload: MessagePayload::Handshake(HandshakeMessagePayload {
+                typ: HandshakeType::ServerKeyExchange,
+                payload: HandshakePayload::ServerKeyExchange(skx),
+            }),
+        };
+
+        transcript.add_message(&m);
+        common.send_msg(m, false);
+        Ok(kx)
+    }
+
     fn emit_certificate_req(
         config: &ServerConfig,
         transcript: &mut HandshakeHash,
@@ -445,7 +490,8 @@ mod client_hello {
     ) -> Result<bool, Error> {
         let client_auth = &config.verifier;
 
-        if !client_auth.offer_client_auth() {
+        if !client_auth.offer_client_auth_for_sni(cx.data.get_sni()) {
+            transcript.abandon_client_auth();
             return Ok(false);
         }
 
@@ -522,13 +568,15 @@ impl hs::State for ExpectCertificate {
         let mandatory = self
             .config
             .verifier
-            .client_auth_mandatory(cx.data.get_sni())
-            .ok_or_else(|| {
-                debug!("could not determine if client auth is mandatory based on SNI");
-                cx.common
-                    .send_fatal_alert(AlertDescription::AccessDenied);
-                Error::General("client rejected by client_auth_mandatory".into())
-            })?;
+            .client_auth_mandatory_in_context(
+                &cx.client_cert_verify_context(ProtocolVersion::TLSv1_2),
+            );
+        let mandatory = mandatory.ok_or_else(|| {
+            debug!("could not determine if client auth is mandatory based on SNI");
+            cx.common
+                .send_fatal_alert(AlertDescription::AccessDenied);
+            Error::General("client rejected by client_auth_mandatory".into())
+        })?;
 
         trace!("certs {:?}", cert_chain);
 
@@ -544,14 +592,25 @@ impl hs::State for ExpectCertificate {
                 None
             }
             Some((end_entity, intermediates)) => {
-                let now = std::time::SystemTime::now();
-                self.config
+                let now = self
+                    .config
+                    .time_provider
+                    .current_time()
+                    .ok_or(Error::FailedToGetCurrentTime)?;
+                let verified = self
+                    .config
                     .verifier
-                    .verify_client_cert(end_entity, intermediates, cx.data.get_sni(), now)
-                    .map_err(|err| {
-                        hs::incompatible(&mut cx.common, "certificate invalid");
-                        err
-                    })?;
+                    .verify_client_cert_in_context(
+                        end_entity,
+                        intermediates,
+                        &cx.client_cert_verify_context(ProtocolVersion::TLSv1_2),
+                        now,
+                    );
+                verified.map_err(|err| {
+                    cx.common
+                        .send_fatal_alert(err.certificate_alert());
+                    err
+                })?;
 
                 Some(cert_chain)
             }
@@ -595,9 +654,28 @@ impl hs::State for ExpectClientKx {
 
         // Complete key agreement, and set up encryption with the
         // resulting premaster secret.
-        let peer_kx_params =
-            tls12::decode_ecdh_params::<ClientECDHParams>(&mut cx.common, &client_kx.0)?;
-        let kxd = tls12::complete_ecdh(self.server_kx, &peer_kx_params.public.0)?;
+        let premaster_secret = if self.suite.supported_suite().uses_psk() {
+            let peer_kx_params =
+                tls12::decode_ecdh_params::<ClientECDHEPSKParams>(&mut cx.common, &client_kx.0)?;
+            let psk = self
+                .config
+                .psk_resolver
+                .as_ref()
+                .and_then(|resolver| resolver.resolve(&peer_kx_params.identity.0))
+                .ok_or_else(|| {
+                    cx.common
+                        .send_fatal_alert(AlertDescription::UnknownPSKIdentity);
+                    Error::PeerMisbehavedError("client used an unknown PSK identity".to_string())
+                })?;
+            let kxd = tls12::complete_ecdh(self.server_kx, &peer_kx_params.public.0)?;
+
+            cx.data.psk_identity = Some(peer_kx_params.identity.0);
+            tls12::ecdhe_psk_premaster_secret(&kxd.shared_secret, psk.secret())
+        } else {
+            let peer_kx_params =
+                tls12::decode_ecdh_params::<ClientECDHParams>(&mut cx.common, &client_kx.0)?;
+            tls12::complete_ecdh(self.server_kx, &peer_kx_params.public.0)?.shared_secret
+        };
 
         let secrets = if self.using_ems {
             let handshake_hash = self.transcript.get_current_hash();
@@ -605,10 +683,10 @@ impl hs::State for ExpectClientKx {
                 &self.randoms,
                 &handshake_hash,
                 self.suite,
-                &kxd.shared_secret,
+                &premaster_secret,
             )
         } else {
-            ConnectionSecrets::new(&self.randoms, self.suite, &kxd.shared_secret)
+            ConnectionSecrets::new(&self.randoms, self.suite, &premaster_secret)
         };
         self.config.key_log.log(
             "CLIENT_RANDOM",
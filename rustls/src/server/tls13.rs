@@ -61,6 +61,12 @@ mod client_hello {
 
     use super::*;
 
+    /// How many bytes of records we skip when rejecting early data,
+    /// before failing the connection.  We never allow early data in
+    /// our tickets, but clients may send it with tickets from servers
+    /// sharing our ticket keys which do.
+    const MAX_REJECTED_EARLY_DATA: usize = 16384;
+
     pub(in crate::server) struct CompleteClientHelloHandling {
         pub(in crate::server) config: Arc<ServerConfig>,
         pub(in crate::server) transcript: HandshakeHash,
@@ -78,6 +84,7 @@ mod client_hello {
             client_hello: &Message,
             psk: &[u8],
             binder: &[u8],
+            external_psk: bool,
         ) -> bool {
             let binder_plaintext = match client_hello.payload {
                 MessagePayload::Handshake(ref hmp) => hmp.get_encoding_for_binder_signing(),
@@ -90,8 +97,11 @@ mod client_hello {
                 .get_hash_given(suite_hash, &binder_plaintext);
 
             let key_schedule = KeyScheduleEarly::new(suite.hkdf_algorithm, &psk);
-            let real_binder =
-                key_schedule.resumption_psk_binder_key_and_sign_verify_data(&handshake_hash);
+            let real_binder = if external_psk {
+                key_schedule.external_psk_binder_key_and_sign_verify_data(&handshake_hash)
+            } else {
+                key_schedule.resumption_psk_binder_key_and_sign_verify_data(&handshake_hash)
+            };
 
             constant_time::verify_slices_are_equal(real_binder.as_ref(), binder).is_ok()
         }
@@ -113,11 +123,70 @@ mod client_hello {
             }
         }
 
+        /// We don't have a suitable key share.  Choose a suitable group and
+        /// send a HelloRetryRequest.
+        fn retry_for_key_share(
+            mut self,
+            suite: &'static SupportedCipherSuite,
+            cx: &mut ServerContext<'_>,
+            chm: &Message,
+            client_hello: &ClientHelloPayload,
+        ) -> hs::NextStateOrError {
+            let groups_ext = client_hello
+                .get_namedgroups_extension()
+                .ok_or_else(|| hs::incompatible(&mut cx.common, "client didn't describe groups"))?;
+
+            if client_hello
+                .get_keyshare_extension()
+                .is_none()
+            {
+                return Err(hs::incompatible(
+                    &mut cx.common,
+                    "client didn't send keyshares",
+                ));
+            }
+
+            let retry_group_maybe = self
+                .config
+                .kx_groups
+                .iter()
+                .find(|group| groups_ext.contains(&group.name))
+                .cloned();
+
+            self.transcript.add_message(chm);
+
+            if let Some(group) = retry_group_maybe {
+                if self.done_retry {
+                    return Err(cx
+                        .common
+                        .illegal_param("did not follow retry request"));
+                }
+
+                emit_hello_retry_request(&mut self.transcript, suite, &mut cx.common, group.name);
+                emit_fake_ccs(&mut cx.common);
+                skip_rejected_early_data(cx, client_hello);
+                return Ok(Box::new(hs::ExpectClientHello {
+                    config: self.config,
+                    transcript: self.transcript,
+                    session_id: SessionID::empty(),
+                    using_ems: false,
+                    done_retry: true,
+                    send_ticket: self.send_ticket,
+                    extra_exts: self.extra_exts,
+                }));
+            }
+
+            Err(hs::incompatible(
+                &mut cx.common,
+                "no kx group overlap with client",
+            ))
+        }
+
         pub(in crate::server) fn handle_client_hello(
             mut self,
             suite: &'static SupportedCipherSuite,
             cx: &mut ServerContext<'_>,
-            server_key: ActiveCertifiedKey,
+            server_key: Option<ActiveCertifiedKey>,
             chm: &Message,
         ) -> hs::NextStateOrError {
             let client_hello = require_handshake_msg!(
@@ -132,89 +201,43 @@ mod client_hello {
                     .illegal_param("client offered wrong compressions"));
             }
 
-            let groups_ext = client_hello
-                .get_namedgroups_extension()
-                .ok_or_else(|| hs::incompatible(&mut cx.common, "client didn't describe groups"))?;
-
-            let mut sigschemes_ext = client_hello
-                .get_sigalgs_extension()
-                .ok_or_else(|| {
-                    hs::incompatible(&mut cx.common, "client didn't describe sigschemes")
-                })?
-                .clone();
-
-            let tls13_schemes = sign::supported_sign_tls13();
-            sigschemes_ext.retain(|scheme| tls13_schemes.contains(scheme));
-
-            let shares_ext = client_hello
-                .get_keyshare_extension()
-                .ok_or_else(|| hs::incompatible(&mut cx.common, "client didn't send keyshares"))?;
-
             if client_hello.has_keyshare_extension_with_duplicates() {
                 return Err(cx
                     .common
                     .illegal_param("client sent duplicate keyshares"));
             }
 
-            // choose a share that we support
-            let chosen_share = self
+            // The first of our PSK modes which the client offers.
+            let psk_mode = self
                 .config
-                .kx_groups
+                .psk_key_exchange_modes
                 .iter()
-                .find_map(|group| {
-                    shares_ext
-                        .iter()
-                        .find(|share| share.group == group.name)
-                });
+                .copied()
+                .find(|mode| client_hello.psk_mode_offered(*mode));
 
-            let chosen_share = match chosen_share {
-                Some(s) => s,
-                None => {
-                    // We don't have a suitable key share.  Choose a suitable group and
-                    // send a HelloRetryRequest.
-                    let retry_group_maybe = self
-                        .config
+            // choose a share that we support
+            let chosen_share = client_hello
+                .get_keyshare_extension()
+                .and_then(|shares_ext| {
+                    self.config
                         .kx_groups
                         .iter()
-                        .find(|group| groups_ext.contains(&group.name))
-                        .cloned();
-
-                    self.transcript.add_message(chm);
-
-                    if let Some(group) = retry_group_maybe {
-                        if self.done_retry {
-                            return Err(cx
-                                .common
-                                .illegal_param("did not follow retry request"));
-                        }
-
-                        emit_hello_retry_request(
-                            &mut self.transcript,
-                            suite,
-                            &mut cx.common,
-                            group.name,
-                        );
-                        emit_fake_ccs(&mut cx.common);
-                        return Ok(Box::new(hs::ExpectClientHello {
-                            config: self.config,
-                            transcript: self.transcript,
-                            session_id: SessionID::empty(),
-                            using_ems: false,
-                            done_retry: true,
-                            send_ticket: self.send_ticket,
-                            extra_exts: self.extra_exts,
-                        }));
-                    }
+                        .find_map(|group| {
+                            shares_ext
+                                .iter()
+                                .find(|share| share.group == group.name)
+                        })
+                });
 
-                    return Err(hs::incompatible(
-                        &mut cx.common,
-                        "no kx group overlap with client",
-                    ));
-                }
-            };
+            // Unless a PSK might do without a key exchange, we need a
+            // suitable key share now.
+            if chosen_share.is_none() && psk_mode != Some(PSKKeyExchangeMode::PSK_KE) {
+                return self.retry_for_key_share(suite, cx, chm, client_hello);
+            }
 
             let mut chosen_psk_index = None;
             let mut resumedata = None;
+            let mut external_psk = None;
             if let Some(psk_offer) = client_hello.get_psk() {
                 if !client_hello.check_psk_ext_is_last() {
                     return Err(cx
@@ -236,69 +259,125 @@ mod client_hello {
                 }
 
                 for (i, psk_id) in psk_offer.identities.iter().enumerate() {
-                    let resume = match self
+                    if psk_mode.is_none() {
+                        break;
+                    }
+
+                    if let Some(resume) = self
                         .attempt_tls13_ticket_decryption(&psk_id.identity.0)
                         .filter(|resumedata| {
                             hs::can_resume(self.suite, &cx.data.sni, false, resumedata)
-                        }) {
-                        Some(resume) => resume,
-                        None => continue,
-                    };
-
-                    if !self.check_binder(
-                        suite,
-                        chm,
-                        &resume.master_secret.0,
-                        &psk_offer.binders[i].0,
-                    ) {
-                        cx.common
-                            .send_fatal_alert(AlertDescription::DecryptError);
-                        return Err(Error::PeerMisbehavedError(
-                            "client sent wrong binder".to_string(),
-                        ));
+                        })
+                    {
+                        if !self.check_binder(
+                            suite,
+                            chm,
+                            &resume.master_secret.0,
+                            &psk_offer.binders[i].0,
+                            false,
+                        ) {
+                            cx.common
+                                .send_fatal_alert(AlertDescription::DecryptError);
+                            return Err(Error::PeerMisbehavedError(
+                                "client sent wrong binder".to_string(),
+                            ));
+                        }
+
+                        chosen_psk_index = Some(i);
+                        resumedata = Some(resume);
+                        break;
                     }
 
-                    chosen_psk_index = Some(i);
-                    resumedata = Some(resume);
-                    break;
+                    if let Some(psk) = self
+                        .config
+                        .psk_resolver
+                        .as_ref()
+                        .and_then(|resolver| resolver.resolve(&psk_id.identity.0))
+                        .filter(|psk| psk.usable_with(suite))
+                    {
+                        if !self.check_binder(
+                            suite,
+                            chm,
+                            psk.secret(),
+                            &psk_offer.binders[i].0,
+                            true,
+                        ) {
+                            cx.common
+                                .send_fatal_alert(AlertDescription::DecryptError);
+                            return Err(Error::PeerMisbehavedError(
+                                "client sent wrong binder".to_string(),
+                            ));
+                        }
+
+                        chosen_psk_index = Some(i);
+                        external_psk = Some(psk);
+                        break;
+                    }
                 }
             }
 
-            if !client_hello.psk_mode_offered(PSKKeyExchangeMode::PSK_DHE_KE) {
-                debug!("Client unwilling to resume, DHE_KE not offered");
+            if psk_mode.is_none() {
+                debug!("Client unwilling to use PSKs in any mode we allow");
                 self.send_ticket = false;
-                chosen_psk_index = None;
-                resumedata = None;
             } else {
                 self.send_ticket = true;
             }
 
+            // psk_ke needs a PSK; otherwise we ask again for a key share.
+            let share = match (chosen_psk_index, psk_mode) {
+                (Some(_), Some(PSKKeyExchangeMode::PSK_KE)) => None,
+                _ => match chosen_share {
+                    Some(share) => Some(share),
+                    None => return self.retry_for_key_share(suite, cx, chm, client_hello),
+                },
+            };
+
             if let Some(ref resume) = resumedata {
                 cx.data.received_resumption_data = Some(resume.application_data.0.clone());
                 cx.data.client_cert_chain = resume.client_cert_chain.clone();
             }
 
-            let full_handshake = resumedata.is_none();
+            if let Some(ref psk) = external_psk {
+                debug!("Client authenticated with external PSK");
+                cx.data.psk_identity = Some(psk.identity().to_vec());
+            }
+
+            let full_handshake = chosen_psk_index.is_none();
+            if full_handshake && server_key.is_none() {
+                cx.common
+                    .send_fatal_alert(AlertDescription::AccessDenied);
+                return Err(Error::General(
+                    "no server certificate chain resolved".to_string(),
+                ));
+            }
+
             self.transcript.add_message(chm);
+            let psk_secret = match (&resumedata, &external_psk) {
+                (Some(resume), _) => Some(&resume.master_secret.0[..]),
+                (None, Some(psk)) => Some(psk.secret()),
+                (None, None) => None,
+            };
             let key_schedule = emit_server_hello(
                 &mut self.transcript,
                 &self.randoms,
                 suite,
                 cx,
                 &client_hello.session_id,
-                chosen_share,
+                share,
                 chosen_psk_index,
-                resumedata
-                    .as_ref()
-                    .map(|x| &x.master_secret.0[..]),
+                psk_secret,
+                resumedata.is_some(),
                 &self.config,
             )?;
             if !self.done_retry {
                 emit_fake_ccs(&mut cx.common);
             }
+            skip_rejected_early_data(cx, client_hello);
 
-            let (mut ocsp_response, mut sct_list) =
-                (server_key.get_ocsp(), server_key.get_sct_list());
+            let (mut ocsp_response, mut sct_list) = match &server_key {
+                Some(server_key) => (server_key.get_ocsp(), server_key.get_sct_list()),
+                None => (None, None),
+            };
             emit_encrypted_extensions(
                 &mut self.transcript,
                 suite,
@@ -311,25 +390,36 @@ mod client_hello {
                 &self.config,
             )?;
 
-            let doing_client_auth = if full_handshake {
-                let client_auth =
-                    emit_certificate_req_tls13(&mut self.transcript, cx, &self.config)?;
-                emit_certificate_tls13(
-                    &mut self.transcript,
-                    &mut cx.common,
-                    server_key.get_cert(),
-                    ocsp_response,
-                    sct_list,
-                );
-                emit_certificate_verify_tls13(
-                    &mut self.transcript,
-                    &mut cx.common,
-                    server_key.get_key(),
-                    &sigschemes_ext,
-                )?;
-                client_auth
-            } else {
-                false
+            let doing_client_auth = match &server_key {
+                Some(server_key) if full_handshake => {
+                    let mut sigschemes_ext = client_hello
+                        .get_sigalgs_extension()
+                        .ok_or_else(|| {
+                            hs::incompatible(&mut cx.common, "client didn't describe sigschemes")
+                        })?
+                        .clone();
+
+                    let tls13_schemes = sign::supported_sign_tls13();
+                    sigschemes_ext.retain(|scheme| tls13_schemes.contains(scheme));
+
+                    let client_auth =
+                        emit_certificate_req_tls13(&mut self.transcript, cx, &self.config)?;
+                    emit_certificate_tls13(
+                        &mut self.transcript,
+                        &mut cx.common,
+                        server_key.get_cert(),
+                        ocsp_response,
+                        sct_list,
+                    );
+                    emit_certificate_verify_tls13(
+                        &mut self.transcript,
+                        &mut cx.common,
+                        server_key.get_key(),
+                        &sigschemes_ext,
+                    )?;
+                    client_auth
+                }
+                _ => false,
             };
 
             cx.common.check_aligned_handshake()?;
@@ -372,22 +462,31 @@ mod client_hello {
         suite: &'static SupportedCipherSuite,
         cx: &mut ServerContext<'_>,
         session_id: &SessionID,
-        share: &KeyShareEntry,
+        share: Option<&KeyShareEntry>,
         chosen_psk_idx: Option<usize>,
-        resuming_psk: Option<&[u8]>,
+        psk: Option<&[u8]>,
+        #[allow(unused_variables)] // #[cfg(feature = "quic")] only
+        resuming: bool,
         config: &ServerConfig,
     ) -> Result<KeyScheduleHandshake, Error> {
         let mut extensions = Vec::new();
 
-        // Do key exchange
-        let kxr = kx::KeyExchange::choose(share.group, &config.kx_groups)
-            .and_then(kx::KeyExchange::start)
-            .ok_or(Error::FailedToGetRandomBytes)?
-            .complete(&share.payload.0)
-            .ok_or_else(|| Error::PeerMisbehavedError("key exchange failed".to_string()))?;
-
-        let kse = KeyShareEntry::new(share.group, kxr.pubkey.as_ref());
-        extensions.push(ServerExtension::KeyShare(kse));
+        // Do key exchange, unless we're using a PSK without one (psk_ke),
+        // when the key schedule takes zeroes instead.
+        let shared_secret = match share {
+            Some(share) => {
+                let kxr = kx::KeyExchange::choose(share.group, &config.kx_groups)
+                    .and_then(kx::KeyExchange::start)
+                    .ok_or(Error::FailedToGetRandomBytes)?
+                    .complete(&share.payload.0)
+                    .ok_or_else(|| Error::PeerMisbehavedError("key exchange failed".to_string()))?;
+
+                let kse = KeyShareEntry::new(share.group, kxr.pubkey.as_ref());
+                extensions.push(ServerExtension::KeyShare(kse));
+                kxr.shared_secret
+            }
+            None => vec![0u8; suite.get_hash().output_len],
+        };
         extensions.push(ServerExtension::SupportedVersions(ProtocolVersion::TLSv1_3));
 
         if let Some(psk_idx) = chosen_psk_idx {
@@ -419,12 +518,12 @@ mod client_hello {
         cx.common.send_msg(sh, false);
 
         // Start key schedule
-        let mut key_schedule = if let Some(psk) = resuming_psk {
+        let mut key_schedule = if let Some(psk) = psk {
             let early_key_schedule = KeyScheduleEarly::new(suite.hkdf_algorithm, psk);
 
             #[cfg(feature = "quic")]
             {
-                if cx.common.protocol == Protocol::Quic {
+                if cx.common.protocol == Protocol::Quic && resuming {
                     let client_early_traffic_secret = early_key_schedule
                         .client_early_traffic_secret(
                             &client_hello_hash,
@@ -437,9 +536,9 @@ mod client_hello {
                 }
             }
 
-            early_key_schedule.into_handshake(&kxr.shared_secret)
+            early_key_schedule.into_handshake(&shared_secret)
         } else {
-            KeyScheduleNonSecret::new(suite.hkdf_algorithm).into_handshake(&kxr.shared_secret)
+            KeyScheduleNonSecret::new(suite.hkdf_algorithm).into_handshake(&shared_secret)
         };
 
         let handshake_hash = transcript.get_current_hash();
@@ -483,6 +582,15 @@ mod client_hello {
         common.send_msg(m, false);
     }
 
+    /// Skip any early data the client sends: we only accept it in QUIC.
+    fn skip_rejected_early_data(cx: &mut ServerContext<'_>, client_hello: &ClientHelloPayload) {
+        if !cx.common.is_quic() && client_hello.early_data_extension_offered() {
+            debug!("Rejecting early data");
+            cx.common
+                .skip_rejected_early_data(MAX_REJECTED_EARLY_DATA);
+        }
+    }
+
     fn emit_hello_retry_request(
         transcript: &mut HandshakeHash,
         suite: &'static SupportedCipherSuite,
@@ -559,7 +667,11 @@ mod client_hello {
         cx: &mut ServerContext<'_>,
         config: &ServerConfig,
     ) -> Result<bool, Error> {
-        if !config.verifier.offer_client_auth() {
+        if !config
+            .verifier
+            .offer_client_auth_for_sni(cx.data.get_sni())
+        {
+            transcript.abandon_client_auth();
             return Ok(false);
         }
 
@@ -776,13 +888,15 @@ impl hs::State for ExpectCertificate {
         let mandatory = self
             .config
             .verifier
-            .client_auth_mandatory(cx.data.get_sni())
-            .ok_or_else(|| {
-                debug!("could not determine if client auth is mandatory based on SNI");
-                cx.common
-                    .send_fatal_alert(AlertDescription::AccessDenied);
-                Error::General("client rejected by client_auth_mandatory".into())
-            })?;
+            .client_auth_mandatory_in_context(
+                &cx.client_cert_verify_context(ProtocolVersion::TLSv1_3),
+            );
+        let mandatory = mandatory.ok_or_else(|| {
+            debug!("could not determine if client auth is mandatory based on SNI");
+            cx.common
+                .send_fatal_alert(AlertDescription::AccessDenied);
+            Error::General("client rejected by client_auth_mandatory".into())
+        })?;
 
         let (end_entity, intermediates) = match client_cert.split_first() {
             None => {
@@ -807,14 +921,25 @@ impl hs::State for ExpectCertificate {
             Some(chain) => chain,
         };
 
-        let now = std::time::SystemTime::now();
-        self.config
+        let now = self
+            .config
+            .time_provider
+            .current_time()
+            .ok_or(Error::FailedToGetCurrentTime)?;
+        let verified = self
+            .config
             .verifier
-            .verify_client_cert(end_entity, intermediates, cx.data.get_sni(), now)
-            .map_err(|err| {
-                hs::incompatible(&mut cx.common, "certificate invalid");
-                err
-            })?;
+            .verify_client_cert_in_context(
+                end_entity,
+                intermediates,
+                &cx.client_cert_verify_context(ProtocolVersion::TLSv1_3),
+                now,
+            );
+        verified.map_err(|err| {
+            cx.common
+                .send_fatal_alert(err.certificate_alert());
+            err
+        })?;
 
         Ok(Box::new(ExpectCertificateVerify {
             config: self.config,
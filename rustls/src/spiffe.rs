@@ -0,0 +1,296 @@
+// SPIFFE identities, as carried by X.509-SVIDs for mutually
+// authenticated TLS within a service mesh.
+
+use crate::anchors::{DistinguishedNames, RootCertStore};
+use crate::client::ServerName;
+use crate::error::{Error, WebPkiOp};
+use crate::key::Certificate;
+#[cfg(feature = "logging")]
+use crate::log::debug;
+use crate::verify::{self, ClientCertVerified, ClientCertVerifier};
+use crate::verify::{ServerCertVerified, ServerCertVerifier};
+use crate::x509;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::time::SystemTime;
+
+/// The longest SPIFFE ID allowed, in bytes.
+const MAX_ID_LEN: usize = 2048;
+
+/// A SPIFFE ID, such as `spiffe://example.org/web`: a trust domain and
+/// a path within it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SpiffeId {
+    id: String,
+    path_start: usize,
+}
+
+impl SpiffeId {
+    /// Parse `id`, returning `None` if it isn't a valid SPIFFE ID.
+    ///
+    /// The trust domain must be lowercase, and the path must not have
+    /// empty, `.` or `..` segments, or a trailing `/`.  Query strings,
+    /// fragments, ports and user information are not allowed.
+    pub fn parse(id: &str) -> Option<Self> {
+        if id.len() > MAX_ID_LEN {
+            return None;
+        }
+
+        let rest = id.strip_prefix("spiffe://")?;
+        let path_start = rest.find('/').unwrap_or(rest.len());
+        let (trust_domain, path) = rest.split_at(path_start);
+
+        let trust_domain_ok = !trust_domain.is_empty()
+            && trust_domain
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b"-._".contains(&b));
+        let path_ok = path.is_empty()
+            || path[1..].split('/').all(|segment| {
+                !segment.is_empty()
+                    && segment != "."
+                    && segment != ".."
+                    && segment
+                        .bytes()
+                        .all(|b| b.is_ascii_alphanumeric() || b"-._".contains(&b))
+            });
+
+        if trust_domain_ok && path_ok {
+            Some(Self {
+                id: id.to_string(),
+                path_start: "spiffe://".len() + path_start,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the SPIFFE ID of the X.509-SVID `end_entity`, or `None`
+    /// if it doesn't have exactly one URI subject alternative name, or
+    /// that isn't a SPIFFE ID.
+    ///
+    /// The certificate is not verified.  After a handshake verified by
+    /// a [`SpiffeVerifier`], applying this to the first of the
+    /// connection's `peer_certificates` gives the peer's authenticated
+    /// ID.
+    pub fn from_certificate(end_entity: &Certificate) -> Option<Self> {
+        let cert = x509::CertFields::parse(&end_entity.0)?;
+        let mut uris = cert
+            .subject_alt_names()?
+            .into_iter()
+            .filter(|(tag, _)| *tag == x509::GENERAL_NAME_URI);
+
+        let uri = uris.next()?.1;
+        if uris.next().is_some() {
+            return None;
+        }
+
+        Self::parse(std::str::from_utf8(uri).ok()?)
+    }
+
+    /// The trust domain, such as `example.org`.
+    pub fn trust_domain(&self) -> &str {
+        &self.id["spiffe://".len()..self.path_start]
+    }
+
+    /// The path, such as `/web`.  This is empty if the ID names the
+    /// trust domain itself.
+    pub fn path(&self) -> &str {
+        &self.id[self.path_start..]
+    }
+
+    /// The whole ID, such as `spiffe://example.org/web`.
+    pub fn as_str(&self) -> &str {
+        &self.id
+    }
+}
+
+impl fmt::Display for SpiffeId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.id)
+    }
+}
+
+/// A verifier for peers authenticating with SPIFFE X.509-SVIDs, for
+/// use as either a `ServerCertVerifier` or a `ClientCertVerifier`.
+///
+/// The peer's certificate must carry exactly one SPIFFE ID, and chain
+/// to the roots of that ID's trust domain.  The ID is then passed to an
+/// authorizer, which decides whether the peer is acceptable.  DNS names
+/// are not checked: the authorizer is the only check of who the peer is.
+///
+/// Certificates are rejected with `Error::InvalidSpiffeId` if they
+/// don't carry a valid SPIFFE ID, and with `Error::SpiffeIdNotAuthorized`
+/// if the authorizer rejects it.  A trust domain with no roots is
+/// treated as an unknown issuer.
+///
+/// After the handshake, [`SpiffeId::from_certificate`] gives the ID
+/// the peer authenticated as.
+pub struct SpiffeVerifier {
+    trust_domains: HashMap<String, RootCertStore>,
+    authorizer: Box<dyn Fn(&SpiffeId) -> bool + Send + Sync>,
+}
+
+impl SpiffeVerifier {
+    /// Make a verifier accepting peers in `trust_domain`, whose roots
+    /// are `roots`, if `authorizer` returns true for their ID.
+    pub fn new(
+        trust_domain: &str,
+        roots: RootCertStore,
+        authorizer: impl Fn(&SpiffeId) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        let mut trust_domains = HashMap::new();
+        trust_domains.insert(trust_domain.to_string(), roots);
+        Self {
+            trust_domains,
+            authorizer: Box::new(authorizer),
+        }
+    }
+
+    /// Also accept peers in `trust_domain`, whose roots are `roots`:
+    /// for example, a federated trust domain.  This replaces any roots
+    /// previously given for `trust_domain`.
+    pub fn with_trust_domain(mut self, trust_domain: &str, roots: RootCertStore) -> Self {
+        self.trust_domains
+            .insert(trust_domain.to_string(), roots);
+        self
+    }
+
+    /// Find the peer's SPIFFE ID and the roots for its trust domain.
+    fn identify(
+        &self,
+        end_entity: &Certificate,
+        op: WebPkiOp,
+    ) -> Result<(SpiffeId, &RootCertStore), Error> {
+        let id = SpiffeId::from_certificate(end_entity).ok_or(Error::InvalidSpiffeId)?;
+        match self
+            .trust_domains
+            .get(id.trust_domain())
+        {
+            Some(roots) => Ok((id, roots)),
+            None => {
+                debug!("no roots for SPIFFE trust domain of {}", id);
+                Err(Error::WebPkiError(webpki::Error::UnknownIssuer, op))
+            }
+        }
+    }
+
+    fn authorize(&self, id: &SpiffeId) -> Result<(), Error> {
+        if (self.authorizer)(id) {
+            Ok(())
+        } else {
+            debug!("SPIFFE ID {} not authorized", id);
+            Err(Error::SpiffeIdNotAuthorized)
+        }
+    }
+}
+
+impl ServerCertVerifier for SpiffeVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let (id, roots) = self.identify(end_entity, WebPkiOp::ValidateServerCert)?;
+        let (cert, chain, trustroots) = verify::prepare(end_entity, intermediates, roots, now)?;
+        let now = webpki::Time::try_from(now).map_err(|_| Error::FailedToGetCurrentTime)?;
+        cert.verify_is_valid_tls_server_cert(
+            verify::SUPPORTED_SIG_ALGS,
+            &webpki::TlsServerTrustAnchors(&trustroots),
+            &chain,
+            now,
+        )
+        .map_err(|e| Error::WebPkiError(e, WebPkiOp::ValidateServerCert))?;
+
+        self.authorize(&id)?;
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn request_scts(&self) -> bool {
+        false
+    }
+}
+
+impl ClientCertVerifier for SpiffeVerifier {
+    fn client_auth_root_subjects(
+        &self,
+        _sni: Option<&webpki::DnsName>,
+    ) -> Option<DistinguishedNames> {
+        Some(
+            self.trust_domains
+                .values()
+                .flat_map(RootCertStore::subjects)
+                .collect(),
+        )
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        _sni: Option<&webpki::DnsName>,
+        now: SystemTime,
+    ) -> Result<ClientCertVerified, Error> {
+        let (id, roots) = self.identify(end_entity, WebPkiOp::ValidateClientCert)?;
+        let (cert, chain, trustroots) = verify::prepare(end_entity, intermediates, roots, now)?;
+        let now = webpki::Time::try_from(now).map_err(|_| Error::FailedToGetCurrentTime)?;
+        cert.verify_is_valid_tls_client_cert(
+            verify::SUPPORTED_SIG_ALGS,
+            &webpki::TlsClientTrustAnchors(&trustroots),
+            &chain,
+            now,
+        )
+        .map_err(|e| Error::WebPkiError(e, WebPkiOp::ValidateClientCert))?;
+
+        self.authorize(&id)?;
+        Ok(ClientCertVerified::assertion())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SpiffeId;
+
+    #[test]
+    fn parses_valid_ids() {
+        let id = SpiffeId::parse("spiffe://example.org/ns/prod/sa/web").unwrap();
+        assert_eq!(id.trust_domain(), "example.org");
+        assert_eq!(id.path(), "/ns/prod/sa/web");
+        assert_eq!(id.as_str(), "spiffe://example.org/ns/prod/sa/web");
+        assert_eq!(id.to_string(), "spiffe://example.org/ns/prod/sa/web");
+
+        let id = SpiffeId::parse("spiffe://my_domain-1.test").unwrap();
+        assert_eq!(id.trust_domain(), "my_domain-1.test");
+        assert_eq!(id.path(), "");
+    }
+
+    #[test]
+    fn rejects_invalid_ids() {
+        for id in &[
+            "",
+            "spiffe://",
+            "spiffe:///path",
+            "https://example.org/web",
+            "SPIFFE://example.org/web",
+            "spiffe://Example.org/web",
+            "spiffe://example.org:8080/web",
+            "spiffe://user@example.org/web",
+            "spiffe://example.org/",
+            "spiffe://example.org//web",
+            "spiffe://example.org/./web",
+            "spiffe://example.org/../web",
+            "spiffe://example.org/web?query",
+            "spiffe://example.org/web#fragment",
+            "spiffe://example.org/we%20b",
+        ] {
+            assert_eq!(SpiffeId::parse(id), None, "{}", id);
+        }
+
+        let long = format!("spiffe://example.org/{}", "a".repeat(2048));
+        assert_eq!(SpiffeId::parse(&long), None);
+    }
+}
@@ -24,7 +24,8 @@ pub enum BulkAlgorithm {
 /// A cipher suite supported by rustls.
 ///
 /// All possible instances of this class are provided by the library in
-/// the `ALL_CIPHERSUITES` array.
+/// the `ALL_CIPHERSUITES` array, apart from the TLS1.2 ECDHE_PSK suites
+/// which must be enabled explicitly.
 pub struct SupportedCipherSuite {
     /// The TLS enumeration naming this cipher suite.
     pub suite: CipherSuite,
@@ -89,6 +90,21 @@ impl Tls12CipherSuite {
         self.tls12
     }
 
+    /// May a client send application data before the server's Finished
+    /// with this suite ("False Start", RFC7918 section 3.1)?  This needs
+    /// forward-secret key exchange, an AEAD, and a server authenticated by
+    /// its certificate: the ECDHE_PSK suites only prove the server knows
+    /// the PSK with its Finished message.
+    pub fn allows_false_start(&self) -> bool {
+        let forward_secret = matches!(self.tls12.kx, KeyExchangeAlgorithm::ECDHE);
+        let aead = match self.scs.bulk {
+            BulkAlgorithm::Aes128Gcm
+            | BulkAlgorithm::Aes256Gcm
+            | BulkAlgorithm::Chacha20Poly1305 => true,
+        };
+        forward_secret && aead
+    }
+
     /// Resolve the set of supported `SignatureScheme`s from the
     /// offered `SupportedSignatureSchemes`.  If we return an empty
     /// set, the handshake terminates.
@@ -152,6 +168,18 @@ impl SupportedCipherSuite {
         }
     }
 
+    /// Does this suite authenticate with a pre-shared key, rather than
+    /// a certificate?  Only true of the TLS1.2 ECDHE_PSK suites.
+    pub(crate) fn uses_psk(&self) -> bool {
+        matches!(
+            self.tls12,
+            Some(Tls12Parameters {
+                kx: KeyExchangeAlgorithm::ECDHE_PSK,
+                ..
+            })
+        )
+    }
+
     /// Can a session using suite self resume using suite new_suite?
     pub fn can_resume_to(&self, new_suite: &SupportedCipherSuite) -> bool {
         if self.usable_for_version(ProtocolVersion::TLSv1_3)
@@ -287,6 +315,64 @@ pub static TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384: SupportedCipherSuite = Suppo
     }),
 };
 
+/// The TLS1.2 ciphersuite TLS_ECDHE_PSK_WITH_CHACHA20_POLY1305_SHA256.
+///
+/// This isn't in `ALL_CIPHERSUITES`: it is only useful alongside a
+/// configured external PSK.
+pub static TLS_ECDHE_PSK_WITH_CHACHA20_POLY1305_SHA256: SupportedCipherSuite =
+    SupportedCipherSuite {
+        suite: CipherSuite::TLS_ECDHE_PSK_WITH_CHACHA20_POLY1305_SHA256,
+        bulk: BulkAlgorithm::Chacha20Poly1305,
+        hkdf_algorithm: ring::hkdf::HKDF_SHA256,
+        aead_algorithm: &ring::aead::CHACHA20_POLY1305,
+        tls12: Some(Tls12Parameters {
+            kx: KeyExchangeAlgorithm::ECDHE_PSK,
+            sign: &[],
+            fixed_iv_len: 12,
+            explicit_nonce_len: 0,
+            build_tls12_encrypter: cipher::build_tls12_chacha_encrypter,
+            build_tls12_decrypter: cipher::build_tls12_chacha_decrypter,
+        }),
+    };
+
+/// The TLS1.2 ciphersuite TLS_ECDHE_PSK_WITH_AES_128_GCM_SHA256.
+///
+/// This isn't in `ALL_CIPHERSUITES`: it is only useful alongside a
+/// configured external PSK.
+pub static TLS_ECDHE_PSK_WITH_AES_128_GCM_SHA256: SupportedCipherSuite = SupportedCipherSuite {
+    suite: CipherSuite::TLS_ECDHE_PSK_WITH_AES_128_GCM_SHA256,
+    bulk: BulkAlgorithm::Aes128Gcm,
+    hkdf_algorithm: ring::hkdf::HKDF_SHA256,
+    aead_algorithm: &ring::aead::AES_128_GCM,
+    tls12: Some(Tls12Parameters {
+        kx: KeyExchangeAlgorithm::ECDHE_PSK,
+        sign: &[],
+        fixed_iv_len: 4,
+        explicit_nonce_len: 8,
+        build_tls12_encrypter: cipher::build_tls12_gcm_encrypter,
+        build_tls12_decrypter: cipher::build_tls12_gcm_decrypter,
+    }),
+};
+
+/// The TLS1.2 ciphersuite TLS_ECDHE_PSK_WITH_AES_256_GCM_SHA384.
+///
+/// This isn't in `ALL_CIPHERSUITES`: it is only useful alongside a
+/// configured external PSK.
+pub static TLS_ECDHE_PSK_WITH_AES_256_GCM_SHA384: SupportedCipherSuite = SupportedCipherSuite {
+    suite: CipherSuite::TLS_ECDHE_PSK_WITH_AES_256_GCM_SHA384,
+    bulk: BulkAlgorithm::Aes256Gcm,
+    hkdf_algorithm: ring::hkdf::HKDF_SHA384,
+    aead_algorithm: &ring::aead::AES_256_GCM,
+    tls12: Some(Tls12Parameters {
+        kx: KeyExchangeAlgorithm::ECDHE_PSK,
+        sign: &[],
+        fixed_iv_len: 4,
+        explicit_nonce_len: 8,
+        build_tls12_encrypter: cipher::build_tls12_gcm_encrypter,
+        build_tls12_decrypter: cipher::build_tls12_gcm_decrypter,
+    }),
+};
+
 /// The TLS1.3 ciphersuite TLS_CHACHA20_POLY1305_SHA256
 pub static TLS13_CHACHA20_POLY1305_SHA256: SupportedCipherSuite = SupportedCipherSuite {
     suite: CipherSuite::TLS13_CHACHA20_POLY1305_SHA256,
@@ -366,18 +452,6 @@ pub fn choose_ciphersuite_preferring_server(
     None
 }
 
-/// Return a list of the ciphersuites in `all` with the suites
-/// incompatible with `SignatureAlgorithm` `sigalg` removed.
-pub fn reduce_given_sigalg(
-    all: &[&'static SupportedCipherSuite],
-    sigalg: SignatureAlgorithm,
-) -> Vec<&'static SupportedCipherSuite> {
-    all.iter()
-        .filter(|&&suite| suite.usable_for_sigalg(sigalg))
-        .cloned()
-        .collect()
-}
-
 /// Return a list of the ciphersuites in `all` with the suites
 /// incompatible with the chosen `version` removed.
 pub fn reduce_given_version(
@@ -482,6 +556,38 @@ mod test {
         ok_tls12(&TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384);
         ok_tls12(&TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256);
         ok_tls12(&TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384);
+        ok_tls12(&TLS_ECDHE_PSK_WITH_AES_128_GCM_SHA256);
+        ok_tls12(&TLS_ECDHE_PSK_WITH_AES_256_GCM_SHA384);
+        ok_tls12(&TLS_ECDHE_PSK_WITH_CHACHA20_POLY1305_SHA256);
+    }
+
+    #[test]
+    fn test_uses_psk() {
+        assert!(TLS_ECDHE_PSK_WITH_AES_128_GCM_SHA256.uses_psk());
+        assert!(TLS_ECDHE_PSK_WITH_AES_256_GCM_SHA384.uses_psk());
+        assert!(TLS_ECDHE_PSK_WITH_CHACHA20_POLY1305_SHA256.uses_psk());
+        assert!(!ALL_CIPHERSUITES
+            .iter()
+            .any(|scs| scs.uses_psk()));
+    }
+
+    #[test]
+    fn test_allows_false_start() {
+        let allows = |scs: &'static SupportedCipherSuite| {
+            Tls12CipherSuite::try_from(scs)
+                .unwrap()
+                .allows_false_start()
+        };
+
+        for scs in ALL_CIPHERSUITES
+            .iter()
+            .filter(|scs| scs.usable_for_version(ProtocolVersion::TLSv1_2))
+        {
+            assert!(allows(scs));
+        }
+        assert!(!allows(&TLS_ECDHE_PSK_WITH_AES_128_GCM_SHA256));
+        assert!(!allows(&TLS_ECDHE_PSK_WITH_AES_256_GCM_SHA384));
+        assert!(!allows(&TLS_ECDHE_PSK_WITH_CHACHA20_POLY1305_SHA256));
     }
 
     #[test]
@@ -1,5 +1,6 @@
 use crate::rand;
 use crate::server::ProducesTickets;
+use crate::time_provider::TimeProvider;
 use crate::Error;
 
 use ring::aead;
@@ -22,6 +23,14 @@ impl TimeBase {
         ))
     }
 
+    /// The current time, according to `provider`.
+    pub fn from_provider(provider: &dyn TimeProvider) -> Result<Self, Error> {
+        let now = provider
+            .current_time()
+            .ok_or(Error::FailedToGetCurrentTime)?;
+        Ok(Self(now.duration_since(time::UNIX_EPOCH)?))
+    }
+
     #[inline]
     pub fn as_secs(&self) -> u64 {
         self.0.as_secs()
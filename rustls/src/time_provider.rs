@@ -0,0 +1,27 @@
+use std::time::SystemTime;
+
+/// This trait represents a source of the current wall-clock time.
+///
+/// It is consulted when verifying certificates (and any SCTs or OCSP
+/// responses stapled to them), and when deciding whether a stored
+/// session has expired.
+///
+/// Targets without a system clock, or without one that can be trusted,
+/// can supply the time from elsewhere: for example from NTP, or from an
+/// attested source.  Tests can use it to fix the time.
+pub trait TimeProvider: Send + Sync {
+    /// Returns the current time, or `None` if it is not known.
+    ///
+    /// If this returns `None`, operations needing the time fail with
+    /// `Error::FailedToGetCurrentTime`.
+    fn current_time(&self) -> Option<SystemTime>;
+}
+
+/// TimeProvider which reads the system clock.
+pub struct DefaultTimeProvider;
+
+impl TimeProvider for DefaultTimeProvider {
+    fn current_time(&self) -> Option<SystemTime> {
+        Some(SystemTime::now())
+    }
+}
@@ -31,6 +31,17 @@ pub fn complete_ecdh(
         .ok_or_else(|| Error::PeerMisbehavedError("key agreement failed".to_string()))
 }
 
+/// Build the premaster secret for an ECDHE_PSK ciphersuite from the
+/// ECDHE shared secret and the PSK, per RFC5489 section 2.
+pub fn ecdhe_psk_premaster_secret(shared_secret: &[u8], psk: &[u8]) -> Vec<u8> {
+    let mut pms = Vec::with_capacity(4 + shared_secret.len() + psk.len());
+    (shared_secret.len() as u16).encode(&mut pms);
+    pms.extend_from_slice(shared_secret);
+    (psk.len() as u16).encode(&mut pms);
+    pms.extend_from_slice(psk);
+    pms
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -50,4 +61,12 @@ mod tests {
     fn client_ecdhe_invalid() {
         assert!(decode_ecdh_params_::<ClientECDHParams>(&[34]).is_none());
     }
+
+    #[test]
+    fn ecdhe_psk_premaster_secret_layout() {
+        assert_eq!(
+            ecdhe_psk_premaster_secret(&[1, 2, 3], &[4, 5]),
+            vec![0, 3, 1, 2, 3, 0, 2, 4, 5]
+        );
+    }
 }
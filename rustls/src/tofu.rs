@@ -0,0 +1,124 @@
+// Trust-on-first-use authentication of servers, as SSH clients do.
+
+use crate::client::ServerName;
+use crate::error::{Error, WebPkiOp};
+use crate::key::Certificate;
+#[cfg(feature = "logging")]
+use crate::log::{debug, warn};
+use crate::verify::{ServerCertVerified, ServerCertVerifier};
+use crate::x509;
+use parking_lot::Mutex;
+use ring::digest;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Somewhere a [`TofuVerifier`] records the key each server presented
+/// the first time it was seen.
+///
+/// Keys are the SHA-256 hash of the DER-encoded `SubjectPublicKeyInfo`
+/// of the server's end-entity certificate.  Implementations persisting
+/// these to a file or database must be safe to share between threads.
+pub trait TofuStore: Send + Sync {
+    /// Returns the key hash recorded for `host`, or `None` if there
+    /// isn't one.
+    fn get(&self, host: &str) -> Option<Vec<u8>>;
+
+    /// Records `spki_sha256` for `host`, which has no key recorded.
+    /// Returns `true` if it was stored.
+    fn put(&self, host: &str, spki_sha256: Vec<u8>) -> bool;
+}
+
+/// A `TofuStore` which keeps keys in memory, and so forgets them when
+/// dropped.
+#[derive(Default)]
+pub struct TofuMemoryStore {
+    keys: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl TofuMemoryStore {
+    /// Make an empty `TofuMemoryStore`.
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+}
+
+impl TofuStore for TofuMemoryStore {
+    fn get(&self, host: &str) -> Option<Vec<u8>> {
+        self.keys.lock().get(host).cloned()
+    }
+
+    fn put(&self, host: &str, spki_sha256: Vec<u8>) -> bool {
+        self.keys
+            .lock()
+            .insert(host.to_string(), spki_sha256);
+        true
+    }
+}
+
+/// A `ServerCertVerifier` which trusts the key a server presents the
+/// first time it is seen, and rejects any other key from that server
+/// thereafter with `Error::ServerKeyChanged`.
+///
+/// Only the key matters: the certificate's issuer, names and validity
+/// period are not checked, so servers may use self-signed certificates.
+/// Servers are identified by the name the client connects to.
+///
+/// If the key can't be recorded in the `TofuStore`, the certificate is
+/// rejected: the next connection would have nothing to compare against.
+pub struct TofuVerifier {
+    store: Arc<dyn TofuStore>,
+}
+
+impl TofuVerifier {
+    /// Constructs a `TofuVerifier` recording keys in `store`.
+    pub fn new(store: Arc<dyn TofuStore>) -> Self {
+        Self { store }
+    }
+}
+
+impl ServerCertVerifier for TofuVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let fields = x509::CertFields::parse(&end_entity.0).ok_or(Error::WebPkiError(
+            webpki::Error::BadDer,
+            WebPkiOp::ParseEndEntity,
+        ))?;
+        let mut spki = fields.spki.to_vec();
+        x509::wrap_in_sequence(&mut spki);
+        let spki_sha256 = digest::digest(&digest::SHA256, &spki);
+
+        let host = server_name.to_str();
+        match self.store.get(&host) {
+            Some(recorded) if recorded == spki_sha256.as_ref() => {
+                Ok(ServerCertVerified::assertion())
+            }
+            Some(_) => {
+                warn!("key for {} differs from the one first seen", host);
+                Err(Error::ServerKeyChanged)
+            }
+            None => {
+                debug!("trusting key for {} on first use", host);
+                if self
+                    .store
+                    .put(&host, spki_sha256.as_ref().to_vec())
+                {
+                    Ok(ServerCertVerified::assertion())
+                } else {
+                    Err(Error::General(format!("cannot record key for {}", host)))
+                }
+            }
+        }
+    }
+
+    fn request_scts(&self) -> bool {
+        false
+    }
+}
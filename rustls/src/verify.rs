@@ -1,25 +1,37 @@
-use parking_lot::RwLock;
+use arc_swap::ArcSwap;
+use parking_lot::{Mutex, RwLock};
 use ring::digest::Digest;
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
-use std::ops::{Deref, DerefMut};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::time::{Duration, Instant, SystemTime};
 
+use crate::aia::{self, IntermediateFetcher, KnownIntermediates};
 use crate::anchors::OwnedTrustAnchor;
 use crate::anchors::{DistinguishedNames, RootCertStore};
+use crate::client::ServerName;
+use crate::ct::{self, OwnedCtLog, SctSource, SctSources};
 use crate::error::Error;
 use crate::error::WebPkiOp;
 use crate::key::Certificate;
 #[cfg(feature = "logging")]
 use crate::log::{debug, trace, warn};
-use crate::msgs::enums::SignatureScheme;
+use crate::msgs::base::PayloadU16;
+use crate::msgs::codec::{Codec, Reader};
+use crate::msgs::enums::{HashAlgorithm, ProtocolVersion, SignatureAlgorithm, SignatureScheme};
 use crate::msgs::handshake::DigitallySignedStruct;
+use crate::ocsp::{self, CertStatus, OcspFreshness, OcspPolicy, OcspUnknownStatus};
+use crate::revocation::RevocationFilter;
+use crate::x509;
 
-type SignatureAlgorithms = &'static [&'static webpki::SignatureAlgorithm];
+pub(crate) type SignatureAlgorithms = &'static [&'static webpki::SignatureAlgorithm];
 
 /// Which signature verification mechanisms we support.  No particular
 /// order.
-static SUPPORTED_SIG_ALGS: SignatureAlgorithms = &[
+pub(crate) static SUPPORTED_SIG_ALGS: SignatureAlgorithms = &[
     &webpki::ECDSA_P256_SHA256,
     &webpki::ECDSA_P256_SHA384,
     &webpki::ECDSA_P384_SHA256,
@@ -67,6 +79,95 @@ impl ServerCertVerified {
     }
 }
 
+/// The outcome of `ServerCertVerifier::start_verify_server_cert`.
+#[cfg_attr(not(feature = "dangerous_configuration"), allow(dead_code))]
+pub enum ServerCertVerification {
+    /// Verification has finished, with this result.
+    Complete(Result<ServerCertVerified, Error>),
+
+    /// Verification is still in progress.  The handshake waits until
+    /// the `PendingVerification` is completed.
+    Pending(PendingVerification),
+}
+
+/// A server certificate verification which has not finished yet.
+///
+/// This is a handle shared between the verifier and the connection:
+/// the verifier keeps a clone, and calls `complete` once it has
+/// reached a verdict.
+#[derive(Clone, Default)]
+pub struct PendingVerification(Arc<Mutex<VerificationOutcome>>);
+
+/// A function applied to the result of a `PendingVerification`, and
+/// the time it was completed.
+type VerificationMap = Box<
+    dyn FnOnce(Result<ServerCertVerified, Error>, Instant) -> Result<ServerCertVerified, Error>
+        + Send,
+>;
+
+#[cfg_attr(not(feature = "dangerous_configuration"), allow(dead_code))]
+#[derive(Default)]
+struct VerificationOutcome {
+    completed_at: Option<Instant>,
+    result: Option<Result<ServerCertVerified, Error>>,
+    /// Applied in order by `take`, so wrapping verifiers can process
+    /// the inner verifier's result.
+    maps: Vec<VerificationMap>,
+}
+
+#[cfg_attr(not(feature = "dangerous_configuration"), allow(dead_code))]
+impl PendingVerification {
+    /// Make a new, incomplete, `PendingVerification`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Finish the verification with `result`.
+    ///
+    /// Only the first call has any effect.  The connection continues
+    /// the handshake the next time its `process_new_packets` is called.
+    pub fn complete(&self, result: Result<ServerCertVerified, Error>) {
+        let mut outcome = self.0.lock();
+        if outcome.completed_at.is_none() {
+            outcome.completed_at = Some(Instant::now());
+            outcome.result = Some(result);
+        }
+    }
+
+    /// Returns `true` once `complete` has been called.
+    pub fn is_complete(&self) -> bool {
+        self.0.lock().completed_at.is_some()
+    }
+
+    /// Replace the result with `map(result, completed_at)` when it is
+    /// taken, where `completed_at` is when `complete` was called.
+    pub(crate) fn map(
+        &self,
+        map: impl FnOnce(Result<ServerCertVerified, Error>, Instant) -> Result<ServerCertVerified, Error>
+            + Send
+            + 'static,
+    ) {
+        self.0.lock().maps.push(Box::new(map));
+    }
+
+    /// Take the result, if the verification has completed.
+    ///
+    /// Maps are applied without holding the lock, since they may run
+    /// user callbacks.
+    pub(crate) fn take(&self) -> Option<Result<ServerCertVerified, Error>> {
+        let (result, completed_at, maps) = {
+            let mut outcome = self.0.lock();
+            let result = outcome.result.take()?;
+            let completed_at = outcome.completed_at?;
+            (result, completed_at, std::mem::take(&mut outcome.maps))
+        };
+        Some(
+            maps.into_iter()
+                .fold(result, |result, map| map(result, completed_at)),
+        )
+    }
+}
+
 /// Zero-sized marker type representing verification of a client cert chain.
 pub struct ClientCertVerified(());
 impl ClientCertVerified {
@@ -80,7 +181,7 @@ impl ClientCertVerified {
 /// signatures made by certificates.
 pub trait ServerCertVerifier: Send + Sync {
     /// Verify the end-entity certificate `end_entity` is valid for the
-    /// hostname `dns_name` and chains to at least one trust anchor.
+    /// name `server_name` and chains to at least one trust anchor.
     ///
     /// `intermediates` contains the intermediate certificates the client sent
     /// along with the end-entity certificate; it is in the same order that the
@@ -92,12 +193,41 @@ pub trait ServerCertVerifier: Send + Sync {
         &self,
         end_entity: &Certificate,
         intermediates: &[Certificate],
-        dns_name: webpki::DnsNameRef,
+        server_name: &ServerName,
         scts: &mut dyn Iterator<Item = &[u8]>,
         ocsp_response: &[u8],
         now: SystemTime,
     ) -> Result<ServerCertVerified, Error>;
 
+    /// Start verifying the end-entity certificate, for verifiers which
+    /// may need to wait -- for example, on network I/O -- before reaching
+    /// a verdict.  The arguments are as for `verify_server_cert`.
+    ///
+    /// Returning `ServerCertVerification::Pending` suspends the handshake
+    /// until the `PendingVerification` is completed; in the meantime
+    /// `ClientConnection::is_verification_pending` returns true.
+    ///
+    /// This trait method has a default implementation that calls
+    /// `verify_server_cert` and returns its result.
+    fn start_verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> ServerCertVerification {
+        ServerCertVerification::Complete(self.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        ))
+    }
+
     /// Verify a signature allegedly by the given server certificate.
     ///
     /// `message` is not hashed, and needs hashing during the verification.
@@ -166,6 +296,55 @@ pub trait ServerCertVerifier: Send + Sync {
     fn request_scts(&self) -> bool {
         true
     }
+
+    /// Returns `true` if Rustls should offer to accept a raw public key
+    /// (RFC7250) from the server in place of a certificate chain.
+    ///
+    /// If the server sends one, `verify_server_raw_public_key` is called
+    /// instead of `verify_server_cert`, and `verify_tls12_signature` and
+    /// `verify_tls13_signature` are given the DER-encoded
+    /// `SubjectPublicKeyInfo` in place of a certificate.
+    ///
+    /// The default implementation of this function returns false.
+    fn offer_raw_public_keys(&self) -> bool {
+        false
+    }
+
+    /// Verify `spki`, the DER-encoded `SubjectPublicKeyInfo` the server
+    /// sent instead of a certificate chain, is acceptable for
+    /// `server_name`.
+    ///
+    /// This is only called if `offer_raw_public_keys` returns true.  The
+    /// default implementation rejects every key.
+    fn verify_server_raw_public_key(
+        &self,
+        _spki: &[u8],
+        _server_name: &ServerName,
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        Err(Error::PeerIncompatibleError(
+            "raw public keys not supported".to_string(),
+        ))
+    }
+}
+
+/// Details of the handshake in which a client is authenticated, for
+/// [`ClientCertVerifier::client_auth_mandatory_in_context`] and
+/// [`ClientCertVerifier::verify_client_cert_in_context`].
+#[non_exhaustive]
+pub struct ClientCertVerifyContext<'a> {
+    /// The server name quoted by the client in its ClientHello; it has
+    /// been validated as a proper DNS name but is otherwise untrusted.
+    pub sni: Option<&'a webpki::DnsName>,
+    /// The negotiated ALPN protocol, if any.
+    pub alpn_protocol: Option<&'a [u8]>,
+    /// The negotiated TLS version.
+    pub protocol_version: ProtocolVersion,
+    /// The client's address, if given with `ServerConnection::set_peer_addr`.
+    pub peer_addr: Option<SocketAddr>,
+    /// Data the application attached to the connection with
+    /// `ServerConnection::set_verifier_data`, if any.
+    pub user_data: Option<&'a (dyn Any + Send + Sync)>,
 }
 
 /// Something that can verify a client certificate chain
@@ -176,6 +355,17 @@ pub trait ClientCertVerifier: Send + Sync {
         true
     }
 
+    /// Like `offer_client_auth`, but for a client connecting to `sni`.
+    /// Return `false` to skip requesting a client certificate from it.
+    /// This is what rustls calls when deciding whether to send a
+    /// CertificateRequest.
+    ///
+    /// This must return `false` whenever `offer_client_auth` does.
+    /// Defaults to `self.offer_client_auth()`.
+    fn offer_client_auth_for_sni(&self, _sni: Option<&webpki::DnsName>) -> bool {
+        self.offer_client_auth()
+    }
+
     /// Return `Some(true)` to require a client certificate and `Some(false)` to make
     /// client authentication optional. Return `None` to abort the connection.
     /// Defaults to `Some(self.offer_client_auth())`.
@@ -215,6 +405,30 @@ pub trait ClientCertVerifier: Send + Sync {
         now: SystemTime,
     ) -> Result<ClientCertVerified, Error>;
 
+    /// Like `client_auth_mandatory`, but given more details of the
+    /// handshake, such as the negotiated ALPN protocol.  This is what
+    /// rustls calls.
+    ///
+    /// Defaults to `self.client_auth_mandatory(cx.sni)`.
+    fn client_auth_mandatory_in_context(&self, cx: &ClientCertVerifyContext) -> Option<bool> {
+        self.client_auth_mandatory(cx.sni)
+    }
+
+    /// Like `verify_client_cert`, but given more details of the
+    /// handshake, such as the negotiated ALPN protocol.  This is what
+    /// rustls calls.
+    ///
+    /// Defaults to `self.verify_client_cert(end_entity, intermediates, cx.sni, now)`.
+    fn verify_client_cert_in_context(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        cx: &ClientCertVerifyContext,
+        now: SystemTime,
+    ) -> Result<ClientCertVerified, Error> {
+        self.verify_client_cert(end_entity, intermediates, cx.sni, now)
+    }
+
     /// Verify a signature allegedly by the given server certificate.
     ///
     /// `message` is not hashed, and needs hashing during the verification.
@@ -280,45 +494,415 @@ impl ServerCertVerifier for WebPkiVerifier {
     /// - Signed by a  trusted `RootCertStore` CA
     /// - Not Expired
     /// - Valid for DNS entry
-    /// - OCSP data is present
+    /// - Not revoked, according to any stapled OCSP response, if an
+    ///   `OcspPolicy` is configured
     fn verify_server_cert(
         &self,
         end_entity: &Certificate,
         intermediates: &[Certificate],
-        dns_name: webpki::DnsNameRef,
+        server_name: &ServerName,
         scts: &mut dyn Iterator<Item = &[u8]>,
         ocsp_response: &[u8],
         now: SystemTime,
     ) -> Result<ServerCertVerified, Error> {
-        let (cert, chain, trustroots) = prepare(end_entity, intermediates, &self.roots)?;
-        let webpki_now = webpki::Time::try_from(now).map_err(|_| Error::FailedToGetCurrentTime)?;
+        let verified = self.verify_server_cert_detailed(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        )?;
+        *self.last_verification.lock() = Some(verified);
+        Ok(ServerCertVerified::assertion())
+    }
 
-        let cert = cert
-            .verify_is_valid_tls_server_cert(
-                SUPPORTED_SIG_ALGS,
-                &webpki::TlsServerTrustAnchors(&trustroots),
-                &chain,
-                webpki_now,
-            )
-            .map_err(|e| Error::WebPkiError(e, WebPkiOp::ValidateServerCert))
-            .map(|_| cert)?;
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.check_signature_scheme(dss.scheme)?;
+        verify_signed_struct(message, cert, dss)
+    }
 
-        verify_scts(end_entity, now, scts, &self.ct_logs)?;
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.check_signature_scheme(dss.scheme)?;
+        verify_tls13(message, cert, dss)
+    }
 
-        if !ocsp_response.is_empty() {
-            trace!("Unvalidated OCSP response: {:?}", ocsp_response.to_vec());
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        match &self.signature_schemes {
+            Some(schemes) => schemes.clone(),
+            None => Self::verification_schemes(),
         }
+    }
 
-        cert.verify_is_valid_for_dns_name(dns_name)
-            .map_err(|e| Error::WebPkiError(e, WebPkiOp::ValidateForDnsName))
-            .map(|_| ServerCertVerified::assertion())
+    fn request_scts(&self) -> bool {
+        self.request_scts
     }
 }
 
+/// Called with the end-entity certificate and the reason for each OCSP
+/// soft failure; see `WebPkiVerifier::with_ocsp_soft_fail`.
+type OcspSoftFailObserver = dyn Fn(&Certificate, &Error) + Send + Sync;
+
 /// Default `ServerCertVerifier`, see the trait impl for more information.
 pub struct WebPkiVerifier {
-    roots: RootCertStore,
-    ct_logs: &'static [&'static sct::Log<'static>],
+    roots: RwLock<VersionedRoots>,
+    ct_logs: RwLock<Arc<[OwnedCtLog]>>,
+    ct_policy: CtPolicy,
+    ocsp_policy: Option<OcspPolicy>,
+    ocsp_soft_fail: Option<Box<OcspSoftFailObserver>>,
+    anchor_usage: Option<Mutex<AnchorUsageTracker>>,
+    allowed_key_algorithms: Option<Vec<SignatureAlgorithm>>,
+    validity_policy: Option<ValidityPolicy>,
+    min_not_before: Option<SystemTime>,
+    clock_skew_tolerance: Option<Duration>,
+    expiry_override_pins: Vec<[u8; 32]>,
+    check_aki_ski: bool,
+    check_eku_chaining: bool,
+    enforce_must_staple: bool,
+    public_suffixes: Option<Vec<String>>,
+    min_serial_octets: Option<usize>,
+    known_intermediates: KnownIntermediates,
+    intermediate_fetcher: Option<Arc<dyn IntermediateFetcher>>,
+    revocation_filter: Option<Arc<dyn RevocationFilter>>,
+    signature_schemes: Option<Vec<SignatureScheme>>,
+    request_scts: bool,
+    signature_algorithms: SignatureAlgorithms,
+    last_verification: Mutex<Option<VerifiedServerCert>>,
+}
+
+/// A builder for a [`WebPkiVerifier`], for when the defaults chosen by
+/// [`WebPkiVerifier::new`] aren't wanted.
+///
+/// Only `roots` is required: by default, no CT logs are trusted, stapled
+/// OCSP responses are ignored, every signature scheme webpki supports is
+/// accepted, and SCTs are requested.
+///
+/// Each `with_` method of `WebPkiVerifier` is a shorthand for the one of
+/// the same name here.
+pub struct WebPkiVerifierBuilder {
+    verifier: WebPkiVerifier,
+}
+
+impl WebPkiVerifierBuilder {
+    /// Start building a verifier trusting `roots` to issue server
+    /// certificates.
+    pub fn new(roots: RootCertStore) -> Self {
+        Self {
+            verifier: WebPkiVerifier {
+                roots: RwLock::new(VersionedRoots {
+                    store: Arc::new(roots),
+                    generation: 0,
+                }),
+                ct_logs: RwLock::new(Arc::new([])),
+                ct_policy: CtPolicy::new(),
+                ocsp_policy: None,
+                ocsp_soft_fail: None,
+                anchor_usage: None,
+                allowed_key_algorithms: None,
+                validity_policy: None,
+                min_not_before: None,
+                clock_skew_tolerance: None,
+                expiry_override_pins: Vec::new(),
+                check_aki_ski: false,
+                check_eku_chaining: false,
+                enforce_must_staple: false,
+                public_suffixes: None,
+                min_serial_octets: None,
+                known_intermediates: KnownIntermediates::default(),
+                intermediate_fetcher: None,
+                revocation_filter: None,
+                signature_schemes: None,
+                request_scts: true,
+                signature_algorithms: SUPPORTED_SIG_ALGS,
+                last_verification: Mutex::new(None),
+            },
+        }
+    }
+
+    /// Trust `ct_logs` for Certificate Transparency.
+    ///
+    /// See [`WebPkiVerifier::new_with_ct_logs`].
+    pub fn with_ct_logs(mut self, ct_logs: Arc<[OwnedCtLog]>) -> Self {
+        self.verifier.ct_logs = RwLock::new(ct_logs);
+        self
+    }
+
+    /// Validate SCTs sent by the server according to `policy`, rather
+    /// than `CtPolicy::new()`.
+    ///
+    /// This has no effect if there are no `ct_logs`.
+    pub fn with_ct_policy(mut self, policy: CtPolicy) -> Self {
+        self.verifier.ct_policy = policy;
+        self
+    }
+
+    /// Validate OCSP responses stapled by the server according to `policy`,
+    /// and reject certificates they say are revoked.
+    ///
+    /// Without an `OcspPolicy`, stapled responses are ignored.
+    pub fn with_ocsp_policy(mut self, policy: OcspPolicy) -> Self {
+        self.verifier.ocsp_policy = Some(policy);
+        self
+    }
+
+    /// Reject certificates which `filter` says are revoked, with
+    /// `Error::CertificateRevoked`.
+    ///
+    /// Each certificate in the chain other than the trust anchor is
+    /// checked, before any stapled OCSP response.  A filter such as a
+    /// [`RevocationFilterCascade`](crate::RevocationFilterCascade) gives
+    /// revocation checking without network access during the handshake.
+    pub fn with_revocation_filter(mut self, filter: Arc<dyn RevocationFilter>) -> Self {
+        self.verifier.revocation_filter = Some(filter);
+        self
+    }
+
+    /// Reject end-entity certificates whose TLS feature extension
+    /// requires OCSP stapling (RFC7633 "must-staple") if the server
+    /// doesn't staple a response, with `Error::OcspStapleRequired`.
+    ///
+    /// The stapled response must be valid for the certificate, even
+    /// with `with_ocsp_soft_fail`.  It is validated with the verifier's
+    /// `OcspPolicy`, or with `OcspPolicy::new()` if it has none.
+    pub fn with_must_staple(mut self) -> Self {
+        self.verifier.enforce_must_staple = true;
+        self
+    }
+
+    /// Accept certificates whose stapled OCSP response is invalid,
+    /// calling `observer` with the end-entity certificate and the reason
+    /// each time.  `observer` is also called for an `unknown` status the
+    /// `OcspPolicy` warns about, but not for one it accepts.
+    ///
+    /// The observer is required so that soft failures can't go
+    /// unnoticed.  Responses saying the certificate is revoked are still
+    /// rejected, as is an `unknown` status if the `OcspPolicy` rejects
+    /// it.  This has no effect without an `OcspPolicy`.
+    pub fn with_ocsp_soft_fail(
+        mut self,
+        observer: impl Fn(&Certificate, &Error) + Send + Sync + 'static,
+    ) -> Self {
+        self.verifier.ocsp_soft_fail = Some(Box::new(observer));
+        self
+    }
+
+    /// Only accept end-entity certificates whose public key is one of
+    /// the `allowed` families, such as `SignatureAlgorithm::ECDSA`.
+    ///
+    /// This constrains the key itself, regardless of the signature
+    /// schemes used with it.  Certificates with other keys are rejected
+    /// with `Error::DisallowedKeyAlgorithm`.
+    pub fn with_allowed_key_algorithms(mut self, allowed: &[SignatureAlgorithm]) -> Self {
+        self.verifier.allowed_key_algorithms = Some(allowed.to_vec());
+        self
+    }
+
+    /// Limit the validity period of end-entity certificates issued
+    /// recently enough, according to `policy`.  Certificates whose
+    /// validity is too long are rejected with
+    /// `Error::CertificateValidityTooLong`.
+    pub fn with_validity_policy(mut self, policy: ValidityPolicy) -> Self {
+        self.verifier.validity_policy = Some(policy);
+        self
+    }
+
+    /// Reject end-entity certificates whose `notBefore` is earlier than
+    /// `cutoff`, with `Error::CertificateIssuedTooEarly`.
+    ///
+    /// This distrusts everything a CA issued before some date, such as
+    /// that of a key compromise, without listing serial numbers.
+    pub fn with_min_notbefore(mut self, cutoff: SystemTime) -> Self {
+        self.verifier.min_not_before = Some(cutoff);
+        self
+    }
+
+    /// Accept certificates which are not yet valid, or have expired,
+    /// by up to `tolerance`, to allow for a client's clock being wrong.
+    ///
+    /// A chain failing validation because of its validity period is
+    /// validated again with the time moved by `tolerance`, so all of its
+    /// other checks still apply.
+    pub fn with_clock_skew_tolerance(mut self, tolerance: Duration) -> Self {
+        self.verifier.clock_skew_tolerance = Some(tolerance);
+        self
+    }
+
+    /// Accept expired chains whose end-entity key, or the key of the
+    /// trust anchor they lead to, is pinned: for fleets of devices with
+    /// long-lived certificates which aren't renewed.
+    ///
+    /// Each pin is the SHA-256 hash of a DER-encoded
+    /// `SubjectPublicKeyInfo`, as in [`VerifiedServerCert::end_entity_spki`].
+    /// A pinned chain which has expired is validated again at a time
+    /// when its certificates were valid, so all of its other checks
+    /// still apply.  Chains without a pinned key are validated as
+    /// usual.
+    pub fn with_expiry_override_pins(mut self, pins: &[[u8; 32]]) -> Self {
+        self.verifier.expiry_override_pins = pins.to_vec();
+        self
+    }
+
+    /// Reject end-entity certificates with a wildcard name whose
+    /// remainder is one of `suffixes`, such as `*.co.uk` when `suffixes`
+    /// contains `co.uk`, with `Error::WildcardSpansPublicSuffix`.
+    ///
+    /// `suffixes` would usually come from the Public Suffix List, but
+    /// only plain suffixes are understood: its wildcard and exception
+    /// rules must be expanded by the caller.  A wildcard over a single
+    /// label, such as `*.com`, is always rejected.
+    pub fn with_public_suffixes(mut self, suffixes: &[&str]) -> Self {
+        self.verifier.public_suffixes = Some(
+            suffixes
+                .iter()
+                .map(|suffix| {
+                    suffix
+                        .trim_end_matches('.')
+                        .to_ascii_lowercase()
+                })
+                .collect(),
+        );
+        self
+    }
+
+    /// Check that the end-entity certificate's serial number is
+    /// positive, is encoded in at most 20 octets, and has at least
+    /// `min_octets` significant octets.
+    ///
+    /// webpki rejects most malformed serial numbers itself, with
+    /// `Error::WebPkiError`; this reports them with
+    /// `Error::InvalidSerialNumber` instead.  The CA/Browser Forum
+    /// baseline requirements call for 64 bits of entropy, so a
+    /// `min_octets` of 8 is a reasonable check of that.  Some private
+    /// CAs issue small sequential serial numbers, so this is off by
+    /// default.
+    pub fn with_serial_number_check(mut self, min_octets: usize) -> Self {
+        self.verifier.min_serial_octets = Some(min_octets);
+        self
+    }
+
+    /// Check that the authority key identifier of each certificate in
+    /// the chain matches the subject key identifier of its issuer, where
+    /// both are present.
+    ///
+    /// webpki doesn't use key identifiers to build chains, so a mismatch
+    /// does not stop a chain validating.  But other implementations do
+    /// use them, so a mismatch (usually caused by a server sending the
+    /// wrong intermediate) is rejected with `Error::AkiSkiMismatch`
+    /// if this is enabled.
+    pub fn with_aki_ski_check(mut self) -> Self {
+        self.verifier.check_aki_ski = true;
+        self
+    }
+
+    /// Check that every intermediate certificate sent by the server
+    /// which has an extended key usage extension allows `serverAuth`.
+    ///
+    /// webpki applies this to the certificates on the path it builds,
+    /// but reports only that no valid path was found.  This instead
+    /// checks every intermediate sent, before building a path, and
+    /// rejects an intermediate scoped to other usages (such as
+    /// `clientAuth`) with `Error::EkuNotChained`.
+    pub fn with_eku_chaining_check(mut self) -> Self {
+        self.verifier.check_eku_chaining = true;
+        self
+    }
+
+    /// Only accept certificate chains signed using `algorithms`, rather
+    /// than any algorithm webpki supports: for example, to reject
+    /// RSA PKCS#1 signatures, or to require P-384.
+    ///
+    /// webpki finds no valid path through chains signed otherwise, so
+    /// they are usually rejected as having an unknown issuer.  This
+    /// doesn't affect the schemes accepted
+    /// for handshake signatures; see
+    /// [`WebPkiVerifierBuilder::with_signature_schemes`] for those.
+    pub fn with_signature_algorithms(
+        mut self,
+        algorithms: &'static [&'static webpki::SignatureAlgorithm],
+    ) -> Self {
+        self.verifier.signature_algorithms = algorithms;
+        self
+    }
+
+    /// Complete server chains missing intermediates using
+    /// `intermediates`, such as Mozilla's intermediate preload list,
+    /// when no path to a trust anchor can be built from those the
+    /// server sent.
+    ///
+    /// Certificates which can't be parsed are ignored.  Those used
+    /// follow the intermediates the server sent, so appear in
+    /// [`VerifiedServerCert::path`] as `ChainPosition::Intermediate`
+    /// with indices beyond them.  They are tried before any
+    /// [`IntermediateFetcher`].
+    pub fn with_known_intermediates(mut self, intermediates: &[Certificate]) -> Self {
+        self.verifier.known_intermediates = KnownIntermediates::new(intermediates);
+        self
+    }
+
+    /// Ask `fetcher` for intermediates missing from a server's chain,
+    /// when no path to a trust anchor can be built from those it sent.
+    ///
+    /// The fetcher is only consulted for certificates with `caIssuers`
+    /// URLs whose issuer is neither sent by the server nor a trust
+    /// anchor.  The certificates it supplies follow those the server
+    /// sent, so appear in [`VerifiedServerCert::path`] as
+    /// `ChainPosition::Intermediate` with indices beyond them.
+    pub fn with_intermediate_fetcher(mut self, fetcher: Arc<dyn IntermediateFetcher>) -> Self {
+        self.verifier.intermediate_fetcher = Some(fetcher);
+        self
+    }
+
+    /// Record which trust anchor each validated certificate chain
+    /// terminates at.
+    ///
+    /// This has a cost: each successful validation is repeated against
+    /// the candidate anchors to find the one actually used.  The results
+    /// are available from [`WebPkiVerifier::anchor_usage_stats`].
+    pub fn with_anchor_usage_tracking(mut self) -> Self {
+        self.verifier.anchor_usage = Some(Mutex::new(AnchorUsageTracker::default()));
+        self
+    }
+
+    /// Only accept handshake signatures using `schemes`, which are
+    /// offered to the server in this order of preference.
+    ///
+    /// Schemes webpki doesn't support are ignored.  Signatures using
+    /// other schemes are rejected with `Error::PeerMisbehavedError`.
+    pub fn with_signature_schemes(mut self, schemes: &[SignatureScheme]) -> Self {
+        let supported = WebPkiVerifier::verification_schemes();
+        self.verifier.signature_schemes = Some(
+            schemes
+                .iter()
+                .copied()
+                .filter(|scheme| supported.contains(scheme))
+                .collect(),
+        );
+        self
+    }
+
+    /// Choose whether to ask the server to send SCTs.
+    ///
+    /// SCTs embedded in the certificate or stapled OCSP response are
+    /// still validated if this is false.
+    pub fn with_sct_requests(mut self, request_scts: bool) -> Self {
+        self.verifier.request_scts = request_scts;
+        self
+    }
+
+    /// Build the `WebPkiVerifier`.
+    pub fn build(self) -> WebPkiVerifier {
+        self.verifier
+    }
 }
 
 impl WebPkiVerifier {
@@ -329,104 +913,2109 @@ impl WebPkiVerifier {
     /// `ct_logs` is the list of logs that are trusted for Certificate
     /// Transparency. Currently CT log enforcement is opportunistic; see
     /// https://github.com/ctz/rustls/issues/479.
+    ///
+    /// Use [`WebPkiVerifier::builder`] to configure more than this.
     pub fn new(roots: RootCertStore, ct_logs: &'static [&'static sct::Log<'static>]) -> Self {
-        Self { roots, ct_logs }
+        Self::new_with_ct_logs(
+            roots,
+            ct_logs
+                .iter()
+                .map(|log| OwnedCtLog::from_log(log))
+                .collect(),
+        )
     }
 
-    /// Returns the signature verification methods supported by
-    /// webpki.
-    pub fn verification_schemes() -> Vec<SignatureScheme> {
-        vec![
-            SignatureScheme::ECDSA_NISTP384_SHA384,
-            SignatureScheme::ECDSA_NISTP256_SHA256,
-            SignatureScheme::ED25519,
-            SignatureScheme::RSA_PSS_SHA512,
-            SignatureScheme::RSA_PSS_SHA384,
-            SignatureScheme::RSA_PSS_SHA256,
-            SignatureScheme::RSA_PKCS1_SHA512,
-            SignatureScheme::RSA_PKCS1_SHA384,
-            SignatureScheme::RSA_PKCS1_SHA256,
-        ]
+    /// Constructs a `WebPkiVerifier` trusting the operating system's
+    /// roots, as loaded by [`RootCertStore::from_platform`], and no CT
+    /// logs.
+    #[cfg(feature = "platform_roots")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "platform_roots")))]
+    pub fn new_with_platform_roots() -> Result<Self, Error> {
+        Ok(Self::new(RootCertStore::from_platform()?, &[]))
     }
-}
 
-type CertChainAndRoots<'a, 'b> = (
-    webpki::EndEntityCert<'a>,
-    Vec<&'a [u8]>,
-    Vec<webpki::TrustAnchor<'b>>,
-);
+    /// Start building a `WebPkiVerifier` trusting `roots`.
+    pub fn builder(roots: RootCertStore) -> WebPkiVerifierBuilder {
+        WebPkiVerifierBuilder::new(roots)
+    }
 
-fn prepare<'a, 'b>(
-    end_entity: &'a Certificate,
-    intermediates: &'a [Certificate],
-    roots: &'b RootCertStore,
-) -> Result<CertChainAndRoots<'a, 'b>, Error> {
-    // EE cert must appear first.
-    let cert = webpki::EndEntityCert::try_from(end_entity.0.as_ref())
-        .map_err(|e| Error::WebPkiError(e, WebPkiOp::ParseEndEntity))?;
+    /// Constructs a new `WebPkiVerifier`, like [`WebPkiVerifier::new`],
+    /// trusting `ct_logs` for Certificate Transparency.
+    ///
+    /// These can be loaded at runtime, such as with
+    /// `OwnedCtLog::from_log_list_json` (with the `ct_log_lists`
+    /// feature), and later replaced with
+    /// [`WebPkiVerifier::replace_ct_logs`].
+    pub fn new_with_ct_logs(roots: RootCertStore, ct_logs: Arc<[OwnedCtLog]>) -> Self {
+        WebPkiVerifierBuilder::new(roots)
+            .with_ct_logs(ct_logs)
+            .build()
+    }
 
-    let intermediates: Vec<&'a [u8]> = intermediates
-        .iter()
-        .map(|cert| cert.0.as_ref())
-        .collect();
+    /// See [`WebPkiVerifierBuilder::with_ct_policy`].
+    pub fn with_ct_policy(self, policy: CtPolicy) -> Self {
+        self.into_builder()
+            .with_ct_policy(policy)
+            .build()
+    }
 
-    let trustroots: Vec<webpki::TrustAnchor> = roots
-        .roots
-        .iter()
-        .map(OwnedTrustAnchor::to_trust_anchor)
-        .collect();
+    /// See [`WebPkiVerifierBuilder::with_ocsp_policy`].
+    pub fn with_ocsp_policy(self, policy: OcspPolicy) -> Self {
+        self.into_builder()
+            .with_ocsp_policy(policy)
+            .build()
+    }
 
-    Ok((cert, intermediates, trustroots))
-}
+    /// See [`WebPkiVerifierBuilder::with_revocation_filter`].
+    pub fn with_revocation_filter(self, filter: Arc<dyn RevocationFilter>) -> Self {
+        self.into_builder()
+            .with_revocation_filter(filter)
+            .build()
+    }
 
-/// A `ClientCertVerifier` that will ensure that every client provides a trusted
-/// certificate, without any name checking.
-pub struct AllowAnyAuthenticatedClient {
-    roots: RootCertStore,
+    /// See [`WebPkiVerifierBuilder::with_must_staple`].
+    pub fn with_must_staple(self) -> Self {
+        self.into_builder()
+            .with_must_staple()
+            .build()
+    }
+
+    /// See [`WebPkiVerifierBuilder::with_ocsp_soft_fail`].
+    pub fn with_ocsp_soft_fail(
+        self,
+        observer: impl Fn(&Certificate, &Error) + Send + Sync + 'static,
+    ) -> Self {
+        self.into_builder()
+            .with_ocsp_soft_fail(observer)
+            .build()
+    }
+
+    /// See [`WebPkiVerifierBuilder::with_allowed_key_algorithms`].
+    pub fn with_allowed_key_algorithms(self, allowed: &[SignatureAlgorithm]) -> Self {
+        self.into_builder()
+            .with_allowed_key_algorithms(allowed)
+            .build()
+    }
+
+    /// See [`WebPkiVerifierBuilder::with_validity_policy`].
+    pub fn with_validity_policy(self, policy: ValidityPolicy) -> Self {
+        self.into_builder()
+            .with_validity_policy(policy)
+            .build()
+    }
+
+    /// See [`WebPkiVerifierBuilder::with_min_notbefore`].
+    pub fn with_min_notbefore(self, cutoff: SystemTime) -> Self {
+        self.into_builder()
+            .with_min_notbefore(cutoff)
+            .build()
+    }
+
+    /// See [`WebPkiVerifierBuilder::with_clock_skew_tolerance`].
+    pub fn with_clock_skew_tolerance(self, tolerance: Duration) -> Self {
+        self.into_builder()
+            .with_clock_skew_tolerance(tolerance)
+            .build()
+    }
+
+    /// See [`WebPkiVerifierBuilder::with_expiry_override_pins`].
+    pub fn with_expiry_override_pins(self, pins: &[[u8; 32]]) -> Self {
+        self.into_builder()
+            .with_expiry_override_pins(pins)
+            .build()
+    }
+
+    /// See [`WebPkiVerifierBuilder::with_public_suffixes`].
+    pub fn with_public_suffixes(self, suffixes: &[&str]) -> Self {
+        self.into_builder()
+            .with_public_suffixes(suffixes)
+            .build()
+    }
+
+    /// See [`WebPkiVerifierBuilder::with_serial_number_check`].
+    pub fn with_serial_number_check(self, min_octets: usize) -> Self {
+        self.into_builder()
+            .with_serial_number_check(min_octets)
+            .build()
+    }
+
+    /// See [`WebPkiVerifierBuilder::with_aki_ski_check`].
+    pub fn with_aki_ski_check(self) -> Self {
+        self.into_builder()
+            .with_aki_ski_check()
+            .build()
+    }
+
+    /// See [`WebPkiVerifierBuilder::with_eku_chaining_check`].
+    pub fn with_eku_chaining_check(self) -> Self {
+        self.into_builder()
+            .with_eku_chaining_check()
+            .build()
+    }
+
+    /// See [`WebPkiVerifierBuilder::with_signature_algorithms`].
+    pub fn with_signature_algorithms(
+        self,
+        algorithms: &'static [&'static webpki::SignatureAlgorithm],
+    ) -> Self {
+        self.into_builder()
+            .with_signature_algorithms(algorithms)
+            .build()
+    }
+
+    /// See [`WebPkiVerifierBuilder::with_known_intermediates`].
+    pub fn with_known_intermediates(self, intermediates: &[Certificate]) -> Self {
+        self.into_builder()
+            .with_known_intermediates(intermediates)
+            .build()
+    }
+
+    /// See [`WebPkiVerifierBuilder::with_intermediate_fetcher`].
+    pub fn with_intermediate_fetcher(self, fetcher: Arc<dyn IntermediateFetcher>) -> Self {
+        self.into_builder()
+            .with_intermediate_fetcher(fetcher)
+            .build()
+    }
+
+    /// See [`WebPkiVerifierBuilder::with_anchor_usage_tracking`].
+    pub fn with_anchor_usage_tracking(self) -> Self {
+        self.into_builder()
+            .with_anchor_usage_tracking()
+            .build()
+    }
+
+    /// Continue building this verifier.
+    fn into_builder(self) -> WebPkiVerifierBuilder {
+        WebPkiVerifierBuilder { verifier: self }
+    }
+
+    /// Verify `end_entity` just as `ServerCertVerifier::verify_server_cert`
+    /// does, returning details of the verification.
+    pub fn verify_server_cert_detailed(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<VerifiedServerCert, Error> {
+        self.verify_detailed(
+            end_entity,
+            intermediates,
+            PeerIdentity::ServerName(server_name),
+            scts,
+            ocsp_response,
+            now,
+        )
+    }
+
+    /// Verify `end_entity` as `verify_server_cert_detailed` does, but for
+    /// a peer identified by the email address `email`, as in SMTP, rather
+    /// than by a server name.
+    ///
+    /// The certificate must have an rfc822Name subject alternative name
+    /// matching `email`: the local parts must be identical, and the
+    /// domains are compared case-insensitively.  Otherwise, this fails with
+    /// `Error::WebPkiError(CertNotValidForName, ValidateForEmail)`.
+    pub fn verify_cert_for_email(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        email: &str,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<VerifiedServerCert, Error> {
+        self.verify_detailed(
+            end_entity,
+            intermediates,
+            PeerIdentity::Email(email),
+            scts,
+            ocsp_response,
+            now,
+        )
+    }
+
+    /// Verify `end_entity` as `verify_server_cert_detailed` does, but for
+    /// a peer identified by the URI `uri`, as in SIP, rather than by a
+    /// server name.
+    ///
+    /// The certificate must have a uniformResourceIdentifier subject
+    /// alternative name matching `uri`: the schemes and hosts are
+    /// compared case-insensitively, and the rest must be identical.
+    /// Otherwise, this fails with
+    /// `Error::WebPkiError(CertNotValidForName, ValidateForUri)`.
+    pub fn verify_cert_for_uri(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        uri: &str,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<VerifiedServerCert, Error> {
+        self.verify_detailed(
+            end_entity,
+            intermediates,
+            PeerIdentity::Uri(uri),
+            scts,
+            ocsp_response,
+            now,
+        )
+    }
+
+    fn verify_detailed(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        identity: PeerIdentity,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<VerifiedServerCert, Error> {
+        if self.check_eku_chaining {
+            check_eku_chaining(intermediates)?;
+        }
+
+        if let Some(min_octets) = self.min_serial_octets {
+            check_serial_number(end_entity, min_octets)?;
+        }
+
+        let roots = self.roots.read().clone();
+        let completed;
+        let intermediates = if (!self.known_intermediates.is_empty()
+            || self.intermediate_fetcher.is_some())
+            && self.issuer_unknown(end_entity, intermediates, &roots.store, now)
+        {
+            completed = self.complete_chain(end_entity, intermediates, &roots.store);
+            &completed[..]
+        } else {
+            intermediates
+        };
+
+        let (cert, chain, trustroots) = prepare(end_entity, intermediates, &roots.store, now)?;
+        let webpki_now = match self.verify_chain(&cert, &chain, &trustroots, now) {
+            Err(err @ Error::WebPkiError(webpki::Error::CertExpired, _)) => self
+                .verify_expired_pinned(end_entity, &cert, &chain, &trustroots)
+                .ok_or(err)?,
+            result => result?,
+        };
+
+        check_extra_name_constraints(
+            end_entity,
+            intermediates,
+            &roots.store,
+            &trustroots,
+            now,
+            WebPkiOp::ValidateServerCert,
+            |ta| {
+                cert.verify_is_valid_tls_server_cert(
+                    self.signature_algorithms,
+                    &webpki::TlsServerTrustAnchors(std::slice::from_ref(ta)),
+                    &chain,
+                    webpki_now,
+                )
+                .is_ok()
+            },
+        )?;
+
+        if let Some(allowed) = &self.allowed_key_algorithms {
+            check_key_algorithm(end_entity, allowed)?;
+        }
+
+        if let Some(policy) = &self.validity_policy {
+            check_validity_period(end_entity, policy)?;
+        }
+
+        if let Some(cutoff) = self.min_not_before {
+            check_not_before(end_entity, cutoff)?;
+        }
+
+        if let Some(suffixes) = &self.public_suffixes {
+            check_wildcard_suffixes(end_entity, suffixes)?;
+        }
+
+        if self.check_aki_ski {
+            check_aki_ski(end_entity, intermediates)?;
+        }
+
+        let path = build_path(end_entity, intermediates, &roots.store)?;
+
+        if let Some(filter) = &self.revocation_filter {
+            check_revocation_filter(&path, filter.as_ref())?;
+        }
+
+        if let Some(usage) = &self.anchor_usage {
+            if let Some(ta) = matched_anchor(
+                end_entity,
+                &cert,
+                &chain,
+                &trustroots,
+                self.signature_algorithms,
+                webpki_now,
+            ) {
+                usage.lock().record(ta, now);
+            }
+        }
+
+        let ct_logs = self.ct_logs.read().clone();
+        let ct_logs: Vec<sct::Log> = ct_logs
+            .iter()
+            .map(OwnedCtLog::to_log)
+            .collect();
+        let sct_logs = verify_scts(
+            &SctSources::new(end_entity, intermediates, &roots.store, scts, ocsp_response),
+            end_entity,
+            intermediates,
+            &roots.store,
+            now,
+            &ct_logs.iter().collect::<Vec<_>>(),
+            &self.ct_policy,
+        )?;
+
+        let must_staple = self.enforce_must_staple && requires_ocsp_staple(end_entity);
+        if must_staple && ocsp_response.is_empty() {
+            return Err(Error::OcspStapleRequired);
+        }
+
+        let default_policy;
+        let ocsp_policy = match &self.ocsp_policy {
+            Some(policy) => Some(policy),
+            None if must_staple => {
+                default_policy = OcspPolicy::new();
+                Some(&default_policy)
+            }
+            None => None,
+        };
+
+        let mut ocsp_freshness = None;
+        let revocation = match ocsp_policy {
+            Some(policy) if !ocsp_response.is_empty() => {
+                let status = ocsp::verify_response(
+                    policy,
+                    ocsp_response,
+                    end_entity,
+                    intermediates,
+                    &roots.store,
+                    self.signature_algorithms,
+                    unix_time_secs(now)?,
+                )
+                .map(|(status, freshness)| {
+                    ocsp_freshness = Some(freshness);
+                    status
+                });
+
+                match status {
+                    Ok(CertStatus::Good) => RevocationStatus::Checked(CertStatus::Good),
+                    Ok(CertStatus::Revoked) => return Err(Error::CertificateRevoked),
+                    Ok(CertStatus::Unknown) => match policy.unknown_status {
+                        OcspUnknownStatus::Accept => {
+                            debug!("OCSP responder does not know certificate");
+                            RevocationStatus::Checked(CertStatus::Unknown)
+                        }
+                        OcspUnknownStatus::Warn => {
+                            warn!("OCSP responder does not know certificate");
+                            if let Some(observer) = &self.ocsp_soft_fail {
+                                observer(end_entity, &Error::OcspStatusUnknown);
+                            }
+                            RevocationStatus::SoftFailed
+                        }
+                        OcspUnknownStatus::Reject => return Err(Error::OcspStatusUnknown),
+                    },
+                    Err(err) => match &self.ocsp_soft_fail {
+                        Some(observer) if !must_staple => {
+                            warn!("accepting certificate despite OCSP failure: {:?}", err);
+                            observer(end_entity, &err);
+                            RevocationStatus::SoftFailed
+                        }
+                        _ => return Err(err),
+                    },
+                }
+            }
+            _ if !ocsp_response.is_empty() => {
+                trace!("Unvalidated OCSP response: {:?}", ocsp_response.to_vec());
+                RevocationStatus::NotChecked
+            }
+            _ => RevocationStatus::NotChecked,
+        };
+
+        match identity {
+            PeerIdentity::ServerName(server_name) => {
+                verify_server_name(&cert, end_entity, server_name)?
+            }
+            PeerIdentity::Email(email) => verify_email(end_entity, email)?,
+            PeerIdentity::Uri(uri) => verify_uri(end_entity, uri)?,
+        }
+
+        let mut end_entity_spki = x509::CertFields::parse(&end_entity.0)
+            .ok_or(Error::WebPkiError(
+                webpki::Error::BadDer,
+                WebPkiOp::ParseEndEntity,
+            ))?
+            .spki
+            .to_vec();
+        x509::wrap_in_sequence(&mut end_entity_spki);
+
+        Ok(VerifiedServerCert {
+            generation: roots.generation,
+            revocation,
+            end_entity_spki,
+            earliest_expiry: earliest_expiry(&path),
+            path: path
+                .iter()
+                .map(PathEntry::to_path_certificate)
+                .collect(),
+            sct_logs,
+            ocsp_freshness,
+        })
+    }
+
+    /// Returns details of the last certificate successfully verified
+    /// by this verifier's [`ServerCertVerifier::verify_server_cert`],
+    /// if any.
+    ///
+    /// This is for logging and monitoring PKI details of connections
+    /// made with a `ClientConfig`.  A verifier shared by concurrent
+    /// connections reports whichever finished verification last: use
+    /// [`WebPkiVerifier::verify_server_cert_detailed`] to be sure of
+    /// details for a particular certificate.
+    pub fn last_verification(&self) -> Option<VerifiedServerCert> {
+        self.last_verification.lock().clone()
+    }
+
+    /// Returns the generation of this verifier's trust anchors.  This
+    /// starts at zero, and increases each time the anchors are changed
+    /// through this verifier.
+    ///
+    /// Compare with [`VerifiedServerCert::generation`] to find
+    /// connections verified using an older set of anchors.
+    pub fn generation(&self) -> u64 {
+        self.roots.read().generation
+    }
+
+    /// Adds a single DER-encoded certificate to the trust anchors.
+    ///
+    /// This is a mutating operation managed by interior mutability, and
+    /// increments the generation if successful.
+    pub fn add_trusted_root_ca(&self, der: &Certificate) -> Result<(), webpki::Error> {
+        let mut roots = self.roots.write();
+        Arc::make_mut(&mut roots.store).add(der)?;
+        roots.generation += 1;
+        Ok(())
+    }
+
+    /// Adds all the given TrustAnchors `anchors` to the trust anchors.
+    ///
+    /// This is a mutating operation managed by interior mutability, and
+    /// increments the generation.
+    pub fn add_server_trust_anchors(
+        &self,
+        &webpki::TlsServerTrustAnchors(anchors): &webpki::TlsServerTrustAnchors,
+    ) {
+        let mut roots = self.roots.write();
+        Arc::make_mut(&mut roots.store)
+            .add_server_trust_anchors(&webpki::TlsServerTrustAnchors(anchors));
+        roots.generation += 1;
+    }
+
+    /// Parses the given DER-encoded certificates and adds all that can be
+    /// parsed to the trust anchors, in a best-effort fashion.
+    ///
+    /// Returns the number of certificates added, and the number that were
+    /// ignored.
+    ///
+    /// This is a mutating operation managed by interior mutability, and
+    /// increments the generation if any certificates were added.
+    pub fn batch_add_certificates(&self, der_certs: &[Vec<u8>]) -> (usize, usize) {
+        let mut roots = self.roots.write();
+        let (valid, invalid) = Arc::make_mut(&mut roots.store).add_parsable_certificates(der_certs);
+        if valid > 0 {
+            roots.generation += 1;
+        }
+        (valid, invalid)
+    }
+
+    /// Replaces the trusted Certificate Transparency logs with `ct_logs`.
+    ///
+    /// This is a mutating operation managed by interior mutability.
+    /// Verifications already in progress use the previous logs.
+    pub fn replace_ct_logs(&self, ct_logs: Arc<[OwnedCtLog]>) {
+        *self.ct_logs.write() = ct_logs;
+    }
+
+    /// Replaces all the trust anchors with `store`.
+    ///
+    /// This is a mutating operation managed by interior mutability, and
+    /// increments the generation.
+    pub fn replace_root_cert_store(&self, store: RootCertStore) {
+        self.set_roots(Arc::new(store));
+    }
+
+    /// Replaces all the trust anchors with `roots`, which may be shared
+    /// with other verifiers.
+    ///
+    /// This lets a long-lived `ClientConfig` pick up a rotated CA without
+    /// being rebuilt.  It doesn't wait for verifications in progress:
+    /// those complete using the previous anchors.
+    ///
+    /// This is a mutating operation managed by interior mutability, and
+    /// increments the generation.
+    pub fn set_roots(&self, roots: Arc<RootCertStore>) {
+        let mut versioned = self.roots.write();
+        versioned.store = roots;
+        versioned.generation += 1;
+    }
+
+    /// Returns the current trust anchors.
+    pub fn roots(&self) -> Arc<RootCertStore> {
+        Arc::clone(&self.roots.read().store)
+    }
+
+    /// Returns usage statistics for every anchor in this verifier's
+    /// `RootCertStore`, in the order they appear there.  Anchors which
+    /// have never been used are included, with a `count` of zero.
+    ///
+    /// This returns an empty `Vec` unless usage tracking was enabled
+    /// with [`WebPkiVerifier::with_anchor_usage_tracking`].
+    pub fn anchor_usage_stats(&self) -> Vec<AnchorUsage> {
+        let usage = match &self.anchor_usage {
+            Some(usage) => usage.lock(),
+            None => return Vec::new(),
+        };
+
+        self.roots
+            .read()
+            .store
+            .roots
+            .iter()
+            .map(|ota| {
+                let ta = ota.to_trust_anchor();
+                let spki_sha256 = spki_sha256(&ta);
+                let mut subject = ta.subject.to_vec();
+                x509::wrap_in_sequence(&mut subject);
+
+                match usage.by_spki.get(&spki_sha256) {
+                    Some(seen) => AnchorUsage {
+                        subject,
+                        spki_sha256,
+                        count: seen.count,
+                        first_used: Some(seen.first_used),
+                        last_used: Some(seen.last_used),
+                    },
+                    None => AnchorUsage {
+                        subject,
+                        spki_sha256,
+                        count: 0,
+                        first_used: None,
+                        last_used: None,
+                    },
+                }
+            })
+            .collect()
+    }
+
+    /// Describe the policy this verifier applies.
+    ///
+    /// This is intended for auditing that a deployment enables the
+    /// checks it intends to.
+    pub fn policy_summary(&self) -> VerifierPolicy {
+        VerifierPolicy {
+            root_count: self.roots.read().store.len(),
+            ct_log_count: self.ct_logs.read().len(),
+            ct_policy: self.ct_policy.clone(),
+            signature_schemes: self
+                .signature_schemes
+                .clone()
+                .unwrap_or_else(Self::verification_schemes),
+            chain_signature_schemes: Self::verification_schemes()
+                .into_iter()
+                .filter(|scheme| scheme_in_algorithms(*scheme, self.signature_algorithms))
+                .collect(),
+            ocsp_policy: self.ocsp_policy.clone(),
+            ocsp_soft_fail: self.ocsp_soft_fail.is_some(),
+            allowed_key_algorithms: self.allowed_key_algorithms.clone(),
+            validity_policy: self.validity_policy.clone(),
+            min_not_before: self.min_not_before,
+            clock_skew_tolerance: self.clock_skew_tolerance,
+            expiry_override_pin_count: self.expiry_override_pins.len(),
+            anchor_usage_tracking: self.anchor_usage.is_some(),
+            aki_ski_check: self.check_aki_ski,
+            eku_chaining_check: self.check_eku_chaining,
+            must_staple: self.enforce_must_staple,
+            public_suffix_count: self
+                .public_suffixes
+                .as_ref()
+                .map(Vec::len),
+            serial_number_min_octets: self.min_serial_octets,
+            intermediate_fetching: self.intermediate_fetcher.is_some(),
+            known_intermediate_count: self.known_intermediates.len(),
+            revocation_filter: self.revocation_filter.is_some(),
+        }
+    }
+
+    /// Validate `cert` and `chain` at `now`, or failing that at a time
+    /// within the clock skew tolerance.  Returns the time used.
+    fn verify_chain(
+        &self,
+        cert: &webpki::EndEntityCert,
+        chain: &[&[u8]],
+        trustroots: &[webpki::TrustAnchor],
+        now: SystemTime,
+    ) -> Result<webpki::Time, Error> {
+        let verify_at = |time: SystemTime| {
+            let time = webpki::Time::try_from(time).map_err(|_| Error::FailedToGetCurrentTime)?;
+            cert.verify_is_valid_tls_server_cert(
+                self.signature_algorithms,
+                &webpki::TlsServerTrustAnchors(trustroots),
+                chain,
+                time,
+            )
+            .map(|_| time)
+            .map_err(|e| Error::WebPkiError(e, WebPkiOp::ValidateServerCert))
+        };
+
+        let result = verify_at(now);
+        let tolerance = match self.clock_skew_tolerance {
+            Some(tolerance) => tolerance,
+            None => return result,
+        };
+
+        let skewed = match &result {
+            Err(Error::WebPkiError(webpki::Error::CertNotValidYet, _)) => {
+                now.checked_add(tolerance)
+            }
+            Err(Error::WebPkiError(webpki::Error::CertExpired, _)) => now.checked_sub(tolerance),
+            _ => None,
+        };
+
+        match skewed {
+            Some(skewed) => {
+                debug!("retrying certificate validation allowing for clock skew");
+                verify_at(skewed).or(result)
+            }
+            None => result,
+        }
+    }
+
+    /// Add known and then fetched intermediates to `intermediates`,
+    /// for certificates whose issuer is missing.
+    fn complete_chain(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        roots: &RootCertStore,
+    ) -> Vec<Certificate> {
+        let intermediates =
+            self.known_intermediates
+                .complete_chain(end_entity, intermediates, roots);
+
+        match &self.intermediate_fetcher {
+            Some(fetcher) => aia::fetch_missing_intermediates(
+                fetcher.as_ref(),
+                end_entity,
+                &intermediates,
+                roots,
+            ),
+            None => intermediates,
+        }
+    }
+
+    /// Validate the expired chain from `end_entity` again at a time when
+    /// it was valid, if its end-entity key is one of the
+    /// `expiry_override_pins`, or using only the trust anchors whose
+    /// keys are.  Returns the time it was valid at.
+    fn verify_expired_pinned(
+        &self,
+        end_entity: &Certificate,
+        cert: &webpki::EndEntityCert,
+        chain: &[&[u8]],
+        trustroots: &[webpki::TrustAnchor],
+    ) -> Option<webpki::Time> {
+        if self.expiry_override_pins.is_empty() {
+            return None;
+        }
+
+        let pinned = |spki: &[u8]| {
+            let mut spki = spki.to_vec();
+            x509::wrap_in_sequence(&mut spki);
+            let hash = ring::digest::digest(&ring::digest::SHA256, &spki);
+            self.expiry_override_pins
+                .iter()
+                .any(|pin| pin[..] == *hash.as_ref())
+        };
+
+        let fields = x509::CertFields::parse(&end_entity.0)?;
+        let anchors: Vec<webpki::TrustAnchor> = trustroots
+            .iter()
+            .filter(|ta| pinned(fields.spki) || pinned(ta.spki))
+            .map(|ta| webpki::TrustAnchor {
+                subject: ta.subject,
+                spki: ta.spki,
+                name_constraints: ta.name_constraints,
+            })
+            .collect();
+        if anchors.is_empty() {
+            return None;
+        }
+
+        // The chain was last valid when one of its certificates expired.
+        let mut expiries: Vec<u64> = Some(fields.not_after)
+            .into_iter()
+            .chain(
+                chain
+                    .iter()
+                    .filter_map(|der| x509::CertFields::parse(der))
+                    .map(|fields| fields.not_after),
+            )
+            .collect();
+        expiries.sort_unstable();
+        expiries.dedup();
+
+        let time = expiries
+            .into_iter()
+            .find_map(|expiry| {
+                let time = webpki::Time::from_seconds_since_unix_epoch(expiry);
+                cert.verify_is_valid_tls_server_cert(
+                    self.signature_algorithms,
+                    &webpki::TlsServerTrustAnchors(&anchors),
+                    chain,
+                    time,
+                )
+                .ok()
+                .map(|_| time)
+            })?;
+
+        warn!("accepting expired certificate chain with pinned key");
+        Some(time)
+    }
+
+    /// Does validating `end_entity` fail because no path to a trust
+    /// anchor can be built?
+    fn issuer_unknown(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        roots: &RootCertStore,
+        now: SystemTime,
+    ) -> bool {
+        let result =
+            prepare(end_entity, intermediates, roots, now).and_then(|(cert, chain, trustroots)| {
+                self.verify_chain(&cert, &chain, &trustroots, now)
+            });
+        matches!(
+            result,
+            Err(Error::WebPkiError(webpki::Error::UnknownIssuer, _))
+        )
+    }
+
+    fn check_signature_scheme(&self, scheme: SignatureScheme) -> Result<(), Error> {
+        match &self.signature_schemes {
+            Some(schemes) if !schemes.contains(&scheme) => Err(Error::PeerMisbehavedError(
+                format!("signature scheme {:?} not allowed", scheme),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the signature verification methods supported by
+    /// webpki.
+    pub fn verification_schemes() -> Vec<SignatureScheme> {
+        vec![
+            SignatureScheme::ECDSA_NISTP384_SHA384,
+            SignatureScheme::ECDSA_NISTP256_SHA256,
+            SignatureScheme::ED25519,
+            SignatureScheme::RSA_PSS_SHA512,
+            SignatureScheme::RSA_PSS_SHA384,
+            SignatureScheme::RSA_PSS_SHA256,
+            SignatureScheme::RSA_PKCS1_SHA512,
+            SignatureScheme::RSA_PKCS1_SHA384,
+            SignatureScheme::RSA_PKCS1_SHA256,
+        ]
+    }
+}
+
+fn check_key_algorithm(
+    end_entity: &Certificate,
+    allowed: &[SignatureAlgorithm],
+) -> Result<(), Error> {
+    let key_alg = x509::CertFields::parse(&end_entity.0).and_then(|c| x509::key_algorithm(c.spki));
+
+    match key_alg {
+        Some(alg) if allowed.contains(&alg) => Ok(()),
+        _ => {
+            debug!("End-entity key algorithm {:?} is not allowed", key_alg);
+            Err(Error::DisallowedKeyAlgorithm)
+        }
+    }
+}
+
+/// A `WebPkiVerifier`'s trust anchors, and the number of times
+/// they have been changed.
+///
+/// Each verification uses a snapshot of these, so changing them only
+/// waits for other changes.  Changes copy the store if any
+/// verification is still using it.
+#[derive(Clone)]
+struct VersionedRoots {
+    store: Arc<RootCertStore>,
+    generation: u64,
+}
+
+/// Details of a certificate verified by
+/// [`WebPkiVerifier::verify_server_cert_detailed`].
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifiedServerCert {
+    /// The [`WebPkiVerifier::generation`] of the trust anchors used.
+    pub generation: u64,
+
+    /// Whether the certificate was checked for revocation.
+    pub revocation: RevocationStatus,
+
+    /// The DER encoding of the end-entity certificate's
+    /// `SubjectPublicKeyInfo`, for use with external pinning systems.
+    pub end_entity_spki: Vec<u8>,
+
+    /// The certificate in the chain which expires first.  The chain is
+    /// only valid until then, which may be before the end-entity
+    /// certificate expires.
+    pub earliest_expiry: ChainExpiry,
+
+    /// The chain, from the end-entity certificate to the trust anchor
+    /// it terminates at.  The chain is followed as for
+    /// [`ChainExpiry`], so ends without a trust anchor if its issuer
+    /// isn't found by name.
+    pub path: Vec<PathCertificate>,
+
+    /// The indices in the verifier's CT logs of the logs whose SCTs
+    /// were validated.  Validation stops once the `CtPolicy` is met, so
+    /// this need not include every log whose SCTs the server sent.
+    pub sct_logs: Vec<usize>,
+
+    /// When the stapled OCSP response was produced and when it expires,
+    /// if one was validated.
+    pub ocsp_freshness: Option<OcspFreshness>,
+}
+
+/// A certificate in a verified chain, as reported in
+/// [`VerifiedServerCert::path`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathCertificate {
+    /// Where the certificate is in the chain.
+    pub position: ChainPosition,
+
+    /// The DER encoding of the certificate's subject `Name`.
+    pub subject: Vec<u8>,
+
+    /// The family of the certificate's public key, or `None` if it is
+    /// not recognised.
+    pub key_algorithm: Option<SignatureAlgorithm>,
+
+    /// The size of the certificate's public key in bits, or `None` if
+    /// it is not known.  This is the modulus length for RSA keys, and
+    /// the field size for elliptic curve keys.
+    pub key_bits: Option<usize>,
+}
+
+/// The certificate in a verified chain which expires first, as reported
+/// in [`VerifiedServerCert::earliest_expiry`].
+///
+/// The chain is followed from the end-entity certificate by issuer name,
+/// through the intermediates the server sent, to a trust anchor.  Only
+/// anchors added with [`RootCertStore::add`] have a known expiry: others
+/// are not considered.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainExpiry {
+    /// The certificate's `notAfter`.
+    pub not_after: SystemTime,
+
+    /// Where the certificate is in the chain.
+    pub position: ChainPosition,
+
+    /// The DER encoding of the certificate's subject `Name`.
+    pub subject: Vec<u8>,
+}
+
+/// Where a certificate is in a verified chain.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChainPosition {
+    /// The end-entity certificate.
+    EndEntity,
+
+    /// An intermediate certificate, with its index in the intermediates
+    /// the server sent, followed by any added from the verifier's known
+    /// intermediates or supplied by an
+    /// [`IntermediateFetcher`](crate::IntermediateFetcher).
+    Intermediate(usize),
+
+    /// The trust anchor the chain terminates at.
+    TrustAnchor,
+}
+
+/// The outcome of checking a server certificate for revocation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RevocationStatus {
+    /// A stapled OCSP response was validated, and gave this status.
+    /// This is `CertStatus::Unknown` only if the [`OcspPolicy`] accepts
+    /// that with `OcspUnknownStatus::Accept`.
+    Checked(CertStatus),
+
+    /// A stapled OCSP response was validated, but the responder did not
+    /// know the certificate, and the [`OcspPolicy`] warns about that
+    /// with `OcspUnknownStatus::Warn`.  Or, with
+    /// [`WebPkiVerifier::with_ocsp_soft_fail`], the stapled response was
+    /// invalid.
+    SoftFailed,
+
+    /// No revocation information was checked: either there was no
+    /// stapled OCSP response, or the verifier has no [`OcspPolicy`].
+    NotChecked,
+}
+
+/// How a [`WebPkiVerifier`] treats Signed Certificate Timestamps (SCTs)
+/// sent by the server, when it has trusted Certificate Transparency logs.
+///
+/// SCTs embedded in the certificate, carried in a stapled OCSP response,
+/// and sent in the TLS extension are treated alike.  An SCT delivered
+/// more than once is only examined once.
+///
+/// By default SCTs are checked opportunistically: a certificate without
+/// SCTs is accepted, but one whose SCTs are all invalid is rejected.
+/// Setting `min_operators` or `require_embedded` makes SCTs mandatory,
+/// in the manner of browser CT policies.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CtPolicy {
+    /// The most distinct SCTs to examine for one certificate, across all
+    /// the places the server can deliver them.  If the policy isn't met
+    /// by these, and the server sent more, the certificate is rejected
+    /// with `Error::TooManyScts`.
+    ///
+    /// This bounds the work a server can cause by sending many SCTs.
+    pub max_scts: usize,
+
+    /// The number of distinct log operators which must have issued a
+    /// valid SCT for the certificate.  Logs are distinguished by their
+    /// `operated_by`.
+    pub min_operators: usize,
+
+    /// Whether at least one valid SCT must be embedded in the
+    /// certificate, rather than delivered in OCSP or the TLS extension.
+    pub require_embedded: bool,
+
+    /// If set, `min_operators` and `require_embedded` only apply to
+    /// certificates with a `notBefore` on or after this time.  Earlier
+    /// certificates are checked opportunistically.
+    pub enforced_from: Option<SystemTime>,
+}
+
+impl CtPolicy {
+    /// Make a `CtPolicy` which examines up to 32 SCTs, opportunistically.
+    pub fn new() -> Self {
+        Self {
+            max_scts: 32,
+            min_operators: 0,
+            require_embedded: false,
+            enforced_from: None,
+        }
+    }
+
+    /// Are `min_operators` and `require_embedded` enforced for
+    /// `end_entity`?
+    fn enforced_for(&self, end_entity: &Certificate) -> Result<bool, Error> {
+        if self.min_operators == 0 && !self.require_embedded {
+            return Ok(false);
+        }
+
+        let enforced_from = match self.enforced_from {
+            Some(time) => time,
+            None => return Ok(true),
+        };
+
+        let cert = x509::CertFields::parse(&end_entity.0).ok_or(Error::WebPkiError(
+            webpki::Error::BadDer,
+            WebPkiOp::ParseEndEntity,
+        ))?;
+        Ok(cert.not_before >= unix_time_secs(enforced_from)?)
+    }
+}
+
+impl Default for CtPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A limit on the validity period of end-entity certificates, applying
+/// only to certificates issued on or after a given date.
+///
+/// This matches how browser and CA/Browser Forum rules reduce maximum
+/// certificate lifetimes, without rejecting certificates issued under
+/// earlier rules.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidityPolicy {
+    /// Certificates with a `notBefore` on or after this time are limited.
+    pub issued_after: SystemTime,
+
+    /// The longest allowed time between `notBefore` and `notAfter`.
+    pub max_validity: Duration,
+}
+
+impl ValidityPolicy {
+    /// Make a `ValidityPolicy` limiting certificates issued on or after
+    /// `issued_after` to 398 days of validity.
+    pub fn new(issued_after: SystemTime) -> Self {
+        Self {
+            issued_after,
+            max_validity: Duration::from_secs(398 * 24 * 60 * 60),
+        }
+    }
+}
+
+/// A description of the policy applied by a [`WebPkiVerifier`],
+/// as returned by [`WebPkiVerifier::policy_summary`].
+///
+/// This contains no key material.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifierPolicy {
+    /// The number of trust anchors.
+    pub root_count: usize,
+    /// The number of trusted Certificate Transparency logs.  Zero means
+    /// SCTs are not checked.
+    pub ct_log_count: usize,
+    /// How SCTs are validated, if there are any trusted logs.
+    pub ct_policy: CtPolicy,
+    /// The signature schemes accepted for handshake signatures.
+    pub signature_schemes: Vec<SignatureScheme>,
+    /// The signature schemes accepted for certificate chains and OCSP
+    /// responses.
+    pub chain_signature_schemes: Vec<SignatureScheme>,
+    /// How stapled OCSP responses are validated, if at all.
+    pub ocsp_policy: Option<OcspPolicy>,
+    /// Whether OCSP failures are reported to an observer rather than
+    /// rejecting the certificate.
+    pub ocsp_soft_fail: bool,
+    /// The end-entity key algorithms allowed, or `None` for any.
+    pub allowed_key_algorithms: Option<Vec<SignatureAlgorithm>>,
+    /// The limit on end-entity certificate validity periods, if any.
+    pub validity_policy: Option<ValidityPolicy>,
+    /// The earliest `notBefore` allowed for end-entity certificates,
+    /// if any.
+    pub min_not_before: Option<SystemTime>,
+    /// How far certificates may be outside their validity period, if
+    /// at all.
+    pub clock_skew_tolerance: Option<Duration>,
+    /// The number of keys for which expired chains are accepted.
+    pub expiry_override_pin_count: usize,
+    /// Whether trust anchor usage is being recorded.
+    pub anchor_usage_tracking: bool,
+    /// Whether authority and subject key identifiers must match.
+    pub aki_ski_check: bool,
+    /// Whether intermediates must allow the `serverAuth` usage.
+    pub eku_chaining_check: bool,
+    /// Whether must-staple certificates require a stapled OCSP
+    /// response.
+    pub must_staple: bool,
+    /// The number of public suffixes wildcard names are checked
+    /// against, or `None` if they aren't.
+    pub public_suffix_count: Option<usize>,
+    /// The fewest significant octets allowed in end-entity serial
+    /// numbers, or `None` if serial numbers aren't checked.
+    pub serial_number_min_octets: Option<usize>,
+    /// Whether missing intermediates are fetched by an
+    /// [`IntermediateFetcher`](crate::IntermediateFetcher).
+    pub intermediate_fetching: bool,
+    /// The number of known intermediates used to complete chains.
+    pub known_intermediate_count: usize,
+    /// Whether certificates are checked against a revocation filter.
+    pub revocation_filter: bool,
+}
+
+fn check_validity_period(end_entity: &Certificate, policy: &ValidityPolicy) -> Result<(), Error> {
+    let cert = x509::CertFields::parse(&end_entity.0).ok_or(Error::WebPkiError(
+        webpki::Error::BadDer,
+        WebPkiOp::ParseEndEntity,
+    ))?;
+
+    if cert.not_before < unix_time_secs(policy.issued_after)? {
+        return Ok(());
+    }
+
+    let validity = cert
+        .not_after
+        .saturating_sub(cert.not_before);
+    if validity > policy.max_validity.as_secs() {
+        debug!(
+            "certificate is valid for {}s, more than {:?}",
+            validity, policy.max_validity
+        );
+        return Err(Error::CertificateValidityTooLong);
+    }
+
+    Ok(())
+}
+
+/// A certificate in the chain found by `build_path`.
+struct PathEntry<'a> {
+    position: ChainPosition,
+    /// The serial number's `INTEGER` contents, or empty for a trust
+    /// anchor.
+    serial: &'a [u8],
+    subject: &'a [u8],
+    spki: &'a [u8],
+    not_after: Option<u64>,
+}
+
+impl PathEntry<'_> {
+    fn to_path_certificate(&self) -> PathCertificate {
+        let mut subject = self.subject.to_vec();
+        x509::wrap_in_sequence(&mut subject);
+
+        PathCertificate {
+            position: self.position,
+            subject,
+            key_algorithm: x509::key_algorithm(self.spki),
+            key_bits: x509::key_bits(self.spki),
+        }
+    }
+}
+
+/// Follow the chain from `end_entity` by issuer name, through
+/// `intermediates`, to a trust anchor in `roots`.  The chain stops
+/// short if an issuer isn't found.
+fn build_path<'a>(
+    end_entity: &'a Certificate,
+    intermediates: &'a [Certificate],
+    roots: &'a RootCertStore,
+) -> Result<Vec<PathEntry<'a>>, Error> {
+    let mut cert = x509::CertFields::parse(&end_entity.0).ok_or(Error::WebPkiError(
+        webpki::Error::BadDer,
+        WebPkiOp::ParseEndEntity,
+    ))?;
+    let mut path = vec![PathEntry {
+        position: ChainPosition::EndEntity,
+        serial: cert.serial,
+        subject: cert.subject,
+        spki: cert.spki,
+        not_after: Some(cert.not_after),
+    }];
+    let mut used = vec![false; intermediates.len()];
+
+    loop {
+        if let Some(anchor) = roots
+            .roots
+            .iter()
+            .find(|ota| ota.to_trust_anchor().subject == cert.issuer)
+        {
+            let ta = anchor.to_trust_anchor();
+            path.push(PathEntry {
+                position: ChainPosition::TrustAnchor,
+                serial: &[],
+                subject: ta.subject,
+                spki: ta.spki,
+                not_after: anchor.not_after(),
+            });
+            break;
+        }
+
+        let issuer = intermediates
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !used[*i])
+            .filter_map(|(i, c)| x509::CertFields::parse(&c.0).map(|c| (i, c)))
+            .find(|(_, c)| c.subject == cert.issuer);
+
+        match issuer {
+            Some((i, issuer)) => {
+                used[i] = true;
+                path.push(PathEntry {
+                    position: ChainPosition::Intermediate(i),
+                    serial: issuer.serial,
+                    subject: issuer.subject,
+                    spki: issuer.spki,
+                    not_after: Some(issuer.not_after),
+                });
+                cert = issuer;
+            }
+            None => break,
+        }
+    }
+
+    Ok(path)
+}
+
+/// Check each certificate on `path`, other than the trust anchor,
+/// against `filter`.
+fn check_revocation_filter(path: &[PathEntry], filter: &dyn RevocationFilter) -> Result<(), Error> {
+    for pair in path.windows(2) {
+        let (cert, issuer) = (&pair[0], &pair[1]);
+        let mut issuer_spki = issuer.spki.to_vec();
+        x509::wrap_in_sequence(&mut issuer_spki);
+        if filter.is_revoked(&issuer_spki, cert.serial) {
+            debug!("revocation filter says {:?} is revoked", cert.position);
+            return Err(Error::CertificateRevoked);
+        }
+    }
+    Ok(())
+}
+
+/// Find the certificate in `path` which expires first.  Ties go to the
+/// certificate nearest the end-entity, and trust anchors without a
+/// known expiry are not considered.
+fn earliest_expiry(path: &[PathEntry]) -> ChainExpiry {
+    let mut earliest = (path[0].not_after.unwrap_or(u64::MAX), &path[0]);
+    for entry in &path[1..] {
+        match entry.not_after {
+            Some(not_after) if not_after < earliest.0 => earliest = (not_after, entry),
+            _ => {}
+        }
+    }
+
+    let (not_after, entry) = earliest;
+    let mut subject = entry.subject.to_vec();
+    x509::wrap_in_sequence(&mut subject);
+
+    ChainExpiry {
+        not_after: std::time::UNIX_EPOCH + Duration::from_secs(not_after),
+        position: entry.position,
+        subject,
+    }
+}
+
+/// Returns `true` if `end_entity` has the TLS feature extension, and
+/// it includes `status_request`.
+fn requires_ocsp_staple(end_entity: &Certificate) -> bool {
+    const STATUS_REQUEST: &[u8] = &[5];
+
+    x509::CertFields::parse(&end_entity.0)
+        .and_then(|cert| cert.tls_features())
+        .map(|features| features.contains(&STATUS_REQUEST))
+        .unwrap_or(false)
+}
+
+/// Reject `end_entity` if its `notBefore` precedes `cutoff`.
+fn check_not_before(end_entity: &Certificate, cutoff: SystemTime) -> Result<(), Error> {
+    let cert = x509::CertFields::parse(&end_entity.0).ok_or(Error::WebPkiError(
+        webpki::Error::BadDer,
+        WebPkiOp::ParseEndEntity,
+    ))?;
+
+    if cert.not_before < unix_time_secs(cutoff)? {
+        debug!(
+            "certificate notBefore {} precedes cutoff {:?}",
+            cert.not_before, cutoff
+        );
+        return Err(Error::CertificateIssuedTooEarly);
+    }
+
+    Ok(())
+}
+
+/// Check `end_entity` (already parsed as `cert`) is valid for
+/// `server_name`.
+pub(crate) fn verify_server_name(
+    cert: &webpki::EndEntityCert,
+    end_entity: &Certificate,
+    server_name: &ServerName,
+) -> Result<(), Error> {
+    match server_name {
+        ServerName::DnsName(dns_name) => cert
+            .verify_is_valid_for_dns_name(dns_name.as_ref())
+            .map_err(|e| Error::WebPkiError(e, WebPkiOp::ValidateForDnsName)),
+        ServerName::IpAddress(addr) => verify_ip_address(end_entity, *addr),
+    }
+}
+
+/// How the peer whose certificate is verified is identified.
+enum PeerIdentity<'a> {
+    ServerName(&'a ServerName),
+    Email(&'a str),
+    Uri(&'a str),
+}
+
+/// Check `end_entity` has a subject alternative name with `tag` for
+/// which `matches` is true, failing with `CertNotValidForName` for `op`.
+fn verify_subject_alt_name(
+    end_entity: &Certificate,
+    tag: u8,
+    op: WebPkiOp,
+    matches: impl Fn(&[u8]) -> bool,
+) -> Result<(), Error> {
+    let cert = x509::CertFields::parse(&end_entity.0).ok_or(Error::WebPkiError(
+        webpki::Error::BadDer,
+        WebPkiOp::ParseEndEntity,
+    ))?;
+
+    let found = cert
+        .subject_alt_names()
+        .unwrap_or_default()
+        .into_iter()
+        .any(|(t, value)| t == tag && matches(value));
+    if !found {
+        return Err(Error::WebPkiError(webpki::Error::CertNotValidForName, op));
+    }
+
+    Ok(())
+}
+
+/// Match `email` against the certificate's rfc822Name subject
+/// alternative names.  A domain-only pattern, as accepted by
+/// `AllowAnyAuthenticatedClient::new_requiring_email`, matches nothing.
+fn verify_email(end_entity: &Certificate, email: &str) -> Result<(), Error> {
+    let complete = !email.starts_with('@');
+    verify_subject_alt_name(
+        end_entity,
+        x509::GENERAL_NAME_RFC822_NAME,
+        WebPkiOp::ValidateForEmail,
+        |value| match std::str::from_utf8(value) {
+            Ok(value) => complete && email_matches(value, email),
+            Err(_) => false,
+        },
+    )
+}
+
+/// Match `uri` against the certificate's uniformResourceIdentifier
+/// subject alternative names.
+fn verify_uri(end_entity: &Certificate, uri: &str) -> Result<(), Error> {
+    verify_subject_alt_name(
+        end_entity,
+        x509::GENERAL_NAME_URI,
+        WebPkiOp::ValidateForUri,
+        |value| match std::str::from_utf8(value) {
+            Ok(value) => uri_matches(value, uri),
+            Err(_) => false,
+        },
+    )
+}
+
+/// Compare URIs, ignoring the case of the scheme and host.
+fn uri_matches(uri: &str, expected: &str) -> bool {
+    normalize_uri(uri) == normalize_uri(expected)
+}
+
+/// Lowercase the scheme of `uri` and, if it has an authority, its host.
+/// Anything else, such as the user of `sip:alice@example.com`, may be
+/// case-sensitive so is left alone.
+fn normalize_uri(uri: &str) -> String {
+    let (scheme, rest) = match uri.find(':') {
+        Some(i) => uri.split_at(i),
+        None => return uri.to_string(),
+    };
+
+    let mut normalized = scheme.to_ascii_lowercase();
+    match rest.strip_prefix("://") {
+        Some(rest) => {
+            let authority_end = rest
+                .find(&['/', '?', '#'][..])
+                .unwrap_or(rest.len());
+            let (authority, path) = rest.split_at(authority_end);
+            let (user_info, host) = match authority.rfind('@') {
+                Some(i) => authority.split_at(i + 1),
+                None => ("", authority),
+            };
+            normalized.push_str("://");
+            normalized.push_str(user_info);
+            normalized.push_str(&host.to_ascii_lowercase());
+            normalized.push_str(path);
+        }
+        None => normalized.push_str(rest),
+    }
+    normalized
+}
+
+/// webpki only matches DNS names, so IP addresses are matched here
+/// against the certificate's iPAddress subject alternative names.  The
+/// subject common name is never consulted.
+fn verify_ip_address(end_entity: &Certificate, addr: IpAddr) -> Result<(), Error> {
+    verify_subject_alt_name(
+        end_entity,
+        x509::GENERAL_NAME_IP_ADDRESS,
+        WebPkiOp::ValidateForIpAddress,
+        |value| match addr {
+            IpAddr::V4(addr) => value == addr.octets(),
+            IpAddr::V6(addr) => value == addr.octets(),
+        },
+    )
+}
+
+/// Check the serial number of `end_entity` as described in
+/// `WebPkiVerifier::with_serial_number_check`.
+fn check_serial_number(end_entity: &Certificate, min_octets: usize) -> Result<(), Error> {
+    let cert = x509::CertFields::parse(&end_entity.0).ok_or(Error::WebPkiError(
+        webpki::Error::BadDer,
+        WebPkiOp::ParseEndEntity,
+    ))?;
+
+    let significant = match cert.serial {
+        [] => &[][..],
+        [0, rest @ ..] => rest,
+        serial => serial,
+    };
+
+    let negative = matches!(cert.serial.first(), Some(b) if b & 0x80 != 0);
+    let zero = significant.iter().all(|b| *b == 0);
+
+    if negative || zero || cert.serial.len() > 20 || significant.len() < min_octets {
+        debug!("rejecting serial number {:?}", cert.serial);
+        return Err(Error::InvalidSerialNumber);
+    }
+
+    Ok(())
+}
+
+/// Reject `end_entity` if any of its DNS name SANs is a wildcard over
+/// one of `suffixes`, or over a single label.
+fn check_wildcard_suffixes(end_entity: &Certificate, suffixes: &[String]) -> Result<(), Error> {
+    let cert = x509::CertFields::parse(&end_entity.0).ok_or(Error::WebPkiError(
+        webpki::Error::BadDer,
+        WebPkiOp::ParseEndEntity,
+    ))?;
+
+    let names = cert
+        .subject_alt_names()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(tag, _)| *tag == x509::GENERAL_NAME_DNS_NAME)
+        .filter_map(|(_, name)| std::str::from_utf8(name).ok());
+
+    for name in names {
+        let rest = match name.strip_prefix("*.") {
+            Some(rest) => rest
+                .trim_end_matches('.')
+                .to_ascii_lowercase(),
+            None => continue,
+        };
+
+        if !rest.contains('.') || suffixes.contains(&rest) {
+            debug!("wildcard name {:?} spans a public suffix", name);
+            return Err(Error::WildcardSpansPublicSuffix);
+        }
+    }
+
+    Ok(())
+}
+
+/// Walk the chain up from `end_entity` through `intermediates`, checking
+/// each certificate's authority key identifier against its issuer's
+/// subject key identifier.  The chain must already have been validated.
+fn check_aki_ski(end_entity: &Certificate, intermediates: &[Certificate]) -> Result<(), Error> {
+    let intermediates: Vec<x509::CertFields> = intermediates
+        .iter()
+        .filter_map(|c| x509::CertFields::parse(&c.0))
+        .collect();
+    let mut cert = x509::CertFields::parse(&end_entity.0).ok_or(Error::WebPkiError(
+        webpki::Error::BadDer,
+        WebPkiOp::ParseEndEntity,
+    ))?;
+
+    // Each intermediate is used at most once, so this terminates.
+    for _ in 0..intermediates.len() {
+        let issuer = intermediates.iter().find(|issuer| {
+            issuer.subject == cert.issuer
+                && x509::signature_scheme_for_algorithm(cert.signature_alg)
+                    .map(|scheme| {
+                        x509::verify_signed_data(issuer.spki, scheme, cert.tbs, cert.signature)
+                            .is_ok()
+                    })
+                    .unwrap_or(false)
+        });
+
+        let issuer = match issuer {
+            Some(issuer) => issuer,
+            None => break, // issued by a trust anchor
+        };
+
+        match (
+            cert.authority_key_identifier(),
+            issuer.subject_key_identifier(),
+        ) {
+            (Some(aki), Some(ski)) if aki != ski => {
+                debug!("AKI {:?} does not match issuer SKI {:?}", aki, ski);
+                return Err(Error::AkiSkiMismatch);
+            }
+            _ => {}
+        }
+
+        cert = *issuer;
+    }
+
+    Ok(())
+}
+
+/// Check that each of `intermediates` which restricts its extended key
+/// usage allows `serverAuth`, or any usage.  Unparseable certificates are left for
+/// chain validation to reject.
+fn check_eku_chaining(intermediates: &[Certificate]) -> Result<(), Error> {
+    for cert in intermediates
+        .iter()
+        .filter_map(|c| x509::CertFields::parse(&c.0))
+    {
+        if let Some(ekus) = cert.extended_key_usage() {
+            if !ekus.contains(&x509::oid::KP_SERVER_AUTH)
+                && !ekus.contains(&x509::oid::ANY_EXTENDED_KEY_USAGE)
+            {
+                debug!("intermediate EKUs {:?} exclude serverAuth", ekus);
+                return Err(Error::EkuNotChained);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// How a trust anchor has been used by a [`WebPkiVerifier`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnchorUsage {
+    /// The anchor's subject, as a DER-encoded X.500 name.
+    pub subject: Vec<u8>,
+    /// SHA-256 hash of the anchor's DER-encoded subjectPublicKeyInfo.
+    pub spki_sha256: Vec<u8>,
+    /// The number of certificate chains validated by this anchor.
+    pub count: u64,
+    /// When this anchor first validated a chain.
+    pub first_used: Option<SystemTime>,
+    /// When this anchor most recently validated a chain.
+    pub last_used: Option<SystemTime>,
+}
+
+struct AnchorSeen {
+    count: u64,
+    first_used: SystemTime,
+    last_used: SystemTime,
+}
+
+/// Usage of trust anchors, keyed by `spki_sha256`.  Keying by
+/// key rather than position means statistics are not confused
+/// by anchors with the same subject.
+#[derive(Default)]
+struct AnchorUsageTracker {
+    by_spki: HashMap<Vec<u8>, AnchorSeen>,
+}
+
+impl AnchorUsageTracker {
+    fn record(&mut self, ta: &webpki::TrustAnchor, now: SystemTime) {
+        let seen = self
+            .by_spki
+            .entry(spki_sha256(ta))
+            .or_insert(AnchorSeen {
+                count: 0,
+                first_used: now,
+                last_used: now,
+            });
+        seen.count += 1;
+        seen.last_used = now;
+    }
+}
+
+fn spki_sha256(ta: &webpki::TrustAnchor) -> Vec<u8> {
+    let mut spki = ta.spki.to_vec();
+    x509::wrap_in_sequence(&mut spki);
+    ring::digest::digest(&ring::digest::SHA256, &spki)
+        .as_ref()
+        .to_vec()
+}
+
+/// Find the anchor in `trustroots` which `cert` chains to.  `cert`
+/// must already have been validated against `trustroots`.
+///
+/// Only anchors named as the issuer of some certificate in the chain
+/// are candidates; each is tried alone until the chain validates.
+fn matched_anchor<'b>(
+    end_entity: &Certificate,
+    cert: &webpki::EndEntityCert,
+    chain: &[&[u8]],
+    trustroots: &'b [webpki::TrustAnchor<'b>],
+    signature_algorithms: SignatureAlgorithms,
+    now: webpki::Time,
+) -> Option<&'b webpki::TrustAnchor<'b>> {
+    let issuers: Vec<&[u8]> = Some(end_entity.0.as_ref())
+        .into_iter()
+        .chain(chain.iter().copied())
+        .filter_map(x509::CertFields::parse)
+        .map(|c| c.issuer)
+        .collect();
+
+    trustroots.iter().find(|ta| {
+        issuers.contains(&ta.subject)
+            && cert
+                .verify_is_valid_tls_server_cert(
+                    signature_algorithms,
+                    &webpki::TlsServerTrustAnchors(std::slice::from_ref(*ta)),
+                    chain,
+                    now,
+                )
+                .is_ok()
+    })
+}
+
+/// Check the chain from `end_entity` meets the name constraints set
+/// with `OwnedTrustAnchor::set_name_constraints` for some anchor in
+/// `roots` it leads to, according to `chains_to`.  `trustroots` is
+/// `roots` as returned by `prepare` for `now`.
+///
+/// webpki has already accepted the chain, so this only does the
+/// per-anchor path validation if some anchor has such constraints.
+fn check_extra_name_constraints(
+    end_entity: &Certificate,
+    intermediates: &[Certificate],
+    roots: &RootCertStore,
+    trustroots: &[webpki::TrustAnchor],
+    now: SystemTime,
+    op: WebPkiOp,
+    chains_to: impl Fn(&webpki::TrustAnchor) -> bool,
+) -> Result<(), Error> {
+    if roots
+        .roots
+        .iter()
+        .all(|ota| ota.extra_name_constraints().is_none())
+    {
+        return Ok(());
+    }
+
+    let end_entity = x509::CertFields::parse(&end_entity.0).ok_or(Error::WebPkiError(
+        webpki::Error::BadDer,
+        WebPkiOp::ParseEndEntity,
+    ))?;
+    let intermediates: Vec<x509::CertFields> = intermediates
+        .iter()
+        .filter_map(|cert| x509::CertFields::parse(&cert.0))
+        .collect();
+
+    // The certificates from `end_entity` up to the one issued by `ta`,
+    // following issuer names.
+    let path_to = |ta: &webpki::TrustAnchor| {
+        let mut path = vec![&end_entity];
+        let mut used = vec![false; intermediates.len()];
+        while path.last().unwrap().issuer != ta.subject {
+            let issuer = path.last().unwrap().issuer;
+            match (0..intermediates.len()).find(|&i| !used[i] && intermediates[i].subject == issuer)
+            {
+                Some(i) => {
+                    used[i] = true;
+                    path.push(&intermediates[i]);
+                }
+                None => break,
+            }
+        }
+        path
+    };
+
+    let acceptable = roots
+        .trusted_at(now)
+        .zip(trustroots)
+        .any(|(ota, ta)| match ota.extra_name_constraints() {
+            None => chains_to(ta),
+            Some(constraints) => {
+                path_to(ta)
+                    .iter()
+                    .all(|cert| constraints.permits(cert))
+                    && chains_to(ta)
+            }
+        });
+    if !acceptable {
+        debug!("chain does not meet name constraints of its trust anchor");
+        return Err(Error::WebPkiError(
+            webpki::Error::NameConstraintViolation,
+            op,
+        ));
+    }
+
+    Ok(())
+}
+
+type CertChainAndRoots<'a, 'b> = (
+    webpki::EndEntityCert<'a>,
+    Vec<&'a [u8]>,
+    Vec<webpki::TrustAnchor<'b>>,
+);
+
+/// Parse `end_entity` and get the anchors in `roots` trusted at `now`,
+/// ready for webpki.
+pub(crate) fn prepare<'a, 'b>(
+    end_entity: &'a Certificate,
+    intermediates: &'a [Certificate],
+    roots: &'b RootCertStore,
+    now: SystemTime,
+) -> Result<CertChainAndRoots<'a, 'b>, Error> {
+    // EE cert must appear first.
+    let cert = webpki::EndEntityCert::try_from(end_entity.0.as_ref())
+        .map_err(|e| Error::WebPkiError(e, WebPkiOp::ParseEndEntity))?;
+
+    let intermediates: Vec<&'a [u8]> = intermediates
+        .iter()
+        .map(|cert| cert.0.as_ref())
+        .collect();
+
+    let trustroots: Vec<webpki::TrustAnchor> = roots
+        .trusted_at(now)
+        .map(OwnedTrustAnchor::to_trust_anchor)
+        .collect();
+
+    Ok((cert, intermediates, trustroots))
+}
+
+/// A `ClientCertVerifier` that will ensure that every client provides a trusted
+/// certificate, without any name checking.
+pub struct AllowAnyAuthenticatedClient {
+    roots: RootCertStore,
+    email_pattern: Option<String>,
+    required_ekus: Vec<Vec<u8>>,
+    acceptable_policies: Vec<Vec<u8>>,
+    signature_algorithms: SignatureAlgorithms,
+}
+
+impl AllowAnyAuthenticatedClient {
+    /// Construct a new `AllowAnyAuthenticatedClient`.
+    ///
+    /// `roots` is the list of trust anchors to use for certificate validation.
+    pub fn new(roots: RootCertStore) -> Arc<dyn ClientCertVerifier> {
+        Arc::new(AllowAnyAuthenticatedClient::from_roots(roots))
+    }
+
+    fn from_roots(roots: RootCertStore) -> Self {
+        Self {
+            roots,
+            email_pattern: None,
+            required_ekus: Vec::new(),
+            acceptable_policies: Vec::new(),
+            signature_algorithms: SUPPORTED_SIG_ALGS,
+        }
+    }
+
+    /// Construct a new `AllowAnyAuthenticatedClient` which only accepts
+    /// client certificate chains signed using `algorithms`.
+    ///
+    /// See [`WebPkiVerifier::with_signature_algorithms`].
+    pub fn new_with_signature_algorithms(
+        roots: RootCertStore,
+        algorithms: &'static [&'static webpki::SignatureAlgorithm],
+    ) -> Arc<Self> {
+        Arc::new(AllowAnyAuthenticatedClient {
+            signature_algorithms: algorithms,
+            ..AllowAnyAuthenticatedClient::from_roots(roots)
+        })
+    }
+
+    /// Construct a new `AllowAnyAuthenticatedClient` which also requires
+    /// the client certificate to have an email address (rfc822Name)
+    /// subject alternative name matching `pattern`.
+    ///
+    /// A `pattern` like `@example.com` matches any address in that
+    /// domain; otherwise it must be a complete address.  Domains are
+    /// compared case-insensitively.  Certificates without a matching
+    /// address are rejected with `Error::EmailIdentityMismatch`.
+    ///
+    /// Keep a clone of the result to find the matched address
+    /// with [`AllowAnyAuthenticatedClient::email_identity`].
+    pub fn new_requiring_email(roots: RootCertStore, pattern: &str) -> Arc<Self> {
+        Arc::new(AllowAnyAuthenticatedClient {
+            email_pattern: Some(pattern.to_string()),
+            ..AllowAnyAuthenticatedClient::from_roots(roots)
+        })
+    }
+
+    /// Construct a new `AllowAnyAuthenticatedClient` which also requires
+    /// the client certificate to have been issued for a particular
+    /// purpose.
+    ///
+    /// The certificate's extended key usage extension must include all of
+    /// `extended_key_usages`, and, if `policies` is not empty, its
+    /// certificate policies extension must include at least one of
+    /// `policies`.  Otherwise it is rejected with
+    /// `Error::RequiredEkuMissing` or `Error::RequiredPolicyMissing`.
+    /// `anyExtendedKeyUsage` and `anyPolicy` get no special treatment.
+    ///
+    /// Each OID is given as the contents of its DER encoding: for example
+    /// `[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x02]` for `clientAuth`.
+    pub fn new_requiring_purpose(
+        roots: RootCertStore,
+        extended_key_usages: &[&[u8]],
+        policies: &[&[u8]],
+    ) -> Arc<Self> {
+        Arc::new(AllowAnyAuthenticatedClient {
+            required_ekus: extended_key_usages
+                .iter()
+                .map(|oid| oid.to_vec())
+                .collect(),
+            acceptable_policies: policies
+                .iter()
+                .map(|oid| oid.to_vec())
+                .collect(),
+            ..AllowAnyAuthenticatedClient::from_roots(roots)
+        })
+    }
+
+    /// Returns the email address in `end_entity` which satisfied this
+    /// verifier's requirement, such as the first of
+    /// `ServerConnection::peer_certificates`.
+    ///
+    /// Returns `None` if no email address is required.
+    pub fn email_identity(&self, end_entity: &Certificate) -> Option<String> {
+        let pattern = self.email_pattern.as_ref()?;
+        let cert = x509::CertFields::parse(&end_entity.0)?;
+
+        cert.subject_alt_names()?
+            .into_iter()
+            .filter(|(tag, _)| *tag == x509::GENERAL_NAME_RFC822_NAME)
+            .filter_map(|(_, email)| std::str::from_utf8(email).ok())
+            .find(|email| email_matches(email, pattern))
+            .map(str::to_string)
+    }
+}
+
+impl AllowAnyAuthenticatedClient {
+    /// Check the purposes required by `new_requiring_purpose`.
+    fn check_purpose(&self, end_entity: &Certificate) -> Result<(), Error> {
+        let cert = x509::CertFields::parse(&end_entity.0).ok_or(Error::WebPkiError(
+            webpki::Error::BadDer,
+            WebPkiOp::ParseEndEntity,
+        ))?;
+
+        let ekus = cert
+            .extended_key_usage()
+            .unwrap_or_default();
+        if !self
+            .required_ekus
+            .iter()
+            .all(|eku| ekus.contains(&eku.as_slice()))
+        {
+            debug!("client certificate EKUs {:?} not acceptable", ekus);
+            return Err(Error::RequiredEkuMissing);
+        }
+
+        if !self.acceptable_policies.is_empty() {
+            let policies = cert
+                .certificate_policies()
+                .unwrap_or_default();
+            if !self
+                .acceptable_policies
+                .iter()
+                .any(|policy| policies.contains(&policy.as_slice()))
+            {
+                debug!("client certificate policies {:?} not acceptable", policies);
+                return Err(Error::RequiredPolicyMissing);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Does `email` satisfy `pattern`?  See
+/// `AllowAnyAuthenticatedClient::new_requiring_email`.
+fn email_matches(email: &str, pattern: &str) -> bool {
+    fn split(addr: &str) -> Option<(&str, &str)> {
+        addr.rfind('@')
+            .map(|at| (&addr[..at], &addr[at + 1..]))
+    }
+
+    match (split(email), split(pattern)) {
+        (Some((local, domain)), Some((want_local, want_domain))) => {
+            !local.is_empty()
+                && (want_local.is_empty() || local == want_local)
+                && domain.eq_ignore_ascii_case(want_domain)
+        }
+        _ => false,
+    }
+}
+
+impl ClientCertVerifier for AllowAnyAuthenticatedClient {
+    fn offer_client_auth(&self) -> bool {
+        true
+    }
+
+    fn client_auth_mandatory(&self, _sni: Option<&webpki::DnsName>) -> Option<bool> {
+        Some(true)
+    }
+
+    fn client_auth_root_subjects(
+        &self,
+        _sni: Option<&webpki::DnsName>,
+    ) -> Option<DistinguishedNames> {
+        Some(self.roots.subjects())
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        _sni: Option<&webpki::DnsName>,
+        now: SystemTime,
+    ) -> Result<ClientCertVerified, Error> {
+        let (cert, chain, trustroots) = prepare(end_entity, intermediates, &self.roots, now)?;
+        let webpki_now = webpki::Time::try_from(now).map_err(|_| Error::FailedToGetCurrentTime)?;
+        cert.verify_is_valid_tls_client_cert(
+            self.signature_algorithms,
+            &webpki::TlsClientTrustAnchors(&trustroots),
+            &chain,
+            webpki_now,
+        )
+        .map_err(|e| Error::WebPkiError(e, WebPkiOp::ValidateClientCert))?;
+
+        check_extra_name_constraints(
+            end_entity,
+            intermediates,
+            &self.roots,
+            &trustroots,
+            now,
+            WebPkiOp::ValidateClientCert,
+            |ta| {
+                cert.verify_is_valid_tls_client_cert(
+                    self.signature_algorithms,
+                    &webpki::TlsClientTrustAnchors(std::slice::from_ref(ta)),
+                    &chain,
+                    webpki_now,
+                )
+                .is_ok()
+            },
+        )?;
+
+        if self.email_pattern.is_some() {
+            self.email_identity(end_entity)
+                .ok_or(Error::EmailIdentityMismatch)?;
+        }
+
+        if !self.required_ekus.is_empty() || !self.acceptable_policies.is_empty() {
+            self.check_purpose(end_entity)?;
+        }
+
+        Ok(ClientCertVerified::assertion())
+    }
+}
+
+/// Computes the trust anchors for client certificates, given the
+/// SNI sent by the client (if any).
+pub type ClientRootResolver = dyn Fn(Option<&webpki::DnsName>) -> RootCertStore + Send + Sync;
+
+/// A `ClientCertVerifier` which requires every client to provide a trusted
+/// certificate, like `AllowAnyAuthenticatedClient`, but which computes the
+/// trust anchors afresh for each handshake from the client's SNI.
+///
+/// The trait gives us nothing but the SNI to tell concurrent handshakes
+/// apart, so nothing is carried between the certificate request and the
+/// verification of the client's certificate: the resolver is called for
+/// each, and so should give the same answer for the same SNI.
+pub struct DynamicRootClientVerifier {
+    resolver: Arc<ClientRootResolver>,
+}
+
+impl DynamicRootClientVerifier {
+    /// Construct a new `DynamicRootClientVerifier`, which calls `resolver`
+    /// to find the trust anchors for each handshake.
+    pub fn new(resolver: Arc<ClientRootResolver>) -> Arc<dyn ClientCertVerifier> {
+        Arc::new(Self { resolver })
+    }
+}
+
+impl ClientCertVerifier for DynamicRootClientVerifier {
+    fn offer_client_auth(&self) -> bool {
+        true
+    }
+
+    fn client_auth_mandatory(&self, _sni: Option<&webpki::DnsName>) -> Option<bool> {
+        Some(true)
+    }
+
+    fn client_auth_root_subjects(
+        &self,
+        sni: Option<&webpki::DnsName>,
+    ) -> Option<DistinguishedNames> {
+        Some((self.resolver)(sni).subjects())
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        sni: Option<&webpki::DnsName>,
+        now: SystemTime,
+    ) -> Result<ClientCertVerified, Error> {
+        AllowAnyAuthenticatedClient::from_roots((self.resolver)(sni)).verify_client_cert(
+            end_entity,
+            intermediates,
+            sni,
+            now,
+        )
+    }
+}
+
+/// A `ClientCertVerifier` which requires every client to provide a trusted
+/// certificate, like `AllowAnyAuthenticatedClient`, but which trusts
+/// different roots depending on the server name the client sent with SNI.
+///
+/// Clients which send no SNI, or a name without roots of its own, are
+/// verified with the fallback roots.  Without fallback roots, their
+/// handshakes are aborted.
+pub struct SniClientCertVerifier {
+    by_name: HashMap<String, AllowAnyAuthenticatedClient>,
+    fallback: Option<AllowAnyAuthenticatedClient>,
 }
 
-impl AllowAnyAuthenticatedClient {
-    /// Construct a new `AllowAnyAuthenticatedClient`.
+impl SniClientCertVerifier {
+    /// Create a new verifier which knows no server names, and trusts
+    /// `fallback` (if any) for every client.
+    pub fn new(fallback: Option<RootCertStore>) -> Self {
+        Self {
+            by_name: HashMap::new(),
+            fallback: fallback.map(AllowAnyAuthenticatedClient::from_roots),
+        }
+    }
+
+    /// Trust `roots` for clients which send the SNI `name`, instead of
+    /// the fallback roots.
     ///
-    /// `roots` is the list of trust anchors to use for certificate validation.
-    pub fn new(roots: RootCertStore) -> Arc<dyn ClientCertVerifier> {
-        Arc::new(AllowAnyAuthenticatedClient { roots })
+    /// This function fails if `name` is not a valid DNS name.
+    pub fn add(&mut self, name: &str, roots: RootCertStore) -> Result<(), Error> {
+        let checked_name = webpki::DnsNameRef::try_from_ascii_str(name)
+            .map_err(|_| Error::General("Bad DNS name".into()))?
+            .to_owned();
+        let name: &str = checked_name.as_ref().into();
+        self.by_name.insert(
+            name.to_string(),
+            AllowAnyAuthenticatedClient::from_roots(roots),
+        );
+        Ok(())
+    }
+
+    fn verifier_for(&self, sni: Option<&webpki::DnsName>) -> Option<&AllowAnyAuthenticatedClient> {
+        sni.and_then(|name| {
+            let name: &str = name.as_ref().into();
+            self.by_name.get(name)
+        })
+        .or(self.fallback.as_ref())
     }
 }
 
-impl ClientCertVerifier for AllowAnyAuthenticatedClient {
+impl ClientCertVerifier for SniClientCertVerifier {
     fn offer_client_auth(&self) -> bool {
         true
     }
 
-    fn client_auth_mandatory(&self, _sni: Option<&webpki::DnsName>) -> Option<bool> {
-        Some(true)
+    fn client_auth_mandatory(&self, sni: Option<&webpki::DnsName>) -> Option<bool> {
+        self.verifier_for(sni).map(|_| true)
     }
 
     fn client_auth_root_subjects(
         &self,
-        _sni: Option<&webpki::DnsName>,
+        sni: Option<&webpki::DnsName>,
     ) -> Option<DistinguishedNames> {
-        Some(self.roots.subjects())
+        self.verifier_for(sni)
+            .map(|verifier| verifier.roots.subjects())
     }
 
     fn verify_client_cert(
         &self,
         end_entity: &Certificate,
         intermediates: &[Certificate],
-        _sni: Option<&webpki::DnsName>,
+        sni: Option<&webpki::DnsName>,
         now: SystemTime,
     ) -> Result<ClientCertVerified, Error> {
-        let (cert, chain, trustroots) = prepare(end_entity, intermediates, &self.roots)?;
-        let now = webpki::Time::try_from(now).map_err(|_| Error::FailedToGetCurrentTime)?;
-        cert.verify_is_valid_tls_client_cert(
-            SUPPORTED_SIG_ALGS,
-            &webpki::TlsClientTrustAnchors(&trustroots),
-            &chain,
-            now,
-        )
-        .map_err(|e| Error::WebPkiError(e, WebPkiOp::ValidateClientCert))
-        .map(|_| ClientCertVerified::assertion())
+        self.verifier_for(sni)
+            .ok_or(Error::WebPkiError(
+                webpki::Error::UnknownIssuer,
+                WebPkiOp::ValidateClientCert,
+            ))?
+            .verify_client_cert(end_entity, intermediates, sni, now)
     }
 }
 
@@ -463,11 +3052,156 @@ impl ClientCertVerifier for NoClientAuth {
     }
 }
 
+#[derive(Clone)]
 enum ClientCertVerifyMode {
     AllowAnyClient,
-    MustVerifyClientCert(AllowAnyAuthenticatedClient),
+    MustVerifyClientCert(Arc<AllowAnyAuthenticatedClient>),
+}
+
+impl ClientCertVerifyMode {
+    fn must_verify(roots: RootCertStore) -> Self {
+        Self::MustVerifyClientCert(Arc::new(AllowAnyAuthenticatedClient::from_roots(roots)))
+    }
+
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        match self {
+            Self::AllowAnyClient => 0u8.encode(bytes),
+            Self::MustVerifyClientCert(verifier) => {
+                1u8.encode(bytes);
+                verifier.roots.encode(bytes);
+            }
+        }
+    }
+
+    fn read(r: &mut Reader) -> Option<Self> {
+        match u8::read(r)? {
+            0 => Some(Self::AllowAnyClient),
+            1 => Some(Self::must_verify(RootCertStore::read(r)?)),
+            _ => None,
+        }
+    }
+}
+
+/// The modes of a `SafeDefaultClientVerifier` at some point in time.
+/// This is never changed once published; changes are made to a copy.
+#[derive(Clone)]
+struct ClientVerifierState {
+    mode: ClientCertVerifyMode,
+    sni_modes: HashMap<String, ClientCertVerifyMode>,
+    denied_fingerprints: HashSet<[u8; 32]>,
+    denied_serials: HashSet<(Vec<u8>, Vec<u8>)>,
+}
+
+impl ClientVerifierState {
+    /// The mode used for clients connecting to `sni`.
+    fn mode_for(&self, sni: Option<&webpki::DnsName>) -> &ClientCertVerifyMode {
+        sni.and_then(|sni| {
+            let sni: &str = sni.as_ref().into();
+            self.sni_modes
+                .get(&sni.to_ascii_lowercase())
+        })
+        .unwrap_or(&self.mode)
+    }
+
+    /// The version of the encoding written by `encode`.
+    const ENCODING_VERSION: u8 = 1;
+
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        Self::ENCODING_VERSION.encode(bytes);
+        self.mode.encode(bytes);
+
+        // Sorted, so equal states have equal encodings.
+        let mut sni_modes: Vec<_> = self.sni_modes.iter().collect();
+        sni_modes.sort_by(|a, b| a.0.cmp(b.0));
+        (sni_modes.len() as u32).encode(bytes);
+        for (sni, mode) in sni_modes {
+            PayloadU16::encode_slice(sni.as_bytes(), bytes);
+            mode.encode(bytes);
+        }
+
+        let mut fingerprints: Vec<_> = self
+            .denied_fingerprints
+            .iter()
+            .collect();
+        fingerprints.sort();
+        (fingerprints.len() as u32).encode(bytes);
+        for fingerprint in fingerprints {
+            bytes.extend_from_slice(fingerprint);
+        }
+
+        let mut serials: Vec<_> = self.denied_serials.iter().collect();
+        serials.sort();
+        (serials.len() as u32).encode(bytes);
+        for (issuer, serial) in serials {
+            PayloadU16::encode_slice(issuer, bytes);
+            PayloadU16::encode_slice(serial, bytes);
+        }
+    }
+
+    fn read(r: &mut Reader) -> Option<Self> {
+        if u8::read(r)? != Self::ENCODING_VERSION {
+            return None;
+        }
+        let mode = ClientCertVerifyMode::read(r)?;
+
+        let mut sni_modes = HashMap::new();
+        for _ in 0..u32::read(r)? {
+            let sni = String::from_utf8(PayloadU16::read(r)?.0).ok()?;
+            sni_modes.insert(sni, ClientCertVerifyMode::read(r)?);
+        }
+
+        let mut denied_fingerprints = HashSet::new();
+        for _ in 0..u32::read(r)? {
+            denied_fingerprints.insert(<[u8; 32]>::try_from(r.take(32)?).ok()?);
+        }
+
+        let mut denied_serials = HashSet::new();
+        for _ in 0..u32::read(r)? {
+            let issuer = PayloadU16::read(r)?.0;
+            let serial = PayloadU16::read(r)?.0;
+            denied_serials.insert((issuer, serial));
+        }
+
+        Some(Self {
+            mode,
+            sni_modes,
+            denied_fingerprints,
+            denied_serials,
+        })
+    }
+
+    /// Is `end_entity` on the deny-list?
+    fn is_denied(&self, end_entity: &Certificate) -> bool {
+        let fingerprint = ring::digest::digest(&ring::digest::SHA256, &end_entity.0);
+        if let Ok(fingerprint) = <[u8; 32]>::try_from(fingerprint.as_ref()) {
+            if self
+                .denied_fingerprints
+                .contains(&fingerprint)
+            {
+                return true;
+            }
+        }
+
+        if self.denied_serials.is_empty() {
+            return false;
+        }
+
+        match x509::CertFields::parse(&end_entity.0) {
+            Some(fields) => {
+                let mut issuer = fields.issuer.to_vec();
+                x509::wrap_in_sequence(&mut issuer);
+                self.denied_serials
+                    .contains(&(issuer, fields.serial.to_vec()))
+            }
+            None => false,
+        }
+    }
 }
 
+/// Called with the new epoch after each change to a
+/// [`SafeDefaultClientVerifier`]; see its `subscribe` method.
+type EpochSubscriber = dyn Fn(u64) + Send + Sync;
+
 /// A `ClientVerifier` impl which can be set to allow anonymous clients
 /// but will reject anonymous clients when set to verify them.
 ///
@@ -475,19 +3209,119 @@ enum ClientCertVerifyMode {
 /// accept anonymous clients while verifying others who present their
 /// client certificate), a client rejected for presenting a bad certificate
 /// can then turn anonymous and be served.
+///
+/// The mode can be overridden for particular server names, so a server
+/// can require client certificates for one hostname while serving
+/// anonymous clients on others.  Clients connecting to a hostname served
+/// anonymously aren't asked for a certificate, so those holding one are
+/// served anonymously too.
+///
+/// Every change to a mode or root store advances an epoch counter and
+/// notifies any subscribers, so applications can invalidate session
+/// caches and record changes in trust.
+///
+/// Handshakes verify against an immutable snapshot of the modes and root
+/// stores, loaded without taking a lock.  Changes are made to a copy
+/// which then atomically replaces the snapshot, so a handshake never
+/// waits for a change to be made, and sees either all of it or none of it.
+///
+/// Individual client certificates can be denied, for example when their
+/// keys are compromised, without removing trust in their issuer.
 pub struct SafeDefaultClientVerifier {
-    mode: RwLock<ClientCertVerifyMode>,
+    state: ArcSwap<ClientVerifierState>,
+    update_lock: Mutex<()>,
+    epoch: AtomicU64,
+    subscribers: Mutex<Vec<Arc<EpochSubscriber>>>,
 }
 
 impl SafeDefaultClientVerifier {
     /// Creates a new `SafeDefaultClientVerifier` and wraps it in an Arc.
     pub fn new() -> Arc<Self> {
         Arc::new(Self {
-            mode: RwLock::new(ClientCertVerifyMode::MustVerifyClientCert(
-                AllowAnyAuthenticatedClient {
-                    roots: RootCertStore::empty(),
-                },
-            )),
+            state: ArcSwap::from_pointee(ClientVerifierState {
+                mode: ClientCertVerifyMode::must_verify(RootCertStore::empty()),
+                sni_modes: HashMap::new(),
+                denied_fingerprints: HashSet::new(),
+                denied_serials: HashSet::new(),
+            }),
+            update_lock: Mutex::new(()),
+            epoch: AtomicU64::new(0),
+            subscribers: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Returns the number of changes made so far to the modes and root
+    /// stores.  This starts at zero and only ever increases.
+    pub fn epoch(&self) -> u64 {
+        self.epoch.load(Ordering::Acquire)
+    }
+
+    /// Call `callback` with the new epoch after every change to the modes
+    /// and root stores.
+    ///
+    /// `callback` is called on the thread making the change, after the
+    /// change is visible to handshakes and before any later change is
+    /// made, so epochs are delivered in order.  It may read this
+    /// verifier, but must not change it: that would deadlock.
+    pub fn subscribe(&self, callback: impl Fn(u64) + Send + Sync + 'static) {
+        self.subscribers
+            .lock()
+            .push(Arc::new(callback));
+    }
+
+    /// Advance the epoch and notify subscribers, if `changed`.  This
+    /// must be called with `update_lock` held.
+    fn notify_if(&self, changed: bool) {
+        if !changed {
+            return;
+        }
+
+        let epoch = self
+            .epoch
+            .fetch_add(1, Ordering::AcqRel)
+            + 1;
+        let subscribers = self.subscribers.lock().clone();
+        for callback in subscribers {
+            callback(epoch);
+        }
+    }
+
+    /// The current state, which later changes won't affect.
+    fn snapshot(&self) -> Arc<ClientVerifierState> {
+        self.state.load_full()
+    }
+
+    /// Call `f` with a copy of the current state, which it changes and
+    /// returns true, or leaves alone and returns false.  A changed copy
+    /// replaces the current state.
+    fn update<T>(&self, f: impl FnOnce(&mut ClientVerifierState) -> (T, bool)) -> T {
+        let update_guard = self.update_lock.lock();
+        let mut next = ClientVerifierState::clone(&self.snapshot());
+        let (result, changed) = f(&mut next);
+        if changed {
+            self.state.store(Arc::new(next));
+        }
+
+        // Notify before releasing the lock, so no later change can
+        // overtake this one.
+        self.notify_if(changed);
+        drop(update_guard);
+        result
+    }
+
+    /// Call `f` with a copy of the root store if currently serving only
+    /// authenticated clients, as for `update`.  Otherwise, returns `None`.
+    fn update_roots<T>(&self, f: impl FnOnce(&mut RootCertStore) -> (T, bool)) -> Option<T> {
+        self.update(|state| {
+            let mut roots = match &state.mode {
+                ClientCertVerifyMode::AllowAnyClient => return (None, false),
+                ClientCertVerifyMode::MustVerifyClientCert(verifier) => verifier.roots.clone(),
+            };
+            let (result, changed) = f(&mut roots);
+            if changed {
+                state.mode = ClientCertVerifyMode::must_verify(roots);
+            }
+            (Some(result), changed)
         })
     }
 
@@ -496,7 +3330,11 @@ impl SafeDefaultClientVerifier {
     ///
     /// This is a mutating operation managed by interior mutability (mutex).
     pub fn serve_anonymous_clients(&self) {
-        *self.mode.write() = ClientCertVerifyMode::AllowAnyClient;
+        self.update(|state| {
+            let changed = matches!(state.mode, ClientCertVerifyMode::MustVerifyClientCert(_));
+            state.mode = ClientCertVerifyMode::AllowAnyClient;
+            ((), changed)
+        })
     }
 
     /// If currently serving anonymous clients, start serving only
@@ -505,22 +3343,20 @@ impl SafeDefaultClientVerifier {
     /// This is possibly a mutating operation managed by interior mutability
     /// (mutex).
     pub fn serve_only_authenticated_clients(&self) {
-        let mut mode = self.mode.write();
-        match mode.deref() {
+        self.update(|state| match state.mode {
             ClientCertVerifyMode::AllowAnyClient => {
-                *mode = ClientCertVerifyMode::MustVerifyClientCert(AllowAnyAuthenticatedClient {
-                    roots: RootCertStore::empty(),
-                });
+                state.mode = ClientCertVerifyMode::must_verify(RootCertStore::empty());
+                ((), true)
             }
-            ClientCertVerifyMode::MustVerifyClientCert(_) => {}
-        };
+            ClientCertVerifyMode::MustVerifyClientCert(_) => ((), false),
+        })
     }
 
     /// Returns true if currently serving anonymous clients or if currently
     /// serving authenticated clients but no client certificate has been
     /// stored.
     pub fn is_cert_store_empty(&self) -> bool {
-        match self.mode.read().deref() {
+        match &self.snapshot().mode {
             ClientCertVerifyMode::AllowAnyClient => true,
             ClientCertVerifyMode::MustVerifyClientCert(verifier) => verifier.roots.is_empty(),
         }
@@ -529,7 +3365,7 @@ impl SafeDefaultClientVerifier {
     /// Returns the number of client certificates stored in the underlying
     /// certificate store. Returns 0 if currently serving anonymous clients.
     pub fn root_cert_store_len(&self) -> usize {
-        match self.mode.read().deref() {
+        match &self.snapshot().mode {
             ClientCertVerifyMode::AllowAnyClient => 0,
             ClientCertVerifyMode::MustVerifyClientCert(verifier) => verifier.roots.len(),
         }
@@ -539,7 +3375,7 @@ impl SafeDefaultClientVerifier {
     /// store if currently serving authenticated clients. Otherwise,
     /// returns None.
     pub fn root_cert_store_subjects(&self) -> Option<DistinguishedNames> {
-        match self.mode.read().deref() {
+        match &self.snapshot().mode {
             ClientCertVerifyMode::AllowAnyClient => None,
             ClientCertVerifyMode::MustVerifyClientCert(verifier) => Some(verifier.roots.subjects()),
         }
@@ -552,10 +3388,11 @@ impl SafeDefaultClientVerifier {
     /// This is possibly a mutating operation managed by interior mutability
     /// (mutex).
     pub fn add_trusted_root_ca(&self, der: &Certificate) -> Option<Result<(), webpki::Error>> {
-        match self.mode.write().deref_mut() {
-            ClientCertVerifyMode::AllowAnyClient => None,
-            ClientCertVerifyMode::MustVerifyClientCert(verifier) => Some(verifier.roots.add(der)),
-        }
+        self.update_roots(|roots| {
+            let result = roots.add(der);
+            let changed = result.is_ok();
+            (result, changed)
+        })
     }
 
     /// Adds all the given TrustAnchors `anchors` and returns true if
@@ -568,15 +3405,11 @@ impl SafeDefaultClientVerifier {
         &self,
         &webpki::TlsServerTrustAnchors(anchors): &webpki::TlsServerTrustAnchors,
     ) -> bool {
-        match self.mode.write().deref_mut() {
-            ClientCertVerifyMode::AllowAnyClient => false,
-            ClientCertVerifyMode::MustVerifyClientCert(verifier) => {
-                verifier
-                    .roots
-                    .add_server_trust_anchors(&webpki::TlsServerTrustAnchors(anchors));
-                true
-            }
-        }
+        self.update_roots(|roots| {
+            roots.add_server_trust_anchors(&webpki::TlsServerTrustAnchors(anchors));
+            ((), !anchors.is_empty())
+        })
+        .is_some()
     }
 
     /// Parses the given DER-encoded certificates and add all that can be parsed
@@ -593,14 +3426,64 @@ impl SafeDefaultClientVerifier {
     /// This is possibly a mutating operation managed by interior mutability
     /// (mutex).
     pub fn batch_add_certificates(&self, der_certs: &[Vec<u8>]) -> Option<(usize, usize)> {
-        match self.mode.write().deref_mut() {
-            ClientCertVerifyMode::AllowAnyClient => None,
-            ClientCertVerifyMode::MustVerifyClientCert(verifier) => Some(
-                verifier
-                    .roots
-                    .add_parsable_certificates(der_certs),
-            ),
-        }
+        self.update_roots(|roots| {
+            let (added, ignored) = roots.add_parsable_certificates(der_certs);
+            ((added, ignored), added > 0)
+        })
+    }
+
+    /// Removes trust in a single client CA from the underlying
+    /// `RootCertStore` if currently serving only authenticated clients.
+    ///
+    /// `der_or_subject` is either the DER-encoded certificate the CA was
+    /// added from, or its DER-encoded Subject Name as returned by
+    /// `root_cert_store_subjects`.  A certificate removes only the anchor
+    /// with the same subject and public key; a Subject Name removes every
+    /// anchor with that subject.
+    ///
+    /// Returns the number of anchors removed wrapped inside `Some`. If
+    /// currently serving anonymous clients, returns `None` and the function
+    /// is a no-op.
+    ///
+    /// Caveat: cached handshakes using a previously trusted client may be
+    /// left intact.
+    ///
+    /// This is possibly a mutating operation managed by interior mutability
+    /// (mutex).
+    pub fn remove_trusted_root_ca(&self, der_or_subject: &[u8]) -> Option<usize> {
+        self.update_roots(|roots| {
+            let removed = roots
+                .remove(&Certificate(der_or_subject.to_vec()))
+                .unwrap_or_else(|_| roots.remove_by_subject(der_or_subject));
+            (removed, removed > 0)
+        })
+    }
+
+    /// Replaces trust in the client CA added from the DER-encoded
+    /// certificate `old` with trust in `new`, as
+    /// [`RootCertStore::replace`] does, if currently serving only
+    /// authenticated clients.
+    ///
+    /// Returns the number of anchors removed wrapped inside `Some`, or
+    /// an error if either certificate can't be parsed.  If currently
+    /// serving anonymous clients, returns `None` and the function is a
+    /// no-op.
+    ///
+    /// Caveat: cached handshakes using a previously trusted client may be
+    /// left intact.
+    ///
+    /// This is possibly a mutating operation managed by interior mutability
+    /// (mutex).
+    pub fn replace_trusted_root_ca(
+        &self,
+        old: &Certificate,
+        new: &Certificate,
+    ) -> Option<Result<usize, webpki::Error>> {
+        self.update_roots(|roots| {
+            let result = roots.replace(old, new);
+            let changed = result.is_ok();
+            (result, changed)
+        })
     }
 
     /// Empties the underlying `RootCertStore` and returns true if currently
@@ -613,34 +3496,186 @@ impl SafeDefaultClientVerifier {
     /// This is possibly a mutating operation managed by interior mutability
     /// (mutex).
     pub fn reset_root_cert_store(&self) -> bool {
-        match self.mode.write().deref_mut() {
-            ClientCertVerifyMode::AllowAnyClient => false,
-            ClientCertVerifyMode::MustVerifyClientCert(verifier) => {
-                verifier.roots = RootCertStore::empty();
-                true
-            }
-        }
+        self.update_roots(|roots| {
+            *roots = RootCertStore::empty();
+            ((), true)
+        })
+        .is_some()
+    }
+
+    /// Serve anonymous clients connecting to `sni`, whatever mode is
+    /// used for other server names.
+    ///
+    /// This is a mutating operation managed by interior mutability (mutex).
+    pub fn serve_anonymous_clients_for_sni(&self, sni: &str) {
+        self.update(|state| {
+            state.sni_modes.insert(
+                sni.to_ascii_lowercase(),
+                ClientCertVerifyMode::AllowAnyClient,
+            );
+            ((), true)
+        })
+    }
+
+    /// Serve only clients connecting to `sni` which present a certificate
+    /// issued by one of `roots`, whatever mode is used for other server
+    /// names.
+    ///
+    /// This is a mutating operation managed by interior mutability (mutex).
+    pub fn serve_only_authenticated_clients_for_sni(&self, sni: &str, roots: RootCertStore) {
+        self.update(|state| {
+            state.sni_modes.insert(
+                sni.to_ascii_lowercase(),
+                ClientCertVerifyMode::must_verify(roots),
+            );
+            ((), true)
+        })
+    }
+
+    /// Stop overriding the mode for clients connecting to `sni`, so they
+    /// are served like those connecting to any other server name.
+    /// Returns true if the mode was overridden.
+    ///
+    /// This is possibly a mutating operation managed by interior mutability
+    /// (mutex).
+    pub fn remove_sni_mode(&self, sni: &str) -> bool {
+        self.update(|state| {
+            let removed = state
+                .sni_modes
+                .remove(&sni.to_ascii_lowercase())
+                .is_some();
+            (removed, removed)
+        })
+    }
+
+    /// Reject client certificates whose DER encoding has the SHA-256
+    /// `fingerprint`, even if they chain to a trusted root.  Returns true
+    /// if they weren't already rejected.
+    ///
+    /// Such certificates are rejected with `Error::CertificateRevoked`.
+    ///
+    /// Caveat: cached handshakes using a denied client certificate may be
+    /// left intact.
+    ///
+    /// This is possibly a mutating operation managed by interior mutability
+    /// (mutex).
+    pub fn deny_client_cert_fingerprint(&self, fingerprint: [u8; 32]) -> bool {
+        self.update(|state| {
+            let added = state
+                .denied_fingerprints
+                .insert(fingerprint);
+            (added, added)
+        })
+    }
+
+    /// Reject client certificates with the serial number `serial` issued
+    /// by `issuer`, even if they chain to a trusted root.  Returns true if
+    /// they weren't already rejected.
+    ///
+    /// `issuer` and `serial` are encoded as in [`x509::CertificateInfo`]:
+    /// the DER encoding of the issuer's `Name`, and the big-endian contents
+    /// of the serial number's `INTEGER`.
+    ///
+    /// Such certificates are rejected with `Error::CertificateRevoked`.
+    ///
+    /// Caveat: cached handshakes using a denied client certificate may be
+    /// left intact.
+    ///
+    /// This is possibly a mutating operation managed by interior mutability
+    /// (mutex).
+    pub fn deny_client_cert_serial(&self, issuer: &[u8], serial: &[u8]) -> bool {
+        self.update(|state| {
+            let added = state
+                .denied_serials
+                .insert((issuer.to_vec(), serial.to_vec()));
+            (added, added)
+        })
+    }
+
+    /// Stop rejecting the client certificates passed to
+    /// `deny_client_cert_fingerprint` and `deny_client_cert_serial`.
+    /// Returns true if any were rejected.
+    ///
+    /// This is possibly a mutating operation managed by interior mutability
+    /// (mutex).
+    pub fn clear_denied_client_certs(&self) -> bool {
+        self.update(|state| {
+            let any = !state.denied_fingerprints.is_empty() || !state.denied_serials.is_empty();
+            state.denied_fingerprints.clear();
+            state.denied_serials.clear();
+            (any, any)
+        })
+    }
+
+    /// Encode the current modes, root stores and denied client
+    /// certificates, for restoring later with `import`.
+    ///
+    /// The encoding is stable: it can be imported by later versions of
+    /// rustls.
+    pub fn export(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.snapshot().encode(&mut bytes);
+        bytes
+    }
+
+    /// Replace the modes, root stores and denied client certificates with
+    /// those encoded by `export`.
+    ///
+    /// Fails with `Error::General` and changes nothing if `bytes` is
+    /// malformed.
+    ///
+    /// This is a mutating operation managed by interior mutability (mutex).
+    pub fn import(&self, bytes: &[u8]) -> Result<(), Error> {
+        let mut r = Reader::init(bytes);
+        let imported = match ClientVerifierState::read(&mut r) {
+            Some(state) if !r.any_left() => state,
+            _ => return Err(Error::General("malformed client verifier state".into())),
+        };
+
+        self.update(|state| {
+            *state = imported;
+            ((), true)
+        });
+        Ok(())
     }
 }
 
 impl ClientCertVerifier for SafeDefaultClientVerifier {
     fn offer_client_auth(&self) -> bool {
-        match self.mode.read().deref() {
+        // The server name isn't known here, so offer client auth if any
+        // server name needs it.
+        let must_verify = |mode: &ClientCertVerifyMode| match mode {
+            ClientCertVerifyMode::AllowAnyClient => false,
+            ClientCertVerifyMode::MustVerifyClientCert(_) => true,
+        };
+        let state = self.snapshot();
+        must_verify(&state.mode)
+            || state
+                .sni_modes
+                .values()
+                .any(must_verify)
+    }
+
+    fn offer_client_auth_for_sni(&self, sni: Option<&webpki::DnsName>) -> bool {
+        match self.snapshot().mode_for(sni) {
             ClientCertVerifyMode::AllowAnyClient => false,
             ClientCertVerifyMode::MustVerifyClientCert(_) => true,
         }
     }
 
-    fn client_auth_mandatory(&self, _sni: Option<&webpki::DnsName>) -> Option<bool> {
-        Some(self.offer_client_auth())
+    fn client_auth_mandatory(&self, sni: Option<&webpki::DnsName>) -> Option<bool> {
+        match self.snapshot().mode_for(sni) {
+            ClientCertVerifyMode::AllowAnyClient => Some(false),
+            ClientCertVerifyMode::MustVerifyClientCert(_) => Some(true),
+        }
     }
 
     fn client_auth_root_subjects(
         &self,
-        _sni: Option<&webpki::DnsName>,
+        sni: Option<&webpki::DnsName>,
     ) -> Option<DistinguishedNames> {
-        match self.mode.read().deref() {
-            ClientCertVerifyMode::AllowAnyClient => unimplemented!(),
+        match self.snapshot().mode_for(sni) {
+            ClientCertVerifyMode::AllowAnyClient => Some(DistinguishedNames::new()),
             ClientCertVerifyMode::MustVerifyClientCert(strict_verifier) => {
                 Some(strict_verifier.roots.subjects())
             }
@@ -654,8 +3689,14 @@ impl ClientCertVerifier for SafeDefaultClientVerifier {
         sni: Option<&webpki::DnsName>,
         now: SystemTime,
     ) -> Result<ClientCertVerified, Error> {
-        match self.mode.read().deref() {
-            ClientCertVerifyMode::AllowAnyClient => unimplemented!(),
+        let state = self.snapshot();
+        match state.mode_for(sni) {
+            ClientCertVerifyMode::AllowAnyClient => Err(Error::General(
+                "client certificate presented to anonymous server name".into(),
+            )),
+            ClientCertVerifyMode::MustVerifyClientCert(_) if state.is_denied(end_entity) => {
+                Err(Error::CertificateRevoked)
+            }
             ClientCertVerifyMode::MustVerifyClientCert(strict_verifier) => {
                 strict_verifier.verify_client_cert(end_entity, intermediates, sni, now)
             }
@@ -701,6 +3742,22 @@ fn convert_scheme(scheme: SignatureScheme) -> Result<SignatureAlgorithms, Error>
     }
 }
 
+/// Whether `scheme` is one of `algorithms`.
+pub(crate) fn scheme_in_algorithms(
+    scheme: SignatureScheme,
+    algorithms: SignatureAlgorithms,
+) -> bool {
+    convert_scheme(scheme)
+        .map(|algs| {
+            algs.iter().any(|alg| {
+                algorithms
+                    .iter()
+                    .any(|allowed| std::ptr::eq(*alg, *allowed))
+            })
+        })
+        .unwrap_or(false)
+}
+
 fn verify_sig_using_any_alg(
     cert: &webpki::EndEntityCert,
     algs: SignatureAlgorithms,
@@ -719,7 +3776,7 @@ fn verify_sig_using_any_alg(
     Err(webpki::Error::UnsupportedSignatureAlgorithmForPublicKey)
 }
 
-fn verify_signed_struct(
+pub(crate) fn verify_signed_struct(
     message: &[u8],
     cert: &Certificate,
     dss: &DigitallySignedStruct,
@@ -733,6 +3790,37 @@ fn verify_signed_struct(
         .map(|_| HandshakeSignatureValid::assertion())
 }
 
+/// Verify a TLS1.2 signature `dss` allegedly made by the key in `cert`,
+/// just as rustls does when verifying a `ServerKeyExchange` message.
+///
+/// For a `ServerKeyExchange`, `message` is the concatenation of the
+/// `ClientHello.random`, the `ServerHello.random`, and the encoded
+/// key exchange parameters, as described in section 7.4.3 of RFC5246.
+///
+/// As in TLS1.2, ECDSA signature schemes are not bound to a specific
+/// curve.  `cert` is not otherwise validated.
+pub fn verify_tls12_signature_detached(
+    message: &[u8],
+    cert: &Certificate,
+    dss: &DigitallySignedStruct,
+) -> Result<HandshakeSignatureValid, Error> {
+    verify_signed_struct(message, cert, dss)
+}
+
+/// Verify that `dss` is a signature over `challenge` made by the private
+/// key corresponding to the public key in `cert`, for proof-of-possession
+/// checks in enrollment or attestation protocols.
+///
+/// This is exactly [`verify_tls12_signature_detached`], with `challenge`
+/// as the message.
+pub fn verify_possession(
+    cert: &Certificate,
+    challenge: &[u8],
+    dss: &DigitallySignedStruct,
+) -> Result<HandshakeSignatureValid, Error> {
+    verify_tls12_signature_detached(challenge, cert, dss)
+}
+
 fn convert_alg_tls13(
     scheme: SignatureScheme,
 ) -> Result<&'static webpki::SignatureAlgorithm, Error> {
@@ -762,6 +3850,52 @@ pub fn construct_tls13_server_verify_message(handshake_hash: &Digest) -> Vec<u8>
     construct_tls13_verify_message(handshake_hash, b"TLS 1.3, server CertificateVerify\x00")
 }
 
+/// Like `construct_tls13_client_verify_message`, but first checks that
+/// `handshake_hash` is as long as an output of `hash`, which should be
+/// the hash function of the negotiated cipher suite.
+pub fn construct_tls13_client_verify_message_checked(
+    hash: HashAlgorithm,
+    handshake_hash: &Digest,
+) -> Result<Vec<u8>, Error> {
+    check_handshake_hash_len(hash, handshake_hash)?;
+    Ok(construct_tls13_client_verify_message(handshake_hash))
+}
+
+/// Like `construct_tls13_server_verify_message`, but first checks that
+/// `handshake_hash` is as long as an output of `hash`, which should be
+/// the hash function of the negotiated cipher suite.
+pub fn construct_tls13_server_verify_message_checked(
+    hash: HashAlgorithm,
+    handshake_hash: &Digest,
+) -> Result<Vec<u8>, Error> {
+    check_handshake_hash_len(hash, handshake_hash)?;
+    Ok(construct_tls13_server_verify_message(handshake_hash))
+}
+
+fn check_handshake_hash_len(hash: HashAlgorithm, handshake_hash: &Digest) -> Result<(), Error> {
+    let expected = match hash {
+        HashAlgorithm::SHA256 => 32,
+        HashAlgorithm::SHA384 => 48,
+        HashAlgorithm::SHA512 => 64,
+        _ => {
+            return Err(Error::General(format!(
+                "{:?} is not a TLS1.3 transcript hash",
+                hash
+            )))
+        }
+    };
+
+    let actual = handshake_hash.as_ref().len();
+    if actual != expected {
+        return Err(Error::General(format!(
+            "{:?} transcript hash should be {} bytes, not {}",
+            hash, expected, actual
+        )));
+    }
+
+    Ok(())
+}
+
 fn construct_tls13_verify_message(
     handshake_hash: &Digest,
     context_string_with_0: &[u8],
@@ -773,7 +3907,7 @@ fn construct_tls13_verify_message(
     msg
 }
 
-fn verify_tls13(
+pub(crate) fn verify_tls13(
     msg: &[u8],
     cert: &Certificate,
     dss: &DigitallySignedStruct,
@@ -788,48 +3922,89 @@ fn verify_tls13(
         .map(|_| HandshakeSignatureValid::assertion())
 }
 
-fn unix_time_millis(now: SystemTime) -> Result<u64, Error> {
+pub(crate) fn unix_time_secs(now: SystemTime) -> Result<u64, Error> {
     now.duration_since(std::time::UNIX_EPOCH)
         .map(|dur| dur.as_secs())
         .map_err(|_| Error::FailedToGetCurrentTime)
-        .and_then(|secs| {
-            secs.checked_mul(1000)
-                .ok_or(Error::FailedToGetCurrentTime)
-        })
+}
+
+fn unix_time_millis(now: SystemTime) -> Result<u64, Error> {
+    unix_time_secs(now).and_then(|secs| {
+        secs.checked_mul(1000)
+            .ok_or(Error::FailedToGetCurrentTime)
+    })
 }
 
 fn verify_scts(
-    cert: &Certificate,
+    sources: &SctSources,
+    end_entity: &Certificate,
+    intermediates: &[Certificate],
+    roots: &RootCertStore,
     now: SystemTime,
-    scts: &mut dyn Iterator<Item = &[u8]>,
     logs: &[&sct::Log],
-) -> Result<(), Error> {
+    policy: &CtPolicy,
+) -> Result<Vec<usize>, Error> {
     if logs.is_empty() {
-        return Ok(());
+        return Ok(Vec::new());
     }
 
+    let enforced = policy.enforced_for(end_entity)?;
     let now = unix_time_millis(now)?;
     let mut last_sct_error = None;
-    for sct in scts {
-        #[cfg_attr(not(feature = "logging"), allow(unused_variables))]
-        match sct::verify_sct(&cert.0, sct, now, logs) {
+    let mut operators = Vec::new();
+    let mut embedded = false;
+    let mut valid_logs = Vec::new();
+    for (i, (source, sct)) in sources.distinct().enumerate() {
+        if i == policy.max_scts {
+            warn!(
+                "CT policy not met within the first {} SCTs",
+                policy.max_scts
+            );
+            return Err(Error::TooManyScts);
+        }
+
+        match ct::verify_sct(source, end_entity, intermediates, roots, sct, now, logs) {
             Ok(index) => {
+                let log = logs[index];
                 debug!(
-                    "Valid SCT signed by {} on {}",
-                    logs[index].operated_by, logs[index].description
+                    "Valid {:?} SCT signed by {} on {}",
+                    source, log.operated_by, log.description
                 );
-                return Ok(());
+                if !valid_logs.contains(&index) {
+                    valid_logs.push(index);
+                }
+                if !enforced {
+                    return Ok(valid_logs);
+                }
+
+                if !operators.contains(&log.operated_by) {
+                    operators.push(log.operated_by);
+                }
+                embedded |= source == SctSource::Embedded;
+                if operators.len() >= policy.min_operators && (embedded || !policy.require_embedded)
+                {
+                    return Ok(valid_logs);
+                }
             }
             Err(e) => {
                 if e.should_be_fatal() {
                     return Err(Error::InvalidSct(e));
                 }
-                debug!("SCT ignored because {:?}", e);
+                debug!("{:?} SCT ignored because {:?}", source, e);
                 last_sct_error = Some(e);
             }
         }
     }
 
+    if enforced {
+        warn!(
+            "CT policy not met: valid SCTs from {} operators, embedded: {}",
+            operators.len(),
+            embedded
+        );
+        return Err(Error::CtPolicyNotSatisfied);
+    }
+
     /* If we were supplied with some logs, and some SCTs,
      * but couldn't verify any of them, fail the handshake. */
     if let Some(last_sct_error) = last_sct_error {
@@ -837,5 +4012,42 @@ fn verify_scts(
         return Err(Error::InvalidSct(last_sct_error));
     }
 
-    Ok(())
+    Ok(valid_logs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ring::digest;
+
+    #[test]
+    fn checked_verify_message_accepts_sha256() {
+        let hash = digest::digest(&digest::SHA256, b"transcript");
+        let msg = construct_tls13_server_verify_message_checked(HashAlgorithm::SHA256, &hash);
+        assert_eq!(msg, Ok(construct_tls13_server_verify_message(&hash)));
+        assert_eq!(msg.unwrap().len(), 64 + 34 + 32);
+    }
+
+    #[test]
+    fn checked_verify_message_accepts_sha384() {
+        let hash = digest::digest(&digest::SHA384, b"transcript");
+        let msg = construct_tls13_client_verify_message_checked(HashAlgorithm::SHA384, &hash);
+        assert_eq!(msg, Ok(construct_tls13_client_verify_message(&hash)));
+        assert_eq!(msg.unwrap().len(), 64 + 34 + 48);
+    }
+
+    #[test]
+    fn checked_verify_message_rejects_mismatched_hash() {
+        let sha256 = digest::digest(&digest::SHA256, b"transcript");
+        let sha384 = digest::digest(&digest::SHA384, b"transcript");
+        assert!(
+            construct_tls13_server_verify_message_checked(HashAlgorithm::SHA384, &sha256).is_err()
+        );
+        assert!(
+            construct_tls13_client_verify_message_checked(HashAlgorithm::SHA256, &sha384).is_err()
+        );
+        assert!(
+            construct_tls13_client_verify_message_checked(HashAlgorithm::SHA1, &sha256).is_err()
+        );
+    }
 }
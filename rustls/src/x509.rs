@@ -1,6 +1,349 @@
-// Additional x509/asn1 functions to those provided in webpki/ring.
+//! Access to the fields of a certificate most often needed for logging
+//! and policy decisions, without a separate X.509 parser.
+//!
+//! Nothing here validates a certificate: use a verifier for that.
+//!
+//! ```no_run
+//! # fn show(cert: &rustls::Certificate) -> Result<(), rustls::Error> {
+//! let info = rustls::x509::CertificateInfo::parse(cert)?;
+//! println!("{} (serial {:x?})", info.subject_string().unwrap_or_default(), info.serial);
+//! # Ok(())
+//! # }
+//! ```
 
+// Also additional x509/asn1 functions to those provided in webpki/ring,
+// for use within the crate.
+
+use crate::error::{Error, WebPkiOp};
+use crate::key::Certificate;
+use crate::msgs::enums::{SignatureAlgorithm, SignatureScheme};
 use ring::io::der;
+use ring::signature;
+use std::fmt::Write;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The parsed subject, issuer, serial number, validity period and subject
+/// alternative names of a certificate.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct CertificateInfo {
+    /// The DER encoding of the certificate's subject `Name`.
+    pub subject: Vec<u8>,
+    /// The DER encoding of the certificate's issuer `Name`.
+    pub issuer: Vec<u8>,
+    /// The certificate's serial number, as the big-endian contents of
+    /// its `INTEGER`.  This may have a leading zero byte.
+    pub serial: Vec<u8>,
+    /// The start of the certificate's validity period.
+    pub not_before: SystemTime,
+    /// The end of the certificate's validity period.
+    pub not_after: SystemTime,
+    /// The certificate's subject alternative names, in the order they
+    /// are encoded.  Empty if it has no subject alternative name
+    /// extension.
+    pub subject_alt_names: Vec<SubjectAltName>,
+}
+
+impl CertificateInfo {
+    /// Parse the DER-encoded `cert`.
+    ///
+    /// Fails with `Error::WebPkiError(BadDer, ParseEndEntity)` if `cert`
+    /// is not a well-formed certificate.
+    pub fn parse(cert: &Certificate) -> Result<Self, Error> {
+        let bad_der = || Error::WebPkiError(webpki::Error::BadDer, WebPkiOp::ParseEndEntity);
+        let fields = CertFields::parse(&cert.0).ok_or_else(bad_der)?;
+
+        let subject_alt_names = match fields.extension(oid::SUBJECT_ALT_NAME) {
+            Some(_) => fields
+                .subject_alt_names()
+                .ok_or_else(bad_der)?
+                .into_iter()
+                .map(|(tag, value)| SubjectAltName::from_general_name(tag, value))
+                .collect(),
+            None => Vec::new(),
+        };
+
+        let mut subject = fields.subject.to_vec();
+        wrap_in_sequence(&mut subject);
+        let mut issuer = fields.issuer.to_vec();
+        wrap_in_sequence(&mut issuer);
+
+        Ok(Self {
+            subject,
+            issuer,
+            serial: fields.serial.to_vec(),
+            not_before: UNIX_EPOCH + Duration::from_secs(fields.not_before),
+            not_after: UNIX_EPOCH + Duration::from_secs(fields.not_after),
+            subject_alt_names,
+        })
+    }
+
+    /// The subject as comma-separated attributes in the order they're
+    /// encoded, like `CN=example.com, O=Example`.  Attributes without a
+    /// well-known short name are given by OID.
+    ///
+    /// Returns `None` if the subject is malformed.
+    pub fn subject_string(&self) -> Option<String> {
+        name_to_string(DerReader::new(&self.subject).read(der::Tag::Sequence as u8)?)
+    }
+
+    /// The issuer, in the same form as [`CertificateInfo::subject_string`].
+    pub fn issuer_string(&self) -> Option<String> {
+        name_to_string(DerReader::new(&self.issuer).read(der::Tag::Sequence as u8)?)
+    }
+
+    /// The DNS name subject alternative names.
+    pub fn dns_names(&self) -> impl Iterator<Item = &str> {
+        self.subject_alt_names
+            .iter()
+            .filter_map(|name| match name {
+                SubjectAltName::DnsName(name) => Some(name.as_str()),
+                _ => None,
+            })
+    }
+}
+
+/// One subject alternative name of a certificate.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubjectAltName {
+    /// A `dNSName`.
+    DnsName(String),
+    /// An `rfc822Name`: an email address.
+    Email(String),
+    /// A `uniformResourceIdentifier`.
+    Uri(String),
+    /// An `iPAddress`.
+    IpAddress(IpAddr),
+    /// Any other `GeneralName`, or one of the above which is malformed:
+    /// its tag and the contents of its encoding.
+    Other(u8, Vec<u8>),
+}
+
+impl SubjectAltName {
+    fn from_general_name(tag: u8, value: &[u8]) -> Self {
+        let text = || std::str::from_utf8(value).map(str::to_string);
+
+        let parsed = match tag {
+            GENERAL_NAME_DNS_NAME => text().map(Self::DnsName).ok(),
+            GENERAL_NAME_RFC822_NAME => text().map(Self::Email).ok(),
+            GENERAL_NAME_URI => text().map(Self::Uri).ok(),
+            GENERAL_NAME_IP_ADDRESS => ip_address(value).map(Self::IpAddress),
+            _ => None,
+        };
+
+        parsed.unwrap_or_else(|| Self::Other(tag, value.to_vec()))
+    }
+}
+
+/// A parsed X.501 `Name`, such as a certificate's subject or one of the
+/// `DistinguishedNames` a server sends to say which issuers it accepts
+/// client certificates from.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct DistinguishedNameInfo {
+    /// The relative distinguished names, in the order they're encoded:
+    /// the least specific, such as the country, first.  Each has one or
+    /// more attributes.
+    pub rdns: Vec<Vec<NameAttribute>>,
+}
+
+impl DistinguishedNameInfo {
+    /// Parse the DER-encoded `Name` in `der`, as in a
+    /// `DistinguishedName` or [`CertificateInfo::subject`].  The
+    /// contents of the `Name`, without the outer SEQUENCE, are also
+    /// accepted.
+    ///
+    /// Returns `None` if `der` is malformed.
+    pub fn parse(der: &[u8]) -> Option<Self> {
+        let mut reader = DerReader::new(der);
+        let contents = match reader.read(SEQUENCE) {
+            Some(contents) if reader.at_end() => contents,
+            _ => der,
+        };
+
+        let mut rdns = DerReader::new(contents);
+        let mut parsed = Vec::new();
+        while !rdns.at_end() {
+            let mut rdn = Vec::new();
+            for attr in DerReader::new(rdns.read(SET)?).read_all(SEQUENCE)? {
+                let mut attr = DerReader::new(attr);
+                let oid = attr.read(der::Tag::OID as u8)?;
+                let (tag, value, _) = attr.read_element()?;
+                if !attr.at_end() {
+                    return None;
+                }
+                rdn.push(NameAttribute {
+                    oid: oid.to_vec(),
+                    tag,
+                    value: value.to_vec(),
+                });
+            }
+            if rdn.is_empty() {
+                return None;
+            }
+            parsed.push(rdn);
+        }
+
+        Some(Self { rdns: parsed })
+    }
+
+    /// All the attributes, in the order they're encoded.
+    pub fn attributes(&self) -> impl Iterator<Item = &NameAttribute> {
+        self.rdns.iter().flatten()
+    }
+
+    /// The value of the most specific common name (`CN`) attribute, if
+    /// there is one and it's a string.
+    pub fn common_name(&self) -> Option<String> {
+        self.attributes()
+            .filter(|attr| attr.oid == oid::COMMON_NAME)
+            .last()?
+            .value_string()
+    }
+
+    /// The name as an RFC 4514 string, like `CN=example.com,O=Example`:
+    /// the most specific relative distinguished name first.
+    pub fn to_rfc4514_string(&self) -> String {
+        let mut out = String::new();
+        for (i, rdn) in self.rdns.iter().rev().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            for (j, attr) in rdn.iter().enumerate() {
+                if j > 0 {
+                    out.push('+');
+                }
+                attr.write_rfc4514(&mut out);
+            }
+        }
+        out
+    }
+}
+
+impl std::fmt::Display for DistinguishedNameInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.to_rfc4514_string())
+    }
+}
+
+/// One attribute of a relative distinguished name, like `CN=example.com`.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct NameAttribute {
+    /// The contents of the attribute type's `OBJECT IDENTIFIER`.
+    pub oid: Vec<u8>,
+    /// The tag of the value's encoding, such as `0x0c` for a
+    /// `UTF8String`.
+    pub tag: u8,
+    /// The contents of the value's encoding.
+    pub value: Vec<u8>,
+}
+
+impl NameAttribute {
+    /// The short name RFC 4514 gives the attribute type, like `CN`, or
+    /// `None` if it has none.
+    pub fn short_name(&self) -> Option<&'static str> {
+        Some(match self.oid.as_slice() {
+            oid::COMMON_NAME => "CN",
+            [0x55, 0x04, 0x06] => "C",
+            [0x55, 0x04, 0x07] => "L",
+            [0x55, 0x04, 0x08] => "ST",
+            [0x55, 0x04, 0x09] => "STREET",
+            [0x55, 0x04, 0x0a] => "O",
+            [0x55, 0x04, 0x0b] => "OU",
+            [0x09, 0x92, 0x26, 0x89, 0x93, 0xf2, 0x2c, 0x64, 0x01, 0x19] => "DC",
+            [0x09, 0x92, 0x26, 0x89, 0x93, 0xf2, 0x2c, 0x64, 0x01, 0x01] => "UID",
+            _ => return None,
+        })
+    }
+
+    /// The attribute type: its short name, or else its OID in dotted
+    /// decimal.
+    pub fn type_string(&self) -> String {
+        match self.short_name() {
+            Some(name) => name.to_string(),
+            None => oid_to_string(&self.oid).unwrap_or_else(|| hex("", &self.oid)),
+        }
+    }
+
+    /// The value, if it's a `UTF8String`, `PrintableString`,
+    /// `IA5String` or `BMPString`.
+    pub fn value_string(&self) -> Option<String> {
+        match self.tag {
+            UTF8_STRING | PRINTABLE_STRING | IA5_STRING => {
+                String::from_utf8(self.value.clone()).ok()
+            }
+            BMP_STRING if self.value.len() % 2 == 0 => String::from_utf16(
+                &self
+                    .value
+                    .chunks(2)
+                    .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                    .collect::<Vec<_>>(),
+            )
+            .ok(),
+            _ => None,
+        }
+    }
+
+    /// Append `type=value` to `out`, escaping the value as RFC 4514
+    /// requires.  Values which aren't strings, and those of types
+    /// without a short name, are given as `#` and the hex of their
+    /// encoding.
+    fn write_rfc4514(&self, out: &mut String) {
+        out.push_str(&self.type_string());
+        out.push('=');
+
+        let value = match (self.short_name(), self.value_string()) {
+            (Some(_), Some(value)) => value,
+            _ => {
+                let mut encoding = self.value.clone();
+                wrap_in_tag(self.tag, &mut encoding);
+                out.push_str(&hex("#", &encoding));
+                return;
+            }
+        };
+
+        let last = value.chars().count().saturating_sub(1);
+        for (i, c) in value.chars().enumerate() {
+            match c {
+                '"' | '+' | ',' | ';' | '<' | '>' | '\\' => {
+                    out.push('\\');
+                    out.push(c);
+                }
+                '#' if i == 0 => out.push_str("\\#"),
+                ' ' if i == 0 || i == last => out.push_str("\\ "),
+                '\0' => out.push_str("\\00"),
+                _ => out.push(c),
+            }
+        }
+    }
+}
+
+fn hex(prefix: &str, bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .fold(String::from(prefix), |mut hex, b| {
+            write!(hex, "{:02x}", b).unwrap();
+            hex
+        })
+}
+
+fn ip_address(value: &[u8]) -> Option<IpAddr> {
+    match value.len() {
+        4 => {
+            let mut addr = [0u8; 4];
+            addr.copy_from_slice(value);
+            Some(Ipv4Addr::from(addr).into())
+        }
+        16 => {
+            let mut addr = [0u8; 16];
+            addr.copy_from_slice(value);
+            Some(Ipv6Addr::from(addr).into())
+        }
+        _ => None,
+    }
+}
 
 fn wrap_in_asn1_len(bytes: &mut Vec<u8>) {
     let len = bytes.len();
@@ -20,9 +363,608 @@ fn wrap_in_asn1_len(bytes: &mut Vec<u8>) {
 }
 
 /// Prepend stuff to `bytes` to put it in a DER SEQUENCE.
-pub fn wrap_in_sequence(bytes: &mut Vec<u8>) {
+pub(crate) fn wrap_in_sequence(bytes: &mut Vec<u8>) {
+    wrap_in_tag(der::Tag::Sequence as u8, bytes);
+}
+
+/// Prepend stuff to `bytes` to make it the contents of a DER element
+/// with the given `tag`.
+pub(crate) fn wrap_in_tag(tag: u8, bytes: &mut Vec<u8>) {
     wrap_in_asn1_len(bytes);
-    bytes.insert(0, der::Tag::Sequence as u8);
+    bytes.insert(0, tag);
+}
+
+pub(crate) const ENUMERATED: u8 = 0x0a;
+pub(crate) const SEQUENCE: u8 = der::Tag::Sequence as u8;
+const UTF8_STRING: u8 = 0x0c;
+const PRINTABLE_STRING: u8 = 0x13;
+const IA5_STRING: u8 = 0x16;
+const BMP_STRING: u8 = 0x1e;
+pub(crate) const CONTEXT_SPECIFIC_0: u8 = der::CONTEXT_SPECIFIC;
+pub(crate) const CONTEXT_SPECIFIC_2: u8 = der::CONTEXT_SPECIFIC | 2;
+
+/// `GeneralName` tags, as used in subject alternative names.
+pub(crate) const GENERAL_NAME_RFC822_NAME: u8 = der::CONTEXT_SPECIFIC | 1;
+pub(crate) const GENERAL_NAME_DNS_NAME: u8 = der::CONTEXT_SPECIFIC | 2;
+pub(crate) const GENERAL_NAME_URI: u8 = der::CONTEXT_SPECIFIC | 6;
+pub(crate) const GENERAL_NAME_IP_ADDRESS: u8 = der::CONTEXT_SPECIFIC | 7;
+
+/// A minimal DER reader, for the handful of structures webpki doesn't
+/// expose to us.  Every method returns `None` on malformed input.
+pub(crate) struct DerReader<'a> {
+    input: &'a [u8],
+}
+
+impl<'a> DerReader<'a> {
+    pub fn new(input: &'a [u8]) -> Self {
+        Self { input }
+    }
+
+    pub fn at_end(&self) -> bool {
+        self.input.is_empty()
+    }
+
+    pub fn peek(&self, tag: u8) -> bool {
+        self.input.first() == Some(&tag)
+    }
+
+    /// Read one element, returning its tag, its contents and its
+    /// entire encoding.
+    pub fn read_element(&mut self) -> Option<(u8, &'a [u8], &'a [u8])> {
+        let tag = *self.input.first()?;
+        let first = *self.input.get(1)?;
+
+        let (len, header) = match first {
+            n if n & 0x80 == 0 => (usize::from(n), 2),
+            0x81..=0x84 => {
+                let count = usize::from(first & 0x7f);
+                let bytes = self.input.get(2..2 + count)?;
+                let len = bytes
+                    .iter()
+                    .fold(0usize, |acc, b| (acc << 8) | usize::from(*b));
+                (len, 2 + count)
+            }
+            _ => return None,
+        };
+
+        let end = header.checked_add(len)?;
+        let whole = self.input.get(..end)?;
+        self.input = &self.input[end..];
+        Some((tag, &whole[header..], whole))
+    }
+
+    /// Read an element which must have the given tag, returning its
+    /// contents.
+    pub fn read(&mut self, tag: u8) -> Option<&'a [u8]> {
+        match self.read_element()? {
+            (actual, contents, _) if actual == tag => Some(contents),
+            _ => None,
+        }
+    }
+
+    /// Read an element with the given tag if it is next, otherwise
+    /// consume nothing.
+    pub fn read_optional(&mut self, tag: u8) -> Option<&'a [u8]> {
+        if self.peek(tag) {
+            self.read(tag)
+        } else {
+            None
+        }
+    }
+
+    /// Read all elements with the given tag until the end of input.
+    pub fn read_all(mut self, tag: u8) -> Option<Vec<&'a [u8]>> {
+        let mut items = Vec::new();
+        while !self.at_end() {
+            items.push(self.read(tag)?);
+        }
+        Some(items)
+    }
+}
+
+/// Convert a DER `UTCTime` or `GeneralizedTime` to seconds since the
+/// UNIX epoch.  Only the `Z`-suffixed forms required by RFC5280 are
+/// accepted.
+///
+/// Times before the epoch, which `UTCTime` can express back to 1950,
+/// are returned as zero.  That still compares correctly with any
+/// current time.
+pub(crate) fn parse_time(tag: u8, value: &[u8]) -> Option<u64> {
+    let digits = |range: std::ops::Range<usize>| -> Option<u64> {
+        value
+            .get(range)?
+            .iter()
+            .try_fold(0u64, |acc, b| match b {
+                b'0'..=b'9' => Some(acc * 10 + u64::from(b - b'0')),
+                _ => None,
+            })
+    };
+
+    let (year, rest) = if tag == der::Tag::UTCTime as u8 && value.len() == 13 {
+        let yy = digits(0..2)?;
+        (if yy >= 50 { 1900 + yy } else { 2000 + yy }, 2)
+    } else if tag == der::Tag::GeneralizedTime as u8 && value.len() == 15 {
+        (digits(0..4)?, 4)
+    } else {
+        return None;
+    };
+
+    if value.last() != Some(&b'Z') {
+        return None;
+    }
+
+    let month = digits(rest..rest + 2)?;
+    let day = digits(rest + 2..rest + 4)?;
+    let hours = digits(rest + 4..rest + 6)?;
+    let minutes = digits(rest + 6..rest + 8)?;
+    let seconds = digits(rest + 8..rest + 10)?;
+
+    if !(1..=12).contains(&month)
+        || !(1..=days_in_month(year, month)).contains(&day)
+        || hours > 23
+        || minutes > 59
+        || seconds > 59
+    {
+        return None;
+    }
+
+    // Days since the epoch, per Howard Hinnant's days_from_civil.
+    let (y, m) = if month <= 2 {
+        (year.checked_sub(1)?, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = y / 400;
+    let yoe = y - era * 400;
+    let doy = (153 * m + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe;
+
+    Some((days * 86400 + hours * 3600 + minutes * 60 + seconds).saturating_sub(719_468 * 86400))
+}
+
+fn days_in_month(year: u64, month: u64) -> u64 {
+    match month {
+        4 | 6 | 9 | 11 => 30,
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        _ => 31,
+    }
+}
+
+/// Read a `BIT STRING` with no unused bits from `reader`.
+pub(crate) fn read_bit_string<'a>(reader: &mut DerReader<'a>) -> Option<&'a [u8]> {
+    match reader
+        .read(der::Tag::BitString as u8)?
+        .split_first()?
+    {
+        (0, bits) => Some(bits),
+        _ => None,
+    }
+}
+
+/// Read a `Time` (either encoding) from `reader`.
+fn read_time(reader: &mut DerReader) -> Option<u64> {
+    let (tag, value, _) = reader.read_element()?;
+    parse_time(tag, value)
+}
+
+/// The fields of a certificate that rustls inspects itself, rather than
+/// delegating to webpki.  This does no validation beyond parsing: it must
+/// only be used on certificates that webpki has accepted, or to produce
+/// diagnostics.
+#[derive(Clone, Copy)]
+pub(crate) struct CertFields<'a> {
+    /// The entire encoding of the `TBSCertificate`: this is what is signed.
+    pub tbs: &'a [u8],
+    /// The serial number's `INTEGER` contents.
+    pub serial: &'a [u8],
+    /// The contents of the issuer `Name`.
+    pub issuer: &'a [u8],
+    /// `notBefore`, in seconds since the UNIX epoch.
+    pub not_before: u64,
+    /// `notAfter`, in seconds since the UNIX epoch.
+    pub not_after: u64,
+    /// The contents of the subject `Name`.
+    pub subject: &'a [u8],
+    /// The contents of the `SubjectPublicKeyInfo`.  This matches the
+    /// `spki` field of `webpki::TrustAnchor`.
+    pub spki: &'a [u8],
+    /// The contents of the `Extensions` sequence, if present.
+    pub extensions: Option<&'a [u8]>,
+    /// The contents of the certificate's signature `AlgorithmIdentifier`.
+    pub signature_alg: &'a [u8],
+    /// The certificate's signature.
+    pub signature: &'a [u8],
+}
+
+impl<'a> CertFields<'a> {
+    pub fn parse(cert_der: &'a [u8]) -> Option<Self> {
+        let mut outer = DerReader::new(cert_der);
+        let mut cert = DerReader::new(outer.read(der::Tag::Sequence as u8)?);
+        let (tag, tbs_contents, tbs) = cert.read_element()?;
+        if tag != der::Tag::Sequence as u8 {
+            return None;
+        }
+
+        let signature_alg = cert.read(der::Tag::Sequence as u8)?;
+        let signature = read_bit_string(&mut cert)?;
+
+        let mut tbs_reader = DerReader::new(tbs_contents);
+        tbs_reader.read_optional(der::Tag::ContextSpecificConstructed0 as u8);
+        let serial = tbs_reader.read(der::Tag::Integer as u8)?;
+        tbs_reader.read(der::Tag::Sequence as u8)?;
+        let issuer = tbs_reader.read(der::Tag::Sequence as u8)?;
+
+        let mut validity = DerReader::new(tbs_reader.read(der::Tag::Sequence as u8)?);
+        let not_before = read_time(&mut validity)?;
+        let not_after = read_time(&mut validity)?;
+
+        let subject = tbs_reader.read(der::Tag::Sequence as u8)?;
+        let spki = tbs_reader.read(der::Tag::Sequence as u8)?;
+        tbs_reader.read_optional(der::CONTEXT_SPECIFIC | 1);
+        tbs_reader.read_optional(der::CONTEXT_SPECIFIC | 2);
+        let extensions = match tbs_reader.read_optional(der::Tag::ContextSpecificConstructed3 as u8)
+        {
+            Some(explicit) => Some(DerReader::new(explicit).read(der::Tag::Sequence as u8)?),
+            None => None,
+        };
+
+        Some(Self {
+            tbs,
+            serial,
+            issuer,
+            not_before,
+            not_after,
+            subject,
+            spki,
+            extensions,
+            signature_alg,
+            signature,
+        })
+    }
+
+    /// Find the value of the extension identified by `oid` (the DER
+    /// contents of the `OBJECT IDENTIFIER`).
+    pub fn extension(&self, oid: &[u8]) -> Option<&'a [u8]> {
+        let mut exts = DerReader::new(self.extensions?);
+        while !exts.at_end() {
+            let mut ext = DerReader::new(exts.read(der::Tag::Sequence as u8)?);
+            let ext_oid = ext.read(der::Tag::OID as u8)?;
+            ext.read_optional(der::Tag::Boolean as u8); // critical
+            let value = ext.read(der::Tag::OctetString as u8)?;
+            if ext_oid == oid {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    /// Returns the extended key usage OIDs, or `None` if the certificate
+    /// has no extended key usage extension.
+    pub fn extended_key_usage(&self) -> Option<Vec<&'a [u8]>> {
+        let mut value = DerReader::new(self.extension(oid::EXTENDED_KEY_USAGE)?);
+        DerReader::new(value.read(der::Tag::Sequence as u8)?).read_all(der::Tag::OID as u8)
+    }
+
+    /// Returns the policy identifiers from the certificate policies
+    /// extension, or `None` if the certificate has no such extension.
+    pub fn certificate_policies(&self) -> Option<Vec<&'a [u8]>> {
+        let mut value = DerReader::new(self.extension(oid::CERTIFICATE_POLICIES)?);
+        let mut policies = DerReader::new(value.read(der::Tag::Sequence as u8)?);
+        let mut out = Vec::new();
+        while !policies.at_end() {
+            let info = policies.read(der::Tag::Sequence as u8)?;
+            out.push(DerReader::new(info).read(der::Tag::OID as u8)?);
+        }
+        Some(out)
+    }
+
+    /// Returns the TLS features (as `INTEGER` contents) the certificate
+    /// requires, or `None` if it has no TLS feature extension.
+    pub fn tls_features(&self) -> Option<Vec<&'a [u8]>> {
+        let mut value = DerReader::new(self.extension(oid::TLS_FEATURE)?);
+        DerReader::new(value.read(der::Tag::Sequence as u8)?).read_all(der::Tag::Integer as u8)
+    }
+
+    /// Returns the subject key identifier, or `None` if the certificate
+    /// doesn't have one.
+    pub fn subject_key_identifier(&self) -> Option<&'a [u8]> {
+        DerReader::new(self.extension(oid::SUBJECT_KEY_IDENTIFIER)?)
+            .read(der::Tag::OctetString as u8)
+    }
+
+    /// Returns the `keyIdentifier` from the authority key identifier, or
+    /// `None` if the certificate doesn't have one.
+    pub fn authority_key_identifier(&self) -> Option<&'a [u8]> {
+        let mut value = DerReader::new(self.extension(oid::AUTHORITY_KEY_IDENTIFIER)?);
+        DerReader::new(value.read(der::Tag::Sequence as u8)?).read_optional(CONTEXT_SPECIFIC_0)
+    }
+
+    /// Returns the `caIssuers` URIs from the authority information
+    /// access extension, or `None` if the certificate has no such
+    /// extension.
+    pub fn ca_issuers(&self) -> Option<Vec<&'a [u8]>> {
+        let mut value = DerReader::new(self.extension(oid::AUTHORITY_INFO_ACCESS)?);
+        let mut descriptions = DerReader::new(value.read(der::Tag::Sequence as u8)?);
+        let mut out = Vec::new();
+        while !descriptions.at_end() {
+            let mut description = DerReader::new(descriptions.read(der::Tag::Sequence as u8)?);
+            let method = description.read(der::Tag::OID as u8)?;
+            let (tag, location, _) = description.read_element()?;
+            if method == oid::AD_CA_ISSUERS && tag == GENERAL_NAME_URI {
+                out.push(location);
+            }
+        }
+        Some(out)
+    }
+
+    /// Returns the subject alternative names, as pairs of `GeneralName`
+    /// tag and contents, or `None` if the certificate has no subject
+    /// alternative name extension.
+    pub fn subject_alt_names(&self) -> Option<Vec<(u8, &'a [u8])>> {
+        let mut value = DerReader::new(self.extension(oid::SUBJECT_ALT_NAME)?);
+        let mut names = DerReader::new(value.read(der::Tag::Sequence as u8)?);
+        let mut out = Vec::new();
+        while !names.at_end() {
+            let (tag, contents, _) = names.read_element()?;
+            out.push((tag, contents));
+        }
+        Some(out)
+    }
+}
+
+/// Split the contents of a `SubjectPublicKeyInfo` into the contents of
+/// its algorithm identifier and its public key bits.
+pub(crate) fn split_spki(spki: &[u8]) -> Option<(&[u8], &[u8])> {
+    let mut reader = DerReader::new(spki);
+    let alg = reader.read(der::Tag::Sequence as u8)?;
+    let key = read_bit_string(&mut reader)?;
+    if reader.at_end() {
+        Some((alg, key))
+    } else {
+        None
+    }
+}
+
+/// The length in bits of the modulus of the RSA public key in `spki`
+/// (the contents of a `SubjectPublicKeyInfo`).
+pub(crate) fn rsa_modulus_bits(spki: &[u8]) -> Option<usize> {
+    let (_, key) = split_spki(spki)?;
+    let mut rsa_key = DerReader::new(DerReader::new(key).read(der::Tag::Sequence as u8)?);
+    let modulus = rsa_key.read(der::Tag::Integer as u8)?;
+
+    match modulus.iter().position(|&b| b != 0) {
+        Some(first) => Some((modulus.len() - first) * 8 - modulus[first].leading_zeros() as usize),
+        None => Some(0),
+    }
+}
+
+/// The named curve of the elliptic curve public key in `spki` (the
+/// contents of a `SubjectPublicKeyInfo`), as the DER contents of its
+/// `OBJECT IDENTIFIER`.
+pub(crate) fn ec_curve(spki: &[u8]) -> Option<&[u8]> {
+    let (alg_id, _) = split_spki(spki)?;
+    let mut alg_id = DerReader::new(alg_id);
+    if alg_id.read(der::Tag::OID as u8)? != oid::EC_PUBLIC_KEY {
+        return None;
+    }
+    alg_id.read(der::Tag::OID as u8)
+}
+
+/// The size in bits of the public key in `spki` (the contents of a
+/// `SubjectPublicKeyInfo`): the modulus length for RSA keys, and the
+/// field size for elliptic curve keys.  Returns `None` for keys not
+/// recognised.
+pub(crate) fn key_bits(spki: &[u8]) -> Option<usize> {
+    match key_algorithm(spki)? {
+        SignatureAlgorithm::RSA => rsa_modulus_bits(spki),
+        SignatureAlgorithm::ECDSA => match ec_curve(spki)? {
+            oid::SECP256R1 => Some(256),
+            oid::SECP384R1 => Some(384),
+            _ => None,
+        },
+        SignatureAlgorithm::ED25519 => Some(255),
+        SignatureAlgorithm::ED448 => Some(448),
+        _ => None,
+    }
+}
+
+/// Object identifiers, as the DER contents of the `OBJECT IDENTIFIER`.
+pub(crate) mod oid {
+    pub const COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+    pub const SUBJECT_KEY_IDENTIFIER: &[u8] = &[0x55, 0x1d, 0x0e];
+    pub const SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+    pub const AUTHORITY_KEY_IDENTIFIER: &[u8] = &[0x55, 0x1d, 0x23];
+    pub const CERTIFICATE_POLICIES: &[u8] = &[0x55, 0x1d, 0x20];
+    pub const EXTENDED_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x25];
+    pub const ANY_EXTENDED_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x25, 0x00];
+    pub const AUTHORITY_INFO_ACCESS: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x01, 0x01];
+    pub const AD_CA_ISSUERS: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x02];
+    pub const TLS_FEATURE: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x01, 0x18];
+    pub const KP_SERVER_AUTH: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x01];
+    pub const KP_OCSP_SIGNING: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x09];
+    pub const PKIX_OCSP_BASIC: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01];
+    pub const PKIX_OCSP_NOCHECK: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x05];
+    pub const CT_PRECERT_SCTS: &[u8] =
+        &[0x2b, 0x06, 0x01, 0x04, 0x01, 0xd6, 0x79, 0x02, 0x04, 0x02];
+    pub const CT_OCSP_SCTS: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0xd6, 0x79, 0x02, 0x04, 0x05];
+
+    pub const RSA_ENCRYPTION: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+    pub const DSA: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x38, 0x04, 0x01];
+    pub const EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+    pub const ED448: &[u8] = &[0x2b, 0x65, 0x71];
+
+    pub const SECP256R1: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+    pub const SECP384R1: &[u8] = &[0x2b, 0x81, 0x04, 0x00, 0x22];
+
+    pub const SHA1_WITH_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x05];
+    pub const SHA256_WITH_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b];
+    pub const SHA384_WITH_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0c];
+    pub const SHA512_WITH_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0d];
+    pub const RSASSA_PSS: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0a];
+    pub const ECDSA_WITH_SHA1: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x01];
+    pub const ECDSA_WITH_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+    pub const ECDSA_WITH_SHA384: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x03];
+    pub const ECDSA_WITH_SHA512: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x04];
+    pub const ED25519: &[u8] = &[0x2b, 0x65, 0x70];
+
+    pub const SHA1: &[u8] = &[0x2b, 0x0e, 0x03, 0x02, 0x1a];
+    pub const SHA256: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+    pub const SHA384: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x02];
+    pub const SHA512: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x03];
+}
+
+/// Return the family of the public key in `spki` (the contents of a
+/// `SubjectPublicKeyInfo`), or `None` if it is not recognised.
+pub(crate) fn key_algorithm(spki: &[u8]) -> Option<SignatureAlgorithm> {
+    let (alg_id, _) = split_spki(spki)?;
+    let alg_oid = DerReader::new(alg_id).read(der::Tag::OID as u8)?;
+
+    Some(match alg_oid {
+        oid::RSA_ENCRYPTION => SignatureAlgorithm::RSA,
+        oid::DSA => SignatureAlgorithm::DSA,
+        oid::EC_PUBLIC_KEY => SignatureAlgorithm::ECDSA,
+        oid::ED25519 => SignatureAlgorithm::ED25519,
+        oid::ED448 => SignatureAlgorithm::ED448,
+        _ => return None,
+    })
+}
+
+/// Map the contents of a signature `AlgorithmIdentifier` to the
+/// TLS `SignatureScheme` with the same meaning.
+///
+/// For ECDSA the curve is not fixed by the algorithm identifier,
+/// exactly as for TLS1.2 signature schemes.
+pub(crate) fn signature_scheme_for_algorithm(alg_id: &[u8]) -> Option<SignatureScheme> {
+    let mut reader = DerReader::new(alg_id);
+    let alg_oid = reader.read(der::Tag::OID as u8)?;
+
+    Some(match alg_oid {
+        oid::SHA1_WITH_RSA => SignatureScheme::RSA_PKCS1_SHA1,
+        oid::SHA256_WITH_RSA => SignatureScheme::RSA_PKCS1_SHA256,
+        oid::SHA384_WITH_RSA => SignatureScheme::RSA_PKCS1_SHA384,
+        oid::SHA512_WITH_RSA => SignatureScheme::RSA_PKCS1_SHA512,
+        oid::ECDSA_WITH_SHA1 => SignatureScheme::ECDSA_SHA1_Legacy,
+        oid::ECDSA_WITH_SHA256 => SignatureScheme::ECDSA_NISTP256_SHA256,
+        oid::ECDSA_WITH_SHA384 => SignatureScheme::ECDSA_NISTP384_SHA384,
+        oid::ECDSA_WITH_SHA512 => SignatureScheme::ECDSA_NISTP521_SHA512,
+        oid::ED25519 => SignatureScheme::ED25519,
+        oid::RSASSA_PSS => rsa_pss_scheme(reader.read(der::Tag::Sequence as u8)?)?,
+        _ => return None,
+    })
+}
+
+/// Map the contents of `RSASSA-PSS-params` to the TLS `SignatureScheme`
+/// with the same hash.  The mask generation function and salt length
+/// are left to signature verification.
+fn rsa_pss_scheme(params: &[u8]) -> Option<SignatureScheme> {
+    // The hash defaults to SHA-1 when absent, which TLS has no PSS scheme for.
+    let hash_alg = DerReader::new(params).read(der::Tag::ContextSpecificConstructed0 as u8)?;
+    let hash_alg = DerReader::new(hash_alg).read(der::Tag::Sequence as u8)?;
+    let hash_oid = DerReader::new(hash_alg).read(der::Tag::OID as u8)?;
+
+    Some(match hash_oid {
+        oid::SHA256 => SignatureScheme::RSA_PSS_SHA256,
+        oid::SHA384 => SignatureScheme::RSA_PSS_SHA384,
+        oid::SHA512 => SignatureScheme::RSA_PSS_SHA512,
+        _ => return None,
+    })
+}
+
+/// Verify `signature` over `message` using the public key in `spki` (the
+/// contents of a `SubjectPublicKeyInfo`).
+pub(crate) fn verify_signed_data(
+    spki: &[u8],
+    scheme: SignatureScheme,
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), webpki::Error> {
+    let algs: &[&dyn signature::VerificationAlgorithm] = match scheme {
+        SignatureScheme::RSA_PKCS1_SHA256 => &[&signature::RSA_PKCS1_2048_8192_SHA256],
+        SignatureScheme::RSA_PKCS1_SHA384 => &[&signature::RSA_PKCS1_2048_8192_SHA384],
+        SignatureScheme::RSA_PKCS1_SHA512 => &[&signature::RSA_PKCS1_2048_8192_SHA512],
+        SignatureScheme::RSA_PSS_SHA256 => &[&signature::RSA_PSS_2048_8192_SHA256],
+        SignatureScheme::RSA_PSS_SHA384 => &[&signature::RSA_PSS_2048_8192_SHA384],
+        SignatureScheme::RSA_PSS_SHA512 => &[&signature::RSA_PSS_2048_8192_SHA512],
+        SignatureScheme::ECDSA_NISTP256_SHA256 => &[
+            &signature::ECDSA_P256_SHA256_ASN1,
+            &signature::ECDSA_P384_SHA256_ASN1,
+        ],
+        SignatureScheme::ECDSA_NISTP384_SHA384 => &[
+            &signature::ECDSA_P256_SHA384_ASN1,
+            &signature::ECDSA_P384_SHA384_ASN1,
+        ],
+        SignatureScheme::ED25519 => &[&signature::ED25519],
+        _ => return Err(webpki::Error::UnsupportedSignatureAlgorithm),
+    };
+
+    let (_, key) = split_spki(spki).ok_or(webpki::Error::BadDer)?;
+
+    if algs.iter().any(|alg| {
+        signature::UnparsedPublicKey::new(*alg, key)
+            .verify(message, signature)
+            .is_ok()
+    }) {
+        Ok(())
+    } else {
+        Err(webpki::Error::InvalidSignatureForPublicKey)
+    }
+}
+
+/// The DER tag for `SET`, which ring doesn't define.
+const SET: u8 = 0x31;
+
+/// Render the contents of a `Name` as comma-separated attributes, in
+/// the order they're encoded.  Attributes without a well-known short
+/// name are given by OID.
+pub(crate) fn name_to_string(name: &[u8]) -> Option<String> {
+    let parts: Vec<String> = DistinguishedNameInfo::parse(name)?
+        .attributes()
+        .map(|attr| {
+            let value = attr
+                .value_string()
+                .unwrap_or_else(|| hex("#", &attr.value));
+            format!("{}={}", attr.type_string(), value)
+        })
+        .collect();
+    Some(parts.join(", "))
+}
+
+/// Render the contents of an `OBJECT IDENTIFIER` in dotted decimal.
+fn oid_to_string(oid: &[u8]) -> Option<String> {
+    let mut arcs = Vec::new();
+    let mut arc = 0u64;
+    for b in oid {
+        arc = arc.checked_mul(128)? | u64::from(b & 0x7f);
+        if b & 0x80 == 0 {
+            arcs.push(arc);
+            arc = 0;
+        }
+    }
+
+    let first = *arcs.first()?;
+    let (a, b) = match first {
+        0..=39 => (0, first),
+        40..=79 => (1, first - 40),
+        _ => (2, first - 80),
+    };
+
+    let mut out = format!("{}.{}", a, b);
+    for arc in &arcs[1..] {
+        write!(out, ".{}", arc).unwrap();
+    }
+    Some(out)
+}
+
+#[cfg(feature = "dangerous_configuration")]
+pub(crate) fn general_name_to_string(tag: u8, value: &[u8]) -> Option<String> {
+    match tag {
+        GENERAL_NAME_DNS_NAME => Some(format!("DNS:{}", std::str::from_utf8(value).ok()?)),
+        GENERAL_NAME_RFC822_NAME => Some(format!("email:{}", std::str::from_utf8(value).ok()?)),
+        GENERAL_NAME_IP_ADDRESS => Some(format!("IP:{}", ip_address(value)?)),
+        _ => None,
+    }
 }
 
 #[test]
@@ -91,3 +1033,100 @@ fn test_ludicrous() {
     );
     assert_eq!(val.len(), 0x1000000 + 6);
 }
+
+#[cfg(test)]
+fn name_attribute(oid: &[u8], tag: u8, value: &[u8]) -> Vec<u8> {
+    let mut oid = oid.to_vec();
+    wrap_in_tag(der::Tag::OID as u8, &mut oid);
+    let mut value = value.to_vec();
+    wrap_in_tag(tag, &mut value);
+    let mut attr = [oid, value].concat();
+    wrap_in_sequence(&mut attr);
+    attr
+}
+
+#[cfg(test)]
+fn name_rdn(attrs: &[Vec<u8>]) -> Vec<u8> {
+    let mut rdn = attrs.concat();
+    wrap_in_tag(SET, &mut rdn);
+    rdn
+}
+
+#[test]
+fn test_distinguished_name_formatting() {
+    let mut name = [
+        name_rdn(&[name_attribute(&[0x55, 0x04, 0x06], PRINTABLE_STRING, b"GB")]),
+        name_rdn(&[name_attribute(
+            &[0x55, 0x04, 0x0a],
+            UTF8_STRING,
+            b"Acme, Inc",
+        )]),
+        name_rdn(&[
+            name_attribute(&[0x55, 0x04, 0x0b], UTF8_STRING, b"Eng"),
+            name_attribute(&[0x55, 0x04, 0x05], PRINTABLE_STRING, b"42"),
+        ]),
+        name_rdn(&[name_attribute(oid::COMMON_NAME, UTF8_STRING, b" #x ")]),
+    ]
+    .concat();
+
+    let parsed = DistinguishedNameInfo::parse(&name).unwrap();
+    assert_eq!(parsed.rdns.len(), 4);
+    assert_eq!(parsed.attributes().count(), 5);
+    assert_eq!(parsed.common_name(), Some(" #x ".to_string()));
+    assert_eq!(
+        parsed.to_rfc4514_string(),
+        "CN=\\ #x\\ ,OU=Eng+2.5.4.5=#13023432,O=Acme\\, Inc,C=GB"
+    );
+    assert_eq!(
+        name_to_string(&name).unwrap(),
+        "C=GB, O=Acme, Inc, OU=Eng, 2.5.4.5=42, CN= #x "
+    );
+
+    wrap_in_sequence(&mut name);
+    assert_eq!(DistinguishedNameInfo::parse(&name), Some(parsed));
+    assert_eq!(DistinguishedNameInfo::parse(&name[..name.len() - 1]), None);
+    assert_eq!(
+        DistinguishedNameInfo::parse(&[]),
+        Some(DistinguishedNameInfo { rdns: vec![] })
+    );
+}
+
+#[test]
+fn test_distinguished_name_values() {
+    let name = [
+        name_rdn(&[name_attribute(oid::COMMON_NAME, BMP_STRING, &[0x00, 0xe9])]),
+        name_rdn(&[name_attribute(oid::COMMON_NAME, 0x04, &[0x01, 0x02])]),
+    ]
+    .concat();
+
+    let parsed = DistinguishedNameInfo::parse(&name).unwrap();
+    let attrs: Vec<&NameAttribute> = parsed.attributes().collect();
+    assert_eq!(attrs[0].value_string(), Some("\u{e9}".to_string()));
+    assert_eq!(attrs[1].value_string(), None);
+    assert_eq!(attrs[1].short_name(), Some("CN"));
+    assert_eq!(parsed.common_name(), None);
+    assert_eq!(parsed.to_string(), "CN=#04020102,CN=\u{e9}");
+}
+
+#[test]
+fn test_parse_time() {
+    let utc = |value: &str| parse_time(der::Tag::UTCTime as u8, value.as_bytes());
+    let generalized = |value: &str| parse_time(der::Tag::GeneralizedTime as u8, value.as_bytes());
+
+    assert_eq!(utc("700101000000Z"), Some(0));
+    assert_eq!(utc("491231235959Z"), Some(2_524_607_999));
+    assert_eq!(generalized("20240229120000Z"), Some(1_709_208_000));
+
+    // UTCTime covers 1950 to 1969, before the epoch.
+    assert_eq!(utc("500101000000Z"), Some(0));
+    assert_eq!(utc("691231235959Z"), Some(0));
+
+    assert_eq!(utc("240231000000Z"), None);
+    assert_eq!(utc("230229000000Z"), None);
+    assert_eq!(generalized("21000229000000Z"), None);
+    assert_eq!(utc("240431000000Z"), None);
+    assert_eq!(utc("241301000000Z"), None);
+    assert_eq!(utc("240100000000Z"), None);
+    assert_eq!(utc("240101240000Z"), None);
+    assert_eq!(utc("240101000000"), None);
+}
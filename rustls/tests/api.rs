@@ -7,28 +7,62 @@ use std::mem;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime};
 
 use rustls;
 
-use rustls::internal::msgs::{codec::Codec, persist::ClientSessionValue};
+use rustls::internal::msgs::{codec::Codec, enums::AlertDescription, persist::ClientSessionValue};
 #[cfg(feature = "quic")]
 use rustls::quic::{self, ClientQuicExt, QuicExt, ServerQuicExt};
 use rustls::sign;
+use rustls::AllowAnyAuthenticatedClient;
 use rustls::ClientHello;
 use rustls::ConfigBuilder;
 use rustls::Connection;
+use rustls::DynamicRootClientVerifier;
 use rustls::Error;
+use rustls::FipsModeVerifier;
 use rustls::KeyLog;
+use rustls::NameConstraints;
+use rustls::OwnedTrustAnchor;
+use rustls::RevocationFilterCascade;
+use rustls::RootCertStore;
 use rustls::SafeDefaultClientVerifier;
+use rustls::SignatureAlgorithm;
+use rustls::SniClientCertVerifier;
+use rustls::ValidityPolicy;
 use rustls::WebPkiOp;
+use rustls::WebPkiVerifier;
+use rustls::{CertStatus, CtPolicy, OcspPolicy, OcspUnknownStatus, RevocationStatus};
+use rustls::{ChainExpiry, ChainPosition, PathCertificate};
 use rustls::{CipherSuite, ProtocolVersion, SignatureScheme};
-use rustls::{ClientConfig, ClientConnection, ResolvesClientCert};
+use rustls::{ClientCertRequest, ClientConfig, ClientConnection, ConnectionOptions};
+use rustls::{ClientHelloProfile, EarlyDataStatus, ExtensionType, NamedGroup};
+use rustls::{ConfigBuilderWithVersions, ConfigProfile, MaxFragmentLength};
+use rustls::{ResolvesClientCert, ServerName};
 use rustls::{ResolvesServerCert, ServerConfig, ServerConnection};
+use rustls::{SpiffeId, SpiffeVerifier};
 use rustls::{Stream, StreamOwned};
 use rustls::{SupportedCipherSuite, ALL_CIPHERSUITES};
 
 #[cfg(feature = "dangerous_configuration")]
 use rustls::ClientCertVerified;
+#[cfg(feature = "dangerous_configuration")]
+use rustls::NoCertificateVerification;
+#[cfg(feature = "dangerous_configuration")]
+use rustls::RawPublicKeyVerifier;
+#[cfg(feature = "dangerous_configuration")]
+use rustls::SelfSignedPinnedVerifier;
+#[cfg(feature = "dangerous_configuration")]
+use rustls::{
+    AndVerifier, AuditingVerifier, BudgetedVerifier, OrVerifier, PendingVerification,
+    ReportOnlyVerifier, ServerCertVerified, ServerCertVerifier, ShadowVerifier,
+};
+#[cfg(feature = "dangerous_configuration")]
+use rustls::{DaneVerifier, TlsaMatchingType, TlsaRecord, TlsaSelector, TlsaUsage};
+#[cfg(feature = "dangerous_configuration")]
+use rustls::{TofuMemoryStore, TofuStore, TofuVerifier};
 
 use webpki;
 
@@ -86,6 +120,40 @@ fn alpn() {
     alpn_test(vec![b"PROTO".to_vec()], vec![b"proto".to_vec()], None);
 }
 
+#[test]
+fn alpn_can_be_overridden_per_connection() {
+    let verifier = WebPkiVerifier::new(verifier_roots(), &[]);
+    let mut client_config = make_client_config_with_verifier(Arc::new(verifier));
+    let mut server_config = make_verifier_server_config(&[]);
+
+    client_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    let server_config = Arc::new(server_config);
+
+    for client_config in AllClientVersions::new(client_config) {
+        let client_config = Arc::new(client_config);
+        let check = |client_protos: Option<Vec<Vec<u8>>>, agreed: Option<&[u8]>| {
+            let mut options = ConnectionOptions::new();
+            options.alpn_protocols = client_protos;
+            let mut client = ClientConnection::new_with_options(
+                Arc::clone(&client_config),
+                server_name("localhost"),
+                options,
+            )
+            .unwrap();
+            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+            do_handshake(&mut client, &mut server);
+            assert_eq!(client.alpn_protocol(), agreed);
+            assert_eq!(server.alpn_protocol(), agreed);
+        };
+
+        check(None, Some(b"http/1.1"));
+        check(Some(vec![b"h2".to_vec()]), Some(b"h2"));
+        check(Some(vec![]), None);
+    }
+}
+
 fn version_test(
     client_versions: &[&'static rustls::SupportedProtocolVersion],
     server_versions: &[&'static rustls::SupportedProtocolVersion],
@@ -207,6 +275,108 @@ fn config_builder_for_client_rejects_incompatible_cipher_suites() {
     );
 }
 
+#[test]
+fn config_builders_can_start_from_the_config_type() {
+    let client_config = ClientConfig::builder()
+        .with_cipher_suites(&[&rustls::cipher_suite::TLS13_CHACHA20_POLY1305_SHA256])
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .for_client()
+        .unwrap()
+        .with_root_certificates(verifier_roots(), &[])
+        .with_no_client_auth();
+    let server_config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(verifier_chain(), verifier_key("end"))
+        .unwrap();
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(
+        client
+            .negotiated_cipher_suite()
+            .map(|suite| suite.suite),
+        Some(CipherSuite::TLS13_CHACHA20_POLY1305_SHA256)
+    );
+
+    assert_eq!(
+        ServerConfig::builder()
+            .with_cipher_suites(&[&rustls::cipher_suite::TLS13_AES_256_GCM_SHA384])
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(&[&rustls::version::TLS12])
+            .for_server()
+            .err(),
+        Some(Error::General("no usable cipher suites configured".into()))
+    );
+}
+
+#[test]
+fn config_profiles_select_mozilla_configurations() {
+    assert_eq!(
+        ConfigProfile::Modern.protocol_versions(),
+        &[&rustls::version::TLS13]
+    );
+    assert!(ConfigProfile::Modern
+        .cipher_suites()
+        .iter()
+        .all(|suite| suite.usable_for_version(ProtocolVersion::TLSv1_3)));
+    assert_eq!(
+        ConfigProfile::Intermediate.cipher_suites()[3].suite,
+        CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256
+    );
+    assert_eq!(
+        ConfigProfile::Old.cipher_suites(),
+        ConfigProfile::Intermediate.cipher_suites()
+    );
+
+    let server_config = |profile| {
+        ServerConfig::builder()
+            .with_profile(profile)
+            .with_no_client_auth()
+            .with_single_cert(verifier_chain(), verifier_key("end"))
+            .unwrap()
+    };
+    let client_config = |builder: ConfigBuilderWithVersions| {
+        builder
+            .for_client()
+            .unwrap()
+            .with_root_certificates(verifier_roots(), &[])
+            .with_no_client_auth()
+    };
+
+    let (mut client, mut server) = make_pair_for_configs(
+        client_config(ConfigBuilder::with_profile(ConfigProfile::Intermediate)),
+        server_config(ConfigProfile::Modern),
+    );
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.protocol_version(), Some(ProtocolVersion::TLSv1_3));
+    assert_eq!(
+        client
+            .negotiated_cipher_suite()
+            .map(|suite| suite.suite),
+        Some(CipherSuite::TLS13_AES_128_GCM_SHA256)
+    );
+
+    let tls12_only = || {
+        ConfigBuilder::with_safe_default_cipher_suites()
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(&[&rustls::version::TLS12])
+    };
+    let (mut client, mut server) = make_pair_for_configs(
+        client_config(tls12_only()),
+        server_config(ConfigProfile::Intermediate),
+    );
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.protocol_version(), Some(ProtocolVersion::TLSv1_2));
+
+    let (mut client, mut server) = make_pair_for_configs(
+        client_config(tls12_only()),
+        server_config(ConfigProfile::Modern),
+    );
+    assert!(do_handshake_until_error(&mut client, &mut server).is_err());
+}
+
 #[test]
 fn config_builder_for_server_rejects_empty_kx_groups() {
     assert_eq!(
@@ -261,6 +431,88 @@ fn buffered_client_data_sent() {
     }
 }
 
+#[test]
+fn client_false_starts_when_enabled() {
+    let server_config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(verifier_chain(), verifier_key("end"))
+        .unwrap();
+
+    for &false_start in &[false, true] {
+        let mut client_config = ClientConfig::builder()
+            .with_safe_default_cipher_suites()
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(&[&rustls::version::TLS12])
+            .for_client()
+            .unwrap()
+            .with_root_certificates(verifier_roots(), &[])
+            .with_no_client_auth();
+        client_config.enable_false_start = false_start;
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config.clone());
+
+        assert_eq!(5, client.writer().write(b"hello").unwrap());
+
+        // ClientHello, then the server's first flight.
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+        transfer(&mut server, &mut client);
+        client.process_new_packets().unwrap();
+
+        // The client's second flight, with the data if false starting.
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+        assert!(client.is_handshaking());
+        if false_start {
+            check_read(&mut server.reader(), b"hello");
+        } else {
+            assert!(matches!(server.reader().read(&mut [0u8; 1]),
+                             Err(err) if err.kind() == io::ErrorKind::WouldBlock));
+        }
+
+        transfer(&mut server, &mut client);
+        client.process_new_packets().unwrap();
+        assert!(!client.is_handshaking());
+        if !false_start {
+            transfer(&mut client, &mut server);
+            server.process_new_packets().unwrap();
+            check_read(&mut server.reader(), b"hello");
+        }
+    }
+}
+
+#[test]
+fn client_does_not_false_start_with_psk_suite() {
+    let psk = external_psk(b"sensor-17", b"provisioned secret");
+    let (mut client_config, server_config) = tls12_external_psk_configs(
+        &rustls::cipher_suite::TLS_ECDHE_PSK_WITH_AES_128_GCM_SHA256,
+        Some(psk.clone()),
+        vec![psk],
+    );
+    client_config.enable_false_start = true;
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+
+    assert_eq!(5, client.writer().write(b"hello").unwrap());
+
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    assert!(client.is_handshaking());
+    assert!(matches!(server.reader().read(&mut [0u8; 1]),
+                     Err(err) if err.kind() == io::ErrorKind::WouldBlock));
+
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    assert!(!client.is_handshaking());
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    check_read(&mut server.reader(), b"hello");
+}
+
 #[test]
 fn buffered_server_data_sent() {
     let server_config = Arc::new(make_server_config(KeyType::RSA));
@@ -475,6 +727,7 @@ struct ServerCheckCertResolve {
     expected_sni: Option<String>,
     expected_sigalgs: Option<Vec<SignatureScheme>>,
     expected_alpn: Option<Vec<Vec<u8>>>,
+    expected_certificate_authorities: Option<Vec<Vec<u8>>>,
 }
 
 impl ResolvesServerCert for ServerCheckCertResolve {
@@ -512,6 +765,17 @@ impl ResolvesServerCert for ServerCheckCertResolve {
             }
         }
 
+        if let Some(expected_cas) = &self.expected_certificate_authorities {
+            let cas = client_hello
+                .certificate_authorities()
+                .expect("certificate_authorities unexpectedly absent");
+            assert_eq!(cas.len(), expected_cas.len());
+
+            for (got, wanted) in cas.iter().zip(expected_cas.iter()) {
+                assert_eq!(got, &wanted.as_slice());
+            }
+        }
+
         None
     }
 }
@@ -528,7 +792,8 @@ fn server_cert_resolve_with_sni() {
         });
 
         let mut client =
-            ClientConnection::new(Arc::new(client_config), dns_name("the-value-from-sni")).unwrap();
+            ClientConnection::new(Arc::new(client_config), server_name("the-value-from-sni"))
+                .unwrap();
         let mut server = ServerConnection::new(Arc::new(server_config)).unwrap();
 
         let err = do_handshake_until_error(&mut client, &mut server);
@@ -549,7 +814,7 @@ fn server_cert_resolve_with_alpn() {
         });
 
         let mut client =
-            ClientConnection::new(Arc::new(client_config), dns_name("sni-value")).unwrap();
+            ClientConnection::new(Arc::new(client_config), server_name("sni-value")).unwrap();
         let mut server = ServerConnection::new(Arc::new(server_config)).unwrap();
 
         let err = do_handshake_until_error(&mut client, &mut server);
@@ -557,6 +822,108 @@ fn server_cert_resolve_with_alpn() {
     }
 }
 
+#[test]
+fn server_cert_resolve_with_certificate_authorities() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let subjects = get_client_root_store(*kt).subjects();
+        let mut client_config = make_client_config(*kt);
+        client_config.certificate_authorities = subjects.clone();
+
+        let mut server_config = make_server_config(*kt);
+        server_config.cert_resolver = Arc::new(ServerCheckCertResolve {
+            expected_certificate_authorities: Some(
+                subjects
+                    .into_iter()
+                    .map(|name| name.0)
+                    .collect(),
+            ),
+            ..Default::default()
+        });
+
+        let mut client =
+            ClientConnection::new(Arc::new(client_config), server_name("sni-value")).unwrap();
+        let mut server = ServerConnection::new(Arc::new(server_config)).unwrap();
+
+        let err = do_handshake_until_error(&mut client, &mut server);
+        assert_eq!(err.is_err(), true);
+    }
+}
+
+/// Serves test-ca/verifier/end.cert to clients naming the verifier CA
+/// or no authorities, and fips-ed25519.cert to those naming its
+/// intermediate.
+fn make_server_config_with_authorities_resolver() -> ServerConfig {
+    let mut resolver = rustls::ResolvesServerCertUsingAuthorities::new();
+    for (authority, name) in &[("ca.cert", "end"), ("inter.cert", "fips-ed25519")] {
+        let key = sign::any_supported_type(&verifier_key(name)).unwrap();
+        resolver
+            .add(
+                &verifier_certs(authority)[0],
+                sign::CertifiedKey::new(verifier_chain_for(name), key),
+            )
+            .unwrap();
+    }
+
+    ConfigBuilder::with_safe_defaults()
+        .for_server()
+        .unwrap()
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(resolver))
+}
+
+#[test]
+fn server_picks_certificate_by_client_certificate_authorities() {
+    let mut authorities = RootCertStore::empty();
+    authorities
+        .add(&verifier_certs("inter.cert")[0])
+        .unwrap();
+    let verifier = WebPkiVerifier::new(verifier_roots(), &[]);
+    let mut client_config = make_client_config_with_verifier(Arc::new(verifier));
+    client_config.certificate_authorities = authorities.subjects();
+    let server_config = make_server_config_with_authorities_resolver();
+
+    let mut client =
+        ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+    let mut server = ServerConnection::new(Arc::new(server_config)).unwrap();
+    do_handshake(&mut client, &mut server);
+
+    assert_eq!(
+        client.peer_certificates(),
+        Some(verifier_chain_for("fips-ed25519").as_slice())
+    );
+}
+
+#[test]
+fn server_falls_back_to_first_certificate_without_certificate_authorities() {
+    let verifier = WebPkiVerifier::new(verifier_roots(), &[]);
+    let client_config = make_client_config_with_verifier(Arc::new(verifier));
+    let server_config = make_server_config_with_authorities_resolver();
+
+    let mut client =
+        ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+    let mut server = ServerConnection::new(Arc::new(server_config)).unwrap();
+    do_handshake(&mut client, &mut server);
+
+    assert_eq!(
+        client.peer_certificates(),
+        Some(verifier_chain().as_slice())
+    );
+}
+
+#[test]
+fn authorities_resolver_rejects_invalid_root() {
+    let mut resolver = rustls::ResolvesServerCertUsingAuthorities::new();
+    let kt = KeyType::ECDSA;
+    let key = sign::any_supported_type(&kt.get_key()).unwrap();
+    let err = resolver
+        .add(
+            &rustls::Certificate(vec![0x30, 0x00]),
+            sign::CertifiedKey::new(kt.get_chain(), key),
+        )
+        .unwrap_err();
+    assert_eq!(err, Error::General("invalid CA certificate".into()));
+}
+
 #[test]
 fn client_trims_terminating_dot() {
     for kt in ALL_KEY_TYPES.iter() {
@@ -569,7 +936,7 @@ fn client_trims_terminating_dot() {
         });
 
         let mut client =
-            ClientConnection::new(Arc::new(client_config), dns_name("some-host.com.")).unwrap();
+            ClientConnection::new(Arc::new(client_config), server_name("some-host.com.")).unwrap();
         let mut server = ServerConnection::new(Arc::new(server_config)).unwrap();
 
         let err = do_handshake_until_error(&mut client, &mut server);
@@ -592,7 +959,8 @@ fn check_sigalgs_reduced_by_ciphersuite(
         ..Default::default()
     });
 
-    let mut client = ClientConnection::new(Arc::new(client_config), dns_name("localhost")).unwrap();
+    let mut client =
+        ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
     let mut server = ServerConnection::new(Arc::new(server_config)).unwrap();
 
     let err = do_handshake_until_error(&mut client, &mut server);
@@ -650,7 +1018,28 @@ fn client_with_sni_disabled_does_not_send_sni() {
 
         for client_config in AllClientVersions::new(client_config) {
             let mut client =
-                ClientConnection::new(Arc::new(client_config), dns_name("value-not-sent")).unwrap();
+                ClientConnection::new(Arc::new(client_config), server_name("value-not-sent"))
+                    .unwrap();
+            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+
+            let err = do_handshake_until_error(&mut client, &mut server);
+            assert_eq!(err.is_err(), true);
+        }
+    }
+}
+
+#[test]
+fn client_does_not_send_sni_for_ip_address() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let client_config = make_client_config(*kt);
+        let mut server_config = make_server_config(*kt);
+        server_config.cert_resolver = Arc::new(ServerCheckNoSNI {});
+        let server_config = Arc::new(server_config);
+
+        for client_config in AllClientVersions::new(client_config) {
+            let mut client =
+                ClientConnection::new(Arc::new(client_config), server_name("198.51.100.1"))
+                    .unwrap();
             let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
 
             let err = do_handshake_until_error(&mut client, &mut server);
@@ -659,6 +1048,46 @@ fn client_with_sni_disabled_does_not_send_sni() {
     }
 }
 
+#[test]
+fn client_connects_to_ip_address() {
+    let server_config = Arc::new(
+        ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(verifier_chain_for("end-ip"), verifier_key("end"))
+            .unwrap(),
+    );
+    let client_config = Arc::new(
+        ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(verifier_roots(), &[])
+            .with_no_client_auth(),
+    );
+
+    assert_eq!(server_name("[2001:db8::1]"), server_name("2001:db8::1"));
+    assert!(ServerName::try_from("[198.51.100.1]").is_err());
+
+    // end-ip.cert is for testserver.com, 198.51.100.1 and 2001:db8::1.
+    for name in &["198.51.100.1", "[2001:db8::1]", "2001:db8::1"] {
+        let mut client =
+            ClientConnection::new(Arc::clone(&client_config), server_name(name)).unwrap();
+        let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+        assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
+        assert_eq!(server.sni_hostname(), None);
+    }
+
+    let mut client =
+        ClientConnection::new(Arc::clone(&client_config), server_name("198.51.100.2")).unwrap();
+    let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Client(Error::WebPkiError(
+            webpki::Error::CertNotValidForName,
+            WebPkiOp::ValidateForIpAddress,
+        )))
+    );
+}
+
 #[test]
 fn client_checks_server_certificate_with_given_name() {
     for kt in ALL_KEY_TYPES.iter() {
@@ -668,7 +1097,7 @@ fn client_checks_server_certificate_with_given_name() {
         for client_config in AllClientVersions::new(client_config) {
             let mut client = ClientConnection::new(
                 Arc::new(client_config),
-                dns_name("not-the-right-hostname.com"),
+                server_name("not-the-right-hostname.com"),
             )
             .unwrap();
             let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
@@ -685,6 +1114,118 @@ fn client_checks_server_certificate_with_given_name() {
     }
 }
 
+fn sni_options(name: &str) -> ConnectionOptions {
+    let mut options = ConnectionOptions::new();
+    options.sni_name = Some(server_name(name));
+    options
+}
+
+#[test]
+fn client_verifies_server_certificate_against_verification_name() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let mut client_config = make_client_config(*kt);
+        client_config.time_provider = within_validity();
+        let server_config = Arc::new(make_server_config(*kt));
+
+        for client_config in AllClientVersions::new(client_config) {
+            let client_config = Arc::new(client_config);
+
+            let mut client = ClientConnection::new_with_options(
+                Arc::clone(&client_config),
+                server_name("testserver.com"),
+                sni_options("front.example.com"),
+            )
+            .unwrap();
+            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+            assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
+            assert_eq!(server.sni_hostname(), Some("front.example.com"));
+
+            let mut client = ClientConnection::new_with_options(
+                Arc::clone(&client_config),
+                server_name("testserver.com"),
+                sni_options("198.51.100.1"),
+            )
+            .unwrap();
+            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+            assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
+            assert_eq!(server.sni_hostname(), None);
+
+            let mut client = ClientConnection::new_with_options(
+                Arc::clone(&client_config),
+                server_name("not-the-right-hostname.com"),
+                sni_options("testserver.com"),
+            )
+            .unwrap();
+            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+            assert_eq!(
+                do_handshake_until_error(&mut client, &mut server),
+                Err(ErrorFromPeer::Client(Error::WebPkiError(
+                    webpki::Error::CertNotValidForName,
+                    WebPkiOp::ValidateForDnsName,
+                )))
+            );
+            assert_eq!(server.sni_hostname(), Some("testserver.com"));
+        }
+    }
+}
+
+#[test]
+fn client_can_omit_sni_or_send_decoy_name() {
+    let server_config = Arc::new(
+        ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(verifier_chain(), verifier_key("end"))
+            .unwrap(),
+    );
+    let client_config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(verifier_roots(), &[])
+        .with_no_client_auth();
+
+    let mut decoy_config = client_config.clone();
+    decoy_config.sni_decoy_name = Some(server_name("decoy.example.com"));
+    let decoy_config = Arc::new(decoy_config);
+
+    let mut client =
+        ClientConnection::new(Arc::clone(&decoy_config), server_name("testserver.com")).unwrap();
+    let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+    assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
+    assert_eq!(server.sni_hostname(), Some("decoy.example.com"));
+
+    // Still verified against the real name.
+    let mut client =
+        ClientConnection::new(Arc::clone(&decoy_config), server_name("other.example.com")).unwrap();
+    let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+    assert!(do_handshake_until_error(&mut client, &mut server).is_err());
+    assert_eq!(server.sni_hostname(), Some("decoy.example.com"));
+
+    // The connection's choices override the config's.
+    let mut client = ClientConnection::new_with_options(
+        Arc::clone(&decoy_config),
+        server_name("testserver.com"),
+        sni_options("front.example.com"),
+    )
+    .unwrap();
+    let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+    assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
+    assert_eq!(server.sni_hostname(), Some("front.example.com"));
+
+    let mut without_sni = ConnectionOptions::new();
+    without_sni.enable_sni = Some(false);
+    for config in &[decoy_config, Arc::new(client_config)] {
+        let mut client = ClientConnection::new_with_options(
+            Arc::clone(config),
+            server_name("testserver.com"),
+            without_sni.clone(),
+        )
+        .unwrap();
+        let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+        assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
+        assert_eq!(server.sni_hostname(), None);
+    }
+}
+
 struct ClientCheckCertResolve {
     query_count: AtomicUsize,
     expect_queries: usize,
@@ -753,9 +1294,77 @@ fn client_cert_resolve() {
     }
 }
 
-#[test]
-fn client_auth_works() {
-    for kt in ALL_KEY_TYPES.iter() {
+struct ClientRecordCertRequest {
+    requests: Mutex<Vec<(ServerName, Vec<String>)>>,
+}
+
+impl ResolvesClientCert for ClientRecordCertRequest {
+    fn resolve(&self, _: &[&[u8]], _: &[SignatureScheme]) -> Option<Arc<sign::CertifiedKey>> {
+        unreachable!("resolve_request is implemented")
+    }
+
+    fn resolve_request(&self, request: &ClientCertRequest) -> Option<Arc<sign::CertifiedKey>> {
+        assert!(!request.signature_schemes().is_empty());
+        assert_eq!(
+            request.acceptable_issuers().len(),
+            request.acceptable_issuer_names().len()
+        );
+        self.requests.lock().unwrap().push((
+            request.server_name().clone(),
+            request
+                .acceptable_issuer_names()
+                .iter()
+                .map(|name| name.to_rfc4514_string())
+                .collect(),
+        ));
+        None
+    }
+
+    fn has_certs(&self) -> bool {
+        true
+    }
+}
+
+#[test]
+fn client_cert_resolver_is_given_request_details() {
+    let resolver = Arc::new(ClientRecordCertRequest {
+        requests: Mutex::new(Vec::new()),
+    });
+    let verifier = WebPkiVerifier::new(verifier_roots(), &[]);
+    let mut client_config = make_client_config_with_verifier(Arc::new(verifier));
+    client_config.client_auth_cert_resolver = resolver.clone();
+
+    let server_config = Arc::new(
+        ServerConfig::builder()
+            .with_safe_defaults()
+            .with_client_cert_verifier(AllowAnyAuthenticatedClient::new(verifier_roots()))
+            .with_single_cert(verifier_chain(), verifier_key("end"))
+            .unwrap(),
+    );
+
+    for client_config in AllClientVersions::new(client_config) {
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+
+        assert_eq!(
+            do_handshake_until_error(&mut client, &mut server),
+            Err(ErrorFromPeer::Server(Error::NoCertificatesPresented))
+        );
+    }
+
+    let expected = (
+        server_name("localhost"),
+        vec!["CN=ponytown verifier CA".to_string()],
+    );
+    assert_eq!(
+        *resolver.requests.lock().unwrap(),
+        vec![expected.clone(), expected]
+    );
+}
+
+#[test]
+fn client_auth_works() {
+    for kt in ALL_KEY_TYPES.iter() {
         let client_config = make_client_config_with_auth(*kt);
         let server_config = Arc::new(make_server_config_with_mandatory_client_auth(*kt));
 
@@ -862,12 +1471,330 @@ fn safe_default_client_auth_works() {
     }
 }
 
+#[test]
+fn safe_default_client_verifier_removes_single_root() {
+    let safe_default_verifier = SafeDefaultClientVerifier::new();
+    let verifier_handle = Arc::clone(&safe_default_verifier);
+    let server_config = ConfigBuilder::with_safe_defaults()
+        .for_server()
+        .unwrap()
+        .with_client_cert_verifier(safe_default_verifier)
+        .with_single_cert(verifier_chain(), verifier_key("end"))
+        .unwrap();
+    let server_config = Arc::new(server_config);
+
+    let mut chain = verifier_chain_for("client");
+    chain.extend(verifier_certs("ca.cert"));
+    for cert in &chain {
+        assert!(
+            verifier_handle
+                .add_trusted_root_ca(cert)
+                .unwrap()
+                .is_ok()
+        );
+    }
+    assert_eq!(verifier_handle.root_cert_store_len(), chain.len());
+
+    // The client's intermediate is trusted directly, and via the root.
+    let inter = &chain[1];
+    assert_eq!(verifier_handle.remove_trusted_root_ca(&inter.0), Some(1));
+    assert_eq!(verifier_handle.remove_trusted_root_ca(&inter.0), Some(0));
+    assert_eq!(verifier_handle.root_cert_store_len(), chain.len() - 1);
+
+    // A fresh client config each time, so the session isn't resumed.
+    let client_config = Arc::new(make_verifier_client_config_with_auth("client"));
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
+
+    // Remove the root by the subject name the verifier reports.
+    let root = &chain[2];
+    let root_subject = verifier_handle
+        .root_cert_store_subjects()
+        .unwrap()
+        .pop()
+        .unwrap();
+    assert_eq!(
+        verifier_handle.remove_trusted_root_ca(&root_subject.0),
+        Some(1)
+    );
+    assert_eq!(verifier_handle.root_cert_store_len(), chain.len() - 2);
+
+    // Again with a fresh client config, so the session isn't resumed.
+    let client_config = Arc::new(make_verifier_client_config_with_auth("client"));
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::WebPkiError(
+            webpki::Error::UnknownIssuer,
+            WebPkiOp::ValidateClientCert,
+        )))
+    );
+
+    // Trust the root again in place of the already-removed intermediate.
+    assert_eq!(
+        verifier_handle.replace_trusted_root_ca(inter, root),
+        Some(Ok(0))
+    );
+    let client_config = Arc::new(make_verifier_client_config_with_auth("client"));
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
+
+    verifier_handle.serve_anonymous_clients();
+    assert_eq!(verifier_handle.remove_trusted_root_ca(&root.0), None);
+    assert_eq!(verifier_handle.replace_trusted_root_ca(inter, root), None);
+}
+
+#[test]
+fn safe_default_client_verifier_modes_per_sni() {
+    let safe_default_verifier = SafeDefaultClientVerifier::new();
+    let verifier_handle = Arc::clone(&safe_default_verifier);
+    let server_config = ConfigBuilder::with_safe_defaults()
+        .for_server()
+        .unwrap()
+        .with_client_cert_verifier(safe_default_verifier)
+        .with_single_cert(verifier_chain(), verifier_key("end"))
+        .unwrap();
+    let server_config = Arc::new(server_config);
+    // Fresh client configs for each handshake, so sessions aren't resumed.
+    let anonymous_config = || Arc::new(make_verifier_client_config());
+    let auth_config = || Arc::new(make_verifier_client_config_with_auth("client"));
+
+    // Anonymous by default, but certificates required for "localhost".
+    verifier_handle.serve_anonymous_clients();
+    verifier_handle.serve_only_authenticated_clients_for_sni("LOCALHOST", verifier_roots());
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&anonymous_config(), &server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::NoCertificatesPresented))
+    );
+    let (mut client, mut server) = make_pair_for_arc_configs(&auth_config(), &server_config);
+    assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
+
+    assert!(verifier_handle.remove_sni_mode("localhost"));
+    assert!(!verifier_handle.remove_sni_mode("localhost"));
+    let (mut client, mut server) = make_pair_for_arc_configs(&anonymous_config(), &server_config);
+    assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
+
+    // Authenticated by default, but anonymous for "localhost".
+    verifier_handle.serve_only_authenticated_clients();
+    verifier_handle.serve_anonymous_clients_for_sni("localhost");
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&anonymous_config(), &server_config);
+    assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
+}
+
+#[test]
+fn safe_default_client_verifier_serves_client_with_cert_on_anonymous_sni() {
+    let safe_default_verifier = SafeDefaultClientVerifier::new();
+    let verifier_handle = Arc::clone(&safe_default_verifier);
+    let server_config = ConfigBuilder::with_safe_defaults()
+        .for_server()
+        .unwrap()
+        .with_client_cert_verifier(safe_default_verifier)
+        .with_single_cert(verifier_chain(), verifier_key("end"))
+        .unwrap();
+    let server_config = Arc::new(server_config);
+
+    // Certificates are required for another name, so client auth is
+    // offered, but not to clients connecting to "localhost".
+    verifier_handle.serve_anonymous_clients();
+    verifier_handle.serve_only_authenticated_clients_for_sni("admin.example.com", verifier_roots());
+
+    let client_config = Arc::new(make_verifier_client_config_with_auth("client"));
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
+    assert_eq!(server.peer_certificates(), None);
+}
+
+#[test]
+fn safe_default_client_verifier_reports_changes() {
+    let verifier = SafeDefaultClientVerifier::new();
+    let notified = Arc::new(Mutex::new(Vec::new()));
+    let notified_clone = Arc::clone(&notified);
+    verifier.subscribe(move |epoch| notified_clone.lock().unwrap().push(epoch));
+    assert_eq!(verifier.epoch(), 0);
+
+    let chain = KeyType::RSA.get_chain();
+    assert!(
+        verifier
+            .add_trusted_root_ca(&chain[2])
+            .unwrap()
+            .is_ok()
+    );
+    assert_eq!(verifier.epoch(), 1);
+
+    // Things which change nothing don't advance the epoch.
+    verifier.serve_only_authenticated_clients();
+    assert!(
+        verifier
+            .add_trusted_root_ca(&rustls::Certificate(b"garbage".to_vec()))
+            .unwrap()
+            .is_err()
+    );
+    assert_eq!(verifier.remove_trusted_root_ca(&chain[1].0), Some(0));
+    assert!(!verifier.remove_sni_mode("localhost"));
+    assert_eq!(verifier.epoch(), 1);
+
+    assert_eq!(verifier.remove_trusted_root_ca(&chain[2].0), Some(1));
+    verifier.serve_anonymous_clients_for_sni("localhost");
+    verifier.serve_anonymous_clients();
+    verifier.serve_anonymous_clients();
+    assert_eq!(verifier.epoch(), 4);
+    assert_eq!(*notified.lock().unwrap(), vec![1, 2, 3, 4]);
+}
+
+#[test]
+fn safe_default_client_verifier_notifies_in_order() {
+    let verifier = SafeDefaultClientVerifier::new();
+    let notified = Arc::new(Mutex::new(Vec::new()));
+    let notified_clone = Arc::clone(&notified);
+    verifier.subscribe(move |epoch| notified_clone.lock().unwrap().push(epoch));
+    let chain = KeyType::RSA.get_chain();
+
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let verifier = Arc::clone(&verifier);
+            let chain = chain.clone();
+            thread::spawn(move || {
+                for cert in &chain {
+                    verifier
+                        .add_trusted_root_ca(cert)
+                        .unwrap()
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+    for t in threads {
+        t.join().unwrap();
+    }
+
+    let expected: Vec<u64> = (1..=4 * chain.len() as u64).collect();
+    assert_eq!(*notified.lock().unwrap(), expected);
+}
+
+#[test]
+fn safe_default_client_verifier_concurrent_updates() {
+    let verifier = SafeDefaultClientVerifier::new();
+    let chain = KeyType::RSA.get_chain();
+
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let verifier = Arc::clone(&verifier);
+            let chain = chain.clone();
+            thread::spawn(move || {
+                for cert in &chain {
+                    assert!(
+                        verifier
+                            .add_trusted_root_ca(cert)
+                            .unwrap()
+                            .is_ok()
+                    );
+                    assert!(verifier.root_cert_store_len() > 0);
+                }
+            })
+        })
+        .collect();
+    for t in threads {
+        t.join().unwrap();
+    }
+
+    // No update was lost.
+    assert_eq!(verifier.root_cert_store_len(), 4 * chain.len());
+    assert_eq!(verifier.epoch(), 4 * chain.len() as u64);
+}
+
+#[test]
+fn safe_default_client_verifier_denies_client_certs() {
+    let safe_default_verifier = SafeDefaultClientVerifier::new();
+    let verifier_handle = Arc::clone(&safe_default_verifier);
+    let server_config = ConfigBuilder::with_safe_defaults()
+        .for_server()
+        .unwrap()
+        .with_client_cert_verifier(safe_default_verifier)
+        .with_single_cert(verifier_chain(), verifier_key("end"))
+        .unwrap();
+    let server_config = Arc::new(server_config);
+    for cert in verifier_certs("ca.cert") {
+        assert!(
+            verifier_handle
+                .add_trusted_root_ca(&cert)
+                .unwrap()
+                .is_ok()
+        );
+    }
+
+    // Fresh client configs for each handshake, so sessions aren't resumed.
+    let handshake = || {
+        let client_config = Arc::new(make_verifier_client_config_with_auth("client"));
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake_until_error(&mut client, &mut server)
+    };
+    let revoked = Err(ErrorFromPeer::Server(Error::CertificateRevoked));
+
+    let client_cert = &verifier_certs("client.cert")[0];
+    let digest = ring::digest::digest(&ring::digest::SHA256, &client_cert.0);
+    let mut fingerprint = [0u8; 32];
+    fingerprint.copy_from_slice(digest.as_ref());
+
+    assert!(verifier_handle.deny_client_cert_fingerprint(fingerprint));
+    assert!(!verifier_handle.deny_client_cert_fingerprint(fingerprint));
+    assert_eq!(handshake(), revoked);
+
+    assert!(verifier_handle.clear_denied_client_certs());
+    assert!(!verifier_handle.clear_denied_client_certs());
+    assert_eq!(handshake(), Ok(()));
+
+    let info = rustls::x509::CertificateInfo::parse(client_cert).unwrap();
+    assert!(verifier_handle.deny_client_cert_serial(&info.issuer, &info.serial));
+    assert_eq!(handshake(), revoked);
+}
+
+#[test]
+fn safe_default_client_verifier_exports_and_imports() {
+    let chain = KeyType::RSA.get_chain();
+    let verifier = SafeDefaultClientVerifier::new();
+    assert!(
+        verifier
+            .add_trusted_root_ca(&chain[2])
+            .unwrap()
+            .is_ok()
+    );
+    let mut roots = RootCertStore::empty();
+    roots.add(&chain[1]).unwrap();
+    verifier.serve_only_authenticated_clients_for_sni("admin.example.com", roots);
+    verifier.serve_anonymous_clients_for_sni("www.example.com");
+    verifier.deny_client_cert_fingerprint([7u8; 32]);
+    verifier.deny_client_cert_serial(b"issuer", b"\x01");
+    let exported = verifier.export();
+
+    let restored = SafeDefaultClientVerifier::new();
+    assert_eq!(restored.import(&exported), Ok(()));
+    assert_eq!(restored.export(), exported);
+    assert_eq!(restored.epoch(), 1);
+    assert_eq!(
+        restored.root_cert_store_subjects(),
+        verifier.root_cert_store_subjects()
+    );
+    assert!(restored.remove_sni_mode("admin.example.com"));
+    assert!(!restored.deny_client_cert_fingerprint([7u8; 32]));
+
+    let err = Err(Error::General("malformed client verifier state".into()));
+    assert_eq!(restored.import(&exported[..exported.len() - 1]), err);
+    assert_eq!(restored.import(&[exported.as_slice(), &[0]].concat()), err);
+    assert_eq!(restored.import(&[]), err);
+    assert_eq!(restored.epoch(), 2);
+}
+
 #[cfg(feature = "dangerous_configuration")]
 mod test_clientverifier {
     use super::*;
     use crate::common::MockClientVerifier;
     use rustls::internal::msgs::enums::AlertDescription;
     use rustls::internal::msgs::enums::ContentType;
+    use rustls::{Certificate, ClientCertVerifier, ClientCertVerifyContext, DistinguishedNames};
+    use std::net::SocketAddr;
 
     // Client is authorized!
     fn ver_ok() -> Result<ClientCertVerified, Error> {
@@ -967,7 +1894,7 @@ mod test_clientverifier {
             for client_config in AllClientVersions::new(client_config) {
                 let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
                 let mut client =
-                    ClientConnection::new(Arc::new(client_config), dns_name("notlocalhost"))
+                    ClientConnection::new(Arc::new(client_config), server_name("notlocalhost"))
                         .unwrap();
                 let errs = do_handshake_until_both_error(&mut client, &mut server);
                 assert_eq!(
@@ -1001,7 +1928,7 @@ mod test_clientverifier {
             for client_config in AllClientVersions::new(client_config) {
                 let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
                 let mut client =
-                    ClientConnection::new(Arc::new(client_config), dns_name("notlocalhost"))
+                    ClientConnection::new(Arc::new(client_config), server_name("notlocalhost"))
                         .unwrap();
                 let errs = do_handshake_until_both_error(&mut client, &mut server);
                 assert_eq!(
@@ -1036,7 +1963,8 @@ mod test_clientverifier {
                 println!("Failing: {:?}", client_config.versions);
                 let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
                 let mut client =
-                    ClientConnection::new(Arc::new(client_config), dns_name("localhost")).unwrap();
+                    ClientConnection::new(Arc::new(client_config), server_name("localhost"))
+                        .unwrap();
                 let errs = do_handshake_until_both_error(&mut client, &mut server);
                 assert_eq!(
                     errs,
@@ -1069,7 +1997,8 @@ mod test_clientverifier {
             for client_config in AllClientVersions::new(client_config) {
                 let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
                 let mut client =
-                    ClientConnection::new(Arc::new(client_config), dns_name("localhost")).unwrap();
+                    ClientConnection::new(Arc::new(client_config), server_name("localhost"))
+                        .unwrap();
                 let err = do_handshake_until_error(&mut client, &mut server);
                 assert_eq!(
                     err,
@@ -1097,7 +2026,8 @@ mod test_clientverifier {
             for client_config in AllClientVersions::new(client_config) {
                 let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
                 let mut client =
-                    ClientConnection::new(Arc::new(client_config), dns_name("localhost")).unwrap();
+                    ClientConnection::new(Arc::new(client_config), server_name("localhost"))
+                        .unwrap();
                 let errs = do_handshake_until_both_error(&mut client, &mut server);
                 assert_eq!(
                     errs,
@@ -1111,6 +2041,124 @@ mod test_clientverifier {
             }
         }
     }
+
+    /// The ALPN protocol, version, peer address and verifier data of a
+    /// verification.
+    type SeenContext = (Option<Vec<u8>>, ProtocolVersion, Option<SocketAddr>, Option<String>);
+
+    /// Requires client certificates only for the "admin" protocol, and
+    /// records the details of each verification.
+    struct AdminProtocolVerifier {
+        seen: Mutex<Vec<SeenContext>>,
+    }
+
+    impl ClientCertVerifier for AdminProtocolVerifier {
+        fn client_auth_root_subjects(
+            &self,
+            _sni: Option<&webpki::DnsName>,
+        ) -> Option<DistinguishedNames> {
+            Some(DistinguishedNames::new())
+        }
+
+        fn verify_client_cert(
+            &self,
+            _end_entity: &Certificate,
+            _intermediates: &[Certificate],
+            _sni: Option<&webpki::DnsName>,
+            _now: SystemTime,
+        ) -> Result<ClientCertVerified, Error> {
+            unreachable!()
+        }
+
+        fn client_auth_mandatory_in_context(&self, cx: &ClientCertVerifyContext) -> Option<bool> {
+            Some(cx.alpn_protocol == Some(b"admin"))
+        }
+
+        fn verify_client_cert_in_context(
+            &self,
+            _end_entity: &Certificate,
+            _intermediates: &[Certificate],
+            cx: &ClientCertVerifyContext,
+            _now: SystemTime,
+        ) -> Result<ClientCertVerified, Error> {
+            assert!(cx.sni.is_some());
+            self.seen.lock().unwrap().push((
+                cx.alpn_protocol.map(|p| p.to_vec()),
+                cx.protocol_version,
+                cx.peer_addr,
+                cx.user_data
+                    .and_then(|data| data.downcast_ref::<String>())
+                    .cloned(),
+            ));
+            ver_ok()
+        }
+    }
+
+    #[test]
+    fn client_verifier_gets_handshake_context() {
+        let verifier = Arc::new(AdminProtocolVerifier {
+            seen: Mutex::new(Vec::new()),
+        });
+        let mut server_config = ConfigBuilder::with_safe_defaults()
+            .for_server()
+            .unwrap()
+            .with_client_cert_verifier(Arc::clone(&verifier) as Arc<dyn ClientCertVerifier>)
+            .with_single_cert(verifier_chain(), verifier_key("end"))
+            .unwrap();
+        server_config.alpn_protocols = vec![b"admin".to_vec(), b"h2".to_vec()];
+        let server_config = Arc::new(server_config);
+        let peer_addr: SocketAddr = "192.0.2.1:4433".parse().unwrap();
+
+        let cases: &[(&[u8], bool, Result<(), ErrorFromPeer>)] = &[
+            (b"admin", true, Ok(())),
+            (
+                b"admin",
+                false,
+                Err(ErrorFromPeer::Server(Error::NoCertificatesPresented)),
+            ),
+            (b"h2", false, Ok(())),
+        ];
+        for (protocol, with_auth, expected) in cases {
+            let mut client_config = if *with_auth {
+                make_verifier_client_config_with_auth("client")
+            } else {
+                make_client_config_with_verifier(Arc::new(WebPkiVerifier::new(
+                    verifier_roots(),
+                    &[],
+                )))
+            };
+            client_config.alpn_protocols = vec![protocol.to_vec()];
+
+            for client_config in AllClientVersions::new(client_config) {
+                let (mut client, mut server) =
+                    make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+                server.set_peer_addr(peer_addr);
+                server.set_verifier_data(Arc::new("tenant-1".to_string()));
+                assert_eq!(
+                    &do_handshake_until_error(&mut client, &mut server),
+                    expected
+                );
+            }
+        }
+
+        assert_eq!(
+            *verifier.seen.lock().unwrap(),
+            vec![
+                (
+                    Some(b"admin".to_vec()),
+                    ProtocolVersion::TLSv1_2,
+                    Some(peer_addr),
+                    Some("tenant-1".to_string())
+                ),
+                (
+                    Some(b"admin".to_vec()),
+                    ProtocolVersion::TLSv1_3,
+                    Some(peer_addr),
+                    Some("tenant-1".to_string())
+                ),
+            ]
+        );
+    }
 } // mod test_clientverifier
 
 #[cfg(feature = "dangerous_configuration")]
@@ -1139,6 +2187,33 @@ mod test_serververifier {
         }
     }
 
+    #[test]
+    fn no_certificate_verification_accepts_any_certificate() {
+        let mut client_config = make_client_config(KeyType::RSA);
+        client_config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(NoCertificateVerification));
+
+        // Self-signed, expired, and not for the name the client uses.
+        let server_config = ConfigBuilder::with_safe_defaults()
+            .for_server()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(
+                verifier_certs("selfsigned-expired.cert"),
+                verifier_key("selfsigned-expired"),
+            )
+            .unwrap();
+
+        let mut client = ClientConnection::new(
+            Arc::new(client_config),
+            server_name("not-the-right-hostname.com"),
+        )
+        .unwrap();
+        let mut server = ServerConnection::new(Arc::new(server_config)).unwrap();
+        assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
+    }
+
     #[test]
     fn client_can_override_certificate_verification_and_reject_certificate() {
         for kt in ALL_KEY_TYPES.iter() {
@@ -1171,74 +2246,443 @@ mod test_serververifier {
     }
 
     #[test]
-    fn client_can_override_certificate_verification_and_reject_tls12_signatures() {
+    fn client_waits_for_pending_certificate_verification() {
         for kt in ALL_KEY_TYPES.iter() {
-            let mut client_config = make_client_config(*kt);
-            let verifier = Arc::new(MockServerVerifier::rejects_tls12_signatures(
-                Error::CorruptMessage,
-            ));
+            let server_config = Arc::new(make_server_config(*kt));
 
-            client_config
-                .dangerous()
-                .set_certificate_verifier(verifier);
-            client_config
-                .versions
-                .replace(&[&rustls::version::TLS12]);
+            for mut client_config in AllClientVersions::new(make_client_config(*kt)) {
+                let pending = PendingVerification::new();
+                let verifier = Arc::new(MockServerVerifier::completes_later(pending.clone()));
+                client_config
+                    .dangerous()
+                    .set_certificate_verifier(verifier);
 
-            let server_config = Arc::new(make_server_config(*kt));
+                let (mut client, mut server) =
+                    make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+                transfer(&mut client, &mut server);
+                server.process_new_packets().unwrap();
+                transfer(&mut server, &mut client);
+                client.process_new_packets().unwrap();
+
+                assert!(client.is_verification_pending());
+                client.process_new_packets().unwrap();
+                assert!(client.is_verification_pending());
+
+                pending.complete(Ok(ServerCertVerified::assertion()));
+                pending.complete(Err(Error::CorruptMessage));
+                assert!(pending.is_complete());
+                assert!(client.is_verification_pending());
+                client.process_new_packets().unwrap();
+                assert!(!client.is_verification_pending());
 
-            let (mut client, mut server) =
-                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
-            let errs = do_handshake_until_both_error(&mut client, &mut server);
-            assert_eq!(
-                errs,
-                Err(vec![
-                    ErrorFromPeer::Client(Error::CorruptMessage),
-                    ErrorFromPeer::Server(Error::AlertReceived(AlertDescription::BadCertificate))
-                ])
-            );
+                do_handshake(&mut client, &mut server);
+                assert!(!client.is_handshaking());
+                assert!(!server.is_handshaking());
+            }
         }
     }
 
     #[test]
-    fn client_can_override_certificate_verification_and_reject_tls13_signatures() {
+    fn client_fails_pending_certificate_verification() {
         for kt in ALL_KEY_TYPES.iter() {
-            let mut client_config = make_client_config(*kt);
-            let verifier = Arc::new(MockServerVerifier::rejects_tls13_signatures(
-                Error::CorruptMessage,
-            ));
-
-            client_config
-                .dangerous()
-                .set_certificate_verifier(verifier);
-            client_config
-                .versions
-                .replace(&[&rustls::version::TLS13]);
-
             let server_config = Arc::new(make_server_config(*kt));
 
-            let (mut client, mut server) =
-                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
-            let errs = do_handshake_until_both_error(&mut client, &mut server);
-            assert_eq!(
-                errs,
-                Err(vec![
-                    ErrorFromPeer::Client(Error::CorruptMessage),
-                    ErrorFromPeer::Server(Error::AlertReceived(AlertDescription::BadCertificate))
-                ])
-            );
+            for mut client_config in AllClientVersions::new(make_client_config(*kt)) {
+                let pending = PendingVerification::new();
+                let verifier = Arc::new(MockServerVerifier::completes_later(pending.clone()));
+                client_config
+                    .dangerous()
+                    .set_certificate_verifier(verifier);
+
+                let (mut client, mut server) =
+                    make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+                transfer(&mut client, &mut server);
+                server.process_new_packets().unwrap();
+                transfer(&mut server, &mut client);
+                client.process_new_packets().unwrap();
+                assert!(client.is_verification_pending());
+
+                pending.complete(Err(Error::CorruptMessage));
+                assert_eq!(client.process_new_packets(), Err(Error::CorruptMessage));
+                transfer(&mut client, &mut server);
+                assert_eq!(
+                    server.process_new_packets(),
+                    Err(Error::AlertReceived(AlertDescription::BadCertificate))
+                );
+            }
         }
     }
 
-    #[test]
-    fn client_can_override_certificate_verification_and_offer_no_signature_schemes() {
-        for kt in ALL_KEY_TYPES.iter() {
-            let mut client_config = make_client_config(*kt);
-            let verifier = Arc::new(MockServerVerifier::offers_no_signature_schemes());
+    /// Run a TLS1.3 handshake with `verifier` until it waits for
+    /// `pending`, then complete `pending` with `result`.
+    fn handshake_with_pending_verifier(
+        verifier: Arc<dyn ServerCertVerifier>,
+        pending: &PendingVerification,
+        result: Result<ServerCertVerified, Error>,
+    ) -> Result<(), Error> {
+        let mut client_config = make_client_config(KeyType::RSA);
+        client_config
+            .dangerous()
+            .set_certificate_verifier(verifier);
+        client_config
+            .versions
+            .replace(&[&rustls::version::TLS13]);
+        let server_config = make_server_config(KeyType::RSA);
 
-            client_config
-                .dangerous()
-                .set_certificate_verifier(verifier);
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+        transfer(&mut server, &mut client);
+        client.process_new_packets().unwrap();
+        assert!(client.is_verification_pending());
+
+        pending.complete(result);
+        client.process_new_packets().map(|_| ())
+    }
+
+    #[test]
+    fn wrapping_verifiers_wait_for_pending_verification() {
+        let wrappers: Vec<fn(MockServerVerifier) -> Arc<dyn ServerCertVerifier>> = vec![
+            |inner| Arc::new(BudgetedVerifier::new(inner, Duration::from_secs(10))),
+            |inner| Arc::new(ReportOnlyVerifier::new(inner, |_, _, _| {})),
+            |inner| Arc::new(AuditingVerifier::new(inner, |_| {})),
+            |inner| {
+                Arc::new(AndVerifier::new(
+                    MockServerVerifier::accepts_anything(),
+                    inner,
+                ))
+            },
+            |inner| {
+                Arc::new(OrVerifier::new(
+                    MockServerVerifier::rejects_certificate(Error::DecryptError),
+                    inner,
+                ))
+            },
+            |inner| {
+                Arc::new(ShadowVerifier::new(
+                    inner,
+                    MockServerVerifier::accepts_anything(),
+                    |_| {},
+                ))
+            },
+        ];
+
+        for wrap in wrappers {
+            let pending = PendingVerification::new();
+            let verifier = wrap(MockServerVerifier::completes_later(pending.clone()));
+            assert_eq!(
+                handshake_with_pending_verifier(
+                    verifier,
+                    &pending,
+                    Ok(ServerCertVerified::assertion())
+                ),
+                Ok(())
+            );
+        }
+    }
+
+    #[test]
+    fn budgeted_verifier_times_pending_verification() {
+        let pending = PendingVerification::new();
+        let verifier = BudgetedVerifier::new(
+            MockServerVerifier::completes_later(pending.clone()),
+            Duration::from_millis(10),
+        );
+        let delayed = pending.clone();
+        let completion = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            delayed.complete(Ok(ServerCertVerified::assertion()));
+        });
+
+        let mut client_config = make_client_config(KeyType::RSA);
+        client_config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(verifier));
+        let server_config = make_server_config(KeyType::RSA);
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+        transfer(&mut server, &mut client);
+        client.process_new_packets().unwrap();
+
+        completion.join().unwrap();
+        assert_eq!(
+            client.process_new_packets(),
+            Err(Error::VerificationTimedOut)
+        );
+    }
+
+    #[test]
+    fn report_only_verifier_reports_pending_failure() {
+        let failures = Arc::new(AtomicUsize::new(0));
+        let pending = PendingVerification::new();
+        let verifier = ReportOnlyVerifier::new(
+            MockServerVerifier::completes_later(pending.clone()),
+            {
+                let failures = failures.clone();
+                move |_, _, err| {
+                    assert_eq!(err, &Error::CorruptMessage);
+                    failures.fetch_add(1, Ordering::SeqCst);
+                }
+            },
+        );
+
+        assert_eq!(
+            handshake_with_pending_verifier(
+                Arc::new(verifier),
+                &pending,
+                Err(Error::CorruptMessage)
+            ),
+            Ok(())
+        );
+        assert_eq!(failures.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn or_verifier_returns_first_error_when_pending_second_rejects() {
+        let pending = PendingVerification::new();
+        let verifier = OrVerifier::new(
+            MockServerVerifier::rejects_certificate(Error::DecryptError),
+            MockServerVerifier::completes_later(pending.clone()),
+        );
+        assert_eq!(
+            handshake_with_pending_verifier(
+                Arc::new(verifier),
+                &pending,
+                Err(Error::CorruptMessage)
+            ),
+            Err(Error::DecryptError)
+        );
+    }
+
+    #[test]
+    fn combinators_reject_pending_first_verifier() {
+        let result = tls13_handshake_with_verifier(AndVerifier::new(
+            MockServerVerifier::completes_later(PendingVerification::new()),
+            MockServerVerifier::accepts_anything(),
+        ));
+        assert!(matches!(result, Err(ErrorFromPeer::Client(Error::General(_)))));
+
+        let result = tls13_handshake_with_verifier(OrVerifier::new(
+            MockServerVerifier::completes_later(PendingVerification::new()),
+            MockServerVerifier::accepts_anything(),
+        ));
+        assert!(matches!(result, Err(ErrorFromPeer::Client(Error::General(_)))));
+    }
+
+    #[test]
+    fn client_verifier_can_choose_alert() {
+        let rejection = Error::InvalidCertificate(
+            AlertDescription::AccessDenied,
+            Box::new(Error::CorruptMessage),
+        );
+        let mut client_config = make_client_config(KeyType::RSA);
+        client_config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(MockServerVerifier::rejects_certificate(
+                rejection.clone(),
+            )));
+        let server_config = Arc::new(make_server_config(KeyType::RSA));
+
+        for client_config in AllClientVersions::new(client_config) {
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+            assert_eq!(
+                do_handshake_until_both_error(&mut client, &mut server),
+                Err(vec![
+                    ErrorFromPeer::Client(rejection.clone()),
+                    ErrorFromPeer::Server(Error::AlertReceived(AlertDescription::AccessDenied)),
+                ])
+            );
+        }
+    }
+
+    #[test]
+    fn client_can_override_certificate_verification_and_reject_tls12_signatures() {
+        for kt in ALL_KEY_TYPES.iter() {
+            let mut client_config = make_client_config(*kt);
+            let verifier = Arc::new(MockServerVerifier::rejects_tls12_signatures(
+                Error::CorruptMessage,
+            ));
+
+            client_config
+                .dangerous()
+                .set_certificate_verifier(verifier);
+            client_config
+                .versions
+                .replace(&[&rustls::version::TLS12]);
+
+            let server_config = Arc::new(make_server_config(*kt));
+
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+            let errs = do_handshake_until_both_error(&mut client, &mut server);
+            assert_eq!(
+                errs,
+                Err(vec![
+                    ErrorFromPeer::Client(Error::CorruptMessage),
+                    ErrorFromPeer::Server(Error::AlertReceived(AlertDescription::BadCertificate))
+                ])
+            );
+        }
+    }
+
+    #[test]
+    fn client_can_override_certificate_verification_and_reject_tls13_signatures() {
+        for kt in ALL_KEY_TYPES.iter() {
+            let mut client_config = make_client_config(*kt);
+            let verifier = Arc::new(MockServerVerifier::rejects_tls13_signatures(
+                Error::CorruptMessage,
+            ));
+
+            client_config
+                .dangerous()
+                .set_certificate_verifier(verifier);
+            client_config
+                .versions
+                .replace(&[&rustls::version::TLS13]);
+
+            let server_config = Arc::new(make_server_config(*kt));
+
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+            let errs = do_handshake_until_both_error(&mut client, &mut server);
+            assert_eq!(
+                errs,
+                Err(vec![
+                    ErrorFromPeer::Client(Error::CorruptMessage),
+                    ErrorFromPeer::Server(Error::AlertReceived(AlertDescription::BadCertificate))
+                ])
+            );
+        }
+    }
+
+    #[test]
+    fn webpki_verifier_builder_controls_sct_requests() {
+        let default = WebPkiVerifier::builder(verifier_roots()).build();
+        assert!(default.request_scts());
+        assert_eq!(
+            default.supported_verify_schemes(),
+            WebPkiVerifier::verification_schemes()
+        );
+
+        let verifier = WebPkiVerifier::builder(verifier_roots())
+            .with_sct_requests(false)
+            .build();
+        assert!(!verifier.request_scts());
+    }
+
+    fn tls13_handshake_with_verifier(
+        verifier: impl ServerCertVerifier + 'static,
+    ) -> Result<(), ErrorFromPeer> {
+        let mut client_config = make_client_config(KeyType::RSA);
+        client_config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(verifier));
+        client_config
+            .versions
+            .replace(&[&rustls::version::TLS13]);
+        let server_config = make_server_config(KeyType::RSA);
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake_until_error(&mut client, &mut server)
+    }
+
+    #[test]
+    fn and_verifier_requires_both_verifiers() {
+        assert_eq!(
+            tls13_handshake_with_verifier(AndVerifier::new(
+                MockServerVerifier::accepts_anything(),
+                MockServerVerifier::accepts_anything(),
+            )),
+            Ok(())
+        );
+        assert_eq!(
+            tls13_handshake_with_verifier(AndVerifier::new(
+                MockServerVerifier::accepts_anything(),
+                MockServerVerifier::rejects_certificate(Error::CorruptMessage),
+            )),
+            Err(ErrorFromPeer::Client(Error::CorruptMessage))
+        );
+        assert_eq!(
+            tls13_handshake_with_verifier(AndVerifier::new(
+                MockServerVerifier::rejects_certificate(Error::DecryptError),
+                MockServerVerifier::rejects_certificate(Error::CorruptMessage),
+            )),
+            Err(ErrorFromPeer::Client(Error::DecryptError))
+        );
+    }
+
+    #[test]
+    fn and_verifier_checks_signatures_with_first_verifier() {
+        assert_eq!(
+            tls13_handshake_with_verifier(AndVerifier::new(
+                MockServerVerifier::accepts_anything(),
+                MockServerVerifier::rejects_tls13_signatures(Error::CorruptMessage),
+            )),
+            Ok(())
+        );
+        assert_eq!(
+            tls13_handshake_with_verifier(AndVerifier::new(
+                MockServerVerifier::rejects_tls13_signatures(Error::CorruptMessage),
+                MockServerVerifier::accepts_anything(),
+            )),
+            Err(ErrorFromPeer::Client(Error::CorruptMessage))
+        );
+    }
+
+    #[test]
+    fn or_verifier_requires_either_verifier() {
+        assert_eq!(
+            tls13_handshake_with_verifier(OrVerifier::new(
+                MockServerVerifier::rejects_certificate(Error::CorruptMessage),
+                MockServerVerifier::accepts_anything(),
+            )),
+            Ok(())
+        );
+        assert_eq!(
+            tls13_handshake_with_verifier(OrVerifier::new(
+                MockServerVerifier::accepts_anything(),
+                MockServerVerifier::rejects_certificate(Error::CorruptMessage),
+            )),
+            Ok(())
+        );
+        assert_eq!(
+            tls13_handshake_with_verifier(OrVerifier::new(
+                MockServerVerifier::rejects_certificate(Error::DecryptError),
+                MockServerVerifier::rejects_certificate(Error::CorruptMessage),
+            )),
+            Err(ErrorFromPeer::Client(Error::DecryptError))
+        );
+    }
+
+    #[test]
+    fn or_verifier_checks_signatures_with_accepting_verifier() {
+        assert_eq!(
+            tls13_handshake_with_verifier(OrVerifier::new(
+                MockServerVerifier::accepts_anything(),
+                MockServerVerifier::rejects_tls13_signatures(Error::CorruptMessage),
+            )),
+            Ok(())
+        );
+        assert_eq!(
+            tls13_handshake_with_verifier(OrVerifier::new(
+                MockServerVerifier::rejects_certificate(Error::DecryptError),
+                MockServerVerifier::rejects_tls13_signatures(Error::CorruptMessage),
+            )),
+            Err(ErrorFromPeer::Client(Error::CorruptMessage))
+        );
+    }
+
+    #[test]
+    fn client_can_override_certificate_verification_and_offer_no_signature_schemes() {
+        for kt in ALL_KEY_TYPES.iter() {
+            let mut client_config = make_client_config(*kt);
+            let verifier = Arc::new(MockServerVerifier::offers_no_signature_schemes());
+
+            client_config
+                .dangerous()
+                .set_certificate_verifier(verifier);
             client_config
                 .versions
                 .replace(&[&rustls::version::TLS13]);
@@ -1263,1119 +2707,4558 @@ mod test_serververifier {
             }
         }
     }
-}
 
-#[test]
-fn client_error_is_sticky() {
-    let (mut client, _) = make_pair(KeyType::RSA);
-    client
-        .read_tls(&mut b"\x16\x03\x03\x00\x08\x0f\x00\x00\x04junk".as_ref())
-        .unwrap();
-    let mut err = client.process_new_packets();
-    assert_eq!(err.is_err(), true);
-    err = client.process_new_packets();
-    assert_eq!(err.is_err(), true);
-}
+    fn pinned_handshake(pinned: &str, presented: &str) -> Result<(), ErrorFromPeer> {
+        let pin = ring::digest::digest(&ring::digest::SHA256, &verifier_certs(pinned)[0].0);
+        let mut fingerprint = [0u8; 32];
+        fingerprint.copy_from_slice(pin.as_ref());
 
-#[test]
-fn server_error_is_sticky() {
-    let (_, mut server) = make_pair(KeyType::RSA);
-    server
-        .read_tls(&mut b"\x16\x03\x03\x00\x08\x0f\x00\x00\x04junk".as_ref())
-        .unwrap();
-    let mut err = server.process_new_packets();
-    assert_eq!(err.is_err(), true);
-    err = server.process_new_packets();
-    assert_eq!(err.is_err(), true);
-}
+        let mut client_config = make_client_config_with_verifier(Arc::new(WebPkiVerifier::new(
+            RootCertStore::empty(),
+            &[],
+        )));
+        client_config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(SelfSignedPinnedVerifier::new(&[fingerprint])));
 
-#[test]
-fn server_flush_does_nothing() {
-    let (_, mut server) = make_pair(KeyType::RSA);
-    assert!(matches!(server.writer().flush(), Ok(())));
-}
+        let server_config = ConfigBuilder::with_safe_defaults()
+            .for_server()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(
+                verifier_certs(&format!("{}.cert", presented)),
+                verifier_key(presented),
+            )
+            .unwrap();
 
-#[test]
-fn client_flush_does_nothing() {
-    let (mut client, _) = make_pair(KeyType::RSA);
-    assert!(matches!(client.writer().flush(), Ok(())));
-}
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake_until_error(&mut client, &mut server)
+    }
 
-#[test]
-fn server_is_send_and_sync() {
-    let (_, server) = make_pair(KeyType::RSA);
-    &server as &dyn Send;
-    &server as &dyn Sync;
-}
+    #[test]
+    fn pinned_verifier_accepts_matching_certificate() {
+        assert_eq!(pinned_handshake("selfsigned.cert", "selfsigned"), Ok(()));
+    }
 
-#[test]
-fn client_is_send_and_sync() {
-    let (client, _) = make_pair(KeyType::RSA);
-    &client as &dyn Send;
-    &client as &dyn Sync;
-}
+    #[test]
+    fn pinned_verifier_rejects_other_certificate() {
+        assert_eq!(
+            pinned_handshake("selfsigned.cert", "selfsigned-expired"),
+            Err(ErrorFromPeer::Client(Error::WebPkiError(
+                webpki::Error::UnknownIssuer,
+                WebPkiOp::ValidateServerCert
+            )))
+        );
+    }
 
-#[test]
-fn server_respects_buffer_limit_pre_handshake() {
-    let (mut client, mut server) = make_pair(KeyType::RSA);
+    #[test]
+    fn pinned_verifier_rejects_expired_certificate() {
+        assert_eq!(
+            pinned_handshake("selfsigned-expired.cert", "selfsigned-expired"),
+            Err(ErrorFromPeer::Client(Error::WebPkiError(
+                webpki::Error::CertExpired,
+                WebPkiOp::ValidateServerCert
+            )))
+        );
+    }
 
-    server.set_buffer_limit(32);
+    fn tofu_handshake(store: Arc<dyn TofuStore>, kt: KeyType) -> Result<(), ErrorFromPeer> {
+        let mut client_config = make_client_config(kt);
+        client_config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(TofuVerifier::new(store)));
+        let server_config = make_server_config(kt);
 
-    assert_eq!(
-        server
-            .writer()
-            .write(b"01234567890123456789")
-            .unwrap(),
-        20
-    );
-    assert_eq!(
-        server
-            .writer()
-            .write(b"01234567890123456789")
-            .unwrap(),
-        12
-    );
-
-    do_handshake(&mut client, &mut server);
-    transfer(&mut server, &mut client);
-    client.process_new_packets().unwrap();
-
-    check_read(&mut client.reader(), b"01234567890123456789012345678901");
-}
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake_until_error(&mut client, &mut server)
+    }
 
-#[test]
-fn server_respects_buffer_limit_pre_handshake_with_vectored_write() {
-    let (mut client, mut server) = make_pair(KeyType::RSA);
+    #[test]
+    fn tofu_verifier_trusts_first_key_seen() {
+        let store = TofuMemoryStore::new();
+        assert_eq!(store.get("localhost"), None);
 
-    server.set_buffer_limit(32);
+        assert_eq!(tofu_handshake(store.clone(), KeyType::RSA), Ok(()));
+        let recorded = store.get("localhost").unwrap();
+        assert_eq!(recorded.len(), 32);
 
-    assert_eq!(
-        server
-            .writer()
-            .write_vectored(&[
-                IoSlice::new(b"01234567890123456789"),
-                IoSlice::new(b"01234567890123456789")
-            ])
-            .unwrap(),
-        32
-    );
+        assert_eq!(tofu_handshake(store.clone(), KeyType::RSA), Ok(()));
+        assert_eq!(
+            tofu_handshake(store.clone(), KeyType::ECDSA),
+            Err(ErrorFromPeer::Client(Error::ServerKeyChanged))
+        );
+        assert_eq!(store.get("localhost"), Some(recorded));
+    }
 
-    do_handshake(&mut client, &mut server);
-    transfer(&mut server, &mut client);
-    client.process_new_packets().unwrap();
+    #[test]
+    fn tofu_verifier_rejects_key_it_cannot_record() {
+        struct ReadOnlyStore;
 
-    check_read(&mut client.reader(), b"01234567890123456789012345678901");
-}
+        impl TofuStore for ReadOnlyStore {
+            fn get(&self, _host: &str) -> Option<Vec<u8>> {
+                None
+            }
 
-#[test]
-fn server_respects_buffer_limit_post_handshake() {
-    let (mut client, mut server) = make_pair(KeyType::RSA);
+            fn put(&self, _host: &str, _spki_sha256: Vec<u8>) -> bool {
+                false
+            }
+        }
 
-    // this test will vary in behaviour depending on the default suites
-    do_handshake(&mut client, &mut server);
-    server.set_buffer_limit(48);
+        assert_eq!(
+            tofu_handshake(Arc::new(ReadOnlyStore), KeyType::RSA),
+            Err(ErrorFromPeer::Client(Error::General(
+                "cannot record key for localhost".into()
+            )))
+        );
+    }
 
-    assert_eq!(
-        server
-            .writer()
-            .write(b"01234567890123456789")
-            .unwrap(),
-        20
-    );
-    assert_eq!(
-        server
-            .writer()
-            .write(b"01234567890123456789")
-            .unwrap(),
-        6
-    );
+    fn make_client_config_with_raw_public_keys(kt: KeyType, allowed: &[&[u8]]) -> ClientConfig {
+        let mut client_config = make_client_config(kt);
+        client_config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(RawPublicKeyVerifier::new(allowed)));
+        client_config
+    }
 
-    transfer(&mut server, &mut client);
-    client.process_new_packets().unwrap();
+    #[test]
+    fn raw_public_key_verifier_accepts_listed_key_in_certificate() {
+        for kt in ALL_KEY_TYPES.iter() {
+            let client_config = make_client_config_with_raw_public_keys(
+                *kt,
+                &[KeyType::RSA.get_spki(), kt.get_spki()],
+            );
+            let server_config = Arc::new(make_server_config(*kt));
 
-    check_read(&mut client.reader(), b"01234567890123456789012345");
-}
+            for client_config in AllClientVersions::new(client_config) {
+                let (mut client, mut server) =
+                    make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+                do_handshake(&mut client, &mut server);
+            }
+        }
+    }
 
-#[test]
-fn client_respects_buffer_limit_pre_handshake() {
-    let (mut client, mut server) = make_pair(KeyType::RSA);
+    #[test]
+    fn raw_public_key_verifier_rejects_unlisted_key_in_certificate() {
+        let client_config =
+            make_client_config_with_raw_public_keys(KeyType::ECDSA, &[KeyType::RSA.get_spki()]);
+        let server_config = make_server_config(KeyType::ECDSA);
 
-    client.set_buffer_limit(32);
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        assert_eq!(
+            do_handshake_until_error(&mut client, &mut server),
+            Err(ErrorFromPeer::Client(Error::WebPkiError(
+                webpki::Error::UnknownIssuer,
+                WebPkiOp::ValidateServerCert
+            )))
+        );
+    }
 
-    assert_eq!(
-        client
-            .writer()
-            .write(b"01234567890123456789")
-            .unwrap(),
-        20
-    );
-    assert_eq!(
-        client
-            .writer()
-            .write(b"01234567890123456789")
-            .unwrap(),
-        12
-    );
+    /// Have a TLS1.2 server using `kt` appear to send its key as a raw
+    /// public key, and return the client's verdict.
+    ///
+    /// The server's transcript no longer matches the client's, but the
+    /// key exchange signature remains valid.
+    fn raw_public_key_handshake(
+        kt: KeyType,
+        allowed: &[u8],
+    ) -> (ClientConnection, Result<(), Error>) {
+        use rustls::internal::msgs::base::PayloadU24;
+        use rustls::internal::msgs::enums::CertificateType;
+        use rustls::internal::msgs::handshake::ServerExtension;
 
-    do_handshake(&mut client, &mut server);
-    transfer(&mut client, &mut server);
-    server.process_new_packets().unwrap();
+        let mut client_config = make_client_config_with_raw_public_keys(kt, &[allowed]);
+        client_config
+            .versions
+            .replace(&[&rustls::version::TLS12]);
+        let server_config = make_server_config(kt);
+
+        let send_raw_public_key = |msg: &mut Message| {
+            if let MessagePayload::Handshake(hs) = &mut msg.payload {
+                match &mut hs.payload {
+                    HandshakePayload::ServerHello(sh) => {
+                        sh.extensions
+                            .push(ServerExtension::ServerCertificateType(
+                                CertificateType::RawPublicKey,
+                            ));
+                    }
+                    HandshakePayload::Certificate(_) => {
+                        hs.payload = HandshakePayload::CertificateRawPublicKey(PayloadU24::new(
+                            kt.get_spki().to_vec(),
+                        ));
+                    }
+                    _ => {}
+                }
+            }
+        };
 
-    check_read(&mut server.reader(), b"01234567890123456789012345678901");
-}
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+        transfer_altered(&mut server, send_raw_public_key, &mut client);
+        let result = client.process_new_packets().map(|_| ());
+        (client, result)
+    }
 
-#[test]
-fn client_respects_buffer_limit_pre_handshake_with_vectored_write() {
-    let (mut client, mut server) = make_pair(KeyType::RSA);
+    #[test]
+    fn raw_public_key_verifier_accepts_listed_raw_public_key() {
+        for kt in ALL_KEY_TYPES.iter() {
+            let (client, result) = raw_public_key_handshake(*kt, kt.get_spki());
+            assert_eq!(result, Ok(()));
+            assert_eq!(
+                client.peer_certificates(),
+                Some(&[rustls::Certificate(kt.get_spki().to_vec())][..])
+            );
+        }
+    }
 
-    client.set_buffer_limit(32);
+    #[test]
+    fn raw_public_key_verifier_rejects_unlisted_raw_public_key() {
+        let (_, result) = raw_public_key_handshake(KeyType::ECDSA, KeyType::RSA.get_spki());
+        assert_eq!(
+            result,
+            Err(Error::WebPkiError(
+                webpki::Error::UnknownIssuer,
+                WebPkiOp::ValidateServerCert
+            ))
+        );
+    }
 
-    assert_eq!(
-        client
-            .writer()
-            .write_vectored(&[
-                IoSlice::new(b"01234567890123456789"),
-                IoSlice::new(b"01234567890123456789")
-            ])
-            .unwrap(),
-        32
-    );
+    /// Accepts any certificate, after a delay.
+    struct SlowVerifier(Duration);
+
+    impl ServerCertVerifier for SlowVerifier {
+        fn verify_server_cert(
+            &self,
+            _end_entity: &rustls::Certificate,
+            _intermediates: &[rustls::Certificate],
+            _server_name: &ServerName,
+            _scts: &mut dyn Iterator<Item = &[u8]>,
+            _ocsp_response: &[u8],
+            _now: std::time::SystemTime,
+        ) -> Result<ServerCertVerified, Error> {
+            std::thread::sleep(self.0);
+            Ok(ServerCertVerified::assertion())
+        }
+    }
 
-    do_handshake(&mut client, &mut server);
-    transfer(&mut client, &mut server);
-    server.process_new_packets().unwrap();
+    fn budgeted_handshake(verifier: BudgetedVerifier<SlowVerifier>) -> Result<(), ErrorFromPeer> {
+        let mut client_config = make_client_config_with_verifier(Arc::new(WebPkiVerifier::new(
+            RootCertStore::empty(),
+            &[],
+        )));
+        client_config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(verifier));
 
-    check_read(&mut server.reader(), b"01234567890123456789012345678901");
-}
+        let server_config = make_verifier_server_config(&[]);
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake_until_error(&mut client, &mut server)
+    }
 
-#[test]
-fn client_respects_buffer_limit_post_handshake() {
-    let (mut client, mut server) = make_pair(KeyType::RSA);
+    fn fips_verify(chain: &[rustls::Certificate]) -> Result<(), Error> {
+        FipsModeVerifier::new(verifier_roots(), &[])
+            .unwrap()
+            .verify_server_cert(
+                &chain[0],
+                &chain[1..],
+                &server_name("testserver.com"),
+                &mut std::iter::empty(),
+                &[],
+                std::time::SystemTime::now(),
+            )
+            .map(|_| ())
+    }
 
-    do_handshake(&mut client, &mut server);
-    client.set_buffer_limit(48);
+    #[test]
+    fn fips_verifier_accepts_conforming_chain() {
+        assert_eq!(fips_verify(&verifier_chain()), Ok(()));
+    }
 
-    assert_eq!(
-        client
-            .writer()
-            .write(b"01234567890123456789")
-            .unwrap(),
-        20
-    );
-    assert_eq!(
-        client
-            .writer()
-            .write(b"01234567890123456789")
-            .unwrap(),
-        6
-    );
+    #[test]
+    fn fips_verifier_accepts_rsa_pss_chain_signature() {
+        assert_eq!(fips_verify(&verifier_chain_for("fips-rsa-pss")), Ok(()));
+    }
 
-    transfer(&mut client, &mut server);
-    server.process_new_packets().unwrap();
+    #[test]
+    fn fips_verifier_rejects_small_rsa_key() {
+        assert_eq!(
+            fips_verify(&verifier_chain_for("fips-rsa1024")),
+            Err(Error::KeyTooSmall)
+        );
+    }
 
-    check_read(&mut server.reader(), b"01234567890123456789012345");
-}
+    #[test]
+    fn fips_verifier_rejects_ed25519_key() {
+        assert_eq!(
+            fips_verify(&verifier_chain_for("fips-ed25519")),
+            Err(Error::DisallowedKeyAlgorithm)
+        );
+    }
 
-struct OtherSession<'a> {
-    sess: &'a mut dyn Connection,
-    pub reads: usize,
-    pub writevs: Vec<Vec<usize>>,
-    fail_ok: bool,
-    pub short_writes: bool,
-    pub last_error: Option<rustls::Error>,
-}
+    #[test]
+    fn fips_verifier_rejects_ed25519_chain_signature() {
+        let mut chain = verifier_certs("fips-ed25519-issued.cert");
+        chain.extend(verifier_certs("inter-ed25519.cert"));
+        assert_eq!(
+            fips_verify(&chain),
+            Err(Error::ChainSignatureAlgorithmNotAllowed)
+        );
+    }
 
-impl<'a> OtherSession<'a> {
-    fn new(sess: &'a mut dyn Connection) -> OtherSession<'a> {
-        OtherSession {
-            sess,
-            reads: 0,
-            writevs: vec![],
-            fail_ok: false,
-            short_writes: false,
-            last_error: None,
-        }
+    #[test]
+    fn fips_verifier_offers_only_approved_schemes() {
+        let schemes = FipsModeVerifier::new(verifier_roots(), &[])
+            .unwrap()
+            .supported_verify_schemes();
+        assert!(!schemes.contains(&SignatureScheme::ED25519));
+        assert!(!schemes.contains(&SignatureScheme::RSA_PKCS1_SHA1));
+        assert!(schemes.contains(&SignatureScheme::ECDSA_NISTP256_SHA256));
+        assert!(schemes.contains(&SignatureScheme::RSA_PSS_SHA256));
     }
 
-    fn new_fails(sess: &'a mut dyn Connection) -> OtherSession<'a> {
-        let mut os = OtherSession::new(sess);
-        os.fail_ok = true;
-        os
+    #[test]
+    fn fips_verifier_rejects_nonconforming_anchors() {
+        let fips_new = |file: &str| {
+            let mut roots = verifier_roots();
+            for root in verifier_certs(file) {
+                roots.add(&root).unwrap();
+            }
+            FipsModeVerifier::new(roots, &[]).map(|_| ())
+        };
+
+        assert_eq!(fips_new("fips-rsa1024.cert"), Err(Error::KeyTooSmall));
+        assert_eq!(
+            fips_new("inter-ed25519.cert"),
+            Err(Error::DisallowedKeyAlgorithm)
+        );
     }
-}
 
-impl<'a> io::Read for OtherSession<'a> {
-    fn read(&mut self, mut b: &mut [u8]) -> io::Result<usize> {
-        self.reads += 1;
-        self.sess.write_tls(b.by_ref())
+    #[test]
+    fn budgeted_verifier_accepts_verification_within_budget() {
+        let verifier = BudgetedVerifier::new(
+            SlowVerifier(Duration::from_millis(0)),
+            Duration::from_secs(10),
+        );
+        assert_eq!(budgeted_handshake(verifier), Ok(()));
     }
-}
 
-impl<'a> io::Write for OtherSession<'a> {
-    fn write(&mut self, _: &[u8]) -> io::Result<usize> {
-        unreachable!()
+    #[test]
+    fn budgeted_verifier_rejects_slow_verification() {
+        let verifier = BudgetedVerifier::new(
+            SlowVerifier(Duration::from_millis(100)),
+            Duration::from_millis(10),
+        );
+        assert_eq!(
+            budgeted_handshake(verifier),
+            Err(ErrorFromPeer::Client(Error::VerificationTimedOut))
+        );
     }
 
-    fn flush(&mut self) -> io::Result<()> {
-        Ok(())
+    #[test]
+    fn budgeted_verifier_rejects_long_chain_without_verifying() {
+        let verifier = BudgetedVerifier::new(
+            SlowVerifier(Duration::from_secs(10)),
+            Duration::from_secs(1),
+        )
+        .with_max_intermediates(0);
+        assert_eq!(
+            budgeted_handshake(verifier),
+            Err(ErrorFromPeer::Client(Error::TooManyIntermediates))
+        );
     }
 
-    fn write_vectored<'b>(&mut self, b: &[io::IoSlice<'b>]) -> io::Result<usize> {
-        let mut total = 0;
-        let mut lengths = vec![];
-        for bytes in b {
-            let write_len = if self.short_writes {
-                if bytes.len() > 5 {
-                    bytes.len() / 2
-                } else {
-                    bytes.len()
-                }
-            } else {
-                bytes.len()
-            };
-
-            let l = self
-                .sess
-                .read_tls(&mut io::Cursor::new(&bytes[..write_len]))?;
-            lengths.push(l);
-            total += l;
-            if bytes.len() != l {
-                break;
+    #[test]
+    fn auditing_verifier_records_each_verification() {
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let seen = records.clone();
+        let good = AuditingVerifier::new(WebPkiVerifier::new(verifier_roots(), &[]), {
+            let seen = seen.clone();
+            move |record| {
+                seen.lock()
+                    .unwrap()
+                    .push(record.clone())
             }
+        });
+        let bad = AuditingVerifier::new(
+            WebPkiVerifier::new(RootCertStore::empty(), &[]),
+            move |record| {
+                seen.lock()
+                    .unwrap()
+                    .push(record.clone())
+            },
+        );
+
+        let chain = verifier_chain();
+        let now = std::time::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        for verifier in &[good, bad] {
+            let _ = verifier.verify_server_cert(
+                &chain[0],
+                &chain[1..],
+                &server_name("testserver.com"),
+                &mut std::iter::empty(),
+                &[],
+                now,
+            );
         }
 
-        let rc = self.sess.process_new_packets();
-        if !self.fail_ok {
-            rc.unwrap();
-        } else if rc.is_err() {
-            self.last_error = rc.err();
+        let records = records.lock().unwrap();
+        assert_eq!(records.len(), 2);
+        for record in records.iter() {
+            assert_eq!(record.time, 1_700_000_000);
+            assert_eq!(record.peer_name.as_deref(), Some("testserver.com"));
+            assert_eq!(record.subject.as_deref(), Some("CN=testserver.com"));
+            assert_eq!(
+                record.issuer.as_deref(),
+                Some("CN=ponytown verifier intermediate")
+            );
+            assert_eq!(record.not_before, Some(1_577_836_800));
+            assert_eq!(record.not_after, Some(2_524_608_000));
+            assert_eq!(
+                record.subject_alt_names,
+                vec![
+                    "DNS:testserver.com",
+                    "DNS:second.testserver.com",
+                    "DNS:localhost"
+                ]
+            );
         }
 
-        self.writevs.push(lengths);
-        Ok(total)
+        assert!(records[0].verified());
+        assert_eq!(records[0].error, None);
+        assert_eq!(
+            records[0].to_json(),
+            "{\"time\":1700000000,\"peer_name\":\"testserver.com\",\
+             \"subject\":\"CN=testserver.com\",\
+             \"issuer\":\"CN=ponytown verifier intermediate\",\
+             \"not_before\":1577836800,\"not_after\":2524608000,\
+             \"subject_alt_names\":[\"DNS:testserver.com\",\"DNS:second.testserver.com\",\
+             \"DNS:localhost\"],\"verified\":true,\"error\":null}"
+        );
+
+        let error = Error::WebPkiError(webpki::Error::UnknownIssuer, WebPkiOp::ValidateServerCert);
+        assert!(!records[1].verified());
+        assert_eq!(records[1].error, Some(error.clone()));
+        assert!(records[1]
+            .to_json()
+            .ends_with(&format!("\"verified\":false,\"error\":\"{}\"}}", error)));
+    }
+
+    #[test]
+    fn shadow_verifier_reports_divergence_from_candidate() {
+        let divergences = Arc::new(Mutex::new(Vec::new()));
+        let seen = divergences.clone();
+        let verifier = ShadowVerifier::new(
+            WebPkiVerifier::new(verifier_roots(), &[]),
+            WebPkiVerifier::new(RootCertStore::empty(), &[]),
+            move |divergence| {
+                seen.lock()
+                    .unwrap()
+                    .push(divergence.clone())
+            },
+        );
+
+        let mut client_config = make_client_config_with_verifier(Arc::new(WebPkiVerifier::new(
+            RootCertStore::empty(),
+            &[],
+        )));
+        client_config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(verifier));
+
+        // The primary's decision is used.
+        let server_config = make_verifier_server_config(&[]);
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
+
+        let divergences = divergences.lock().unwrap();
+        assert_eq!(divergences.len(), 1);
+        assert_eq!(divergences[0].server_name, server_name("localhost"));
+        assert_eq!(divergences[0].end_entity, verifier_chain()[0]);
+        assert_eq!(divergences[0].primary, Ok(()));
+        assert_eq!(
+            divergences[0].candidate,
+            Err(Error::WebPkiError(
+                webpki::Error::UnknownIssuer,
+                WebPkiOp::ValidateServerCert
+            ))
+        );
+    }
+
+    #[test]
+    fn shadow_verifier_is_silent_when_verifiers_agree() {
+        let divergences = Arc::new(AtomicUsize::new(0));
+        let seen = divergences.clone();
+        let verifier = ShadowVerifier::new(
+            WebPkiVerifier::new(verifier_roots(), &[]),
+            WebPkiVerifier::new(verifier_roots(), &[]).with_aki_ski_check(),
+            move |_| {
+                seen.fetch_add(1, Ordering::SeqCst);
+            },
+        );
+
+        let good = verifier_chain();
+        let mut bad = verifier_certs("end.cert");
+        bad.extend(verifier_certs("inter.cert"));
+        bad.reverse();
+        for chain in [good, bad] {
+            let _ = verifier.verify_server_cert(
+                &chain[0],
+                &chain[1..],
+                &server_name("testserver.com"),
+                &mut std::iter::empty(),
+                &[],
+                std::time::SystemTime::now(),
+            );
+        }
+        assert_eq!(divergences.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn report_only_verifier_reports_failures_and_continues() {
+        let failures = Arc::new(Mutex::new(Vec::new()));
+        let seen = failures.clone();
+        let verifier = ReportOnlyVerifier::new(
+            WebPkiVerifier::new(RootCertStore::empty(), &[]),
+            move |name: &ServerName, end_entity: &rustls::Certificate, err: &Error| {
+                seen.lock()
+                    .unwrap()
+                    .push((name.clone(), end_entity.clone(), err.clone()))
+            },
+        );
+
+        let mut client_config = make_client_config_with_verifier(Arc::new(WebPkiVerifier::new(
+            RootCertStore::empty(),
+            &[],
+        )));
+        client_config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(verifier));
+
+        let server_config = make_verifier_server_config(&[]);
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
+
+        let failures = failures.lock().unwrap();
+        assert_eq!(
+            *failures,
+            vec![(
+                server_name("localhost"),
+                verifier_chain()[0].clone(),
+                Error::WebPkiError(webpki::Error::UnknownIssuer, WebPkiOp::ValidateServerCert),
+            )]
+        );
+    }
+
+    #[test]
+    fn report_only_verifier_is_silent_for_valid_certificates() {
+        let failures = Arc::new(AtomicUsize::new(0));
+        let seen = failures.clone();
+        let verifier = ReportOnlyVerifier::new(
+            WebPkiVerifier::new(verifier_roots(), &[]),
+            move |_: &ServerName, _: &rustls::Certificate, _: &Error| {
+                seen.fetch_add(1, Ordering::SeqCst);
+            },
+        );
+
+        let chain = verifier_chain();
+        assert!(verifier
+            .verify_server_cert(
+                &chain[0],
+                &chain[1..],
+                &server_name("testserver.com"),
+                &mut std::iter::empty(),
+                &[],
+                std::time::SystemTime::now(),
+            )
+            .is_ok());
+        assert_eq!(failures.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn spiffe_verifier_authenticates_server() {
+        let handshake = |name: &str| {
+            let verifier = SpiffeVerifier::new("example.org", verifier_roots(), |id: &SpiffeId| {
+                id.path() == "/web"
+            });
+            let mut client_config = make_client_config(KeyType::RSA);
+            client_config
+                .dangerous()
+                .set_certificate_verifier(Arc::new(verifier));
+            let server_config = ConfigBuilder::with_safe_defaults()
+                .for_server()
+                .unwrap()
+                .with_no_client_auth()
+                .with_single_cert(verifier_chain_for(name), verifier_key(name))
+                .unwrap();
+
+            let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+            do_handshake_until_error(&mut client, &mut server)
+        };
+
+        assert_eq!(handshake("spiffe"), Ok(()));
+        assert_eq!(
+            handshake("end"),
+            Err(ErrorFromPeer::Client(Error::InvalidSpiffeId))
+        );
     }
 }
 
 #[test]
-fn server_read_returns_wouldblock_when_no_data() {
-    let (_, mut server) = make_pair(KeyType::RSA);
-    assert!(matches!(server.reader().read(&mut [0u8; 1]),
-                     Err(err) if err.kind() == io::ErrorKind::WouldBlock));
+fn client_validates_stapled_ocsp_response() {
+    let verifier = WebPkiVerifier::new(verifier_roots(), &[]).with_ocsp_policy(OcspPolicy::new());
+    let client_config = make_client_config_with_verifier(Arc::new(verifier));
+    let server_config = make_verifier_server_config(bytes_for("verifier", "end.ocsp-sha256"));
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
 }
 
 #[test]
-fn client_read_returns_wouldblock_when_no_data() {
-    let (mut client, _) = make_pair(KeyType::RSA);
-    assert!(matches!(client.reader().read(&mut [0u8; 1]),
-                     Err(err) if err.kind() == io::ErrorKind::WouldBlock));
+fn client_rejects_ocsp_response_with_disallowed_signature_algorithm() {
+    let server_config = make_verifier_server_config(bytes_for("verifier", "end.ocsp-sha1"));
+    let verifier = WebPkiVerifier::new(verifier_roots(), &[]).with_ocsp_policy(OcspPolicy::new());
+    let client_config = make_client_config_with_verifier(Arc::new(verifier));
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Client(
+            Error::OcspSignatureAlgorithmNotAllowed(SignatureScheme::RSA_PKCS1_SHA1)
+        ))
+    );
+
+    // A strict policy can also rule out algorithms webpki supports.
+    let server_config = make_verifier_server_config(bytes_for("verifier", "end.ocsp-sha256"));
+    let verifier = WebPkiVerifier::new(verifier_roots(), &[]).with_ocsp_policy(OcspPolicy {
+        signature_schemes: Some(vec![SignatureScheme::ECDSA_NISTP384_SHA384]),
+        ..OcspPolicy::new()
+    });
+    let client_config = make_client_config_with_verifier(Arc::new(verifier));
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Client(
+            Error::OcspSignatureAlgorithmNotAllowed(SignatureScheme::RSA_PKCS1_SHA256)
+        ))
+    );
 }
 
 #[test]
-fn new_server_returns_initial_io_state() {
-    let (_, mut server) = make_pair(KeyType::RSA);
-    let io_state = server.process_new_packets().unwrap();
-    println!("IoState is Debug {:?}", io_state);
-    assert_eq!(io_state.plaintext_bytes_to_read(), 0);
-    assert_eq!(io_state.peer_has_closed(), false);
-    assert_eq!(io_state.tls_bytes_to_write(), 0);
+fn client_holds_ocsp_response_to_chain_signature_algorithms() {
+    let verifier = || {
+        WebPkiVerifier::new(verifier_roots(), &[])
+            .with_signature_algorithms(RSA_PKCS1_SHA256_ONLY)
+            .with_ocsp_policy(OcspPolicy::new())
+    };
+
+    let server_config = make_verifier_server_config(bytes_for("verifier", "end.ocsp-sha256"));
+    let client_config = make_client_config_with_verifier(Arc::new(verifier()));
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
+
+    let server_config = make_verifier_server_config(bytes_for("verifier", "end.ocsp-sha384"));
+    let client_config = make_client_config_with_verifier(Arc::new(verifier()));
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Client(
+            Error::OcspSignatureAlgorithmNotAllowed(SignatureScheme::RSA_PKCS1_SHA384)
+        ))
+    );
 }
 
 #[test]
-fn new_client_returns_initial_io_state() {
-    let (mut client, _) = make_pair(KeyType::RSA);
-    let io_state = client.process_new_packets().unwrap();
-    println!("IoState is Debug {:?}", io_state);
-    assert_eq!(io_state.plaintext_bytes_to_read(), 0);
-    assert_eq!(io_state.peer_has_closed(), false);
-    assert!(io_state.tls_bytes_to_write() > 200);
+fn client_applies_policy_to_unknown_ocsp_status() {
+    // Only a status accepted with a warning is a soft failure.
+    let treatments = [
+        (OcspUnknownStatus::Accept, Ok(()), 0),
+        (OcspUnknownStatus::Warn, Ok(()), 1),
+        (
+            OcspUnknownStatus::Reject,
+            Err(ErrorFromPeer::Client(Error::OcspStatusUnknown)),
+            0,
+        ),
+    ];
+
+    for (unknown_status, expected, soft_failures) in treatments.iter() {
+        let observed = Arc::new(AtomicUsize::new(0));
+        let observed_clone = Arc::clone(&observed);
+        let verifier = WebPkiVerifier::new(verifier_roots(), &[])
+            .with_ocsp_policy(OcspPolicy {
+                unknown_status: *unknown_status,
+                ..OcspPolicy::new()
+            })
+            .with_ocsp_soft_fail(move |_, err| {
+                assert_eq!(err, &Error::OcspStatusUnknown);
+                observed_clone.fetch_add(1, Ordering::SeqCst);
+            });
+        let client_config = make_client_config_with_verifier(Arc::new(verifier));
+        let server_config = make_verifier_server_config(bytes_for("verifier", "end.ocsp-unknown"));
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        assert_eq!(
+            &do_handshake_until_error(&mut client, &mut server),
+            expected
+        );
+        assert_eq!(observed.load(Ordering::SeqCst), *soft_failures);
+    }
 }
 
 #[test]
-fn client_complete_io_for_handshake() {
-    let (mut client, mut server) = make_pair(KeyType::RSA);
+fn client_matches_ocsp_response_by_issuer_and_serial() {
+    // The response first says the status of end.cert's serial number
+    // under another issuer is unknown, then that end.cert is good.
+    let verifier = WebPkiVerifier::new(verifier_roots(), &[]).with_ocsp_policy(OcspPolicy {
+        unknown_status: OcspUnknownStatus::Reject,
+        ..OcspPolicy::new()
+    });
+    let client_config = make_client_config_with_verifier(Arc::new(verifier));
+    let server_config = make_verifier_server_config(bytes_for(
+        "verifier",
+        "end.ocsp-other-issuer-first",
+    ));
 
-    assert_eq!(true, client.is_handshaking());
-    let (rdlen, wrlen) = client
-        .complete_io(&mut OtherSession::new(&mut server))
-        .unwrap();
-    assert!(rdlen > 0 && wrlen > 0);
-    assert_eq!(false, client.is_handshaking());
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
 }
 
 #[test]
-fn client_complete_io_for_handshake_eof() {
-    let (mut client, _) = make_pair(KeyType::RSA);
-    let mut input = io::Cursor::new(Vec::new());
-
-    assert_eq!(true, client.is_handshaking());
-    let err = client
-        .complete_io(&mut input)
-        .unwrap_err();
-    assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+fn client_finds_ocsp_issuer_by_key_not_just_name() {
+    // test-ca/verifier/inter-same-name.cert has the same name as
+    // end.cert's issuer, but a different key.
+    let mut chain = verifier_chain();
+    chain.insert(1, verifier_certs("inter-same-name.cert").remove(0));
+
+    let verified = WebPkiVerifier::new(verifier_roots(), &[])
+        .with_ocsp_policy(OcspPolicy::new())
+        .verify_server_cert_detailed(
+            &chain[0],
+            &chain[1..],
+            &server_name("testserver.com"),
+            &mut std::iter::empty(),
+            bytes_for("verifier", "end.ocsp-sha256"),
+            std::time::SystemTime::now(),
+        )
+        .unwrap();
+    assert_eq!(
+        verified.revocation,
+        RevocationStatus::Checked(CertStatus::Good)
+    );
 }
 
 #[test]
-fn client_complete_io_for_write() {
-    for kt in ALL_KEY_TYPES.iter() {
-        let (mut client, mut server) = make_pair(*kt);
+fn client_accepts_ocsp_response_from_delegated_responder() {
+    let verify = |require_responder_nocheck: bool, response: &str| {
+        let verifier = WebPkiVerifier::new(verifier_roots(), &[]).with_ocsp_policy(OcspPolicy {
+            require_responder_nocheck,
+            ..OcspPolicy::new()
+        });
+        let client_config = make_client_config_with_verifier(Arc::new(verifier));
+        let server_config = make_verifier_server_config(bytes_for("verifier", response));
 
-        do_handshake(&mut client, &mut server);
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake_until_error(&mut client, &mut server)
+    };
 
-        client
-            .writer()
-            .write(b"01234567890123456789")
-            .unwrap();
-        client
-            .writer()
-            .write(b"01234567890123456789")
-            .unwrap();
-        {
-            let mut pipe = OtherSession::new(&mut server);
-            let (rdlen, wrlen) = client.complete_io(&mut pipe).unwrap();
-            assert!(rdlen == 0 && wrlen > 0);
-            println!("{:?}", pipe.writevs);
-            assert_eq!(pipe.writevs, vec![vec![42, 42]]);
-        }
-        check_read(
-            &mut server.reader(),
-            b"0123456789012345678901234567890123456789",
-        );
-    }
+    assert_eq!(verify(false, "end.ocsp-responder"), Ok(()));
+    assert_eq!(verify(false, "end.ocsp-responder-nocheck"), Ok(()));
+    assert_eq!(verify(true, "end.ocsp-responder-nocheck"), Ok(()));
+    assert_eq!(
+        verify(true, "end.ocsp-responder"),
+        Err(ErrorFromPeer::Client(Error::InvalidOcspResponse(
+            "responder certificate lacks id-pkix-ocsp-nocheck".to_string()
+        )))
+    );
+
+    // This response carries end.ocsp-responder's certificate, then a
+    // renewal of it with id-pkix-ocsp-nocheck.
+    assert_eq!(verify(true, "end.ocsp-responder-renewed"), Ok(()));
 }
 
 #[test]
-fn client_complete_io_for_read() {
-    for kt in ALL_KEY_TYPES.iter() {
-        let (mut client, mut server) = make_pair(*kt);
+fn client_reports_ocsp_soft_failures_to_observer() {
+    let failures = Arc::new(Mutex::new(Vec::new()));
+    let verifier = {
+        let failures = failures.clone();
+        WebPkiVerifier::new(verifier_roots(), &[])
+            .with_ocsp_policy(OcspPolicy::new())
+            .with_ocsp_soft_fail(move |end_entity, err| {
+                failures
+                    .lock()
+                    .unwrap()
+                    .push((end_entity.clone(), err.clone()))
+            })
+    };
+    let verifier = Arc::new(verifier);
+
+    let responses: [&[u8]; 3] = [
+        b"\x30\x03\x0a\x01\x00",
+        bytes_for("verifier", "end.ocsp-unknown"),
+        bytes_for("verifier", "end.ocsp-sha256"),
+    ];
+    for response in responses.iter() {
+        let client_config = make_client_config_with_verifier(verifier.clone());
+        let server_config = make_verifier_server_config(response);
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
+    }
 
-        do_handshake(&mut client, &mut server);
+    let end_entity = verifier_chain()[0].clone();
+    assert_eq!(
+        *failures.lock().unwrap(),
+        vec![
+            (
+                end_entity.clone(),
+                Error::InvalidOcspResponse("malformed response".to_string())
+            ),
+            (end_entity, Error::OcspStatusUnknown),
+        ]
+    );
 
-        server
-            .writer()
-            .write(b"01234567890123456789")
-            .unwrap();
-        {
-            let mut pipe = OtherSession::new(&mut server);
-            let (rdlen, wrlen) = client.complete_io(&mut pipe).unwrap();
-            assert!(rdlen > 0 && wrlen == 0);
-            assert_eq!(pipe.reads, 1);
-        }
-        check_read(&mut client.reader(), b"01234567890123456789");
-    }
+    // Without soft-fail, the malformed response is fatal.
+    let verifier = WebPkiVerifier::new(verifier_roots(), &[]).with_ocsp_policy(OcspPolicy::new());
+    let client_config = make_client_config_with_verifier(Arc::new(verifier));
+    let server_config = make_verifier_server_config(responses[0]);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Client(Error::InvalidOcspResponse(
+            "malformed response".to_string()
+        )))
+    );
 }
 
 #[test]
-fn server_complete_io_for_handshake() {
-    for kt in ALL_KEY_TYPES.iter() {
-        let (mut client, mut server) = make_pair(*kt);
+fn client_ignores_stapled_ocsp_response_without_policy() {
+    let verifier = WebPkiVerifier::new(verifier_roots(), &[]);
+    let client_config = make_client_config_with_verifier(Arc::new(verifier));
+    let server_config = make_verifier_server_config(bytes_for("verifier", "end.ocsp-sha1"));
 
-        assert_eq!(true, server.is_handshaking());
-        let (rdlen, wrlen) = server
-            .complete_io(&mut OtherSession::new(&mut client))
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
+}
+
+#[test]
+fn webpki_verifier_reports_revocation_status() {
+    let with_policy =
+        WebPkiVerifier::new(verifier_roots(), &[]).with_ocsp_policy(OcspPolicy::new());
+    let accepting_unknown =
+        WebPkiVerifier::new(verifier_roots(), &[]).with_ocsp_policy(OcspPolicy {
+            unknown_status: OcspUnknownStatus::Accept,
+            ..OcspPolicy::new()
+        });
+    let without_policy = WebPkiVerifier::new(verifier_roots(), &[]);
+    let cases = [
+        (
+            &with_policy,
+            "end.ocsp-sha256",
+            RevocationStatus::Checked(CertStatus::Good),
+        ),
+        (
+            &with_policy,
+            "end.ocsp-unknown",
+            RevocationStatus::SoftFailed,
+        ),
+        (
+            &accepting_unknown,
+            "end.ocsp-unknown",
+            RevocationStatus::Checked(CertStatus::Unknown),
+        ),
+        (
+            &without_policy,
+            "end.ocsp-sha256",
+            RevocationStatus::NotChecked,
+        ),
+    ];
+
+    let chain = verifier_chain();
+    let server_name = server_name("testserver.com");
+    for (verifier, ocsp, expected) in cases.iter() {
+        let verified = verifier
+            .verify_server_cert_detailed(
+                &chain[0],
+                &chain[1..],
+                &server_name,
+                &mut std::iter::empty(),
+                bytes_for("verifier", ocsp),
+                std::time::SystemTime::now(),
+            )
             .unwrap();
-        assert!(rdlen > 0 && wrlen > 0);
-        assert_eq!(false, server.is_handshaking());
+        assert_eq!(verified.revocation, *expected);
     }
+
+    let verified = with_policy
+        .verify_server_cert_detailed(
+            &chain[0],
+            &chain[1..],
+            &server_name,
+            &mut std::iter::empty(),
+            &[],
+            std::time::SystemTime::now(),
+        )
+        .unwrap();
+    assert_eq!(verified.revocation, RevocationStatus::NotChecked);
 }
 
+static TEST_CT_LOG: sct::Log = sct::Log {
+    description: "test log",
+    url: "ct.example.com",
+    operated_by: "test",
+    key: &[],
+    id: [0u8; 32],
+    max_merge_delay: 0,
+};
+
 #[test]
-fn server_complete_io_for_handshake_eof() {
-    let (_, mut server) = make_pair(KeyType::RSA);
-    let mut input = io::Cursor::new(Vec::new());
+fn webpki_verifier_limits_scts_processed() {
+    static LOGS: &[&sct::Log] = &[&TEST_CT_LOG];
+    // Distinct SCTs of an unsupported version, which are ignored rather
+    // than fatal.
+    let unsupported_sct = |i: u16| [&[1], &i.to_be_bytes()[..]].concat();
+
+    let chain = verifier_chain();
+    let server_name = server_name("testserver.com");
+    let verify = |verifier: &WebPkiVerifier, sct_count| {
+        let scts: Vec<Vec<u8>> = (0..sct_count)
+            .map(unsupported_sct)
+            .collect();
+        verifier.verify_server_cert_detailed(
+            &chain[0],
+            &chain[1..],
+            &server_name,
+            &mut scts.iter().map(|sct| sct.as_slice()),
+            &[],
+            std::time::SystemTime::now(),
+        )
+    };
 
-    assert_eq!(true, server.is_handshaking());
-    let err = server
-        .complete_io(&mut input)
-        .unwrap_err();
-    assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+    let verifier = WebPkiVerifier::new(verifier_roots(), LOGS);
+    assert_eq!(
+        verify(&verifier, 32),
+        Err(Error::InvalidSct(sct::Error::UnsupportedSctVersion))
+    );
+    assert_eq!(verify(&verifier, 1000), Err(Error::TooManyScts));
+
+    let verifier = verifier.with_ct_policy(CtPolicy {
+        max_scts: 4,
+        ..CtPolicy::new()
+    });
+    assert_eq!(
+        verify(&verifier, 4),
+        Err(Error::InvalidSct(sct::Error::UnsupportedSctVersion))
+    );
+    assert_eq!(verify(&verifier, 5), Err(Error::TooManyScts));
 }
 
+// The log which issued the SCT embedded in test-ca/verifier/end-sct.cert.
+static VERIFIER_CT_LOG: sct::Log = sct::Log {
+    description: "test-ca/verifier log",
+    url: "ct.testserver.com",
+    operated_by: "test",
+    key: include_bytes!("../../test-ca/verifier/ct-log.pub"),
+    id: *include_bytes!("../../test-ca/verifier/ct-log.id"),
+    max_merge_delay: 0,
+};
+
 #[test]
-fn server_complete_io_for_write() {
-    for kt in ALL_KEY_TYPES.iter() {
-        let (mut client, mut server) = make_pair(*kt);
+fn webpki_verifier_checks_embedded_scts() {
+    let server_name = server_name("testserver.com");
+    let verify = |name, logs| {
+        let chain = verifier_chain_for(name);
+        WebPkiVerifier::new(verifier_roots(), logs)
+            .verify_server_cert_detailed(
+                &chain[0],
+                &chain[1..],
+                &server_name,
+                &mut std::iter::empty(),
+                &[],
+                std::time::SystemTime::now(),
+            )
+            .map(|_| ())
+    };
 
-        do_handshake(&mut client, &mut server);
+    static LOGS: &[&sct::Log] = &[&VERIFIER_CT_LOG];
+    assert_eq!(verify("end-sct", LOGS), Ok(()));
+    assert_eq!(verify("end", LOGS), Ok(()), "no SCTs to check");
 
-        server
-            .writer()
-            .write(b"01234567890123456789")
-            .unwrap();
-        server
-            .writer()
-            .write(b"01234567890123456789")
-            .unwrap();
-        {
-            let mut pipe = OtherSession::new(&mut client);
-            let (rdlen, wrlen) = server.complete_io(&mut pipe).unwrap();
-            assert!(rdlen == 0 && wrlen > 0);
-            assert_eq!(pipe.writevs, vec![vec![42, 42]]);
-        }
-        check_read(
-            &mut client.reader(),
-            b"0123456789012345678901234567890123456789",
-        );
-    }
+    static OTHER_LOGS: &[&sct::Log] = &[&TEST_CT_LOG];
+    assert_eq!(
+        verify("end-sct", OTHER_LOGS),
+        Err(Error::InvalidSct(sct::Error::UnknownLog))
+    );
 }
 
+#[cfg(feature = "ct_log_lists")]
 #[test]
-fn server_complete_io_for_read() {
-    for kt in ALL_KEY_TYPES.iter() {
-        let (mut client, mut server) = make_pair(*kt);
+fn webpki_verifier_ct_logs_can_be_loaded_and_replaced() {
+    use rustls::OwnedCtLog;
+
+    const LOG_LIST: &str = r#"{
+        "version": "1.0",
+        "operators": [
+            {
+                "name": "ponytown",
+                "email": ["ct@testserver.com"],
+                "logs": [
+                    {
+                        "description": "test-ca/verifier log",
+                        "log_id": "LOG_ID",
+                        "key": "KEY",
+                        "url": "https://ct.testserver.com/",
+                        "mmd": 86400,
+                        "state": { "usable": { "timestamp": "2020-01-01T00:00:00Z" } }
+                    },
+                    {
+                        "description": "rejected log",
+                        "log_id": "LOG_ID",
+                        "key": "KEY",
+                        "url": "https://rejected.testserver.com/",
+                        "mmd": 86400,
+                        "state": { "rejected": { "timestamp": "2020-01-01T00:00:00Z" } }
+                    }
+                ]
+            }
+        ]
+    }"#;
+
+    // A P-256 SubjectPublicKeyInfo, less the key itself.
+    let mut spki = vec![
+        0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x08,
+        0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00,
+    ];
+    spki.extend_from_slice(VERIFIER_CT_LOG.key);
+    let log_list = LOG_LIST
+        .replace("LOG_ID", &base64::encode(VERIFIER_CT_LOG.id))
+        .replace("KEY", &base64::encode(&spki));
+
+    let logs = OwnedCtLog::from_log_list_json(&log_list).unwrap();
+    assert_eq!(
+        logs,
+        vec![OwnedCtLog {
+            operated_by: "ponytown".to_string(),
+            url: "https://ct.testserver.com/".to_string(),
+            max_merge_delay: 86400,
+            ..OwnedCtLog::from_log(&VERIFIER_CT_LOG)
+        }]
+    );
 
-        do_handshake(&mut client, &mut server);
+    let chain = verifier_chain_for("end-sct");
+    let server_name = server_name("testserver.com");
+    let verify = |verifier: &WebPkiVerifier| {
+        verifier
+            .verify_server_cert_detailed(
+                &chain[0],
+                &chain[1..],
+                &server_name,
+                &mut std::iter::empty(),
+                &[],
+                std::time::SystemTime::now(),
+            )
+            .map(|_| ())
+    };
 
-        client
-            .writer()
-            .write(b"01234567890123456789")
-            .unwrap();
-        {
-            let mut pipe = OtherSession::new(&mut client);
-            let (rdlen, wrlen) = server.complete_io(&mut pipe).unwrap();
-            assert!(rdlen > 0 && wrlen == 0);
-            assert_eq!(pipe.reads, 1);
-        }
-        check_read(&mut server.reader(), b"01234567890123456789");
-    }
-}
+    let verifier = WebPkiVerifier::new_with_ct_logs(verifier_roots(), logs.into());
+    assert_eq!(verify(&verifier), Ok(()));
 
-#[test]
-fn client_stream_write() {
-    for kt in ALL_KEY_TYPES.iter() {
-        let (mut client, mut server) = make_pair(*kt);
+    verifier.replace_ct_logs(vec![OwnedCtLog::from_log(&TEST_CT_LOG)].into());
+    assert_eq!(
+        verify(&verifier),
+        Err(Error::InvalidSct(sct::Error::UnknownLog))
+    );
+    assert_eq!(verifier.policy_summary().ct_log_count, 1);
 
-        {
-            let mut pipe = OtherSession::new(&mut server);
-            let mut stream = Stream::new(&mut client, &mut pipe);
-            assert_eq!(stream.write(b"hello").unwrap(), 5);
-        }
-        check_read(&mut server.reader(), b"hello");
-    }
+    assert!(matches!(
+        OwnedCtLog::from_log_list_json("{"),
+        Err(Error::InvalidCtLogList(_))
+    ));
+    assert!(matches!(
+        OwnedCtLog::from_log_list_json(&log_list.replacen(&base64::encode(&spki), "AAAA", 1)),
+        Err(Error::InvalidCtLogList(_))
+    ));
 }
 
 #[test]
-fn client_streamowned_write() {
-    for kt in ALL_KEY_TYPES.iter() {
-        let (client, mut server) = make_pair(*kt);
+fn webpki_verifier_counts_duplicate_scts_once() {
+    static LOGS: &[&sct::Log] = &[&TEST_CT_LOG];
+    // test-ca/verifier/end-sct-unsupported.cert embeds this SCT, of an
+    // unsupported version.
+    let embedded_sct: &[u8] = &[1];
+    let other_sct: &[u8] = &[2];
+
+    let chain = verifier_chain_for("end-sct-unsupported");
+    let server_name = server_name("testserver.com");
+    let verifier = WebPkiVerifier::new(verifier_roots(), LOGS).with_ct_policy(CtPolicy {
+        max_scts: 1,
+        ..CtPolicy::new()
+    });
+    let verify = |scts: &[&[u8]]| {
+        verifier.verify_server_cert_detailed(
+            &chain[0],
+            &chain[1..],
+            &server_name,
+            &mut scts.iter().copied(),
+            &[],
+            std::time::SystemTime::now(),
+        )
+    };
 
-        {
-            let pipe = OtherSession::new(&mut server);
-            let mut stream = StreamOwned::new(client, pipe);
-            assert_eq!(stream.write(b"hello").unwrap(), 5);
-        }
-        check_read(&mut server.reader(), b"hello");
-    }
+    assert_eq!(
+        verify(&[]),
+        Err(Error::InvalidSct(sct::Error::UnsupportedSctVersion))
+    );
+    assert_eq!(
+        verify(&[embedded_sct]),
+        Err(Error::InvalidSct(sct::Error::UnsupportedSctVersion))
+    );
+    assert_eq!(verify(&[other_sct]), Err(Error::TooManyScts));
 }
 
 #[test]
-fn client_stream_read() {
-    for kt in ALL_KEY_TYPES.iter() {
-        let (mut client, mut server) = make_pair(*kt);
+fn webpki_verifier_enforces_ct_policy() {
+    static LOGS: &[&sct::Log] = &[&VERIFIER_CT_LOG];
+    let server_name = server_name("testserver.com");
+    let verify = |name, policy| {
+        let chain = verifier_chain_for(name);
+        WebPkiVerifier::new(verifier_roots(), LOGS)
+            .with_ct_policy(policy)
+            .verify_server_cert_detailed(
+                &chain[0],
+                &chain[1..],
+                &server_name,
+                &mut std::iter::empty(),
+                &[],
+                std::time::SystemTime::now(),
+            )
+            .map(|_| ())
+    };
 
-        server.writer().write(b"world").unwrap();
+    let one_operator = CtPolicy {
+        min_operators: 1,
+        ..CtPolicy::new()
+    };
+    assert_eq!(verify("end-sct", one_operator.clone()), Ok(()));
+    assert_eq!(
+        verify("end", one_operator.clone()),
+        Err(Error::CtPolicyNotSatisfied)
+    );
 
-        {
-            let mut pipe = OtherSession::new(&mut server);
-            let mut stream = Stream::new(&mut client, &mut pipe);
-            check_read(&mut stream, b"world");
-        }
-    }
+    let embedded = CtPolicy {
+        require_embedded: true,
+        ..CtPolicy::new()
+    };
+    assert_eq!(verify("end-sct", embedded.clone()), Ok(()));
+    assert_eq!(verify("end", embedded), Err(Error::CtPolicyNotSatisfied));
+
+    let two_operators = CtPolicy {
+        min_operators: 2,
+        ..CtPolicy::new()
+    };
+    assert_eq!(
+        verify("end-sct", two_operators),
+        Err(Error::CtPolicyNotSatisfied)
+    );
+
+    // The test certificates were issued in 2020.
+    let enforced_from = |time| CtPolicy {
+        enforced_from: Some(time),
+        ..one_operator.clone()
+    };
+    assert_eq!(
+        verify("end", enforced_from(std::time::UNIX_EPOCH)),
+        Err(Error::CtPolicyNotSatisfied)
+    );
+    assert_eq!(
+        verify("end", enforced_from(std::time::SystemTime::now())),
+        Ok(())
+    );
 }
 
 #[test]
-fn client_streamowned_read() {
-    for kt in ALL_KEY_TYPES.iter() {
-        let (client, mut server) = make_pair(*kt);
-
-        server.writer().write(b"world").unwrap();
+fn webpki_verifier_limits_validity_of_recent_certificates() {
+    // test-ca/verifier/end.cert is valid from 2020 to 2050.
+    let before_issue = std::time::UNIX_EPOCH + Duration::from_secs(1567296000); // 2019-09-01
+    let after_issue = std::time::UNIX_EPOCH + Duration::from_secs(1598918400); // 2020-09-01
+
+    let chain = verifier_chain();
+    let server_name = server_name("testserver.com");
+    let verify = |policy: ValidityPolicy| {
+        WebPkiVerifier::new(verifier_roots(), &[])
+            .with_validity_policy(policy)
+            .verify_server_cert_detailed(
+                &chain[0],
+                &chain[1..],
+                &server_name,
+                &mut std::iter::empty(),
+                &[],
+                std::time::SystemTime::now(),
+            )
+            .map(|_| ())
+    };
 
-        {
-            let pipe = OtherSession::new(&mut server);
-            let mut stream = StreamOwned::new(client, pipe);
-            check_read(&mut stream, b"world");
-        }
-    }
+    assert_eq!(
+        verify(ValidityPolicy::new(before_issue)),
+        Err(Error::CertificateValidityTooLong)
+    );
+    assert_eq!(verify(ValidityPolicy::new(after_issue)), Ok(()));
+    assert_eq!(
+        verify(ValidityPolicy {
+            max_validity: Duration::from_secs(40 * 365 * 24 * 60 * 60),
+            ..ValidityPolicy::new(before_issue)
+        }),
+        Ok(())
+    );
 }
 
 #[test]
-fn server_stream_write() {
-    for kt in ALL_KEY_TYPES.iter() {
-        let (mut client, mut server) = make_pair(*kt);
+fn client_rejects_server_key_of_disallowed_algorithm() {
+    let verifier = WebPkiVerifier::new(verifier_roots(), &[])
+        .with_allowed_key_algorithms(&[SignatureAlgorithm::ECDSA]);
+    let client_config = make_client_config_with_verifier(Arc::new(verifier));
+    let server_config = make_verifier_server_config(&[]);
 
-        {
-            let mut pipe = OtherSession::new(&mut client);
-            let mut stream = Stream::new(&mut server, &mut pipe);
-            assert_eq!(stream.write(b"hello").unwrap(), 5);
-        }
-        check_read(&mut client.reader(), b"hello");
-    }
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Client(Error::DisallowedKeyAlgorithm))
+    );
 }
 
 #[test]
-fn server_streamowned_write() {
-    for kt in ALL_KEY_TYPES.iter() {
-        let (mut client, server) = make_pair(*kt);
+fn client_accepts_server_key_of_allowed_algorithm() {
+    let verifier = WebPkiVerifier::new(verifier_roots(), &[])
+        .with_allowed_key_algorithms(&[SignatureAlgorithm::ECDSA, SignatureAlgorithm::RSA]);
+    let client_config = make_client_config_with_verifier(Arc::new(verifier));
+    let server_config = make_verifier_server_config(&[]);
 
-        {
-            let pipe = OtherSession::new(&mut client);
-            let mut stream = StreamOwned::new(server, pipe);
-            assert_eq!(stream.write(b"hello").unwrap(), 5);
-        }
-        check_read(&mut client.reader(), b"hello");
-    }
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
 }
 
-#[test]
-fn server_stream_read() {
-    for kt in ALL_KEY_TYPES.iter() {
-        let (mut client, mut server) = make_pair(*kt);
+static RSA_PKCS1_SHA256_ONLY: &[&webpki::SignatureAlgorithm] =
+    &[&webpki::RSA_PKCS1_2048_8192_SHA256];
+static ECDSA_P384_ONLY: &[&webpki::SignatureAlgorithm] = &[&webpki::ECDSA_P384_SHA384];
 
-        client.writer().write(b"world").unwrap();
+#[test]
+fn client_restricts_server_chain_signature_algorithms() {
+    let handshake = |algorithms| {
+        let verifier =
+            WebPkiVerifier::new(verifier_roots(), &[]).with_signature_algorithms(algorithms);
+        let client_config = make_client_config_with_verifier(Arc::new(verifier));
+        let server_config = make_verifier_server_config(&[]);
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake_until_error(&mut client, &mut server)
+    };
 
-        {
-            let mut pipe = OtherSession::new(&mut client);
-            let mut stream = Stream::new(&mut server, &mut pipe);
-            check_read(&mut stream, b"world");
-        }
-    }
+    assert_eq!(handshake(RSA_PKCS1_SHA256_ONLY), Ok(()));
+    assert_eq!(
+        handshake(ECDSA_P384_ONLY),
+        Err(ErrorFromPeer::Client(Error::WebPkiError(
+            webpki::Error::UnknownIssuer,
+            WebPkiOp::ValidateServerCert
+        )))
+    );
 }
 
 #[test]
-fn server_streamowned_read() {
-    for kt in ALL_KEY_TYPES.iter() {
-        let (mut client, server) = make_pair(*kt);
+fn server_restricts_client_chain_signature_algorithms() {
+    let handshake = |algorithms| {
+        let verifier = AllowAnyAuthenticatedClient::new_with_signature_algorithms(
+            verifier_roots(),
+            algorithms,
+        );
+        let server_config = make_verifier_server_config_with_client_verifier(verifier);
+        let client_config = make_verifier_client_config_with_auth("client");
 
-        client.writer().write(b"world").unwrap();
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake_until_error(&mut client, &mut server)
+    };
 
-        {
-            let pipe = OtherSession::new(&mut client);
-            let mut stream = StreamOwned::new(server, pipe);
-            check_read(&mut stream, b"world");
-        }
-    }
+    assert_eq!(handshake(RSA_PKCS1_SHA256_ONLY), Ok(()));
+    assert_eq!(
+        handshake(ECDSA_P384_ONLY),
+        Err(ErrorFromPeer::Server(Error::WebPkiError(
+            webpki::Error::UnknownIssuer,
+            WebPkiOp::ValidateClientCert
+        )))
+    );
 }
 
-struct FailsWrites {
-    errkind: io::ErrorKind,
-    after: usize,
-}
+#[test]
+fn webpki_verifier_builder_restricts_signature_schemes() {
+    let handshake = |schemes: &[SignatureScheme]| {
+        let verifier = WebPkiVerifier::builder(verifier_roots())
+            .with_signature_schemes(schemes)
+            .build();
+        let client_config = make_client_config_with_verifier(Arc::new(verifier));
+        let server_config = make_verifier_server_config(&[]);
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake_until_error(&mut client, &mut server)
+    };
 
-impl io::Read for FailsWrites {
-    fn read(&mut self, _b: &mut [u8]) -> io::Result<usize> {
-        Ok(0)
-    }
+    assert_eq!(handshake(&[SignatureScheme::RSA_PSS_SHA384]), Ok(()));
+    assert_eq!(
+        handshake(&[SignatureScheme::ECDSA_NISTP256_SHA256]),
+        Err(ErrorFromPeer::Server(Error::PeerIncompatibleError(
+            "no overlapping sigschemes".into()
+        )))
+    );
 }
 
-impl io::Write for FailsWrites {
-    fn write(&mut self, b: &[u8]) -> io::Result<usize> {
-        if self.after > 0 {
-            self.after -= 1;
-            Ok(b.len())
-        } else {
-            Err(io::Error::new(self.errkind, "oops"))
-        }
-    }
+#[test]
+fn client_verifier_accepts_matching_email_identity() {
+    let verifier =
+        AllowAnyAuthenticatedClient::new_requiring_email(verifier_roots(), "@EXAMPLE.com");
+    let server_config = make_verifier_server_config_with_client_verifier(verifier.clone());
+    let client_config = make_verifier_client_config_with_auth("client-email");
 
-    fn flush(&mut self) -> io::Result<()> {
-        Ok(())
-    }
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
+
+    let peer = &server.peer_certificates().unwrap()[0];
+    assert_eq!(
+        verifier.email_identity(peer),
+        Some("alice@example.com".to_string())
+    );
 }
 
 #[test]
-fn stream_write_reports_underlying_io_error_before_plaintext_processed() {
-    let (mut client, mut server) = make_pair(KeyType::RSA);
-    do_handshake(&mut client, &mut server);
+fn client_verifier_rejects_email_identity_in_wrong_domain() {
+    let verifier =
+        AllowAnyAuthenticatedClient::new_requiring_email(verifier_roots(), "@example.com");
+    let server_config = make_verifier_server_config_with_client_verifier(verifier);
+    let client_config = make_verifier_client_config_with_auth("client-email-other");
 
-    let mut pipe = FailsWrites {
-        errkind: io::ErrorKind::ConnectionAborted,
-        after: 0,
-    };
-    client.writer().write(b"hello").unwrap();
-    let mut client_stream = Stream::new(&mut client, &mut pipe);
-    let rc = client_stream.write(b"world");
-    assert!(rc.is_err());
-    let err = rc.err().unwrap();
-    assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::EmailIdentityMismatch))
+    );
 }
 
 #[test]
-fn stream_write_swallows_underlying_io_error_after_plaintext_processed() {
-    let (mut client, mut server) = make_pair(KeyType::RSA);
-    do_handshake(&mut client, &mut server);
+fn client_verifier_rejects_missing_email_identity() {
+    let verifier =
+        AllowAnyAuthenticatedClient::new_requiring_email(verifier_roots(), "alice@example.com");
+    let server_config = make_verifier_server_config_with_client_verifier(verifier.clone());
+    let client_config = make_verifier_client_config_with_auth("client");
 
-    let mut pipe = FailsWrites {
-        errkind: io::ErrorKind::ConnectionAborted,
-        after: 1,
-    };
-    client.writer().write(b"hello").unwrap();
-    let mut client_stream = Stream::new(&mut client, &mut pipe);
-    let rc = client_stream.write(b"world");
-    assert_eq!(format!("{:?}", rc), "Ok(5)");
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::EmailIdentityMismatch))
+    );
+
+    let client_cert = &verifier_certs("client.cert")[0];
+    assert_eq!(verifier.email_identity(client_cert), None);
 }
 
-fn make_disjoint_suite_configs() -> (ClientConfig, ServerConfig) {
-    let kt = KeyType::RSA;
-    let mut server_config = make_server_config(kt);
-    server_config.cipher_suites = vec![find_suite(
-        CipherSuite::TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
-    )];
+fn make_spiffe_server_config(verifier: SpiffeVerifier) -> ServerConfig {
+    ConfigBuilder::with_safe_defaults()
+        .for_server()
+        .unwrap()
+        .with_client_cert_verifier(Arc::new(verifier))
+        .with_single_cert(verifier_chain(), verifier_key("end"))
+        .unwrap()
+}
 
-    let mut client_config = make_client_config(kt);
-    client_config.cipher_suites = vec![find_suite(
-        CipherSuite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
-    )];
+#[test]
+fn spiffe_verifier_accepts_authorized_client() {
+    let verifier = SpiffeVerifier::new("example.org", verifier_roots(), |id: &SpiffeId| {
+        id.path() == "/web"
+    });
+    let server_config = make_spiffe_server_config(verifier);
+    let client_config = make_verifier_client_config_with_auth("spiffe");
 
-    (client_config, server_config)
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
+
+    let id = SpiffeId::from_certificate(&server.peer_certificates().unwrap()[0]).unwrap();
+    assert_eq!(id.as_str(), "spiffe://example.org/web");
+    assert_eq!(id.trust_domain(), "example.org");
+    assert_eq!(id.path(), "/web");
 }
 
 #[test]
-fn client_stream_handshake_error() {
-    let (client_config, server_config) = make_disjoint_suite_configs();
-    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+fn spiffe_verifier_rejects_unauthorized_client() {
+    let verifier = SpiffeVerifier::new("example.org", verifier_roots(), |id: &SpiffeId| {
+        id.path() == "/db"
+    });
+    let server_config = make_spiffe_server_config(verifier);
+    let client_config = make_verifier_client_config_with_auth("spiffe");
 
-    {
-        let mut pipe = OtherSession::new_fails(&mut server);
-        let mut client_stream = Stream::new(&mut client, &mut pipe);
-        let rc = client_stream.write(b"hello");
-        assert!(rc.is_err());
-        assert_eq!(
-            format!("{:?}", rc),
-            "Err(Custom { kind: InvalidData, error: AlertReceived(HandshakeFailure) })"
-        );
-        let rc = client_stream.write(b"hello");
-        assert!(rc.is_err());
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::SpiffeIdNotAuthorized))
+    );
+}
+
+#[test]
+fn spiffe_verifier_rejects_client_without_single_spiffe_id() {
+    for name in &["client", "spiffe-two-ids"] {
+        let verifier = SpiffeVerifier::new("example.org", verifier_roots(), |_: &SpiffeId| true);
+        let server_config = make_spiffe_server_config(verifier);
+        let client_config = make_verifier_client_config_with_auth(name);
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
         assert_eq!(
-            format!("{:?}", rc),
-            "Err(Custom { kind: InvalidData, error: AlertReceived(HandshakeFailure) })"
+            do_handshake_until_error(&mut client, &mut server),
+            Err(ErrorFromPeer::Server(Error::InvalidSpiffeId)),
+            "{}",
+            name
         );
     }
 }
 
 #[test]
-fn client_streamowned_handshake_error() {
-    let (client_config, server_config) = make_disjoint_suite_configs();
-    let (client, mut server) = make_pair_for_configs(client_config, server_config);
+fn spiffe_verifier_rejects_client_in_unknown_trust_domain() {
+    let verifier = SpiffeVerifier::new("example.com", verifier_roots(), |_: &SpiffeId| true);
+    let server_config = make_spiffe_server_config(verifier);
+    let client_config = make_verifier_client_config_with_auth("spiffe");
 
-    let pipe = OtherSession::new_fails(&mut server);
-    let mut client_stream = StreamOwned::new(client, pipe);
-    let rc = client_stream.write(b"hello");
-    assert!(rc.is_err());
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
     assert_eq!(
-        format!("{:?}", rc),
-        "Err(Custom { kind: InvalidData, error: AlertReceived(HandshakeFailure) })"
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::WebPkiError(
+            webpki::Error::UnknownIssuer,
+            WebPkiOp::ValidateClientCert
+        )))
     );
-    let rc = client_stream.write(b"hello");
-    assert!(rc.is_err());
-    assert_eq!(
-        format!("{:?}", rc),
-        "Err(Custom { kind: InvalidData, error: AlertReceived(HandshakeFailure) })"
+
+    let verifier = SpiffeVerifier::new("example.com", RootCertStore::empty(), |_: &SpiffeId| true)
+        .with_trust_domain("example.org", verifier_roots());
+    let server_config = make_spiffe_server_config(verifier);
+    let client_config = make_verifier_client_config_with_auth("spiffe");
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
+}
+
+const KP_CLIENT_AUTH: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x02];
+const KP_EMAIL_PROTECTION: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x04];
+const TEST_POLICY: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x83, 0xb2, 0x03, 0x01, 0x01];
+const OTHER_POLICY: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x83, 0xb2, 0x03, 0x01, 0x02];
+
+#[test]
+fn client_verifier_accepts_required_purpose() {
+    let verifier = AllowAnyAuthenticatedClient::new_requiring_purpose(
+        verifier_roots(),
+        &[KP_CLIENT_AUTH, KP_EMAIL_PROTECTION],
+        &[OTHER_POLICY, TEST_POLICY],
     );
+    let server_config = make_verifier_server_config_with_client_verifier(verifier);
+    let client_config = make_verifier_client_config_with_auth("client-policy");
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
 }
 
 #[test]
-fn server_stream_handshake_error() {
-    let (client_config, server_config) = make_disjoint_suite_configs();
+fn client_verifier_rejects_missing_eku() {
+    let verifier = AllowAnyAuthenticatedClient::new_requiring_purpose(
+        verifier_roots(),
+        &[KP_CLIENT_AUTH, KP_EMAIL_PROTECTION],
+        &[],
+    );
+    let server_config = make_verifier_server_config_with_client_verifier(verifier);
+    let client_config = make_verifier_client_config_with_auth("client");
+
     let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::RequiredEkuMissing))
+    );
+}
 
-    client.writer().write(b"world").unwrap();
+#[test]
+fn client_verifier_rejects_missing_policy() {
+    for (name, policies) in &[
+        ("client", &[TEST_POLICY][..]),
+        ("client-policy", &[OTHER_POLICY][..]),
+    ] {
+        let verifier = AllowAnyAuthenticatedClient::new_requiring_purpose(
+            verifier_roots(),
+            &[KP_CLIENT_AUTH],
+            policies,
+        );
+        let server_config = make_verifier_server_config_with_client_verifier(verifier);
+        let client_config = make_verifier_client_config_with_auth(name);
 
-    {
-        let mut pipe = OtherSession::new_fails(&mut client);
-        let mut server_stream = Stream::new(&mut server, &mut pipe);
-        let mut bytes = [0u8; 5];
-        let rc = server_stream.read(&mut bytes);
-        assert!(rc.is_err());
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
         assert_eq!(
-            format!("{:?}", rc),
-            "Err(Custom { kind: InvalidData, error: PeerIncompatibleError(\"no ciphersuites in common\") })"
+            do_handshake_until_error(&mut client, &mut server),
+            Err(ErrorFromPeer::Server(Error::RequiredPolicyMissing)),
+            "{}",
+            name
         );
     }
 }
 
 #[test]
-fn server_streamowned_handshake_error() {
-    let (client_config, server_config) = make_disjoint_suite_configs();
-    let (mut client, server) = make_pair_for_configs(client_config, server_config);
+fn dynamic_root_client_verifier_resolves_roots_by_sni() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let resolver_calls = Arc::clone(&calls);
+    let verifier = DynamicRootClientVerifier::new(Arc::new(move |sni| {
+        resolver_calls.fetch_add(1, Ordering::SeqCst);
+        match sni.map(|name| name.as_ref().into()) {
+            Some("testserver.com") => verifier_roots(),
+            _ => RootCertStore::empty(),
+        }
+    }));
+    let server_config = Arc::new(
+        ConfigBuilder::with_safe_defaults()
+            .for_server()
+            .unwrap()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(verifier_chain(), verifier_key("end"))
+            .unwrap(),
+    );
+    let client_config = Arc::new(make_verifier_client_config_with_auth("client"));
+
+    let mut client =
+        ClientConnection::new(Arc::clone(&client_config), server_name("testserver.com")).unwrap();
+    let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+    assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+    let mut client = ClientConnection::new(
+        Arc::clone(&client_config),
+        server_name("second.testserver.com"),
+    )
+    .unwrap();
+    let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::WebPkiError(
+            webpki::Error::UnknownIssuer,
+            WebPkiOp::ValidateClientCert
+        )))
+    );
+    assert_eq!(calls.load(Ordering::SeqCst), 4);
+}
 
-    client.writer().write(b"world").unwrap();
+#[test]
+fn sni_client_cert_verifier_selects_roots_by_sni() {
+    let handshake = |verifier: SniClientCertVerifier, name: &str| {
+        let server_config = ConfigBuilder::with_safe_defaults()
+            .for_server()
+            .unwrap()
+            .with_client_cert_verifier(Arc::new(verifier))
+            .with_single_cert(verifier_chain(), verifier_key("end"))
+            .unwrap();
+        let client_config = make_verifier_client_config_with_auth("client");
 
-    let pipe = OtherSession::new_fails(&mut client);
-    let mut server_stream = StreamOwned::new(server, pipe);
-    let mut bytes = [0u8; 5];
-    let rc = server_stream.read(&mut bytes);
-    assert!(rc.is_err());
+        let mut client = ClientConnection::new(Arc::new(client_config), server_name(name)).unwrap();
+        let mut server = ServerConnection::new(Arc::new(server_config)).unwrap();
+        do_handshake_until_error(&mut client, &mut server)
+    };
+    let unknown_issuer = Err(ErrorFromPeer::Server(Error::WebPkiError(
+        webpki::Error::UnknownIssuer,
+        WebPkiOp::ValidateClientCert,
+    )));
+
+    let mut verifier = SniClientCertVerifier::new(None);
+    verifier
+        .add("TestServer.com", verifier_roots())
+        .unwrap();
+    assert_eq!(handshake(verifier, "testserver.com"), Ok(()));
+
+    let mut verifier = SniClientCertVerifier::new(Some(verifier_roots()));
+    verifier
+        .add("testserver.com", RootCertStore::empty())
+        .unwrap();
+    assert_eq!(handshake(verifier, "testserver.com"), unknown_issuer);
+
+    let mut verifier = SniClientCertVerifier::new(Some(verifier_roots()));
+    verifier
+        .add("testserver.com", RootCertStore::empty())
+        .unwrap();
+    assert_eq!(handshake(verifier, "second.testserver.com"), Ok(()));
+
+    let mut verifier = SniClientCertVerifier::new(None);
+    verifier
+        .add("testserver.com", verifier_roots())
+        .unwrap();
     assert_eq!(
-        format!("{:?}", rc),
-        "Err(Custom { kind: InvalidData, error: PeerIncompatibleError(\"no ciphersuites in common\") })"
+        handshake(verifier, "second.testserver.com"),
+        Err(ErrorFromPeer::Server(Error::General(
+            "client rejected by client_auth_root_subjects".into()
+        )))
+    );
+
+    assert_eq!(
+        SniClientCertVerifier::new(None).add("not a dns name", verifier_roots()),
+        Err(Error::General("Bad DNS name".into()))
     );
 }
 
+#[cfg(feature = "pem_files")]
 #[test]
-fn server_config_is_clone() {
-    let _ = make_server_config(KeyType::RSA).clone();
+fn root_store_adds_certificates_from_mixed_pem_file() {
+    let mut bundle = Vec::new();
+    bundle.extend_from_slice(b"# A bundle with a comment.\n");
+    bundle.extend_from_slice(bytes_for("verifier", "ca.cert"));
+    bundle.extend_from_slice(b"Some text between sections.\n");
+    bundle.extend_from_slice(bytes_for("verifier", "end.key"));
+    bundle.extend_from_slice(b"-----BEGIN X509 CRL-----\nMAA=\n-----END X509 CRL-----\n");
+    bundle.extend_from_slice(b"-----BEGIN CERTIFICATE-----\nMAA=\n-----END CERTIFICATE-----\n");
+    bundle.extend_from_slice(b"-----BEGIN CERTIFICATE-----\n!!!!\n-----END CERTIFICATE-----\n");
+    bundle.extend_from_slice(bytes_for("verifier", "inter.cert"));
+
+    let mut roots = RootCertStore::empty();
+    assert_eq!(
+        roots.add_pem_file(&mut io::BufReader::new(&bundle[..])),
+        Ok((2, 2))
+    );
+    assert_eq!(roots.len(), 2);
 }
 
 #[test]
-fn client_config_is_clone() {
-    let _ = make_client_config(KeyType::RSA).clone();
+fn root_store_ignores_and_prunes_expired_anchors() {
+    let at = |secs| std::time::UNIX_EPOCH + Duration::from_secs(secs);
+    let (start_2025, start_2027, start_2030) = (1735689600, 1798761600, 1893456000);
+    let ca = &verifier_certs("ca.cert")[0];
+
+    let mut roots = RootCertStore::empty();
+    roots
+        .add_with_validity(ca, Some(at(start_2025)), Some(at(start_2030)))
+        .unwrap();
+    roots
+        .add(&KeyType::RSA.get_chain()[2])
+        .unwrap();
+    assert!(roots.roots[0].is_trusted_at(at(start_2027)));
+    assert!(!roots.roots[0].is_trusted_at(at(start_2030 + 1)));
+    assert_eq!(
+        roots
+            .anchor_info()
+            .next()
+            .unwrap()
+            .trusted_until,
+        Some(at(start_2030))
+    );
+
+    let loaded = RootCertStore::deserialize(&roots.serialize()).unwrap();
+    assert!(loaded
+        .anchor_info()
+        .eq(roots.anchor_info()));
+
+    let verifier = WebPkiVerifier::new(roots.clone(), &[]);
+    let chain = verifier_chain();
+    let verify = |now| {
+        verifier
+            .verify_server_cert_detailed(
+                &chain[0],
+                &chain[1..],
+                &server_name("testserver.com"),
+                &mut std::iter::empty(),
+                &[],
+                at(now),
+            )
+            .map(|_| ())
+    };
+    let unknown_issuer = Err(Error::WebPkiError(
+        webpki::Error::UnknownIssuer,
+        WebPkiOp::ValidateServerCert,
+    ));
+    assert_eq!(verify(start_2027), Ok(()));
+    assert_eq!(verify(start_2025 - 1), unknown_issuer);
+    assert_eq!(verify(start_2030 + 1), unknown_issuer);
+
+    assert_eq!(roots.prune_expired(at(start_2027)), 0);
+    assert_eq!(roots.prune_expired(at(start_2025 - 1)), 0);
+    assert_eq!(roots.prune_expired(at(start_2030 + 1)), 1);
+    assert_eq!(roots.len(), 1);
+    assert!(!roots.contains(ca));
 }
 
 #[test]
-fn client_connection_is_debug() {
-    let (client, _) = make_pair(KeyType::RSA);
-    println!("{:?}", client);
+fn root_store_reports_duplicate_and_invalid_certificates() {
+    let ca = verifier_certs("ca.cert")[0].0.clone();
+    let inter = verifier_certs("inter.cert")[0]
+        .0
+        .clone();
+
+    let mut roots = RootCertStore::empty();
+    roots
+        .add(&rustls::Certificate(ca.clone()))
+        .unwrap();
+    let outcome = roots.add_parsable_certificates_detailed(&[
+        ca.clone(),
+        inter.clone(),
+        b"garbage".to_vec(),
+        inter.clone(),
+    ]);
+    assert_eq!(outcome.added, 1);
+    assert_eq!(outcome.duplicates, vec![0, 3]);
+    assert_eq!(outcome.invalid, vec![(2, webpki::Error::BadDer)]);
+    assert_eq!(roots.len(), 2);
+
+    assert_eq!(
+        roots.add_parsable_certificates(&[inter, b"garbage".to_vec()]),
+        (0, 2)
+    );
+    assert_eq!(roots.len(), 2);
 }
 
+#[cfg(feature = "pem_files")]
 #[test]
-fn server_connection_is_debug() {
-    let (_, server) = make_pair(KeyType::RSA);
-    println!("{:?}", server);
+fn root_store_adds_certificates_from_dir() {
+    use std::fs;
+
+    let dir = std::env::temp_dir().join(format!("rustls-capath-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    fs::write(dir.join("ca.pem"), bytes_for("verifier", "ca.cert")).unwrap();
+    fs::write(dir.join("inter.der"), &verifier_certs("inter.cert")[0].0).unwrap();
+    fs::write(dir.join("broken.der"), b"\x30\x00").unwrap();
+    fs::write(dir.join("readme.txt"), b"Not a certificate.\n").unwrap();
+    fs::write(dir.join("sub/end.pem"), bytes_for("verifier", "end.cert")).unwrap();
+    fs::write(
+        dir.join("0123abcd.r0"),
+        b"-----BEGIN X509 CRL-----\nMAA=\n-----END X509 CRL-----\n",
+    )
+    .unwrap();
+    #[cfg(unix)]
+    std::os::unix::fs::symlink("ca.pem", dir.join("0123abcd.0")).unwrap();
+
+    let mut roots = RootCertStore::empty();
+    let outcomes = roots.add_from_dir(&dir).unwrap();
+    let results: Vec<_> = outcomes
+        .iter()
+        .map(|outcome| {
+            (
+                outcome
+                    .path
+                    .file_name()
+                    .unwrap()
+                    .to_str()
+                    .unwrap(),
+                outcome.result.clone(),
+            )
+        })
+        .collect();
+    let ca_name = if cfg!(unix) { "0123abcd.0" } else { "ca.pem" };
+    let mut expected = vec![
+        ("broken.der", Ok((0, 1))),
+        (ca_name, Ok((1, 0))),
+        ("inter.der", Ok((1, 0))),
+        ("readme.txt", Ok((0, 0))),
+    ];
+    expected.sort_by_key(|(name, _)| *name);
+    assert_eq!(results, expected);
+    assert_eq!(roots.len(), 2);
+    assert!(roots.contains(&verifier_certs("ca.cert")[0]));
+    assert!(roots.contains(&verifier_certs("inter.cert")[0]));
+
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(roots.add_from_dir(&dir).is_err());
 }
 
 #[test]
-fn server_complete_io_for_handshake_ending_with_alert() {
-    let (client_config, server_config) = make_disjoint_suite_configs();
-    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+fn root_store_removes_and_replaces_anchors() {
+    let rsa = KeyType::RSA.get_chain();
+    let (rsa_inter, rsa_root) = (&rsa[1], &rsa[2]);
+    let ecdsa_root = &KeyType::ECDSA.get_chain()[2];
+    let subjects = |roots: &RootCertStore| -> Vec<Vec<u8>> {
+        roots
+            .subjects()
+            .into_iter()
+            .map(|name| name.0)
+            .collect()
+    };
+    let subject = |cert: &rustls::Certificate| {
+        let mut store = RootCertStore::empty();
+        store.add(cert).unwrap();
+        subjects(&store).pop().unwrap()
+    };
 
-    assert_eq!(true, server.is_handshaking());
+    let mut roots = RootCertStore::empty();
+    roots.add(rsa_inter).unwrap();
+    roots.add(rsa_root).unwrap();
+    roots.add(rsa_inter).unwrap();
 
-    let mut pipe = OtherSession::new_fails(&mut client);
-    let rc = server.complete_io(&mut pipe);
-    assert!(rc.is_err(), "server io failed due to handshake failure");
-    assert!(!server.wants_write(), "but server did send its alert");
+    assert_eq!(roots.remove(rsa_inter), Ok(2));
+    assert_eq!(roots.remove(rsa_inter), Ok(0));
     assert_eq!(
-        format!("{:?}", pipe.last_error),
-        "Some(AlertReceived(HandshakeFailure))",
-        "which was received by client"
+        roots.remove(&rustls::Certificate(b"garbage".to_vec())),
+        Err(webpki::Error::BadDer)
+    );
+    assert_eq!(subjects(&roots), vec![subject(rsa_root)]);
+
+    assert_eq!(roots.remove_by_subject(&subject(rsa_root)), 1);
+    assert_eq!(roots.remove_by_subject(&subject(rsa_root)), 0);
+    assert!(roots.is_empty());
+
+    roots.add(rsa_inter).unwrap();
+    roots.add(rsa_root).unwrap();
+    assert_eq!(roots.replace(rsa_inter, ecdsa_root), Ok(1));
+    assert_eq!(
+        subjects(&roots),
+        vec![subject(ecdsa_root), subject(rsa_root)]
     );
+    assert_eq!(roots.replace(rsa_inter, rsa_inter), Ok(0));
+    assert_eq!(roots.len(), 3);
+    assert!(roots
+        .replace(rsa_root, &rustls::Certificate(b"garbage".to_vec()))
+        .is_err());
+    assert_eq!(roots.len(), 3);
 }
 
 #[test]
-fn server_exposes_offered_sni() {
-    let kt = KeyType::RSA;
-    for client_config in AllClientVersions::new(make_client_config(kt)) {
-        let mut client =
-            ClientConnection::new(Arc::new(client_config), dns_name("second.testserver.com"))
-                .unwrap();
-        let mut server = ServerConnection::new(Arc::new(make_server_config(kt))).unwrap();
-
-        assert_eq!(None, server.sni_hostname());
-        do_handshake(&mut client, &mut server);
-        assert_eq!(Some("second.testserver.com"), server.sni_hostname());
-    }
+fn root_store_finds_anchors_by_subject() {
+    let rsa = KeyType::RSA.get_chain();
+    let (rsa_inter, rsa_root) = (&rsa[1], &rsa[2]);
+    let ecdsa_root = &KeyType::ECDSA.get_chain()[2];
+
+    let mut roots = RootCertStore::empty();
+    roots.add(rsa_root).unwrap();
+    roots.add(ecdsa_root).unwrap();
+
+    assert!(roots.contains(rsa_root));
+    assert!(roots.contains(ecdsa_root));
+    assert!(!roots.contains(rsa_inter));
+    assert!(!roots.contains(&rustls::Certificate(b"garbage".to_vec())));
+
+    let info: Vec<rustls::TrustAnchorInfo> = roots.anchor_info().collect();
+    assert_eq!(info.len(), 2);
+    assert_eq!(info[0].subject_string().unwrap(), "CN=ponytown RSA CA");
+    assert_eq!(info[0].key_algorithm, Some(SignatureAlgorithm::RSA));
+    assert_eq!(info[1].subject_string().unwrap(), "CN=ponytown ECDSA CA");
+    assert_eq!(info[1].key_algorithm, Some(SignatureAlgorithm::ECDSA));
+    assert_eq!(info[1].key_bits, Some(384));
+    assert_eq!(info[0].spki_sha256.len(), 32);
+    assert_ne!(info[0].spki_sha256, info[1].spki_sha256);
+    assert!(info
+        .iter()
+        .all(|info| info.not_after.is_some() && !info.name_constrained));
+
+    let found: Vec<_> = roots
+        .find_by_subject(&info[1].subject)
+        .collect();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].info(), info[1]);
+
+    // the contents of the Name, without its SEQUENCE, are accepted too
+    assert_eq!(
+        roots
+            .find_by_subject(&info[0].subject[2..])
+            .count(),
+        1
+    );
+    assert_eq!(
+        roots
+            .find_by_subject(b"\x30\x00")
+            .count(),
+        0
+    );
 }
 
 #[test]
-fn server_exposes_offered_sni_smashed_to_lowercase() {
-    // webpki actually does this for us in its DnsName type
-    let kt = KeyType::RSA;
-    for client_config in AllClientVersions::new(make_client_config(kt)) {
-        let mut client =
-            ClientConnection::new(Arc::new(client_config), dns_name("SECOND.TESTServer.com"))
-                .unwrap();
-        let mut server = ServerConnection::new(Arc::new(make_server_config(kt))).unwrap();
+fn root_store_serialization_roundtrips() {
+    let (mut roots, _) = RootCertStore::from_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+    roots
+        .add_with_name_constraints(
+            &KeyType::RSA.get_chain()[2],
+            NameConstraints::new().permit_dns_name("testserver.com"),
+        )
+        .unwrap();
 
-        assert_eq!(None, server.sni_hostname());
-        do_handshake(&mut client, &mut server);
-        assert_eq!(Some("second.testserver.com"), server.sni_hostname());
-    }
+    let bytes = roots.serialize();
+    let loaded = RootCertStore::deserialize(&bytes).unwrap();
+    assert_eq!(loaded.len(), roots.len());
+    assert!(loaded
+        .anchor_info()
+        .eq(roots.anchor_info()));
+    assert_eq!(loaded.serialize(), bytes);
+
+    assert!(RootCertStore::deserialize(&bytes[..bytes.len() - 1]).is_err());
+    assert!(RootCertStore::deserialize(&[bytes.as_slice(), &[0]].concat()).is_err());
+    assert!(RootCertStore::deserialize(&bytes[4..]).is_err());
+    let mut other_version = bytes.clone();
+    other_version[3] = 0xff;
+    assert!(RootCertStore::deserialize(&other_version).is_err());
+
+    let empty = RootCertStore::empty().serialize();
+    assert!(RootCertStore::deserialize(&empty)
+        .unwrap()
+        .is_empty());
 }
 
 #[test]
-fn server_exposes_offered_sni_even_if_resolver_fails() {
-    let kt = KeyType::RSA;
-    let resolver = rustls::ResolvesServerCertUsingSni::new();
-
-    let mut server_config = make_server_config(kt);
-    server_config.cert_resolver = Arc::new(resolver);
-    let server_config = Arc::new(server_config);
+fn owned_trust_anchor_can_be_made_from_der() {
+    let ca = verifier_certs("ca.cert")[0].0.clone();
+    let anchor = OwnedTrustAnchor::try_from_der(&ca).unwrap();
+    let ta = webpki::TrustAnchor::try_from_cert_der(&ca).unwrap();
+    assert_eq!(anchor.subject(), ta.subject);
+    assert_eq!(anchor.spki(), ta.spki);
+    assert_eq!(anchor.name_constraints(), None);
+    assert!(anchor.info().not_after.is_some());
+    assert_eq!(
+        OwnedTrustAnchor::try_from_der(b"garbage").unwrap_err(),
+        webpki::Error::BadDer
+    );
 
-    for client_config in AllClientVersions::new(make_client_config(kt)) {
-        let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
-        let mut client =
-            ClientConnection::new(Arc::new(client_config), dns_name("thisdoesNOTexist.com"))
+    let copy = OwnedTrustAnchor::try_from_components(
+        anchor.subject(),
+        anchor.spki(),
+        anchor.name_constraints(),
+    )
+    .unwrap();
+    assert_eq!(copy.info().subject, anchor.info().subject);
+    assert_eq!(copy.info().spki_sha256, anchor.info().spki_sha256);
+    assert!(OwnedTrustAnchor::try_from_components(b"garbage", anchor.spki(), None).is_err());
+    assert!(OwnedTrustAnchor::try_from_components(anchor.subject(), b"garbage", None).is_err());
+
+    for ta in webpki_roots::TLS_SERVER_ROOTS.0 {
+        let anchor =
+            OwnedTrustAnchor::try_from_components(ta.subject, ta.spki, ta.name_constraints)
                 .unwrap();
-
-        assert_eq!(None, server.sni_hostname());
-        transfer(&mut client, &mut server);
-        assert_eq!(
-            server.process_new_packets(),
-            Err(Error::General(
-                "no server certificate chain resolved".to_string()
-            ))
-        );
-        assert_eq!(Some("thisdoesnotexist.com"), server.sni_hostname());
+        assert_eq!(anchor.name_constraints(), ta.name_constraints);
     }
+
+    let mut roots = RootCertStore::empty();
+    roots.roots.push(copy);
+    assert!(roots.contains(&rustls::Certificate(ca)));
 }
 
 #[test]
-fn sni_resolver_works() {
-    let kt = KeyType::RSA;
-    let mut resolver = rustls::ResolvesServerCertUsingSni::new();
-    let signing_key = sign::RsaSigningKey::new(&kt.get_key()).unwrap();
-    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
-    resolver
-        .add(
-            "localhost",
-            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone()),
-        )
-        .unwrap();
+fn root_store_diffs_and_merges_with_another() {
+    let rsa_root = &KeyType::RSA.get_chain()[2];
+    let ecdsa_root = &KeyType::ECDSA.get_chain()[2];
+    let verifier_ca = &verifier_certs("ca.cert")[0];
+    let store = |certs: &[&rustls::Certificate]| {
+        let mut roots = RootCertStore::empty();
+        for cert in certs {
+            roots.add(cert).unwrap();
+        }
+        roots
+    };
 
-    let mut server_config = make_server_config(kt);
-    server_config.cert_resolver = Arc::new(resolver);
-    let server_config = Arc::new(server_config);
+    let mut current = store(&[rsa_root, verifier_ca]);
+    let upstream = store(&[ecdsa_root, verifier_ca, rsa_root]);
+    let newer = store(&[ecdsa_root]);
 
-    let mut server1 = ServerConnection::new(Arc::clone(&server_config)).unwrap();
-    let mut client1 =
-        ClientConnection::new(Arc::new(make_client_config(kt)), dns_name("localhost")).unwrap();
-    let err = do_handshake_until_error(&mut client1, &mut server1);
-    assert_eq!(err, Ok(()));
+    let (added, removed) = current.diff(&upstream);
+    assert_eq!(added.len(), 1);
+    assert_eq!(added[0].info(), upstream.roots[0].info());
+    assert!(removed.is_empty());
 
-    let mut server2 = ServerConnection::new(Arc::clone(&server_config)).unwrap();
-    let mut client2 =
-        ClientConnection::new(Arc::new(make_client_config(kt)), dns_name("notlocalhost")).unwrap();
-    let err = do_handshake_until_error(&mut client2, &mut server2);
+    let (added, removed) = upstream.diff(&newer);
+    assert!(added.is_empty());
     assert_eq!(
-        err,
-        Err(ErrorFromPeer::Server(Error::General(
-            "no server certificate chain resolved".into()
-        )))
+        removed
+            .iter()
+            .map(|ota| ota.info().subject_string().unwrap())
+            .collect::<Vec<_>>(),
+        vec!["CN=ponytown verifier CA", "CN=ponytown RSA CA"]
     );
+
+    assert_eq!(current.merge(&upstream), 1);
+    assert_eq!(current.len(), 3);
+    assert!(current.contains(ecdsa_root));
+    assert_eq!(current.merge(&upstream), 0);
+    let (added, removed) = current.diff(&upstream);
+    assert!(added.is_empty() && removed.is_empty());
 }
 
 #[test]
-fn shared_sni_resolver_works() {
-    let kt = KeyType::RSA;
-    let resolver = Arc::new(rustls::SharedSniResolver::new());
-    let signing_key = sign::RsaSigningKey::new(&kt.get_key()).unwrap();
-    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
-
-    assert!(resolver.remove("localhost").is_none());
+fn root_store_can_hold_static_certificates() {
+    let ca = verifier_certs("ca.cert")[0].clone();
+    let ca_der: &'static [u8] = Box::leak(ca.0.clone().into_boxed_slice());
+    assert!(RootCertStore::empty()
+        .add_static(b"garbage")
+        .is_err());
 
-    resolver
-        .add(
-            "localhost",
-            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone()),
+    let mut roots = RootCertStore::empty();
+    roots.add_static(ca_der).unwrap();
+    let eager = OwnedTrustAnchor::try_from_der(ca_der).unwrap();
+    assert_eq!(roots.roots[0].info(), eager.info());
+    assert_eq!(roots.roots[0].subject(), eager.subject());
+    assert!(roots.contains(&ca));
+    assert_eq!(roots.subjects()[0].0, eager.info().subject);
+
+    let loaded = RootCertStore::deserialize(&roots.serialize()).unwrap();
+    assert_eq!(loaded.roots[0].info(), eager.info());
+
+    let chain = verifier_chain();
+    assert!(WebPkiVerifier::new(roots, &[])
+        .verify_server_cert_detailed(
+            &chain[0],
+            &chain[1..],
+            &server_name("testserver.com"),
+            &mut std::iter::empty(),
+            &[],
+            std::time::SystemTime::now(),
         )
-        .unwrap();
+        .is_ok());
+}
 
-    let mut server_config = make_server_config(kt);
-    server_config.cert_resolver = resolver.clone();
-    let server_config = Arc::new(server_config);
+#[test]
+fn root_store_can_be_built_from_trust_anchors() {
+    let (roots, invalid) = RootCertStore::from_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+    assert_eq!(invalid, 0);
+    assert_eq!(roots.len(), webpki_roots::TLS_SERVER_ROOTS.0.len());
+
+    let ca = verifier_certs("ca.cert");
+    let ca = webpki::TrustAnchor::try_from_cert_der(&ca[0].0).unwrap();
+    let anchor = |subject, spki| webpki::TrustAnchor {
+        subject,
+        spki,
+        name_constraints: None,
+    };
 
-    let mut server1 = ServerConnection::new(server_config.clone()).unwrap();
-    let mut client1 =
-        ClientConnection::new(Arc::new(make_client_config(kt)), dns_name("localhost")).unwrap();
-    let err = do_handshake_until_error(&mut client1, &mut server1);
-    assert_eq!(err, Ok(()));
+    let (roots, invalid) = RootCertStore::from_trust_anchors(&webpki::TlsServerTrustAnchors(&[
+        anchor(ca.subject, b"\x30\x00"),
+        anchor(ca.subject, ca.spki),
+        anchor(b"\x31\x05", ca.spki),
+    ]));
+    assert_eq!(invalid, 2);
+    let subjects: Vec<_> = roots
+        .roots
+        .iter()
+        .map(|ota| ota.to_trust_anchor().subject.to_vec())
+        .collect();
+    assert_eq!(subjects, vec![ca.subject.to_vec()]);
+}
 
-    let mut server2 = ServerConnection::new(server_config.clone()).unwrap();
-    let mut client2 =
-        ClientConnection::new(Arc::new(make_client_config(kt)), dns_name("notlocalhost")).unwrap();
-    let err = do_handshake_until_error(&mut client2, &mut server2);
-    assert_eq!(
-        err,
-        Err(ErrorFromPeer::Server(Error::General(
-            "no server certificate chain resolved".into()
-        )))
+#[test]
+fn root_store_enforces_name_constraints_on_added_anchors() {
+    let verify_with = |roots: RootCertStore, name: &str, server: &str| {
+        let chain = verifier_chain_for(name);
+        WebPkiVerifier::new(roots, &[])
+            .verify_server_cert_detailed(
+                &chain[0],
+                &chain[1..],
+                &server_name(server),
+                &mut std::iter::empty(),
+                &[],
+                std::time::SystemTime::now(),
+            )
+            .map(|_| ())
+    };
+    let verify = |constraints: NameConstraints, name: &str, server: &str| {
+        let mut roots = RootCertStore::empty();
+        roots
+            .add_with_name_constraints(&verifier_certs("ca.cert")[0], constraints)
+            .unwrap();
+        verify_with(roots, name, server)
+    };
+    let violation = Err(Error::WebPkiError(
+        webpki::Error::NameConstraintViolation,
+        WebPkiOp::ValidateServerCert,
+    ));
+    let ip = |addr: [u8; 4]| std::net::IpAddr::from(addr);
+
+    // end.cert is for testserver.com, second.testserver.com and localhost.
+    let ours = || {
+        NameConstraints::new()
+            .permit_dns_name("testserver.com")
+            .permit_dns_name("localhost")
+    };
+    assert_eq!(
+        verify(NameConstraints::new(), "end", "testserver.com"),
+        Ok(())
     );
-
-    assert!(resolver.remove("localhost").is_some());
-
-    let mut client3 =
-        ClientConnection::new(Arc::new(make_client_config(kt)), dns_name("localhost")).unwrap();
-    let mut server3 = ServerConnection::new(server_config).unwrap();
-    let err = do_handshake_until_error(&mut client3, &mut server3);
+    assert_eq!(verify(ours(), "end", "testserver.com"), Ok(()));
     assert_eq!(
-        err,
-        Err(ErrorFromPeer::Server(Error::General(
-            "no server certificate chain resolved".into()
-        )))
+        verify(
+            NameConstraints::new().permit_dns_name("testserver.com"),
+            "end",
+            "testserver.com"
+        ),
+        violation
+    );
+    assert_eq!(
+        verify(
+            ours().exclude_dns_name("second.testserver.com"),
+            "end",
+            "testserver.com"
+        ),
+        violation
     );
-}
-
-#[test]
-fn sni_resolver_rejects_wrong_names() {
-    let kt = KeyType::RSA;
-    let mut resolver = rustls::ResolvesServerCertUsingSni::new();
-    let signing_key = sign::RsaSigningKey::new(&kt.get_key()).unwrap();
-    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
 
+    // end-ip.cert is for testserver.com, 198.51.100.1 and 2001:db8::1.
     assert_eq!(
-        Ok(()),
-        resolver.add(
-            "localhost",
-            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
-        )
+        verify(
+            NameConstraints::new().exclude_ip_subnet(ip([203, 0, 113, 0]), 24),
+            "end-ip",
+            "198.51.100.1"
+        ),
+        Ok(())
     );
     assert_eq!(
-        Err(Error::General(
-            "The server certificate is not valid for the given name".into()
-        )),
-        resolver.add(
-            "not-localhost",
-            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
-        )
+        verify(
+            NameConstraints::new().exclude_ip_subnet(ip([198, 51, 100, 0]), 24),
+            "end-ip",
+            "198.51.100.1"
+        ),
+        violation
     );
     assert_eq!(
-        Err(Error::General("Bad DNS name".into())),
-        resolver.add(
-            "not ascii 🦀",
-            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
-        )
+        verify(
+            NameConstraints::new().permit_ip_subnet(ip([203, 0, 113, 0]), 24),
+            "end-ip",
+            "198.51.100.1"
+        ),
+        violation
+    );
+
+    // Constraints can also be set on anchors already in a store.  A chain
+    // is acceptable if it leads to any anchor whose constraints it meets.
+    let mut roots = verifier_roots();
+    for anchor in &mut roots.roots {
+        anchor.set_name_constraints(NameConstraints::new().permit_dns_name("example.com"));
+    }
+    assert_eq!(
+        verify_with(roots.clone(), "end", "testserver.com"),
+        violation
     );
+    roots
+        .add(&verifier_certs("ca.cert")[0])
+        .unwrap();
+    assert_eq!(verify_with(roots, "end", "testserver.com"), Ok(()));
 }
 
 #[test]
-fn sni_resolver_rejects_bad_certs() {
-    let kt = KeyType::RSA;
-    let mut resolver = rustls::ResolvesServerCertUsingSni::new();
-    let signing_key = sign::RsaSigningKey::new(&kt.get_key()).unwrap();
-    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
+fn client_verifier_enforces_name_constraints_on_anchors() {
+    // client-email.cert is for alice.example.com.
+    let mut roots = RootCertStore::empty();
+    roots
+        .add_with_name_constraints(
+            &verifier_certs("ca.cert")[0],
+            NameConstraints::new().exclude_dns_name("example.com"),
+        )
+        .unwrap();
+    let server_config = ConfigBuilder::with_safe_defaults()
+        .for_server()
+        .unwrap()
+        .with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots))
+        .with_single_cert(verifier_chain(), verifier_key("end"))
+        .unwrap();
 
+    let client_config = make_verifier_client_config_with_auth("client-email");
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config.clone());
     assert_eq!(
-        Err(Error::General(
-            "No end-entity certificate in certificate chain".into()
-        )),
-        resolver.add(
-            "localhost",
-            sign::CertifiedKey::new(vec![], signing_key.clone())
-        )
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::WebPkiError(
+            webpki::Error::NameConstraintViolation,
+            WebPkiOp::ValidateClientCert
+        )))
     );
 
-    let bad_chain = vec![rustls::Certificate(vec![0xa0])];
+    let client_config = make_verifier_client_config_with_auth("client-email-other");
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
+}
+
+#[test]
+fn webpki_verifier_roots_can_be_swapped_under_live_config() {
+    let verifier = Arc::new(WebPkiVerifier::new(RootCertStore::empty(), &[]));
+    let client_config = Arc::new(make_client_config_with_verifier(verifier.clone()));
+    let server_config = Arc::new(make_verifier_server_config(&[]));
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
     assert_eq!(
-        Err(Error::General(
-            "End-entity certificate in certificate chain is syntactically invalid".into()
-        )),
-        resolver.add(
-            "localhost",
-            sign::CertifiedKey::new(bad_chain, signing_key.clone())
-        )
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Client(Error::WebPkiError(
+            webpki::Error::UnknownIssuer,
+            WebPkiOp::ValidateServerCert
+        )))
     );
+
+    let roots = Arc::new(verifier_roots());
+    verifier.set_roots(roots.clone());
+    assert!(Arc::ptr_eq(&verifier.roots(), &roots));
+    assert_eq!(verifier.generation(), 1);
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
+
+    // Adding to a shared store leaves the other holders' copy alone.
+    verifier.batch_add_certificates(&[KeyType::RSA.get_chain()[2].0.clone()]);
+    assert_eq!(verifier.roots().len(), 2);
+    assert_eq!(roots.len(), 1);
 }
 
-fn do_exporter_test(client_config: ClientConfig, server_config: ServerConfig) {
-    let mut client_secret = [0u8; 64];
-    let mut server_secret = [0u8; 64];
+#[test]
+fn webpki_verifier_generation_counts_root_changes() {
+    let verifier = WebPkiVerifier::new(RootCertStore::empty(), &[]);
+    assert_eq!(verifier.generation(), 0);
+
+    verifier
+        .add_trusted_root_ca(&verifier_certs("ca.cert")[0])
+        .unwrap();
+    assert_eq!(verifier.generation(), 1);
+
+    // Failed mutations do not count.
+    assert!(verifier
+        .add_trusted_root_ca(&rustls::Certificate(vec![0x30, 0x00]))
+        .is_err());
+    assert_eq!(verifier.batch_add_certificates(&[]), (0, 0));
+    assert_eq!(verifier.generation(), 1);
+
+    verifier.replace_root_cert_store(verifier_roots());
+    assert_eq!(verifier.generation(), 2);
+
+    let chain = verifier_chain();
+    let server_name = server_name("testserver.com");
+    let verified = verifier
+        .verify_server_cert_detailed(
+            &chain[0],
+            &chain[1..],
+            &server_name,
+            &mut std::iter::empty(),
+            &[],
+            std::time::SystemTime::now(),
+        )
+        .unwrap();
+    assert_eq!(verified.generation, 2);
+
+    verifier.replace_root_cert_store(RootCertStore::empty());
+    assert_eq!(verifier.generation(), 3);
+    assert!(verifier
+        .verify_server_cert_detailed(
+            &chain[0],
+            &chain[1..],
+            &server_name,
+            &mut std::iter::empty(),
+            &[],
+            std::time::SystemTime::now(),
+        )
+        .is_err());
+}
+
+#[test]
+fn client_with_fips_verifier_accepts_conforming_server() {
+    let client_config = ConfigBuilder::with_safe_defaults()
+        .for_client()
+        .unwrap()
+        .with_fips_verifier(Arc::new(
+            FipsModeVerifier::new(verifier_roots(), &[]).unwrap(),
+        ))
+        .with_no_client_auth();
+    let server_config = make_verifier_server_config(&[]);
 
     let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
+}
 
+#[test]
+fn webpki_verifier_reports_earliest_expiry_in_chain() {
+    let verify = |chain: &[rustls::Certificate]| {
+        WebPkiVerifier::new(verifier_roots(), &[])
+            .verify_server_cert_detailed(
+                &chain[0],
+                &chain[1..],
+                &server_name("testserver.com"),
+                &mut std::iter::empty(),
+                &[],
+                std::time::SystemTime::now(),
+            )
+            .unwrap()
+            .earliest_expiry
+    };
+    let subject = |cert: &rustls::Certificate| {
+        let name = webpki::TrustAnchor::try_from_cert_der(&cert.0)
+            .unwrap()
+            .subject;
+        assert!(name.len() < 0x80);
+        [&[0x30, name.len() as u8][..], name].concat()
+    };
+    let in_2040 = std::time::UNIX_EPOCH + Duration::from_secs(2208988800);
+    let in_2050 = std::time::UNIX_EPOCH + Duration::from_secs(2524608000);
+
+    // Everything in this chain expires in 2050.
+    let chain = verifier_chain();
     assert_eq!(
-        Err(Error::HandshakeNotComplete),
-        client.export_keying_material(&mut client_secret, b"label", Some(b"context"))
-    );
-    assert_eq!(
-        Err(Error::HandshakeNotComplete),
-        server.export_keying_material(&mut server_secret, b"label", Some(b"context"))
+        verify(&chain),
+        ChainExpiry {
+            not_after: in_2050,
+            position: ChainPosition::EndEntity,
+            subject: subject(&chain[0]),
+        }
     );
-    do_handshake(&mut client, &mut server);
 
+    // test-ca/verifier/inter-short.cert expires in 2040, before the
+    // certificate it issued.
+    let mut chain = verifier_certs("end-inter-short.cert");
+    chain.extend(verifier_certs("inter.cert"));
+    chain.extend(verifier_certs("inter-short.cert"));
     assert_eq!(
-        Ok(()),
-        client.export_keying_material(&mut client_secret, b"label", Some(b"context"))
+        verify(&chain),
+        ChainExpiry {
+            not_after: in_2040,
+            position: ChainPosition::Intermediate(1),
+            subject: subject(&chain[2]),
+        }
     );
+}
+
+#[test]
+fn webpki_verifier_reports_end_entity_spki() {
+    let chain = verifier_chain();
+    let server_name = server_name("testserver.com");
+    let verified = WebPkiVerifier::new(verifier_roots(), &[])
+        .verify_server_cert_detailed(
+            &chain[0],
+            &chain[1..],
+            &server_name,
+            &mut std::iter::empty(),
+            &[],
+            std::time::SystemTime::now(),
+        )
+        .unwrap();
+
+    // openssl x509 -in test-ca/verifier/end.cert -pubkey -noout |
+    //   openssl pkey -pubin -outform der | sha256sum
+    let expected = "de072e1ff524ff321c3225c17686e3b81d63d4966e9055410a4778704e557bcd";
+    let hash = ring::digest::digest(&ring::digest::SHA256, &verified.end_entity_spki);
+    let hex: String = hash
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    assert_eq!(hex, expected);
+}
+
+#[test]
+fn webpki_verifier_reports_verification_details() {
+    let server_name = server_name("testserver.com");
+    let in_2040 = std::time::UNIX_EPOCH + Duration::from_secs(2208988800);
+    let verify = |verifier: &WebPkiVerifier, name, ocsp| {
+        let chain = verifier_chain_for(name);
+        verifier
+            .verify_server_cert_detailed(
+                &chain[0],
+                &chain[1..],
+                &server_name,
+                &mut std::iter::empty(),
+                ocsp,
+                in_2040,
+            )
+            .unwrap()
+    };
+    let subject = |cert: &rustls::Certificate| {
+        let name = webpki::TrustAnchor::try_from_cert_der(&cert.0)
+            .unwrap()
+            .subject;
+        assert!(name.len() < 0x80);
+        [&[0x30, name.len() as u8][..], name].concat()
+    };
+    let rsa_2048 = |position, subject| PathCertificate {
+        position,
+        subject,
+        key_algorithm: Some(SignatureAlgorithm::RSA),
+        key_bits: Some(2048),
+    };
+
+    let verifier = WebPkiVerifier::new(verifier_roots(), &[]);
+    let verified = verify(&verifier, "end", &[]);
+    let chain = verifier_chain();
+    let ca = verifier_certs("ca.cert");
     assert_eq!(
-        Ok(()),
-        server.export_keying_material(&mut server_secret, b"label", Some(b"context"))
+        verified.path,
+        vec![
+            rsa_2048(ChainPosition::EndEntity, subject(&chain[0])),
+            rsa_2048(ChainPosition::Intermediate(0), subject(&chain[1])),
+            rsa_2048(ChainPosition::TrustAnchor, subject(&ca[0])),
+        ]
     );
-    assert_eq!(client_secret.to_vec(), server_secret.to_vec());
+    assert_eq!(verified.sct_logs, Vec::<usize>::new());
+    assert_eq!(verified.ocsp_freshness, None);
+
+    static LOGS: &[&sct::Log] = &[&TEST_CT_LOG, &VERIFIER_CT_LOG];
+    let verifier = WebPkiVerifier::new(verifier_roots(), LOGS);
+    assert_eq!(verify(&verifier, "end-sct", &[]).sct_logs, vec![1]);
+
+    let verifier = WebPkiVerifier::new(verifier_roots(), &[]).with_ocsp_policy(OcspPolicy::new());
+    let freshness = verify(&verifier, "end", bytes_for("verifier", "end.ocsp-sha256"))
+        .ocsp_freshness
+        .unwrap();
+    assert!(freshness.this_update <= in_2040);
+    assert!(freshness.next_update.unwrap() >= in_2040);
+}
+
+#[test]
+fn webpki_verifier_records_last_verification() {
+    let verifier = Arc::new(WebPkiVerifier::new(verifier_roots(), &[]));
+    assert_eq!(verifier.last_verification(), None);
+
+    let client_config = ConfigBuilder::with_safe_defaults()
+        .for_client()
+        .unwrap()
+        .with_webpki_verifier(Arc::clone(&verifier))
+        .with_no_client_auth();
+    let server_config = make_verifier_server_config(&[]);
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
+
+    let verified = verifier.last_verification().unwrap();
+    assert_eq!(verified.path.len(), 3);
+    assert_eq!(verified.path[0].position, ChainPosition::EndEntity);
+    assert_eq!(verified.path[2].position, ChainPosition::TrustAnchor);
+}
+
+#[test]
+fn decompressed_certificate_chain_can_be_verified() {
+    use rustls::internal::msgs::enums::ContentType;
+    use rustls::internal::msgs::handshake::{CertificateEntry, CertificatePayloadTLS13};
+
+    let chain = verifier_chain();
+    let message = CertificatePayloadTLS13::new(
+        chain
+            .iter()
+            .cloned()
+            .map(CertificateEntry::new)
+            .collect(),
+    )
+    .get_encoding();
+
+    // No compression algorithm is available here, so the message stands
+    // in for its own decompression.
+    let decompressed = rustls::Certificate::from_decompressed(&message).unwrap();
+    assert_eq!(decompressed, chain);
+
+    let server_name = server_name("testserver.com");
+    assert!(WebPkiVerifier::new(verifier_roots(), &[])
+        .verify_server_cert_detailed(
+            &decompressed[0],
+            &decompressed[1..],
+            &server_name,
+            &mut std::iter::empty(),
+            &[],
+            std::time::SystemTime::now(),
+        )
+        .is_ok());
 
     assert_eq!(
-        Ok(()),
-        client.export_keying_material(&mut client_secret, b"label", None)
+        rustls::Certificate::from_decompressed(&message[..message.len() - 1]),
+        Err(Error::CorruptMessagePayload(ContentType::Handshake))
     );
-    assert_ne!(client_secret.to_vec(), server_secret.to_vec());
     assert_eq!(
-        Ok(()),
-        server.export_keying_material(&mut server_secret, b"label", None)
+        rustls::Certificate::from_decompressed(
+            &CertificatePayloadTLS13::new(vec![]).get_encoding()
+        ),
+        Err(Error::NoCertificatesPresented)
     );
-    assert_eq!(client_secret.to_vec(), server_secret.to_vec());
 }
 
 #[test]
-fn test_tls12_exporter() {
+fn tls12_signature_can_be_verified_detached() {
+    use rustls::internal::msgs::handshake::DigitallySignedStruct;
+
+    let client_random = [0x11u8; 32];
+    let server_random = [0x22u8; 32];
+    let params = b"\x03\x00\x1d\x04ecdh";
+    let mut message = Vec::new();
+    message.extend_from_slice(&client_random);
+    message.extend_from_slice(&server_random);
+    message.extend_from_slice(params);
+
     for kt in ALL_KEY_TYPES.iter() {
-        let mut client_config = make_client_config(*kt);
-        let server_config = make_server_config(*kt);
-        client_config
-            .versions
-            .replace(&[&rustls::version::TLS12]);
+        let cert = &kt.get_chain()[0];
+        let key = sign::any_supported_type(&kt.get_key()).unwrap();
+        let signer = key
+            .choose_scheme(&WebPkiVerifier::verification_schemes())
+            .unwrap();
+        let dss = DigitallySignedStruct::new(signer.get_scheme(), signer.sign(&message).unwrap());
 
-        do_exporter_test(client_config, server_config);
+        assert!(rustls::verify_tls12_signature_detached(&message, cert, &dss).is_ok());
+
+        message[0] ^= 0x01;
+        assert_eq!(
+            rustls::verify_tls12_signature_detached(&message, cert, &dss).err(),
+            Some(Error::WebPkiError(
+                webpki::Error::InvalidSignatureForPublicKey,
+                WebPkiOp::VerifySignature
+            ))
+        );
+        message[0] ^= 0x01;
     }
 }
 
 #[test]
-fn test_tls13_exporter() {
+fn certificate_possession_can_be_verified() {
+    use rustls::internal::msgs::handshake::DigitallySignedStruct;
+
+    let challenge = b"enrollment challenge 0123456789";
+
     for kt in ALL_KEY_TYPES.iter() {
-        let mut client_config = make_client_config(*kt);
-        let server_config = make_server_config(*kt);
-        client_config
-            .versions
-            .replace(&[&rustls::version::TLS13]);
+        let cert = &kt.get_chain()[0];
+        let key = sign::any_supported_type(&kt.get_key()).unwrap();
+        let signer = key
+            .choose_scheme(&WebPkiVerifier::verification_schemes())
+            .unwrap();
+        let dss = DigitallySignedStruct::new(signer.get_scheme(), signer.sign(challenge).unwrap());
 
-        do_exporter_test(client_config, server_config);
+        assert!(rustls::verify_possession(cert, challenge, &dss).is_ok());
+        assert_eq!(
+            rustls::verify_possession(cert, b"another challenge", &dss).err(),
+            Some(Error::WebPkiError(
+                webpki::Error::InvalidSignatureForPublicKey,
+                WebPkiOp::VerifySignature
+            ))
+        );
     }
+
+    // A proof made with a different key fails.
+    let key = sign::any_supported_type(&verifier_key("end")).unwrap();
+    let signer = key
+        .choose_scheme(&WebPkiVerifier::verification_schemes())
+        .unwrap();
+    let dss = DigitallySignedStruct::new(signer.get_scheme(), signer.sign(challenge).unwrap());
+    assert!(rustls::verify_possession(&verifier_certs("client.cert")[0], challenge, &dss).is_err());
+    assert!(rustls::verify_possession(&verifier_chain()[0], challenge, &dss).is_ok());
 }
 
-fn do_suite_test(
-    client_config: ClientConfig,
-    server_config: ServerConfig,
-    expect_suite: &'static SupportedCipherSuite,
-    expect_version: ProtocolVersion,
-) {
-    println!(
-        "do_suite_test {:?} {:?}",
-        expect_version, expect_suite.suite
-    );
+#[test]
+fn client_checks_aki_matches_issuer_ski() {
+    let mut wrong_chain = verifier_certs("end.cert");
+    wrong_chain.extend(verifier_certs("inter-wrong-ski.cert"));
+
+    // webpki accepts the chain, because it ignores key identifiers.
+    let verifier = WebPkiVerifier::new(verifier_roots(), &[]);
+    let client_config = make_client_config_with_verifier(Arc::new(verifier));
+    let server_config = make_verifier_server_config_with_chain(wrong_chain.clone());
     let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
 
-    assert_eq!(None, client.negotiated_cipher_suite());
-    assert_eq!(None, server.negotiated_cipher_suite());
-    assert_eq!(None, client.protocol_version());
-    assert_eq!(None, server.protocol_version());
-    assert_eq!(true, client.is_handshaking());
-    assert_eq!(true, server.is_handshaking());
+    let verifier = Arc::new(WebPkiVerifier::new(verifier_roots(), &[]).with_aki_ski_check());
 
-    transfer(&mut client, &mut server);
-    server.process_new_packets().unwrap();
+    let client_config = make_client_config_with_verifier(verifier.clone());
+    let server_config = make_verifier_server_config_with_chain(wrong_chain);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Client(Error::AkiSkiMismatch))
+    );
 
-    assert_eq!(true, client.is_handshaking());
-    assert_eq!(true, server.is_handshaking());
-    assert_eq!(None, client.protocol_version());
-    assert_eq!(Some(expect_version), server.protocol_version());
-    assert_eq!(None, client.negotiated_cipher_suite());
-    assert_eq!(Some(expect_suite), server.negotiated_cipher_suite());
+    let client_config = make_client_config_with_verifier(verifier);
+    let server_config = make_verifier_server_config_with_chain(verifier_chain());
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
+}
 
-    transfer(&mut server, &mut client);
-    client.process_new_packets().unwrap();
+#[test]
+fn client_checks_intermediate_eku_allows_server_auth() {
+    let mut client_auth_chain = verifier_certs("end-client-auth-issued.cert");
+    client_auth_chain.extend(verifier_certs("inter-client-auth.cert"));
+
+    // The server also sends an unneeded intermediate restricted to
+    // clientAuth, which webpki never looks at.
+    let mut extra_chain = verifier_chain();
+    extra_chain.extend(verifier_certs("inter-client-auth.cert"));
+
+    let verifier = WebPkiVerifier::new(verifier_roots(), &[]);
+    let client_config = make_client_config_with_verifier(Arc::new(verifier));
+    let server_config = make_verifier_server_config_with_chain(extra_chain.clone());
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
 
-    assert_eq!(Some(expect_suite), client.negotiated_cipher_suite());
-    assert_eq!(Some(expect_suite), server.negotiated_cipher_suite());
+    let verifier = Arc::new(WebPkiVerifier::new(verifier_roots(), &[]).with_eku_chaining_check());
 
-    transfer(&mut client, &mut server);
-    server.process_new_packets().unwrap();
-    transfer(&mut server, &mut client);
-    client.process_new_packets().unwrap();
+    for chain in [client_auth_chain, extra_chain] {
+        let client_config = make_client_config_with_verifier(verifier.clone());
+        let server_config = make_verifier_server_config_with_chain(chain);
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        assert_eq!(
+            do_handshake_until_error(&mut client, &mut server),
+            Err(ErrorFromPeer::Client(Error::EkuNotChained))
+        );
+    }
 
-    assert_eq!(false, client.is_handshaking());
-    assert_eq!(false, server.is_handshaking());
-    assert_eq!(Some(expect_version), client.protocol_version());
-    assert_eq!(Some(expect_version), server.protocol_version());
-    assert_eq!(Some(expect_suite), client.negotiated_cipher_suite());
-    assert_eq!(Some(expect_suite), server.negotiated_cipher_suite());
+    let client_config = make_client_config_with_verifier(verifier);
+    let server_config = make_verifier_server_config_with_chain(verifier_chain());
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
 }
 
-fn find_suite(suite: CipherSuite) -> &'static SupportedCipherSuite {
-    for scs in ALL_CIPHERSUITES.iter() {
-        if scs.suite == suite {
-            return scs;
-        }
-    }
+#[test]
+fn webpki_verifier_rejects_wildcard_over_public_suffix() {
+    let verify = |verifier: &WebPkiVerifier, name: &str, dns_name: &str| {
+        let chain = verifier_chain_for(name);
+        verifier
+            .verify_server_cert_detailed(
+                &chain[0],
+                &chain[1..],
+                &server_name(dns_name),
+                &mut std::iter::empty(),
+                &[],
+                std::time::SystemTime::now(),
+            )
+            .map(|_| ())
+    };
 
-    panic!("find_suite given unsupported suite");
-}
+    let verifier = WebPkiVerifier::new(verifier_roots(), &[]);
+    assert_eq!(verify(&verifier, "end-wildcard-co-uk", "www.co.uk"), Ok(()));
+
+    let verifier = verifier.with_public_suffixes(&["com", "uk", "co.uk"]);
+    assert_eq!(
+        verify(&verifier, "end-wildcard-example", "www.example.com"),
+        Ok(())
+    );
+    assert_eq!(
+        verify(&verifier, "end-wildcard-co-uk", "www.co.uk"),
+        Err(Error::WildcardSpansPublicSuffix)
+    );
+    assert_eq!(verify(&verifier, "end", "testserver.com"), Ok(()));
+}
+
+#[test]
+fn webpki_verifier_matches_ip_addresses() {
+    let verify = |name: &str, server: &str| {
+        let chain = verifier_chain_for(name);
+        WebPkiVerifier::new(verifier_roots(), &[])
+            .verify_server_cert_detailed(
+                &chain[0],
+                &chain[1..],
+                &server_name(server),
+                &mut std::iter::empty(),
+                &[],
+                std::time::SystemTime::now(),
+            )
+            .map(|_| ())
+    };
+    let not_valid_for_address = Err(Error::WebPkiError(
+        webpki::Error::CertNotValidForName,
+        WebPkiOp::ValidateForIpAddress,
+    ));
+
+    assert_eq!(
+        server_name("198.51.100.1"),
+        ServerName::IpAddress([198, 51, 100, 1].into())
+    );
+    assert_eq!(verify("end-ip", "198.51.100.1"), Ok(()));
+    assert_eq!(verify("end-ip", "2001:db8::1"), Ok(()));
+    assert_eq!(verify("end-ip", "testserver.com"), Ok(()));
+    assert_eq!(verify("end-ip", "198.51.100.2"), not_valid_for_address);
+    assert_eq!(
+        verify("end-ip", "::ffff:198.51.100.1"),
+        not_valid_for_address
+    );
+    assert_eq!(verify("end", "198.51.100.1"), not_valid_for_address);
+}
+
+#[test]
+fn webpki_verifier_matches_email_addresses_and_uris() {
+    let chain = verifier_chain_for("end-email-uri");
+    let verifier = WebPkiVerifier::new(verifier_roots(), &[]);
+    let now = std::time::SystemTime::now();
+    let verify_email = |chain: &[rustls::Certificate], email: &str| {
+        verifier
+            .verify_cert_for_email(
+                &chain[0],
+                &chain[1..],
+                email,
+                &mut std::iter::empty(),
+                &[],
+                now,
+            )
+            .map(|_| ())
+    };
+    let verify_uri = |chain: &[rustls::Certificate], uri: &str| {
+        verifier
+            .verify_cert_for_uri(
+                &chain[0],
+                &chain[1..],
+                uri,
+                &mut std::iter::empty(),
+                &[],
+                now,
+            )
+            .map(|_| ())
+    };
+    let not_valid_for_email = Err(Error::WebPkiError(
+        webpki::Error::CertNotValidForName,
+        WebPkiOp::ValidateForEmail,
+    ));
+    let not_valid_for_uri = Err(Error::WebPkiError(
+        webpki::Error::CertNotValidForName,
+        WebPkiOp::ValidateForUri,
+    ));
+
+    assert_eq!(verify_email(&chain, "Postmaster@Example.com"), Ok(()));
+    assert_eq!(verify_email(&chain, "Postmaster@example.COM"), Ok(()));
+    assert_eq!(
+        verify_email(&chain, "postmaster@example.com"),
+        not_valid_for_email
+    );
+    assert_eq!(verify_email(&chain, "@example.com"), not_valid_for_email);
+    assert_eq!(
+        verify_email(&chain, "Alice@Example.com"),
+        not_valid_for_email
+    );
+    assert_eq!(
+        verify_email(&verifier_chain(), "Postmaster@Example.com"),
+        not_valid_for_email
+    );
+
+    assert_eq!(
+        verify_uri(&chain, "SIP:Alice@Example.com;transport=tls"),
+        Ok(())
+    );
+    assert_eq!(
+        verify_uri(&chain, "sip:alice@Example.com;transport=tls"),
+        not_valid_for_uri
+    );
+    assert_eq!(verify_uri(&chain, "https://example.com/Alice"), Ok(()));
+    assert_eq!(
+        verify_uri(&chain, "https://example.com/alice"),
+        not_valid_for_uri
+    );
+    assert_eq!(
+        verify_uri(&verifier_chain_for("spiffe"), "spiffe://example.org/web"),
+        Ok(())
+    );
+    assert_eq!(
+        verify_uri(&verifier_chain(), "https://example.com/Alice"),
+        not_valid_for_uri
+    );
+
+    // The certificate must still chain to a trusted root.
+    let untrusted = WebPkiVerifier::new(RootCertStore::empty(), &[]);
+    assert!(
+        untrusted
+            .verify_cert_for_email(
+                &chain[0],
+                &chain[1..],
+                "Postmaster@Example.com",
+                &mut std::iter::empty(),
+                &[],
+                now,
+            )
+            .is_err()
+    );
+}
+
+#[cfg(feature = "dangerous_configuration")]
+fn tlsa_record(usage: u8, selector: u8, matching_type: u8, data: &[u8]) -> TlsaRecord {
+    let mut rdata = vec![usage, selector, matching_type];
+    rdata.extend_from_slice(data);
+    TlsaRecord::from_rdata(&rdata).unwrap()
+}
+
+#[cfg(feature = "dangerous_configuration")]
+fn dane_verify(
+    verifier: &DaneVerifier,
+    chain: &[rustls::Certificate],
+    server: &str,
+) -> Result<(), Error> {
+    verifier
+        .verify_server_cert(
+            &chain[0],
+            &chain[1..],
+            &server_name(server),
+            &mut std::iter::empty(),
+            &[],
+            std::time::SystemTime::now(),
+        )
+        .map(|_| ())
+}
+
+#[cfg(feature = "dangerous_configuration")]
+#[test]
+fn tlsa_records_parse() {
+    assert_eq!(
+        TlsaRecord::from_rdata(&[3, 1, 1, 0xab, 0xcd]),
+        Some(TlsaRecord {
+            usage: TlsaUsage::DaneEe,
+            selector: TlsaSelector::SubjectPublicKeyInfo,
+            matching_type: TlsaMatchingType::Sha256,
+            data: vec![0xab, 0xcd],
+        })
+    );
+    assert_eq!(
+        TlsaRecord::from_rdata(&[2, 0, 2]).map(|r| (r.usage, r.matching_type)),
+        Some((TlsaUsage::DaneTa, TlsaMatchingType::Sha512))
+    );
+
+    // PKIX-TA and PKIX-EE usages are not supported.
+    assert_eq!(TlsaRecord::from_rdata(&[0, 0, 1, 0xab]), None);
+    assert_eq!(TlsaRecord::from_rdata(&[1, 0, 1, 0xab]), None);
+    assert_eq!(TlsaRecord::from_rdata(&[3, 2, 1, 0xab]), None);
+    assert_eq!(TlsaRecord::from_rdata(&[3, 1, 3, 0xab]), None);
+    assert_eq!(TlsaRecord::from_rdata(&[3, 1]), None);
+}
+
+#[cfg(feature = "dangerous_configuration")]
+#[test]
+fn dane_verifier_matches_end_entity_records() {
+    let chain = verifier_chain_for("end");
+    let cert_sha256 = ring::digest::digest(&ring::digest::SHA256, &chain[0].0);
+    // The SHA-256 hash of the certificate's SubjectPublicKeyInfo.
+    let spki_sha256 = [
+        0xde, 0x07, 0x2e, 0x1f, 0xf5, 0x24, 0xff, 0x32, 0x1c, 0x32, 0x25, 0xc1, 0x76, 0x86, 0xe3,
+        0xb8, 0x1d, 0x63, 0xd4, 0x96, 0x6e, 0x90, 0x55, 0x41, 0x0a, 0x47, 0x78, 0x70, 0x4e, 0x55,
+        0x7b, 0xcd,
+    ];
+
+    for record in &[
+        tlsa_record(3, 0, 0, &chain[0].0),
+        tlsa_record(3, 0, 1, cert_sha256.as_ref()),
+        tlsa_record(3, 1, 1, &spki_sha256),
+    ] {
+        let verifier = DaneVerifier::new(vec![record.clone()]);
+        assert_eq!(dane_verify(&verifier, &chain, "testserver.com"), Ok(()));
+
+        // DANE-EE ignores the certificate's names.
+        assert_eq!(dane_verify(&verifier, &chain, "example.com"), Ok(()));
+    }
+
+    // ... and its validity period.
+    let expired = verifier_certs("selfsigned-expired.cert");
+    let verifier = DaneVerifier::new(vec![tlsa_record(3, 0, 0, &expired[0].0)]);
+    assert_eq!(dane_verify(&verifier, &expired, "testserver.com"), Ok(()));
+    assert_eq!(
+        dane_verify(&verifier, &chain, "testserver.com"),
+        Err(Error::NoMatchingTlsaRecord)
+    );
+}
+
+#[cfg(feature = "dangerous_configuration")]
+#[test]
+fn dane_verifier_matches_trust_anchor_records() {
+    let chain = verifier_chain_for("end");
+    let inter_sha512 = ring::digest::digest(&ring::digest::SHA512, &chain[1].0);
+    let verifier = DaneVerifier::new(vec![tlsa_record(2, 0, 2, inter_sha512.as_ref())]);
+
+    assert_eq!(dane_verify(&verifier, &chain, "testserver.com"), Ok(()));
+    assert_eq!(
+        dane_verify(&verifier, &chain, "example.com"),
+        Err(Error::WebPkiError(
+            webpki::Error::CertNotValidForName,
+            WebPkiOp::ValidateForDnsName
+        ))
+    );
+
+    // The anchor must be sent by the server.
+    assert_eq!(
+        dane_verify(&verifier, &chain[..1], "testserver.com"),
+        Err(Error::NoMatchingTlsaRecord)
+    );
+
+    // A DANE-TA record doesn't match the end-entity certificate.
+    let verifier = DaneVerifier::new(vec![tlsa_record(2, 0, 0, &chain[0].0)]);
+    assert_eq!(
+        dane_verify(&verifier, &chain, "testserver.com"),
+        Err(Error::NoMatchingTlsaRecord)
+    );
+}
+
+#[cfg(feature = "dangerous_configuration")]
+#[test]
+fn dane_verifier_can_require_webpki() {
+    let chain = verifier_chain_for("end");
+    let selfsigned = verifier_certs("selfsigned.cert");
+    let webpki = || WebPkiVerifier::new(verifier_roots(), &[]);
+
+    let verifier = DaneVerifier::new(vec![
+        tlsa_record(3, 0, 0, &chain[0].0),
+        tlsa_record(3, 0, 0, &selfsigned[0].0),
+    ])
+    .require_webpki(webpki());
+    assert_eq!(dane_verify(&verifier, &chain, "testserver.com"), Ok(()));
+    assert_eq!(
+        dane_verify(&verifier, &selfsigned, "testserver.com"),
+        Err(Error::WebPkiError(
+            webpki::Error::UnknownIssuer,
+            WebPkiOp::ValidateServerCert
+        ))
+    );
+
+    let verifier =
+        DaneVerifier::new(vec![tlsa_record(3, 0, 0, &selfsigned[0].0)]).require_webpki(webpki());
+    assert_eq!(
+        dane_verify(&verifier, &chain, "testserver.com"),
+        Err(Error::NoMatchingTlsaRecord)
+    );
+
+    // Without any records, DANE doesn't apply.
+    let verifier = DaneVerifier::new(vec![]).require_webpki(webpki());
+    assert_eq!(dane_verify(&verifier, &chain, "testserver.com"), Ok(()));
+    assert_eq!(
+        dane_verify(&DaneVerifier::new(vec![]), &chain, "testserver.com"),
+        Err(Error::NoMatchingTlsaRecord)
+    );
+}
+
+#[test]
+fn webpki_verifier_checks_serial_numbers() {
+    let verify = |verifier: &WebPkiVerifier, name: &str| {
+        let chain = verifier_chain_for(name);
+        verifier
+            .verify_server_cert_detailed(
+                &chain[0],
+                &chain[1..],
+                &server_name("testserver.com"),
+                &mut std::iter::empty(),
+                &[],
+                std::time::SystemTime::now(),
+            )
+            .map(|_| ())
+    };
+
+    let verifier = WebPkiVerifier::new(verifier_roots(), &[]).with_serial_number_check(0);
+    assert_eq!(verify(&verifier, "end-serial-random"), Ok(()));
+    assert_eq!(verify(&verifier, "end"), Ok(()));
+    assert_eq!(
+        verify(&verifier, "end-serial-zero"),
+        Err(Error::InvalidSerialNumber)
+    );
+    assert_eq!(
+        verify(&verifier, "end-serial-long"),
+        Err(Error::InvalidSerialNumber)
+    );
+
+    let verifier = WebPkiVerifier::new(verifier_roots(), &[]).with_serial_number_check(8);
+    assert_eq!(verify(&verifier, "end-serial-random"), Ok(()));
+    assert_eq!(verify(&verifier, "end"), Err(Error::InvalidSerialNumber));
+}
+
+/// An `IntermediateFetcher` serving test-ca/verifier/inter.cert from
+/// the URL in end-aia.cert, and counting the fetches made.
+#[derive(Default)]
+struct TestIntermediateFetcher {
+    fetches: Mutex<Vec<String>>,
+}
+
+impl rustls::IntermediateFetcher for TestIntermediateFetcher {
+    fn fetch_intermediates(
+        &self,
+        _cert: &rustls::Certificate,
+        ca_issuers: &[&str],
+    ) -> Vec<rustls::Certificate> {
+        let mut fetches = self.fetches.lock().unwrap();
+        ca_issuers
+            .iter()
+            .flat_map(|url| {
+                fetches.push(url.to_string());
+                match *url {
+                    "http://ca.example.com/inter.der" => verifier_certs("inter.cert"),
+                    _ => vec![],
+                }
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn webpki_verifier_fetches_missing_intermediates() {
+    let verify = |verifier: &WebPkiVerifier, chain: &[rustls::Certificate]| {
+        verifier
+            .verify_server_cert_detailed(
+                &chain[0],
+                &chain[1..],
+                &server_name("testserver.com"),
+                &mut std::iter::empty(),
+                &[],
+                std::time::SystemTime::now(),
+            )
+            .map(|verified| {
+                verified
+                    .path
+                    .iter()
+                    .map(|cert| cert.position)
+                    .collect::<Vec<_>>()
+            })
+    };
+    let unknown_issuer = Err(Error::WebPkiError(
+        webpki::Error::UnknownIssuer,
+        WebPkiOp::ValidateServerCert,
+    ));
+
+    // Without a fetcher, the intermediate is needed.
+    let end_entity_only = verifier_certs("end-aia.cert");
+    let verifier = WebPkiVerifier::new(verifier_roots(), &[]);
+    assert_eq!(verify(&verifier, &end_entity_only), unknown_issuer);
+
+    let fetcher = Arc::new(TestIntermediateFetcher::default());
+    let verifier = WebPkiVerifier::new(verifier_roots(), &[])
+        .with_intermediate_fetcher(Arc::clone(&fetcher) as Arc<dyn rustls::IntermediateFetcher>);
+    assert!(verifier.policy_summary().intermediate_fetching);
+    assert_eq!(
+        verify(&verifier, &end_entity_only),
+        Ok(vec![
+            ChainPosition::EndEntity,
+            ChainPosition::Intermediate(0),
+            ChainPosition::TrustAnchor,
+        ])
+    );
+    assert_eq!(
+        *fetcher.fetches.lock().unwrap(),
+        vec!["http://ca.example.com/inter.der".to_string()]
+    );
+
+    // Complete chains, and certificates naming no caIssuers, cause no
+    // fetches.
+    fetcher.fetches.lock().unwrap().clear();
+    assert!(verify(&verifier, &verifier_chain_for("end-aia")).is_ok());
+    assert_eq!(
+        verify(&verifier, &verifier_certs("end.cert")),
+        unknown_issuer
+    );
+    assert!(fetcher.fetches.lock().unwrap().is_empty());
+}
+
+#[test]
+fn webpki_verifier_uses_known_intermediates() {
+    let verify = |verifier: &WebPkiVerifier, chain: &[rustls::Certificate]| {
+        verifier
+            .verify_server_cert_detailed(
+                &chain[0],
+                &chain[1..],
+                &server_name("testserver.com"),
+                &mut std::iter::empty(),
+                &[],
+                std::time::SystemTime::now(),
+            )
+            .map(|verified| {
+                verified
+                    .path
+                    .iter()
+                    .map(|cert| cert.position)
+                    .collect::<Vec<_>>()
+            })
+    };
+
+    let mut known = verifier_certs("inter-short.cert");
+    known.extend(verifier_certs("inter.cert"));
+    known.push(rustls::Certificate(b"garbage".to_vec()));
+    let verifier = WebPkiVerifier::new(verifier_roots(), &[]).with_known_intermediates(&known);
+    assert_eq!(verifier.policy_summary().known_intermediate_count, 2);
+
+    assert_eq!(
+        verify(&verifier, &verifier_certs("end.cert")),
+        Ok(vec![
+            ChainPosition::EndEntity,
+            ChainPosition::Intermediate(0),
+            ChainPosition::TrustAnchor,
+        ])
+    );
+    assert_eq!(
+        verify(&verifier, &verifier_certs("end-inter-short.cert")),
+        Ok(vec![
+            ChainPosition::EndEntity,
+            ChainPosition::Intermediate(0),
+            ChainPosition::TrustAnchor,
+        ])
+    );
+
+    // Known intermediates follow those sent by the server.
+    let mut chain = verifier_certs("end-inter-short.cert");
+    chain.extend(verifier_certs("selfsigned.cert"));
+    assert_eq!(
+        verify(&verifier, &chain),
+        Ok(vec![
+            ChainPosition::EndEntity,
+            ChainPosition::Intermediate(1),
+            ChainPosition::TrustAnchor,
+        ])
+    );
+
+    // Known intermediates are tried before fetching.
+    let fetcher = Arc::new(TestIntermediateFetcher::default());
+    let verifier = WebPkiVerifier::new(verifier_roots(), &[])
+        .with_known_intermediates(&known)
+        .with_intermediate_fetcher(Arc::clone(&fetcher) as Arc<dyn rustls::IntermediateFetcher>);
+    assert!(verify(&verifier, &verifier_certs("end-aia.cert")).is_ok());
+    assert!(fetcher.fetches.lock().unwrap().is_empty());
+}
+
+#[test]
+fn webpki_verifier_consults_revocation_filter() {
+    let verify = |verifier: &WebPkiVerifier, name: &str| {
+        let chain = verifier_chain_for(name);
+        verifier
+            .verify_server_cert_detailed(
+                &chain[0],
+                &chain[1..],
+                &server_name("testserver.com"),
+                &mut std::iter::empty(),
+                &[],
+                std::time::SystemTime::now(),
+            )
+            .map(|_| ())
+    };
+
+    let inter = &verifier_certs("inter.cert")[0];
+    let spki = webpki::TrustAnchor::try_from_cert_der(&inter.0)
+        .unwrap()
+        .spki;
+    let inter_spki = [&[0x30, 0x82, (spki.len() >> 8) as u8, spki.len() as u8][..], spki].concat();
+
+    // test-ca/verifier/end.cert has serial 456, and end-ip.cert 470.
+    let cascade = RevocationFilterCascade::build(
+        &[(&inter_spki, &[0x01, 0xc8])],
+        &[(&inter_spki, &[0x01, 0xd6])],
+    )
+    .unwrap();
+    let cascade = RevocationFilterCascade::from_bytes(&cascade.to_bytes()).unwrap();
+
+    let verifier = WebPkiVerifier::new(verifier_roots(), &[])
+        .with_revocation_filter(Arc::new(cascade) as Arc<dyn rustls::RevocationFilter>);
+    assert!(verifier.policy_summary().revocation_filter);
+    assert_eq!(verify(&verifier, "end"), Err(Error::CertificateRevoked));
+    assert_eq!(verify(&verifier, "end-ip"), Ok(()));
+}
+
+#[test]
+fn webpki_verifier_rejects_certificates_issued_before_cutoff() {
+    // test-ca/verifier/end.cert is valid from 2020 to 2050.
+    let before_issue = std::time::UNIX_EPOCH + Duration::from_secs(1567296000); // 2019-09-01
+    let after_issue = std::time::UNIX_EPOCH + Duration::from_secs(1598918400); // 2020-09-01
+
+    let chain = verifier_chain();
+    let verify = |cutoff| {
+        WebPkiVerifier::new(verifier_roots(), &[])
+            .with_min_notbefore(cutoff)
+            .verify_server_cert_detailed(
+                &chain[0],
+                &chain[1..],
+                &server_name("testserver.com"),
+                &mut std::iter::empty(),
+                &[],
+                std::time::SystemTime::now(),
+            )
+            .map(|_| ())
+    };
+
+    assert_eq!(verify(before_issue), Ok(()));
+    assert_eq!(verify(after_issue), Err(Error::CertificateIssuedTooEarly));
+}
+
+#[test]
+fn webpki_verifier_tolerates_clock_skew() {
+    // test-ca/verifier certificates are valid from 2020 to 2050.
+    let not_before = std::time::UNIX_EPOCH + Duration::from_secs(1577836800); // 2020-01-01
+    let not_after = std::time::UNIX_EPOCH + Duration::from_secs(2524608000); // 2050-01-01
+    let minute = Duration::from_secs(60);
+
+    let chain = verifier_chain();
+    let verify = |verifier: &WebPkiVerifier, now| {
+        verifier
+            .verify_server_cert_detailed(
+                &chain[0],
+                &chain[1..],
+                &server_name("testserver.com"),
+                &mut std::iter::empty(),
+                &[],
+                now,
+            )
+            .map(|_| ())
+    };
+    let not_yet_valid = Err(Error::WebPkiError(
+        webpki::Error::CertNotValidYet,
+        WebPkiOp::ValidateServerCert,
+    ));
+    let expired = Err(Error::WebPkiError(
+        webpki::Error::CertExpired,
+        WebPkiOp::ValidateServerCert,
+    ));
+
+    let verifier = WebPkiVerifier::new(verifier_roots(), &[]);
+    assert_eq!(verify(&verifier, not_before - minute), not_yet_valid);
+    assert_eq!(verify(&verifier, not_after + minute), expired);
+
+    let verifier = verifier.with_clock_skew_tolerance(5 * minute);
+    assert_eq!(verify(&verifier, not_before - minute), Ok(()));
+    assert_eq!(verify(&verifier, not_after + minute), Ok(()));
+    assert_eq!(verify(&verifier, not_before - 10 * minute), not_yet_valid);
+    assert_eq!(verify(&verifier, not_after + 10 * minute), expired);
+    assert_eq!(
+        verifier
+            .policy_summary()
+            .clock_skew_tolerance,
+        Some(5 * minute)
+    );
+}
+
+#[test]
+fn webpki_verifier_accepts_expired_chains_with_pinned_keys() {
+    // test-ca/verifier certificates expire at the start of 2050.
+    let expired_time = std::time::UNIX_EPOCH + Duration::from_secs(2524608000 + 86400);
+
+    let verify = |verifier: &WebPkiVerifier, name: &str, server: &str| {
+        let chain = verifier_chain_for(name);
+        verifier
+            .verify_server_cert_detailed(
+                &chain[0],
+                &chain[1..],
+                &server_name(server),
+                &mut std::iter::empty(),
+                &[],
+                expired_time,
+            )
+            .map(|_| ())
+    };
+    let pin = |spki: &[u8]| {
+        let mut pin = [0u8; 32];
+        pin.copy_from_slice(ring::digest::digest(&ring::digest::SHA256, spki).as_ref());
+        pin
+    };
+    let expired = Err(Error::WebPkiError(
+        webpki::Error::CertExpired,
+        WebPkiOp::ValidateServerCert,
+    ));
+
+    let chain = verifier_chain();
+    let end_pin = pin(&WebPkiVerifier::new(verifier_roots(), &[])
+        .verify_server_cert_detailed(
+            &chain[0],
+            &chain[1..],
+            &server_name("testserver.com"),
+            &mut std::iter::empty(),
+            &[],
+            std::time::SystemTime::now(),
+        )
+        .unwrap()
+        .end_entity_spki);
+    let ca_pin = {
+        let ca = verifier_certs("ca.cert");
+        let ca = webpki::TrustAnchor::try_from_cert_der(&ca[0].0).unwrap();
+        let len = ca.spki.len();
+        pin(&[&[0x30, 0x82, (len >> 8) as u8, len as u8], ca.spki].concat())
+    };
+
+    let verifier = WebPkiVerifier::new(verifier_roots(), &[]);
+    assert_eq!(verify(&verifier, "end", "testserver.com"), expired);
+
+    let verifier = WebPkiVerifier::new(verifier_roots(), &[]).with_expiry_override_pins(&[end_pin]);
+    assert_eq!(verify(&verifier, "end", "testserver.com"), Ok(()));
+    assert_eq!(verify(&verifier, "spiffe", "testserver.com"), expired);
+    assert_eq!(
+        verify(&verifier, "end", "example.com"),
+        Err(Error::WebPkiError(
+            webpki::Error::CertNotValidForName,
+            WebPkiOp::ValidateForDnsName
+        ))
+    );
+    assert_eq!(
+        verifier
+            .policy_summary()
+            .expiry_override_pin_count,
+        1
+    );
+
+    let verifier = WebPkiVerifier::new(verifier_roots(), &[]).with_expiry_override_pins(&[ca_pin]);
+    assert_eq!(verify(&verifier, "end", "testserver.com"), Ok(()));
+    assert_eq!(verify(&verifier, "end-ip", "198.51.100.1"), Ok(()));
+
+    let verifier =
+        WebPkiVerifier::new(verifier_roots(), &[]).with_expiry_override_pins(&[pin(b"other")]);
+    assert_eq!(verify(&verifier, "end", "testserver.com"), expired);
+}
+
+#[test]
+fn webpki_verifier_enforces_must_staple() {
+    let verify = |verifier: &WebPkiVerifier, name: &str, ocsp: &[u8]| {
+        let chain = verifier_chain_for(name);
+        verifier
+            .verify_server_cert_detailed(
+                &chain[0],
+                &chain[1..],
+                &server_name("testserver.com"),
+                &mut std::iter::empty(),
+                ocsp,
+                std::time::SystemTime::now(),
+            )
+            .map(|verified| verified.revocation)
+    };
+    let ocsp = bytes_for("verifier", "end.ocsp-sha256");
+
+    let verifier = WebPkiVerifier::new(verifier_roots(), &[]);
+    assert_eq!(
+        verify(&verifier, "end-must-staple", &[]),
+        Ok(RevocationStatus::NotChecked)
+    );
+
+    let unvalidated = WebPkiVerifier::new(verifier_roots(), &[]).with_must_staple();
+    assert!(matches!(
+        verify(&unvalidated, "end-must-staple", b"garbage"),
+        Err(Error::InvalidOcspResponse(_))
+    ));
+    assert_eq!(
+        verify(&unvalidated, "end-must-staple", ocsp),
+        Ok(RevocationStatus::Checked(CertStatus::Good))
+    );
+    assert_eq!(
+        verify(&unvalidated, "end", b"garbage"),
+        Ok(RevocationStatus::NotChecked)
+    );
+
+    let verifier = verifier
+        .with_ocsp_policy(OcspPolicy::new())
+        .with_ocsp_soft_fail(|_, _| {})
+        .with_must_staple();
+    assert_eq!(
+        verify(&verifier, "end-must-staple", &[]),
+        Err(Error::OcspStapleRequired)
+    );
+    assert_eq!(
+        verify(&verifier, "end-must-staple", ocsp),
+        Ok(RevocationStatus::Checked(CertStatus::Good))
+    );
+    assert!(matches!(
+        verify(&verifier, "end-must-staple", &ocsp[..ocsp.len() - 1]),
+        Err(Error::InvalidOcspResponse(_))
+    ));
+    assert_eq!(
+        verify(&verifier, "end", &[]),
+        Ok(RevocationStatus::NotChecked)
+    );
+}
+
+#[test]
+fn webpki_verifier_summarises_policy() {
+    let policy = WebPkiVerifier::new(verifier_roots(), &[]).policy_summary();
+    assert_eq!(policy.root_count, 1);
+    assert_eq!(policy.ct_log_count, 0);
+    assert_eq!(policy.ct_policy, CtPolicy::new());
+    assert_eq!(
+        policy.signature_schemes,
+        WebPkiVerifier::verification_schemes()
+    );
+    assert_eq!(
+        policy.chain_signature_schemes,
+        WebPkiVerifier::verification_schemes()
+    );
+    assert_eq!(policy.ocsp_policy, None);
+    assert!(!policy.ocsp_soft_fail);
+    assert_eq!(policy.allowed_key_algorithms, None);
+    assert_eq!(policy.validity_policy, None);
+    assert_eq!(policy.min_not_before, None);
+    assert_eq!(policy.clock_skew_tolerance, None);
+    assert!(!policy.anchor_usage_tracking);
+    assert!(!policy.aki_ski_check);
+    assert!(!policy.eku_chaining_check);
+    assert!(!policy.must_staple);
+    assert_eq!(policy.public_suffix_count, None);
+    assert_eq!(policy.serial_number_min_octets, None);
+    assert!(!policy.intermediate_fetching);
+    assert_eq!(policy.known_intermediate_count, 0);
+    assert!(!policy.revocation_filter);
+
+    let ocsp_policy = OcspPolicy {
+        signature_schemes: Some(vec![SignatureScheme::ECDSA_NISTP384_SHA384]),
+        unknown_status: OcspUnknownStatus::Reject,
+        require_responder_nocheck: true,
+    };
+    let policy = WebPkiVerifier::new(verifier_roots(), &[])
+        .with_ocsp_policy(ocsp_policy.clone())
+        .with_ocsp_soft_fail(|_, _| {})
+        .with_allowed_key_algorithms(&[SignatureAlgorithm::ECDSA])
+        .with_anchor_usage_tracking()
+        .with_aki_ski_check()
+        .with_eku_chaining_check()
+        .with_must_staple()
+        .with_public_suffixes(&["co.uk"])
+        .with_serial_number_check(8)
+        .with_min_notbefore(std::time::UNIX_EPOCH)
+        .with_signature_algorithms(RSA_PKCS1_SHA256_ONLY)
+        .with_ct_policy(CtPolicy {
+            max_scts: 4,
+            ..CtPolicy::new()
+        })
+        .policy_summary();
+    assert_eq!(policy.ct_policy.max_scts, 4);
+    assert_eq!(
+        policy.chain_signature_schemes,
+        vec![SignatureScheme::RSA_PKCS1_SHA256]
+    );
+    assert_eq!(policy.ocsp_policy, Some(ocsp_policy));
+    assert!(policy.ocsp_soft_fail);
+    assert_eq!(
+        policy.allowed_key_algorithms,
+        Some(vec![SignatureAlgorithm::ECDSA])
+    );
+    assert!(policy.anchor_usage_tracking);
+    assert!(policy.aki_ski_check);
+    assert!(policy.eku_chaining_check);
+    assert!(policy.must_staple);
+    assert_eq!(policy.public_suffix_count, Some(1));
+    assert_eq!(policy.serial_number_min_octets, Some(8));
+    assert_eq!(policy.min_not_before, Some(std::time::UNIX_EPOCH));
+
+    let no_revocations = RevocationFilterCascade::build(&[], &[]).unwrap();
+    let policy = WebPkiVerifier::builder(verifier_roots())
+        .with_signature_schemes(&[SignatureScheme::ED25519])
+        .with_signature_algorithms(RSA_PKCS1_SHA256_ONLY)
+        .with_revocation_filter(Arc::new(no_revocations))
+        .with_must_staple()
+        .with_ocsp_soft_fail(|_, _| {})
+        .build()
+        .policy_summary();
+    assert_eq!(policy.signature_schemes, vec![SignatureScheme::ED25519]);
+    assert_eq!(
+        policy.chain_signature_schemes,
+        vec![SignatureScheme::RSA_PKCS1_SHA256]
+    );
+    assert!(policy.revocation_filter);
+    assert!(policy.must_staple);
+    assert!(policy.ocsp_soft_fail);
+}
+
+#[test]
+fn webpki_verifier_tracks_anchor_usage() {
+    let mut roots = verifier_roots();
+    let mut rootbuf = io::BufReader::new(bytes_for("rsa", "ca.cert"));
+    roots.add_parsable_certificates(&rustls_pemfile::certs(&mut rootbuf).unwrap());
+
+    let verifier = Arc::new(WebPkiVerifier::new(roots, &[]).with_anchor_usage_tracking());
+    let stats = verifier.anchor_usage_stats();
+    assert_eq!(stats.len(), 2);
+    assert!(stats
+        .iter()
+        .all(|s| s.count == 0 && s.last_used.is_none()));
+
+    // Separate client configs, so the second handshake does not resume.
+    for _ in 0..2 {
+        let client_config = make_client_config_with_verifier(verifier.clone());
+        let server_config = make_verifier_server_config(&[]);
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+    }
+
+    let stats = verifier.anchor_usage_stats();
+    assert_eq!(stats[0].count, 2);
+    assert!(stats[0].first_used.is_some());
+    assert!(stats[0].first_used <= stats[0].last_used);
+    assert_eq!(stats[1].count, 0);
+    assert_eq!(stats[1].first_used, None);
+    assert_ne!(stats[0].spki_sha256, stats[1].spki_sha256);
+}
+
+#[test]
+fn webpki_verifier_reports_no_anchor_usage_by_default() {
+    let verifier = WebPkiVerifier::new(verifier_roots(), &[]);
+    assert!(verifier.anchor_usage_stats().is_empty());
+}
+
+#[test]
+fn client_error_is_sticky() {
+    let (mut client, _) = make_pair(KeyType::RSA);
+    client
+        .read_tls(&mut b"\x16\x03\x03\x00\x08\x0f\x00\x00\x04junk".as_ref())
+        .unwrap();
+    let mut err = client.process_new_packets();
+    assert_eq!(err.is_err(), true);
+    err = client.process_new_packets();
+    assert_eq!(err.is_err(), true);
+}
+
+#[test]
+fn server_error_is_sticky() {
+    let (_, mut server) = make_pair(KeyType::RSA);
+    server
+        .read_tls(&mut b"\x16\x03\x03\x00\x08\x0f\x00\x00\x04junk".as_ref())
+        .unwrap();
+    let mut err = server.process_new_packets();
+    assert_eq!(err.is_err(), true);
+    err = server.process_new_packets();
+    assert_eq!(err.is_err(), true);
+}
+
+#[test]
+fn server_flush_does_nothing() {
+    let (_, mut server) = make_pair(KeyType::RSA);
+    assert!(matches!(server.writer().flush(), Ok(())));
+}
+
+#[test]
+fn client_flush_does_nothing() {
+    let (mut client, _) = make_pair(KeyType::RSA);
+    assert!(matches!(client.writer().flush(), Ok(())));
+}
+
+#[test]
+fn server_is_send_and_sync() {
+    let (_, server) = make_pair(KeyType::RSA);
+    &server as &dyn Send;
+    &server as &dyn Sync;
+}
+
+#[test]
+fn client_is_send_and_sync() {
+    let (client, _) = make_pair(KeyType::RSA);
+    &client as &dyn Send;
+    &client as &dyn Sync;
+}
+
+#[test]
+fn server_respects_buffer_limit_pre_handshake() {
+    let (mut client, mut server) = make_pair(KeyType::RSA);
+
+    server.set_buffer_limit(32);
+
+    assert_eq!(
+        server
+            .writer()
+            .write(b"01234567890123456789")
+            .unwrap(),
+        20
+    );
+    assert_eq!(
+        server
+            .writer()
+            .write(b"01234567890123456789")
+            .unwrap(),
+        12
+    );
+
+    do_handshake(&mut client, &mut server);
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+
+    check_read(&mut client.reader(), b"01234567890123456789012345678901");
+}
+
+#[test]
+fn server_respects_buffer_limit_pre_handshake_with_vectored_write() {
+    let (mut client, mut server) = make_pair(KeyType::RSA);
+
+    server.set_buffer_limit(32);
+
+    assert_eq!(
+        server
+            .writer()
+            .write_vectored(&[
+                IoSlice::new(b"01234567890123456789"),
+                IoSlice::new(b"01234567890123456789")
+            ])
+            .unwrap(),
+        32
+    );
+
+    do_handshake(&mut client, &mut server);
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+
+    check_read(&mut client.reader(), b"01234567890123456789012345678901");
+}
+
+#[test]
+fn server_respects_buffer_limit_post_handshake() {
+    let (mut client, mut server) = make_pair(KeyType::RSA);
+
+    // this test will vary in behaviour depending on the default suites
+    do_handshake(&mut client, &mut server);
+    server.set_buffer_limit(48);
+
+    assert_eq!(
+        server
+            .writer()
+            .write(b"01234567890123456789")
+            .unwrap(),
+        20
+    );
+    assert_eq!(
+        server
+            .writer()
+            .write(b"01234567890123456789")
+            .unwrap(),
+        6
+    );
+
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+
+    check_read(&mut client.reader(), b"01234567890123456789012345");
+}
+
+#[test]
+fn client_respects_buffer_limit_pre_handshake() {
+    let (mut client, mut server) = make_pair(KeyType::RSA);
+
+    client.set_buffer_limit(32);
+
+    assert_eq!(
+        client
+            .writer()
+            .write(b"01234567890123456789")
+            .unwrap(),
+        20
+    );
+    assert_eq!(
+        client
+            .writer()
+            .write(b"01234567890123456789")
+            .unwrap(),
+        12
+    );
+
+    do_handshake(&mut client, &mut server);
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+
+    check_read(&mut server.reader(), b"01234567890123456789012345678901");
+}
+
+#[test]
+fn client_respects_buffer_limit_pre_handshake_with_vectored_write() {
+    let (mut client, mut server) = make_pair(KeyType::RSA);
+
+    client.set_buffer_limit(32);
+
+    assert_eq!(
+        client
+            .writer()
+            .write_vectored(&[
+                IoSlice::new(b"01234567890123456789"),
+                IoSlice::new(b"01234567890123456789")
+            ])
+            .unwrap(),
+        32
+    );
+
+    do_handshake(&mut client, &mut server);
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+
+    check_read(&mut server.reader(), b"01234567890123456789012345678901");
+}
+
+#[test]
+fn client_respects_buffer_limit_post_handshake() {
+    let (mut client, mut server) = make_pair(KeyType::RSA);
+
+    do_handshake(&mut client, &mut server);
+    client.set_buffer_limit(48);
+
+    assert_eq!(
+        client
+            .writer()
+            .write(b"01234567890123456789")
+            .unwrap(),
+        20
+    );
+    assert_eq!(
+        client
+            .writer()
+            .write(b"01234567890123456789")
+            .unwrap(),
+        6
+    );
+
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+
+    check_read(&mut server.reader(), b"01234567890123456789012345");
+}
+
+struct OtherSession<'a> {
+    sess: &'a mut dyn Connection,
+    pub reads: usize,
+    pub writevs: Vec<Vec<usize>>,
+    fail_ok: bool,
+    pub short_writes: bool,
+    pub last_error: Option<rustls::Error>,
+}
+
+impl<'a> OtherSession<'a> {
+    fn new(sess: &'a mut dyn Connection) -> OtherSession<'a> {
+        OtherSession {
+            sess,
+            reads: 0,
+            writevs: vec![],
+            fail_ok: false,
+            short_writes: false,
+            last_error: None,
+        }
+    }
+
+    fn new_fails(sess: &'a mut dyn Connection) -> OtherSession<'a> {
+        let mut os = OtherSession::new(sess);
+        os.fail_ok = true;
+        os
+    }
+}
+
+impl<'a> io::Read for OtherSession<'a> {
+    fn read(&mut self, mut b: &mut [u8]) -> io::Result<usize> {
+        self.reads += 1;
+        self.sess.write_tls(b.by_ref())
+    }
+}
+
+impl<'a> io::Write for OtherSession<'a> {
+    fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+        unreachable!()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write_vectored<'b>(&mut self, b: &[io::IoSlice<'b>]) -> io::Result<usize> {
+        let mut total = 0;
+        let mut lengths = vec![];
+        for bytes in b {
+            let write_len = if self.short_writes {
+                if bytes.len() > 5 {
+                    bytes.len() / 2
+                } else {
+                    bytes.len()
+                }
+            } else {
+                bytes.len()
+            };
+
+            let l = self
+                .sess
+                .read_tls(&mut io::Cursor::new(&bytes[..write_len]))?;
+            lengths.push(l);
+            total += l;
+            if bytes.len() != l {
+                break;
+            }
+        }
+
+        let rc = self.sess.process_new_packets();
+        if !self.fail_ok {
+            rc.unwrap();
+        } else if rc.is_err() {
+            self.last_error = rc.err();
+        }
+
+        self.writevs.push(lengths);
+        Ok(total)
+    }
+}
+
+#[test]
+fn server_read_returns_wouldblock_when_no_data() {
+    let (_, mut server) = make_pair(KeyType::RSA);
+    assert!(matches!(server.reader().read(&mut [0u8; 1]),
+                     Err(err) if err.kind() == io::ErrorKind::WouldBlock));
+}
+
+#[test]
+fn client_read_returns_wouldblock_when_no_data() {
+    let (mut client, _) = make_pair(KeyType::RSA);
+    assert!(matches!(client.reader().read(&mut [0u8; 1]),
+                     Err(err) if err.kind() == io::ErrorKind::WouldBlock));
+}
+
+#[test]
+fn new_server_returns_initial_io_state() {
+    let (_, mut server) = make_pair(KeyType::RSA);
+    let io_state = server.process_new_packets().unwrap();
+    println!("IoState is Debug {:?}", io_state);
+    assert_eq!(io_state.plaintext_bytes_to_read(), 0);
+    assert_eq!(io_state.peer_has_closed(), false);
+    assert_eq!(io_state.tls_bytes_to_write(), 0);
+}
+
+#[test]
+fn new_client_returns_initial_io_state() {
+    let (mut client, _) = make_pair(KeyType::RSA);
+    let io_state = client.process_new_packets().unwrap();
+    println!("IoState is Debug {:?}", io_state);
+    assert_eq!(io_state.plaintext_bytes_to_read(), 0);
+    assert_eq!(io_state.peer_has_closed(), false);
+    assert!(io_state.tls_bytes_to_write() > 200);
+}
+
+#[test]
+fn client_complete_io_for_handshake() {
+    let (mut client, mut server) = make_pair(KeyType::RSA);
+
+    assert_eq!(true, client.is_handshaking());
+    let (rdlen, wrlen) = client
+        .complete_io(&mut OtherSession::new(&mut server))
+        .unwrap();
+    assert!(rdlen > 0 && wrlen > 0);
+    assert_eq!(false, client.is_handshaking());
+}
+
+#[test]
+fn client_complete_io_for_handshake_eof() {
+    let (mut client, _) = make_pair(KeyType::RSA);
+    let mut input = io::Cursor::new(Vec::new());
+
+    assert_eq!(true, client.is_handshaking());
+    let err = client
+        .complete_io(&mut input)
+        .unwrap_err();
+    assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+}
+
+#[test]
+fn client_complete_io_for_write() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let (mut client, mut server) = make_pair(*kt);
+
+        do_handshake(&mut client, &mut server);
+
+        client
+            .writer()
+            .write(b"01234567890123456789")
+            .unwrap();
+        client
+            .writer()
+            .write(b"01234567890123456789")
+            .unwrap();
+        {
+            let mut pipe = OtherSession::new(&mut server);
+            let (rdlen, wrlen) = client.complete_io(&mut pipe).unwrap();
+            assert!(rdlen == 0 && wrlen > 0);
+            println!("{:?}", pipe.writevs);
+            assert_eq!(pipe.writevs, vec![vec![42, 42]]);
+        }
+        check_read(
+            &mut server.reader(),
+            b"0123456789012345678901234567890123456789",
+        );
+    }
+}
+
+#[test]
+fn client_complete_io_for_read() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let (mut client, mut server) = make_pair(*kt);
+
+        do_handshake(&mut client, &mut server);
+
+        server
+            .writer()
+            .write(b"01234567890123456789")
+            .unwrap();
+        {
+            let mut pipe = OtherSession::new(&mut server);
+            let (rdlen, wrlen) = client.complete_io(&mut pipe).unwrap();
+            assert!(rdlen > 0 && wrlen == 0);
+            assert_eq!(pipe.reads, 1);
+        }
+        check_read(&mut client.reader(), b"01234567890123456789");
+    }
+}
+
+#[test]
+fn server_complete_io_for_handshake() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let (mut client, mut server) = make_pair(*kt);
+
+        assert_eq!(true, server.is_handshaking());
+        let (rdlen, wrlen) = server
+            .complete_io(&mut OtherSession::new(&mut client))
+            .unwrap();
+        assert!(rdlen > 0 && wrlen > 0);
+        assert_eq!(false, server.is_handshaking());
+    }
+}
+
+#[test]
+fn server_complete_io_for_handshake_eof() {
+    let (_, mut server) = make_pair(KeyType::RSA);
+    let mut input = io::Cursor::new(Vec::new());
+
+    assert_eq!(true, server.is_handshaking());
+    let err = server
+        .complete_io(&mut input)
+        .unwrap_err();
+    assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+}
+
+#[test]
+fn server_complete_io_for_write() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let (mut client, mut server) = make_pair(*kt);
+
+        do_handshake(&mut client, &mut server);
+
+        server
+            .writer()
+            .write(b"01234567890123456789")
+            .unwrap();
+        server
+            .writer()
+            .write(b"01234567890123456789")
+            .unwrap();
+        {
+            let mut pipe = OtherSession::new(&mut client);
+            let (rdlen, wrlen) = server.complete_io(&mut pipe).unwrap();
+            assert!(rdlen == 0 && wrlen > 0);
+            assert_eq!(pipe.writevs, vec![vec![42, 42]]);
+        }
+        check_read(
+            &mut client.reader(),
+            b"0123456789012345678901234567890123456789",
+        );
+    }
+}
+
+#[test]
+fn server_complete_io_for_read() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let (mut client, mut server) = make_pair(*kt);
+
+        do_handshake(&mut client, &mut server);
+
+        client
+            .writer()
+            .write(b"01234567890123456789")
+            .unwrap();
+        {
+            let mut pipe = OtherSession::new(&mut client);
+            let (rdlen, wrlen) = server.complete_io(&mut pipe).unwrap();
+            assert!(rdlen > 0 && wrlen == 0);
+            assert_eq!(pipe.reads, 1);
+        }
+        check_read(&mut server.reader(), b"01234567890123456789");
+    }
+}
+
+#[test]
+fn client_stream_write() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let (mut client, mut server) = make_pair(*kt);
+
+        {
+            let mut pipe = OtherSession::new(&mut server);
+            let mut stream = Stream::new(&mut client, &mut pipe);
+            assert_eq!(stream.write(b"hello").unwrap(), 5);
+        }
+        check_read(&mut server.reader(), b"hello");
+    }
+}
+
+#[test]
+fn client_streamowned_write() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let (client, mut server) = make_pair(*kt);
+
+        {
+            let pipe = OtherSession::new(&mut server);
+            let mut stream = StreamOwned::new(client, pipe);
+            assert_eq!(stream.write(b"hello").unwrap(), 5);
+        }
+        check_read(&mut server.reader(), b"hello");
+    }
+}
+
+#[test]
+fn client_stream_read() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let (mut client, mut server) = make_pair(*kt);
+
+        server.writer().write(b"world").unwrap();
+
+        {
+            let mut pipe = OtherSession::new(&mut server);
+            let mut stream = Stream::new(&mut client, &mut pipe);
+            check_read(&mut stream, b"world");
+        }
+    }
+}
+
+#[test]
+fn client_streamowned_read() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let (client, mut server) = make_pair(*kt);
+
+        server.writer().write(b"world").unwrap();
+
+        {
+            let pipe = OtherSession::new(&mut server);
+            let mut stream = StreamOwned::new(client, pipe);
+            check_read(&mut stream, b"world");
+        }
+    }
+}
+
+#[test]
+fn server_stream_write() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let (mut client, mut server) = make_pair(*kt);
+
+        {
+            let mut pipe = OtherSession::new(&mut client);
+            let mut stream = Stream::new(&mut server, &mut pipe);
+            assert_eq!(stream.write(b"hello").unwrap(), 5);
+        }
+        check_read(&mut client.reader(), b"hello");
+    }
+}
+
+#[test]
+fn server_streamowned_write() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let (mut client, server) = make_pair(*kt);
+
+        {
+            let pipe = OtherSession::new(&mut client);
+            let mut stream = StreamOwned::new(server, pipe);
+            assert_eq!(stream.write(b"hello").unwrap(), 5);
+        }
+        check_read(&mut client.reader(), b"hello");
+    }
+}
+
+#[test]
+fn server_stream_read() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let (mut client, mut server) = make_pair(*kt);
+
+        client.writer().write(b"world").unwrap();
+
+        {
+            let mut pipe = OtherSession::new(&mut client);
+            let mut stream = Stream::new(&mut server, &mut pipe);
+            check_read(&mut stream, b"world");
+        }
+    }
+}
+
+#[test]
+fn server_streamowned_read() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let (mut client, server) = make_pair(*kt);
+
+        client.writer().write(b"world").unwrap();
+
+        {
+            let pipe = OtherSession::new(&mut client);
+            let mut stream = StreamOwned::new(server, pipe);
+            check_read(&mut stream, b"world");
+        }
+    }
+}
+
+struct FailsWrites {
+    errkind: io::ErrorKind,
+    after: usize,
+}
+
+impl io::Read for FailsWrites {
+    fn read(&mut self, _b: &mut [u8]) -> io::Result<usize> {
+        Ok(0)
+    }
+}
+
+impl io::Write for FailsWrites {
+    fn write(&mut self, b: &[u8]) -> io::Result<usize> {
+        if self.after > 0 {
+            self.after -= 1;
+            Ok(b.len())
+        } else {
+            Err(io::Error::new(self.errkind, "oops"))
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn stream_write_reports_underlying_io_error_before_plaintext_processed() {
+    let (mut client, mut server) = make_pair(KeyType::RSA);
+    do_handshake(&mut client, &mut server);
+
+    let mut pipe = FailsWrites {
+        errkind: io::ErrorKind::ConnectionAborted,
+        after: 0,
+    };
+    client.writer().write(b"hello").unwrap();
+    let mut client_stream = Stream::new(&mut client, &mut pipe);
+    let rc = client_stream.write(b"world");
+    assert!(rc.is_err());
+    let err = rc.err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+}
+
+#[test]
+fn stream_write_swallows_underlying_io_error_after_plaintext_processed() {
+    let (mut client, mut server) = make_pair(KeyType::RSA);
+    do_handshake(&mut client, &mut server);
+
+    let mut pipe = FailsWrites {
+        errkind: io::ErrorKind::ConnectionAborted,
+        after: 1,
+    };
+    client.writer().write(b"hello").unwrap();
+    let mut client_stream = Stream::new(&mut client, &mut pipe);
+    let rc = client_stream.write(b"world");
+    assert_eq!(format!("{:?}", rc), "Ok(5)");
+}
+
+fn make_disjoint_suite_configs() -> (ClientConfig, ServerConfig) {
+    let kt = KeyType::RSA;
+    let mut server_config = make_server_config(kt);
+    server_config.cipher_suites = vec![find_suite(
+        CipherSuite::TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+    )];
+
+    let mut client_config = make_client_config(kt);
+    client_config.cipher_suites = vec![find_suite(
+        CipherSuite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+    )];
+
+    (client_config, server_config)
+}
+
+#[test]
+fn client_stream_handshake_error() {
+    let (client_config, server_config) = make_disjoint_suite_configs();
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+
+    {
+        let mut pipe = OtherSession::new_fails(&mut server);
+        let mut client_stream = Stream::new(&mut client, &mut pipe);
+        let rc = client_stream.write(b"hello");
+        assert!(rc.is_err());
+        assert_eq!(
+            format!("{:?}", rc),
+            "Err(Custom { kind: InvalidData, error: AlertReceived(HandshakeFailure) })"
+        );
+        let rc = client_stream.write(b"hello");
+        assert!(rc.is_err());
+        assert_eq!(
+            format!("{:?}", rc),
+            "Err(Custom { kind: InvalidData, error: AlertReceived(HandshakeFailure) })"
+        );
+    }
+}
+
+#[test]
+fn client_streamowned_handshake_error() {
+    let (client_config, server_config) = make_disjoint_suite_configs();
+    let (client, mut server) = make_pair_for_configs(client_config, server_config);
+
+    let pipe = OtherSession::new_fails(&mut server);
+    let mut client_stream = StreamOwned::new(client, pipe);
+    let rc = client_stream.write(b"hello");
+    assert!(rc.is_err());
+    assert_eq!(
+        format!("{:?}", rc),
+        "Err(Custom { kind: InvalidData, error: AlertReceived(HandshakeFailure) })"
+    );
+    let rc = client_stream.write(b"hello");
+    assert!(rc.is_err());
+    assert_eq!(
+        format!("{:?}", rc),
+        "Err(Custom { kind: InvalidData, error: AlertReceived(HandshakeFailure) })"
+    );
+}
+
+#[test]
+fn server_stream_handshake_error() {
+    let (client_config, server_config) = make_disjoint_suite_configs();
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+
+    client.writer().write(b"world").unwrap();
+
+    {
+        let mut pipe = OtherSession::new_fails(&mut client);
+        let mut server_stream = Stream::new(&mut server, &mut pipe);
+        let mut bytes = [0u8; 5];
+        let rc = server_stream.read(&mut bytes);
+        assert!(rc.is_err());
+        assert_eq!(
+            format!("{:?}", rc),
+            "Err(Custom { kind: InvalidData, error: PeerIncompatibleError(\"no ciphersuites in common\") })"
+        );
+    }
+}
+
+#[test]
+fn server_streamowned_handshake_error() {
+    let (client_config, server_config) = make_disjoint_suite_configs();
+    let (mut client, server) = make_pair_for_configs(client_config, server_config);
+
+    client.writer().write(b"world").unwrap();
+
+    let pipe = OtherSession::new_fails(&mut client);
+    let mut server_stream = StreamOwned::new(server, pipe);
+    let mut bytes = [0u8; 5];
+    let rc = server_stream.read(&mut bytes);
+    assert!(rc.is_err());
+    assert_eq!(
+        format!("{:?}", rc),
+        "Err(Custom { kind: InvalidData, error: PeerIncompatibleError(\"no ciphersuites in common\") })"
+    );
+}
+
+#[test]
+fn server_config_is_clone() {
+    let _ = make_server_config(KeyType::RSA).clone();
+}
+
+#[test]
+fn client_config_is_clone() {
+    let _ = make_client_config(KeyType::RSA).clone();
+}
+
+#[test]
+fn client_connection_is_debug() {
+    let (client, _) = make_pair(KeyType::RSA);
+    println!("{:?}", client);
+}
+
+#[test]
+fn server_connection_is_debug() {
+    let (_, server) = make_pair(KeyType::RSA);
+    println!("{:?}", server);
+}
+
+#[test]
+fn server_complete_io_for_handshake_ending_with_alert() {
+    let (client_config, server_config) = make_disjoint_suite_configs();
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+
+    assert_eq!(true, server.is_handshaking());
+
+    let mut pipe = OtherSession::new_fails(&mut client);
+    let rc = server.complete_io(&mut pipe);
+    assert!(rc.is_err(), "server io failed due to handshake failure");
+    assert!(!server.wants_write(), "but server did send its alert");
+    assert_eq!(
+        format!("{:?}", pipe.last_error),
+        "Some(AlertReceived(HandshakeFailure))",
+        "which was received by client"
+    );
+}
+
+#[test]
+fn server_exposes_offered_sni() {
+    let kt = KeyType::RSA;
+    for client_config in AllClientVersions::new(make_client_config(kt)) {
+        let mut client = ClientConnection::new(
+            Arc::new(client_config),
+            server_name("second.testserver.com"),
+        )
+        .unwrap();
+        let mut server = ServerConnection::new(Arc::new(make_server_config(kt))).unwrap();
+
+        assert_eq!(None, server.sni_hostname());
+        do_handshake(&mut client, &mut server);
+        assert_eq!(Some("second.testserver.com"), server.sni_hostname());
+    }
+}
+
+#[test]
+fn server_exposes_offered_sni_smashed_to_lowercase() {
+    // webpki actually does this for us in its DnsName type
+    let kt = KeyType::RSA;
+    for client_config in AllClientVersions::new(make_client_config(kt)) {
+        let mut client = ClientConnection::new(
+            Arc::new(client_config),
+            server_name("SECOND.TESTServer.com"),
+        )
+        .unwrap();
+        let mut server = ServerConnection::new(Arc::new(make_server_config(kt))).unwrap();
+
+        assert_eq!(None, server.sni_hostname());
+        do_handshake(&mut client, &mut server);
+        assert_eq!(Some("second.testserver.com"), server.sni_hostname());
+    }
+}
+
+#[test]
+fn server_exposes_offered_sni_even_if_resolver_fails() {
+    let kt = KeyType::RSA;
+    let resolver = rustls::ResolvesServerCertUsingSni::new();
+
+    let mut server_config = make_server_config(kt);
+    server_config.cert_resolver = Arc::new(resolver);
+    let server_config = Arc::new(server_config);
+
+    for client_config in AllClientVersions::new(make_client_config(kt)) {
+        let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+        let mut client =
+            ClientConnection::new(Arc::new(client_config), server_name("thisdoesNOTexist.com"))
+                .unwrap();
+
+        assert_eq!(None, server.sni_hostname());
+        transfer(&mut client, &mut server);
+        assert_eq!(
+            server.process_new_packets(),
+            Err(Error::General(
+                "no server certificate chain resolved".to_string()
+            ))
+        );
+        assert_eq!(Some("thisdoesnotexist.com"), server.sni_hostname());
+    }
+}
+
+#[test]
+fn sni_resolver_works() {
+    let kt = KeyType::RSA;
+    let mut resolver = rustls::ResolvesServerCertUsingSni::new();
+    let signing_key = sign::RsaSigningKey::new(&kt.get_key()).unwrap();
+    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
+    resolver
+        .add(
+            "localhost",
+            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone()),
+        )
+        .unwrap();
+
+    let mut server_config = make_server_config(kt);
+    server_config.cert_resolver = Arc::new(resolver);
+    let server_config = Arc::new(server_config);
+
+    let mut server1 = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+    let mut client1 =
+        ClientConnection::new(Arc::new(make_client_config(kt)), server_name("localhost")).unwrap();
+    let err = do_handshake_until_error(&mut client1, &mut server1);
+    assert_eq!(err, Ok(()));
+
+    let mut server2 = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+    let mut client2 = ClientConnection::new(
+        Arc::new(make_client_config(kt)),
+        server_name("notlocalhost"),
+    )
+    .unwrap();
+    let err = do_handshake_until_error(&mut client2, &mut server2);
+    assert_eq!(
+        err,
+        Err(ErrorFromPeer::Server(Error::General(
+            "no server certificate chain resolved".into()
+        )))
+    );
+}
+
+#[test]
+fn shared_sni_resolver_works() {
+    let kt = KeyType::RSA;
+    let resolver = Arc::new(rustls::SharedSniResolver::new());
+    let signing_key = sign::RsaSigningKey::new(&kt.get_key()).unwrap();
+    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
+
+    assert!(resolver.remove("localhost").is_none());
+
+    resolver
+        .add(
+            "localhost",
+            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone()),
+        )
+        .unwrap();
+
+    let mut server_config = make_server_config(kt);
+    server_config.cert_resolver = resolver.clone();
+    let server_config = Arc::new(server_config);
+
+    let mut server1 = ServerConnection::new(server_config.clone()).unwrap();
+    let mut client1 =
+        ClientConnection::new(Arc::new(make_client_config(kt)), server_name("localhost")).unwrap();
+    let err = do_handshake_until_error(&mut client1, &mut server1);
+    assert_eq!(err, Ok(()));
+
+    let mut server2 = ServerConnection::new(server_config.clone()).unwrap();
+    let mut client2 = ClientConnection::new(
+        Arc::new(make_client_config(kt)),
+        server_name("notlocalhost"),
+    )
+    .unwrap();
+    let err = do_handshake_until_error(&mut client2, &mut server2);
+    assert_eq!(
+        err,
+        Err(ErrorFromPeer::Server(Error::General(
+            "no server certificate chain resolved".into()
+        )))
+    );
+
+    assert!(resolver.remove("localhost").is_some());
+
+    let mut client3 =
+        ClientConnection::new(Arc::new(make_client_config(kt)), server_name("localhost")).unwrap();
+    let mut server3 = ServerConnection::new(server_config).unwrap();
+    let err = do_handshake_until_error(&mut client3, &mut server3);
+    assert_eq!(
+        err,
+        Err(ErrorFromPeer::Server(Error::General(
+            "no server certificate chain resolved".into()
+        )))
+    );
+}
+
+#[test]
+fn sni_resolver_rejects_wrong_names() {
+    let kt = KeyType::RSA;
+    let mut resolver = rustls::ResolvesServerCertUsingSni::new();
+    let signing_key = sign::RsaSigningKey::new(&kt.get_key()).unwrap();
+    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
+
+    assert_eq!(
+        Ok(()),
+        resolver.add(
+            "localhost",
+            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
+        )
+    );
+    assert_eq!(
+        Err(Error::General(
+            "The server certificate is not valid for the given name".into()
+        )),
+        resolver.add(
+            "not-localhost",
+            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
+        )
+    );
+    assert_eq!(
+        Err(Error::General("Bad DNS name".into())),
+        resolver.add(
+            "not ascii 🦀",
+            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
+        )
+    );
+}
+
+#[test]
+fn sni_resolver_rejects_bad_certs() {
+    let kt = KeyType::RSA;
+    let mut resolver = rustls::ResolvesServerCertUsingSni::new();
+    let signing_key = sign::RsaSigningKey::new(&kt.get_key()).unwrap();
+    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
+
+    assert_eq!(
+        Err(Error::General(
+            "No end-entity certificate in certificate chain".into()
+        )),
+        resolver.add(
+            "localhost",
+            sign::CertifiedKey::new(vec![], signing_key.clone())
+        )
+    );
+
+    let bad_chain = vec![rustls::Certificate(vec![0xa0])];
+    assert_eq!(
+        Err(Error::General(
+            "End-entity certificate in certificate chain is syntactically invalid".into()
+        )),
+        resolver.add(
+            "localhost",
+            sign::CertifiedKey::new(bad_chain, signing_key.clone())
+        )
+    );
+}
+
+fn do_exporter_test(client_config: ClientConfig, server_config: ServerConfig) {
+    let mut client_secret = [0u8; 64];
+    let mut server_secret = [0u8; 64];
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+
+    assert_eq!(
+        Err(Error::HandshakeNotComplete),
+        client.export_keying_material(&mut client_secret, b"label", Some(b"context"))
+    );
+    assert_eq!(
+        Err(Error::HandshakeNotComplete),
+        server.export_keying_material(&mut server_secret, b"label", Some(b"context"))
+    );
+    do_handshake(&mut client, &mut server);
+
+    assert_eq!(
+        Ok(()),
+        client.export_keying_material(&mut client_secret, b"label", Some(b"context"))
+    );
+    assert_eq!(
+        Ok(()),
+        server.export_keying_material(&mut server_secret, b"label", Some(b"context"))
+    );
+    assert_eq!(client_secret.to_vec(), server_secret.to_vec());
+
+    assert_eq!(
+        Ok(()),
+        client.export_keying_material(&mut client_secret, b"label", None)
+    );
+    assert_ne!(client_secret.to_vec(), server_secret.to_vec());
+    assert_eq!(
+        Ok(()),
+        server.export_keying_material(&mut server_secret, b"label", None)
+    );
+    assert_eq!(client_secret.to_vec(), server_secret.to_vec());
+}
+
+#[test]
+fn test_tls12_exporter() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let mut client_config = make_client_config(*kt);
+        let server_config = make_server_config(*kt);
+        client_config
+            .versions
+            .replace(&[&rustls::version::TLS12]);
+
+        do_exporter_test(client_config, server_config);
+    }
+}
+
+#[test]
+fn test_tls13_exporter() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let mut client_config = make_client_config(*kt);
+        let server_config = make_server_config(*kt);
+        client_config
+            .versions
+            .replace(&[&rustls::version::TLS13]);
+
+        do_exporter_test(client_config, server_config);
+    }
+}
+
+fn do_suite_test(
+    client_config: ClientConfig,
+    server_config: ServerConfig,
+    expect_suite: &'static SupportedCipherSuite,
+    expect_version: ProtocolVersion,
+) {
+    println!(
+        "do_suite_test {:?} {:?}",
+        expect_version, expect_suite.suite
+    );
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+
+    assert_eq!(None, client.negotiated_cipher_suite());
+    assert_eq!(None, server.negotiated_cipher_suite());
+    assert_eq!(None, client.protocol_version());
+    assert_eq!(None, server.protocol_version());
+    assert_eq!(true, client.is_handshaking());
+    assert_eq!(true, server.is_handshaking());
+
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+
+    assert_eq!(true, client.is_handshaking());
+    assert_eq!(true, server.is_handshaking());
+    assert_eq!(None, client.protocol_version());
+    assert_eq!(Some(expect_version), server.protocol_version());
+    assert_eq!(None, client.negotiated_cipher_suite());
+    assert_eq!(Some(expect_suite), server.negotiated_cipher_suite());
+
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+
+    assert_eq!(Some(expect_suite), client.negotiated_cipher_suite());
+    assert_eq!(Some(expect_suite), server.negotiated_cipher_suite());
+
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+
+    assert_eq!(false, client.is_handshaking());
+    assert_eq!(false, server.is_handshaking());
+    assert_eq!(Some(expect_version), client.protocol_version());
+    assert_eq!(Some(expect_version), server.protocol_version());
+    assert_eq!(Some(expect_suite), client.negotiated_cipher_suite());
+    assert_eq!(Some(expect_suite), server.negotiated_cipher_suite());
+}
+
+fn find_suite(suite: CipherSuite) -> &'static SupportedCipherSuite {
+    for scs in ALL_CIPHERSUITES.iter() {
+        if scs.suite == suite {
+            return scs;
+        }
+    }
+
+    panic!("find_suite given unsupported suite");
+}
 
 static TEST_CIPHERSUITES: [(
     &'static rustls::SupportedProtocolVersion,
@@ -2430,643 +7313,1471 @@ static TEST_CIPHERSUITES: [(
 ];
 
 #[test]
-fn negotiated_ciphersuite_default() {
+fn negotiated_ciphersuite_default() {
+    for kt in ALL_KEY_TYPES.iter() {
+        do_suite_test(
+            make_client_config(*kt),
+            make_server_config(*kt),
+            find_suite(CipherSuite::TLS13_AES_256_GCM_SHA384),
+            ProtocolVersion::TLSv1_3,
+        );
+    }
+}
+
+#[test]
+fn all_suites_covered() {
+    assert_eq!(ALL_CIPHERSUITES.len(), TEST_CIPHERSUITES.len());
+}
+
+#[test]
+fn negotiated_ciphersuite_client() {
+    for item in TEST_CIPHERSUITES.iter() {
+        let (version, kt, suite) = *item;
+        let scs = find_suite(suite);
+        let mut client_config = make_client_config(kt);
+        client_config.cipher_suites = vec![scs];
+        client_config
+            .versions
+            .replace(&[version]);
+
+        do_suite_test(client_config, make_server_config(kt), scs, version.version);
+    }
+}
+
+#[test]
+fn negotiated_ciphersuite_server() {
+    for item in TEST_CIPHERSUITES.iter() {
+        let (version, kt, suite) = *item;
+        let scs = find_suite(suite);
+        let mut server_config = make_server_config(kt);
+        server_config.cipher_suites = vec![scs];
+        server_config
+            .versions
+            .replace(&[version]);
+
+        do_suite_test(make_client_config(kt), server_config, scs, version.version);
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct KeyLogItem {
+    label: String,
+    client_random: Vec<u8>,
+    secret: Vec<u8>,
+}
+
+struct KeyLogToVec {
+    label: &'static str,
+    items: Mutex<Vec<KeyLogItem>>,
+}
+
+impl KeyLogToVec {
+    fn new(who: &'static str) -> Self {
+        KeyLogToVec {
+            label: who,
+            items: Mutex::new(vec![]),
+        }
+    }
+
+    fn take(&self) -> Vec<KeyLogItem> {
+        mem::replace(&mut self.items.lock().unwrap(), vec![])
+    }
+}
+
+impl KeyLog for KeyLogToVec {
+    fn log(&self, label: &str, client: &[u8], secret: &[u8]) {
+        let value = KeyLogItem {
+            label: label.into(),
+            client_random: client.into(),
+            secret: secret.into(),
+        };
+
+        println!("key log {:?}: {:?}", self.label, value);
+
+        self.items.lock().unwrap().push(value);
+    }
+}
+
+#[test]
+fn key_log_for_tls12() {
+    let client_key_log = Arc::new(KeyLogToVec::new("client"));
+    let server_key_log = Arc::new(KeyLogToVec::new("server"));
+
+    let kt = KeyType::RSA;
+    let mut client_config = make_client_config(kt);
+    client_config
+        .versions
+        .replace(&[&rustls::version::TLS12]);
+    client_config.key_log = client_key_log.clone();
+    let client_config = Arc::new(client_config);
+
+    let mut server_config = make_server_config(kt);
+    server_config.key_log = server_key_log.clone();
+    let server_config = Arc::new(server_config);
+
+    // full handshake
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+
+    let client_full_log = client_key_log.take();
+    let server_full_log = server_key_log.take();
+    assert_eq!(client_full_log, server_full_log);
+    assert_eq!(1, client_full_log.len());
+    assert_eq!("CLIENT_RANDOM", client_full_log[0].label);
+
+    // resumed
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+
+    let client_resume_log = client_key_log.take();
+    let server_resume_log = server_key_log.take();
+    assert_eq!(client_resume_log, server_resume_log);
+    assert_eq!(1, client_resume_log.len());
+    assert_eq!("CLIENT_RANDOM", client_resume_log[0].label);
+    assert_eq!(client_full_log[0].secret, client_resume_log[0].secret);
+}
+
+#[test]
+fn key_log_for_tls13() {
+    let client_key_log = Arc::new(KeyLogToVec::new("client"));
+    let server_key_log = Arc::new(KeyLogToVec::new("server"));
+
+    let kt = KeyType::RSA;
+    let mut client_config = make_client_config(kt);
+    client_config
+        .versions
+        .replace(&[&rustls::version::TLS13]);
+    client_config.key_log = client_key_log.clone();
+    let client_config = Arc::new(client_config);
+
+    let mut server_config = make_server_config(kt);
+    server_config.key_log = server_key_log.clone();
+    let server_config = Arc::new(server_config);
+
+    // full handshake
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+
+    let client_full_log = client_key_log.take();
+    let server_full_log = server_key_log.take();
+
+    assert_eq!(5, client_full_log.len());
+    assert_eq!("CLIENT_HANDSHAKE_TRAFFIC_SECRET", client_full_log[0].label);
+    assert_eq!("SERVER_HANDSHAKE_TRAFFIC_SECRET", client_full_log[1].label);
+    assert_eq!("SERVER_TRAFFIC_SECRET_0", client_full_log[2].label);
+    assert_eq!("EXPORTER_SECRET", client_full_log[3].label);
+    assert_eq!("CLIENT_TRAFFIC_SECRET_0", client_full_log[4].label);
+
+    assert_eq!(client_full_log[0], server_full_log[1]);
+    assert_eq!(client_full_log[1], server_full_log[0]);
+    assert_eq!(client_full_log[2], server_full_log[2]);
+    assert_eq!(client_full_log[3], server_full_log[3]);
+    assert_eq!(client_full_log[4], server_full_log[4]);
+
+    // resumed
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+
+    let client_resume_log = client_key_log.take();
+    let server_resume_log = server_key_log.take();
+
+    assert_eq!(5, client_resume_log.len());
+    assert_eq!(
+        "CLIENT_HANDSHAKE_TRAFFIC_SECRET",
+        client_resume_log[0].label
+    );
+    assert_eq!(
+        "SERVER_HANDSHAKE_TRAFFIC_SECRET",
+        client_resume_log[1].label
+    );
+    assert_eq!("SERVER_TRAFFIC_SECRET_0", client_resume_log[2].label);
+    assert_eq!("EXPORTER_SECRET", client_resume_log[3].label);
+    assert_eq!("CLIENT_TRAFFIC_SECRET_0", client_resume_log[4].label);
+
+    assert_eq!(client_resume_log[0], server_resume_log[1]);
+    assert_eq!(client_resume_log[1], server_resume_log[0]);
+    assert_eq!(client_resume_log[2], server_resume_log[2]);
+    assert_eq!(client_resume_log[3], server_resume_log[3]);
+    assert_eq!(client_resume_log[4], server_resume_log[4]);
+}
+
+struct FixedTime(Option<SystemTime>);
+
+impl rustls::TimeProvider for FixedTime {
+    fn current_time(&self) -> Option<SystemTime> {
+        self.0
+    }
+}
+
+/// A time at which all of the test-ca certificates are valid.
+fn within_validity() -> Arc<FixedTime> {
+    Arc::new(FixedTime(Some(
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000),
+    )))
+}
+
+/// A time after all of the test-ca certificates have expired.
+fn after_expiry() -> Arc<FixedTime> {
+    Arc::new(FixedTime(Some(
+        SystemTime::UNIX_EPOCH + Duration::from_secs(4_000_000_000),
+    )))
+}
+
+#[test]
+fn handshake_succeeds_at_provided_time() {
     for kt in ALL_KEY_TYPES.iter() {
-        do_suite_test(
-            make_client_config(*kt),
-            make_server_config(*kt),
-            find_suite(CipherSuite::TLS13_AES_256_GCM_SHA384),
-            ProtocolVersion::TLSv1_3,
+        let mut client_config = make_client_config_with_auth(*kt);
+        client_config.time_provider = within_validity();
+        let mut server_config = make_server_config_with_mandatory_client_auth(*kt);
+        server_config.time_provider = within_validity();
+        let server_config = Arc::new(server_config);
+
+        for client_config in AllClientVersions::new(client_config) {
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+            assert_eq!(do_handshake_until_error(&mut client, &mut server), Ok(()));
+        }
+    }
+}
+
+#[test]
+fn client_verifies_server_cert_at_provided_time() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let mut client_config = make_client_config(*kt);
+        client_config.time_provider = after_expiry();
+        let server_config = Arc::new(make_server_config(*kt));
+
+        for client_config in AllClientVersions::new(client_config) {
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+            assert_eq!(
+                do_handshake_until_error(&mut client, &mut server),
+                Err(ErrorFromPeer::Client(Error::WebPkiError(
+                    webpki::Error::CertExpired,
+                    WebPkiOp::ValidateServerCert,
+                )))
+            );
+        }
+    }
+}
+
+#[test]
+fn server_verifies_client_cert_at_provided_time() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let mut client_config = make_client_config_with_auth(*kt);
+        client_config.time_provider = within_validity();
+        let mut server_config = make_server_config_with_mandatory_client_auth(*kt);
+        server_config.time_provider = after_expiry();
+        let server_config = Arc::new(server_config);
+
+        for client_config in AllClientVersions::new(client_config) {
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+            assert_eq!(
+                do_handshake_until_error(&mut client, &mut server),
+                Err(ErrorFromPeer::Server(Error::WebPkiError(
+                    webpki::Error::CertExpired,
+                    WebPkiOp::ValidateClientCert,
+                )))
+            );
+        }
+    }
+}
+
+#[test]
+fn client_fails_without_current_time() {
+    let mut client_config = make_client_config(KeyType::RSA);
+    client_config.time_provider = Arc::new(FixedTime(None));
+    let server_config = Arc::new(make_server_config(KeyType::RSA));
+
+    for client_config in AllClientVersions::new(client_config) {
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        assert_eq!(
+            do_handshake_until_error(&mut client, &mut server),
+            Err(ErrorFromPeer::Client(Error::FailedToGetCurrentTime))
+        );
+    }
+}
+
+#[test]
+fn client_sends_certificate_expired_alert() {
+    let mut client_config = make_client_config(KeyType::RSA);
+    client_config.time_provider = after_expiry();
+    let server_config = Arc::new(make_server_config(KeyType::RSA));
+
+    for client_config in AllClientVersions::new(client_config) {
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        assert_eq!(
+            do_handshake_until_both_error(&mut client, &mut server),
+            Err(vec![
+                ErrorFromPeer::Client(Error::WebPkiError(
+                    webpki::Error::CertExpired,
+                    WebPkiOp::ValidateServerCert,
+                )),
+                ErrorFromPeer::Server(Error::AlertReceived(AlertDescription::CertificateExpired)),
+            ])
+        );
+    }
+}
+
+#[test]
+fn server_sends_certificate_expired_alert() {
+    let mut client_config = make_client_config_with_auth(KeyType::RSA);
+    client_config.time_provider = within_validity();
+    let mut server_config = make_server_config_with_mandatory_client_auth(KeyType::RSA);
+    server_config.time_provider = after_expiry();
+    let server_config = Arc::new(server_config);
+
+    for client_config in AllClientVersions::new(client_config) {
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        assert_eq!(
+            do_handshake_until_both_error(&mut client, &mut server),
+            Err(vec![
+                ErrorFromPeer::Server(Error::WebPkiError(
+                    webpki::Error::CertExpired,
+                    WebPkiOp::ValidateClientCert,
+                )),
+                ErrorFromPeer::Client(Error::AlertReceived(AlertDescription::CertificateExpired)),
+            ])
+        );
+    }
+}
+
+#[test]
+fn client_sends_unknown_ca_alert() {
+    let client_config = make_client_config(KeyType::RSA);
+    let server_config = Arc::new(make_verifier_server_config(&[]));
+
+    for client_config in AllClientVersions::new(client_config) {
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        assert_eq!(
+            do_handshake_until_both_error(&mut client, &mut server),
+            Err(vec![
+                ErrorFromPeer::Client(Error::WebPkiError(
+                    webpki::Error::UnknownIssuer,
+                    WebPkiOp::ValidateServerCert,
+                )),
+                ErrorFromPeer::Server(Error::AlertReceived(AlertDescription::UnknownCA)),
+            ])
         );
     }
 }
 
 #[test]
-fn all_suites_covered() {
-    assert_eq!(ALL_CIPHERSUITES.len(), TEST_CIPHERSUITES.len());
+fn vectored_write_for_server_appdata() {
+    let (mut client, mut server) = make_pair(KeyType::RSA);
+    do_handshake(&mut client, &mut server);
+
+    server
+        .writer()
+        .write(b"01234567890123456789")
+        .unwrap();
+    server
+        .writer()
+        .write(b"01234567890123456789")
+        .unwrap();
+    {
+        let mut pipe = OtherSession::new(&mut client);
+        let wrlen = server.write_tls(&mut pipe).unwrap();
+        assert_eq!(84, wrlen);
+        assert_eq!(pipe.writevs, vec![vec![42, 42]]);
+    }
+    check_read(
+        &mut client.reader(),
+        b"0123456789012345678901234567890123456789",
+    );
+}
+
+#[test]
+fn vectored_write_for_client_appdata() {
+    let (mut client, mut server) = make_pair(KeyType::RSA);
+    do_handshake(&mut client, &mut server);
+
+    client
+        .writer()
+        .write(b"01234567890123456789")
+        .unwrap();
+    client
+        .writer()
+        .write(b"01234567890123456789")
+        .unwrap();
+    {
+        let mut pipe = OtherSession::new(&mut server);
+        let wrlen = client.write_tls(&mut pipe).unwrap();
+        assert_eq!(84, wrlen);
+        assert_eq!(pipe.writevs, vec![vec![42, 42]]);
+    }
+    check_read(
+        &mut server.reader(),
+        b"0123456789012345678901234567890123456789",
+    );
+}
+
+#[test]
+fn vectored_write_for_server_handshake() {
+    let (mut client, mut server) = make_pair(KeyType::RSA);
+
+    server
+        .writer()
+        .write(b"01234567890123456789")
+        .unwrap();
+    server
+        .writer()
+        .write(b"0123456789")
+        .unwrap();
+
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    {
+        let mut pipe = OtherSession::new(&mut client);
+        let wrlen = server.write_tls(&mut pipe).unwrap();
+        // don't assert exact sizes here, to avoid a brittle test
+        assert!(wrlen > 4000); // its pretty big (contains cert chain)
+        assert_eq!(pipe.writevs.len(), 1); // only one writev
+        assert!(pipe.writevs[0].len() > 3); // at least a server hello/cert/serverkx
+    }
+
+    client.process_new_packets().unwrap();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    {
+        let mut pipe = OtherSession::new(&mut client);
+        let wrlen = server.write_tls(&mut pipe).unwrap();
+        assert_eq!(wrlen, 177);
+        assert_eq!(pipe.writevs, vec![vec![103, 42, 32]]);
+    }
+
+    assert_eq!(server.is_handshaking(), false);
+    assert_eq!(client.is_handshaking(), false);
+    check_read(&mut client.reader(), b"012345678901234567890123456789");
+}
+
+#[test]
+fn vectored_write_for_client_handshake() {
+    let (mut client, mut server) = make_pair(KeyType::RSA);
+
+    client
+        .writer()
+        .write(b"01234567890123456789")
+        .unwrap();
+    client
+        .writer()
+        .write(b"0123456789")
+        .unwrap();
+    {
+        let mut pipe = OtherSession::new(&mut server);
+        let wrlen = client.write_tls(&mut pipe).unwrap();
+        // don't assert exact sizes here, to avoid a brittle test
+        assert!(wrlen > 200); // just the client hello
+        assert_eq!(pipe.writevs.len(), 1); // only one writev
+        assert!(pipe.writevs[0].len() == 1); // only a client hello
+    }
+
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+
+    {
+        let mut pipe = OtherSession::new(&mut server);
+        let wrlen = client.write_tls(&mut pipe).unwrap();
+        assert_eq!(wrlen, 154);
+        // CCS, finished, then two application datas
+        assert_eq!(pipe.writevs, vec![vec![6, 74, 42, 32]]);
+    }
+
+    assert_eq!(server.is_handshaking(), false);
+    assert_eq!(client.is_handshaking(), false);
+    check_read(&mut server.reader(), b"012345678901234567890123456789");
 }
 
 #[test]
-fn negotiated_ciphersuite_client() {
-    for item in TEST_CIPHERSUITES.iter() {
-        let (version, kt, suite) = *item;
-        let scs = find_suite(suite);
-        let mut client_config = make_client_config(kt);
-        client_config.cipher_suites = vec![scs];
-        client_config
-            .versions
-            .replace(&[version]);
+fn vectored_write_with_slow_client() {
+    let (mut client, mut server) = make_pair(KeyType::RSA);
 
-        do_suite_test(client_config, make_server_config(kt), scs, version.version);
+    client.set_buffer_limit(32);
+
+    do_handshake(&mut client, &mut server);
+    server
+        .writer()
+        .write(b"01234567890123456789")
+        .unwrap();
+
+    {
+        let mut pipe = OtherSession::new(&mut client);
+        pipe.short_writes = true;
+        let wrlen = server.write_tls(&mut pipe).unwrap()
+            + server.write_tls(&mut pipe).unwrap()
+            + server.write_tls(&mut pipe).unwrap()
+            + server.write_tls(&mut pipe).unwrap()
+            + server.write_tls(&mut pipe).unwrap()
+            + server.write_tls(&mut pipe).unwrap();
+        assert_eq!(42, wrlen);
+        assert_eq!(
+            pipe.writevs,
+            vec![vec![21], vec![10], vec![5], vec![3], vec![3]]
+        );
     }
+    check_read(&mut client.reader(), b"01234567890123456789");
 }
 
-#[test]
-fn negotiated_ciphersuite_server() {
-    for item in TEST_CIPHERSUITES.iter() {
-        let (version, kt, suite) = *item;
-        let scs = find_suite(suite);
-        let mut server_config = make_server_config(kt);
-        server_config.cipher_suites = vec![scs];
-        server_config
-            .versions
-            .replace(&[version]);
+struct ServerStorage {
+    storage: Arc<dyn rustls::StoresServerSessions>,
+    put_count: AtomicUsize,
+    get_count: AtomicUsize,
+    take_count: AtomicUsize,
+}
 
-        do_suite_test(make_client_config(kt), server_config, scs, version.version);
+impl ServerStorage {
+    fn new() -> ServerStorage {
+        ServerStorage {
+            storage: rustls::ServerSessionMemoryCache::new(1024),
+            put_count: AtomicUsize::new(0),
+            get_count: AtomicUsize::new(0),
+            take_count: AtomicUsize::new(0),
+        }
+    }
+
+    fn puts(&self) -> usize {
+        self.put_count.load(Ordering::SeqCst)
+    }
+    fn gets(&self) -> usize {
+        self.get_count.load(Ordering::SeqCst)
+    }
+    fn takes(&self) -> usize {
+        self.take_count.load(Ordering::SeqCst)
     }
 }
 
-#[derive(Debug, PartialEq)]
-struct KeyLogItem {
-    label: String,
-    client_random: Vec<u8>,
-    secret: Vec<u8>,
+impl fmt::Debug for ServerStorage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "(put: {:?}, get: {:?}, take: {:?})",
+            self.put_count, self.get_count, self.take_count
+        )
+    }
 }
 
-struct KeyLogToVec {
-    label: &'static str,
-    items: Mutex<Vec<KeyLogItem>>,
+impl rustls::StoresServerSessions for ServerStorage {
+    fn put(&self, key: Vec<u8>, value: Vec<u8>) -> bool {
+        self.put_count
+            .fetch_add(1, Ordering::SeqCst);
+        self.storage.put(key, value)
+    }
+
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.get_count
+            .fetch_add(1, Ordering::SeqCst);
+        self.storage.get(key)
+    }
+
+    fn take(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.take_count
+            .fetch_add(1, Ordering::SeqCst);
+        self.storage.take(key)
+    }
 }
 
-impl KeyLogToVec {
-    fn new(who: &'static str) -> Self {
-        KeyLogToVec {
-            label: who,
-            items: Mutex::new(vec![]),
+struct ClientStorage {
+    storage: Arc<dyn rustls::StoresClientSessions>,
+    put_count: AtomicUsize,
+    get_count: AtomicUsize,
+    last_put_key: Mutex<Option<Vec<u8>>>,
+}
+
+impl ClientStorage {
+    fn new() -> ClientStorage {
+        ClientStorage {
+            storage: rustls::ClientSessionMemoryCache::new(1024),
+            put_count: AtomicUsize::new(0),
+            get_count: AtomicUsize::new(0),
+            last_put_key: Mutex::new(None),
         }
     }
 
-    fn take(&self) -> Vec<KeyLogItem> {
-        mem::replace(&mut self.items.lock().unwrap(), vec![])
+    fn puts(&self) -> usize {
+        self.put_count.load(Ordering::SeqCst)
+    }
+    fn gets(&self) -> usize {
+        self.get_count.load(Ordering::SeqCst)
     }
 }
 
-impl KeyLog for KeyLogToVec {
-    fn log(&self, label: &str, client: &[u8], secret: &[u8]) {
-        let value = KeyLogItem {
-            label: label.into(),
-            client_random: client.into(),
-            secret: secret.into(),
-        };
+impl fmt::Debug for ClientStorage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "(puts: {:?}, gets: {:?} )",
+            self.put_count, self.get_count
+        )
+    }
+}
 
-        println!("key log {:?}: {:?}", self.label, value);
+impl rustls::StoresClientSessions for ClientStorage {
+    fn put(&self, key: Vec<u8>, value: Vec<u8>) -> bool {
+        self.put_count
+            .fetch_add(1, Ordering::SeqCst);
+        *self.last_put_key.lock().unwrap() = Some(key.clone());
+        self.storage.put(key, value)
+    }
 
-        self.items.lock().unwrap().push(value);
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.get_count
+            .fetch_add(1, Ordering::SeqCst);
+        self.storage.get(key)
     }
 }
 
 #[test]
-fn key_log_for_tls12() {
-    let client_key_log = Arc::new(KeyLogToVec::new("client"));
-    let server_key_log = Arc::new(KeyLogToVec::new("server"));
-
+fn tls13_stateful_resumption() {
     let kt = KeyType::RSA;
     let mut client_config = make_client_config(kt);
     client_config
         .versions
-        .replace(&[&rustls::version::TLS12]);
-    client_config.key_log = client_key_log.clone();
+        .replace(&[&rustls::version::TLS13]);
     let client_config = Arc::new(client_config);
 
     let mut server_config = make_server_config(kt);
-    server_config.key_log = server_key_log.clone();
+    let storage = Arc::new(ServerStorage::new());
+    server_config.session_storage = storage.clone();
     let server_config = Arc::new(server_config);
 
     // full handshake
     let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    do_handshake(&mut client, &mut server);
-
-    let client_full_log = client_key_log.take();
-    let server_full_log = server_key_log.take();
-    assert_eq!(client_full_log, server_full_log);
-    assert_eq!(1, client_full_log.len());
-    assert_eq!("CLIENT_RANDOM", client_full_log[0].label);
+    let (full_c2s, full_s2c) = do_handshake(&mut client, &mut server);
+    assert_eq!(storage.puts(), 1);
+    assert_eq!(storage.gets(), 0);
+    assert_eq!(storage.takes(), 0);
+    assert_eq!(
+        client
+            .peer_certificates()
+            .map(|certs| certs.len()),
+        Some(3)
+    );
 
     // resumed
     let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    do_handshake(&mut client, &mut server);
+    let (resume_c2s, resume_s2c) = do_handshake(&mut client, &mut server);
+    assert!(resume_c2s > full_c2s);
+    assert!(resume_s2c < full_s2c);
+    assert_eq!(storage.puts(), 2);
+    assert_eq!(storage.gets(), 0);
+    assert_eq!(storage.takes(), 1);
+    assert_eq!(
+        client
+            .peer_certificates()
+            .map(|certs| certs.len()),
+        Some(3)
+    );
 
-    let client_resume_log = client_key_log.take();
-    let server_resume_log = server_key_log.take();
-    assert_eq!(client_resume_log, server_resume_log);
-    assert_eq!(1, client_resume_log.len());
-    assert_eq!("CLIENT_RANDOM", client_resume_log[0].label);
-    assert_eq!(client_full_log[0].secret, client_resume_log[0].secret);
+    // resumed again
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    let (resume2_c2s, resume2_s2c) = do_handshake(&mut client, &mut server);
+    assert_eq!(resume_s2c, resume2_s2c);
+    assert_eq!(resume_c2s, resume2_c2s);
+    assert_eq!(storage.puts(), 3);
+    assert_eq!(storage.gets(), 0);
+    assert_eq!(storage.takes(), 2);
+    assert_eq!(
+        client
+            .peer_certificates()
+            .map(|certs| certs.len()),
+        Some(3)
+    );
 }
 
 #[test]
-fn key_log_for_tls13() {
-    let client_key_log = Arc::new(KeyLogToVec::new("client"));
-    let server_key_log = Arc::new(KeyLogToVec::new("server"));
-
+fn tls13_stateless_resumption() {
     let kt = KeyType::RSA;
     let mut client_config = make_client_config(kt);
     client_config
         .versions
         .replace(&[&rustls::version::TLS13]);
-    client_config.key_log = client_key_log.clone();
     let client_config = Arc::new(client_config);
 
     let mut server_config = make_server_config(kt);
-    server_config.key_log = server_key_log.clone();
+    server_config.ticketer = rustls::Ticketer::new().unwrap();
+    let storage = Arc::new(ServerStorage::new());
+    server_config.session_storage = storage.clone();
     let server_config = Arc::new(server_config);
 
     // full handshake
     let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    do_handshake(&mut client, &mut server);
-
-    let client_full_log = client_key_log.take();
-    let server_full_log = server_key_log.take();
-
-    assert_eq!(5, client_full_log.len());
-    assert_eq!("CLIENT_HANDSHAKE_TRAFFIC_SECRET", client_full_log[0].label);
-    assert_eq!("SERVER_HANDSHAKE_TRAFFIC_SECRET", client_full_log[1].label);
-    assert_eq!("SERVER_TRAFFIC_SECRET_0", client_full_log[2].label);
-    assert_eq!("EXPORTER_SECRET", client_full_log[3].label);
-    assert_eq!("CLIENT_TRAFFIC_SECRET_0", client_full_log[4].label);
-
-    assert_eq!(client_full_log[0], server_full_log[1]);
-    assert_eq!(client_full_log[1], server_full_log[0]);
-    assert_eq!(client_full_log[2], server_full_log[2]);
-    assert_eq!(client_full_log[3], server_full_log[3]);
-    assert_eq!(client_full_log[4], server_full_log[4]);
+    let (full_c2s, full_s2c) = do_handshake(&mut client, &mut server);
+    assert_eq!(storage.puts(), 0);
+    assert_eq!(storage.gets(), 0);
+    assert_eq!(storage.takes(), 0);
+    assert_eq!(
+        client
+            .peer_certificates()
+            .map(|certs| certs.len()),
+        Some(3)
+    );
 
     // resumed
     let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    do_handshake(&mut client, &mut server);
-
-    let client_resume_log = client_key_log.take();
-    let server_resume_log = server_key_log.take();
-
-    assert_eq!(5, client_resume_log.len());
+    let (resume_c2s, resume_s2c) = do_handshake(&mut client, &mut server);
+    assert!(resume_c2s > full_c2s);
+    assert!(resume_s2c < full_s2c);
+    assert_eq!(storage.puts(), 0);
+    assert_eq!(storage.gets(), 0);
+    assert_eq!(storage.takes(), 0);
     assert_eq!(
-        "CLIENT_HANDSHAKE_TRAFFIC_SECRET",
-        client_resume_log[0].label
+        client
+            .peer_certificates()
+            .map(|certs| certs.len()),
+        Some(3)
     );
+
+    // resumed again
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    let (resume2_c2s, resume2_s2c) = do_handshake(&mut client, &mut server);
+    assert_eq!(resume_s2c, resume2_s2c);
+    assert_eq!(resume_c2s, resume2_c2s);
+    assert_eq!(storage.puts(), 0);
+    assert_eq!(storage.gets(), 0);
+    assert_eq!(storage.takes(), 0);
     assert_eq!(
-        "SERVER_HANDSHAKE_TRAFFIC_SECRET",
-        client_resume_log[1].label
+        client
+            .peer_certificates()
+            .map(|certs| certs.len()),
+        Some(3)
     );
-    assert_eq!("SERVER_TRAFFIC_SECRET_0", client_resume_log[2].label);
-    assert_eq!("EXPORTER_SECRET", client_resume_log[3].label);
-    assert_eq!("CLIENT_TRAFFIC_SECRET_0", client_resume_log[4].label);
-
-    assert_eq!(client_resume_log[0], server_resume_log[1]);
-    assert_eq!(client_resume_log[1], server_resume_log[0]);
-    assert_eq!(client_resume_log[2], server_resume_log[2]);
-    assert_eq!(client_resume_log[3], server_resume_log[3]);
-    assert_eq!(client_resume_log[4], server_resume_log[4]);
 }
 
 #[test]
-fn vectored_write_for_server_appdata() {
-    let (mut client, mut server) = make_pair(KeyType::RSA);
-    do_handshake(&mut client, &mut server);
+fn early_data_not_available() {
+    let (mut client, _) = make_pair(KeyType::RSA);
+    assert!(client.early_data().is_none());
+}
 
-    server
-        .writer()
-        .write(b"01234567890123456789")
+/// Edit the session `storage` last stored to fool the client on
+/// resumption that the server allows `max_early_data_size` bytes of
+/// early data.
+fn allow_early_data_in_stored_session(storage: &ClientStorage, max_early_data_size: u32) {
+    let session_key = storage
+        .last_put_key
+        .lock()
+        .unwrap()
+        .clone()
         .unwrap();
-    server
-        .writer()
-        .write(b"01234567890123456789")
+
+    let session_value_bytes = storage
+        .storage
+        .get(&session_key)
         .unwrap();
-    {
-        let mut pipe = OtherSession::new(&mut client);
-        let wrlen = server.write_tls(&mut pipe).unwrap();
-        assert_eq!(84, wrlen);
-        assert_eq!(pipe.writevs, vec![vec![42, 42]]);
-    }
-    check_read(
-        &mut client.reader(),
-        b"0123456789012345678901234567890123456789",
-    );
+    let mut session_value = ClientSessionValue::read_bytes(&session_value_bytes).unwrap();
+    session_value.max_early_data_size = max_early_data_size;
+
+    storage
+        .storage
+        .put(session_key, session_value.get_encoding());
+}
+
+/// Configs for a client which will offer `max_early_data_size` bytes
+/// of early data in its next connection, to a server like `server_config`.
+fn early_data_configs(
+    server_config: ServerConfig,
+    max_early_data_size: u32,
+) -> (Arc<ClientConfig>, Arc<ServerConfig>) {
+    let mut client_config = ClientConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .for_client()
+        .unwrap()
+        .with_root_certificates(verifier_roots(), &[])
+        .with_no_client_auth();
+    client_config.enable_early_data = true;
+    let storage = Arc::new(ClientStorage::new());
+    client_config.session_storage = storage.clone();
+    let client_config = Arc::new(client_config);
+    let server_config = Arc::new(server_config);
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    allow_early_data_in_stored_session(&storage, max_early_data_size);
+    (client_config, server_config)
+}
+
+fn verifier_server_config() -> ServerConfig {
+    ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(verifier_chain(), verifier_key("end"))
+        .unwrap()
 }
 
 #[test]
-fn vectored_write_for_client_appdata() {
-    let (mut client, mut server) = make_pair(KeyType::RSA);
+fn early_data_is_available_on_resumption() {
+    let (client_config, server_config) = early_data_configs(verifier_server_config(), 128);
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    assert!(client.early_data().is_some());
+    assert_eq!(client.early_data_status(), EarlyDataStatus::Pending);
+    assert_eq!(
+        client
+            .early_data()
+            .unwrap()
+            .bytes_left(),
+        128
+    );
+    assert_eq!(
+        client
+            .early_data()
+            .unwrap()
+            .flush()
+            .unwrap(),
+        ()
+    );
+    assert_eq!(
+        client
+            .early_data()
+            .unwrap()
+            .write(b"hello")
+            .unwrap(),
+        5
+    );
+
+    // rustls servers reject early data over TCP, and skip it.
     do_handshake(&mut client, &mut server);
+    assert_eq!(client.early_data_status(), EarlyDataStatus::Rejected);
+    assert!(!client.is_early_data_accepted());
+    assert!(client.early_data().is_none());
 
+    // So it can be sent again.
     client
         .writer()
-        .write(b"01234567890123456789")
-        .unwrap();
-    client
-        .writer()
-        .write(b"01234567890123456789")
+        .write_all(b"hello")
         .unwrap();
-    {
-        let mut pipe = OtherSession::new(&mut server);
-        let wrlen = client.write_tls(&mut pipe).unwrap();
-        assert_eq!(84, wrlen);
-        assert_eq!(pipe.writevs, vec![vec![42, 42]]);
-    }
-    check_read(
-        &mut server.reader(),
-        b"0123456789012345678901234567890123456789",
-    );
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    check_read(&mut server.reader(), b"hello");
 }
 
 #[test]
-fn vectored_write_for_server_handshake() {
-    let (mut client, mut server) = make_pair(KeyType::RSA);
+fn early_data_writes_are_limited_by_the_ticket() {
+    let (client_config, server_config) = early_data_configs(verifier_server_config(), 128);
 
-    server
-        .writer()
-        .write(b"01234567890123456789")
-        .unwrap();
-    server
-        .writer()
-        .write(b"0123456789")
-        .unwrap();
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    let mut early_data = client.early_data().unwrap();
+    assert_eq!(early_data.write(&[0; 100]).unwrap(), 100);
+    assert_eq!(early_data.bytes_left(), 28);
+    assert_eq!(early_data.write(&[0; 100]).unwrap(), 28);
+    assert_eq!(early_data.bytes_left(), 0);
+    assert_eq!(early_data.write(&[0; 100]).unwrap(), 0);
 
-    transfer(&mut client, &mut server);
-    server.process_new_packets().unwrap();
-    {
-        let mut pipe = OtherSession::new(&mut client);
-        let wrlen = server.write_tls(&mut pipe).unwrap();
-        // don't assert exact sizes here, to avoid a brittle test
-        assert!(wrlen > 4000); // its pretty big (contains cert chain)
-        assert_eq!(pipe.writevs.len(), 1); // only one writev
-        assert!(pipe.writevs[0].len() > 3); // at least a server hello/cert/serverkx
-    }
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.early_data_status(), EarlyDataStatus::Rejected);
+}
 
-    client.process_new_packets().unwrap();
-    transfer(&mut client, &mut server);
-    server.process_new_packets().unwrap();
-    {
-        let mut pipe = OtherSession::new(&mut client);
-        let wrlen = server.write_tls(&mut pipe).unwrap();
-        assert_eq!(wrlen, 177);
-        assert_eq!(pipe.writevs, vec![vec![103, 42, 32]]);
-    }
+#[test]
+fn early_data_writes_are_limited_by_buffer_limit() {
+    let (client_config, _) = early_data_configs(verifier_server_config(), 4096);
 
-    assert_eq!(server.is_handshaking(), false);
-    assert_eq!(client.is_handshaking(), false);
-    check_read(&mut client.reader(), b"012345678901234567890123456789");
+    let mut client =
+        ClientConnection::new(Arc::clone(&client_config), server_name("localhost")).unwrap();
+    // Leaves room for the ClientHello, and not much more.
+    client.set_buffer_limit(1024);
+    let mut early_data = client.early_data().unwrap();
+    let written = early_data.write(&[0; 4096]).unwrap();
+    assert!(written < 4096);
+    assert_eq!(early_data.bytes_left(), 4096 - written);
 }
 
 #[test]
-fn vectored_write_for_client_handshake() {
-    let (mut client, mut server) = make_pair(KeyType::RSA);
+fn server_skips_rejected_early_data_after_hello_retry_request() {
+    let (client_config, server_config) = early_data_configs(verifier_server_config(), 128);
 
-    client
-        .writer()
-        .write(b"01234567890123456789")
-        .unwrap();
-    client
-        .writer()
-        .write(b"0123456789")
-        .unwrap();
-    {
-        let mut pipe = OtherSession::new(&mut server);
-        let wrlen = client.write_tls(&mut pipe).unwrap();
-        // don't assert exact sizes here, to avoid a brittle test
-        assert!(wrlen > 200); // just the client hello
-        assert_eq!(pipe.writevs.len(), 1); // only one writev
-        assert!(pipe.writevs[0].len() == 1); // only a client hello
-    }
+    // The client's key share is for the group the first server chose.
+    let mut retry_server_config = (*server_config).clone();
+    retry_server_config.kx_groups = vec![&rustls::kx_group::SECP384R1];
+    let retry_server_config = Arc::new(retry_server_config);
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &retry_server_config);
+    assert_eq!(
+        client
+            .early_data()
+            .unwrap()
+            .write(b"hello")
+            .unwrap(),
+        5
+    );
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
 
-    transfer(&mut server, &mut client);
+    let sent_retry = Mutex::new(false);
+    let check_retry = |msg: &mut Message| {
+        if let MessagePayload::Handshake(hs) = &msg.payload {
+            if let HandshakePayload::HelloRetryRequest(_) = &hs.payload {
+                *sent_retry.lock().unwrap() = true;
+            }
+        }
+    };
+    transfer_altered(&mut server, check_retry, &mut client);
     client.process_new_packets().unwrap();
+    assert!(*sent_retry.lock().unwrap());
+    assert_eq!(client.early_data_status(), EarlyDataStatus::Rejected);
 
-    {
-        let mut pipe = OtherSession::new(&mut server);
-        let wrlen = client.write_tls(&mut pipe).unwrap();
-        assert_eq!(wrlen, 154);
-        // CCS, finished, then two application datas
-        assert_eq!(pipe.writevs, vec![vec![6, 74, 42, 32]]);
-    }
-
-    assert_eq!(server.is_handshaking(), false);
-    assert_eq!(client.is_handshaking(), false);
-    check_read(&mut server.reader(), b"012345678901234567890123456789");
+    do_handshake(&mut client, &mut server);
 }
 
 #[test]
-fn vectored_write_with_slow_client() {
-    let (mut client, mut server) = make_pair(KeyType::RSA);
+fn server_limits_skipped_early_data() {
+    let (client_config, server_config) = early_data_configs(verifier_server_config(), 32768);
 
-    client.set_buffer_limit(32);
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    assert_eq!(
+        client
+            .early_data()
+            .unwrap()
+            .write(&[0; 32768])
+            .unwrap(),
+        32768
+    );
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::PeerMisbehavedError(
+            "too much early data received".into()
+        )))
+    );
+}
 
-    do_handshake(&mut client, &mut server);
-    server
-        .writer()
-        .write(b"01234567890123456789")
-        .unwrap();
+struct FixedPsks(Vec<rustls::ExternalPsk>);
 
-    {
-        let mut pipe = OtherSession::new(&mut client);
-        pipe.short_writes = true;
-        let wrlen = server.write_tls(&mut pipe).unwrap()
-            + server.write_tls(&mut pipe).unwrap()
-            + server.write_tls(&mut pipe).unwrap()
-            + server.write_tls(&mut pipe).unwrap()
-            + server.write_tls(&mut pipe).unwrap()
-            + server.write_tls(&mut pipe).unwrap();
-        assert_eq!(42, wrlen);
-        assert_eq!(
-            pipe.writevs,
-            vec![vec![21], vec![10], vec![5], vec![3], vec![3]]
-        );
+impl rustls::ResolvesServerPsk for FixedPsks {
+    fn resolve(&self, identity: &[u8]) -> Option<rustls::ExternalPsk> {
+        self.0
+            .iter()
+            .find(|psk| psk.identity() == identity)
+            .cloned()
     }
-    check_read(&mut client.reader(), b"01234567890123456789");
 }
 
-struct ServerStorage {
-    storage: Arc<dyn rustls::StoresServerSessions>,
-    put_count: AtomicUsize,
-    get_count: AtomicUsize,
-    take_count: AtomicUsize,
+fn external_psk(identity: &[u8], secret: &[u8]) -> rustls::ExternalPsk {
+    rustls::ExternalPsk::new(identity, secret, rustls::HashAlgorithm::SHA256).unwrap()
 }
 
-impl ServerStorage {
-    fn new() -> ServerStorage {
-        ServerStorage {
-            storage: rustls::ServerSessionMemoryCache::new(1024),
-            put_count: AtomicUsize::new(0),
-            get_count: AtomicUsize::new(0),
-            take_count: AtomicUsize::new(0),
-        }
-    }
+fn external_psk_configs(
+    client_psk: rustls::ExternalPsk,
+    server_psks: Vec<rustls::ExternalPsk>,
+    server_config: ServerConfig,
+    mode: rustls::PSKKeyExchangeMode,
+) -> (ClientConfig, ServerConfig) {
+    let mut client_config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(verifier_roots(), &[])
+        .with_no_client_auth();
+    client_config.external_psk = Some(client_psk);
+    client_config.psk_key_exchange_modes = vec![mode];
+
+    let mut server_config = server_config;
+    server_config.psk_resolver = Some(Arc::new(FixedPsks(server_psks)));
+    server_config.psk_key_exchange_modes = vec![mode];
+    (client_config, server_config)
+}
 
-    fn puts(&self) -> usize {
-        self.put_count.load(Ordering::SeqCst)
-    }
-    fn gets(&self) -> usize {
-        self.get_count.load(Ordering::SeqCst)
-    }
-    fn takes(&self) -> usize {
-        self.take_count.load(Ordering::SeqCst)
-    }
+fn server_config_without_certificate() -> ServerConfig {
+    ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(rustls::ResolvesServerCertUsingSni::new()))
 }
 
-impl fmt::Debug for ServerStorage {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "(put: {:?}, get: {:?}, take: {:?})",
-            self.put_count, self.get_count, self.take_count
-        )
+#[test]
+fn external_psk_authenticates_without_certificates() {
+    for mode in &[
+        rustls::PSKKeyExchangeMode::PSK_DHE_KE,
+        rustls::PSKKeyExchangeMode::PSK_KE,
+    ] {
+        let psk = external_psk(b"sensor-17", b"provisioned secret");
+        let (client_config, server_config) = external_psk_configs(
+            psk.clone(),
+            vec![external_psk(b"sensor-3", b"another secret"), psk],
+            server_config_without_certificate(),
+            *mode,
+        );
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(server.psk_identity(), Some(&b"sensor-17"[..]));
+        assert_eq!(client.peer_certificates(), None);
+        assert_eq!(client.protocol_version(), Some(ProtocolVersion::TLSv1_3));
+
+        client
+            .writer()
+            .write_all(b"hello")
+            .unwrap();
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+        let mut buf = [0u8; 5];
+        server
+            .reader()
+            .read_exact(&mut buf)
+            .unwrap();
+        assert_eq!(&buf, b"hello");
     }
 }
 
-impl rustls::StoresServerSessions for ServerStorage {
-    fn put(&self, key: Vec<u8>, value: Vec<u8>) -> bool {
-        self.put_count
-            .fetch_add(1, Ordering::SeqCst);
-        self.storage.put(key, value)
-    }
+#[test]
+fn external_psk_with_sha384_chooses_suitable_suite() {
+    let psk = rustls::ExternalPsk::new(
+        b"sensor-17",
+        b"provisioned secret",
+        rustls::HashAlgorithm::SHA384,
+    )
+    .unwrap();
+    let (client_config, server_config) = external_psk_configs(
+        psk.clone(),
+        vec![psk],
+        server_config_without_certificate(),
+        rustls::PSKKeyExchangeMode::PSK_DHE_KE,
+    );
 
-    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
-        self.get_count
-            .fetch_add(1, Ordering::SeqCst);
-        self.storage.get(key)
-    }
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(server.psk_identity(), Some(&b"sensor-17"[..]));
+    assert_eq!(
+        client
+            .negotiated_cipher_suite()
+            .unwrap()
+            .suite,
+        CipherSuite::TLS13_AES_256_GCM_SHA384
+    );
+}
 
-    fn take(&self, key: &[u8]) -> Option<Vec<u8>> {
-        self.take_count
-            .fetch_add(1, Ordering::SeqCst);
-        self.storage.take(key)
-    }
+#[test]
+fn external_psk_survives_hello_retry_request() {
+    let psk = external_psk(b"sensor-17", b"provisioned secret");
+    let (client_config, mut server_config) = external_psk_configs(
+        psk.clone(),
+        vec![psk],
+        server_config_without_certificate(),
+        rustls::PSKKeyExchangeMode::PSK_DHE_KE,
+    );
+    server_config.kx_groups = vec![&rustls::kx_group::SECP384R1];
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(server.psk_identity(), Some(&b"sensor-17"[..]));
 }
 
-struct ClientStorage {
-    storage: Arc<dyn rustls::StoresClientSessions>,
-    put_count: AtomicUsize,
-    get_count: AtomicUsize,
-    last_put_key: Mutex<Option<Vec<u8>>>,
+#[test]
+fn external_psk_with_wrong_secret_is_rejected() {
+    let (client_config, server_config) = external_psk_configs(
+        external_psk(b"sensor-17", b"provisioned secret"),
+        vec![external_psk(b"sensor-17", b"different secret")],
+        server_config_without_certificate(),
+        rustls::PSKKeyExchangeMode::PSK_DHE_KE,
+    );
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::PeerMisbehavedError(
+            "client sent wrong binder".into()
+        )))
+    );
 }
 
-impl ClientStorage {
-    fn new() -> ClientStorage {
-        ClientStorage {
-            storage: rustls::ClientSessionMemoryCache::new(1024),
-            put_count: AtomicUsize::new(0),
-            get_count: AtomicUsize::new(0),
-            last_put_key: Mutex::new(None),
-        }
-    }
+#[test]
+fn unknown_external_psk_falls_back_to_certificate() {
+    let (client_config, server_config) = external_psk_configs(
+        external_psk(b"sensor-17", b"provisioned secret"),
+        vec![external_psk(b"sensor-3", b"another secret")],
+        verifier_server_config(),
+        rustls::PSKKeyExchangeMode::PSK_DHE_KE,
+    );
 
-    fn puts(&self) -> usize {
-        self.put_count.load(Ordering::SeqCst)
-    }
-    fn gets(&self) -> usize {
-        self.get_count.load(Ordering::SeqCst)
-    }
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(server.psk_identity(), None);
+    assert_eq!(client.peer_certificates(), Some(&verifier_chain()[..]));
 }
 
-impl fmt::Debug for ClientStorage {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "(puts: {:?}, gets: {:?} )",
-            self.put_count, self.get_count
-        )
-    }
+#[test]
+fn external_psk_is_not_accepted_by_servers_without_psks() {
+    let (client_config, mut server_config) = external_psk_configs(
+        external_psk(b"sensor-17", b"provisioned secret"),
+        vec![],
+        server_config_without_certificate(),
+        rustls::PSKKeyExchangeMode::PSK_DHE_KE,
+    );
+    server_config.psk_resolver = None;
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::General(
+            "no server certificate chain resolved".into()
+        )))
+    );
 }
 
-impl rustls::StoresClientSessions for ClientStorage {
-    fn put(&self, key: Vec<u8>, value: Vec<u8>) -> bool {
-        self.put_count
-            .fetch_add(1, Ordering::SeqCst);
-        *self.last_put_key.lock().unwrap() = Some(key.clone());
-        self.storage.put(key, value)
-    }
+fn tls12_external_psk_configs(
+    suite: &'static rustls::SupportedCipherSuite,
+    client_psk: Option<rustls::ExternalPsk>,
+    server_psks: Vec<rustls::ExternalPsk>,
+) -> (ClientConfig, ServerConfig) {
+    let mut client_config = ClientConfig::builder()
+        .with_cipher_suites(&[suite])
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&[&rustls::version::TLS12])
+        .for_client()
+        .unwrap()
+        .with_root_certificates(verifier_roots(), &[])
+        .with_no_client_auth();
+    client_config.external_psk = client_psk;
+
+    let mut server_config = ServerConfig::builder()
+        .with_cipher_suites(&[suite])
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&[&rustls::version::TLS12])
+        .for_server()
+        .unwrap()
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(rustls::ResolvesServerCertUsingSni::new()));
+    server_config.psk_resolver = Some(Arc::new(FixedPsks(server_psks)));
+    (client_config, server_config)
+}
 
-    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
-        self.get_count
-            .fetch_add(1, Ordering::SeqCst);
-        self.storage.get(key)
+#[test]
+fn tls12_external_psk_authenticates_without_certificates() {
+    for suite in &[
+        &rustls::cipher_suite::TLS_ECDHE_PSK_WITH_AES_128_GCM_SHA256,
+        &rustls::cipher_suite::TLS_ECDHE_PSK_WITH_AES_256_GCM_SHA384,
+        &rustls::cipher_suite::TLS_ECDHE_PSK_WITH_CHACHA20_POLY1305_SHA256,
+    ] {
+        let psk = external_psk(b"sensor-17", b"provisioned secret");
+        let (client_config, mut server_config) = tls12_external_psk_configs(
+            suite,
+            Some(psk.clone()),
+            vec![external_psk(b"sensor-3", b"another secret"), psk],
+        );
+        server_config.psk_identity_hint = b"gateway".to_vec();
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(server.psk_identity(), Some(&b"sensor-17"[..]));
+        assert_eq!(client.psk_identity_hint(), Some(&b"gateway"[..]));
+        assert_eq!(client.peer_certificates(), None);
+        assert_eq!(client.protocol_version(), Some(ProtocolVersion::TLSv1_2));
+        assert_eq!(
+            client
+                .negotiated_cipher_suite()
+                .unwrap()
+                .suite,
+            suite.suite
+        );
+
+        client
+            .writer()
+            .write_all(b"hello")
+            .unwrap();
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+        let mut buf = [0u8; 5];
+        server
+            .reader()
+            .read_exact(&mut buf)
+            .unwrap();
+        assert_eq!(&buf, b"hello");
     }
 }
 
 #[test]
-fn tls13_stateful_resumption() {
-    let kt = KeyType::RSA;
-    let mut client_config = make_client_config(kt);
-    client_config
-        .versions
-        .replace(&[&rustls::version::TLS13]);
-    let client_config = Arc::new(client_config);
+fn tls12_external_psk_without_hint() {
+    let psk = external_psk(b"sensor-17", b"provisioned secret");
+    let (client_config, server_config) = tls12_external_psk_configs(
+        &rustls::cipher_suite::TLS_ECDHE_PSK_WITH_AES_128_GCM_SHA256,
+        Some(psk.clone()),
+        vec![psk],
+    );
 
-    let mut server_config = make_server_config(kt);
-    let storage = Arc::new(ServerStorage::new());
-    server_config.session_storage = storage.clone();
-    let server_config = Arc::new(server_config);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(server.psk_identity(), Some(&b"sensor-17"[..]));
+    assert_eq!(client.psk_identity_hint(), None);
+}
 
-    // full handshake
-    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    let (full_c2s, full_s2c) = do_handshake(&mut client, &mut server);
-    assert_eq!(storage.puts(), 1);
-    assert_eq!(storage.gets(), 0);
-    assert_eq!(storage.takes(), 0);
+#[test]
+fn tls12_external_psk_with_wrong_secret_is_rejected() {
+    let (client_config, server_config) = tls12_external_psk_configs(
+        &rustls::cipher_suite::TLS_ECDHE_PSK_WITH_AES_128_GCM_SHA256,
+        Some(external_psk(b"sensor-17", b"provisioned secret")),
+        vec![external_psk(b"sensor-17", b"different secret")],
+    );
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
     assert_eq!(
-        client
-            .peer_certificates()
-            .map(|certs| certs.len()),
-        Some(3)
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::DecryptError))
     );
+}
 
-    // resumed
-    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    let (resume_c2s, resume_s2c) = do_handshake(&mut client, &mut server);
-    assert!(resume_c2s > full_c2s);
-    assert!(resume_s2c < full_s2c);
-    assert_eq!(storage.puts(), 2);
-    assert_eq!(storage.gets(), 0);
-    assert_eq!(storage.takes(), 1);
+#[test]
+fn tls12_unknown_external_psk_identity_is_rejected() {
+    let (client_config, server_config) = tls12_external_psk_configs(
+        &rustls::cipher_suite::TLS_ECDHE_PSK_WITH_AES_128_GCM_SHA256,
+        Some(external_psk(b"sensor-17", b"provisioned secret")),
+        vec![external_psk(b"sensor-3", b"another secret")],
+    );
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
     assert_eq!(
-        client
-            .peer_certificates()
-            .map(|certs| certs.len()),
-        Some(3)
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::PeerMisbehavedError(
+            "client used an unknown PSK identity".into()
+        )))
     );
+    assert_eq!(server.psk_identity(), None);
+}
 
-    // resumed again
-    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    let (resume2_c2s, resume2_s2c) = do_handshake(&mut client, &mut server);
-    assert_eq!(resume_s2c, resume2_s2c);
-    assert_eq!(resume_c2s, resume2_c2s);
-    assert_eq!(storage.puts(), 3);
-    assert_eq!(storage.gets(), 0);
-    assert_eq!(storage.takes(), 2);
+#[test]
+fn tls12_psk_suites_are_not_offered_without_external_psk() {
+    let suites = [
+        &rustls::cipher_suite::TLS_ECDHE_PSK_WITH_AES_128_GCM_SHA256,
+        &rustls::cipher_suite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+    ];
+    let client_config = ClientConfig::builder()
+        .with_cipher_suites(&suites)
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&[&rustls::version::TLS12])
+        .for_client()
+        .unwrap()
+        .with_root_certificates(verifier_roots(), &[])
+        .with_no_client_auth();
+    let mut server_config = ServerConfig::builder()
+        .with_cipher_suites(&suites)
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&[&rustls::version::TLS12])
+        .for_server()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(verifier_chain(), verifier_key("end"))
+        .unwrap();
+    server_config.ignore_client_order = true;
+    server_config.psk_resolver = Some(Arc::new(FixedPsks(vec![external_psk(
+        b"sensor-17",
+        b"provisioned secret",
+    )])));
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
     assert_eq!(
         client
-            .peer_certificates()
-            .map(|certs| certs.len()),
-        Some(3)
+            .negotiated_cipher_suite()
+            .unwrap()
+            .suite,
+        CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256
     );
+    assert_eq!(client.peer_certificates(), Some(&verifier_chain()[..]));
 }
 
 #[test]
-fn tls13_stateless_resumption() {
-    let kt = KeyType::RSA;
-    let mut client_config = make_client_config(kt);
+fn connection_options_override_config() {
+    let verifier = WebPkiVerifier::new(verifier_roots(), &[]);
+    let mut client_config = make_client_config_with_verifier(Arc::new(verifier));
     client_config
         .versions
         .replace(&[&rustls::version::TLS13]);
+    client_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    let client_storage = Arc::new(ClientStorage::new());
+    client_config.session_storage = client_storage.clone();
     let client_config = Arc::new(client_config);
 
-    let mut server_config = make_server_config(kt);
-    server_config.ticketer = rustls::Ticketer::new().unwrap();
+    let mut server_config = make_verifier_server_config(&[]);
     let storage = Arc::new(ServerStorage::new());
     server_config.session_storage = storage.clone();
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
     let server_config = Arc::new(server_config);
 
-    // full handshake
-    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    let (full_c2s, full_s2c) = do_handshake(&mut client, &mut server);
-    assert_eq!(storage.puts(), 0);
-    assert_eq!(storage.gets(), 0);
-    assert_eq!(storage.takes(), 0);
-    assert_eq!(
-        client
-            .peer_certificates()
-            .map(|certs| certs.len()),
-        Some(3)
-    );
+    let connect = |options: ConnectionOptions| {
+        (
+            ClientConnection::new_with_options(
+                Arc::clone(&client_config),
+                server_name("localhost"),
+                options,
+            )
+            .unwrap(),
+            ServerConnection::new(Arc::clone(&server_config)).unwrap(),
+        )
+    };
+    let without_resumption = || {
+        let mut options = ConnectionOptions::new();
+        options.enable_resumption = Some(false);
+        options
+    };
 
-    // resumed
-    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    let (resume_c2s, resume_s2c) = do_handshake(&mut client, &mut server);
-    assert!(resume_c2s > full_c2s);
-    assert!(resume_s2c < full_s2c);
-    assert_eq!(storage.puts(), 0);
-    assert_eq!(storage.gets(), 0);
+    // Neither stores its session nor offers one stored before.
+    let (mut client, mut server) = connect(without_resumption());
+    do_handshake(&mut client, &mut server);
+    let (mut client, mut server) = connect(ConnectionOptions::new());
+    do_handshake(&mut client, &mut server);
     assert_eq!(storage.takes(), 0);
-    assert_eq!(
-        client
-            .peer_certificates()
-            .map(|certs| certs.len()),
-        Some(3)
-    );
-
-    // resumed again
-    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    let (resume2_c2s, resume2_s2c) = do_handshake(&mut client, &mut server);
-    assert_eq!(resume_s2c, resume2_s2c);
-    assert_eq!(resume_c2s, resume2_c2s);
-    assert_eq!(storage.puts(), 0);
-    assert_eq!(storage.gets(), 0);
+    let (mut client, mut server) = connect(without_resumption());
+    do_handshake(&mut client, &mut server);
     assert_eq!(storage.takes(), 0);
-    assert_eq!(
-        client
-            .peer_certificates()
-            .map(|certs| certs.len()),
-        Some(3)
-    );
-}
-
-#[test]
-fn early_data_not_available() {
-    let (mut client, _) = make_pair(KeyType::RSA);
-    assert!(client.early_data().is_none());
-}
-
-#[test]
-fn early_data_is_available_on_resumption() {
-    let kt = KeyType::RSA;
-    let mut client_config = make_client_config(kt);
-    client_config.enable_early_data = true;
-
-    let storage = Arc::new(ClientStorage::new());
-    client_config.session_storage = storage.clone();
-
-    let client_config = Arc::new(client_config);
+    let (mut client, mut server) = connect(ConnectionOptions::new());
+    do_handshake(&mut client, &mut server);
+    assert_eq!(storage.takes(), 1);
+    assert_eq!(client.alpn_protocol(), Some(&b"http/1.1"[..]));
+    assert_eq!(server.sni_hostname(), Some("localhost"));
 
-    let server_config = Arc::new(make_server_config(kt));
-    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    let mut options = ConnectionOptions::new();
+    options.alpn_protocols = Some(vec![b"h2".to_vec()]);
+    options.enable_sni = Some(false);
+    let (mut client, mut server) = connect(options);
     do_handshake(&mut client, &mut server);
+    assert_eq!(storage.takes(), 2);
+    assert_eq!(client.alpn_protocol(), Some(&b"h2"[..]));
+    assert_eq!(server.sni_hostname(), None);
 
-    /* discover the session data in the storage, and edit it to fool the
-     * client on resumption that the server supports 0rtt. */
-    let session_key = storage
+    // Fool the client into thinking the server supports early data, as
+    // early_data_is_available_on_resumption does.
+    let session_key = client_storage
         .last_put_key
         .lock()
         .unwrap()
         .clone()
         .unwrap();
-
-    let session_value_bytes = storage
+    let session_value_bytes = client_storage
         .storage
         .get(&session_key)
         .unwrap();
     let mut session_value = ClientSessionValue::read_bytes(&session_value_bytes).unwrap();
     session_value.max_early_data_size = 128;
-
-    storage
+    client_storage
         .storage
         .put(session_key, session_value.get_encoding());
 
-    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    let (mut client, _) = connect(ConnectionOptions::new());
+    assert!(client.early_data().is_none());
+    let mut options = ConnectionOptions::new();
+    options.enable_early_data = Some(true);
+    let (mut client, _) = connect(options);
     assert!(client.early_data().is_some());
-    assert_eq!(
-        client
-            .early_data()
-            .unwrap()
-            .bytes_left(),
-        128
-    );
-    assert_eq!(
-        client
-            .early_data()
-            .unwrap()
-            .flush()
+}
+
+#[test]
+fn connection_options_without_resumption_leave_session_storage_alone() {
+    let server_config = Arc::new(
+        ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(verifier_chain(), verifier_key("end"))
             .unwrap(),
-        ()
     );
-    assert_eq!(
-        client
-            .early_data()
+
+    for version in rustls::ALL_VERSIONS.iter() {
+        let mut client_config = ClientConfig::builder()
+            .with_safe_default_cipher_suites()
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(&[*version])
+            .for_client()
             .unwrap()
-            .write(b"hello")
+            .with_root_certificates(verifier_roots(), &[])
+            .with_no_client_auth();
+        let client_storage = Arc::new(ClientStorage::new());
+        client_config.session_storage = client_storage.clone();
+        let client_config = Arc::new(client_config);
+
+        let connect = |enable_resumption| {
+            let mut options = ConnectionOptions::new();
+            options.enable_resumption = Some(enable_resumption);
+            let mut client = ClientConnection::new_with_options(
+                Arc::clone(&client_config),
+                server_name("localhost"),
+                options,
+            )
+            .unwrap();
+            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+            do_handshake(&mut client, &mut server);
+            transfer(&mut server, &mut client);
+            client.process_new_packets().unwrap();
+            client
+        };
+
+        connect(false);
+        assert_eq!((client_storage.gets(), client_storage.puts()), (0, 0));
+
+        // Others sharing the config still use it.
+        connect(true);
+        let used = (client_storage.gets(), client_storage.puts());
+        assert!(used.0 > 0 && used.1 > 0);
+
+        connect(false);
+        assert_eq!((client_storage.gets(), client_storage.puts()), used);
+    }
+}
+
+#[cfg(feature = "deterministic_testing")]
+#[test]
+fn deterministic_hello_makes_client_hello_reproducible() {
+    use rustls::DeterministicHello;
+
+    let server_config = Arc::new(
+        ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(verifier_chain(), verifier_key("end"))
             .unwrap(),
-        5
     );
-    let err = do_handshake_until_error(&mut client, &mut server);
-    assert_eq!(err, Err(ErrorFromPeer::Server(Error::DecryptError)));
+    let fixed = DeterministicHello {
+        client_random: [1; 32],
+        session_id: [2; 32],
+        key_share_seed: [3; 32],
+    };
+
+    for group in rustls::ALL_KX_GROUPS.iter() {
+        for version in rustls::ALL_VERSIONS.iter() {
+            let client_hello = |fixed: Option<DeterministicHello>| {
+                let client_config = ConfigBuilder::with_safe_default_cipher_suites()
+                    .with_kx_groups(&[*group])
+                    .with_protocol_versions(&[*version])
+                    .for_client()
+                    .unwrap()
+                    .with_root_certificates(verifier_roots(), &[])
+                    .with_no_client_auth();
+                let mut options = ConnectionOptions::new();
+                options.deterministic_hello = fixed;
+                let mut client = ClientConnection::new_with_options(
+                    Arc::new(client_config),
+                    server_name("localhost"),
+                    options,
+                )
+                .unwrap();
+
+                let mut hello = Vec::new();
+                client.write_tls(&mut hello).unwrap();
+
+                let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+                server
+                    .read_tls(&mut io::Cursor::new(&hello))
+                    .unwrap();
+                server.process_new_packets().unwrap();
+                do_handshake(&mut client, &mut server);
+                hello
+            };
+
+            let hello = client_hello(Some(fixed.clone()));
+            assert_eq!(hello, client_hello(Some(fixed.clone())));
+            assert_ne!(hello, client_hello(None));
+
+            // Only TLS1.3 ClientHellos carry key shares.
+            let other_key_share = client_hello(Some(DeterministicHello {
+                key_share_seed: [4; 32],
+                ..fixed.clone()
+            }));
+            assert_eq!(
+                hello != other_key_share,
+                version.version == ProtocolVersion::TLSv1_3
+            );
+        }
+    }
 }
 
 #[cfg(feature = "quic")]
@@ -3128,7 +8839,7 @@ mod test_quic {
         let mut client = ClientConnection::new_quic(
             Arc::clone(&client_config),
             quic::Version::V1,
-            dns_name("localhost"),
+            server_name("localhost"),
             client_params.into(),
         )
         .unwrap();
@@ -3180,7 +8891,7 @@ mod test_quic {
         let mut client = ClientConnection::new_quic(
             Arc::clone(&client_config),
             quic::Version::V1,
-            dns_name("localhost"),
+            server_name("localhost"),
             client_params.into(),
         )
         .unwrap();
@@ -3222,7 +8933,7 @@ mod test_quic {
             let mut client = ClientConnection::new_quic(
                 Arc::new(client_config),
                 quic::Version::V1,
-                dns_name("localhost"),
+                server_name("localhost"),
                 client_params.into(),
             )
             .unwrap();
@@ -3254,7 +8965,7 @@ mod test_quic {
         let mut client = ClientConnection::new_quic(
             client_config,
             quic::Version::V1,
-            dns_name("example.com"),
+            server_name("example.com"),
             client_params.into(),
         )
         .unwrap();
@@ -3297,7 +9008,7 @@ mod test_quic {
             let mut client = ClientConnection::new_quic(
                 client_config,
                 quic::Version::V1,
-                dns_name("localhost"),
+                server_name("localhost"),
                 client_params.into(),
             )
             .unwrap();
@@ -3332,7 +9043,7 @@ mod test_quic {
             ClientConnection::new_quic(
                 client_config,
                 quic::Version::V1,
-                dns_name("localhost"),
+                server_name("localhost"),
                 b"client params".to_vec(),
             )
             .is_err()
@@ -3708,52 +9419,266 @@ fn test_client_mtu_reduction() {
         }
     }
 
-    fn collect_write_lengths(client: &mut ClientConnection) -> Vec<usize> {
-        let mut collector = CollectWrites { writevs: vec![] };
+    fn collect_write_lengths(client: &mut ClientConnection) -> Vec<usize> {
+        let mut collector = CollectWrites { writevs: vec![] };
+
+        client
+            .write_tls(&mut collector)
+            .unwrap();
+        assert_eq!(collector.writevs.len(), 1);
+        collector.writevs[0].clone()
+    }
+
+    for kt in ALL_KEY_TYPES.iter() {
+        let mut client_config = make_client_config(*kt);
+        client_config.max_fragment_size = Some(64);
+        let mut client =
+            ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+        let writes = collect_write_lengths(&mut client);
+        println!("writes at mtu=64: {:?}", writes);
+        assert!(writes.iter().all(|x| *x <= 64));
+        assert!(writes.len() > 1);
+    }
+}
+
+fn check_client_max_fragment_size(size: usize) -> Option<Error> {
+    let mut client_config = make_client_config(KeyType::ED25519);
+    client_config.max_fragment_size = Some(size);
+    ClientConnection::new(Arc::new(client_config), server_name("localhost")).err()
+}
+
+#[test]
+fn bad_client_max_fragment_sizes() {
+    assert_eq!(
+        check_client_max_fragment_size(31),
+        Some(Error::BadMaxFragmentSize)
+    );
+    assert_eq!(check_client_max_fragment_size(32), None);
+    assert_eq!(check_client_max_fragment_size(64), None);
+    assert_eq!(check_client_max_fragment_size(1460), None);
+    assert_eq!(check_client_max_fragment_size(0x4000), None);
+    assert_eq!(check_client_max_fragment_size(0x4005), None);
+    assert_eq!(
+        check_client_max_fragment_size(0x4006),
+        Some(Error::BadMaxFragmentSize)
+    );
+    assert_eq!(
+        check_client_max_fragment_size(0xffff),
+        Some(Error::BadMaxFragmentSize)
+    );
+}
+
+/// The lengths of the records written by `conn`.
+fn written_record_lengths(conn: &mut dyn Connection) -> Vec<usize> {
+    let mut buf = Vec::new();
+    conn.write_tls(&mut buf).unwrap();
+
+    let mut lengths = Vec::new();
+    let mut rest = &buf[..];
+    while !rest.is_empty() {
+        let len = u16::from_be_bytes([rest[3], rest[4]]) as usize;
+        lengths.push(len);
+        rest = &rest[5 + len..];
+    }
+    lengths
+}
+
+#[test]
+fn max_fragment_length_limits_records_both_ways() {
+    let server_config = Arc::new(
+        ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(verifier_chain(), verifier_key("end"))
+            .unwrap(),
+    );
+    let make_client_config = || {
+        ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(verifier_roots(), &[])
+            .with_no_client_auth()
+    };
+    let mut limited_config = make_client_config();
+    limited_config.max_fragment_length = Some(MaxFragmentLength::Len512);
+
+    for client_config in AllClientVersions::new(limited_config) {
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        do_handshake(&mut client, &mut server);
+
+        let data = vec![0x5a; 2000];
+        server
+            .writer()
+            .write_all(&data)
+            .unwrap();
+        let lengths = written_record_lengths(&mut server);
+        assert!(lengths.len() >= 4);
+        assert!(lengths
+            .iter()
+            .all(|len| *len <= 512 + 256));
+
+        client
+            .writer()
+            .write_all(&data)
+            .unwrap();
+        let lengths = written_record_lengths(&mut client);
+        assert!(lengths.len() >= 4);
+        assert!(lengths
+            .iter()
+            .all(|len| *len <= 512 + 256));
+    }
+
+    // Without it, records are as large as possible.
+    for client_config in AllClientVersions::new(make_client_config()) {
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        do_handshake(&mut client, &mut server);
+
+        client
+            .writer()
+            .write_all(&[0x5a; 2000])
+            .unwrap();
+        assert_eq!(written_record_lengths(&mut client).len(), 1);
+    }
+}
+
+#[test]
+fn max_fragment_length_data_is_received_intact() {
+    let server_config = Arc::new(
+        ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(verifier_chain(), verifier_key("end"))
+            .unwrap(),
+    );
+    let mut client_config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(verifier_roots(), &[])
+        .with_no_client_auth();
+    client_config.max_fragment_length = Some(MaxFragmentLength::Len1024);
+
+    for client_config in AllClientVersions::new(client_config) {
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        do_handshake(&mut client, &mut server);
+
+        let data: Vec<u8> = (0..5000u32).map(|i| i as u8).collect();
+        server
+            .writer()
+            .write_all(&data)
+            .unwrap();
+        transfer(&mut server, &mut client);
+        client.process_new_packets().unwrap();
+        let mut received = vec![0u8; data.len()];
+        client
+            .reader()
+            .read_exact(&mut received)
+            .unwrap();
+        assert_eq!(received, data);
+    }
+}
+
+fn record_size_limit_pair(
+    client_limit: Option<usize>,
+    server_limit: Option<usize>,
+) -> impl Iterator<Item = (ClientConnection, ServerConnection)> {
+    let mut server_config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(verifier_chain(), verifier_key("end"))
+        .unwrap();
+    server_config.record_size_limit = server_limit;
+    let server_config = Arc::new(server_config);
+
+    vec![&rustls::version::TLS12, &rustls::version::TLS13]
+        .into_iter()
+        .map(move |version| {
+            let mut client_config = ClientConfig::builder()
+                .with_safe_default_cipher_suites()
+                .with_safe_default_kx_groups()
+                .with_protocol_versions(&[version])
+                .for_client()
+                .unwrap()
+                .with_root_certificates(verifier_roots(), &[])
+                .with_no_client_auth();
+            client_config.record_size_limit = client_limit;
+            // Ignored in favour of the record size limit.
+            client_config.max_fragment_length = Some(MaxFragmentLength::Len512);
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config)
+        })
+}
+
+#[test]
+fn record_size_limit_limits_records_both_ways() {
+    for (mut client, mut server) in record_size_limit_pair(Some(600), Some(1200)) {
+        do_handshake(&mut client, &mut server);
+
+        // Each side sends records no bigger than the other's limit.
+        server
+            .writer()
+            .write_all(&[0x5a; 3000])
+            .unwrap();
+        let lengths = written_record_lengths(&mut server);
+        assert!(lengths
+            .iter()
+            .all(|len| *len <= 600 + 256));
+        assert!(lengths
+            .iter()
+            .any(|len| *len > 512 + 32));
 
         client
-            .write_tls(&mut collector)
+            .writer()
+            .write_all(&[0x5a; 3000])
             .unwrap();
-        assert_eq!(collector.writevs.len(), 1);
-        collector.writevs[0].clone()
+        let lengths = written_record_lengths(&mut client);
+        assert!(lengths
+            .iter()
+            .all(|len| *len <= 1200 + 256));
+        assert!(lengths
+            .iter()
+            .any(|len| *len > 600 + 256));
     }
+}
 
-    for kt in ALL_KEY_TYPES.iter() {
-        let mut client_config = make_client_config(*kt);
-        client_config.max_fragment_size = Some(64);
-        let mut client =
-            ClientConnection::new(Arc::new(client_config), dns_name("localhost")).unwrap();
-        let writes = collect_write_lengths(&mut client);
-        println!("writes at mtu=64: {:?}", writes);
-        assert!(writes.iter().all(|x| *x <= 64));
-        assert!(writes.len() > 1);
+#[test]
+fn record_size_limit_is_answered_by_default() {
+    for (mut client, mut server) in record_size_limit_pair(Some(600), None) {
+        do_handshake(&mut client, &mut server);
+
+        server
+            .writer()
+            .write_all(&[0x5a; 3000])
+            .unwrap();
+        let lengths = written_record_lengths(&mut server);
+        assert!(lengths
+            .iter()
+            .all(|len| *len <= 600 + 256));
+
+        client
+            .writer()
+            .write_all(&[0x5a; 3000])
+            .unwrap();
+        assert_eq!(written_record_lengths(&mut client).len(), 1);
     }
 }
 
-fn check_client_max_fragment_size(size: usize) -> Option<Error> {
+fn check_client_record_size_limit(limit: usize) -> Option<Error> {
     let mut client_config = make_client_config(KeyType::ED25519);
-    client_config.max_fragment_size = Some(size);
-    ClientConnection::new(Arc::new(client_config), dns_name("localhost")).err()
+    client_config.record_size_limit = Some(limit);
+    ClientConnection::new(Arc::new(client_config), server_name("localhost")).err()
 }
 
 #[test]
-fn bad_client_max_fragment_sizes() {
-    assert_eq!(
-        check_client_max_fragment_size(31),
-        Some(Error::BadMaxFragmentSize)
-    );
-    assert_eq!(check_client_max_fragment_size(32), None);
-    assert_eq!(check_client_max_fragment_size(64), None);
-    assert_eq!(check_client_max_fragment_size(1460), None);
-    assert_eq!(check_client_max_fragment_size(0x4000), None);
-    assert_eq!(check_client_max_fragment_size(0x4005), None);
+fn bad_client_record_size_limits() {
     assert_eq!(
-        check_client_max_fragment_size(0x4006),
-        Some(Error::BadMaxFragmentSize)
+        check_client_record_size_limit(63),
+        Some(Error::BadRecordSizeLimit)
     );
+    assert_eq!(check_client_record_size_limit(64), None);
+    assert_eq!(check_client_record_size_limit(0x4001), None);
     assert_eq!(
-        check_client_max_fragment_size(0xffff),
-        Some(Error::BadMaxFragmentSize)
+        check_client_record_size_limit(0x4002),
+        Some(Error::BadRecordSizeLimit)
     );
 }
 
@@ -3890,6 +9815,535 @@ fn test_server_rejects_clients_without_any_kx_group_overlap() {
     );
 }
 
+#[test]
+fn client_sends_same_ech_grease_in_both_client_hellos() {
+    let sent_ech = Mutex::new(Vec::new());
+    let record_ech = |msg: &mut Message| {
+        if let MessagePayload::Handshake(hs) = &mut msg.payload {
+            if let HandshakePayload::ClientHello(ch) = &mut hs.payload {
+                for ext in &ch.extensions {
+                    if let ClientExtension::EncryptedClientHello(ech) = ext {
+                        sent_ech
+                            .lock()
+                            .unwrap()
+                            .push(ech.get_encoding());
+                    }
+                }
+            }
+        }
+    };
+
+    let mut client_config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(verifier_roots(), &[])
+        .with_no_client_auth();
+    client_config.enable_ech_grease = true;
+    // Makes the server send a HelloRetryRequest.
+    let server_config = ConfigBuilder::with_safe_default_cipher_suites()
+        .with_kx_groups(&[&rustls::kx_group::SECP384R1])
+        .with_safe_default_protocol_versions()
+        .for_server()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(verifier_chain(), verifier_key("end"))
+        .unwrap();
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    transfer_altered(&mut client, record_ech, &mut server);
+    server.process_new_packets().unwrap();
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    transfer_altered(&mut client, record_ech, &mut server);
+    server.process_new_packets().unwrap();
+    do_handshake(&mut client, &mut server);
+
+    let sent_ech = sent_ech.into_inner().unwrap();
+    assert_eq!(sent_ech.len(), 2);
+    assert_eq!(sent_ech[0], sent_ech[1]);
+    // type, KDF, AEAD, config id, 32-byte key and padded payload.
+    assert!(sent_ech[0].len() >= 1 + 2 + 2 + 1 + 2 + 32 + 2 + 128 + 16);
+    assert_eq!(&sent_ech[0][..5], &[0, 0, 1, 0, 1]);
+}
+
+#[test]
+fn client_does_not_send_ech_grease_by_default() {
+    fn no_ech(msg: &mut Message) {
+        if let MessagePayload::Handshake(hs) = &mut msg.payload {
+            if let HandshakePayload::ClientHello(ch) = &mut hs.payload {
+                assert!(!ch
+                    .extensions
+                    .iter()
+                    .any(|ext| matches!(ext, ClientExtension::EncryptedClientHello(_))));
+            }
+        }
+    }
+
+    let (mut client, mut server) = make_pair(KeyType::ED25519);
+    transfer_altered(&mut client, no_ech, &mut server);
+    server.process_new_packets().unwrap();
+}
+
+fn is_grease(value: u16) -> bool {
+    value & 0x0f0f == 0x0a0a && value >> 12 == (value >> 4) & 0x0f
+}
+
+/// The first cipher suite, extension type, version, group and signature
+/// scheme in a ClientHello, and the last extension type; and whether
+/// the first key share has a GREASE group.
+fn first_client_hello_values(msg: &Message) -> Option<(Vec<u16>, bool)> {
+    let ch = match &msg.payload {
+        MessagePayload::Handshake(hs) => match &hs.payload {
+            HandshakePayload::ClientHello(ch) => ch,
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    let mut values = vec![
+        ch.cipher_suites[0].get_u16(),
+        ch.extensions[0].get_type().get_u16(),
+    ];
+    let mut grease_share = false;
+    for ext in &ch.extensions {
+        match ext {
+            ClientExtension::SupportedVersions(versions) => values.push(versions[0].get_u16()),
+            ClientExtension::NamedGroups(groups) => values.push(groups[0].get_u16()),
+            ClientExtension::SignatureAlgorithms(schemes) => values.push(schemes[0].get_u16()),
+            ClientExtension::KeyShare(shares) => {
+                grease_share = is_grease(shares[0].group.get_u16());
+            }
+            _ => {}
+        }
+    }
+    let last = ch
+        .extensions
+        .iter()
+        .rev()
+        .find(|ext| !matches!(ext, ClientExtension::PresharedKey(_)))
+        .unwrap();
+    values.push(last.get_type().get_u16());
+    Some((values, grease_share))
+}
+
+#[test]
+fn client_sends_grease_values_which_servers_ignore() {
+    let mut client_config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(verifier_roots(), &[])
+        .with_no_client_auth();
+    client_config.enable_grease = true;
+    let server_config = Arc::new(
+        ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(verifier_chain(), verifier_key("end"))
+            .unwrap(),
+    );
+
+    for client_config in AllClientVersions::new(client_config) {
+        let tls13 = client_config.supports_version(ProtocolVersion::TLSv1_3);
+        let check_grease = |msg: &mut Message| {
+            if let Some((values, grease_share)) = first_client_hello_values(msg) {
+                assert_eq!(values.len(), 6);
+                assert!(values
+                    .iter()
+                    .all(|value| is_grease(*value)));
+                assert_ne!(values[1], values[5]);
+                assert_eq!(grease_share, tls13);
+            }
+        };
+
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        transfer_altered(&mut client, check_grease, &mut server);
+        server.process_new_packets().unwrap();
+        do_handshake(&mut client, &mut server);
+    }
+}
+
+#[test]
+fn client_sends_same_grease_values_in_both_client_hellos() {
+    let sent = Mutex::new(Vec::new());
+    let record = |msg: &mut Message| {
+        if let Some(values) = first_client_hello_values(msg) {
+            sent.lock().unwrap().push(values);
+        }
+    };
+
+    let mut client_config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(verifier_roots(), &[])
+        .with_no_client_auth();
+    client_config.enable_grease = true;
+    // Makes the server send a HelloRetryRequest.
+    let server_config = ConfigBuilder::with_safe_default_cipher_suites()
+        .with_kx_groups(&[&rustls::kx_group::SECP384R1])
+        .with_safe_default_protocol_versions()
+        .for_server()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(verifier_chain(), verifier_key("end"))
+        .unwrap();
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    transfer_altered(&mut client, record, &mut server);
+    server.process_new_packets().unwrap();
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    transfer_altered(&mut client, record, &mut server);
+    server.process_new_packets().unwrap();
+    do_handshake(&mut client, &mut server);
+
+    let sent = sent.into_inner().unwrap();
+    assert_eq!(sent.len(), 2);
+    assert_eq!(sent[0].0, sent[1].0);
+    // The second has only the key share the server asked for.
+    assert!(sent[0].1);
+    assert!(!sent[1].1);
+}
+
+#[test]
+fn client_grease_values_vary_between_connections() {
+    let suites = Mutex::new(Vec::new());
+    let record = |msg: &mut Message| {
+        if let Some((values, _)) = first_client_hello_values(msg) {
+            suites.lock().unwrap().push(values[0]);
+        }
+    };
+
+    let mut client_config = make_client_config(KeyType::ED25519);
+    client_config.enable_grease = true;
+    let client_config = Arc::new(client_config);
+    let server_config = Arc::new(make_server_config(KeyType::ED25519));
+
+    for _ in 0..16 {
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        transfer_altered(&mut client, record, &mut server);
+    }
+
+    let mut suites = suites.into_inner().unwrap();
+    suites.sort_unstable();
+    suites.dedup();
+    assert!(suites.len() > 1);
+}
+
+#[test]
+fn client_does_not_send_grease_by_default() {
+    fn no_grease(msg: &mut Message) {
+        if let Some((values, grease_share)) = first_client_hello_values(msg) {
+            assert!(!values
+                .iter()
+                .any(|value| is_grease(*value)));
+            assert!(!grease_share);
+        }
+    }
+
+    let (mut client, mut server) = make_pair(KeyType::ED25519);
+    transfer_altered(&mut client, no_grease, &mut server);
+    server.process_new_packets().unwrap();
+}
+
+fn client_hello_extension_types(msg: &Message) -> Option<Vec<ExtensionType>> {
+    match &msg.payload {
+        MessagePayload::Handshake(hs) => match &hs.payload {
+            HandshakePayload::ClientHello(ch) => Some(
+                ch.extensions
+                    .iter()
+                    .map(ClientExtension::get_type)
+                    .collect(),
+            ),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[test]
+fn client_hello_profile_shapes_client_hello() {
+    let alps = ExtensionType::Unknown(0x4469);
+    let mut client_config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(verifier_roots(), &[])
+        .with_no_client_auth();
+    client_config.enable_grease = true;
+    client_config.alpn_protocols = vec![b"h2".to_vec()];
+    client_config.client_hello_profile = ClientHelloProfile {
+        extension_order: vec![
+            ExtensionType::Unknown(0x0a0a),
+            ExtensionType::ALProtocolNegotiation,
+            alps,
+            ExtensionType::ServerName,
+            ExtensionType::Unknown(0x0a0a),
+            ExtensionType::Padding,
+        ],
+        extra_cipher_suites: vec![CipherSuite::TLS_RSA_WITH_AES_128_GCM_SHA256],
+        extra_groups: vec![NamedGroup::FFDHE2048],
+        extra_signature_schemes: vec![SignatureScheme::RSA_PKCS1_SHA1],
+        extra_extensions: vec![
+            (alps, vec![0x00, 0x03, 0x02, b'h', b'2']),
+            // We send this ourselves, so it's ignored.
+            (ExtensionType::ServerName, vec![]),
+        ],
+        ..ClientHelloProfile::default()
+    };
+    let server_config = Arc::new(
+        ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(verifier_chain(), verifier_key("end"))
+            .unwrap(),
+    );
+
+    for client_config in AllClientVersions::new(client_config) {
+        let check_hello = |msg: &mut Message| {
+            let ch = match &msg.payload {
+                MessagePayload::Handshake(hs) => match &hs.payload {
+                    HandshakePayload::ClientHello(ch) => ch,
+                    _ => return,
+                },
+                _ => return,
+            };
+
+            let types = client_hello_extension_types(msg).unwrap();
+            assert!(is_grease(types[0].get_u16()));
+            assert_eq!(
+                types[1..4],
+                [
+                    ExtensionType::ALProtocolNegotiation,
+                    alps,
+                    ExtensionType::ServerName
+                ]
+            );
+            assert!(is_grease(types[4].get_u16()));
+            assert_ne!(types[0], types[4]);
+            assert!(!types[5..]
+                .iter()
+                .any(|typ| is_grease(typ.get_u16())));
+            assert_eq!(
+                types
+                    .iter()
+                    .filter(|typ| **typ == ExtensionType::ServerName)
+                    .count(),
+                1
+            );
+
+            assert_eq!(
+                ch.cipher_suites[ch.cipher_suites.len() - 2..],
+                [
+                    CipherSuite::TLS_RSA_WITH_AES_128_GCM_SHA256,
+                    CipherSuite::TLS_EMPTY_RENEGOTIATION_INFO_SCSV
+                ]
+            );
+            for ext in &ch.extensions {
+                match ext {
+                    ClientExtension::NamedGroups(groups) => {
+                        assert_eq!(groups.last(), Some(&NamedGroup::FFDHE2048));
+                    }
+                    ClientExtension::SignatureAlgorithms(schemes) => {
+                        assert_eq!(schemes.last(), Some(&SignatureScheme::RSA_PKCS1_SHA1));
+                    }
+                    _ => {}
+                }
+            }
+        };
+
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        transfer_altered(&mut client, check_hello, &mut server);
+        server.process_new_packets().unwrap();
+        do_handshake(&mut client, &mut server);
+    }
+}
+
+#[test]
+fn client_hello_profile_keeps_psk_extension_last() {
+    let sent = Mutex::new(Vec::new());
+    let record = |msg: &mut Message| {
+        if let Some(types) = client_hello_extension_types(msg) {
+            sent.lock().unwrap().push(types);
+        }
+    };
+
+    let mut client_config = ClientConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .for_client()
+        .unwrap()
+        .with_root_certificates(verifier_roots(), &[])
+        .with_no_client_auth();
+    client_config
+        .client_hello_profile
+        .extension_order = vec![
+        ExtensionType::PreSharedKey,
+        ExtensionType::KeyShare,
+        ExtensionType::SupportedVersions,
+    ];
+    let client_config = Arc::new(client_config);
+    let server_config = Arc::new(
+        ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(verifier_chain(), verifier_key("end"))
+            .unwrap(),
+    );
+
+    for _ in 0..2 {
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        transfer_altered(&mut client, record, &mut server);
+        server.process_new_packets().unwrap();
+        do_handshake(&mut client, &mut server);
+    }
+
+    let sent = sent.into_inner().unwrap();
+    assert_eq!(sent.len(), 2);
+    for types in &sent {
+        assert_eq!(
+            types[..2],
+            [ExtensionType::KeyShare, ExtensionType::SupportedVersions]
+        );
+    }
+    assert!(!sent[0].contains(&ExtensionType::PreSharedKey));
+    assert_eq!(sent[1].last(), Some(&ExtensionType::PreSharedKey));
+}
+
+#[test]
+fn client_hello_padding_pads_to_length() {
+    let sent = Mutex::new(Vec::new());
+    let record = |msg: &mut Message| {
+        if let MessagePayload::Handshake(hs) = &msg.payload {
+            if let Some(types) = client_hello_extension_types(msg) {
+                sent.lock()
+                    .unwrap()
+                    .push((hs.get_encoding().len(), types));
+            }
+        }
+    };
+
+    let server_config = Arc::new(
+        ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(verifier_chain(), verifier_key("end"))
+            .unwrap(),
+    );
+
+    for padded_length in &[100, 512, 1024] {
+        let mut client_config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(verifier_roots(), &[])
+            .with_no_client_auth();
+        client_config
+            .client_hello_profile
+            .padded_length = Some(*padded_length);
+        let client_config = Arc::new(client_config);
+
+        // The second ClientHello offers a ticket.
+        for _ in 0..2 {
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&client_config, &server_config);
+            transfer_altered(&mut client, record, &mut server);
+            server.process_new_packets().unwrap();
+            do_handshake(&mut client, &mut server);
+        }
+    }
+
+    let sent = sent.into_inner().unwrap();
+    assert_eq!(sent.len(), 6);
+    for (len, types) in &sent[..2] {
+        assert!(*len > 100);
+        assert!(!types.contains(&ExtensionType::Padding));
+    }
+    for ((len, types), padded_length) in sent[2..]
+        .iter()
+        .zip(&[512, 512, 1024, 1024])
+    {
+        assert_eq!(len, padded_length);
+        assert!(types.contains(&ExtensionType::Padding));
+    }
+    assert_eq!(sent[3].1.last(), Some(&ExtensionType::PreSharedKey));
+    assert_eq!(sent[5].1.last(), Some(&ExtensionType::PreSharedKey));
+}
+
+#[test]
+fn x509_certificate_info_reports_fields() {
+    use rustls::x509::{CertificateInfo, SubjectAltName};
+
+    let info = CertificateInfo::parse(&verifier_certs("end-ip.cert")[0]).unwrap();
+    assert_eq!(info.subject_string().unwrap(), "CN=testserver.com");
+    assert_eq!(
+        info.issuer_string().unwrap(),
+        "CN=ponytown verifier intermediate"
+    );
+    assert_eq!(info.serial, vec![0x01, 0xd6]);
+    assert_eq!(
+        info.not_before,
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_577_836_800)
+    );
+    assert_eq!(
+        info.not_after,
+        SystemTime::UNIX_EPOCH + Duration::from_secs(2_524_608_000)
+    );
+    assert_eq!(
+        info.subject_alt_names,
+        vec![
+            SubjectAltName::DnsName("testserver.com".into()),
+            SubjectAltName::IpAddress("198.51.100.1".parse().unwrap()),
+            SubjectAltName::IpAddress("2001:db8::1".parse().unwrap()),
+        ]
+    );
+    assert_eq!(info.dns_names().collect::<Vec<_>>(), vec!["testserver.com"]);
+
+    let info = CertificateInfo::parse(&verifier_certs("client-email.cert")[0]).unwrap();
+    assert_eq!(
+        info.subject_alt_names,
+        vec![
+            SubjectAltName::Email("alice@example.com".into()),
+            SubjectAltName::DnsName("alice.example.com".into()),
+        ]
+    );
+
+    let info = CertificateInfo::parse(&verifier_certs("spiffe.cert")[0]).unwrap();
+    assert_eq!(
+        info.subject_alt_names,
+        vec![SubjectAltName::Uri("spiffe://example.org/web".into())]
+    );
+}
+
+#[test]
+fn x509_distinguished_names_can_be_parsed() {
+    use rustls::x509::{CertificateInfo, DistinguishedNameInfo};
+
+    let acceptable_issuers = verifier_roots().subjects();
+    let parsed = DistinguishedNameInfo::parse(&acceptable_issuers[0].0).unwrap();
+    assert_eq!(parsed.to_string(), "CN=ponytown verifier CA");
+    assert_eq!(
+        parsed.common_name().as_deref(),
+        Some("ponytown verifier CA")
+    );
+
+    let inter = CertificateInfo::parse(&verifier_certs("inter.cert")[0]).unwrap();
+    assert_eq!(DistinguishedNameInfo::parse(&inter.issuer), Some(parsed));
+    assert_eq!(
+        DistinguishedNameInfo::parse(&inter.subject)
+            .unwrap()
+            .to_rfc4514_string(),
+        "CN=ponytown verifier intermediate"
+    );
+    assert_eq!(DistinguishedNameInfo::parse(b"garbage"), None);
+}
+
+#[test]
+fn x509_certificate_info_rejects_garbage() {
+    assert_eq!(
+        rustls::x509::CertificateInfo::parse(&rustls::Certificate(b"not a certificate".to_vec())),
+        Err(Error::WebPkiError(
+            webpki::Error::BadDer,
+            WebPkiOp::ParseEndEntity
+        ))
+    );
+}
+
 #[test]
 fn test_ownedtrustanchor_to_trust_anchor_is_public() {
     let _ = rustls::OwnedTrustAnchor::to_trust_anchor;
@@ -10,20 +10,18 @@ use rustls::internal::msgs::message::{Message, OpaqueMessage};
 use rustls::ConfigBuilder;
 use rustls::Connection;
 use rustls::Error;
-use rustls::{AllowAnyAuthenticatedClient, RootCertStore};
+use rustls::{AllowAnyAuthenticatedClient, RootCertStore, WebPkiVerifier};
 use rustls::{Certificate, PrivateKey};
-use rustls::{ClientConfig, ClientConnection};
+use rustls::{ClientConfig, ClientConnection, ServerName};
 use rustls::{ServerConfig, ServerConnection};
 
 #[cfg(feature = "dangerous_configuration")]
 use rustls::{
     internal::msgs::handshake::DigitallySignedStruct, ClientCertVerified, ClientCertVerifier,
-    DistinguishedNames, HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
-    SignatureScheme, WebPkiVerifier,
+    DistinguishedNames, HandshakeSignatureValid, PendingVerification, ServerCertVerification,
+    ServerCertVerified, ServerCertVerifier, SignatureScheme,
 };
 
-use webpki;
-
 macro_rules! embed_files {
     (
         $(
@@ -60,6 +58,7 @@ embed_files! {
     (ECDSA_END_FULLCHAIN, "ecdsa", "end.fullchain");
     (ECDSA_END_KEY, "ecdsa", "end.key");
     (ECDSA_END_REQ, "ecdsa", "end.req");
+    (ECDSA_END_SPKI_DER, "ecdsa", "end.spki.der");
     (ECDSA_INTER_CERT, "ecdsa", "inter.cert");
     (ECDSA_INTER_KEY, "ecdsa", "inter.key");
     (ECDSA_INTER_REQ, "ecdsa", "inter.req");
@@ -79,6 +78,7 @@ embed_files! {
     (EDDSA_END_FULLCHAIN, "eddsa", "end.fullchain");
     (EDDSA_END_KEY, "eddsa", "end.key");
     (EDDSA_END_REQ, "eddsa", "end.req");
+    (EDDSA_END_SPKI_DER, "eddsa", "end.spki.der");
     (EDDSA_INTER_CERT, "eddsa", "inter.cert");
     (EDDSA_INTER_KEY, "eddsa", "inter.key");
     (EDDSA_INTER_REQ, "eddsa", "inter.req");
@@ -98,9 +98,62 @@ embed_files! {
     (RSA_END_KEY, "rsa", "end.key");
     (RSA_END_REQ, "rsa", "end.req");
     (RSA_END_RSA, "rsa", "end.rsa");
+    (RSA_END_SPKI_DER, "rsa", "end.spki.der");
     (RSA_INTER_CERT, "rsa", "inter.cert");
     (RSA_INTER_KEY, "rsa", "inter.key");
     (RSA_INTER_REQ, "rsa", "inter.req");
+
+    (VERIFIER_CA_CERT, "verifier", "ca.cert");
+    (VERIFIER_CLIENT_CERT, "verifier", "client.cert");
+    (VERIFIER_CLIENT_KEY, "verifier", "client.key");
+    (VERIFIER_CLIENT_EMAIL_CERT, "verifier", "client-email.cert");
+    (VERIFIER_CLIENT_EMAIL_KEY, "verifier", "client-email.key");
+    (VERIFIER_CLIENT_EMAIL_OTHER_CERT, "verifier", "client-email-other.cert");
+    (VERIFIER_CLIENT_EMAIL_OTHER_KEY, "verifier", "client-email-other.key");
+    (VERIFIER_CLIENT_POLICY_CERT, "verifier", "client-policy.cert");
+    (VERIFIER_CLIENT_POLICY_KEY, "verifier", "client-policy.key");
+    (VERIFIER_END_CERT, "verifier", "end.cert");
+    (VERIFIER_END_AIA_CERT, "verifier", "end-aia.cert");
+    (VERIFIER_END_EMAIL_URI_CERT, "verifier", "end-email-uri.cert");
+    (VERIFIER_END_CLIENT_AUTH_ISSUED_CERT, "verifier", "end-client-auth-issued.cert");
+    (VERIFIER_END_INTER_SHORT_CERT, "verifier", "end-inter-short.cert");
+    (VERIFIER_END_IP_CERT, "verifier", "end-ip.cert");
+    (VERIFIER_END_MUST_STAPLE_CERT, "verifier", "end-must-staple.cert");
+    (VERIFIER_END_SERIAL_LONG_CERT, "verifier", "end-serial-long.cert");
+    (VERIFIER_END_SERIAL_RANDOM_CERT, "verifier", "end-serial-random.cert");
+    (VERIFIER_END_SERIAL_ZERO_CERT, "verifier", "end-serial-zero.cert");
+    (VERIFIER_END_SCT_CERT, "verifier", "end-sct.cert");
+    (VERIFIER_END_SCT_UNSUPPORTED_CERT, "verifier", "end-sct-unsupported.cert");
+    (VERIFIER_END_WILDCARD_CO_UK_CERT, "verifier", "end-wildcard-co-uk.cert");
+    (VERIFIER_END_WILDCARD_EXAMPLE_CERT, "verifier", "end-wildcard-example.cert");
+    (VERIFIER_END_KEY, "verifier", "end.key");
+    (VERIFIER_END_OCSP_SHA1, "verifier", "end.ocsp-sha1");
+    (VERIFIER_END_OCSP_SHA256, "verifier", "end.ocsp-sha256");
+    (VERIFIER_END_OCSP_SHA384, "verifier", "end.ocsp-sha384");
+    (VERIFIER_END_OCSP_OTHER_ISSUER_FIRST, "verifier", "end.ocsp-other-issuer-first");
+    (VERIFIER_END_OCSP_RESPONDER, "verifier", "end.ocsp-responder");
+    (VERIFIER_END_OCSP_RESPONDER_NOCHECK, "verifier", "end.ocsp-responder-nocheck");
+    (VERIFIER_END_OCSP_RESPONDER_RENEWED, "verifier", "end.ocsp-responder-renewed");
+    (VERIFIER_END_OCSP_UNKNOWN, "verifier", "end.ocsp-unknown");
+    (VERIFIER_FIPS_ED25519_CERT, "verifier", "fips-ed25519.cert");
+    (VERIFIER_FIPS_ED25519_KEY, "verifier", "fips-ed25519.key");
+    (VERIFIER_FIPS_ED25519_ISSUED_CERT, "verifier", "fips-ed25519-issued.cert");
+    (VERIFIER_FIPS_RSA1024_CERT, "verifier", "fips-rsa1024.cert");
+    (VERIFIER_FIPS_RSA_PSS_CERT, "verifier", "fips-rsa-pss.cert");
+    (VERIFIER_INTER_CERT, "verifier", "inter.cert");
+    (VERIFIER_INTER_CLIENT_AUTH_CERT, "verifier", "inter-client-auth.cert");
+    (VERIFIER_INTER_SAME_NAME_CERT, "verifier", "inter-same-name.cert");
+    (VERIFIER_INTER_SHORT_CERT, "verifier", "inter-short.cert");
+    (VERIFIER_INTER_ED25519_CERT, "verifier", "inter-ed25519.cert");
+    (VERIFIER_INTER_WRONG_SKI_CERT, "verifier", "inter-wrong-ski.cert");
+    (VERIFIER_SELFSIGNED_CERT, "verifier", "selfsigned.cert");
+    (VERIFIER_SELFSIGNED_KEY, "verifier", "selfsigned.key");
+    (VERIFIER_SELFSIGNED_EXPIRED_CERT, "verifier", "selfsigned-expired.cert");
+    (VERIFIER_SELFSIGNED_EXPIRED_KEY, "verifier", "selfsigned-expired.key");
+    (VERIFIER_SPIFFE_CERT, "verifier", "spiffe.cert");
+    (VERIFIER_SPIFFE_KEY, "verifier", "spiffe.key");
+    (VERIFIER_SPIFFE_TWO_IDS_CERT, "verifier", "spiffe-two-ids.cert");
+    (VERIFIER_SPIFFE_TWO_IDS_KEY, "verifier", "spiffe-two-ids.key");
 }
 
 pub fn transfer(left: &mut dyn Connection, right: &mut dyn Connection) -> usize {
@@ -202,6 +255,10 @@ impl KeyType {
         )
     }
 
+    pub fn get_spki(&self) -> &'static [u8] {
+        self.bytes_for("end.spki.der")
+    }
+
     pub fn get_client_chain(&self) -> Vec<Certificate> {
         rustls_pemfile::certs(&mut io::BufReader::new(self.bytes_for("client.fullchain")))
             .unwrap()
@@ -277,6 +334,109 @@ pub fn make_client_config_with_auth(kt: KeyType) -> ClientConfig {
         .unwrap()
 }
 
+/// Read the PEM certificates in `test-ca/verifier/{name}`.  These
+/// certificates remain valid until 2050.
+pub fn verifier_certs(name: &str) -> Vec<Certificate> {
+    rustls_pemfile::certs(&mut io::BufReader::new(bytes_for("verifier", name)))
+        .unwrap()
+        .into_iter()
+        .map(Certificate)
+        .collect()
+}
+
+/// The certificate `{name}.cert` and its intermediate.
+pub fn verifier_chain_for(name: &str) -> Vec<Certificate> {
+    let mut chain = verifier_certs(&format!("{}.cert", name));
+    chain.extend(verifier_certs("inter.cert"));
+    chain
+}
+
+pub fn verifier_chain() -> Vec<Certificate> {
+    verifier_chain_for("end")
+}
+
+pub fn verifier_key(name: &str) -> PrivateKey {
+    PrivateKey(
+        rustls_pemfile::pkcs8_private_keys(&mut io::BufReader::new(bytes_for(
+            "verifier",
+            &format!("{}.key", name),
+        )))
+        .unwrap()[0]
+            .clone(),
+    )
+}
+
+pub fn verifier_roots() -> RootCertStore {
+    let mut roots = RootCertStore::empty();
+    for root in verifier_certs("ca.cert") {
+        roots.add(&root).unwrap();
+    }
+    roots
+}
+
+pub fn make_verifier_server_config(ocsp: &[u8]) -> ServerConfig {
+    ConfigBuilder::with_safe_defaults()
+        .for_server()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert_with_ocsp_and_sct(
+            verifier_chain(),
+            verifier_key("end"),
+            ocsp.to_vec(),
+            vec![],
+        )
+        .unwrap()
+}
+
+pub fn make_verifier_server_config_with_chain(chain: Vec<Certificate>) -> ServerConfig {
+    ConfigBuilder::with_safe_defaults()
+        .for_server()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(chain, verifier_key("end"))
+        .unwrap()
+}
+
+pub fn make_verifier_server_config_with_client_verifier(
+    verifier: Arc<AllowAnyAuthenticatedClient>,
+) -> ServerConfig {
+    ConfigBuilder::with_safe_defaults()
+        .for_server()
+        .unwrap()
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(verifier_chain(), verifier_key("end"))
+        .unwrap()
+}
+
+/// A client config trusting the verifier CA, without client
+/// authentication.
+pub fn make_verifier_client_config() -> ClientConfig {
+    ConfigBuilder::with_safe_defaults()
+        .for_client()
+        .unwrap()
+        .with_root_certificates(verifier_roots(), &[])
+        .with_no_client_auth()
+}
+
+/// A client config trusting the verifier CA, and authenticating with
+/// `test-ca/verifier/{name}.cert`.
+pub fn make_verifier_client_config_with_auth(name: &str) -> ClientConfig {
+    ConfigBuilder::with_safe_defaults()
+        .for_client()
+        .unwrap()
+        .with_root_certificates(verifier_roots(), &[])
+        .with_single_cert(verifier_chain_for(name), verifier_key(name))
+        .unwrap()
+}
+
+pub fn make_client_config_with_verifier(verifier: Arc<WebPkiVerifier>) -> ClientConfig {
+    ConfigBuilder::with_safe_defaults()
+        .for_client()
+        .unwrap()
+        .with_webpki_verifier(verifier)
+        .with_no_client_auth()
+}
+
 pub fn make_pair(kt: KeyType) -> (ClientConnection, ServerConnection) {
     make_pair_for_configs(make_client_config(kt), make_server_config(kt))
 }
@@ -293,7 +453,7 @@ pub fn make_pair_for_arc_configs(
     server_config: &Arc<ServerConfig>,
 ) -> (ClientConnection, ServerConnection) {
     (
-        ClientConnection::new(Arc::clone(&client_config), dns_name("localhost")).unwrap(),
+        ClientConnection::new(Arc::clone(&client_config), server_name("localhost")).unwrap(),
         ServerConnection::new(Arc::clone(server_config)).unwrap(),
     )
 }
@@ -358,6 +518,7 @@ pub struct MockServerVerifier {
     tls13_signature_error: Option<Error>,
     wants_scts: bool,
     signature_schemes: Vec<SignatureScheme>,
+    pending: Option<PendingVerification>,
 }
 
 #[cfg(feature = "dangerous_configuration")]
@@ -366,7 +527,7 @@ impl ServerCertVerifier for MockServerVerifier {
         &self,
         end_entity: &rustls::Certificate,
         intermediates: &[rustls::Certificate],
-        dns_name: webpki::DnsNameRef,
+        server_name: &ServerName,
         scts: &mut dyn Iterator<Item = &[u8]>,
         oscp_response: &[u8],
         now: std::time::SystemTime,
@@ -374,7 +535,7 @@ impl ServerCertVerifier for MockServerVerifier {
         let scts: Vec<Vec<u8>> = scts.map(|x| x.to_owned()).collect();
         println!(
             "verify_server_cert({:?}, {:?}, {:?}, {:?}, {:?}, {:?})",
-            end_entity, intermediates, dns_name, scts, oscp_response, now
+            end_entity, intermediates, server_name, scts, oscp_response, now
         );
         if let Some(error) = &self.cert_rejection_error {
             Err(error.clone())
@@ -383,6 +544,28 @@ impl ServerCertVerifier for MockServerVerifier {
         }
     }
 
+    fn start_verify_server_cert(
+        &self,
+        end_entity: &rustls::Certificate,
+        intermediates: &[rustls::Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        oscp_response: &[u8],
+        now: std::time::SystemTime,
+    ) -> ServerCertVerification {
+        match &self.pending {
+            Some(pending) => ServerCertVerification::Pending(pending.clone()),
+            None => ServerCertVerification::Complete(self.verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                scts,
+                oscp_response,
+                now,
+            )),
+        }
+    }
+
     fn verify_tls12_signature(
         &self,
         message: &[u8],
@@ -470,6 +653,13 @@ impl MockServerVerifier {
             ..Default::default()
         }
     }
+
+    pub fn completes_later(pending: PendingVerification) -> Self {
+        MockServerVerifier {
+            pending: Some(pending),
+            ..Default::default()
+        }
+    }
 }
 
 #[cfg(feature = "dangerous_configuration")]
@@ -481,6 +671,7 @@ impl Default for MockServerVerifier {
             tls13_signature_error: None,
             wants_scts: false,
             signature_schemes: WebPkiVerifier::verification_schemes(),
+            pending: None,
         }
     }
 }
@@ -585,8 +776,8 @@ pub fn do_handshake_until_both_error(
     }
 }
 
-pub fn dns_name(name: &'static str) -> webpki::DnsNameRef<'_> {
-    webpki::DnsNameRef::try_from_ascii_str(name).unwrap()
+pub fn server_name(name: &str) -> ServerName {
+    ServerName::try_from(name).unwrap()
 }
 
 pub struct FailsReads {
@@ -173,4 +173,5 @@ for kt in rsa ecdsa eddsa ; do
   cat $kt/client.cert $kt/inter.cert $kt/ca.cert > $kt/client.fullchain
 
   openssl asn1parse -in $kt/ca.cert -out $kt/ca.der > /dev/null
+  openssl pkey -in $kt/end.key -pubout -outform DER -out $kt/end.spki.der
 done
@@ -0,0 +1,586 @@
+#!/bin/sh
+
+# Fixtures for the certificate verification policy tests.  Unlike the
+# certificates made by build-a-pki.sh these have fixed, long validity
+# periods so the tests can supply a fixed verification time.
+
+set -xe
+
+cd verifier/
+
+NOT_BEFORE=20200101000000Z
+NOT_AFTER=20500101000000Z
+
+openssl req -nodes \
+          -x509 \
+          -newkey rsa:2048 \
+          -keyout ca.key \
+          -out ca.cert \
+          -sha256 \
+          -batch \
+          -not_before $NOT_BEFORE \
+          -not_after $NOT_AFTER \
+          -subj "/CN=ponytown verifier CA"
+
+openssl req -nodes \
+          -newkey rsa:2048 \
+          -keyout inter.key \
+          -out inter.req \
+          -sha256 \
+          -batch \
+          -subj "/CN=ponytown verifier intermediate"
+
+openssl x509 -req \
+          -in inter.req \
+          -out inter.cert \
+          -CA ca.cert \
+          -CAkey ca.key \
+          -sha256 \
+          -not_before $NOT_BEFORE \
+          -not_after $NOT_AFTER \
+          -set_serial 123 \
+          -extensions v3_inter -extfile ../openssl.cnf
+
+openssl req -nodes \
+          -newkey rsa:2048 \
+          -keyout end.key \
+          -out end.req \
+          -sha256 \
+          -batch \
+          -subj "/CN=testserver.com"
+
+openssl x509 -req \
+          -in end.req \
+          -out end.cert \
+          -CA inter.cert \
+          -CAkey inter.key \
+          -sha256 \
+          -not_before $NOT_BEFORE \
+          -not_after $NOT_AFTER \
+          -set_serial 456 \
+          -extensions v3_end -extfile ../openssl.cnf
+
+# OCSP responses for end.cert, signed directly by its issuer.
+printf 'V\t500101000000Z\t\t01C8\tunknown\t/CN=testserver.com\n' > index.txt
+
+openssl ocsp -issuer inter.cert \
+          -cert end.cert \
+          -no_nonce \
+          -reqout end.ocsp-req
+
+for md in sha1 sha256 sha384 ; do
+  openssl ocsp -index index.txt \
+            -rsigner inter.cert \
+            -rkey inter.key \
+            -CA inter.cert \
+            -reqin end.ocsp-req \
+            -respout end.ocsp-$md \
+            -ndays 36500 \
+            -rmd $md \
+            -resp_no_certs
+done
+
+# ...and one from a responder which doesn't know about end.cert.
+: > index.txt
+
+openssl ocsp -index index.txt \
+          -rsigner inter.cert \
+          -rkey inter.key \
+          -CA inter.cert \
+          -reqin end.ocsp-req \
+          -respout end.ocsp-unknown \
+          -ndays 36500 \
+          -resp_no_certs
+
+# Client certificates, with and without email address SANs, and with
+# a certificate policy.
+for ext in client client_email client_email_other client_policy ; do
+  name=$(echo $ext | tr _ -)
+
+  openssl req -nodes \
+            -newkey rsa:2048 \
+            -keyout $name.key \
+            -out $name.req \
+            -sha256 \
+            -batch \
+            -subj "/CN=ponytown verifier $name"
+
+  openssl x509 -req \
+            -in $name.req \
+            -out $name.cert \
+            -CA inter.cert \
+            -CAkey inter.key \
+            -sha256 \
+            -not_before $NOT_BEFORE \
+            -not_after $NOT_AFTER \
+            -set_serial 789 \
+            -extensions v3_$ext -extfile ../openssl.cnf
+done
+
+# Self-signed certificates, one of which has expired.
+for name in selfsigned selfsigned-expired ; do
+  if [ $name = selfsigned ] ; then
+    validity="-not_before $NOT_BEFORE -not_after $NOT_AFTER"
+  else
+    validity="-not_before 20000101000000Z -not_after 20010101000000Z"
+  fi
+
+  openssl req -nodes \
+            -x509 \
+            -newkey ec \
+            -pkeyopt ec_paramgen_curve:P-256 \
+            -keyout $name.key \
+            -out $name.cert \
+            -sha256 \
+            -batch \
+            $validity \
+            -subj "/CN=testserver.com" \
+            -addext "subjectAltName = DNS:testserver.com, DNS:localhost" \
+            -addext "basicConstraints = critical,CA:false"
+done
+
+# The intermediate again, with a subjectKeyIdentifier which doesn't
+# match the authorityKeyIdentifier in end.cert.
+openssl req -new \
+          -key inter.key \
+          -out inter-wrong-ski.req \
+          -sha256 \
+          -batch \
+          -subj "/CN=ponytown verifier intermediate"
+
+openssl x509 -req \
+          -in inter-wrong-ski.req \
+          -out inter-wrong-ski.cert \
+          -CA ca.cert \
+          -CAkey ca.key \
+          -sha256 \
+          -not_before $NOT_BEFORE \
+          -not_after $NOT_AFTER \
+          -set_serial 124 \
+          -extensions v3_inter_wrong_ski -extfile ../openssl.cnf
+
+# Certificates which don't conform to FIPS mode: a leaf with a small
+# RSA key, a leaf with an Ed25519 key, and a leaf issued by an
+# intermediate with an Ed25519 key.
+openssl req -nodes \
+          -newkey rsa:1024 \
+          -keyout fips-rsa1024.key \
+          -out fips-rsa1024.req \
+          -sha256 \
+          -batch \
+          -subj "/CN=testserver.com"
+
+openssl req -nodes \
+          -newkey ed25519 \
+          -keyout fips-ed25519.key \
+          -out fips-ed25519.req \
+          -batch \
+          -subj "/CN=testserver.com"
+
+for name in fips-rsa1024 fips-ed25519 ; do
+  openssl x509 -req \
+            -in $name.req \
+            -out $name.cert \
+            -CA inter.cert \
+            -CAkey inter.key \
+            -sha256 \
+            -not_before $NOT_BEFORE \
+            -not_after $NOT_AFTER \
+            -set_serial 457 \
+            -extensions v3_end -extfile ../openssl.cnf
+done
+
+openssl req -nodes \
+          -newkey ed25519 \
+          -keyout inter-ed25519.key \
+          -out inter-ed25519.req \
+          -batch \
+          -subj "/CN=ponytown verifier Ed25519 intermediate"
+
+openssl x509 -req \
+          -in inter-ed25519.req \
+          -out inter-ed25519.cert \
+          -CA ca.cert \
+          -CAkey ca.key \
+          -sha256 \
+          -not_before $NOT_BEFORE \
+          -not_after $NOT_AFTER \
+          -set_serial 125 \
+          -extensions v3_inter -extfile ../openssl.cnf
+
+openssl x509 -req \
+          -in end.req \
+          -out fips-ed25519-issued.cert \
+          -CA inter-ed25519.cert \
+          -CAkey inter-ed25519.key \
+          -not_before $NOT_BEFORE \
+          -not_after $NOT_AFTER \
+          -set_serial 458 \
+          -extensions v3_end -extfile ../openssl.cnf
+
+# A leaf which conforms to FIPS mode, signed with RSA-PSS.
+openssl x509 -req \
+          -in end.req \
+          -out fips-rsa-pss.cert \
+          -CA inter.cert \
+          -CAkey inter.key \
+          -sha256 \
+          -sigopt rsa_padding_mode:pss \
+          -sigopt rsa_pss_saltlen:digest \
+          -not_before $NOT_BEFORE \
+          -not_after $NOT_AFTER \
+          -set_serial 459 \
+          -extensions v3_end -extfile ../openssl.cnf
+
+# An intermediate restricted to clientAuth, and a server certificate
+# it issued.
+openssl req -nodes \
+          -newkey rsa:2048 \
+          -keyout inter-client-auth.key \
+          -out inter-client-auth.req \
+          -sha256 \
+          -batch \
+          -subj "/CN=ponytown verifier client auth intermediate"
+
+openssl x509 -req \
+          -in inter-client-auth.req \
+          -out inter-client-auth.cert \
+          -CA ca.cert \
+          -CAkey ca.key \
+          -sha256 \
+          -not_before $NOT_BEFORE \
+          -not_after $NOT_AFTER \
+          -set_serial 126 \
+          -extensions v3_inter_client_auth -extfile ../openssl.cnf
+
+openssl x509 -req \
+          -in end.req \
+          -out end-client-auth-issued.cert \
+          -CA inter-client-auth.cert \
+          -CAkey inter-client-auth.key \
+          -sha256 \
+          -not_before $NOT_BEFORE \
+          -not_after $NOT_AFTER \
+          -set_serial 459 \
+          -extensions v3_end -extfile ../openssl.cnf
+
+# Wildcard server certificates, one of which spans a public suffix.
+for ext in wildcard_example wildcard_co_uk ; do
+  name=end-$(echo $ext | tr _ -)
+
+  openssl x509 -req \
+            -in end.req \
+            -out $name.cert \
+            -CA inter.cert \
+            -CAkey inter.key \
+            -sha256 \
+            -not_before $NOT_BEFORE \
+            -not_after $NOT_AFTER \
+            -set_serial 460 \
+            -extensions v3_end_$ext -extfile ../openssl.cnf
+done
+
+# Server certificates with a zero serial number, a 21-octet one, and
+# a conforming 16-octet one.
+for serial in zero long random ; do
+  case $serial in
+    zero) value=0 ;;
+    long) value=0x7c5e1f9a2b3d4c6e8f0a1b2c3d4e5f60718293a4b5 ;;
+    random) value=0x5a3c9e71d2f04b6a8c1e3d5f7092b4c6 ;;
+  esac
+
+  openssl x509 -req \
+            -in end.req \
+            -out end-serial-$serial.cert \
+            -CA inter.cert \
+            -CAkey inter.key \
+            -sha256 \
+            -not_before $NOT_BEFORE \
+            -not_after $NOT_AFTER \
+            -set_serial $value \
+            -extensions v3_end -extfile ../openssl.cnf
+done
+
+# OCSP responses for end.cert from delegated responders, with and
+# without the id-pkix-ocsp-nocheck extension.
+printf 'V\t500101000000Z\t\t01C8\tunknown\t/CN=testserver.com\n' > index.txt
+
+for ext in ocsp_responder ocsp_responder_nocheck ; do
+  name=$(echo $ext | tr _ -)
+
+  openssl req -nodes \
+            -newkey rsa:2048 \
+            -keyout $name.key \
+            -out $name.req \
+            -sha256 \
+            -batch \
+            -subj "/CN=ponytown verifier $name"
+
+  openssl x509 -req \
+            -in $name.req \
+            -out $name.cert \
+            -CA inter.cert \
+            -CAkey inter.key \
+            -sha256 \
+            -not_before $NOT_BEFORE \
+            -not_after $NOT_AFTER \
+            -set_serial 790 \
+            -extensions v3_$ext -extfile ../openssl.cnf
+
+  openssl ocsp -index index.txt \
+            -rsigner $name.cert \
+            -rkey $name.key \
+            -CA inter.cert \
+            -reqin end.ocsp-req \
+            -respout end.ocsp-${name#ocsp-} \
+            -ndays 36500 \
+            -rmd sha256
+done
+
+# A certificate transparency log, a server certificate with an SCT
+# from it embedded, and one embedding an SCT of an unsupported version.
+# The embedded SCT is over the precertificate: the same TBSCertificate,
+# less the SCT list extension.
+openssl ecparam -name prime256v1 -genkey -noout -out ct-log.key
+openssl pkey -in ct-log.key -pubout -outform der > ct-log.spki
+tail -c 65 ct-log.spki > ct-log.pub
+openssl dgst -sha256 -binary ct-log.spki > ct-log.id
+
+hex() {
+  xxd -p | tr -d '\n'
+}
+
+openssl x509 -req \
+          -in end.req \
+          -out end-precert.cert \
+          -CA inter.cert \
+          -CAkey inter.key \
+          -sha256 \
+          -not_before $NOT_BEFORE \
+          -not_after $NOT_AFTER \
+          -set_serial 3004 \
+          -extensions v3_end -extfile ../openssl.cnf
+
+precert=$(openssl x509 -in end-precert.cert -outform der | hex)
+tbs_len=$((4 + 0x$(echo $precert | cut -c 13-16)))
+tbs=$(echo $precert | cut -c 9-$((8 + 2 * tbs_len)))
+issuer_key_hash=$(openssl x509 -in inter.cert -pubkey -noout | openssl pkey -pubin -outform der | openssl dgst -sha256 -binary | hex)
+timestamp=0000016f5e66e800
+
+signed=0000${timestamp}0001${issuer_key_hash}$(printf %06x $tbs_len)${tbs}0000
+sig=$(echo $signed | xxd -r -p | openssl dgst -sha256 -sign ct-log.key | hex)
+sct=00$(hex < ct-log.id)${timestamp}00000403$(printf %04x $((${#sig} / 2)))${sig}
+sct_list=$(printf %04x $((${#sct} / 2 + 2)))$(printf %04x $((${#sct} / 2)))${sct}
+
+cp ../openssl.cnf sct.cnf
+cat >> sct.cnf <<EOF
+
+[ v3_end_sct ]
+basicConstraints = critical,CA:false
+keyUsage = nonRepudiation, digitalSignature
+subjectKeyIdentifier = hash
+authorityKeyIdentifier = keyid:always,issuer:always
+subjectAltName = @alt_names
+1.3.6.1.4.1.11129.2.4.2 = DER:04$(printf %02x $((${#sct_list} / 2)))${sct_list}
+EOF
+
+for ext in end_sct end_sct_unsupported ; do
+  openssl x509 -req \
+            -in end.req \
+            -out $(echo $ext | tr _ -).cert \
+            -CA inter.cert \
+            -CAkey inter.key \
+            -sha256 \
+            -not_before $NOT_BEFORE \
+            -not_after $NOT_AFTER \
+            -set_serial 3004 \
+            -extensions v3_$ext -extfile sct.cnf
+done
+
+rm -f ct-log.spki end-precert.cert sct.cnf
+
+# An intermediate which expires before the server certificate it issues.
+openssl req -nodes \
+          -newkey rsa:2048 \
+          -keyout inter-short.key \
+          -out inter-short.req \
+          -sha256 \
+          -batch \
+          -subj "/CN=ponytown verifier short-lived intermediate"
+
+openssl x509 -req \
+          -in inter-short.req \
+          -out inter-short.cert \
+          -CA ca.cert \
+          -CAkey ca.key \
+          -sha256 \
+          -not_before $NOT_BEFORE \
+          -not_after 20400101000000Z \
+          -set_serial 124 \
+          -extensions v3_inter -extfile ../openssl.cnf
+
+openssl x509 -req \
+          -in end.req \
+          -out end-inter-short.cert \
+          -CA inter-short.cert \
+          -CAkey inter-short.key \
+          -sha256 \
+          -not_before $NOT_BEFORE \
+          -not_after $NOT_AFTER \
+          -set_serial 457 \
+          -extensions v3_end -extfile ../openssl.cnf
+
+# An OCSP response from end.cert's issuer which first answers for the
+# same serial number under the short-lived intermediate.  The responder
+# doesn't know that certificate, so says its status is unknown.
+printf 'V\t500101000000Z\t\t01C8\tunknown\t/CN=testserver.com\n' > index.txt
+
+openssl ocsp -issuer inter-short.cert \
+          -serial 456 \
+          -issuer inter.cert \
+          -cert end.cert \
+          -no_nonce \
+          -reqout end.ocsp-req
+
+openssl ocsp -index index.txt \
+          -rsigner inter.cert \
+          -rkey inter.key \
+          -CA inter.cert \
+          -reqin end.ocsp-req \
+          -respout end.ocsp-other-issuer-first \
+          -ndays 36500 \
+          -resp_no_certs
+
+# A server certificate with the TLS feature extension requiring OCSP
+# stapling.  It shares end.cert's serial number, so end.cert's OCSP
+# responses apply to it.
+openssl x509 -req \
+          -in end.req \
+          -out end-must-staple.cert \
+          -CA inter.cert \
+          -CAkey inter.key \
+          -sha256 \
+          -not_before $NOT_BEFORE \
+          -not_after $NOT_AFTER \
+          -set_serial 456 \
+          -extensions v3_end_must_staple -extfile ../openssl.cnf
+
+# A server certificate with IPv4 and IPv6 address names, for
+# connecting to a server by address.
+openssl x509 -req \
+          -in end.req \
+          -out end-ip.cert \
+          -CA inter.cert \
+          -CAkey inter.key \
+          -sha256 \
+          -not_before $NOT_BEFORE \
+          -not_after $NOT_AFTER \
+          -set_serial 470 \
+          -extensions v3_end_ip -extfile ../openssl.cnf
+
+# SPIFFE X.509-SVIDs: one with a single SPIFFE ID, and one with two,
+# which is not allowed.
+serial=480
+for ext in spiffe spiffe_two_ids ; do
+  name=$(echo $ext | tr _ -)
+
+  openssl req -nodes \
+            -newkey rsa:2048 \
+            -keyout $name.key \
+            -out $name.req \
+            -sha256 \
+            -batch \
+            -subj "/CN=ponytown verifier $name"
+
+  openssl x509 -req \
+            -in $name.req \
+            -out $name.cert \
+            -CA inter.cert \
+            -CAkey inter.key \
+            -sha256 \
+            -not_before $NOT_BEFORE \
+            -not_after $NOT_AFTER \
+            -set_serial $serial \
+            -extensions v3_$ext -extfile ../openssl.cnf
+  serial=$((serial + 1))
+done
+
+# A server certificate naming where its issuer can be fetched, in
+# the caIssuers authority information access.
+openssl x509 -req \
+          -in end.req \
+          -out end-aia.cert \
+          -CA inter.cert \
+          -CAkey inter.key \
+          -sha256 \
+          -not_before $NOT_BEFORE \
+          -not_after $NOT_AFTER \
+          -set_serial 490 \
+          -extensions v3_end_aia -extfile ../openssl.cnf
+
+# A server certificate with email address and URI names, for protocols
+# such as SMTP and SIP which identify peers that way.
+openssl x509 -req \
+          -in end.req \
+          -out end-email-uri.cert \
+          -CA inter.cert \
+          -CAkey inter.key \
+          -sha256 \
+          -not_before $NOT_BEFORE \
+          -not_after $NOT_AFTER \
+          -set_serial 491 \
+          -extensions v3_end_email_uri -extfile ../openssl.cnf
+
+# Another intermediate with the same name as inter.cert but a
+# different key, which didn't issue end.cert.
+openssl req -nodes \
+          -newkey rsa:2048 \
+          -keyout inter-same-name.key \
+          -out inter-same-name.req \
+          -sha256 \
+          -batch \
+          -subj "/CN=ponytown verifier intermediate"
+
+openssl x509 -req \
+          -in inter-same-name.req \
+          -out inter-same-name.cert \
+          -CA ca.cert \
+          -CAkey ca.key \
+          -sha256 \
+          -not_before $NOT_BEFORE \
+          -not_after $NOT_AFTER \
+          -set_serial 126 \
+          -extensions v3_inter -extfile ../openssl.cnf
+
+# A renewal of ocsp-responder.cert, for the same key, with the
+# id-pkix-ocsp-nocheck extension.  The OCSP response is signed by the
+# original, and carries the renewal after it.
+printf 'V\t500101000000Z\t\t01C8\tunknown\t/CN=testserver.com\n' > index.txt
+
+openssl x509 -req \
+          -in ocsp-responder.req \
+          -out ocsp-responder-renewed.cert \
+          -CA inter.cert \
+          -CAkey inter.key \
+          -sha256 \
+          -not_before $NOT_BEFORE \
+          -not_after $NOT_AFTER \
+          -set_serial 791 \
+          -extensions v3_ocsp_responder_nocheck -extfile ../openssl.cnf
+
+openssl ocsp -issuer inter.cert \
+          -cert end.cert \
+          -no_nonce \
+          -reqout end.ocsp-req
+
+openssl ocsp -index index.txt \
+          -rsigner ocsp-responder.cert \
+          -rkey ocsp-responder.key \
+          -rother ocsp-responder-renewed.cert \
+          -CA inter.cert \
+          -reqin end.ocsp-req \
+          -respout end.ocsp-responder-renewed \
+          -ndays 36500 \
+          -rmd sha256
+
+rm -f index.txt* *.req end.ocsp-req